@@ -4,71 +4,73 @@ extern crate multiinput;
 extern crate nice_game;
 extern crate simplelog;
 
-use cgmath::{ prelude::*, Quaternion, Rad, vec2, vec3, Vector2, Vector3 };
+use cgmath::{ prelude::*, Quaternion, Rad, vec2, vec3, Vector3 };
 use futures::executor::block_on;
 use multiinput::{ DeviceType, KeyId, RawEvent, RawInputManager, State };
 use nice_game::{
 	Context,
+	ContextOptions,
 	GpuFuture,
 	RenderTarget,
 	Version,
 	batch::{
-		mesh::{ Mesh, MeshBatch, MeshShaders, MeshRenderPass },
+		mesh::{ Mesh, MeshBatch, MeshShaders, MeshRenderPass, MeshRenderPassOptions, PointLight },
 	},
-	camera::Camera,
+	camera::{ Camera, controllers::{ CameraController, ControllerInput, FlyController } },
+	input::{ InputFrame, InputPlayback, InputRecorder, InputState },
 	window::{ Event, EventsLoop, MouseButton, MouseCursor, Window, WindowEvent },
 };
 use simplelog::{ LevelFilter, SimpleLogger };
-use std::f32::consts::PI;
+use std::{ env, f32::consts::PI, time::Instant };
 
 fn main() {
 	SimpleLogger::init(LevelFilter::Debug, simplelog::Config::default()).unwrap();
 
+	// `--record <file>` saves this session's input, and `--replay <file>` plays one back instead of reading devices
+	let args = env::args().collect::<Vec<_>>();
+	let arg = |name: &str| args.iter().position(|arg| arg == name).map(|i| args[i + 1].clone());
+	let mut recorder = arg("--record").map(|path| InputRecorder::create(path).unwrap());
+	let mut playback = arg("--replay").map(|path| InputPlayback::open(path).unwrap());
+
 	let mut events = EventsLoop::new();
 
 	let mut window =
 		Window::new(
-			&Context::new(
+			&Context::new_with_options(
 				Some("Triangle Example"),
 				Some(Version {
 					major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
 					minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
 					patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
 				}),
+				ContextOptions { validation_layers: cfg!(debug_assertions), .. Default::default() },
 			).unwrap(),
 			&mut events,
 			"nIce Game"
 		);
 
 	let (mesh_batch_shaders, mesh_batch_shaders_future) = MeshShaders::new(&mut window).unwrap();
-	let mesh_batch_shared = MeshRenderPass::new(mesh_batch_shaders, window.format());
-
-	let (mesh, mesh_future) =
-		block_on(
-			Mesh::from_file(
-				&window,
-				mesh_batch_shared.clone(),
-				"examples/assets/p250/p250.nmd",
-				vec3(0.0, 0.0, -0.5),
-				Quaternion::one() * Quaternion::from_angle_y(Rad(PI / 2.0)),
-			)
+	let mesh_batch_shared =
+		MeshRenderPass::with_options(
+			mesh_batch_shaders,
+			window.format(),
+			&MeshRenderPassOptions { reverse_z: true, .. Default::default() }
 		).unwrap();
 
+	let (mut mesh, mesh_future) =
+		block_on(Mesh::from_file(&window, mesh_batch_shared.clone(), "examples/assets/p250/p250.nmd")).unwrap();
+	mesh.set_position(vec3(0.0, 0.0, -0.5));
+	mesh.set_rotation(Quaternion::from_angle_y(Rad(PI / 2.0)));
+
 	let (mut mesh_batch, mesh_batch_future) = MeshBatch::new(&window, mesh_batch_shared).unwrap();
 	mesh_batch.add_mesh(mesh);
+	mesh_batch.add_light(PointLight::new(vec3(14.5, -11.0, -28.5), [0.7, 0.85, 1.0], 5f32.sqrt(), 5.0));
 
-	let mut character = Character::new();
+	let mut controller = FlyController::new(Vector3::zero());
 	let [win_width, win_height] = window.images()[0].dimensions().width_height();
 	let mut camera =
-		Camera::new(
-			&window,
-			Vector3::zero(),
-			Quaternion::one(),
-			win_width as f32 / win_height as f32,
-			100.0,
-			0.05,
-			1500.0,
-		).unwrap();
+		Camera::new(Vector3::zero(), Quaternion::one(), win_width as f32 / win_height as f32, 100.0, 0.05, 1500.0);
+	camera.set_reverse_z(true);
 
 	window.join_future(mesh_future.join(mesh_batch_shaders_future).join(mesh_batch_future));
 
@@ -84,27 +86,37 @@ fn main() {
 	raw_input.register_devices(DeviceType::Keyboards);
 	raw_input.register_devices(DeviceType::Mice);
 
+	let mut input_state = InputState::new();
+	let mut last_tick = Instant::now();
 	loop {
 		let mut done = false;
+		let mut look = vec2(0.0, 0.0);
 
-		events.poll_events(|event| match event {
-			Event::WindowEvent { event: WindowEvent::AxisMotion { axis, value, .. } , .. } => {
-				println!("axis {}, value {}", axis, value);
-			},
-			Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => done = true,
-			Event::WindowEvent { event: WindowEvent::Focused(false), .. } => {
-				window.set_cursor(MouseCursor::Default);
-				controls_active = false;
-			},
-			Event::WindowEvent { event: WindowEvent::MouseInput{ button: MouseButton::Left, .. }, .. } => {
-				window.set_cursor(MouseCursor::Grab);
-				controls_active = true;
-			},
-			Event::WindowEvent { event: WindowEvent::Resized(_), .. } => {
-				camera.set_projection(win_width as f32 / win_height as f32, 100.0, 0.05, 1500.0).unwrap();
-			},
-			_ => (),
-		});
+		let handle_event = |event: Event| {
+			input_state.handle_event(&event);
+			match event {
+				Event::WindowEvent { event: WindowEvent::AxisMotion { axis, value, .. } , .. } => {
+					println!("axis {}, value {}", axis, value);
+				},
+				Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => done = true,
+				Event::WindowEvent { event: WindowEvent::Focused(false), .. } => {
+					window.set_cursor(MouseCursor::Default);
+					controls_active = false;
+				},
+				Event::WindowEvent { event: WindowEvent::MouseInput{ button: MouseButton::Left, .. }, .. } => {
+					window.set_cursor(MouseCursor::Grab);
+					controls_active = true;
+				},
+				Event::WindowEvent { event: WindowEvent::Resized(_), .. } => {
+					camera.set_projection(win_width as f32 / win_height as f32, 100.0, 0.05, 1500.0);
+				},
+				_ => (),
+			}
+		};
+		match &playback {
+			Some(playback) => playback.poll_events(&mut events, handle_event),
+			None => events.poll_events(handle_event),
+		}
 
 		while let Some(event) = raw_input.get_event() {
 			match event {
@@ -122,19 +134,7 @@ fn main() {
 				RawEvent::KeyboardEvent(_,  KeyId::Shift, State::Pressed) => shift_down = true,
 				RawEvent::KeyboardEvent(_,  KeyId::Shift, State::Released) => shift_down = false,
 				RawEvent::MouseMoveEvent(_, x, y) => if controls_active {
-					character.rotation += vec2(x as f32 / 300.0, y as f32 / 300.0);
-
-					if character.rotation.x > 2.0 {
-						character.rotation.x -= 4.0;
-					} else if character.rotation.x < -2.0 {
-						character.rotation.x += 4.0;
-					}
-
-					if character.rotation.y > 1.0 {
-						character.rotation.y = 1.0;
-					} else if character.rotation.y < -1.0 {
-						character.rotation.y = -1.0;
-					}
+					look += vec2(x as f32, y as f32) * PI / 600.0;
 				},
 				_ => (),
 			}
@@ -144,21 +144,41 @@ fn main() {
 			break;
 		}
 
-		let yaw = Quaternion::from_angle_y(Rad(-character.rotation.x * PI / 2.0));
-
-		if controls_active && w_down { character.position += yaw.rotate_vector(vec3(0.0, 0.0, -0.1)); }
-		if controls_active && a_down { character.position += yaw.rotate_vector(vec3(-0.1, 0.0, 0.0)); }
-		if controls_active && s_down { character.position += yaw.rotate_vector(vec3(0.0, 0.0, 0.1)); }
-		if controls_active && d_down { character.position += yaw.rotate_vector(vec3(0.1, 0.0, 0.0)); }
-		if controls_active && space_down { character.position.y -= 0.1; }
-		if controls_active && shift_down { character.position.y += 0.1; }
+		let axis = |positive: bool, negative: bool| (positive as i32 - negative as i32) as f32;
+		let movement =
+			if controls_active {
+				vec3(axis(d_down, a_down), axis(space_down, shift_down), axis(s_down, w_down))
+			} else {
+				Vector3::zero()
+			};
+
+		let now = Instant::now();
+		let dt = now.duration_since(last_tick);
+		last_tick = now;
+
+		let mut frame =
+			InputFrame {
+				dt: dt.as_secs() as f32 + dt.subsec_nanos() as f32 / 1_000_000_000.0,
+				input: ControllerInput { movement: movement, look: look, zoom: 0.0 },
+				state: input_state.clone(),
+			};
+		if let Some(playback) = &mut playback {
+			match playback.next_frame().unwrap() {
+				Some(recorded) => frame = recorded,
+				None => break,
+			}
+		}
+		if let Some(recorder) = &mut recorder {
+			recorder.record(&frame).unwrap();
+		}
 
-		camera.set_position(character.position).unwrap();
-		camera.set_rotation(yaw * Quaternion::from_angle_x(Rad(character.rotation.y * PI / 2.0))).unwrap();
+		controller.update(&frame.input, frame.dt);
+		controller.apply(&mut camera);
+		input_state.end_frame();
 
 		window
 			.present(|window, image_num, mut future| {
-				let (cmds, cmds_future) = mesh_batch.commands(window, window, image_num, &camera).unwrap();
+				let (cmds, cmds_future) = mesh_batch.commands(window, image_num, &camera).unwrap();
 				if let Some(cmds_future) = cmds_future {
 					future = Box::new(future.join(cmds_future));
 				}
@@ -168,14 +188,8 @@ fn main() {
 	}
 
 	window.set_cursor(MouseCursor::Default);
-}
 
-struct Character {
-	position: Vector3<f32>,
-	rotation: Vector2<f32>,
-}
-impl Character {
-	fn new() -> Self {
-		Self { position: vec3(0.0, 0.0, 0.0), rotation: vec2(0.0, 0.0) }
+	if let Some(recorder) = recorder {
+		recorder.finish().unwrap();
 	}
 }
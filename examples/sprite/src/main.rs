@@ -7,8 +7,8 @@ use nice_game::{
 	GpuFuture,
 	RenderTarget,
 	Version,
-	batch::sprite::{ SpriteBatch, SpriteBatchShaders, SpriteBatchShared },
-	texture::{ ImageFormat, ImmutableTexture },
+	batch::sprite::{ Console, SpriteBatch, SpriteBatchShaders, SpriteBatchShared, StatsOverlay },
+	texture::{ ColorEncoding, ImageFormat, ImmutableTexture },
 	window::{ Event, WindowEvent },
 };
 
@@ -24,7 +24,7 @@ fn main() {
 		)
 		.unwrap();
 
-	let mut window = ctx.create_window("nIce Game");
+	let mut window = ctx.create_window("nIce Game").unwrap();
 
 	let (shaders, shaders_future) = SpriteBatchShaders::new(&mut window).unwrap();
 
@@ -36,12 +36,13 @@ fn main() {
 				&window,
 				"examples/assets/colors.png",
 				ImageFormat::PNG,
-				true
+				ColorEncoding::Srgb
 			)
 		).unwrap();
-	let (sprite, sprite_future) = sprite_batch_shared.create_sprite(&texture, [10.0, 42.0]).unwrap();
+	let sprite = sprite_batch_shared.create_sprite(&texture, [10.0, 42.0]).unwrap();
 
-	let text = window.device().get_font("examples/assets/consola.ttf", 24.0).unwrap()
+	let font = window.device().get_font("examples/assets/consola.ttf", 24.0).unwrap();
+	let text = font
 		.make_sprite("The quick brown fox jumped over the lazy dog. (╯°□°）╯︵ ┻━┻", &sprite_batch_shared, [10.0, 32.0])
 		.unwrap();
 
@@ -49,22 +50,42 @@ fn main() {
 	sprite_batch.add_sprite(Box::new(sprite));
 	sprite_batch.add_sprite(Box::new(text));
 
-	window.join_future(shaders_future.join(texture_future).join(sprite_future).join(sprite_batch_future));
+	let stats = StatsOverlay::new(font.clone(), window.device().clone(), [10.0, 400.0]);
+	sprite_batch.add_sprite(Box::new(stats.clone()));
+
+	let console = Console::new(font);
+	console.register("echo", |args| args.join(" "));
+	let stats_toggle = stats.clone();
+	console.register("stats", move |_| {
+		stats_toggle.toggle();
+		String::new()
+	});
+	sprite_batch.add_sprite(Box::new(console.clone()));
+
+	window.join_future(shaders_future.join(texture_future).join(sprite_batch_future));
 
 	loop {
 		let mut done = false;
-		ctx.poll_events(|event| match event {
-			Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => done = true,
-			_ => (),
+		ctx.poll_events(|event| {
+			if console.handle_event(&event) {
+				return;
+			}
+
+			match event {
+				Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => done = true,
+				_ => (),
+			}
 		});
 
 		if done {
 			break;
 		}
 
+		stats.tick();
+
 		window
 			.present(|window, image_num, mut future| {
-				let (commands, commands_future) = sprite_batch.commands(window, window, image_num).unwrap();
+				let (commands, commands_future) = sprite_batch.commands(window, image_num).unwrap();
 				if let Some(commands_future) = commands_future {
 					future = Box::new(future.join(commands_future));
 				}
@@ -8,7 +8,8 @@ use nice_game::{
 	RenderTarget,
 	Version,
 	batch::sprite::{ Sprite, SpriteBatch, SpriteBatchShaders, SpriteBatchShared },
-	texture::{ ImageFormat, ImmutableTexture, TargetTexture },
+	frame::Frame,
+	texture::{ ColorEncoding, ImageFormat, ImmutableTexture, TargetTexture },
 	window::{ Event, EventsLoop, Window, WindowEvent },
 };
 
@@ -35,8 +36,14 @@ fn main() {
 	let target = TargetTexture::new(&window, [400, 400]).unwrap();
 
 	let (texture, texture_future) =
-		block_on(ImmutableTexture::from_file_with_format(&window, "examples/assets/colors.png", ImageFormat::PNG, true))
-			.unwrap();
+		block_on(
+			ImmutableTexture::from_file_with_format(
+				&window,
+				"examples/assets/colors.png",
+				ImageFormat::PNG,
+				ColorEncoding::Srgb
+			)
+		).unwrap();
 
 	let (texture_sprite, texture_sprite_future) =
 		Sprite::new(&window, &sprite_batch_shared, &texture, [0.0, 0.0]).unwrap();
@@ -72,23 +79,17 @@ fn main() {
 		}
 
 		window
-			.present(|window, image_num, mut future| {
-				let (target_commands, target_future) = target_sprite_batch.commands(window, &target, 0).unwrap();
-				if let Some(target_future) = target_future {
-					future = Box::new(future.join(target_future));
-				}
-
-				let (window_commands, window_future) = window_sprite_batch.commands(window, window, image_num).unwrap();
-				if let Some(window_future) = window_future {
-					future = Box::new(future.join(window_future));
-				}
-
-				future
-					.then_execute(window.queue().clone(), target_commands)
-					.unwrap()
-					.then_signal_semaphore()
-					.then_execute(window.queue().clone(), window_commands)
-					.unwrap()
+			.present(|window, image_num, future| {
+				let mut frame = Frame::new(window);
+				frame
+					.add_pass("target", target_sprite_batch.commands(&target, 0).unwrap(), &[], &[&target])
+					.add_pass(
+						"window",
+						window_sprite_batch.commands(window, image_num).unwrap(),
+						&[&target],
+						&[&*window]
+					);
+				frame.submit(future).unwrap()
 			})
 			.unwrap();
 	}
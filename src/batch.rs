@@ -1,2 +1,319 @@
+pub mod compositor;
 pub mod mesh;
 pub mod sprite;
+pub mod tilemap;
+
+pub use vulkano::pipeline::depth_stencil::{ Compare, StencilOp };
+use std::{ collections::HashMap, error::Error, fmt, hash::{ Hash, Hasher } };
+use vulkano::{
+	OomError,
+	command_buffer::{ BuildError, DynamicState },
+	device::Device,
+	format::Format,
+	framebuffer::FramebufferCreationError,
+	image::ImageCreationError,
+	memory::DeviceMemoryAllocError,
+	pipeline::{
+		blend::AttachmentBlend,
+		depth_stencil::{ DepthStencil, Stencil },
+		viewport::{ Scissor, Viewport },
+	},
+	sync::FlushError,
+};
+
+/// The format of stencil buffers, and of mesh depth buffers that have a stencil buffer. 32-bit float depth is
+/// preferred, since it's as precise as depth buffers without a stencil, but some GPUs only support the packed 24-bit
+/// format as an attachment. Vulkan requires one of the two.
+pub(crate) fn stencil_format(device: &Device) -> Format {
+	let physical_device = device.physical_device();
+	[Format::D32Sfloat_S8Uint, Format::D24Unorm_S8Uint].iter()
+		.cloned()
+		.find(|format| format.properties(physical_device).optimal_tiling_features.depth_stencil_attachment)
+		.unwrap_or(Format::D24Unorm_S8Uint)
+}
+
+/// A rectangle within a render target, either in pixels or as a fraction of the target's size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum Region {
+	Pixels { origin: [f32; 2], dimensions: [f32; 2] },
+	Normalized { origin: [f32; 2], dimensions: [f32; 2] },
+}
+impl Region {
+	pub fn full() -> Self {
+		Region::Normalized { origin: [0.0, 0.0], dimensions: [1.0, 1.0] }
+	}
+
+	pub(crate) fn pixels(&self, target_dimensions: [f32; 2]) -> ([f32; 2], [f32; 2]) {
+		match *self {
+			Region::Pixels { origin, dimensions } => (origin, dimensions),
+			Region::Normalized { origin, dimensions } => (
+				[origin[0] * target_dimensions[0], origin[1] * target_dimensions[1]],
+				[dimensions[0] * target_dimensions[0], dimensions[1] * target_dimensions[1]],
+			),
+		}
+	}
+
+	/// The same region in pixels of a copy of the target scaled by `scale`, for passes rendered at another resolution.
+	pub(crate) fn scaled(&self, target_dimensions: [f32; 2], scale: [f32; 2]) -> Region {
+		let (origin, dimensions) = self.pixels(target_dimensions);
+		Region::Pixels {
+			origin: [origin[0] * scale[0], origin[1] * scale[1]],
+			dimensions: [dimensions[0] * scale[0], dimensions[1] * scale[1]],
+		}
+	}
+
+	pub(crate) fn viewport(&self, target_dimensions: [f32; 2]) -> Viewport {
+		let (origin, dimensions) = self.pixels(target_dimensions);
+		Viewport { origin: origin, dimensions: dimensions, depth_range: 0.0..1.0 }
+	}
+
+	pub(crate) fn scissor(&self, target_dimensions: [f32; 2]) -> Scissor {
+		let (origin, dimensions) = self.pixels(target_dimensions);
+		let min = [origin[0].max(0.0), origin[1].max(0.0)];
+		let max = [
+			(origin[0] + dimensions[0]).min(target_dimensions[0]).max(min[0]),
+			(origin[1] + dimensions[1]).min(target_dimensions[1]).max(min[1]),
+		];
+
+		Scissor {
+			origin: [min[0] as i32, min[1] as i32],
+			dimensions: [(max[0] - min[0]) as u32, (max[1] - min[1]) as u32],
+		}
+	}
+}
+impl Default for Region {
+	fn default() -> Self {
+		Self::full()
+	}
+}
+
+/// How something tests and writes the stencil buffer, for masks like UI clipping regions, portals, and mirrors. It only
+/// has an effect in batches whose render pass was made with a stencil buffer, which is cleared to 0 each frame.
+///
+/// Masks are usually drawn with `write`, then the masked content with `test`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StencilState {
+	/// Compared against the stencil buffer, and written by `StencilOp::Replace`.
+	pub reference: u32,
+	/// The test passes when the masked reference compares this way to the masked value in the buffer.
+	pub compare: Compare,
+	pub compare_mask: u32,
+	/// What happens to the value in the buffer where the test passes.
+	pub pass_op: StencilOp,
+	/// What happens to the value in the buffer where the test fails.
+	pub fail_op: StencilOp,
+	/// The bits of the buffer that `pass_op` and `fail_op` can change.
+	pub write_mask: u32,
+	/// Whether color is drawn where the test passes. Masks usually only write the stencil buffer.
+	pub write_color: bool,
+	/// Whether depth is written where the test passes, for meshes. Sprites never write depth.
+	pub write_depth: bool,
+}
+impl StencilState {
+	/// Writes `reference` wherever something is drawn, without drawing any color or depth.
+	pub fn write(reference: u32) -> Self {
+		Self {
+			reference: reference,
+			compare: Compare::Always,
+			compare_mask: !0,
+			pass_op: StencilOp::Replace,
+			fail_op: StencilOp::Keep,
+			write_mask: !0,
+			write_color: false,
+			write_depth: false,
+		}
+	}
+
+	/// Draws only where the buffer's value compares to `reference` this way, leaving the buffer unchanged.
+	pub fn test(compare: Compare, reference: u32) -> Self {
+		Self {
+			compare: compare,
+			pass_op: StencilOp::Keep,
+			write_color: true,
+			write_depth: true,
+			.. Self::write(reference)
+		}
+	}
+
+	/// Adds the stencil test to a depth test.
+	pub(crate) fn depth_stencil(&self, depth: DepthStencil) -> DepthStencil {
+		let stencil = Stencil {
+			compare: self.compare,
+			pass_op: self.pass_op,
+			fail_op: self.fail_op,
+			depth_fail_op: StencilOp::Keep,
+			compare_mask: Some(self.compare_mask),
+			write_mask: Some(self.write_mask),
+			reference: Some(self.reference),
+		};
+		DepthStencil {
+			depth_write: depth.depth_write && self.write_depth,
+			stencil_front: stencil.clone(),
+			stencil_back: stencil,
+			.. depth
+		}
+	}
+
+	/// The blend state to draw with, given the one used without a stencil test.
+	pub(crate) fn blend(&self, blend: AttachmentBlend) -> AttachmentBlend {
+		if self.write_color {
+			blend
+		} else {
+			AttachmentBlend { mask_red: false, mask_green: false, mask_blue: false, mask_alpha: false, .. blend }
+		}
+	}
+}
+impl Hash for StencilState {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.reference.hash(state);
+		(self.compare as u32).hash(state);
+		self.compare_mask.hash(state);
+		(self.pass_op as u32).hash(state);
+		(self.fail_op as u32).hash(state);
+		self.write_mask.hash(state);
+		self.write_color.hash(state);
+		self.write_depth.hash(state);
+	}
+}
+
+/// How many stencil states a `StencilCache` keeps. vulkano can't make the stencil reference dynamic state, so every
+/// reference value needs pipelines of its own, and a game drawing a mask for each portal or mirror would otherwise add
+/// pipelines without end.
+const MAX_STENCIL_STATES: usize = 32;
+
+/// Pipelines, or anything else made for a stencil state, keeping only the most recently used `MAX_STENCIL_STATES`.
+/// Whatever is dropped from the cache stays alive as long as the batches and command buffers still using it.
+pub(crate) struct StencilCache<K, T> {
+	entries: HashMap<K, (T, u64)>,
+	uses: u64,
+}
+impl<K: Clone + Eq + Hash, T> StencilCache<K, T> {
+	pub(crate) fn new() -> Self {
+		Self { entries: HashMap::new(), uses: 0 }
+	}
+
+	/// Returns the entry for `key`, making it with `make` if it isn't cached. Adding an entry to a full cache drops the
+	/// least recently used one.
+	pub(crate) fn get_or_insert_with(&mut self, key: K, make: impl FnOnce() -> T) -> &T {
+		self.uses += 1;
+		if !self.entries.contains_key(&key) && self.entries.len() >= MAX_STENCIL_STATES {
+			let oldest = self.entries.iter().min_by_key(|(_, (_, used))| *used).map(|(key, _)| key.clone());
+			if let Some(oldest) = oldest {
+				self.entries.remove(&oldest);
+			}
+		}
+
+		let uses = self.uses;
+		let entry = self.entries.entry(key).or_insert_with(|| (make(), uses));
+		entry.1 = uses;
+		&entry.0
+	}
+}
+
+/// Batches are recorded on worker threads, so this fails to compile if one of them stops being thread safe.
+#[allow(dead_code)]
+fn assert_thread_safe() {
+	fn send<T: Send>() {}
+	fn send_sync<T: Send + Sync>() {}
+	send::<compositor::Compositor>();
+	send::<mesh::ForwardMeshBatch>();
+	send::<mesh::MeshBatch>();
+	send::<sprite::SpriteBatch>();
+	send_sync::<mesh::MeshRenderPass>();
+	send_sync::<sprite::SpriteBatchShared>();
+	send_sync::<crate::window::WindowTarget>();
+}
+
+pub(crate) fn dynamic_state(viewport: &Region, scissor: Option<&Region>, target_dimensions: [f32; 2]) -> DynamicState {
+	DynamicState {
+		line_width: None,
+		viewports: Some(vec![viewport.viewport(target_dimensions)]),
+		scissors: Some(vec![scissor.unwrap_or(&Region::full()).scissor(target_dimensions)]),
+	}
+}
+
+/// Why a batch couldn't be created or its commands couldn't be recorded. Running out of memory is always reported as
+/// `OomError`, so the other variants are real failures, like a render target the batch can't draw to.
+#[derive(Debug)]
+pub enum BatchError {
+	OomError(OomError),
+	/// Device memory couldn't be allocated or mapped for a reason other than running out.
+	DeviceMemoryAllocError(DeviceMemoryAllocError),
+	/// The render target's images don't fit the batch's render pass.
+	FramebufferCreationError(FramebufferCreationError),
+	/// An image, like a G-buffer or a render target texture, couldn't be created at the requested size.
+	ImageCreationError(ImageCreationError),
+	BuildError(BuildError),
+	/// An upload the batch waits on, like a font atlas page, couldn't be submitted or failed on the device.
+	FlushError(FlushError),
+}
+impl fmt::Display for BatchError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			BatchError::OomError(err) => write!(f, "out of memory: {}", err),
+			BatchError::DeviceMemoryAllocError(err) => write!(f, "device memory allocation failed: {}", err),
+			BatchError::FramebufferCreationError(err) => write!(f, "framebuffer creation failed: {}", err),
+			BatchError::ImageCreationError(err) => write!(f, "image creation failed: {}", err),
+			BatchError::BuildError(err) => write!(f, "command buffer build failed: {}", err),
+			BatchError::FlushError(err) => write!(f, "upload failed: {}", err),
+		}
+	}
+}
+impl Error for BatchError {
+	fn source(&self) -> Option<&(Error + 'static)> {
+		match self {
+			BatchError::OomError(err) => Some(err),
+			BatchError::DeviceMemoryAllocError(err) => Some(err),
+			BatchError::FramebufferCreationError(err) => Some(err),
+			BatchError::ImageCreationError(err) => Some(err),
+			BatchError::BuildError(err) => Some(err),
+			BatchError::FlushError(err) => Some(err),
+		}
+	}
+}
+impl From<OomError> for BatchError {
+	fn from(val: OomError) -> Self {
+		BatchError::OomError(val)
+	}
+}
+impl From<DeviceMemoryAllocError> for BatchError {
+	fn from(val: DeviceMemoryAllocError) -> Self {
+		match val {
+			DeviceMemoryAllocError::OomError(err) => BatchError::OomError(err),
+			val => BatchError::DeviceMemoryAllocError(val),
+		}
+	}
+}
+impl From<FramebufferCreationError> for BatchError {
+	fn from(val: FramebufferCreationError) -> Self {
+		match val {
+			FramebufferCreationError::OomError(err) => BatchError::OomError(err),
+			val => BatchError::FramebufferCreationError(val),
+		}
+	}
+}
+impl From<ImageCreationError> for BatchError {
+	fn from(val: ImageCreationError) -> Self {
+		match val {
+			ImageCreationError::AllocError(err) => err.into(),
+			val => BatchError::ImageCreationError(val),
+		}
+	}
+}
+impl From<BuildError> for BatchError {
+	fn from(val: BuildError) -> Self {
+		match val {
+			BuildError::OomError(err) => BatchError::OomError(err),
+			val => BatchError::BuildError(val),
+		}
+	}
+}
+impl From<FlushError> for BatchError {
+	fn from(val: FlushError) -> Self {
+		match val {
+			FlushError::OomError(err) => BatchError::OomError(err),
+			val => BatchError::FlushError(val),
+		}
+	}
+}
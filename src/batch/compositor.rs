@@ -0,0 +1,298 @@
+mod shaders;
+
+use self::shaders::{ CompositeVertex, QUAD, composite_fs, composite_vs };
+use crate::{ ImageFramebuffer, ObjectId, RenderTarget, window::Window };
+use crate::batch::{ BatchError, Region, dynamic_state };
+use crate::batch::sprite::BlendMode;
+use crate::device::DeviceCtx;
+use crate::texture::{ Texture, is_srgb };
+use std::sync::Arc;
+use vulkano::{
+	single_pass_renderpass,
+	buffer::{ BufferUsage, ImmutableBuffer },
+	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder },
+	descriptor::{ DescriptorSet, descriptor_set::PersistentDescriptorSet },
+	framebuffer::{ Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass },
+	image::ImageViewAccess,
+	pipeline::{ GraphicsPipeline, GraphicsPipelineAbstract },
+	sampler::Sampler,
+	sync::{ GpuFuture, NowFuture },
+};
+
+/// Blends the finished images of other batches into a render target, usually the window. Each batch draws into its
+/// own `TargetTexture`, so a mesh batch, a sprite UI, and a video layer don't have to share the window's framebuffer
+/// and agree on which of them clears it.
+///
+/// Layers are drawn in the order they're added, each over the ones before it. The compositor's pass reads every
+/// layer's target, so list them in its `reads` when adding it to a `Frame`.
+pub struct Compositor {
+	device: Arc<DeviceCtx>,
+	render_pass: Arc<RenderPassAbstract + Send + Sync>,
+	/// One pipeline for each `BlendMode`, indexed by the mode.
+	pipelines: Vec<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+	vertices: Arc<ImmutableBuffer<[CompositeVertex; 6]>>,
+	sampler: Arc<Sampler>,
+	layers: Vec<CompositeLayer>,
+	framebuffers: Vec<Option<ImageFramebuffer>>,
+	target_id: ObjectId,
+	clear_color: [f32; 4],
+}
+impl Compositor {
+	pub fn new(window: &Window, target: &RenderTarget) -> Result<(Self, impl GpuFuture), BatchError> {
+		let device = window.device().device();
+		let format = target.format();
+
+		let render_pass: Arc<RenderPassAbstract + Send + Sync> =
+			Arc::new(
+				single_pass_renderpass!(
+					device.clone(),
+					attachments: { color: { load: Clear, store: Store, format: format, samples: 1, } },
+					pass: { color: [color], depth_stencil: {} }
+				).expect("failed to create render pass")
+			);
+
+		let vs = composite_vs::Shader::load(device.clone())?;
+		let fs = composite_fs::Shader::load(device.clone())?;
+		let pipelines =
+			[BlendMode::Alpha, BlendMode::Additive, BlendMode::Multiply, BlendMode::Screen].iter()
+				.map(|&mode| {
+					let pipeline: Arc<GraphicsPipelineAbstract + Send + Sync + 'static> =
+						Arc::new(
+							GraphicsPipeline::start()
+								.vertex_input_single_buffer::<CompositeVertex>()
+								.vertex_shader(vs.main_entry_point(), ())
+								.triangle_list()
+								.viewports_scissors_dynamic(1)
+								.fragment_shader(
+									fs.main_entry_point(),
+									composite_fs::SpecializationConstants {
+										ENCODE_SRGB: !is_srgb(format) as u32,
+										BLEND_MODE: mode as u32,
+									}
+								)
+								.blend_collective(mode.attachment_blend())
+								.render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+								.build(device.clone())
+								.expect("failed to create pipeline")
+						);
+					window.device().debug_names().name_pipeline(&*pipeline, &format!("composite {:?}", mode));
+					pipeline
+				})
+				.collect();
+
+		let (vertices, future) =
+			ImmutableBuffer::from_data(QUAD, BufferUsage::vertex_buffer(), window.device().queue().clone())?;
+
+		Ok((
+			Self {
+				device: window.device().clone(),
+				render_pass: render_pass,
+				pipelines: pipelines,
+				vertices: vertices,
+				sampler: Sampler::simple_repeat_linear_no_mipmap(device.clone()),
+				layers: vec![],
+				framebuffers: target.images().iter().map(|_| None).collect(),
+				target_id: target.id_root().make_id(),
+				clear_color: [0.0, 0.0, 0.0, 1.0],
+			},
+			future
+		))
+	}
+
+	/// Adds a layer over the ones already added, and returns its index for `layer_mut`.
+	pub fn add_layer(&mut self, layer: CompositeLayer) -> usize {
+		self.layers.push(layer);
+		self.layers.len() - 1
+	}
+
+	pub fn layers(&self) -> &[CompositeLayer] {
+		&self.layers
+	}
+
+	pub fn layer_mut(&mut self, index: usize) -> Option<&mut CompositeLayer> {
+		self.layers.get_mut(index)
+	}
+
+	/// Removes a layer, shifting the indices of the layers above it down by one.
+	pub fn remove_layer(&mut self, index: usize) -> CompositeLayer {
+		self.layers.remove(index)
+	}
+
+	/// The color the target is cleared to before the first layer is drawn.
+	pub fn set_clear_color(&mut self, color: [f32; 4]) {
+		self.clear_color = color;
+	}
+
+	pub fn clear_color(&self) -> [f32; 4] {
+		self.clear_color
+	}
+
+	/// Records the compositor's commands. Like the batches, this only needs the target, so it can run on a worker
+	/// thread.
+	pub fn commands(
+		&mut self,
+		target: &RenderTarget,
+		image_num: usize,
+	) -> Result<(AutoCommandBuffer, Option<impl GpuFuture>), BatchError> {
+		assert!(self.target_id.is_child_of(target.id_root()));
+
+		let image = &target.images()[image_num];
+		let framebuffer = self.framebuffers[image_num].as_ref()
+			.filter(|old| old.image.upgrade().map_or(false, |old_image| Arc::ptr_eq(image, &old_image)))
+			.map(|old| old.framebuffer.clone());
+		let framebuffer =
+			if let Some(framebuffer) = framebuffer {
+				framebuffer
+			} else {
+				let framebuffer: Arc<FramebufferAbstract + Send + Sync + 'static> =
+					Arc::new(Framebuffer::start(self.render_pass.clone()).add(image.clone())?.build()?);
+				self.framebuffers[image_num] = Some(ImageFramebuffer::new(Arc::downgrade(image), framebuffer.clone()));
+				framebuffer
+			};
+		let dimensions = [framebuffer.width() as f32, framebuffer.height() as f32];
+
+		let device = &self.device;
+		let mut command_buffer =
+			AutoCommandBufferBuilder::primary_one_time_submit(device.device().clone(), device.queue().family())?
+				.begin_render_pass(framebuffer, false, vec![self.clear_color.into()])
+				.unwrap();
+
+		for layer in self.layers.iter_mut().filter(|layer| layer.visible && layer.opacity > 0.0) {
+			let pipeline = &self.pipelines[layer.blend_mode as usize];
+			let source = &layer.source;
+			let sampler = &self.sampler;
+			let desc =
+				layer.desc
+					.get_or_insert_with(|| {
+						Arc::new(
+							PersistentDescriptorSet::start(pipeline.clone(), 0)
+								.add_sampled_image(source.clone(), sampler.clone())
+								.unwrap()
+								.build()
+								.unwrap()
+						)
+					})
+					.clone();
+
+			let effects = layer.effects.unwrap_or_default();
+			let draw =
+				composite_fs::ty::LayerDraw {
+					tint: effects.tint,
+					opacity: layer.opacity,
+					saturation: effects.saturation,
+					brightness: effects.brightness,
+					vignette: effects.vignette,
+				};
+
+			command_buffer = command_buffer
+				.draw(
+					pipeline.clone(),
+					&dynamic_state(&layer.region, None, dimensions),
+					vec![self.vertices.clone()],
+					desc,
+					draw,
+				)
+				.unwrap();
+		}
+
+		let command_buffer = command_buffer.end_render_pass().unwrap().build()?;
+		device.debug_names().name_command_buffer(&command_buffer, "compositor");
+
+		Ok((command_buffer, None::<NowFuture>))
+	}
+}
+
+/// One image blended into a `Compositor`'s target, usually a `TargetTexture` another batch draws into.
+pub struct CompositeLayer {
+	source: Arc<ImageViewAccess + Send + Sync + 'static>,
+	region: Region,
+	opacity: f32,
+	blend_mode: BlendMode,
+	effects: Option<LayerEffects>,
+	visible: bool,
+	/// Created the first time the layer is drawn, since the pipeline layout isn't known until then.
+	desc: Option<Arc<DescriptorSet + Send + Sync + 'static>>,
+}
+impl CompositeLayer {
+	/// Creates a layer stretched over the whole target, fully opaque and alpha blended.
+	pub fn new(source: &Texture) -> Self {
+		Self {
+			source: source.image().clone(),
+			region: Region::full(),
+			opacity: 1.0,
+			blend_mode: BlendMode::Alpha,
+			effects: None,
+			visible: true,
+			desc: None,
+		}
+	}
+
+	/// Shows a different image, like a new video frame's texture.
+	pub fn set_source(&mut self, source: &Texture) {
+		self.source = source.image().clone();
+		self.desc = None;
+	}
+
+	/// The part of the target the layer is stretched over, like a corner for picture-in-picture.
+	pub fn set_region(&mut self, region: Region) {
+		self.region = region;
+	}
+
+	pub fn region(&self) -> Region {
+		self.region
+	}
+
+	/// From 0.0 for invisible to 1.0 for opaque.
+	pub fn set_opacity(&mut self, opacity: f32) {
+		self.opacity = opacity.max(0.0).min(1.0);
+	}
+
+	pub fn opacity(&self) -> f32 {
+		self.opacity
+	}
+
+	pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+		self.blend_mode = blend_mode;
+	}
+
+	pub fn blend_mode(&self) -> BlendMode {
+		self.blend_mode
+	}
+
+	/// Color effects applied to the layer as it's blended. `None` draws it as it is.
+	pub fn set_effects(&mut self, effects: Option<LayerEffects>) {
+		self.effects = effects;
+	}
+
+	pub fn effects(&self) -> Option<LayerEffects> {
+		self.effects
+	}
+
+	pub fn set_visible(&mut self, visible: bool) {
+		self.visible = visible;
+	}
+
+	pub fn is_visible(&self) -> bool {
+		self.visible
+	}
+}
+
+/// Simple color grading for a `CompositeLayer`, like desaturating the scene behind a pause menu.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct LayerEffects {
+	/// Multiplied with the layer's colors. It's sRGB encoded, so `[1.0; 4]` leaves them as they are.
+	pub tint: [f32; 4],
+	/// 0.0 is grayscale and 1.0 leaves colors as they are. Higher values oversaturate.
+	pub saturation: f32,
+	/// Multiplied with the layer's linear colors after the tint.
+	pub brightness: f32,
+	/// How much the layer's corners are darkened, from 0.0 for not at all to 1.0 for black.
+	pub vignette: f32,
+}
+impl Default for LayerEffects {
+	fn default() -> Self {
+		Self { tint: [1.0; 4], saturation: 1.0, brightness: 1.0, vignette: 0.0 }
+	}
+}
+
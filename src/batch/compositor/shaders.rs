@@ -0,0 +1,76 @@
+use vulkano::impl_vertex;
+
+#[derive(Debug, Clone)]
+pub(super) struct CompositeVertex { position: [f32; 2] }
+impl_vertex!(CompositeVertex, position);
+
+/// The two triangles covering a layer's region.
+pub(super) const QUAD: [CompositeVertex; 6] = [
+	CompositeVertex { position: [0.0, 0.0] },
+	CompositeVertex { position: [1.0, 0.0] },
+	CompositeVertex { position: [0.0, 1.0] },
+	CompositeVertex { position: [0.0, 1.0] },
+	CompositeVertex { position: [1.0, 0.0] },
+	CompositeVertex { position: [1.0, 1.0] },
+];
+
+pub(super) mod composite_vs {
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		src: "#version 450
+layout(location = 0) in vec2 position;
+
+layout(location = 0) out vec2 tex_coords;
+
+void main() {
+	tex_coords = position;
+	gl_Position = vec4(position * 2 - 1, 0.0, 1.0);
+}
+"
+	}
+}
+
+pub(super) mod composite_fs {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+layout(location = 0) in vec2 tex_coords;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D layer;
+
+layout(push_constant) uniform LayerDraw {
+	vec4 tint;
+	float opacity;
+	float saturation;
+	float brightness;
+	float vignette;
+} draw;
+
+// set when the target format is linear, so the hardware won't encode to srgb for us
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+// a BlendMode, handled the same way as in the sprite shaders
+layout(constant_id = 1) const uint BLEND_MODE = 0;
+
+void main() {
+	vec4 color = texture(layer, tex_coords);
+
+	// tints are given in srgb, like every other color the user picks
+	color *= vec4(pow(draw.tint.rgb, vec3(2.2)), draw.tint.a);
+	float luma = dot(color.rgb, vec3(0.2126, 0.7152, 0.0722));
+	color.rgb = mix(vec3(luma), color.rgb, draw.saturation) * draw.brightness;
+	vec2 centered = tex_coords * 2 - 1;
+	color.rgb *= 1 - draw.vignette * smoothstep(0.5, 2.0, dot(centered, centered));
+
+	f_color = ENCODE_SRGB ? vec4(pow(color.rgb, vec3(1.0 / 2.2)), color.a) : color;
+	f_color.a *= draw.opacity;
+	if (BLEND_MODE == 1 || BLEND_MODE == 3) {
+		f_color.rgb *= f_color.a;
+	} else if (BLEND_MODE == 2) {
+		f_color.rgb = mix(vec3(1), f_color.rgb, f_color.a);
+	}
+}
+"
+	}
+}
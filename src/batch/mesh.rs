@@ -1,154 +1,732 @@
+mod forward;
+mod light;
+mod material_shader;
 mod mesh;
+mod post;
+mod quality;
+mod resolution;
 mod shaders;
 mod render_pass;
+mod voxel;
+mod water;
 
-pub use self::mesh::Mesh;
+pub use self::forward::{ ForwardMeshBatch, MAX_FORWARD_LIGHTS };
+pub use self::light::{ LightId, PointLight, PointLightShadow };
+pub use self::material_shader::{ CustomMaterial, MaterialShader };
+pub use self::mesh::{
+	DensityMap,
+	DynamicMeshError,
+	Foliage,
+	FoliageBuilder,
+	FoliageId,
+	Mesh,
+	MeshBuildError,
+	MeshBuilder,
+	MeshFromFileError,
+	MeshGroup,
+	MeshGroupBuilder,
+	MeshGroupError,
+	MeshLoadOptions,
+	MorphTarget,
+	ScatterOptions,
+	Socket,
+	SocketTransform,
+	Topology,
+	VertexFormat,
+	Wind,
+};
+pub(crate) use self::mesh::{ StaticBatcher, supports_groups };
+pub use self::post::{ Bloom, ChromaticAberration, DisplayCalibration, FilmGrain, PostProcessChain, Vignette };
+pub use self::quality::{ QualityPreset, QualitySettings };
+pub use self::resolution::{ DynamicResolution, InternalResolution, UpscaleFilter };
 pub use self::shaders::{ MeshShaders, MeshShadersError };
-pub use self::render_pass::MeshRenderPass;
-use crate::{ ObjectId, RenderTarget, window::Window };
+pub use self::render_pass::{ MeshRenderPass, MeshRenderPassOptions };
+pub use self::voxel::{ CHUNK_SIZE, Voxel, VoxelHit, VoxelWorld, VoxelWorldId };
+pub use self::water::{ GerstnerWave, MAX_WAVES, Water, WaterId };
+use self::light::{
+	CLUSTER_BUFFER_LEN,
+	CLUSTER_GRID,
+	LightData,
+	MAX_LIGHTS,
+	ShadowSlot,
+	cluster_slices,
+	pack_shadows,
+};
+use self::render_pass::GBufferStage;
+use self::resolution::ResolutionController;
+use self::water::WATER_GRID;
+use crate::{ ObjectId, RenderTarget };
+use crate::batch::{ BatchError, Region, dynamic_state };
+use crate::spatial::{ Cast, Hit };
 use crate::camera::Camera;
-use cgmath::{ vec4, Vector4 };
-use std::sync::Arc;
+use crate::device::{ MemoryCategory, MemoryHandle, TransientAttachment, TransientUsage, image_bytes };
+use crate::texture::{ ColorLut, CubeFace, TargetCubemap, Texture };
+use cgmath::{ vec3, vec4, InnerSpace, Quaternion, Vector3, Vector4 };
+use std::{ cmp::Ordering, iter, mem::{ self, size_of }, sync::Arc };
 use vulkano::{
 	impl_vertex,
-	buffer::{ BufferUsage, ImmutableBuffer },
-	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, DynamicState },
+	buffer::{ BufferUsage, CpuBufferPool, DeviceLocalBuffer, ImmutableBuffer, cpu_pool::CpuBufferPoolChunk },
+	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder },
 	descriptor::{ DescriptorSet, descriptor_set::{ FixedSizeDescriptorSetsPool, PersistentDescriptorSet } },
-	device::Device,
 	format::{ ClearValue, Format },
-	framebuffer::{ Framebuffer, FramebufferCreationError },
-	image::{ AttachmentImage, ImageCreationError, ImageViewAccess },
-	memory::{ DeviceMemoryAllocError },
-	pipeline::{ GraphicsPipelineAbstract, viewport::Viewport },
+	framebuffer::{ Framebuffer, FramebufferAbstract, RenderPassAbstract },
+	image::{ AttachmentImage, ImageViewAccess },
+	memory::{ DeviceMemoryAllocError, pool::StdMemoryPool },
+	pipeline::{ ComputePipelineAbstract, GraphicsPipelineAbstract },
 	sync::GpuFuture,
 };
 
 const ALBEDO_FORMAT: Format = Format::A2B10G10R10UnormPack32;
 const NORMAL_FORMAT: Format = Format::R32G32B32A32Sfloat;
+const VELOCITY_FORMAT: Format = Format::R16G16Sfloat;
+const SELECTION_FORMAT: Format = Format::R8Unorm;
+const EMISSIVE_FORMAT: Format = Format::R16Sfloat;
 const DEPTH_FORMAT: Format = Format::D16Unorm;
+const REVERSE_Z_DEPTH_FORMAT: Format = Format::D32Sfloat;
+const SHADOW_FORMAT: Format = Format::D16Unorm;
+
+/// The index of the post processing descriptor sets that read the motion blurred image.
+const MOTION_BLUR_SOURCE: usize = 2;
 
+/// Draws meshes to a render target. The per-frame state lives here and the pipelines live in the shared
+/// `MeshRenderPass`, so batches are `Send` and can each be recorded on their own thread.
 pub struct MeshBatch {
 	render_pass: Arc<MeshRenderPass>,
-	meshes: Vec<Mesh>,
+	meshes: Vec<(MeshId, Mesh)>,
+	next_mesh_id: u64,
+	groups: Vec<(MeshGroupId, MeshGroup)>,
+	next_group_id: u64,
+	foliage: Vec<(FoliageId, Foliage)>,
+	next_foliage_id: u64,
+	voxel_worlds: Vec<(VoxelWorldId, VoxelWorld)>,
+	next_voxel_world_id: u64,
 	target_id: ObjectId,
 	gbuffers: GBuffers,
 	camera_desc_pool_gbuffers: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
 	camera_desc_pool_history: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
-	mesh_desc_pool: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+	camera_position_pool: CpuBufferPool<Vector3<f32>>,
+	camera_rotation_pool: CpuBufferPool<Quaternion<f32>>,
+	camera_projection_pool: CpuBufferPool<[Vector4<f32>; 2]>,
+	/// The cameras from the last time the batch was recorded, for motion vectors.
+	previous_cameras: Vec<Camera>,
+	color_lut_desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	color_grading_blend: f32,
+	outline_color: [f32; 4],
+	outline_thickness: f32,
+	post_process: PostProcessChain,
+	lights: Vec<(LightId, PointLight)>,
+	next_light_id: u64,
+	max_shadowed_lights: usize,
+	light_pool: CpuBufferPool<LightData>,
+	light_desc_pool: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+	light_cull_desc_pool: FixedSizeDescriptorSetsPool<Arc<ComputePipelineAbstract + Send + Sync + 'static>>,
+	/// The lights reaching each cluster, for each camera the batch has been recorded with at once.
+	clusters: Vec<(Arc<DeviceLocalBuffer<[u32]>>, MemoryHandle)>,
+	waters: Vec<(WaterId, Water)>,
+	next_water_id: u64,
+	water_pool: CpuBufferPool<u8>,
+	water_camera_desc_pool: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+	water_desc_pool: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+	/// Created the first time a light casts shadows.
+	shadow_atlas: Option<(Arc<AttachmentImage>, MemoryHandle)>,
+	/// Counts recorded frames, so film grain changes every frame.
+	frame: u32,
+	resolution: ResolutionController,
+	viewport: Region,
+	scissor: Option<Region>,
 }
 impl MeshBatch {
 	pub fn new(
 		target: &RenderTarget,
 		render_pass: Arc<MeshRenderPass>
-	) -> Result<(Self, impl GpuFuture), DeviceMemoryAllocError> {
+	) -> Result<(Self, impl GpuFuture), BatchError> {
 		let camera_desc_pool_gbuffers = FixedSizeDescriptorSetsPool::new(render_pass.pipeline_gbuffers.clone(), 0);
 		let camera_desc_pool_history = FixedSizeDescriptorSetsPool::new(render_pass.pipeline_history.clone(), 1);
-		let mesh_desc_pool = FixedSizeDescriptorSetsPool::new(render_pass.pipeline_gbuffers.clone(), 1);
-		let (gbuffers, future) = Self::make_gbuffers(target, &render_pass)?;
+		let light_desc_pool = FixedSizeDescriptorSetsPool::new(render_pass.pipeline_history.clone(), 2);
+		let light_cull_desc_pool = FixedSizeDescriptorSetsPool::new(render_pass.pipeline_light_cull.clone(), 0);
+		let water_camera_desc_pool = FixedSizeDescriptorSetsPool::new(render_pass.pipeline_water.clone(), 0);
+		let water_desc_pool = FixedSizeDescriptorSetsPool::new(render_pass.pipeline_water.clone(), 1);
+		let device = render_pass.shaders.target_vertices.device().clone();
+		let dimensions = target.images()[0].dimensions().width_height();
+		let (gbuffers, future) = Self::make_gbuffers(target, &render_pass, dimensions)?;
+		let color_lut_desc = Self::make_color_lut_desc(&render_pass, render_pass.shaders.lut_identity.clone());
 
 		Ok((
 			Self {
 				render_pass: render_pass,
 				meshes: vec![],
+				next_mesh_id: 0,
+				groups: vec![],
+				next_group_id: 0,
+				foliage: vec![],
+				next_foliage_id: 0,
+				voxel_worlds: vec![],
+				next_voxel_world_id: 0,
 				target_id: target.id_root().make_id(),
 				gbuffers: gbuffers,
 				camera_desc_pool_gbuffers: camera_desc_pool_gbuffers,
 				camera_desc_pool_history: camera_desc_pool_history,
-				mesh_desc_pool: mesh_desc_pool,
+				camera_position_pool: CpuBufferPool::uniform_buffer(device.clone()),
+				camera_rotation_pool: CpuBufferPool::uniform_buffer(device.clone()),
+				camera_projection_pool: CpuBufferPool::uniform_buffer(device.clone()),
+				previous_cameras: vec![],
+				color_lut_desc: color_lut_desc,
+				color_grading_blend: 0.0,
+				outline_color: [1.0, 0.6, 0.1, 1.0],
+				outline_thickness: 2.0,
+				post_process: PostProcessChain::default(),
+				lights: vec![],
+				next_light_id: 0,
+				max_shadowed_lights: 4,
+				light_pool:
+					CpuBufferPool::new(device.clone(), BufferUsage { storage_buffer: true, .. BufferUsage::none() }),
+				light_desc_pool: light_desc_pool,
+				light_cull_desc_pool: light_cull_desc_pool,
+				clusters: vec![],
+				waters: vec![],
+				next_water_id: 0,
+				water_pool: CpuBufferPool::new(device, BufferUsage::uniform_buffer()),
+				water_camera_desc_pool: water_camera_desc_pool,
+				water_desc_pool: water_desc_pool,
+				shadow_atlas: None,
+				frame: 0,
+				resolution: ResolutionController::new(),
+				viewport: Region::full(),
+				scissor: None,
 			},
 			future
 		))
 	}
 
-	pub fn add_mesh(&mut self, mesh: Mesh) {
-		self.meshes.push(mesh);
+	pub fn add_mesh(&mut self, mesh: Mesh) -> MeshId {
+		let id = MeshId(self.next_mesh_id);
+		self.next_mesh_id += 1;
+		self.meshes.push((id, mesh));
+		id
+	}
+
+	pub fn mesh(&self, id: MeshId) -> Option<&Mesh> {
+		self.meshes.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| &self.meshes[i].1)
+	}
+
+	pub fn mesh_mut(&mut self, id: MeshId) -> Option<&mut Mesh> {
+		self.meshes.binary_search_by_key(&id, |&(id, _)| id).ok().map(move |i| &mut self.meshes[i].1)
+	}
+
+	/// Where one of a mesh's sockets is in world space, or `None` if the mesh or the socket isn't there.
+	pub fn socket_transform(&self, id: MeshId, name: &str) -> Option<SocketTransform> {
+		self.mesh(id)?.socket_transform(name)
+	}
+
+	/// Takes a mesh out of the batch. Its id isn't reused, so other handles to it just stop finding it. To hide a mesh
+	/// for a while, `Mesh::set_visible` is cheaper. Once the mesh is dropped, its buffers and descriptors are released
+	/// as soon as the frames that might still draw it have finished.
+	pub fn remove_mesh(&mut self, id: MeshId) -> Option<Mesh> {
+		self.meshes.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| self.meshes.remove(i).1)
+	}
+
+	/// The batch's meshes, in the order they were added, including hidden ones.
+	pub fn meshes(&self) -> impl Iterator<Item = (MeshId, &Mesh)> {
+		self.meshes.iter().map(|(id, mesh)| (*id, mesh))
+	}
+
+	pub fn meshes_mut(&mut self) -> impl Iterator<Item = (MeshId, &mut Mesh)> {
+		self.meshes.iter_mut().map(|(id, mesh)| (*id, mesh))
+	}
+
+	pub fn mesh_count(&self) -> usize {
+		self.meshes.len()
+	}
+
+	/// Casts a ray or sphere in world space against the visible meshes that have a bounding volume hierarchy, returning
+	/// the closest hit and the mesh it hit.
+	pub fn cast(&self, cast: &Cast) -> Option<(MeshId, Hit)> {
+		let mut closest: Option<(MeshId, Hit)> = None;
+		for (id, mesh) in self.meshes.iter().filter(|(_, mesh)| mesh.is_visible()) {
+			let max_distance = closest.map_or(cast.max_distance, |(_, hit)| hit.distance);
+			if let Some(hit) = mesh.cast(&Cast { max_distance: max_distance, .. *cast }) {
+				closest = Some((*id, hit));
+			}
+		}
+		closest
+	}
+
+	/// Whether a ray or sphere in world space hits any visible mesh that has a bounding volume hierarchy.
+	pub fn cast_any(&self, cast: &Cast) -> bool {
+		self.meshes.iter().any(|(_, mesh)| mesh.is_visible() && mesh.cast_any(cast))
+	}
+
+	/// Adds a group of static objects, which are culled on the GPU and drawn with a single indirect draw.
+	pub fn add_group(&mut self, group: MeshGroup) -> MeshGroupId {
+		let id = MeshGroupId(self.next_group_id);
+		self.next_group_id += 1;
+		self.groups.push((id, group));
+		id
+	}
+
+	pub fn group(&self, id: MeshGroupId) -> Option<&MeshGroup> {
+		self.groups.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| &self.groups[i].1)
+	}
+
+	pub fn group_mut(&mut self, id: MeshGroupId) -> Option<&mut MeshGroup> {
+		self.groups.binary_search_by_key(&id, |&(id, _)| id).ok().map(move |i| &mut self.groups[i].1)
+	}
+
+	pub fn remove_group(&mut self, id: MeshGroupId) -> Option<MeshGroup> {
+		self.groups.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| self.groups.remove(i).1)
+	}
+
+	/// Adds foliage, which is drawn with the meshes and casts shadows like them.
+	pub fn add_foliage(&mut self, foliage: Foliage) -> FoliageId {
+		let id = FoliageId(self.next_foliage_id);
+		self.next_foliage_id += 1;
+		self.foliage.push((id, foliage));
+		id
+	}
+
+	pub fn foliage(&self, id: FoliageId) -> Option<&Foliage> {
+		self.foliage.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| &self.foliage[i].1)
+	}
+
+	pub fn foliage_mut(&mut self, id: FoliageId) -> Option<&mut Foliage> {
+		self.foliage.binary_search_by_key(&id, |&(id, _)| id).ok().map(move |i| &mut self.foliage[i].1)
+	}
+
+	pub fn remove_foliage(&mut self, id: FoliageId) -> Option<Foliage> {
+		self.foliage.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| self.foliage.remove(i).1)
+	}
+
+	/// Advances the wind of all the foliage in the batch.
+	pub fn advance_foliage(&mut self, seconds: f32) {
+		for (_, foliage) in &mut self.foliage {
+			foliage.advance(seconds);
+		}
+	}
+
+	/// Adds a voxel world, whose chunks are drawn with the meshes. Edit it through `voxel_world_mut`, then call
+	/// `VoxelWorld::remesh` to rebuild the chunks that changed.
+	pub fn add_voxel_world(&mut self, world: VoxelWorld) -> VoxelWorldId {
+		let id = VoxelWorldId(self.next_voxel_world_id);
+		self.next_voxel_world_id += 1;
+		self.voxel_worlds.push((id, world));
+		id
+	}
+
+	pub fn voxel_world(&self, id: VoxelWorldId) -> Option<&VoxelWorld> {
+		self.voxel_worlds.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| &self.voxel_worlds[i].1)
+	}
+
+	pub fn voxel_world_mut(&mut self, id: VoxelWorldId) -> Option<&mut VoxelWorld> {
+		self.voxel_worlds.binary_search_by_key(&id, |&(id, _)| id).ok().map(move |i| &mut self.voxel_worlds[i].1)
+	}
+
+	pub fn remove_voxel_world(&mut self, id: VoxelWorldId) -> Option<VoxelWorld> {
+		self.voxel_worlds.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| self.voxel_worlds.remove(i).1)
+	}
+
+	pub fn add_light(&mut self, light: PointLight) -> LightId {
+		let id = LightId(self.next_light_id);
+		self.next_light_id += 1;
+		self.lights.push((id, light));
+		id
+	}
+
+	pub fn light(&self, id: LightId) -> Option<&PointLight> {
+		self.lights.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| &self.lights[i].1)
 	}
 
+	pub fn light_mut(&mut self, id: LightId) -> Option<&mut PointLight> {
+		self.lights.binary_search_by_key(&id, |&(id, _)| id).ok().map(move |i| &mut self.lights[i].1)
+	}
+
+	pub fn remove_light(&mut self, id: LightId) -> Option<PointLight> {
+		self.lights.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| self.lights.remove(i).1)
+	}
+
+	/// Adds a water plane, which is drawn over the meshes after they're lit.
+	pub fn add_water(&mut self, water: Water) -> WaterId {
+		let id = WaterId(self.next_water_id);
+		self.next_water_id += 1;
+		self.waters.push((id, water));
+		id
+	}
+
+	pub fn water(&self, id: WaterId) -> Option<&Water> {
+		self.waters.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| &self.waters[i].1)
+	}
+
+	pub fn water_mut(&mut self, id: WaterId) -> Option<&mut Water> {
+		self.waters.binary_search_by_key(&id, |&(id, _)| id).ok().map(move |i| &mut self.waters[i].1)
+	}
+
+	pub fn remove_water(&mut self, id: WaterId) -> Option<Water> {
+		self.waters.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| self.waters.remove(i).1)
+	}
+
+	/// Advances the animation of every water plane in the batch.
+	pub fn advance_water(&mut self, seconds: f32) {
+		for (_, water) in &mut self.waters {
+			water.advance(seconds);
+		}
+	}
+
+	/// Limits how many lights cast shadows each frame. Of the lights with shadows, the ones nearest the first camera
+	/// get them. Each one renders the batch six more times.
+	pub fn set_max_shadowed_lights(&mut self, count: usize) {
+		self.max_shadowed_lights = count;
+	}
+
+	pub fn max_shadowed_lights(&self) -> usize {
+		self.max_shadowed_lights
+	}
+
+	/// Sets the region of the target that the camera's view is mapped to.
+	pub fn set_viewport(&mut self, viewport: Region) {
+		self.viewport = viewport;
+	}
+
+	/// Restricts drawing to a region of the target. `None` allows drawing to the whole target.
+	pub fn set_scissor(&mut self, scissor: Option<Region>) {
+		self.scissor = scissor;
+	}
+
+	/// Grades the final colors with a lookup table, after tonemapping. `None` removes the grading. The blend is how much
+	/// of the graded color is used, from 0.0 to 1.0, so a grade can be faded in or out.
+	pub fn set_color_grading(&mut self, lut: Option<&ColorLut>, blend: f32) {
+		let image = lut.map_or_else(|| self.render_pass.shaders.lut_identity.clone(), |lut| lut.image().clone());
+		let color_lut_desc = Self::make_color_lut_desc(&self.render_pass, image);
+		let old_color_lut_desc = mem::replace(&mut self.color_lut_desc, color_lut_desc);
+		self.render_pass.shaders.device_ctx.defer_destroy(old_color_lut_desc);
+		self.color_grading_blend = if lut.is_some() { blend.max(0.0).min(1.0) } else { 0.0 };
+	}
+
+	/// Changes how much of the color grading is applied, without changing the lookup table.
+	pub fn set_color_grading_blend(&mut self, blend: f32) {
+		self.color_grading_blend = blend.max(0.0).min(1.0);
+	}
+
+	/// Sets how selected meshes are outlined. The color is sRGB encoded, and its alpha is the outline's opacity. The
+	/// thickness is in pixels, and 0.0 hides outlines.
+	pub fn set_outline(&mut self, color: [f32; 4], thickness: f32) {
+		self.outline_color = color;
+		self.outline_thickness = thickness.max(0.0);
+	}
+
+	pub fn set_post_process(&mut self, post_process: PostProcessChain) {
+		self.post_process = post_process;
+	}
+
+	pub fn post_process(&self) -> &PostProcessChain {
+		&self.post_process
+	}
+
+	/// Renders the 3D passes at a resolution that adapts to the frame rate, upscaled to the target with the render
+	/// pass's `UpscaleFilter`. `None` renders at the render pass's `InternalResolution`.
+	pub fn set_dynamic_resolution(&mut self, dynamic_resolution: Option<DynamicResolution>) {
+		self.resolution.set_settings(dynamic_resolution);
+	}
+
+	pub fn dynamic_resolution(&self) -> Option<DynamicResolution> {
+		self.resolution.settings
+	}
+
+	/// The dynamic resolution scale that the next frame will render at, relative to the internal resolution.
+	pub fn render_scale(&self) -> f32 {
+		self.resolution.scale
+	}
+
+	/// Records the batch's commands. This only needs the batch and the target, so it can run on a worker thread, with a
+	/// `WindowTarget` standing in for the window.
 	pub fn commands(
 		&mut self,
-		window: &Window,
 		target: &RenderTarget,
 		image_num: usize,
 		camera: &Camera,
-	) -> Result<(AutoCommandBuffer, Option<impl GpuFuture>), DeviceMemoryAllocError> {
+	) -> Result<(AutoCommandBuffer, Option<impl GpuFuture>), BatchError> {
+		let viewport = self.viewport;
+		self.commands_multi(target, image_num, &[(camera, viewport)])
+	}
+
+	/// Renders every face of a cube map from the camera's position, with a camera from `CubeFace::camera` for each, and
+	/// returns the commands for each face in the order of `CubeFace::ALL`. Effects that depend on the previous frame,
+	/// like motion blur, should be disabled on the camera.
+	pub fn commands_cubemap(
+		&mut self,
+		target: &TargetCubemap,
+		camera: &Camera,
+	) -> Result<Vec<(AutoCommandBuffer, Option<impl GpuFuture>)>, BatchError> {
+		CubeFace::ALL.iter()
+			.map(|&face| self.commands(target, face as usize, &face.camera(camera)))
+			.collect()
+	}
+
+	/// Experimental. Renders the batch for both eyes of a headset, side by side in a double wide target such as a
+	/// `StereoTarget`. The cameras are usually made with `StereoFrame::cameras`.
+	pub fn commands_stereo(
+		&mut self,
+		target: &RenderTarget,
+		image_num: usize,
+		cameras: &[Camera; 2],
+	) -> Result<(AutoCommandBuffer, Option<impl GpuFuture>), BatchError> {
+		let left = Region::Normalized { origin: [0.0, 0.0], dimensions: [0.5, 1.0] };
+		let right = Region::Normalized { origin: [0.5, 0.0], dimensions: [0.5, 1.0] };
+		self.commands_multi(target, image_num, &[(&cameras[0], left), (&cameras[1], right)])
+	}
+
+	/// Renders the batch once for each camera, into that camera's region of the target, within a single render pass.
+	/// This is the intended path for split-screen rendering. Meshes are frustum culled once for all the cameras.
+	pub fn commands_multi(
+		&mut self,
+		target: &RenderTarget,
+		image_num: usize,
+		cameras: &[(&Camera, Region)],
+	) -> Result<(AutoCommandBuffer, Option<impl GpuFuture>), BatchError> {
 		assert!(self.target_id.is_child_of(target.id_root()));
+		let reverse_z = self.render_pass.reverse_z();
+		assert!(cameras.iter().all(|(camera, _)| camera.reverse_z() == reverse_z), "camera depth mode doesn't match");
 
+		self.resolution.tick();
 		let image = &target.images()[image_num];
+		let dimensions = [image.dimensions().width() as f32, image.dimensions().height() as f32];
+		let base_scale = self.render_pass.internal_resolution().scale(dimensions);
+		let max_scale = self.resolution.max_scale();
+		let gbuffer_dimensions = [
+			(dimensions[0] * base_scale[0] * max_scale).ceil().max(1.0) as u32,
+			(dimensions[1] * base_scale[1] * max_scale).ceil().max(1.0) as u32,
+		];
 		let gbuffers_future =
-			if image.dimensions() != self.gbuffers.color.dimensions() {
-				let (gbuffers, gbuffers_future) = Self::make_gbuffers(target, &self.render_pass)?;
-				self.gbuffers = gbuffers;
+			if gbuffer_dimensions != self.gbuffers.dimensions {
+				let (gbuffers, gbuffers_future) = Self::make_gbuffers(target, &self.render_pass, gbuffer_dimensions)?;
+				let old_gbuffers = mem::replace(&mut self.gbuffers, gbuffers);
+				self.render_pass.shaders.device_ctx.defer_destroy(old_gbuffers);
 				Some(gbuffers_future)
 			} else {
 				None
 			};
 
-		let camera_desc_gbuffers =
-			Arc::new(
-				self.camera_desc_pool_gbuffers.next()
-					.add_buffer(camera.position_buffer.clone())
-					.unwrap()
-					.add_buffer(camera.rotation_buffer.clone())
-					.unwrap()
-					.add_buffer(camera.projection_buffer.clone())
-					.unwrap()
-					.build()
-					.unwrap()
-			);
-
-		let dimensions = [image.dimensions().width() as f32, image.dimensions().height() as f32];
+		// the passes before the target pass draw into the top left corner of the gbuffers, at the current scale
+		let scale = [base_scale[0] * self.resolution.scale, base_scale[1] * self.resolution.scale];
+		let internal_dimensions = [dimensions[0] * scale[0], dimensions[1] * scale[1]];
+		let dynamic_states = cameras.iter()
+			.map(|(_, viewport)| {
+				let scissor = self.scissor.as_ref().unwrap_or(viewport).scaled(dimensions, scale);
+				dynamic_state(&viewport.scaled(dimensions, scale), Some(&scissor), internal_dimensions)
+			})
+			.collect::<Vec<_>>();
+		let target_dynamic_states = cameras.iter()
+			.map(|(_, viewport)| dynamic_state(viewport, Some(self.scissor.as_ref().unwrap_or(viewport)), dimensions))
+			.collect::<Vec<_>>();
 
 		let history_index = self.gbuffers.history_index as usize;
 		self.gbuffers.history_index = !self.gbuffers.history_index;
 
+		let device = self.render_pass.shaders.device_ctx.clone();
 		let mut command_buffer =
-			AutoCommandBufferBuilder
-				::primary_one_time_submit(
-					self.render_pass.shaders.target_vertices.device().clone(),
-					window.device().queue().family()
-				)?
+			AutoCommandBufferBuilder::primary_one_time_submit(device.device().clone(), device.queue().family())?;
+
+		for (_, mesh) in &mut self.meshes {
+			command_buffer = mesh.record_uploads(command_buffer)?;
+		}
+
+		// the lights nearest the first camera light the frame, and the nearest of those with shadows cast them
+		let eye = cameras.first().map_or(vec3(0.0, 0.0, 0.0), |(camera, _)| camera.position());
+		let mut lights = self.lights.iter().map(|&(_, light)| light).collect::<Vec<_>>();
+		lights.sort_by(|a, b| light_priority(a, eye).partial_cmp(&light_priority(b, eye)).unwrap_or(Ordering::Equal));
+		lights.truncate(MAX_LIGHTS);
+		let quality = self.render_pass.quality();
+		let max_shadowed_lights = if quality.shadow_resolution == 0 { 0 } else { self.max_shadowed_lights };
+		let shadowed = lights.iter()
+			.enumerate()
+			.filter_map(|(i, light)| light.shadow.map(|shadow| (i, shadow.resolution)))
+			.take(max_shadowed_lights)
+			.collect::<Vec<_>>();
+		let resolutions = shadowed.iter().map(|&(_, resolution)| resolution).collect::<Vec<_>>();
+		let slots = pack_shadows(&resolutions, quality.shadow_resolution);
+		let shadows = shadowed.iter()
+			.zip(slots)
+			.filter_map(|(&(i, _), slot)| slot.map(|slot| (i, slot)))
+			.collect::<Vec<_>>();
+		let mut command_buffer = self.record_shadows(command_buffer, &lights, &shadows, quality.shadow_resolution)?;
+		let (light_buffer, shadow_atlas) = self.upload_lights(&lights, &shadows)?;
+
+		// cameras are plain state, so their uniforms are uploaded fresh each time the batch is recorded
+		let camera_buffers = cameras.iter()
+			.map(|(camera, _)| -> Result<_, DeviceMemoryAllocError> {
+				Ok((
+					self.camera_position_pool.next(camera.position())?,
+					self.camera_rotation_pool.next(camera.rotation())?,
+					self.camera_projection_pool.next(camera.projection_uniform())?,
+				))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		// each camera's clusters are filled before the render pass, so its history pass can read them
+		while self.clusters.len() < cameras.len() {
+			self.clusters.push(Self::make_clusters(&self.render_pass)?);
+		}
+		let camera_clusters = cameras.iter().zip(&camera_buffers).zip(&self.clusters);
+		for (((camera, _), (position, rotation, projection)), (clusters, _)) in camera_clusters {
+			let (znear, zfar) = camera.depth_range();
+			command_buffer = command_buffer
+				.dispatch(
+					[1, 1, CLUSTER_GRID[2]],
+					self.render_pass.pipeline_light_cull.clone(),
+					self.light_cull_desc_pool.next()
+						.add_buffer(position.clone())
+						.unwrap()
+						.add_buffer(rotation.clone())
+						.unwrap()
+						.add_buffer(projection.clone())
+						.unwrap()
+						.add_buffer(light_buffer.clone())
+						.unwrap()
+						.add_buffer(clusters.clone())
+						.unwrap()
+						.build()
+						.unwrap(),
+					shaders::cs_light_cull::ty::Cull {
+						slices: cluster_slices(znear, zfar),
+						light_count: lights.len() as u32,
+					}
+				)
+				.unwrap();
+		}
+
+		// mesh groups list their visible draws for each camera before the render pass, like the clusters
+		for (i, (camera, _)) in cameras.iter().enumerate() {
+			let planes = camera.frustum_planes();
+			for (_, group) in &mut self.groups {
+				command_buffer = group.record_cull(command_buffer, i, planes)?;
+			}
+		}
+
+		let mut command_buffer =
+			command_buffer
 				.begin_render_pass(
 					Arc::new(
 						Framebuffer::start(self.render_pass.render_pass().clone())
 							.add(self.gbuffers.color.clone())
 							.and_then(|fb| fb.add(self.gbuffers.normal.clone()))
+							.and_then(|fb| fb.add(self.gbuffers.velocity.clone()))
+							.and_then(|fb| fb.add(self.gbuffers.selection.clone()))
+							.and_then(|fb| fb.add(self.gbuffers.emissive.clone()))
 							.and_then(|fb| fb.add(self.gbuffers.depth.clone()))
 							.and_then(|fb| fb.add(self.gbuffers.history[history_index].clone()))
-							.and_then(|fb| fb.add(image.clone()))
-							.and_then(|fb| fb.build())
-							.map_err(|err| match err {
-								FramebufferCreationError::OomError(err) => err,
-								err => unreachable!("{:?}", err),
-							})?
+							.and_then(|fb| fb.build())?
 					),
-					true,
-					vec![[0.0, 0.0, 0.0, 1.0].into(), [0.0; 4].into(), 1.0.into(), ClearValue::None, ClearValue::None]
+					false,
+					vec![
+						[0.0, 0.0, 0.0, 1.0].into(),
+						[0.0; 4].into(),
+						[0.0; 4].into(),
+						[0.0; 4].into(),
+						[0.0; 4].into(),
+						if self.render_pass.has_stencil() {
+							ClearValue::DepthStencil((if reverse_z { 0.0 } else { 1.0 }, 0))
+						} else {
+							(if reverse_z { 0.0 } else { 1.0 }).into()
+						},
+						ClearValue::None
+					]
 				)
 				.unwrap();
 
-		for mesh in &mut self.meshes {
-			command_buffer =
-				unsafe {
-					command_buffer
-						.execute_commands(
-							mesh.make_commands(
-								&self.render_pass,
-								camera_desc_gbuffers.clone(),
-								&mut self.mesh_desc_pool,
-								window.device().queue().family(),
-								dimensions
-							)?
-						)
+		let stages: &[GBufferStage] =
+			if self.render_pass.depth_prepass() {
+				&[GBufferStage::DepthPrepass, GBufferStage::GBuffersAfterPrepass]
+			} else {
+				&[GBufferStage::GBuffers]
+			};
+
+		// a camera without a previous frame is its own previous camera, so it has no motion
+		let previous_camera_buffers = cameras.iter()
+			.enumerate()
+			.map(|(i, (camera, _))| -> Result<_, DeviceMemoryAllocError> {
+				let camera = self.previous_cameras.get(i).unwrap_or(*camera);
+				Ok((
+					self.camera_position_pool.next(camera.position())?,
+					self.camera_rotation_pool.next(camera.rotation())?,
+					self.camera_projection_pool.next(camera.projection_uniform())?,
+				))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		// meshes are culled once against the union of the cameras' frusta, so each camera draws every mesh any of them
+		// can see, and the scissor keeps them to their own region
+		let frusta = cameras.iter().map(|(camera, _)| camera.frustum_planes()).collect::<Vec<_>>();
+		let on_screen = self.meshes.iter()
+			.map(|(_, mesh)| {
+				mesh.world_bounds().map_or(true, |bounds| frusta.iter().any(|planes| bounds.intersects_planes(planes)))
+			})
+			.collect::<Vec<_>>();
+
+		let camera_buffers_gbuffers = camera_buffers.iter().zip(&previous_camera_buffers).zip(&dynamic_states);
+		for (i, (((position, rotation, projection), previous), dynamic_state)) in camera_buffers_gbuffers.enumerate() {
+			let (previous_position, previous_rotation, previous_projection) = previous;
+			let camera_desc_gbuffers =
+				Arc::new(
+					self.camera_desc_pool_gbuffers.next()
+						.add_buffer(position.clone())
 						.unwrap()
-				};
-		}
+						.add_buffer(rotation.clone())
+						.unwrap()
+						.add_buffer(projection.clone())
+						.unwrap()
+						.add_buffer(previous_position.clone())
+						.unwrap()
+						.add_buffer(previous_rotation.clone())
+						.unwrap()
+						.add_buffer(previous_projection.clone())
+						.unwrap()
+						.build()
+						.unwrap()
+				);
 
-		let dynamic_state =
-			DynamicState {
-				line_width: None,
-				viewports: Some(vec![Viewport { origin: [0.0, 0.0], dimensions: dimensions, depth_range: 0.0..1.0 }]),
-				scissors: None,
-			};
+			for &stage in stages {
+				for ((_, mesh), _) in self.meshes.iter_mut().zip(&on_screen).filter(|(_, &on_screen)| on_screen) {
+					command_buffer =
+						mesh.add_commands(
+							command_buffer,
+							&self.render_pass,
+							camera_desc_gbuffers.clone(),
+							dynamic_state,
+							stage,
+						)?;
+				}
+				for (_, group) in &mut self.groups {
+					command_buffer =
+						group.add_commands(
+							command_buffer,
+							&self.render_pass,
+							camera_desc_gbuffers.clone(),
+							dynamic_state,
+							stage,
+							Some(i),
+						);
+				}
+				for (_, foliage) in &mut self.foliage {
+					command_buffer =
+						foliage.add_commands(
+							command_buffer,
+							&self.render_pass,
+							camera_desc_gbuffers.clone(),
+							dynamic_state,
+							stage,
+							cameras[i].0,
+						);
+				}
+				for (_, world) in &mut self.voxel_worlds {
+					command_buffer =
+						world.add_commands(
+							command_buffer,
+							&self.render_pass,
+							camera_desc_gbuffers.clone(),
+							dynamic_state,
+							stage,
+						)?;
+				}
+			}
+		}
 
 		let history_desc =
 			if self.gbuffers.history_initialized {
@@ -166,104 +744,575 @@ impl MeshBatch {
 						.unwrap()
 						.add_image(self.gbuffers.depth.clone())
 						.unwrap()
+						.add_image(self.gbuffers.emissive.clone())
+						.unwrap()
 						.build()
 						.unwrap()
 				)
 			};
-		let command_buffer = command_buffer.next_subpass(false)
-			.unwrap()
-			.draw(
-				self.render_pass.pipeline_history.clone(),
-				&dynamic_state,
-				vec![self.render_pass.shaders.target_vertices.clone()],
-				(
-					history_desc,
-					self.camera_desc_pool_history.next()
-						.add_buffer(camera.position_buffer.clone())
+
+		let mut command_buffer = command_buffer.next_subpass(false).unwrap();
+		let camera_regions = cameras.iter().zip(&camera_buffers).zip(&dynamic_states).zip(&self.clusters);
+		for ((((camera, viewport), (position, rotation, projection)), dynamic_state), (clusters, _)) in camera_regions {
+			let (viewport_origin, viewport_dimensions) = viewport.scaled(dimensions, scale).pixels(internal_dimensions);
+			let (znear, zfar) = camera.depth_range();
+
+			self.render_pass.shaders.device_ctx.render_stats().record_draw(2);
+			command_buffer = command_buffer
+				.draw(
+					self.render_pass.pipeline_history.clone(),
+					dynamic_state,
+					vec![self.render_pass.shaders.target_vertices.clone()],
+					(
+						history_desc.clone(),
+						self.camera_desc_pool_history.next()
+							.add_buffer(position.clone())
+							.unwrap()
+							.add_buffer(rotation.clone())
+							.unwrap()
+							.add_buffer(projection.clone())
+							.unwrap()
+							.build()
+							.unwrap(),
+						self.light_desc_pool.next()
+							.add_buffer(light_buffer.clone())
+							.unwrap()
+							.add_sampled_image(shadow_atlas.clone(), self.render_pass.shaders.clamp_sampler.clone())
+							.unwrap()
+							.add_buffer(clusters.clone())
+							.unwrap()
+							.build()
+							.unwrap(),
+					),
+					shaders::fs_history::ty::ViewportInfo {
+						origin: viewport_origin,
+						inv_size: [1.0 / viewport_dimensions[0], 1.0 / viewport_dimensions[1]],
+						depth_to_ndc: if reverse_z { [1.0, 0.0] } else { [2.0, -1.0] },
+						cluster_slices: cluster_slices(znear, zfar),
+						focus_distance: camera.focus_distance(),
+						aperture: camera.aperture(),
+					}
+				)
+				.unwrap();
+		}
+
+		// water is blended over the lit scene in a subpass of its own, where the depth buffer can still be read
+		let mut command_buffer = command_buffer.next_subpass(false).unwrap();
+		let camera_waters = cameras.iter().zip(&camera_buffers).zip(&dynamic_states);
+		for (((_, viewport), (position, rotation, projection)), dynamic_state) in camera_waters {
+			if self.waters.is_empty() {
+				break;
+			}
+			let (viewport_origin, viewport_dimensions) = viewport.scaled(dimensions, scale).pixels(internal_dimensions);
+			let camera_desc =
+				Arc::new(
+					self.water_camera_desc_pool.next()
+						.add_buffer(position.clone())
 						.unwrap()
-						.add_buffer(camera.rotation_buffer.clone())
+						.add_buffer(rotation.clone())
 						.unwrap()
-						.add_buffer(camera.projection_buffer.clone())
+						.add_buffer(projection.clone())
 						.unwrap()
 						.build()
-						.unwrap(),
-				),
-				()
-			)
-			.unwrap()
-			.next_subpass(false)
-			.unwrap()
-			.draw(
-				self.render_pass.pipeline_target.clone(),
-				&dynamic_state,
-				vec![self.render_pass.shaders.target_vertices.clone()],
-				self.gbuffers.target_descs[history_index].clone(),
-				()
-			)
-			.unwrap()
+						.unwrap()
+				);
+
+			for (_, water) in &self.waters {
+				let params = self.water_pool.chunk(water.uniform_bytes())?;
+				let reflection =
+					water.reflection().cloned().unwrap_or_else(|| self.render_pass.shaders.black_pixel.clone());
+				let water_desc =
+					self.water_desc_pool.next()
+						.add_buffer(params)
+						.unwrap()
+						.add_image(self.gbuffers.depth.clone())
+						.unwrap()
+						.add_sampled_image(reflection, self.render_pass.shaders.clamp_sampler.clone())
+						.unwrap()
+						.build()
+						.unwrap();
+
+				self.render_pass.shaders.device_ctx.render_stats().record_draw((WATER_GRID * WATER_GRID * 2) as usize);
+				command_buffer = command_buffer
+					.draw_indexed(
+						self.render_pass.pipeline_water.clone(),
+						dynamic_state,
+						vec![self.render_pass.shaders.water_vertices.clone()],
+						self.render_pass.shaders.water_indices.clone(),
+						(camera_desc.clone(), water_desc),
+						shaders::fs_water::ty::ViewportInfo {
+							origin: viewport_origin,
+							inv_size: [1.0 / viewport_dimensions[0], 1.0 / viewport_dimensions[1]],
+							depth_to_ndc: if reverse_z { [1.0, 0.0] } else { [2.0, -1.0] },
+						}
+					)
+					.unwrap();
+			}
+		}
+
+		let mut command_buffer = command_buffer.end_render_pass().unwrap();
+
+		// cameras without motion blur are copied as they are, so the later passes can read one image
+		let motion_blur = quality.post_effects && cameras.iter().any(|(camera, _)| camera.motion_blur_samples() > 1);
+		if motion_blur {
+			command_buffer = command_buffer
+				.begin_render_pass(
+					Self::make_framebuffer(&self.render_pass.render_pass_post, self.gbuffers.motion_blur.clone())?,
+					false,
+					vec![ClearValue::None]
+				)
+				.unwrap();
+
+			for ((camera, _), dynamic_state) in cameras.iter().zip(&dynamic_states) {
+				self.render_pass.shaders.device_ctx.render_stats().record_draw(2);
+				command_buffer = command_buffer
+					.draw(
+						self.render_pass.pipeline_motion_blur.clone(),
+						dynamic_state,
+						vec![self.render_pass.shaders.target_vertices.clone()],
+						self.gbuffers.motion_blur_descs[history_index].clone(),
+						shaders::fs_motion_blur::ty::MotionBlur {
+							samples: camera.motion_blur_samples(),
+							// velocities are relative to the viewport, which covers less of the gbuffers when scaled
+							shutter_scale: camera.shutter_scale() * self.resolution.scale / max_scale,
+						}
+					)
+					.unwrap();
+			}
+
+			command_buffer = command_buffer.end_render_pass().unwrap();
+		}
+		let source_index = if motion_blur { MOTION_BLUR_SOURCE } else { history_index };
+
+		let post_process = if quality.post_effects { self.post_process } else { PostProcessChain::default() };
+		if quality.post_effects && cameras.iter().any(|(camera, _)| camera.aperture() > 0.0) {
+			command_buffer = command_buffer
+				.begin_render_pass(
+					Self::make_framebuffer(&self.render_pass.render_pass_post, self.gbuffers.dof.clone())?,
+					false,
+					vec![ClearValue::None]
+				)
+				.unwrap();
+
+			for ((camera, _), dynamic_state) in cameras.iter().zip(&dynamic_states) {
+				if camera.aperture() > 0.0 {
+					self.render_pass.shaders.device_ctx.render_stats().record_draw(2);
+					command_buffer = command_buffer
+						.draw(
+							self.render_pass.pipeline_dof.clone(),
+							dynamic_state,
+							vec![self.render_pass.shaders.target_vertices.clone()],
+							self.gbuffers.dof_descs[source_index].clone(),
+							()
+						)
+						.unwrap();
+				}
+			}
+
+			command_buffer = command_buffer.end_render_pass().unwrap();
+		}
+
+		if let Some(bloom) = post_process.bloom {
+			command_buffer = command_buffer
+				.begin_render_pass(
+					Self::make_framebuffer(&self.render_pass.render_pass_post, self.gbuffers.bloom.clone())?,
+					false,
+					vec![ClearValue::None]
+				)
+				.unwrap();
+
+			for dynamic_state in &dynamic_states {
+				self.render_pass.shaders.device_ctx.render_stats().record_draw(2);
+				command_buffer = command_buffer
+					.draw(
+						self.render_pass.pipeline_bloom.clone(),
+						dynamic_state,
+						vec![self.render_pass.shaders.target_vertices.clone()],
+						self.gbuffers.bloom_descs[source_index].clone(),
+						shaders::fs_bloom::ty::Bloom { threshold: bloom.threshold }
+					)
+					.unwrap();
+			}
+
+			command_buffer = command_buffer.end_render_pass().unwrap();
+		}
+
+		let mut command_buffer =
+			command_buffer
+				.begin_render_pass(
+					Self::make_framebuffer(&self.render_pass.render_pass_target, image.clone())?,
+					false,
+					vec![ClearValue::None]
+				)
+				.unwrap();
+
+		// the outline pass is skipped per pixel unless something is selected
+		let outline_thickness =
+			if self.meshes.iter().any(|(_, mesh)| mesh.is_visible() && mesh.is_selected()) {
+				self.outline_thickness
+			} else {
+				0.0
+			};
+		let vignette = post_process.vignette.unwrap_or(Vignette { intensity: 0.0, smoothness: 0.0 });
+		let calibration = self.render_pass.display_calibration();
+		self.frame = self.frame.wrapping_add(1);
+		for ((camera, viewport), dynamic_state) in cameras.iter().zip(&target_dynamic_states) {
+			let (viewport_origin, viewport_dimensions) = viewport.pixels(dimensions);
+			self.render_pass.shaders.device_ctx.render_stats().record_draw(2);
+			command_buffer = command_buffer
+				.draw(
+					self.render_pass.pipeline_target.clone(),
+					dynamic_state,
+					vec![self.render_pass.shaders.target_vertices.clone()],
+					(self.gbuffers.target_descs[source_index].clone(), self.color_lut_desc.clone()),
+					shaders::fs_target::ty::TargetInfo {
+						viewport: [
+							viewport_origin[0],
+							viewport_origin[1],
+							1.0 / viewport_dimensions[0],
+							1.0 / viewport_dimensions[1],
+						],
+						outline_color: self.outline_color,
+						render_scale: scale,
+						upscale_nearest: (self.render_pass.upscale_filter() == UpscaleFilter::Nearest) as u32,
+						grading_blend: self.color_grading_blend,
+						depth_of_field: (quality.post_effects && camera.aperture() > 0.0) as u32,
+						outline_thickness: outline_thickness * scale[1],
+						chromatic_aberration:
+							post_process.chromatic_aberration.map_or(0.0, |ca| ca.strength) * scale[0],
+						vignette_intensity: vignette.intensity,
+						vignette_smoothness: vignette.smoothness,
+						grain_intensity: post_process.film_grain.map_or(0.0, |grain| grain.intensity),
+						grain_seed: self.frame,
+						calibrate: !calibration.is_identity() as u32,
+						brightness: calibration.brightness,
+						contrast: calibration.contrast,
+						inv_gamma: 1.0 / calibration.gamma.max(0.01),
+						bloom_intensity: post_process.bloom.map_or(0.0, |bloom| bloom.intensity),
+					}
+				)
+				.unwrap();
+		}
+
+		let command_buffer = command_buffer
 			.end_render_pass()
 			.unwrap()
-			.build()
-			.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?;
+			.build()?;
+		device.debug_names().name_command_buffer(&command_buffer, "mesh batch");
+
+		self.previous_cameras = cameras.iter().map(|(camera, _)| **camera).collect();
+		for (_, mesh) in &mut self.meshes {
+			mesh.end_frame();
+		}
+		for (_, foliage) in &mut self.foliage {
+			foliage.end_frame();
+		}
 
 		Ok((command_buffer, gbuffers_future))
 	}
 
-	fn make_sampled_input_attachment(
-		device: Arc<Device>,
-		dimensions: [u32; 2],
-		format: Format,
-	) -> Result<Arc<AttachmentImage>, DeviceMemoryAllocError> {
-		AttachmentImage::sampled_input_attachment(device, dimensions, format)
-			.map_err(|err| match err { ImageCreationError::AllocError(err) => err, err => unreachable!(err) })
+	/// Renders the depth around each shadowed light into the shadow atlas.
+	fn record_shadows(
+		&mut self,
+		command_buffer: AutoCommandBufferBuilder,
+		lights: &[PointLight],
+		shadows: &[(usize, ShadowSlot)],
+		atlas_size: u32,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		// the quality settings may have changed the atlas size, or turned shadows off
+		if self.shadow_atlas.as_ref().map_or(false, |(atlas, _)| atlas.dimensions() != [atlas_size, atlas_size]) {
+			self.shadow_atlas = None;
+		}
+		if shadows.is_empty() {
+			return Ok(command_buffer);
+		}
+
+		if self.shadow_atlas.is_none() {
+			let device = &self.render_pass.shaders.device_ctx;
+			let dimensions = [atlas_size, atlas_size];
+			let atlas = AttachmentImage::sampled(device.device().clone(), dimensions, SHADOW_FORMAT)?;
+			device.debug_names().name_image(&*atlas, "shadow atlas");
+			let bytes = image_bytes(dimensions, SHADOW_FORMAT);
+			let memory = device.memory_stats().track(MemoryCategory::Attachments, "mesh batch shadow atlas", bytes);
+			self.shadow_atlas = Some((atlas, memory));
+		}
+		let atlas = self.shadow_atlas.as_ref().unwrap().0.clone();
+
+		let mut command_buffer =
+			command_buffer
+				.begin_render_pass(
+					Self::make_framebuffer(&self.render_pass.render_pass_shadow, atlas)?,
+					false,
+					vec![1.0.into()]
+				)
+				.unwrap();
+
+		let atlas_dimensions = [atlas_size as f32, atlas_size as f32];
+		for &(i, slot) in shadows {
+			let light_camera = shadow_camera(&lights[i]);
+			for (face_index, face) in CubeFace::ALL.iter().enumerate() {
+				let camera = face.camera(&light_camera);
+				let position = self.camera_position_pool.next(camera.position())?;
+				let rotation = self.camera_rotation_pool.next(camera.rotation())?;
+				let projection = self.camera_projection_pool.next(camera.projection_uniform())?;
+
+				// shadows don't have motion vectors, so the camera is also its own previous camera
+				let camera_desc =
+					Arc::new(
+						self.camera_desc_pool_gbuffers.next()
+							.add_buffer(position.clone())
+							.unwrap()
+							.add_buffer(rotation.clone())
+							.unwrap()
+							.add_buffer(projection.clone())
+							.unwrap()
+							.add_buffer(position)
+							.unwrap()
+							.add_buffer(rotation)
+							.unwrap()
+							.add_buffer(projection)
+							.unwrap()
+							.build()
+							.unwrap()
+					);
+
+				let region =
+					Region::Pixels { origin: slot.face_origin(face_index), dimensions: [slot.resolution as f32; 2] };
+				let dynamic_state = dynamic_state(&region, Some(&region), atlas_dimensions);
+				for (_, mesh) in &mut self.meshes {
+					command_buffer =
+						mesh.add_commands(
+							command_buffer,
+							&self.render_pass,
+							camera_desc.clone(),
+							&dynamic_state,
+							GBufferStage::Shadow,
+						)?;
+				}
+				for (_, group) in &mut self.groups {
+					command_buffer =
+						group.add_commands(
+							command_buffer,
+							&self.render_pass,
+							camera_desc.clone(),
+							&dynamic_state,
+							GBufferStage::Shadow,
+							None,
+						);
+				}
+				for (_, foliage) in &mut self.foliage {
+					command_buffer =
+						foliage.add_commands(
+							command_buffer,
+							&self.render_pass,
+							camera_desc.clone(),
+							&dynamic_state,
+							GBufferStage::Shadow,
+							&camera,
+						);
+				}
+				for (_, world) in &mut self.voxel_worlds {
+					command_buffer =
+						world.add_commands(
+							command_buffer,
+							&self.render_pass,
+							camera_desc.clone(),
+							&dynamic_state,
+							GBufferStage::Shadow,
+						)?;
+				}
+			}
+		}
+
+		Ok(command_buffer.end_render_pass().unwrap())
 	}
 
-	fn make_transient_input_attachment(
-		device: Arc<Device>,
-		dimensions: [u32; 2],
-		format: Format,
-	) -> Result<Arc<AttachmentImage>, DeviceMemoryAllocError> {
-		AttachmentImage::transient_input_attachment(device, dimensions, format)
-			.map_err(|err| match err { ImageCreationError::AllocError(err) => err, err => unreachable!(err) })
+	/// Uploads the lights for the light culling and history passes. Also returns the image shadows are read from, which
+	/// is a placeholder when nothing casts shadows.
+	fn upload_lights(
+		&self,
+		lights: &[PointLight],
+		shadows: &[(usize, ShadowSlot)],
+	) -> Result<
+		(CpuBufferPoolChunk<LightData, Arc<StdMemoryPool>>, Arc<ImageViewAccess + Send + Sync + 'static>),
+		DeviceMemoryAllocError
+	> {
+		let mut data = lights.iter()
+			.map(|light| {
+				let [r, g, b] = light.color;
+				LightData {
+					position_range: light.position.extend(light.range).into(),
+					color: [r * light.intensity, g * light.intensity, b * light.intensity, 0.0],
+					.. LightData::default()
+				}
+			})
+			.collect::<Vec<_>>();
+		for &(i, slot) in shadows {
+			let terms = shadow_camera(&lights[i]).projection_terms();
+			let bias = lights[i].shadow.map_or(0.0, |shadow| shadow.bias);
+			data[i].color[3] = 1.0;
+			data[i].shadow_rect = [slot.origin[0] as f32, slot.origin[1] as f32, slot.resolution as f32, bias];
+			data[i].shadow_depth = [terms.z, terms.w, 0.0, 0.0];
+		}
+
+		// storage buffers can't be empty
+		if data.is_empty() {
+			data.push(LightData::default());
+		}
+
+		let shadow_atlas: Arc<ImageViewAccess + Send + Sync + 'static> =
+			match &self.shadow_atlas {
+				Some((atlas, _)) if !shadows.is_empty() => atlas.clone(),
+				_ => self.render_pass.shaders.black_pixel.clone(),
+			};
+
+		Ok((self.light_pool.chunk(data)?, shadow_atlas))
+	}
+
+	fn make_clusters(
+		render_pass: &MeshRenderPass,
+	) -> Result<(Arc<DeviceLocalBuffer<[u32]>>, MemoryHandle), DeviceMemoryAllocError> {
+		let device = &render_pass.shaders.device_ctx;
+		let clusters =
+			DeviceLocalBuffer::array(
+				device.device().clone(),
+				CLUSTER_BUFFER_LEN,
+				BufferUsage { storage_buffer: true, .. BufferUsage::none() },
+				iter::once(device.queue().family()),
+			)?;
+		device.debug_names().name_buffer(&*clusters, "light clusters");
+		let bytes = (CLUSTER_BUFFER_LEN * size_of::<u32>()) as u64;
+		let memory = device.memory_stats().track(MemoryCategory::Buffers, "mesh batch light clusters", bytes);
+		Ok((clusters, memory))
+	}
+
+	fn make_framebuffer<I>(
+		render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+		image: I,
+	) -> Result<Arc<FramebufferAbstract + Send + Sync + 'static>, BatchError>
+	where I: ImageViewAccess + Send + Sync + 'static {
+		let framebuffer = Framebuffer::start(render_pass.clone()).add(image).and_then(|fb| fb.build())?;
+		Ok(Arc::new(framebuffer))
+	}
+
+	fn make_color_lut_desc(
+		render_pass: &MeshRenderPass,
+		image: Arc<ImageViewAccess + Send + Sync + 'static>,
+	) -> Arc<DescriptorSet + Send + Sync + 'static> {
+		Arc::new(
+			PersistentDescriptorSet::start(render_pass.pipeline_target.clone(), 1)
+				.add_sampled_image(image, render_pass.shaders.clamp_sampler.clone())
+				.unwrap()
+				.build()
+				.unwrap()
+		)
+	}
+
+	fn make_motion_blur_desc(
+		shared: &MeshRenderPass,
+		source: Arc<AttachmentImage>,
+		velocity: Arc<AttachmentImage>,
+	) -> Arc<DescriptorSet + Send + Sync + 'static> {
+		Arc::new(
+			PersistentDescriptorSet::start(shared.pipeline_motion_blur.clone(), 0)
+				.add_sampled_image(source, shared.shaders.clamp_sampler.clone())
+				.unwrap()
+				.add_sampled_image(velocity, shared.shaders.clamp_sampler.clone())
+				.unwrap()
+				.build()
+				.unwrap()
+		)
+	}
+
+	fn make_dof_desc(
+		shared: &MeshRenderPass,
+		source: Arc<AttachmentImage>,
+	) -> Arc<DescriptorSet + Send + Sync + 'static> {
+		Arc::new(
+			PersistentDescriptorSet::start(shared.pipeline_dof.clone(), 0)
+				.add_sampled_image(source, shared.shaders.clamp_sampler.clone())
+				.unwrap()
+				.build()
+				.unwrap()
+		)
+	}
+
+	fn make_bloom_desc(
+		shared: &MeshRenderPass,
+		source: Arc<AttachmentImage>,
+	) -> Arc<DescriptorSet + Send + Sync + 'static> {
+		Arc::new(
+			PersistentDescriptorSet::start(shared.pipeline_bloom.clone(), 0)
+				.add_sampled_image(source, shared.shaders.clamp_sampler.clone())
+				.unwrap()
+				.build()
+				.unwrap()
+		)
+	}
+
+	fn make_target_desc(
+		shared: &MeshRenderPass,
+		source: Arc<AttachmentImage>,
+		dof: Arc<AttachmentImage>,
+		selection: Arc<AttachmentImage>,
+		bloom: Arc<AttachmentImage>,
+	) -> Arc<DescriptorSet + Send + Sync + 'static> {
+		Arc::new(
+			PersistentDescriptorSet::start(shared.pipeline_target.clone(), 0)
+				.add_sampled_image(source, shared.shaders.clamp_sampler.clone())
+				.unwrap()
+				.add_sampled_image(dof, shared.shaders.clamp_sampler.clone())
+				.unwrap()
+				.add_sampled_image(selection, shared.shaders.clamp_sampler.clone())
+				.unwrap()
+				.add_sampled_image(bloom, shared.shaders.clamp_sampler.clone())
+				.unwrap()
+				.build()
+				.unwrap()
+		)
 	}
 
 	fn make_gbuffers(
 		target: &RenderTarget,
 		shared: &MeshRenderPass,
-	) -> Result<(GBuffers, impl GpuFuture), DeviceMemoryAllocError> {
-		let dimensions = target.images()[0].dimensions().width_height();
-		let color =
-			Self::make_transient_input_attachment(
-				shared.shaders.target_vertices.device().clone(),
-				dimensions,
-				ALBEDO_FORMAT
-			)?;
-		let normal =
-			Self::make_transient_input_attachment(
-				shared.shaders.target_vertices.device().clone(),
-				dimensions,
-				NORMAL_FORMAT
-			)?;
-		let depth =
-			Self::make_transient_input_attachment(
-				shared.shaders.target_vertices.device().clone(),
-				dimensions,
-				DEPTH_FORMAT
-			)?;
+		dimensions: [u32; 2],
+	) -> Result<(GBuffers, impl GpuFuture), BatchError> {
+		// everything but the history is only needed while the batch's commands run, so it can be shared with other
+		// batches
+		let device = &shared.shaders.device_ctx;
+		let share = shared.shares_transient_attachments();
+		let mut transient_attachments = vec![];
+		let mut transient = |role, format, usage| -> Result<Arc<AttachmentImage>, BatchError> {
+			let attachment = device.transient_attachment(role, dimensions, format, usage, share)?;
+			let image = attachment.image().clone();
+			transient_attachments.push(attachment);
+			Ok(image)
+		};
+		let color = transient("gbuffer-albedo", ALBEDO_FORMAT, TransientUsage::InputAttachment)?;
+		let normal = transient("gbuffer-normal", NORMAL_FORMAT, TransientUsage::InputAttachment)?;
+		let emissive = transient("gbuffer-emissive", EMISSIVE_FORMAT, TransientUsage::InputAttachment)?;
+		let depth = transient("gbuffer-depth", shared.depth_format(), TransientUsage::InputAttachment)?;
+		let velocity = transient("gbuffer-velocity", VELOCITY_FORMAT, TransientUsage::Sampled)?;
+		let selection = transient("gbuffer-selection", SELECTION_FORMAT, TransientUsage::Sampled)?;
+		let motion_blur = transient("motion-blur", target.format(), TransientUsage::Sampled)?;
+		let dof = transient("depth-of-field", target.format(), TransientUsage::Sampled)?;
+		let bloom = transient("bloom", target.format(), TransientUsage::Sampled)?;
+
+		// the history is read by the next frame, so each batch needs its own
 		let history =
 			[
-				Self::make_sampled_input_attachment(
-					shared.shaders.target_vertices.device().clone(),
-					dimensions,
-					target.format()
-				)?,
-				Self::make_sampled_input_attachment(
-					shared.shaders.target_vertices.device().clone(),
-					dimensions,
-					target.format()
-				)?
+				AttachmentImage::sampled_input_attachment(device.device().clone(), dimensions, target.format())?,
+				AttachmentImage::sampled_input_attachment(device.device().clone(), dimensions, target.format())?,
 			];
 
+		let bytes = 2 * image_bytes(dimensions, target.format()) + size_of::<Vector4<f32>>() as u64;
+		let memory = device.memory_stats().track(MemoryCategory::Attachments, "mesh batch history", bytes);
+
+		let debug_names = device.debug_names();
+		debug_names.name_image(&*history[0], "history-0");
+		debug_names.name_image(&*history[1], "history-1");
+
+		let gbuffer_dimensions = dimensions;
 		let dimensions = [dimensions[0] as f32, dimensions[1] as f32];
 		let (size, size_future) =
 			ImmutableBuffer::from_data(
@@ -291,6 +1340,8 @@ impl MeshBatch {
 						.unwrap()
 						.add_image(depth.clone())
 						.unwrap()
+						.add_image(emissive.clone())
+						.unwrap()
 						.build()
 						.unwrap()
 				) as _,
@@ -306,57 +1357,116 @@ impl MeshBatch {
 						.unwrap()
 						.add_image(depth.clone())
 						.unwrap()
+						.add_image(emissive.clone())
+						.unwrap()
 						.build()
 						.unwrap()
 				) as _
 			];
 
+		let motion_blur_descs =
+			[
+				Self::make_motion_blur_desc(shared, history[0].clone(), velocity.clone()),
+				Self::make_motion_blur_desc(shared, history[1].clone(), velocity.clone()),
+			];
+
+		let sources = [history[0].clone(), history[1].clone(), motion_blur.clone()];
+		let dof_descs =
+			[
+				Self::make_dof_desc(shared, sources[0].clone()),
+				Self::make_dof_desc(shared, sources[1].clone()),
+				Self::make_dof_desc(shared, sources[2].clone()),
+			];
+		let bloom_descs =
+			[
+				Self::make_bloom_desc(shared, sources[0].clone()),
+				Self::make_bloom_desc(shared, sources[1].clone()),
+				Self::make_bloom_desc(shared, sources[2].clone()),
+			];
 		let target_descs =
 			[
-				Arc::new(
-					PersistentDescriptorSet::start(shared.pipeline_target.clone(), 0)
-						.add_image(history[0].clone())
-						.unwrap()
-						.build()
-						.unwrap()
-				) as _,
-				Arc::new(
-					PersistentDescriptorSet::start(shared.pipeline_target.clone(), 0)
-						.add_image(history[1].clone())
-						.unwrap()
-						.build()
-						.unwrap()
-				) as _
+				Self::make_target_desc(shared, sources[0].clone(), dof.clone(), selection.clone(), bloom.clone()),
+				Self::make_target_desc(shared, sources[1].clone(), dof.clone(), selection.clone(), bloom.clone()),
+				Self::make_target_desc(shared, sources[2].clone(), dof.clone(), selection.clone(), bloom.clone()),
 			];
 
 		Ok((
 			GBuffers {
+				dimensions: gbuffer_dimensions,
 				size: size,
 				color: color,
 				normal: normal,
+				velocity: velocity,
+				selection: selection,
+				emissive: emissive,
 				depth: depth,
 				history_descs: history_descs,
+				motion_blur_descs: motion_blur_descs,
+				dof_descs: dof_descs,
+				bloom_descs: bloom_descs,
 				target_descs: target_descs,
 				history: history,
+				motion_blur: motion_blur,
+				dof: dof,
+				bloom: bloom,
 				history_index: false,
 				history_initialized: false,
+				_transient_attachments: transient_attachments,
+				_memory: Arc::new(memory),
 			},
 			size_future
 		))
 	}
 }
 
+/// Sorts lights by how far their light reaches past the camera, so lights that touch it come first.
+fn light_priority(light: &PointLight, eye: Vector3<f32>) -> f32 {
+	(light.position - eye).magnitude() - light.range
+}
+
+/// The camera a point light's shadows are drawn from, before it's turned toward each cube face.
+fn shadow_camera(light: &PointLight) -> Camera {
+	let range = light.range.max(0.01);
+	Camera::new(light.position, Quaternion::new(1.0, 0.0, 0.0, 0.0), 1.0, 90.0, range * 0.01, range)
+}
+
+/// Identifies a mesh that has been added to a `MeshBatch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MeshId(u64);
+
+/// Identifies a mesh group that has been added to a `MeshBatch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MeshGroupId(u64);
+
 #[derive(Clone)]
 struct GBuffers {
+	dimensions: [u32; 2],
 	size: Arc<ImmutableBuffer<Vector4<f32>>>,
 	color: Arc<AttachmentImage>,
 	normal: Arc<AttachmentImage>,
+	velocity: Arc<AttachmentImage>,
+	/// 1.0 where a selected mesh is visible.
+	selection: Arc<AttachmentImage>,
+	/// How brightly each pixel glows in its albedo color.
+	emissive: Arc<AttachmentImage>,
 	depth: Arc<AttachmentImage>,
 	history_descs: [Arc<DescriptorSet + Send + Sync + 'static>; 2],
-	target_descs: [Arc<DescriptorSet + Send + Sync + 'static>; 2],
+	motion_blur_descs: [Arc<DescriptorSet + Send + Sync + 'static>; 2],
+	/// Indexed by the history index, or by `MOTION_BLUR_SOURCE` when the motion blurred image is read instead.
+	dof_descs: [Arc<DescriptorSet + Send + Sync + 'static>; 3],
+	bloom_descs: [Arc<DescriptorSet + Send + Sync + 'static>; 3],
+	target_descs: [Arc<DescriptorSet + Send + Sync + 'static>; 3],
 	history: [Arc<AttachmentImage>; 2],
+	motion_blur: Arc<AttachmentImage>,
+	/// The history image blurred horizontally, for depth of field.
+	dof: Arc<AttachmentImage>,
+	/// The bright parts of the history image, blurred horizontally.
+	bloom: Arc<AttachmentImage>,
 	history_index: bool,
 	history_initialized: bool,
+	/// Keeps the pooled images above counted and shareable for as long as the batch uses them.
+	_transient_attachments: Vec<Arc<TransientAttachment>>,
+	_memory: Arc<MemoryHandle>,
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,306 @@
+use super::{ LightId, MeshId, MeshRenderPass, PointLight, Topology, VertexFormat, light_priority };
+use super::mesh::Mesh;
+use super::render_pass::ForwardPipelines;
+use crate::{ ImageFramebuffer, ObjectId, RenderTarget };
+use crate::batch::{ BatchError, Region, dynamic_state };
+use crate::camera::Camera;
+use crate::device::{ TransientAttachment, TransientUsage };
+use crate::uniform::{ LayoutRules, UniformWriter };
+use cgmath::{ vec3, InnerSpace, Quaternion, Vector3, Vector4 };
+use std::{ cmp::Ordering, sync::Arc };
+use vulkano::{
+	buffer::{ BufferUsage, CpuBufferPool },
+	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder },
+	descriptor::descriptor_set::FixedSizeDescriptorSetsPool,
+	framebuffer::{ Framebuffer, FramebufferAbstract },
+	image::ImageViewAccess,
+	pipeline::GraphicsPipelineAbstract,
+	sync::{ GpuFuture, NowFuture },
+};
+
+/// The most point lights a `ForwardMeshBatch` lights a frame with. Beyond this, the lights nearest the camera are
+/// used. This must match fs_forward.
+pub const MAX_FORWARD_LIGHTS: usize = 8;
+
+/// Draws meshes straight into a render target in a single pass, lighting each fragment as it's drawn. It uses the
+/// same `Mesh` assets and `MeshRenderPass` as `MeshBatch`, but skips the gbuffers, light culling, and post processing,
+/// so it needs far less memory and bandwidth. That suits small scenes and low-end hardware.
+///
+/// Only a handful of point lights are supported, and they don't cast shadows. Custom materials and stencils are
+/// ignored, and there's no water, mesh groups, or foliage.
+pub struct ForwardMeshBatch {
+	render_pass: Arc<MeshRenderPass>,
+	forward: Arc<ForwardPipelines>,
+	meshes: Vec<(MeshId, Mesh)>,
+	next_mesh_id: u64,
+	lights: Vec<(LightId, PointLight)>,
+	next_light_id: u64,
+	sun_direction: Vector3<f32>,
+	sun_color: [f32; 3],
+	ambient: [f32; 3],
+	clear_color: [f32; 4],
+	target_id: ObjectId,
+	framebuffers: Vec<Option<ImageFramebuffer>>,
+	/// Shared with other batches drawing to targets of the same size, since it's only needed within the pass.
+	depth: Option<Arc<TransientAttachment>>,
+	camera_desc_pool: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+	camera_position_pool: CpuBufferPool<Vector3<f32>>,
+	camera_rotation_pool: CpuBufferPool<Quaternion<f32>>,
+	camera_projection_pool: CpuBufferPool<[Vector4<f32>; 2]>,
+	lights_pool: CpuBufferPool<u8>,
+	lights_desc_pool: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+	viewport: Region,
+	scissor: Option<Region>,
+}
+impl ForwardMeshBatch {
+	pub fn new(target: &RenderTarget, render_pass: Arc<MeshRenderPass>) -> Result<Self, BatchError> {
+		let forward = render_pass.forward();
+		let pipeline = forward.get(Topology::TriangleList, VertexFormat::Full).clone();
+		let device = render_pass.shaders.target_vertices.device().clone();
+
+		Ok(Self {
+			render_pass: render_pass,
+			forward: forward,
+			meshes: vec![],
+			next_mesh_id: 0,
+			lights: vec![],
+			next_light_id: 0,
+			sun_direction: vec3(-1.0, -4.0, 2.0).normalize(),
+			sun_color: [0.5, 0.425, 0.35],
+			ambient: [0.001; 3],
+			clear_color: [0.0, 0.0, 0.0, 1.0],
+			target_id: target.id_root().make_id(),
+			framebuffers: target.images().iter().map(|_| None).collect(),
+			depth: None,
+			camera_desc_pool: FixedSizeDescriptorSetsPool::new(pipeline.clone(), 0),
+			camera_position_pool: CpuBufferPool::uniform_buffer(device.clone()),
+			camera_rotation_pool: CpuBufferPool::uniform_buffer(device.clone()),
+			camera_projection_pool: CpuBufferPool::uniform_buffer(device.clone()),
+			lights_pool: CpuBufferPool::new(device, BufferUsage::uniform_buffer()),
+			lights_desc_pool: FixedSizeDescriptorSetsPool::new(pipeline, 2),
+			viewport: Region::full(),
+			scissor: None,
+		})
+	}
+
+	pub fn add_mesh(&mut self, mesh: Mesh) -> MeshId {
+		let id = MeshId(self.next_mesh_id);
+		self.next_mesh_id += 1;
+		self.meshes.push((id, mesh));
+		id
+	}
+
+	pub fn mesh(&self, id: MeshId) -> Option<&Mesh> {
+		self.meshes.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| &self.meshes[i].1)
+	}
+
+	pub fn mesh_mut(&mut self, id: MeshId) -> Option<&mut Mesh> {
+		self.meshes.binary_search_by_key(&id, |&(id, _)| id).ok().map(move |i| &mut self.meshes[i].1)
+	}
+
+	pub fn remove_mesh(&mut self, id: MeshId) -> Option<Mesh> {
+		self.meshes.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| self.meshes.remove(i).1)
+	}
+
+	pub fn meshes(&self) -> impl Iterator<Item = (MeshId, &Mesh)> {
+		self.meshes.iter().map(|(id, mesh)| (*id, mesh))
+	}
+
+	pub fn meshes_mut(&mut self) -> impl Iterator<Item = (MeshId, &mut Mesh)> {
+		self.meshes.iter_mut().map(|(id, mesh)| (*id, mesh))
+	}
+
+	/// Adds a point light. Its `shadow` is ignored, since the forward pass has no shadow maps.
+	pub fn add_light(&mut self, light: PointLight) -> LightId {
+		let id = LightId(self.next_light_id);
+		self.next_light_id += 1;
+		self.lights.push((id, light));
+		id
+	}
+
+	pub fn light(&self, id: LightId) -> Option<&PointLight> {
+		self.lights.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| &self.lights[i].1)
+	}
+
+	pub fn light_mut(&mut self, id: LightId) -> Option<&mut PointLight> {
+		self.lights.binary_search_by_key(&id, |&(id, _)| id).ok().map(move |i| &mut self.lights[i].1)
+	}
+
+	pub fn remove_light(&mut self, id: LightId) -> Option<PointLight> {
+		self.lights.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| self.lights.remove(i).1)
+	}
+
+	/// Sets the sunlight, as the direction toward the sun in world space and its linear RGB color. The default matches
+	/// `MeshBatch`'s sun.
+	pub fn set_sun(&mut self, direction: Vector3<f32>, color: [f32; 3]) {
+		self.sun_direction = direction.normalize();
+		self.sun_color = color;
+	}
+
+	/// The least light any surface gets, in linear RGB.
+	pub fn set_ambient(&mut self, ambient: [f32; 3]) {
+		self.ambient = ambient;
+	}
+
+	pub fn ambient(&self) -> [f32; 3] {
+		self.ambient
+	}
+
+	/// The color the target is cleared to behind the meshes.
+	pub fn set_clear_color(&mut self, color: [f32; 4]) {
+		self.clear_color = color;
+	}
+
+	pub fn clear_color(&self) -> [f32; 4] {
+		self.clear_color
+	}
+
+	/// Sets the region of the target that the camera's view is mapped to.
+	pub fn set_viewport(&mut self, viewport: Region) {
+		self.viewport = viewport;
+	}
+
+	/// Restricts drawing to a region of the target. `None` allows drawing to the whole target.
+	pub fn set_scissor(&mut self, scissor: Option<Region>) {
+		self.scissor = scissor;
+	}
+
+	/// Records the batch's commands. Like `MeshBatch::commands`, this can run on a worker thread.
+	pub fn commands(
+		&mut self,
+		target: &RenderTarget,
+		image_num: usize,
+		camera: &Camera,
+	) -> Result<(AutoCommandBuffer, Option<impl GpuFuture>), BatchError> {
+		assert!(self.target_id.is_child_of(target.id_root()));
+		let reverse_z = self.render_pass.reverse_z();
+		assert!(camera.reverse_z() == reverse_z, "camera depth mode doesn't match");
+
+		let image = &target.images()[image_num];
+		let dimensions = image.dimensions().width_height();
+		let framebuffer = self.framebuffer(image, image_num, dimensions)?;
+		let target_dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+		let scissor = self.scissor.as_ref().unwrap_or(&self.viewport);
+		let state = dynamic_state(&self.viewport, Some(scissor), target_dimensions);
+
+		let device = self.render_pass.shaders.device_ctx.clone();
+		let mut command_buffer =
+			AutoCommandBufferBuilder::primary_one_time_submit(device.device().clone(), device.queue().family())?;
+		for (_, mesh) in &mut self.meshes {
+			command_buffer = mesh.record_uploads(command_buffer)?;
+		}
+
+		// there's no motion blur, so the previous camera is the current one
+		let position = self.camera_position_pool.next(camera.position())?;
+		let rotation = self.camera_rotation_pool.next(camera.rotation())?;
+		let projection = self.camera_projection_pool.next(camera.projection_uniform())?;
+		let camera_desc =
+			Arc::new(
+				self.camera_desc_pool.next()
+					.add_buffer(position.clone())
+					.unwrap()
+					.add_buffer(rotation.clone())
+					.unwrap()
+					.add_buffer(projection.clone())
+					.unwrap()
+					.add_buffer(position)
+					.unwrap()
+					.add_buffer(rotation)
+					.unwrap()
+					.add_buffer(projection)
+					.unwrap()
+					.build()
+					.unwrap()
+			);
+		let lights = self.lights_pool.chunk(self.lights_uniform(camera))?;
+		let lights_desc = Arc::new(self.lights_desc_pool.next().add_buffer(lights).unwrap().build().unwrap());
+
+		let mut command_buffer =
+			command_buffer
+				.begin_render_pass(
+					framebuffer,
+					false,
+					vec![self.clear_color.into(), (if reverse_z { 0.0 } else { 1.0 }).into()],
+				)
+				.unwrap();
+		for (_, mesh) in &mut self.meshes {
+			command_buffer =
+				mesh.add_forward_commands(
+					command_buffer,
+					&self.render_pass,
+					&self.forward,
+					camera_desc.clone(),
+					lights_desc.clone(),
+					&state,
+				)?;
+			mesh.end_frame();
+		}
+
+		let command_buffer = command_buffer.end_render_pass().unwrap().build()?;
+		device.debug_names().name_command_buffer(&command_buffer, "forward mesh batch");
+
+		Ok((command_buffer, None::<NowFuture>))
+	}
+
+	/// The framebuffer for a target image, made again if the image has been replaced since it was last drawn to.
+	fn framebuffer(
+		&mut self,
+		image: &Arc<ImageViewAccess + Send + Sync + 'static>,
+		image_num: usize,
+		dimensions: [u32; 2],
+	) -> Result<Arc<FramebufferAbstract + Send + Sync + 'static>, BatchError> {
+		let depth_stale = self.depth.as_ref().map_or(true, |depth| depth.image().dimensions() != dimensions);
+		if depth_stale {
+			let device = &self.render_pass.shaders.device_ctx;
+			let depth =
+				device.transient_attachment(
+					"forward depth",
+					dimensions,
+					self.forward.depth_format,
+					TransientUsage::InputAttachment,
+					self.render_pass.shares_transient_attachments(),
+				)?;
+			self.depth = Some(depth);
+			self.framebuffers.iter_mut().for_each(|framebuffer| *framebuffer = None);
+		}
+
+		let framebuffer = self.framebuffers[image_num].as_ref()
+			.filter(|old| old.image.upgrade().map_or(false, |old_image| Arc::ptr_eq(image, &old_image)))
+			.map(|old| old.framebuffer.clone());
+		if let Some(framebuffer) = framebuffer {
+			return Ok(framebuffer);
+		}
+
+		let depth = self.depth.as_ref().unwrap().image().clone();
+		let framebuffer: Arc<FramebufferAbstract + Send + Sync + 'static> =
+			Arc::new(Framebuffer::start(self.forward.render_pass.clone()).add(image.clone())?.add(depth)?.build()?);
+		self.framebuffers[image_num] = Some(ImageFramebuffer::new(Arc::downgrade(image), framebuffer.clone()));
+		Ok(framebuffer)
+	}
+
+	/// The `ForwardLights` block of fs_forward, with the lights nearest the camera in its space.
+	fn lights_uniform(&self, camera: &Camera) -> Vec<u8> {
+		let eye = camera.position();
+		let mut lights = self.lights.iter().map(|&(_, light)| light).collect::<Vec<_>>();
+		lights.sort_by(|a, b| light_priority(a, eye).partial_cmp(&light_priority(b, eye)).unwrap_or(Ordering::Equal));
+		lights.truncate(MAX_FORWARD_LIGHTS);
+
+		let to_camera = camera.rotation().conjugate();
+		let mut position_range = [[0.0; 4]; MAX_FORWARD_LIGHTS];
+		let mut color = [[0.0; 4]; MAX_FORWARD_LIGHTS];
+		for (i, light) in lights.iter().enumerate() {
+			position_range[i] = (to_camera * (light.position - eye)).extend(light.range).into();
+			let [r, g, b] = light.color;
+			color[i] = [r * light.intensity, g * light.intensity, b * light.intensity, 0.0];
+		}
+
+		UniformWriter::new(LayoutRules::Std140)
+			.array(&position_range)
+			.array(&color)
+			.field(&(to_camera * self.sun_direction))
+			.field(&(lights.len() as u32))
+			.field(&self.sun_color)
+			.field(&self.ambient)
+			.finish()
+	}
+}
@@ -0,0 +1,127 @@
+use cgmath::Vector3;
+
+/// The most lights that can light a frame. Beyond this, the lights nearest the first camera are used.
+pub(super) const MAX_LIGHTS: usize = 1024;
+/// How many clusters the view is split into across, down, and in depth for light culling. This must match
+/// cs_light_cull and fs_history.
+pub(super) const CLUSTER_GRID: [u32; 3] = [16, 9, 24];
+/// The most lights that can touch one cluster. Past this, the cluster keeps the lights nearest the first camera. This
+/// must match cs_light_cull and fs_history.
+pub(super) const MAX_CLUSTER_LIGHTS: u32 = 63;
+/// The length of a viewport's cluster buffer. Each cluster has its light count, then its light indices.
+pub(super) const CLUSTER_BUFFER_LEN: usize =
+	(CLUSTER_GRID[0] * CLUSTER_GRID[1] * CLUSTER_GRID[2] * (MAX_CLUSTER_LIGHTS + 1)) as usize;
+/// The default width and height of a batch's shadow atlas, which holds the shadow maps of every shadowed light. It's
+/// changed with `QualitySettings::shadow_resolution`.
+pub(super) const SHADOW_ATLAS_SIZE: u32 = 4096;
+/// The largest shadow resolution, so a light's six faces, in three columns and two rows, always fit in the default
+/// atlas. Smaller atlases lower it further.
+const MAX_SHADOW_RESOLUTION: u32 = 1024;
+
+/// A light that shines in every direction from a point, like a lamp or a torch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct PointLight {
+	pub position: Vector3<f32>,
+	/// Linear RGB.
+	pub color: [f32; 3],
+	pub intensity: f32,
+	/// How far the light reaches. It fades out toward this distance.
+	pub range: f32,
+	/// Casts shadows when set, if the light is one of the batch's shadowed lights this frame. See
+	/// `MeshBatch::set_max_shadowed_lights`.
+	pub shadow: Option<PointLightShadow>,
+}
+impl PointLight {
+	pub fn new(position: Vector3<f32>, color: [f32; 3], intensity: f32, range: f32) -> Self {
+		Self { position: position, color: color, intensity: intensity, range: range, shadow: None }
+	}
+}
+
+/// How a point light's shadows are drawn. Each shadowed light renders the batch six times, once for each side of a
+/// cube around it, so shadows are best kept to the lights that need them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct PointLightShadow {
+	/// The width and height of each of the six shadow maps, in pixels. It's limited to 1024.
+	pub resolution: u32,
+	/// How much farther than the nearest surface a point must be to be shadowed, in world units. Raise it if lit
+	/// surfaces show stripes of shadow, and lower it if shadows detach from the objects casting them.
+	pub bias: f32,
+}
+impl Default for PointLightShadow {
+	fn default() -> Self {
+		Self { resolution: 256, bias: 0.05 }
+	}
+}
+
+/// Identifies a light that has been added to a `MeshBatch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LightId(pub(super) u64);
+
+/// A light as fs_history reads it.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub(super) struct LightData {
+	/// The position, then the range.
+	pub(super) position_range: [f32; 4],
+	/// The color times the intensity, then 1.0 if the light is shadowed.
+	pub(super) color: [f32; 4],
+	/// The corner of the light's faces in the shadow atlas and their resolution, in texels, then the bias.
+	pub(super) shadow_rect: [f32; 4],
+	/// The z terms of the shadow cameras' projection, for turning depth back into distance.
+	pub(super) shadow_depth: [f32; 4],
+}
+
+/// The scale and bias that turn the log of a depth into a cluster slice. Slices grow exponentially with depth, so
+/// clusters stay roughly cube shaped from the near plane to the far plane.
+pub(super) fn cluster_slices(znear: f32, zfar: f32) -> [f32; 2] {
+	let znear = znear.max(0.0001);
+	let scale = CLUSTER_GRID[2] as f32 / (zfar.max(znear * 1.01) / znear).ln();
+	[scale, -znear.ln() * scale]
+}
+
+/// A shadowed light's place in the shadow atlas.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) struct ShadowSlot {
+	pub(super) origin: [u32; 2],
+	pub(super) resolution: u32,
+}
+impl ShadowSlot {
+	/// The part of the atlas a cube face is drawn to. The faces are in three columns and two rows, in the order of
+	/// `CubeFace::ALL`.
+	pub(super) fn face_origin(&self, face: usize) -> [f32; 2] {
+		[
+			(self.origin[0] + (face as u32 % 3) * self.resolution) as f32,
+			(self.origin[1] + (face as u32 / 3) * self.resolution) as f32,
+		]
+	}
+}
+
+/// Places each light's faces in a shadow atlas of the given size, in rows. Lights that don't fit get `None`.
+pub(super) fn pack_shadows(resolutions: &[u32], atlas_size: u32) -> Vec<Option<ShadowSlot>> {
+	let max_resolution = MAX_SHADOW_RESOLUTION.min(atlas_size / 3).max(1);
+	let mut order = (0..resolutions.len()).collect::<Vec<_>>();
+	// largest first, so rows waste less space
+	order.sort_by(|&a, &b| resolutions[b].cmp(&resolutions[a]));
+
+	let mut slots = vec![None; resolutions.len()];
+	let (mut x, mut y, mut row_height) = (0, 0, 0);
+	for i in order {
+		let resolution = resolutions[i].max(1).min(max_resolution);
+		let (width, height) = (resolution * 3, resolution * 2);
+		if x + width > atlas_size {
+			x = 0;
+			y += row_height;
+			row_height = 0;
+		}
+		if y + height > atlas_size {
+			continue;
+		}
+
+		slots[i] = Some(ShadowSlot { origin: [x, y], resolution: resolution });
+		x += width;
+		row_height = row_height.max(height);
+	}
+	slots
+}
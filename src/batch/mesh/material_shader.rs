@@ -0,0 +1,302 @@
+use super::render_pass::{ GBufferStage, MaterialShaderPipelines, MeshRenderPass };
+use crate::ObjectIdRoot;
+use crate::device::{
+	BlockLayout,
+	DeviceCtx,
+	MaterialLayout,
+	MaterialSet,
+	ShaderLoadError,
+	ShaderParams,
+	ShaderReflection,
+};
+#[cfg(feature = "shader-compiler")]
+use crate::device::{ ShaderCompileError, ShaderKind };
+use crate::batch::mesh::{ Topology, VertexFormat };
+use std::{ borrow::Cow, ffi::CStr, sync::Arc, vec::IntoIter as VecIntoIter };
+use vulkano::{
+	OomError,
+	descriptor::{
+		descriptor::{
+			DescriptorBufferDesc,
+			DescriptorDesc,
+			DescriptorDescTy,
+			DescriptorImageDesc,
+			DescriptorImageDescArray,
+			DescriptorImageDescDimensions,
+			ShaderStages,
+		},
+		pipeline_layout::{ PipelineLayoutDesc, PipelineLayoutDescPcRange },
+	},
+	format::Format,
+	pipeline::{
+		GraphicsPipelineAbstract,
+		GraphicsPipelineCreationError,
+		shader::{ GraphicsEntryPoint, GraphicsShaderType, ShaderInterfaceDef, ShaderInterfaceDefEntry, ShaderModule },
+	},
+};
+
+/// A user gbuffer fragment shader for meshes, loaded from SPIR-V. It replaces the built-in gbuffer fragment shader for
+/// the submeshes it's assigned to, and must use this interface:
+///
+/// ```glsl
+/// layout(location = 0) in vec3 position_cs;
+/// layout(location = 1) in vec3 normal_cs;
+/// layout(location = 2) in vec2 texcoord;
+/// layout(location = 3) in vec3 base_albedo;
+/// layout(location = 4) in vec4 position_clip;
+/// layout(location = 5) in vec4 prev_position_clip;
+/// layout(location = 6) flat in float selected;
+/// // the submesh's emissive intensity. see `Mesh::set_submesh_emissive`.
+/// layout(location = 7) flat in float emissive;
+///
+/// // albedo is stored as its square root. alpha is unused.
+/// layout(location = 0) out vec4 out_albedo;
+/// // a camera space normal, with roughness in w. the built-in lighting doesn't read roughness yet.
+/// layout(location = 1) out vec4 out_normal_cs;
+/// layout(location = 2) out vec4 out_velocity;
+/// layout(location = 3) out vec4 out_selection;
+/// // how brightly the surface glows in its albedo color, in r.
+/// layout(location = 4) out vec4 out_emissive;
+///
+/// layout(set = 1, binding = 0) uniform Material {
+/// 	uint light_penetration;
+/// 	uint subsurface_scattering;
+/// 	uint emissive_brightness;
+/// 	vec3 base_albedo;
+/// };
+/// layout(set = 1, binding = 1) uniform sampler2D tex_albedo;
+/// layout(set = 1, binding = 2) uniform sampler2D tex_normal;
+/// layout(set = 2, binding = 0) uniform Params { ... } params;
+/// // any number of textures of the material's own, set by name with `ShaderParams::set_texture`
+/// layout(set = 2, binding = 1) uniform sampler2D detail;
+///
+/// layout(push_constant) uniform MeshModel {
+/// 	mat4 model;
+/// 	vec4 position_offset;
+/// 	vec4 position_scale;
+/// 	vec4 prev_position;
+/// 	vec4 prev_rotation;
+/// };
+/// ```
+///
+/// Every output must be written. Velocity, selection, and emission should be written the way the built-in shader does:
+///
+/// ```glsl
+/// vec2 moved = position_clip.xy / position_clip.w - prev_position_clip.xy / prev_position_clip.w;
+/// out_velocity = vec4(moved * 0.5, 0, 0);
+/// out_selection = vec4(selected);
+/// out_emissive = vec4(emissive);
+/// ```
+///
+/// The depth prepass doesn't run custom shaders, so fragments they discard still hide what's behind them when the
+/// prepass is enabled.
+pub struct MaterialShader {
+	module: Arc<ShaderModule>,
+	reflection: ShaderReflection,
+	layout: MaterialLayout<MaterialShaderLayout>,
+	id_root: ObjectIdRoot,
+}
+impl MaterialShader {
+	/// # Safety
+	///
+	/// The bytes must be a valid SPIR-V fragment shader with a `main` entry point, using the interface above. The
+	/// shader's descriptors and push constants are read from the SPIR-V and checked against the interface, and loading
+	/// fails if they don't match, but its inputs and outputs aren't checked, so a mismatch there is undefined behavior.
+	pub unsafe fn from_spirv(device: &DeviceCtx, spirv: &[u8]) -> Result<Arc<Self>, ShaderLoadError> {
+		let reflection = ShaderReflection::new(spirv)?;
+		let stages = ShaderStages { fragment: true, .. ShaderStages::none() };
+		let layout = reflection.material_layout(MaterialShaderLayout, 2, stages)?;
+
+		Ok(Arc::new(Self {
+			module: ShaderModule::new(device.device().clone(), spirv)?,
+			reflection: reflection,
+			layout: layout,
+			id_root: ObjectIdRoot::new(),
+		}))
+	}
+
+	/// The descriptors and push constants the shader declares.
+	pub fn reflection(&self) -> &ShaderReflection {
+		&self.reflection
+	}
+
+	/// The layout of the shader's `Params` block, if it has one.
+	pub fn params_layout(&self) -> Option<&BlockLayout> {
+		self.reflection.descriptor(2, 0).and_then(|desc| desc.block.as_ref())
+	}
+
+	/// Compiles GLSL source with `DeviceCtx::shader_compiler` and loads the result.
+	///
+	/// # Safety
+	///
+	/// The shader must use the interface above, as with `from_spirv`.
+	#[cfg(feature = "shader-compiler")]
+	pub unsafe fn from_glsl(device: &DeviceCtx, source: &str) -> Result<Arc<Self>, ShaderCompileError> {
+		let spirv = device.shader_compiler().compile_glsl(ShaderKind::Fragment, source, "material shader")?;
+		Ok(Self::from_spirv(device, &spirv)?)
+	}
+
+	pub(super) fn entry_point(
+		&self,
+	) -> GraphicsEntryPoint<(), MaterialShaderInterface, MaterialShaderInterface, MaterialLayout<MaterialShaderLayout>>
+	{
+		unsafe {
+			self.module.graphics_entry_point(
+				CStr::from_bytes_with_nul_unchecked(b"main\0"),
+				MaterialShaderInterface(&INPUTS),
+				MaterialShaderInterface(&OUTPUTS),
+				self.layout.clone(),
+				GraphicsShaderType::Fragment,
+			)
+		}
+	}
+
+	pub(super) fn id_root(&self) -> &ObjectIdRoot {
+		&self.id_root
+	}
+}
+
+/// A `MaterialShader` and the parameters it's drawn with. Give it to `Mesh::set_submesh_material`.
+pub struct CustomMaterial {
+	shader: Arc<MaterialShader>,
+	pipelines: Arc<MaterialShaderPipelines>,
+	params: ShaderParams,
+}
+impl CustomMaterial {
+	/// Fails if the shader doesn't match the interface documented on `MaterialShader`.
+	pub fn new(
+		render_pass: &MeshRenderPass,
+		shader: Arc<MaterialShader>,
+	) -> Result<Self, GraphicsPipelineCreationError> {
+		let pipelines = render_pass.pipelines_for(&shader)?;
+		let pipeline = pipelines.get(GBufferStage::GBuffers, Topology::TriangleList, VertexFormat::Full).clone();
+		let (default_texture, sampler) = (&render_pass.shaders.texture1_default, render_pass.shaders.sampler.clone());
+		let params = ShaderParams::new(&pipeline, &shader.reflection, 2, default_texture, sampler);
+
+		Ok(Self { shader: shader, pipelines: pipelines, params: params })
+	}
+
+	pub fn shader(&self) -> &Arc<MaterialShader> {
+		&self.shader
+	}
+
+	pub fn params(&self) -> &ShaderParams {
+		&self.params
+	}
+
+	/// The shader's `Params` block and textures, for setting their contents. Animated materials can set them every
+	/// frame.
+	pub fn params_mut(&mut self) -> &mut ShaderParams {
+		&mut self.params
+	}
+
+	pub(super) fn pipeline(
+		&self,
+		stage: GBufferStage,
+		topology: Topology,
+		vertex_format: VertexFormat,
+	) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		self.pipelines.get(stage, topology, vertex_format)
+	}
+
+	pub(super) fn params_desc(&self) -> Result<MaterialSet, OomError> {
+		self.params.descriptor_set()
+	}
+}
+
+const INPUTS: [(u32, Format, &str); 8] = [
+	(0, Format::R32G32B32Sfloat, "position_cs"),
+	(1, Format::R32G32B32Sfloat, "normal_cs"),
+	(2, Format::R32G32Sfloat, "texcoord"),
+	(3, Format::R32G32B32Sfloat, "base_albedo"),
+	(4, Format::R32G32B32A32Sfloat, "position_clip"),
+	(5, Format::R32G32B32A32Sfloat, "prev_position_clip"),
+	(6, Format::R32Sfloat, "selected"),
+	(7, Format::R32Sfloat, "emissive"),
+];
+
+const OUTPUTS: [(u32, Format, &str); 5] = [
+	(0, Format::R32G32B32A32Sfloat, "out_albedo"),
+	(1, Format::R32G32B32A32Sfloat, "out_normal_cs"),
+	(2, Format::R32G32B32A32Sfloat, "out_velocity"),
+	(3, Format::R32G32B32A32Sfloat, "out_selection"),
+	(4, Format::R32G32B32A32Sfloat, "out_emissive"),
+];
+
+#[derive(Clone, Copy, Debug)]
+pub(super) struct MaterialShaderInterface(&'static [(u32, Format, &'static str)]);
+unsafe impl ShaderInterfaceDef for MaterialShaderInterface {
+	type Iter = VecIntoIter<ShaderInterfaceDefEntry>;
+
+	fn elements(&self) -> Self::Iter {
+		self.0.iter()
+			.map(|&(location, format, name)| ShaderInterfaceDefEntry {
+				location: location..location + 1,
+				format: format,
+				name: Some(Cow::Borrowed(name)),
+			})
+			.collect::<Vec<_>>()
+			.into_iter()
+	}
+}
+
+/// The descriptors and push constants the engine binds for a custom gbuffer fragment shader. The material's own set
+/// comes from the shader's reflection.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct MaterialShaderLayout;
+unsafe impl PipelineLayoutDesc for MaterialShaderLayout {
+	fn num_sets(&self) -> usize {
+		2
+	}
+
+	fn num_bindings_in_set(&self, set: usize) -> Option<usize> {
+		match set {
+			0 => Some(0),
+			1 => Some(3),
+			_ => None,
+		}
+	}
+
+	fn descriptor(&self, set: usize, binding: usize) -> Option<DescriptorDesc> {
+		let uniform =
+			DescriptorDesc {
+				ty: DescriptorDescTy::Buffer(DescriptorBufferDesc { dynamic: Some(false), storage: false }),
+				array_count: 1,
+				stages: ShaderStages { fragment: true, .. ShaderStages::none() },
+				readonly: true,
+			};
+		let texture =
+			DescriptorDesc {
+				ty: DescriptorDescTy::CombinedImageSampler(DescriptorImageDesc {
+					sampled: true,
+					dimensions: DescriptorImageDescDimensions::TwoDimensional,
+					format: None,
+					multisampled: false,
+					array_layers: DescriptorImageDescArray::NonArrayed,
+				}),
+				.. uniform.clone()
+			};
+
+		match (set, binding) {
+			(1, 0) => Some(uniform),
+			(1, 1) | (1, 2) => Some(texture),
+			_ => None,
+		}
+	}
+
+	fn num_push_constants_ranges(&self) -> usize {
+		1
+	}
+
+	fn push_constants_range(&self, num: usize) -> Option<PipelineLayoutDescPcRange> {
+		if num == 0 {
+			Some(PipelineLayoutDescPcRange {
+				offset: 0,
+				size: 128,
+				stages: ShaderStages { fragment: true, .. ShaderStages::none() },
+			})
+		} else {
+			None
+		}
+	}
+}
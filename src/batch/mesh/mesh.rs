@@ -1,119 +1,655 @@
+mod builder;
 mod codec;
+mod dynamic;
+mod foliage;
+mod group;
+mod indices;
+mod morph;
+mod socket;
+mod vertices;
 
+pub use self::builder::{ MeshBuildError, MeshBuilder };
+pub use self::dynamic::DynamicMeshError;
+pub use self::foliage::{ DensityMap, Foliage, FoliageBuilder, FoliageId, ScatterOptions, Wind };
+pub use self::group::{ MeshGroup, MeshGroupBuilder, MeshGroupError };
+pub use self::morph::MorphTarget;
+pub use self::socket::{ Socket, SocketTransform };
+pub use self::vertices::VertexFormat;
+pub(crate) use self::codec::ModelData;
+pub(crate) use self::group::{ StaticBatcher, supports_groups };
+use self::dynamic::DynamicGeometry;
+use self::foliage::FoliageInstance;
+use self::indices::{ IndexBuffer, IndexSlice };
+use self::morph::MorphGeometry;
+use self::vertices::{ VertexBuffers, compressed_normal_format };
+use super::material_shader::CustomMaterial;
+use super::render_pass::{ ForwardPipelines, GBufferStage };
+use super::shaders::vs_gbuffers;
+
+use crate::batch::StencilState;
 use crate::batch::mesh::MeshRenderPass;
 use crate::cpu_pool::spawn_fs;
+use crate::device::{ MemoryCategory, MemoryHandle };
+use crate::progress::{ CancelToken, Cancelled, LoadProgress };
+use crate::spatial::{ Aabb, Bvh, Cast, CollisionData, Hit };
+use crate::texture::{ ImmutableTexture, SamplerSettings, Texture };
+use crate::uniform::{ Uniform, UniformWriter };
 use crate::window::Window;
 use atom::Atom;
-use cgmath::{ Quaternion, Vector3 };
+use cgmath::{ Matrix4, One, Quaternion, Vector3, Zero };
 use futures::prelude::*;
-use std::{ io, mem::size_of, path::Path, sync::Arc, vec::IntoIter as VecIntoIter, };
+use std::{
+	error::Error,
+	fmt,
+	io,
+	mem::{ self, size_of },
+	ops::Range,
+	path::Path,
+	sync::{ Arc, Mutex, atomic::{ AtomicUsize, Ordering } },
+	vec::IntoIter as VecIntoIter,
+};
 use vulkano::{
 	OomError,
-	buffer::{ BufferAccess, BufferSlice, CpuBufferPool, ImmutableBuffer, cpu_pool::CpuBufferPoolSubbuffer },
-	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, DynamicState },
-	descriptor::{ DescriptorSet, descriptor_set::FixedSizeDescriptorSetsPool },
+	buffer::{ BufferAccess, BufferSlice, ImmutableBuffer },
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::{ DescriptorSet, descriptor_set::{ DescriptorSetsCollection, PersistentDescriptorSet } },
+	device::{ Device, Queue },
 	format::Format,
-	instance::QueueFamily,
-	memory::{ DeviceMemoryAllocError, pool::StdMemoryPool },
+	image::ImageViewAccess,
+	memory::DeviceMemoryAllocError,
 	pipeline::{
 		GraphicsPipelineAbstract,
 		vertex::{ AttributeInfo, IncompatibleVertexDefinitionError, InputRate, VertexDefinition, VertexSource },
-		viewport::Viewport
 	},
+	sampler::{ Sampler, SamplerCreationError },
 	sync::GpuFuture,
 };
 
 pub struct Mesh {
-	position_pool: CpuBufferPool<Vector3<f32>>,
-	rotation_pool: CpuBufferPool<Quaternion<f32>>,
-	position: CpuBufferPoolSubbuffer<Vector3<f32>, Arc<StdMemoryPool>>,
-	rotation: CpuBufferPoolSubbuffer<Quaternion<f32>, Arc<StdMemoryPool>>,
-	positions: Arc<ImmutableBuffer<[[f32; 3]]>>,
-	normals: Arc<ImmutableBuffer<[[f32; 3]]>>,
-	texcoords_main: Arc<ImmutableBuffer<[[f32; 2]]>>,
+	render_pass: Arc<MeshRenderPass>,
+	position: Vector3<f32>,
+	rotation: Quaternion<f32>,
+	/// The transform the last time the batch was recorded, for motion vectors.
+	previous_position: Vector3<f32>,
+	previous_rotation: Quaternion<f32>,
+	selected: bool,
+	visible: bool,
+	stencil: Option<StencilState>,
+	sockets: Vec<Socket>,
+	vertices: VertexBuffers,
 	materials: Vec<Material>,
+	topology: Topology,
+	dynamic: Option<DynamicGeometry>,
+	/// Set for meshes built with morph targets, whose vertex buffers are blended from them.
+	morph: Option<MorphGeometry>,
+	/// For casts against the mesh. Only built when asked for, since it keeps a copy of the geometry on the CPU.
+	bvh: Option<Arc<Bvh>>,
+	/// Only kept when asked for, like `bvh`.
+	collision: Option<Arc<CollisionData>>,
+	/// Taken on drop, so the mesh's memory stays tracked until its buffers are actually released.
+	memory: Option<MemoryHandle>,
+	/// Textures loaded for this mesh's materials, kept so their memory is tracked for as long as the mesh is alive.
+	_textures: Arc<Mutex<Vec<ImmutableTexture>>>,
 }
 impl Mesh {
 	pub fn from_file(
 		window: &Window,
 		render_pass: Arc<MeshRenderPass>,
 		path: impl AsRef<Path> + Clone + Send + 'static,
-		position: Vector3<f32>,
-		rotation: Quaternion<f32>,
 	) -> impl Future<Output = Result<(Self, impl GpuFuture + Send + Sync + 'static), MeshFromFileError>>
 	{
-		let device = window.device().device().clone();
+		Self::from_file_with_format(window, render_pass, path, VertexFormat::Full)
+	}
+
+	/// Like `from_file`, but also returns a handle for showing how far along the load is, and stops with
+	/// `MeshFromFileError::Cancelled` if the token is cancelled first. Textures that are still loading for the mesh's
+	/// materials are cancelled with it.
+	pub fn from_file_with_progress(
+		window: &Window,
+		render_pass: Arc<MeshRenderPass>,
+		path: impl AsRef<Path> + Clone + Send + 'static,
+		cancel: &CancelToken,
+	) -> (LoadProgress, impl Future<Output = Result<(Self, impl GpuFuture + Send + Sync + 'static), MeshFromFileError>>)
+	{
+		let progress = LoadProgress::with_cancel_token(cancel.clone());
+		let queue = window.device().queue().clone();
+		(progress.clone(), Self::from_file_impl(queue, render_pass, path, VertexFormat::Full, progress))
+	}
+
+	/// Like `from_file`, but stores the vertices in the given format.
+	pub fn from_file_with_format(
+		window: &Window,
+		render_pass: Arc<MeshRenderPass>,
+		path: impl AsRef<Path> + Clone + Send + 'static,
+		vertex_format: VertexFormat,
+	) -> impl Future<Output = Result<(Self, impl GpuFuture + Send + Sync + 'static), MeshFromFileError>>
+	{
+		Self::from_file_impl(window.device().queue().clone(), render_pass, path, vertex_format, LoadProgress::new())
+	}
+
+	pub(crate) fn from_file_impl(
+		queue: Arc<Queue>,
+		render_pass: Arc<MeshRenderPass>,
+		path: impl AsRef<Path> + Clone + Send + 'static,
+		vertex_format: VertexFormat,
+		progress: LoadProgress,
+	) -> impl Future<Output = Result<(Self, impl GpuFuture + Send + Sync + 'static), MeshFromFileError>>
+	{
+		spawn_fs(move || codec::from_nice_model(queue, render_pass, path, vertex_format, progress))
+	}
+
+	/// Like `from_file`, but also builds a bounding volume hierarchy over the mesh's triangles while it loads, so rays
+	/// and spheres can be cast against it with `cast` and `MeshBatch::cast`.
+	pub fn from_file_with_bvh(
+		window: &Window,
+		render_pass: Arc<MeshRenderPass>,
+		path: impl AsRef<Path> + Clone + Send + 'static,
+	) -> impl Future<Output = Result<(Self, impl GpuFuture + Send + Sync + 'static), MeshFromFileError>>
+	{
+		let options = MeshLoadOptions { build_bvh: true, .. Default::default() };
+		Self::from_file_with_options(window, render_pass, path, options)
+	}
+
+	/// Like `from_file`, but with control over the vertex format and what's kept on the CPU after upload.
+	pub fn from_file_with_options(
+		window: &Window,
+		render_pass: Arc<MeshRenderPass>,
+		path: impl AsRef<Path> + Clone + Send + 'static,
+		options: MeshLoadOptions,
+	) -> impl Future<Output = Result<(Self, impl GpuFuture + Send + Sync + 'static), MeshFromFileError>>
+	{
 		let queue = window.device().queue().clone();
-		spawn_fs(move || codec::from_nice_model(device, queue, render_pass, path, position, rotation))
+		spawn_fs(move || {
+			let progress = LoadProgress::new();
+			let data = codec::read_nice_model(path, &progress)?;
+			let collision = CollisionData { positions: data.positions.clone(), indices: data.indices.clone() };
+			let (mut mesh, future) = codec::upload_model(queue, render_pass, data, options.vertex_format, &progress)?;
+			if options.build_bvh {
+				mesh.set_bvh(Some(Arc::new(collision.build_bvh())));
+			}
+			if options.keep_collision_data {
+				mesh.set_collision_data(Some(Arc::new(collision)));
+			}
+			Ok((mesh, future))
+		})
+	}
+
+	/// Reads a model file without uploading it, so it can be merged into mesh groups or uploaded with `from_model`.
+	pub(crate) fn read_model(
+		path: impl AsRef<Path> + Send + 'static,
+		progress: LoadProgress,
+	) -> impl Future<Output = Result<ModelData, MeshFromFileError>> {
+		spawn_fs(move || codec::read_nice_model(path, &progress))
+	}
+
+	pub(crate) fn from_model(
+		queue: Arc<Queue>,
+		render_pass: Arc<MeshRenderPass>,
+		model: ModelData,
+		vertex_format: VertexFormat,
+		progress: &LoadProgress,
+	) -> Result<(Self, impl GpuFuture + Send + Sync + 'static), MeshFromFileError> {
+		codec::upload_model(queue, render_pass, model, vertex_format, progress)
 	}
 
-	pub fn set_position(&mut self, position: Vector3<f32>) -> Result<(), DeviceMemoryAllocError> {
-		self.position = self.position_pool.next(position)?;
+	/// Moves the mesh. The transform is pushed with each draw, so this is cheap to call every frame.
+	pub fn set_position(&mut self, position: Vector3<f32>) {
+		self.position = position;
+	}
+
+	pub fn set_rotation(&mut self, rotation: Quaternion<f32>) {
+		self.rotation = rotation;
+	}
+
+	pub fn position(&self) -> Vector3<f32> {
+		self.position
+	}
+
+	pub fn rotation(&self) -> Quaternion<f32> {
+		self.rotation
+	}
+
+	/// Selected meshes are outlined. See `MeshBatch::set_outline`.
+	pub fn set_selected(&mut self, selected: bool) {
+		self.selected = selected;
+	}
+
+	pub fn is_selected(&self) -> bool {
+		self.selected
+	}
+
+	/// Hidden meshes stay in their batch, with their buffers, but aren't drawn or cast shadows. This is cheaper than
+	/// removing and re-adding a mesh that's toggled often, like a door or a pickup.
+	pub fn set_visible(&mut self, visible: bool) {
+		self.visible = visible;
+	}
+
+	pub fn is_visible(&self) -> bool {
+		self.visible
+	}
+
+	/// Draws the mesh with a stencil test, or as a mask for later meshes, like a portal or a mirror's surface. This
+	/// only has an effect if the render pass was made with `MeshRenderPassOptions::stencil`. Stenciled meshes are drawn
+	/// with the built-in material shader, even if their materials have custom ones, and shadows ignore the stencil.
+	pub fn set_stencil(&mut self, stencil: Option<StencilState>) {
+		self.stencil = stencil;
+	}
+
+	pub fn stencil(&self) -> Option<StencilState> {
+		self.stencil
+	}
+
+	/// Adds a named attachment point, replacing any socket with the same name.
+	pub fn add_socket(&mut self, socket: Socket) {
+		match self.sockets.iter_mut().find(|old| old.name == socket.name) {
+			Some(old) => *old = socket,
+			None => self.sockets.push(socket),
+		}
+	}
+
+	pub fn socket(&self, name: &str) -> Option<&Socket> {
+		self.sockets.iter().find(|socket| socket.name == name)
+	}
+
+	/// Lets a socket be moved, like a muzzle that slides as a gun is reloaded.
+	pub fn socket_mut(&mut self, name: &str) -> Option<&mut Socket> {
+		self.sockets.iter_mut().find(|socket| socket.name == name)
+	}
+
+	pub fn remove_socket(&mut self, name: &str) -> Option<Socket> {
+		self.sockets.iter().position(|socket| socket.name == name).map(|i| self.sockets.remove(i))
+	}
+
+	pub fn sockets(&self) -> &[Socket] {
+		&self.sockets
+	}
+
+	/// Where a socket is in world space, at the mesh's current transform. Call this each frame after moving the mesh to
+	/// keep whatever's attached to the socket following it, like a weapon's mesh, a particle emitter, or a UI marker
+	/// placed with `Camera::world_to_screen`.
+	pub fn socket_transform(&self, name: &str) -> Option<SocketTransform> {
+		self.socket(name).map(|socket| socket.transform(self.position, self.rotation))
+	}
+
+	/// Sets the hierarchy used for casts against this mesh, like one from `MeshBuilder::build_bvh`. It should be built
+	/// from the same triangles the mesh draws. `None` removes it, freeing its memory.
+	pub fn set_bvh(&mut self, bvh: Option<Arc<Bvh>>) {
+		self.bvh = bvh;
+	}
+
+	pub fn bvh(&self) -> Option<&Arc<Bvh>> {
+		self.bvh.as_ref()
+	}
+
+	/// The mesh's triangles on the CPU, if they were kept with `MeshLoadOptions::keep_collision_data` or
+	/// `MeshBuilder::set_keep_collision_data`. Dynamic meshes don't update these when their geometry changes.
+	pub fn collision_data(&self) -> Option<&Arc<CollisionData>> {
+		self.collision.as_ref()
+	}
+
+	/// Replaces the triangles returned by `collision_data`, like with a simplified version of the mesh. `None` frees
+	/// them.
+	pub fn set_collision_data(&mut self, collision: Option<Arc<CollisionData>>) {
+		self.collision = collision;
+	}
+
+	/// Casts a ray or sphere in world space against the mesh, returning the first hit in world space. Meshes without a
+	/// hierarchy are never hit.
+	pub fn cast(&self, cast: &Cast) -> Option<Hit> {
+		let bvh = self.bvh.as_ref()?;
+		bvh.cast(&cast.to_local(self.position, self.rotation)).map(|hit| hit.to_world(self.position, self.rotation))
+	}
+
+	/// Whether a ray or sphere in world space hits the mesh at all.
+	pub fn cast_any(&self, cast: &Cast) -> bool {
+		self.bvh.as_ref().map_or(false, |bvh| bvh.cast_any(&cast.to_local(self.position, self.rotation)))
+	}
+
+	/// Forgets the previous transform, so a mesh that was teleported isn't motion blurred.
+	pub fn reset_motion(&mut self) {
+		self.previous_position = self.position;
+		self.previous_rotation = self.rotation;
+	}
+
+	/// Replaces the vertices of a mesh built with `MeshBuilder::build_dynamic`. The new vertices are uploaded by the batch
+	/// the next time it records commands.
+	pub fn update_vertices(
+		&mut self,
+		positions: Vec<[f32; 3]>,
+		normals: Vec<[f32; 3]>,
+		texcoords_main: Vec<[f32; 2]>,
+	) -> Result<(), DynamicMeshError> {
+		self.dynamic.as_mut()
+			.ok_or(DynamicMeshError::NotDynamic)?
+			.set_vertices(positions, normals, texcoords_main)
+	}
+
+	/// Replaces the indices of a mesh built with `MeshBuilder::build_dynamic`. The new indices are uploaded by the batch
+	/// the next time it records commands. For triangle strips, `u32::MAX` restarts the strip. Every other index must be
+	/// below the vertex capacity the mesh was built with.
+	pub fn update_indices(&mut self, indices: Vec<u32>) -> Result<(), DynamicMeshError> {
+		let slice = self.dynamic.as_mut().ok_or(DynamicMeshError::NotDynamic)?.set_indices(indices)?;
+		self.materials[0].indices = slice;
 		Ok(())
 	}
 
-	pub fn set_rotation(&mut self, rotation: Quaternion<f32>) -> Result<(), DeviceMemoryAllocError> {
-		self.rotation = self.rotation_pool.next(rotation)?;
+	/// The names of the mesh's morph targets, in the order they were added. Meshes built without any have none.
+	pub fn morph_targets(&self) -> &[String] {
+		self.morph.as_ref().map_or(&[][..], |morph| morph.names())
+	}
+
+	/// The index of the morph target with the given name, for `set_morph_weight`.
+	pub fn morph_target_index(&self, name: &str) -> Option<usize> {
+		self.morph_targets().iter().position(|target| target == name)
+	}
+
+	/// How far the mesh is blended toward a morph target, usually from 0.0 to 1.0. Weights can be changed every frame,
+	/// and the mesh is only blended again when one has changed. Panics if the mesh has no such target.
+	pub fn set_morph_weight(&mut self, target: usize, weight: f32) {
+		self.morph.as_mut().expect("mesh has no morph targets").set_weight(target, weight);
+	}
+
+	/// The weight of each morph target, in the order of `morph_targets`.
+	pub fn morph_weights(&self) -> &[f32] {
+		self.morph.as_ref().map_or(&[][..], |morph| morph.weights())
+	}
+
+	/// The number of sections of the mesh that are drawn with separate materials.
+	pub fn submesh_count(&self) -> usize {
+		self.materials.len()
+	}
+
+	/// The range of the mesh's index buffer that is drawn with the given submesh's material.
+	pub fn submesh_indices(&self, submesh: usize) -> Range<usize> {
+		let mat = &self.materials[submesh];
+		mat.index_start..mat.index_start + mat.indices.len()
+	}
+
+	/// Replaces the textures of a submesh's material. If the mesh's own textures are still loading, they're discarded
+	/// when they finish instead of replacing these.
+	pub fn set_submesh_textures(&mut self, submesh: usize, albedo: &Texture, normal: &Texture) {
+		self.materials[submesh].binding.update(&self.render_pass, |textures| {
+			textures.albedo = albedo.image().clone();
+			textures.normal = normal.image().clone();
+			textures.custom_textures = true;
+		});
+	}
+
+	/// Overrides how a submesh's textures are sampled. Other submeshes keep using the render pass's sampler. The
+	/// override is kept when the render pass's quality settings change.
+	pub fn set_submesh_sampler(
+		&mut self,
+		submesh: usize,
+		settings: &SamplerSettings,
+	) -> Result<(), SamplerCreationError> {
+		let sampler = settings.build(self.render_pass.shaders.queue.device())?;
+		self.materials[submesh].binding.update(&self.render_pass, |textures| {
+			textures.sampler = sampler;
+			textures.custom_sampler = true;
+		});
 		Ok(())
 	}
 
-	pub(super) fn make_commands(
+	/// Draws a submesh with a custom gbuffer shader, or with the built-in one if `material` is `None`.
+	pub fn set_submesh_material(&mut self, submesh: usize, material: Option<CustomMaterial>) {
+		self.materials[submesh].custom = material;
+	}
+
+	/// Makes a submesh glow in its albedo color, like a neon sign or a screen. The intensity is added to the light
+	/// reaching the surface, so 1.0 is about as bright as a well lit surface, and higher values are picked up by bloom.
+	/// It's pushed with each draw, so it can be animated every frame.
+	pub fn set_submesh_emissive(&mut self, submesh: usize, intensity: f32) {
+		self.materials[submesh].emissive = intensity;
+	}
+
+	pub fn submesh_emissive(&self, submesh: usize) -> f32 {
+		self.materials[submesh].emissive
+	}
+
+	/// The custom material a submesh is drawn with, so its parameters can be changed.
+	pub fn submesh_material_mut(&mut self, submesh: usize) -> Option<&mut CustomMaterial> {
+		self.materials[submesh].custom.as_mut()
+	}
+
+	fn from_parts(
+		render_pass: Arc<MeshRenderPass>,
+		name: String,
+		vertices: VertexBuffers,
+		materials: Vec<Material>,
+		topology: Topology,
+		dynamic: Option<DynamicGeometry>,
+		textures: Arc<Mutex<Vec<ImmutableTexture>>>,
+	) -> Self {
+		let index_bytes =
+			match &dynamic {
+				Some(dynamic) => dynamic.index_buffer().size(),
+				None => materials.iter().map(|mat| mat.indices.size()).sum(),
+			};
+		let bytes =
+			vertices.positions.size() +
+			vertices.normals.size() +
+			vertices.texcoords_main.size() +
+			index_bytes +
+			materials.first().map(|mat| mat.binding.uniform.buffer().size()).unwrap_or(0);
+		let memory =
+			render_pass.shaders.device_ctx.memory_stats().track(MemoryCategory::Meshes, name.clone(), bytes as u64);
+
+		let debug_names = render_pass.shaders.device_ctx.debug_names();
+		debug_names.name_buffer(&*vertices.positions, &format!("{} positions", name));
+		debug_names.name_buffer(&*vertices.normals, &format!("{} normals", name));
+		debug_names.name_buffer(&*vertices.texcoords_main, &format!("{} texcoords", name));
+		if let Some(mat) = materials.first() {
+			debug_names.name_buffer(mat.indices.buffer(), &format!("{} indices", name));
+			debug_names.name_buffer(&mat.binding.uniform, &format!("{} materials", name));
+		}
+
+		Self {
+			render_pass: render_pass,
+			position: Vector3::zero(),
+			rotation: Quaternion::one(),
+			previous_position: Vector3::zero(),
+			previous_rotation: Quaternion::one(),
+			selected: false,
+			visible: true,
+			stencil: None,
+			sockets: vec![],
+			vertices: vertices,
+			materials: materials,
+			topology: topology,
+			dynamic: dynamic,
+			morph: None,
+			bvh: None,
+			collision: None,
+			memory: Some(memory),
+			_textures: textures,
+		}
+	}
+
+	/// Records copies of any changed dynamic geometry. This must be called outside of a render pass, before the mesh is
+	/// drawn.
+	pub(super) fn record_uploads(
+		&mut self,
+		mut cmd: AutoCommandBufferBuilder,
+	) -> Result<AutoCommandBufferBuilder, DeviceMemoryAllocError> {
+		if let Some(dynamic) = &mut self.dynamic {
+			cmd = dynamic.record_uploads(cmd)?;
+		}
+		if let Some(morph) = &mut self.morph {
+			cmd = morph.record_blend(cmd)?;
+		}
+
+		Ok(cmd)
+	}
+
+	/// Records the mesh's draws inline into the batch's G-buffer subpass.
+	pub(super) fn add_commands(
 		&mut self,
+		mut cmd: AutoCommandBufferBuilder,
 		render_pass: &MeshRenderPass,
 		camera_desc: impl DescriptorSet + Clone + Send + Sync + 'static,
-		mesh_desc_pool: &mut FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
-		queue_family: QueueFamily,
-		dimensions: [f32; 2],
-	) -> Result<AutoCommandBuffer, OomError> {
-		let mut cmd = AutoCommandBufferBuilder
-			::secondary_graphics_one_time_submit(
-				render_pass.shaders.target_vertices.device().clone(),
-				queue_family,
-				render_pass.subpass_gbuffers.clone()
-			)?;
-
-		let state =
-			DynamicState {
-				line_width: None,
-				viewports: Some(vec![Viewport { origin: [0.0, 0.0], dimensions: dimensions, depth_range: 0.0..1.0 }]),
-				scissors: None,
+		state: &DynamicState,
+		stage: GBufferStage,
+	) -> Result<AutoCommandBufferBuilder, OomError> {
+		if !self.visible {
+			return Ok(cmd);
+		}
+
+		let stencil = self.stencil.filter(|_| render_pass.has_stencil() && stage != GBufferStage::Shadow);
+		let pipeline =
+			match &stencil {
+				Some(stencil) => render_pass.pipeline_stencil_for(stage, self.topology, self.vertices.format, stencil),
+				None => render_pass.pipeline_gbuffers_for(stage, self.topology, self.vertices.format).clone(),
 			};
 
-		for mat in &self.materials {
-			let desc = mat.desc.take().unwrap();
-
-			cmd = cmd
-				.draw_indexed(
-					render_pass.pipeline_gbuffers.clone(),
-					&state,
-					vec![self.positions.clone(), self.normals.clone(), self.texcoords_main.clone()],
-					mat.indices.clone(),
-					(
-						camera_desc.clone(),
-						mesh_desc_pool.next()
-							.add_buffer(self.position.clone())
-							.unwrap()
-							.add_buffer(self.rotation.clone())
-							.unwrap()
-							.build()
-							.unwrap(),
-						desc.clone()
-					),
-					()
-				)
-				.unwrap();
+		let model = self.model();
+		let [sx, sy, sz] = self.vertices.position_scale;
+		for mat in self.materials.iter_mut().filter(|mat| mat.indices.len() != 0) {
+			mat.binding.refresh_sampler(render_pass);
+			let desc = mat.binding.desc.take().unwrap();
+			let model = vs_gbuffers::ty::MeshModel { position_scale: [sx, sy, sz, mat.emissive], .. model };
 
-			mat.desc.set_if_none(desc);
+			let vertex_buffers =
+				vec![self.vertices.positions.clone(), self.vertices.normals.clone(), self.vertices.texcoords_main.clone()];
+			render_pass.shaders.device_ctx.render_stats().record_draw(triangle_count(self.topology, &mat.indices));
+			cmd =
+				match &mut mat.custom {
+					// depth only stages always use the built-in pipeline, and so do stenciled meshes
+					Some(custom) if !stage.is_depth_only() && stencil.is_none() => {
+						let pipeline = custom.pipeline(stage, self.topology, self.vertices.format).clone();
+						let sets = (camera_desc.clone(), desc.clone(), custom.params_desc()?);
+						draw_indexed(cmd, pipeline, state, vertex_buffers, &mat.indices, sets, model)
+					},
+					_ => {
+						let sets = (camera_desc.clone(), desc.clone());
+						draw_indexed(cmd, pipeline.clone(), state, vertex_buffers, &mat.indices, sets, model)
+					},
+				};
+
+			mat.binding.desc.set_if_none(desc);
 		}
 
-		Ok(cmd.build().map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?)
+		Ok(cmd)
+	}
+
+	/// Draws the mesh lit in a single pass, for `ForwardMeshBatch`. Custom materials and stencils are ignored, so every
+	/// submesh is shaded like a built-in material.
+	pub(super) fn add_forward_commands(
+		&mut self,
+		mut cmd: AutoCommandBufferBuilder,
+		render_pass: &MeshRenderPass,
+		forward: &ForwardPipelines,
+		camera_desc: impl DescriptorSet + Clone + Send + Sync + 'static,
+		lights_desc: impl DescriptorSet + Clone + Send + Sync + 'static,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, OomError> {
+		if !self.visible {
+			return Ok(cmd);
+		}
+
+		let pipeline = forward.get(self.topology, self.vertices.format);
+		let model = self.model();
+		let [sx, sy, sz] = self.vertices.position_scale;
+		for mat in self.materials.iter_mut().filter(|mat| mat.indices.len() != 0) {
+			mat.binding.refresh_sampler(render_pass);
+			let desc = mat.binding.desc.take().unwrap();
+			let model = vs_gbuffers::ty::MeshModel { position_scale: [sx, sy, sz, mat.emissive], .. model };
+
+			let vertex_buffers =
+				vec![self.vertices.positions.clone(), self.vertices.normals.clone(), self.vertices.texcoords_main.clone()];
+			render_pass.shaders.device_ctx.render_stats().record_draw(triangle_count(self.topology, &mat.indices));
+			let sets = (camera_desc.clone(), desc.clone(), lights_desc.clone());
+			cmd = draw_indexed(cmd, pipeline.clone(), state, vertex_buffers, &mat.indices, sets, model);
+
+			mat.binding.desc.set_if_none(desc);
+		}
+
+		Ok(cmd)
+	}
+
+	/// The mesh's bounding box in world space, from the bounds its positions were quantized to. `None` for dynamic and
+	/// morphing meshes, whose vertices can move outside those bounds.
+	pub(super) fn world_bounds(&self) -> Option<Aabb> {
+		if self.dynamic.is_some() || self.morph.is_some() {
+			return None;
+		}
+
+		let offset = self.vertices.position_offset;
+		let scale = self.vertices.position_scale;
+		let mut bounds = Aabb::empty();
+		for corner in 0..8 {
+			let axis = |axis: usize| offset[axis] + if corner >> axis & 1 != 0 { scale[axis] } else { 0.0 };
+			bounds.grow(self.position + self.rotation * Vector3::new(axis(0), axis(1), axis(2)));
+		}
+		Some(bounds)
+	}
+
+	/// The push constants for the mesh's transform. The emissive intensity is filled in for each submesh.
+	fn model(&self) -> vs_gbuffers::ty::MeshModel {
+		let [ox, oy, oz] = self.vertices.position_offset;
+		let [sx, sy, sz] = self.vertices.position_scale;
+		vs_gbuffers::ty::MeshModel {
+			model: (Matrix4::from_translation(self.position) * Matrix4::from(self.rotation)).into(),
+			position_offset: [ox, oy, oz, self.selected as u32 as f32],
+			position_scale: [sx, sy, sz, 0.0],
+			prev_position: self.previous_position.extend(0.0).into(),
+			prev_rotation: self.previous_rotation.v.extend(self.previous_rotation.s).into(),
+		}
+	}
+
+	/// Called once the batch has recorded every camera, so the next frame's motion vectors start from this transform.
+	pub(super) fn end_frame(&mut self) {
+		self.reset_motion();
 	}
 }
+impl Drop for Mesh {
+	fn drop(&mut self) {
+		// frames still on the GPU may draw this mesh, so its buffers and descriptors are kept until they finish
+		let resources = (
+			self.vertices.positions.clone(),
+			self.vertices.normals.clone(),
+			self.vertices.texcoords_main.clone(),
+			mem::replace(&mut self.materials, vec![]),
+			self.dynamic.take(),
+			self.morph.take(),
+			self._textures.clone(),
+			self.memory.take(),
+		);
+		self.render_pass.shaders.device_ctx.defer_destroy(resources);
+	}
+}
+
+/// Options for `Mesh::from_file_with_options`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MeshLoadOptions {
+	pub vertex_format: VertexFormat,
+	/// Builds a bounding volume hierarchy for `Mesh::cast`.
+	pub build_bvh: bool,
+	/// Keeps a copy of the positions and indices for `Mesh::collision_data`, so other systems can use the geometry
+	/// without reading the file again.
+	pub keep_collision_data: bool,
+}
 
-pub struct MeshVertexDefinition {}
+pub struct MeshVertexDefinition {
+	format: VertexFormat,
+	/// The format of compressed normals, which depends on what the device supports.
+	normal_format: Format,
+	/// Whether a fourth buffer holds a `FoliageInstance` for each instance.
+	instanced: bool,
+}
 impl MeshVertexDefinition {
-	pub fn new() -> Self {
-		Self {}
+	pub fn new(device: &Device, format: VertexFormat) -> Self {
+		Self { format: format, normal_format: compressed_normal_format(device), instanced: false }
+	}
+
+	pub(in crate::batch::mesh) fn instanced(device: &Device, format: VertexFormat) -> Self {
+		Self { format: format, normal_format: compressed_normal_format(device), instanced: true }
+	}
+
+	fn position_stride(&self) -> usize {
+		match self.format {
+			VertexFormat::Full => size_of::<[f32; 3]>(),
+			VertexFormat::Compressed => size_of::<[u16; 4]>(),
+		}
 	}
 }
 unsafe impl<I> VertexDefinition<I> for MeshVertexDefinition {
@@ -125,18 +661,40 @@ unsafe impl<I> VertexDefinition<I> for MeshVertexDefinition {
 		_interface: &I
 	) -> Result<(Self::BuffersIter, Self::AttribsIter), IncompatibleVertexDefinitionError> {
 		// TODO: validate against shader
-		Ok((
+		let packed_normals = self.normal_format == Format::A2B10G10R10SnormPack32;
+		let (normal_stride, texcoord_stride, formats) =
+			match self.format {
+				VertexFormat::Full => (
+					size_of::<[f32; 3]>(),
+					size_of::<[f32; 2]>(),
+					[Format::R32G32B32Sfloat, Format::R32G32B32Sfloat, Format::R32G32Sfloat],
+				),
+				VertexFormat::Compressed => (
+					if packed_normals { size_of::<u32>() } else { size_of::<[i16; 4]>() },
+					size_of::<[u16; 2]>(),
+					[Format::R16G16B16A16Unorm, self.normal_format, Format::R16G16Sfloat],
+				),
+			};
+
+		let mut buffers =
 			vec![
-				(0, size_of::<[f32; 3]>(), InputRate::Vertex),
-				(1, size_of::<[f32; 3]>(), InputRate::Vertex),
-				(2, size_of::<[f32; 2]>(), InputRate::Vertex)
-			].into_iter(),
+				(0, self.position_stride(), InputRate::Vertex),
+				(1, normal_stride, InputRate::Vertex),
+				(2, texcoord_stride, InputRate::Vertex)
+			];
+		let mut attributes =
 			vec![
-				(0, 0, AttributeInfo { offset: 0, format: Format::R32G32B32Sfloat }),
-				(1, 1, AttributeInfo { offset: 0, format: Format::R32G32B32Sfloat }),
-				(2, 2, AttributeInfo { offset: 0, format: Format::R32G32Sfloat })
-			].into_iter()
-		))
+				(0, 0, AttributeInfo { offset: 0, format: formats[0] }),
+				(1, 1, AttributeInfo { offset: 0, format: formats[1] }),
+				(2, 2, AttributeInfo { offset: 0, format: formats[2] })
+			];
+		if self.instanced {
+			buffers.push((3, size_of::<FoliageInstance>(), InputRate::Instance));
+			attributes.push((3, 3, AttributeInfo { offset: 0, format: Format::R32G32B32A32Sfloat }));
+			attributes.push((4, 3, AttributeInfo { offset: 16, format: Format::R32G32B32A32Sfloat }));
+		}
+
+		Ok((buffers.into_iter(), attributes.into_iter()))
 	}
 }
 unsafe impl VertexSource<Vec<Arc<BufferAccess + Send + Sync>>> for MeshVertexDefinition {
@@ -145,9 +703,10 @@ unsafe impl VertexSource<Vec<Arc<BufferAccess + Send + Sync>>> for MeshVertexDef
 		&self,
 		source: Vec<Arc<BufferAccess + Send + Sync>>
 	) -> (Vec<Box<BufferAccess + Send + Sync>>, usize, usize) {
-		assert_eq!(source.len(), 3);
-		let len = source[0].size() / size_of::<[f32; 3]>();
-		(source.into_iter().map(|x| Box::new(x) as _).collect(), len, 1)
+		assert_eq!(source.len(), if self.instanced { 4 } else { 3 });
+		let len = source[0].size() / self.position_stride();
+		let instances = if self.instanced { source[3].size() / size_of::<FoliageInstance>() } else { 1 };
+		(source.into_iter().map(|x| Box::new(x) as _).collect(), len, instances)
 	}
 }
 
@@ -155,6 +714,26 @@ unsafe impl VertexSource<Vec<Arc<BufferAccess + Send + Sync>>> for MeshVertexDef
 pub enum MeshFromFileError {
 	Io(io::Error),
 	DeviceMemoryAllocError(DeviceMemoryAllocError),
+	/// The load's `CancelToken` was cancelled.
+	Cancelled,
+}
+impl fmt::Display for MeshFromFileError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			MeshFromFileError::Io(err) => write!(f, "i/o error: {}", err),
+			MeshFromFileError::DeviceMemoryAllocError(err) => write!(f, "device memory allocation failed: {}", err),
+			MeshFromFileError::Cancelled => write!(f, "the load was cancelled"),
+		}
+	}
+}
+impl Error for MeshFromFileError {
+	fn source(&self) -> Option<&(Error + 'static)> {
+		match self {
+			MeshFromFileError::Io(err) => Some(err),
+			MeshFromFileError::DeviceMemoryAllocError(err) => Some(err),
+			MeshFromFileError::Cancelled => None,
+		}
+	}
 }
 impl From<io::Error> for MeshFromFileError{
 	fn from(err: io::Error) -> Self {
@@ -166,10 +745,161 @@ impl From<DeviceMemoryAllocError> for MeshFromFileError{
 		MeshFromFileError::DeviceMemoryAllocError(err)
 	}
 }
+impl From<Cancelled> for MeshFromFileError {
+	fn from(_: Cancelled) -> Self {
+		MeshFromFileError::Cancelled
+	}
+}
+
+/// How a mesh's indices are assembled into triangles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Topology {
+	TriangleList,
+	/// Each index after the first two forms a triangle with the two before it. The largest index value restarts the
+	/// strip.
+	TriangleStrip,
+}
+impl Default for Topology {
+	fn default() -> Self {
+		Topology::TriangleList
+	}
+}
 
 struct Material {
-	indices: BufferSlice<[u32], Arc<ImmutableBuffer<[u32]>>>,
-	desc: Arc<Atom<Box<Arc<DescriptorSet + Sync + Send + 'static>>>>,
+	index_start: usize,
+	indices: IndexSlice,
+	binding: Arc<MaterialBinding>,
+	custom: Option<CustomMaterial>,
+	emissive: f32,
+}
+impl Material {
+	/// Creates a material using the default textures. `material_buf` holds `MaterialUniform`s spaced by
+	/// `material_stride`.
+	fn new(
+		render_pass: &MeshRenderPass,
+		indices: &IndexBuffer,
+		index_range: Range<usize>,
+		material_buf: &Arc<ImmutableBuffer<[u8]>>,
+		material_index: usize,
+		emissive: f32,
+	) -> Self {
+		let material_offset = material_stride(render_pass.shaders.queue.device()) * material_index;
+		let uniform =
+			material_buf.clone()
+				.into_buffer_slice()
+				.slice(material_offset..material_offset + MaterialUniform::size())
+				.unwrap();
+
+		let textures =
+			MaterialTextures {
+				albedo: render_pass.shaders.texture1_default.clone(),
+				normal: render_pass.shaders.texture2_default.clone(),
+				sampler: render_pass.sampler(),
+				custom_sampler: false,
+				custom_textures: false,
+			};
+
+		Self {
+			index_start: index_range.start,
+			indices: indices.slice(index_range),
+			binding:
+				Arc::new(MaterialBinding {
+					desc: Atom::new(Box::new(textures.make_desc(render_pass, uniform.clone()))),
+					uniform: uniform,
+					textures: Mutex::new(textures),
+					sampler_generation: AtomicUsize::new(render_pass.sampler_generation()),
+				}),
+			custom: None,
+			emissive: emissive,
+		}
+	}
+}
+
+/// The resources bound to a material's descriptor set. This is shared with any textures that are still loading, so
+/// they can rebuild the set when they finish.
+struct MaterialBinding {
+	uniform: BufferSlice<[u8], Arc<ImmutableBuffer<[u8]>>>,
+	textures: Mutex<MaterialTextures>,
+	desc: Atom<Box<Arc<DescriptorSet + Sync + Send + 'static>>>,
+	/// The render pass's sampler generation when the descriptor set last used its sampler.
+	sampler_generation: AtomicUsize,
+}
+impl MaterialBinding {
+	/// Changes the textures or sampler, and rebuilds the descriptor set to match.
+	fn update(&self, render_pass: &MeshRenderPass, update: impl FnOnce(&mut MaterialTextures)) {
+		let mut textures = self.textures.lock().unwrap();
+		update(&mut textures);
+		self.desc.swap(Box::new(textures.make_desc(render_pass, self.uniform.clone())));
+	}
+
+	/// Switches to the render pass's current sampler if quality settings replaced it, unless the material overrides it.
+	fn refresh_sampler(&self, render_pass: &MeshRenderPass) {
+		let generation = render_pass.sampler_generation();
+		if self.sampler_generation.swap(generation, Ordering::Relaxed) == generation {
+			return;
+		}
+		if !self.textures.lock().unwrap().custom_sampler {
+			self.update(render_pass, |textures| textures.sampler = render_pass.sampler());
+		}
+	}
+}
+
+struct MaterialTextures {
+	albedo: Arc<ImageViewAccess + Send + Sync + 'static>,
+	normal: Arc<ImageViewAccess + Send + Sync + 'static>,
+	sampler: Arc<Sampler>,
+	/// Whether `sampler` was set on the material, instead of coming from the render pass.
+	custom_sampler: bool,
+	/// Whether the textures were set on the material, so textures loaded with the mesh don't replace them.
+	custom_textures: bool,
+}
+impl MaterialTextures {
+	fn make_desc(
+		&self,
+		render_pass: &MeshRenderPass,
+		uniform: BufferSlice<[u8], Arc<ImmutableBuffer<[u8]>>>,
+	) -> Arc<DescriptorSet + Sync + Send + 'static> {
+		Arc::new(
+			PersistentDescriptorSet::start(render_pass.pipeline_gbuffers.clone(), 1)
+				.add_buffer(uniform)
+				.unwrap()
+				.add_sampled_image(self.albedo.clone(), self.sampler.clone())
+				.unwrap()
+				.add_sampled_image(self.normal.clone(), self.sampler.clone())
+				.unwrap()
+				.build()
+				.unwrap()
+		)
+	}
+}
+
+/// Size of `MaterialUniform` rounded up to the device's minimum uniform buffer alignment.
+fn material_stride(device: &Device) -> usize {
+	let alignment = device.physical_device().limits().min_uniform_buffer_offset_alignment() as usize;
+	(MaterialUniform::size() + alignment - 1) / alignment * alignment
+}
+
+fn draw_indexed(
+	cmd: AutoCommandBufferBuilder,
+	pipeline: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	state: &DynamicState,
+	vertex_buffers: Vec<Arc<BufferAccess + Send + Sync>>,
+	indices: &IndexSlice,
+	sets: impl DescriptorSetsCollection,
+	model: vs_gbuffers::ty::MeshModel,
+) -> AutoCommandBufferBuilder {
+	match indices {
+		IndexSlice::U16(indices) => cmd.draw_indexed(pipeline, state, vertex_buffers, indices.clone(), sets, model),
+		IndexSlice::U32(indices) => cmd.draw_indexed(pipeline, state, vertex_buffers, indices.clone(), sets, model),
+	}
+	.unwrap()
+}
+
+fn triangle_count(topology: Topology, indices: &IndexSlice) -> usize {
+	match topology {
+		Topology::TriangleList => indices.len() / 3,
+		Topology::TriangleStrip => indices.len().saturating_sub(2),
+	}
 }
 
 struct MaterialTextureInfo {
@@ -179,10 +909,25 @@ struct MaterialTextureInfo {
 	texture2_name_offset: u32,
 }
 
-#[repr(C)]
+/// The gbuffer shaders' `Material` block. In std140, `base_color` starts at offset 16, not right after the other fields.
+#[derive(Clone, Copy, Default)]
 struct MaterialUniform {
 	light_penetration: u32,
 	subsurface_scattering: u32,
 	emissive_brightness: u32,
 	base_color: [f32; 3],
 }
+impl MaterialUniform {
+	fn size() -> usize {
+		Self::default().to_std140().len()
+	}
+}
+impl Uniform for MaterialUniform {
+	fn write_fields(&self, writer: UniformWriter) -> UniformWriter {
+		writer
+			.field(&self.light_penetration)
+			.field(&self.subsurface_scattering)
+			.field(&self.emissive_brightness)
+			.field(&self.base_color)
+	}
+}
@@ -0,0 +1,326 @@
+use crate::batch::mesh::{
+	MeshRenderPass,
+	mesh::{
+		DynamicGeometry,
+		DynamicMeshError,
+		IndexBuffer,
+		Material,
+		MaterialUniform,
+		Mesh,
+		MorphGeometry,
+		MorphTarget,
+		Topology,
+		VertexBuffers,
+		VertexFormat,
+		material_stride,
+	},
+};
+use crate::spatial::{ Bvh, CollisionData };
+use crate::uniform::Uniform;
+use crate::window::Window;
+use cgmath::{ InnerSpace, Vector3 };
+use std::{ error::Error, fmt, mem, sync::Arc, u32 };
+use vulkano::{
+	buffer::{ BufferUsage, CpuAccessibleBuffer, ImmutableBuffer },
+	memory::DeviceMemoryAllocError,
+	device::Queue,
+	sync::GpuFuture,
+};
+
+/// Builds a `Mesh` from geometry generated in code, rather than loaded from a file.
+#[derive(Clone, Debug, Default)]
+pub struct MeshBuilder {
+	pub(super) positions: Vec<[f32; 3]>,
+	pub(super) normals: Vec<[f32; 3]>,
+	pub(super) texcoords: Vec<[f32; 2]>,
+	pub(super) indices: Vec<u32>,
+	submeshes: Vec<(usize, [f32; 3])>,
+	pub(super) topology: Topology,
+	pub(super) vertex_format: VertexFormat,
+	keep_collision_data: bool,
+	morph_targets: Vec<MorphTarget>,
+}
+impl MeshBuilder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds a vertex and returns its index.
+	pub fn push_vertex(&mut self, position: [f32; 3], normal: [f32; 3], texcoord: [f32; 2]) -> u32 {
+		self.positions.push(position);
+		self.normals.push(normal);
+		self.texcoords.push(texcoord);
+		self.positions.len() as u32 - 1
+	}
+
+	/// Whether no vertices or no indices have been pushed, so there's nothing to draw.
+	pub fn is_empty(&self) -> bool {
+		self.positions.is_empty() || self.indices.is_empty()
+	}
+
+	pub fn push_triangle(&mut self, a: u32, b: u32, c: u32) {
+		self.indices.extend_from_slice(&[a, b, c]);
+	}
+
+	pub fn push_indices(&mut self, indices: &[u32]) {
+		self.indices.extend_from_slice(indices);
+	}
+
+	/// Starts a new strip. Only meaningful with `Topology::TriangleStrip`.
+	pub fn push_restart(&mut self) {
+		self.indices.push(u32::MAX);
+	}
+
+	pub fn set_topology(&mut self, topology: Topology) {
+		self.topology = topology;
+	}
+
+	/// Sets the format `build` stores the vertices in. Dynamic meshes always use `VertexFormat::Full`.
+	pub fn set_vertex_format(&mut self, vertex_format: VertexFormat) {
+		self.vertex_format = vertex_format;
+	}
+
+	/// Ends the current submesh. All indices pushed since the previous submesh are drawn with a material of the given
+	/// linear base color. Any indices left over when the mesh is built are put in a white submesh.
+	pub fn end_submesh(&mut self, base_color: [f32; 3]) {
+		if self.submeshes.last().map(|&(end, _)| end).unwrap_or(0) != self.indices.len() {
+			self.submeshes.push((self.indices.len(), base_color));
+		}
+	}
+
+	/// Replaces all normals with smooth normals, averaged from the faces surrounding each vertex and weighted by area.
+	pub fn compute_normals(&mut self) {
+		let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); self.positions.len()];
+
+		for tri in self.triangles() {
+			let [a, b, c] = [
+				Vector3::from(self.positions[tri[0] as usize]),
+				Vector3::from(self.positions[tri[1] as usize]),
+				Vector3::from(self.positions[tri[2] as usize]),
+			];
+			let face_normal = (b - a).cross(c - a);
+
+			for &i in tri {
+				normals[i as usize] += face_normal;
+			}
+		}
+
+		self.normals = normals.into_iter()
+			.map(|normal| if normal.magnitude2() > 0.0 { normal.normalize().into() } else { [0.0, 1.0, 0.0] })
+			.collect();
+	}
+
+	/// Makes the built mesh keep a copy of its triangles, returned by `Mesh::collision_data`.
+	pub fn set_keep_collision_data(&mut self, keep: bool) {
+		self.keep_collision_data = keep;
+	}
+
+	/// A copy of the triangles so far, as a triangle list with strips unrolled.
+	pub fn collision_data(&self) -> CollisionData {
+		let indices = self.triangles().iter().flat_map(|tri| tri.iter().cloned()).collect();
+		CollisionData { positions: self.positions.clone(), indices: indices }
+	}
+
+	/// Builds a bounding volume hierarchy over the triangles so far, to give the built mesh with `Mesh::set_bvh`. Hits
+	/// on triangle strips count triangles in the unrolled strip.
+	pub fn build_bvh(&self) -> Bvh {
+		self.collision_data().build_bvh()
+	}
+
+	/// Returns the triangles described by the indices, with strips unrolled and restarts removed.
+	pub(super) fn triangles(&self) -> Vec<[u32; 3]> {
+		match self.topology {
+			Topology::TriangleList => self.indices.chunks(3)
+				.filter(|tri| tri.len() == 3)
+				.map(|tri| [tri[0], tri[1], tri[2]])
+				.collect(),
+			Topology::TriangleStrip => self.indices.split(|&i| i == u32::MAX)
+				.flat_map(|strip| strip.windows(3).enumerate())
+				.map(|(i, tri)| if i % 2 == 0 { [tri[0], tri[1], tri[2]] } else { [tri[1], tri[0], tri[2]] })
+				.collect(),
+		}
+	}
+
+	/// Adds a shape the mesh can blend toward with `Mesh::set_morph_weight`, once every vertex has been pushed. Meshes
+	/// with morph targets are always stored in `VertexFormat::Full`, and are blended on the GPU before they're drawn in
+	/// any frame where their weights changed.
+	pub fn add_morph_target(&mut self, target: MorphTarget) {
+		assert_eq!(target.position_deltas.len(), self.positions.len(), "morph target needs an offset for every vertex");
+		assert!(
+			target.normal_deltas.is_empty() || target.normal_deltas.len() == self.positions.len(),
+			"morph target's normal offsets don't match the vertices"
+		);
+		self.morph_targets.push(target);
+	}
+
+	/// Fails with `MeshBuildError::Empty` if the builder `is_empty`.
+	pub fn build(
+		mut self,
+		window: &Window,
+		render_pass: Arc<MeshRenderPass>,
+	) -> Result<(Mesh, impl GpuFuture + Send + Sync + 'static), MeshBuildError> {
+		if self.is_empty() {
+			return Err(MeshBuildError::Empty);
+		}
+		let queue = window.device().queue().clone();
+
+		if self.submeshes.last().map(|&(end, _)| end).unwrap_or(0) != self.indices.len() {
+			self.submeshes.push((self.indices.len(), [1.0, 1.0, 1.0]));
+		}
+
+		let collision = if self.keep_collision_data { Some(Arc::new(self.collision_data())) } else { None };
+		let vertex_count = self.positions.len();
+		let (vertices, morph, vertices_future): (_, _, Box<GpuFuture + Send + Sync>) =
+			if self.morph_targets.is_empty() {
+				let (vertices, future) =
+					VertexBuffers::immutable(
+						queue.clone(),
+						self.vertex_format,
+						self.positions,
+						self.normals,
+						self.texcoords,
+					)?;
+				(vertices, None, Box::new(future))
+			} else {
+				let targets = mem::replace(&mut self.morph_targets, vec![]);
+				let (morph, morph_future) =
+					MorphGeometry::new(&render_pass, &queue, &self.positions, &self.normals, targets)?;
+				let (texcoords, texcoords_future) =
+					ImmutableBuffer::from_iter(self.texcoords.into_iter(), BufferUsage::vertex_buffer(), queue.clone())?;
+				let vertices = VertexBuffers::full(morph.positions().clone(), morph.normals().clone(), texcoords);
+				(vertices, Some(morph), Box::new(morph_future.join(texcoords_future)))
+			};
+		let (indices, indices_future) = IndexBuffer::immutable(queue.clone(), self.indices, vertex_count)?;
+
+		let (materials, materials_future) = Self::make_materials(&render_pass, &queue, &indices, &self.submeshes)?;
+
+		let mut mesh = Mesh::from_parts(
+			render_pass,
+			"procedural mesh".to_owned(),
+			vertices,
+			materials,
+			self.topology,
+			None,
+			Arc::default()
+		);
+		mesh.set_collision_data(collision);
+		mesh.morph = morph;
+
+		Ok((
+			mesh,
+			vertices_future
+				.join(indices_future)
+				.join(materials_future)
+		))
+	}
+
+	/// Builds a mesh whose geometry can be replaced later with `Mesh::update_vertices` and `Mesh::update_indices`. The
+	/// capacities are the most vertices and indices the mesh can ever hold, and neither can be zero. The builder can be
+	/// empty, to fill the mesh in later. Dynamic meshes have a single material, which uses the base color of the first
+	/// submesh.
+	pub fn build_dynamic(
+		self,
+		window: &Window,
+		render_pass: Arc<MeshRenderPass>,
+		vertex_capacity: usize,
+		index_capacity: usize,
+	) -> Result<(Mesh, impl GpuFuture + Send + Sync + 'static), DynamicMeshError> {
+		if vertex_capacity == 0 || index_capacity == 0 {
+			return Err(DynamicMeshError::ZeroCapacity);
+		}
+		let queue = window.device().queue().clone();
+
+		let mut dynamic = DynamicGeometry::new(&queue, vertex_capacity, index_capacity)?;
+		let (positions, normals, texcoords_main) = dynamic.vertex_buffers();
+		let vertices = VertexBuffers::full(positions, normals, texcoords_main);
+
+		let base_color = self.submeshes.first().map(|&(_, base_color)| base_color).unwrap_or([1.0, 1.0, 1.0]);
+		let (mut materials, materials_future) =
+			Self::make_materials(&render_pass, &queue, &dynamic.index_buffer(), &[(0, base_color)])?;
+
+		dynamic.set_vertices(self.positions, self.normals, self.texcoords)?;
+		materials[0].indices = dynamic.set_indices(self.indices)?;
+
+		Ok((
+			Mesh::from_parts(
+				render_pass,
+				"dynamic mesh".to_owned(),
+				vertices,
+				materials,
+				self.topology,
+				Some(dynamic),
+				Arc::default()
+			),
+			materials_future
+		))
+	}
+
+	pub(super) fn make_materials(
+		render_pass: &MeshRenderPass,
+		queue: &Arc<Queue>,
+		indices: &IndexBuffer,
+		submeshes: &[(usize, [f32; 3])],
+	) -> Result<(Vec<Material>, impl GpuFuture + Send + Sync + 'static), DeviceMemoryAllocError> {
+		let material_stride = material_stride(queue.device());
+		let material_buf =
+			unsafe {
+				CpuAccessibleBuffer::uninitialized_array(
+					queue.device().clone(),
+					submeshes.len() * material_stride,
+					BufferUsage::transfer_source()
+				)?
+			};
+		{
+			let mut material_buf_lock = material_buf.write().unwrap();
+			for (i, &(_, base_color)) in submeshes.iter().enumerate() {
+				let uniform =
+					MaterialUniform {
+						light_penetration: 0,
+						subsurface_scattering: 0,
+						emissive_brightness: 0,
+						base_color: base_color,
+					}.to_std140();
+				material_buf_lock[i * material_stride..i * material_stride + uniform.len()].copy_from_slice(&uniform);
+			}
+		}
+		let (material_buf, material_buf_future) =
+			ImmutableBuffer::from_buffer(material_buf, BufferUsage::uniform_buffer(), queue.clone())?;
+
+		let mut materials = Vec::with_capacity(submeshes.len());
+		let mut index_start = 0;
+		for (i, &(index_end, _)) in submeshes.iter().enumerate() {
+			materials.push(Material::new(render_pass, indices, index_start..index_end, &material_buf, i, 0.0));
+			index_start = index_end;
+		}
+
+		Ok((materials, material_buf_future))
+	}
+}
+
+#[derive(Debug)]
+pub enum MeshBuildError {
+	/// The builder has no vertices or no indices. Vulkan buffers can't be empty, so there's no mesh to build.
+	Empty,
+	DeviceMemoryAllocError(DeviceMemoryAllocError),
+}
+impl fmt::Display for MeshBuildError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			MeshBuildError::Empty => write!(f, "the mesh has no vertices or no indices"),
+			MeshBuildError::DeviceMemoryAllocError(err) => write!(f, "device memory allocation failed: {}", err),
+		}
+	}
+}
+impl Error for MeshBuildError {
+	fn source(&self) -> Option<&(Error + 'static)> {
+		match self {
+			MeshBuildError::Empty => None,
+			MeshBuildError::DeviceMemoryAllocError(err) => Some(err),
+		}
+	}
+}
+impl From<DeviceMemoryAllocError> for MeshBuildError {
+	fn from(val: DeviceMemoryAllocError) -> Self {
+		MeshBuildError::DeviceMemoryAllocError(val)
+	}
+}
@@ -1,28 +1,86 @@
-use crate::batch::mesh::{ MeshRenderPass, mesh::{ Material, MaterialTextureInfo, MaterialUniform, Mesh, MeshFromFileError } };
+use crate::batch::mesh::{
+	MeshRenderPass,
+	mesh::{
+		IndexBuffer,
+		Material,
+		MaterialBinding,
+		MaterialTextureInfo,
+		MaterialUniform,
+		Mesh,
+		MeshFromFileError,
+		Topology,
+		VertexBuffers,
+		VertexFormat,
+		material_stride,
+	},
+};
 use crate::cpu_pool::{ execute_future, GpuFutureFuture };
-use crate::texture::{ ImageFormat, ImmutableTexture, Texture };
-use atom::Atom;
+use crate::images::{ self, ImageFormat };
+use crate::progress::{ LoadProgress, LoadStage };
+use crate::texture::{ ColorEncoding, ImmutableTexture, Texture };
+use crate::uniform::Uniform;
+use crate::vfs;
 use byteorder::{LE, ReadBytesExt};
-use cgmath::{ Quaternion, Vector3 };
 use futures::{ FutureExt, future::ready, prelude::* };
 use log::{ debug, log };
-use std::{ fs::File, io::{ self, prelude::*, SeekFrom }, mem::{ size_of, transmute }, path::{ Path }, sync::Arc };
+use std::{
+	io::{ self, prelude::*, Cursor, SeekFrom },
+	path::{ Path, PathBuf },
+	sync::{ Arc, Mutex },
+};
 use vulkano::{
-	buffer::{ BufferAccess, BufferUsage, CpuAccessibleBuffer, CpuBufferPool, ImmutableBuffer },
-	descriptor::descriptor_set::PersistentDescriptorSet,
-	device::{ Device, Queue },
+	buffer::{ BufferUsage, CpuAccessibleBuffer, ImmutableBuffer },
+	device::Queue,
+	image::ImageViewAccess,
 	sync::GpuFuture,
 };
 
+/// A model file's contents, read but not uploaded.
+pub(crate) struct ModelData {
+	pub(super) name: String,
+	pub(super) positions: Vec<[f32; 3]>,
+	pub(super) normals: Vec<[f32; 3]>,
+	pub(super) texcoords_main: Vec<[f32; 2]>,
+	pub(super) indices: Vec<u32>,
+	pub(super) materials: Vec<ModelMaterial>,
+}
+impl ModelData {
+	pub(crate) fn vertex_count(&self) -> usize {
+		self.positions.len()
+	}
+}
+
+/// A material of a model file, which is drawn for the next `index_count` indices after the previous material's.
+pub(super) struct ModelMaterial {
+	pub(super) index_count: usize,
+	pub(super) uniform: MaterialUniform,
+	/// The albedo and normal textures.
+	pub(super) textures: [Option<PathBuf>; 2],
+}
+impl ModelMaterial {
+	/// The file stores emissive brightness in hundredths.
+	pub(super) fn emissive(&self) -> f32 {
+		self.uniform.emissive_brightness as f32 / 100.0
+	}
+}
+
 pub fn from_nice_model(
-	device: Arc<Device>,
 	queue: Arc<Queue>,
 	render_pass: Arc<MeshRenderPass>,
 	path: impl AsRef<Path> + Clone + Send + 'static,
-	position: Vector3<f32>,
-	rotation: Quaternion<f32>,
+	vertex_format: VertexFormat,
+	progress: LoadProgress,
 ) -> Result<(Mesh, impl GpuFuture + Send + Sync + 'static), MeshFromFileError> {
-	let mut file = File::open(path.clone())?;
+	let data = read_nice_model(path, &progress)?;
+	upload_model(queue, render_pass, data, vertex_format, &progress)
+}
+
+/// Reads and parses a model file, without uploading anything.
+pub(crate) fn read_nice_model(path: impl AsRef<Path>, progress: &LoadProgress) -> Result<ModelData, MeshFromFileError> {
+	let bytes = vfs::read_with_progress(path.as_ref(), progress);
+	progress.check_cancelled()?;
+	let mut file = Cursor::new(bytes?);
+	progress.set_stage(LoadStage::Parsing);
 
 	let mut magic_number = [0; 4];
 	file.read_exact(&mut magic_number)?;
@@ -52,243 +110,209 @@ pub fn from_nice_model(
 	debug!("materials_offset: {}", materials_offset);
 
 	file.seek(SeekFrom::Start(positions_offset))?;
-	let (positions, positions_future) =
-		buffer_from_file(
-			queue.clone(),
-			BufferUsage::vertex_buffer(),
-			vertex_count,
-			&mut || Ok([file.read_f32::<LE>()?, file.read_f32::<LE>()?, file.read_f32::<LE>()?])
-		)?;
+	let positions =
+		read_vec(vertex_count, &mut || Ok([file.read_f32::<LE>()?, file.read_f32::<LE>()?, file.read_f32::<LE>()?]))?;
 
 	file.seek(SeekFrom::Start(normals_offset))?;
-	let (normals, normals_future) =
-		buffer_from_file(
-			queue.clone(),
-			BufferUsage::vertex_buffer(),
-			vertex_count,
-			&mut || Ok([file.read_f32::<LE>()?, file.read_f32::<LE>()?, file.read_f32::<LE>()?])
-		)?;
+	let normals =
+		read_vec(vertex_count, &mut || Ok([file.read_f32::<LE>()?, file.read_f32::<LE>()?, file.read_f32::<LE>()?]))?;
 
 	file.seek(SeekFrom::Start(texcoords_main_offset))?;
-	let (texcoords_main, texcoords_main_future) =
-		buffer_from_file(
-			queue.clone(),
-			BufferUsage::vertex_buffer(),
-			vertex_count,
-			&mut || Ok([file.read_f32::<LE>()?, file.read_f32::<LE>()?])
-		)?;
+	let texcoords_main = read_vec(vertex_count, &mut || Ok([file.read_f32::<LE>()?, file.read_f32::<LE>()?]))?;
 
 	file.seek(SeekFrom::Start(indices_offset))?;
-	let (indices, indices_future) =
-		buffer_from_file(
-			queue.clone(),
-			BufferUsage::index_buffer(),
-			index_count,
-			&mut || file.read_u32::<LE>()
-		)?;
+	let indices = read_vec(index_count, &mut || file.read_u32::<LE>())?;
 
 	file.seek(SeekFrom::Start(materials_offset))?;
+	let mut materials = Vec::with_capacity(material_count);
+	let mut texture_infos = Vec::with_capacity(material_count);
+	for _ in 0..material_count {
+		let index_count = file.read_u32::<LE>()? as usize;
+		texture_infos
+			.push(MaterialTextureInfo {
+				texture1_name_size: file.read_u16::<LE>()?,
+				texture1_name_offset: file.read_u32::<LE>()?,
+				texture2_name_size: file.read_u16::<LE>()?,
+				texture2_name_offset: file.read_u32::<LE>()?,
+			});
 
-	// round MaterialUniform size up to minimum alignment
-	let mut material_stride =
-		queue.device().physical_device().limits().min_uniform_buffer_offset_alignment() as usize;
-	material_stride = (size_of::<MaterialUniform>() + material_stride - 1) / material_stride * material_stride;
+		let uniform =
+			MaterialUniform {
+				light_penetration: file.read_u8()? as u32,
+				subsurface_scattering: file.read_u8()? as u32,
+				emissive_brightness: file.read_u16::<LE>()? as u32,
+				base_color: {
+					let mut buf = [0; 3];
+					file.read_exact(&mut buf)?;
+					[
+						(buf[0] as f32 / 255.0).powf(2.2),
+						(buf[1] as f32 / 255.0).powf(2.2),
+						(buf[2] as f32 / 255.0).powf(2.2)
+					]
+				},
+			};
+		materials.push(ModelMaterial { index_count: index_count, uniform: uniform, textures: [None, None] });
+	}
+
+	// texture names are relative to the model file
+	let dir = path.as_ref().parent().unwrap();
+	for (material, info) in materials.iter_mut().zip(texture_infos) {
+		let names = [
+			(info.texture1_name_size, info.texture1_name_offset),
+			(info.texture2_name_size, info.texture2_name_offset),
+		];
+		for (texture, &(size, offset)) in material.textures.iter_mut().zip(&names) {
+			if size != 0 {
+				file.seek(SeekFrom::Start(offset as u64))?;
+				let mut buf = vec![0; size as usize];
+				file.read_exact(&mut buf)?;
+				*texture = Some(dir.join(String::from_utf8(buf).unwrap()));
+			}
+		}
+	}
+
+	Ok(ModelData {
+		name: path.as_ref().display().to_string(),
+		positions: positions,
+		normals: normals,
+		texcoords_main: texcoords_main,
+		indices: indices,
+		materials: materials,
+	})
+}
+
+/// Uploads a model file's contents as a mesh. Its textures are loaded in the background.
+pub(crate) fn upload_model(
+	queue: Arc<Queue>,
+	render_pass: Arc<MeshRenderPass>,
+	data: ModelData,
+	vertex_format: VertexFormat,
+	progress: &LoadProgress,
+) -> Result<(Mesh, impl GpuFuture + Send + Sync + 'static), MeshFromFileError> {
+	progress.check_cancelled()?;
+	progress.set_stage(LoadStage::Uploading);
+	let vertex_count = data.positions.len();
+	let (vertices, vertices_future) =
+		VertexBuffers::immutable(queue.clone(), vertex_format, data.positions, data.normals, data.texcoords_main)?;
+	let (indices, indices_future) = IndexBuffer::immutable(queue.clone(), data.indices, vertex_count)?;
+
+	let material_stride = material_stride(queue.device());
 	debug!("material stride: {}", material_stride);
 
 	let material_buf =
 		unsafe {
 			CpuAccessibleBuffer::uninitialized_array(
 				queue.device().clone(),
-				material_count * material_stride,
+				data.materials.len() * material_stride,
 				BufferUsage::transfer_source()
 			)?
 		};
-	let mut index_counts = Vec::with_capacity(material_count);
-	let mut mat_temp_datas = Vec::with_capacity(material_count);
 	{
 		let mut material_buf_lock = material_buf.write().unwrap();
-		for i in 0..material_count {
-			index_counts.push(file.read_u32::<LE>()?);
-			mat_temp_datas
-				.push(MaterialTextureInfo {
-					texture1_name_size: file.read_u16::<LE>()?,
-					texture1_name_offset: file.read_u32::<LE>()?,
-					texture2_name_size: file.read_u16::<LE>()?,
-					texture2_name_offset: file.read_u32::<LE>()?,
-				});
-
-			material_buf_lock[i * material_stride..i * material_stride + size_of::<MaterialUniform>()]
-				.copy_from_slice(
-					&unsafe {
-						transmute::<_, [u8; size_of::<MaterialUniform>()]>(
-							MaterialUniform {
-								light_penetration: file.read_u8()? as u32,
-								subsurface_scattering: file.read_u8()? as u32,
-								emissive_brightness: file.read_u16::<LE>()? as u32,
-								base_color: {
-									let mut buf = [0; 3];
-									file.read_exact(&mut buf)?;
-									[
-										(buf[0] as f32 / 255.0).powf(2.2),
-										(buf[1] as f32 / 255.0).powf(2.2),
-										(buf[2] as f32 / 255.0).powf(2.2)
-									]
-								},
-							}
-						)
-					}
-				);
+		for (i, material) in data.materials.iter().enumerate() {
+			let uniform = material.uniform;
+			let uniform = uniform.to_std140();
+			material_buf_lock[i * material_stride..i * material_stride + uniform.len()].copy_from_slice(&uniform);
 		}
 	}
 
+	// dropping the buffers here releases them once their uploads finish
+	progress.check_cancelled()?;
 	let (material_buf, material_buf_future) =
 		ImmutableBuffer::from_buffer(material_buf, BufferUsage::uniform_buffer(), queue.clone())?;
 
-	let mut materials = Vec::with_capacity(material_count);
+	let mut materials = Vec::with_capacity(data.materials.len());
 	let mut index_start = 0;
-	for (i, index_count) in index_counts.into_iter().enumerate() {
-		let index_count = index_count as usize;
-		let material_offset = material_stride * i;
-		materials
-			.push(Material {
-				indices: indices.clone().into_buffer_slice().slice(index_start..index_start + index_count).unwrap(),
-				desc:
-					Arc::new(Atom::new(Box::new(Arc::new(
-						PersistentDescriptorSet::start(render_pass.pipeline_gbuffers.clone(), 2)
-							.add_buffer(
-								material_buf.clone()
-									.into_buffer_slice()
-									.slice(material_offset..material_offset + size_of::<MaterialUniform>())
-									.unwrap()
-							)
-							.unwrap()
-							.add_sampled_image(render_pass.shaders.texture1_default.clone(), render_pass.shaders.sampler.clone())
-							.unwrap()
-							.add_sampled_image(render_pass.shaders.texture2_default.clone(), render_pass.shaders.sampler.clone())
-							.unwrap()
-							.build()
-							.unwrap()
-					))))
-			});
-
-		index_start += index_count;
+	for (i, material) in data.materials.iter().enumerate() {
+		let range = index_start..index_start + material.index_count;
+		materials.push(Material::new(&render_pass, &indices, range, &material_buf, i, material.emissive()));
+		index_start += material.index_count;
 	}
 
-	for (i, data) in mat_temp_datas.into_iter().enumerate() {
-		let texture1_default = render_pass.shaders.texture1_default.clone();
-		let future1: Box<Future<Output = _> + Send + Unpin> =
-			if data.texture1_name_size != 0 {
-				file.seek(SeekFrom::Start(data.texture1_name_offset as u64))?;
-				let mut buf = vec![0; data.texture1_name_size as usize];
-				file.read_exact(&mut buf)?;
-				let path = path.as_ref().parent().unwrap().join(String::from_utf8(buf).unwrap());
-
-				Box::new(
-					ImmutableTexture
-						::from_file_with_format_impl(queue.clone(), path.clone(), ImageFormat::PNG, true)
-						.map(|result| result
-							.map(|(tex, future)| {
-								GpuFutureFuture::new(future).map(|_| tex.image().clone()).unwrap()
-							})
-							.unwrap_or_else(move |_| texture1_default)
-						)
-				)
-			} else {
-				Box::new(ready(texture1_default))
-			};
-
-		let texture2_default = render_pass.shaders.texture2_default.clone();
-		let future2: Box<Future<Output = _> + Send + Unpin> =
-			if data.texture2_name_size != 0 {
-				file.seek(SeekFrom::Start(data.texture2_name_offset as u64))?;
-				let mut buf = vec![0; data.texture2_name_size as usize];
-				file.read_exact(&mut buf)?;
-				let path = path.as_ref().parent().unwrap().join(String::from_utf8(buf).unwrap());
-
-				Box::new(
-					ImmutableTexture
-						::from_file_with_format_impl(queue.clone(), path.clone(), ImageFormat::PNG, false)
-						.map(|result| result
-							.map(|(tex, future)| {
-								GpuFutureFuture::new(future).map(|_| tex.image().clone()).unwrap()
-							})
-							.unwrap_or_else(move |_| texture2_default)
-						)
-				)
-			} else {
-				Box::new(ready(texture2_default))
-			};
-
-		let desc = materials[i].desc.clone();
-		let material_buf = material_buf.clone();
-		let material_offset = material_stride * i;
-		let pipeline_gbuffers = render_pass.pipeline_gbuffers.clone();
-		let sampler = render_pass.shaders.sampler.clone();
-
-		execute_future(async move {
-			let tex1 = await!(future1);
-			let tex2 = await!(future2);
-
-			desc.swap(Box::new(Arc::new(
-				PersistentDescriptorSet::start(pipeline_gbuffers.clone(), 2)
-					.add_buffer(
-						material_buf.clone()
-							.into_buffer_slice()
-							.slice(material_offset..material_offset + size_of::<MaterialUniform>())
-							.unwrap()
-					)
-					.unwrap()
-					.add_sampled_image(tex1, sampler.clone())
-					.unwrap()
-					.add_sampled_image(tex2, sampler.clone())
-					.unwrap()
-					.build()
-					.unwrap()
-			)));
-		});
+	let textures = Arc::new(Mutex::new(vec![]));
+	for (material, model_material) in materials.iter().zip(&data.materials) {
+		load_textures(&render_pass, material.binding.clone(), &model_material.textures, &textures, progress);
 	}
 
-	let position_pool = CpuBufferPool::uniform_buffer(device.clone());
-	let rotation_pool = CpuBufferPool::uniform_buffer(device);
-	let position = position_pool.next(position)?;
-	let rotation = rotation_pool.next(rotation)?;
-
+	progress.set_stage(LoadStage::Done);
 	Ok((
-		Mesh {
-			position_pool: position_pool,
-			rotation_pool: rotation_pool,
-			position: position,
-			rotation: rotation,
-			positions: positions,
-			normals: normals,
-			texcoords_main: texcoords_main,
-			materials: materials,
-		},
-		positions_future
-			.join(normals_future)
-			.join(texcoords_main_future)
+		Mesh::from_parts(render_pass, data.name, vertices, materials, Topology::TriangleList, None, textures),
+		vertices_future
 			.join(indices_future)
 			.join(material_buf_future)
 	))
 }
 
-fn buffer_from_file<T>(
-	queue: Arc<Queue>,
-	usage: BufferUsage,
-	count: usize,
-	read: &mut FnMut() -> io::Result<T>
-) -> Result<(Arc<ImmutableBuffer<[T]>>, impl GpuFuture), MeshFromFileError>
-where T: Send + Sync + 'static
-{
-	let tmpbuf =
-		unsafe {
-			CpuAccessibleBuffer::uninitialized_array(queue.device().clone(), count, BufferUsage::transfer_source())?
+/// Loads a material's albedo and normal textures in the background, and binds them once both have finished. Textures
+/// that fail to load are replaced by the defaults. Loaded textures are kept in `textures`, if it's still alive.
+pub(super) fn load_textures(
+	render_pass: &Arc<MeshRenderPass>,
+	binding: Arc<MaterialBinding>,
+	paths: &[Option<PathBuf>; 2],
+	textures: &Arc<Mutex<Vec<ImmutableTexture>>>,
+	progress: &LoadProgress,
+) {
+	let default1 = render_pass.shaders.texture1_default.clone();
+	let future1 = load_texture(render_pass, paths[0].clone(), default1, ColorEncoding::Srgb, textures, progress);
+	let default2 = render_pass.shaders.texture2_default.clone();
+	let future2 = load_texture(render_pass, paths[1].clone(), default2, ColorEncoding::Linear, textures, progress);
+	let render_pass = render_pass.clone();
+
+	execute_future(async move {
+		let tex1 = await!(future1);
+		let tex2 = await!(future2);
+
+		binding.update(&render_pass, |textures| {
+			if !textures.custom_textures {
+				textures.albedo = tex1;
+				textures.normal = tex2;
+			}
+		});
+	});
+}
+
+fn load_texture(
+	render_pass: &MeshRenderPass,
+	path: Option<PathBuf>,
+	default: Arc<ImageViewAccess + Send + Sync + 'static>,
+	encoding: ColorEncoding,
+	textures: &Arc<Mutex<Vec<ImmutableTexture>>>,
+	progress: &LoadProgress,
+) -> Box<Future<Output = Arc<ImageViewAccess + Send + Sync + 'static>> + Send + Unpin> {
+	let path =
+		match path {
+			Some(path) => path,
+			None => return Box::new(ready(default)),
 		};
-	{
-		let mut tmpbuf_lock = tmpbuf.write().unwrap();
-		for i in 0..count {
-			tmpbuf_lock[i] = read()?;
-		}
+	let format = images::format_from_path(&path).unwrap_or(ImageFormat::PNG);
+
+	let textures = Arc::downgrade(textures);
+	Box::new(
+		ImmutableTexture
+			::from_file_with_format_impl(
+				render_pass.shaders.device_ctx.clone(),
+				path,
+				format,
+				encoding,
+				LoadProgress::with_cancel_token(progress.cancel_token().clone()),
+			)
+			.map(move |result| result
+				.map(|(tex, future)| {
+					let image = GpuFutureFuture::new(future).map(|_| tex.image().clone()).unwrap();
+					if let Some(textures) = textures.upgrade() {
+						textures.lock().unwrap().push(tex);
+					}
+					image
+				})
+				.unwrap_or_else(move |_| default)
+			)
+	)
+}
+
+fn read_vec<T>(count: usize, read: &mut FnMut() -> io::Result<T>) -> io::Result<Vec<T>> {
+	let mut vec = Vec::with_capacity(count);
+	for _ in 0..count {
+		vec.push(read()?);
 	}
-	ImmutableBuffer::from_buffer(tmpbuf, usage, queue).map_err(|e| e.into())
+	Ok(vec)
 }
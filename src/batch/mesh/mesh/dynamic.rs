@@ -0,0 +1,185 @@
+use crate::batch::mesh::mesh::{ IndexBuffer, IndexSlice, indices::{ fits_u16, narrow } };
+use std::{ iter, sync::Arc, u32 };
+use vulkano::{
+	buffer::{ BufferAccess, BufferUsage, CpuBufferPool, DeviceLocalBuffer },
+	command_buffer::AutoCommandBufferBuilder,
+	device::Queue,
+	memory::DeviceMemoryAllocError,
+};
+
+/// Device local geometry of a dynamic mesh, along with the staging pools used to update it.
+pub(super) struct DynamicGeometry {
+	vertex_capacity: usize,
+	index_capacity: usize,
+	positions: Arc<DeviceLocalBuffer<[[f32; 3]]>>,
+	normals: Arc<DeviceLocalBuffer<[[f32; 3]]>>,
+	texcoords_main: Arc<DeviceLocalBuffer<[[f32; 2]]>>,
+	indices: DynamicIndices,
+	vec3_staging: CpuBufferPool<[f32; 3]>,
+	vec2_staging: CpuBufferPool<[f32; 2]>,
+	pending_vertices: Option<(Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<[f32; 2]>)>,
+	pending_indices: Option<Vec<u32>>,
+}
+impl DynamicGeometry {
+	pub(super) fn new(
+		queue: &Queue,
+		vertex_capacity: usize,
+		index_capacity: usize,
+	) -> Result<Self, DeviceMemoryAllocError> {
+		let device = queue.device();
+		let vertex_usage = BufferUsage { transfer_destination: true, vertex_buffer: true, .. BufferUsage::none() };
+		let index_usage = BufferUsage { transfer_destination: true, index_buffer: true, .. BufferUsage::none() };
+
+		Ok(Self {
+			vertex_capacity: vertex_capacity,
+			index_capacity: index_capacity,
+			positions:
+				DeviceLocalBuffer::array(device.clone(), vertex_capacity, vertex_usage, iter::once(queue.family()))?,
+			normals:
+				DeviceLocalBuffer::array(device.clone(), vertex_capacity, vertex_usage, iter::once(queue.family()))?,
+			texcoords_main:
+				DeviceLocalBuffer::array(device.clone(), vertex_capacity, vertex_usage, iter::once(queue.family()))?,
+			indices:
+				if fits_u16(vertex_capacity) {
+					DynamicIndices::U16(
+						DeviceLocalBuffer::array(device.clone(), index_capacity, index_usage, iter::once(queue.family()))?,
+						CpuBufferPool::new(device.clone(), BufferUsage::transfer_source()),
+					)
+				} else {
+					DynamicIndices::U32(
+						DeviceLocalBuffer::array(device.clone(), index_capacity, index_usage, iter::once(queue.family()))?,
+						CpuBufferPool::new(device.clone(), BufferUsage::transfer_source()),
+					)
+				},
+			vec3_staging: CpuBufferPool::new(device.clone(), BufferUsage::transfer_source()),
+			vec2_staging: CpuBufferPool::new(device.clone(), BufferUsage::transfer_source()),
+			pending_vertices: None,
+			pending_indices: None,
+		})
+	}
+
+	pub(super) fn vertex_buffers(
+		&self
+	) -> (Arc<BufferAccess + Send + Sync>, Arc<BufferAccess + Send + Sync>, Arc<BufferAccess + Send + Sync>) {
+		(self.positions.clone(), self.normals.clone(), self.texcoords_main.clone())
+	}
+
+	pub(super) fn index_buffer(&self) -> IndexBuffer {
+		match &self.indices {
+			DynamicIndices::U16(buffer, _) => IndexBuffer::U16(buffer.clone()),
+			DynamicIndices::U32(buffer, _) => IndexBuffer::U32(buffer.clone()),
+		}
+	}
+
+	pub(super) fn set_vertices(
+		&mut self,
+		positions: Vec<[f32; 3]>,
+		normals: Vec<[f32; 3]>,
+		texcoords_main: Vec<[f32; 2]>,
+	) -> Result<(), DynamicMeshError> {
+		if positions.len() != normals.len() || positions.len() != texcoords_main.len() {
+			return Err(DynamicMeshError::MismatchedLengths);
+		}
+		if positions.len() > self.vertex_capacity {
+			return Err(DynamicMeshError::CapacityExceeded);
+		}
+
+		self.pending_vertices = Some((positions, normals, texcoords_main));
+		Ok(())
+	}
+
+	/// Queues new indices for upload, and returns the slice of the index buffer that they will occupy.
+	pub(super) fn set_indices(
+		&mut self,
+		indices: Vec<u32>,
+	) -> Result<IndexSlice, DynamicMeshError> {
+		if indices.len() > self.index_capacity {
+			return Err(DynamicMeshError::CapacityExceeded);
+		}
+		let out_of_range = indices.iter().find(|&&index| index as usize >= self.vertex_capacity && index != u32::MAX);
+		if let Some(&index) = out_of_range {
+			return Err(DynamicMeshError::IndexOutOfRange(index));
+		}
+
+		let slice = self.index_buffer().slice(0..indices.len());
+		self.pending_indices = Some(indices);
+		Ok(slice)
+	}
+
+	/// Copies any pending updates into the device local buffers. This must be recorded outside of a render pass, before
+	/// the mesh is drawn.
+	pub(super) fn record_uploads(
+		&mut self,
+		mut cmd: AutoCommandBufferBuilder,
+	) -> Result<AutoCommandBufferBuilder, DeviceMemoryAllocError> {
+		if let Some((positions, normals, texcoords_main)) = self.pending_vertices.take() {
+			let len = positions.len();
+			if len != 0 {
+				cmd = cmd
+					.copy_buffer(
+						self.vec3_staging.chunk(positions)?,
+						self.positions.clone().into_buffer_slice().slice(0..len).unwrap()
+					)
+					.unwrap()
+					.copy_buffer(
+						self.vec3_staging.chunk(normals)?,
+						self.normals.clone().into_buffer_slice().slice(0..len).unwrap()
+					)
+					.unwrap()
+					.copy_buffer(
+						self.vec2_staging.chunk(texcoords_main)?,
+						self.texcoords_main.clone().into_buffer_slice().slice(0..len).unwrap()
+					)
+					.unwrap();
+			}
+		}
+
+		if let Some(indices) = self.pending_indices.take() {
+			let len = indices.len();
+			if len != 0 {
+				cmd =
+					match &self.indices {
+						DynamicIndices::U16(buffer, staging) =>
+							cmd.copy_buffer(
+								staging.chunk(indices.into_iter().map(narrow))?,
+								buffer.clone().into_buffer_slice().slice(0..len).unwrap()
+							),
+						DynamicIndices::U32(buffer, staging) =>
+							cmd.copy_buffer(
+								staging.chunk(indices)?,
+								buffer.clone().into_buffer_slice().slice(0..len).unwrap()
+							),
+					}
+					.unwrap();
+			}
+		}
+
+		Ok(cmd)
+	}
+}
+
+/// The index buffer of a dynamic mesh and its staging pool, using 16-bit indices when the vertex capacity allows it.
+enum DynamicIndices {
+	U16(Arc<DeviceLocalBuffer<[u16]>>, CpuBufferPool<u16>),
+	U32(Arc<DeviceLocalBuffer<[u32]>>, CpuBufferPool<u32>),
+}
+
+#[derive(Debug)]
+pub enum DynamicMeshError {
+	/// The mesh was not built with `MeshBuilder::build_dynamic`.
+	NotDynamic,
+	/// More vertices or indices were given than the mesh was built to hold.
+	CapacityExceeded,
+	/// The positions, normals, and texture coordinates don't all have the same length.
+	MismatchedLengths,
+	/// An index isn't below the mesh's vertex capacity, and isn't the primitive restart value `u32::MAX`.
+	IndexOutOfRange(u32),
+	/// The mesh was built with room for no vertices or no indices. Vulkan buffers can't be empty.
+	ZeroCapacity,
+	DeviceMemoryAllocError(DeviceMemoryAllocError),
+}
+impl From<DeviceMemoryAllocError> for DynamicMeshError {
+	fn from(val: DeviceMemoryAllocError) -> Self {
+		DynamicMeshError::DeviceMemoryAllocError(val)
+	}
+}
@@ -0,0 +1,559 @@
+use crate::batch::mesh::{
+	MeshRenderPass,
+	mesh::{ IndexBuffer, Material, MeshBuilder, Topology, VertexBuffers },
+	render_pass::GBufferStage,
+	shaders::vs_foliage,
+};
+use crate::camera::Camera;
+use crate::device::{ MemoryCategory, MemoryHandle };
+use crate::images;
+use crate::texture::{ SamplerSettings, Texture };
+use crate::vfs;
+use crate::window::Window;
+use cgmath::{ vec2, vec3, InnerSpace, One, Quaternion, Rad, Rotation3, Vector2, Vector3, Vector4 };
+use image::ImageError;
+use std::{ collections::HashMap, f32::consts::PI, path::Path, sync::Arc };
+use vulkano::{
+	buffer::{ BufferAccess, BufferUsage, ImmutableBuffer },
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::DescriptorSet,
+	memory::DeviceMemoryAllocError,
+	sampler::SamplerCreationError,
+	sync::GpuFuture,
+};
+
+/// How many world units each side of a foliage cell covers. Cells are culled as a whole against each camera.
+const CELL_SIZE: f32 = 16.0;
+
+/// Places copies of one small mesh, like a tuft of grass or a fern, over the ground. The copies are drawn with
+/// instancing, so a field of thousands costs a few draws.
+#[derive(Clone, Debug)]
+pub struct FoliageBuilder {
+	instances: Vec<FoliageInstance>,
+	base_color: [f32; 3],
+	random: u64,
+}
+impl FoliageBuilder {
+	pub fn new() -> Self {
+		Self { instances: vec![], base_color: [1.0, 1.0, 1.0], random: 0x9E37_79B9_7F4A_7C15 }
+	}
+
+	/// Sets where the random placement starts, so the same seed scatters the same way every time.
+	pub fn set_seed(&mut self, seed: u64) {
+		self.random = seed;
+	}
+
+	/// Sets the linear base color of the foliage's material.
+	pub fn set_base_color(&mut self, base_color: [f32; 3]) {
+		self.base_color = base_color;
+	}
+
+	/// Places one instance. Its mesh's origin should be where it meets the ground, with Y up.
+	pub fn push_instance(&mut self, position: Vector3<f32>, rotation: Quaternion<f32>, scale: f32) {
+		self.instances.push(FoliageInstance {
+			position_scale: position.extend(scale).into(),
+			rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+		});
+	}
+
+	pub fn instance_count(&self) -> usize {
+		self.instances.len()
+	}
+
+	/// Scatters instances over the triangles of a mesh, placed in the world with the given transform. Each triangle
+	/// gets instances in proportion to its area.
+	pub fn scatter_mesh(
+		&mut self,
+		surface: &MeshBuilder,
+		position: Vector3<f32>,
+		rotation: Quaternion<f32>,
+		options: &ScatterOptions,
+	) {
+		let world = |index: u32| position + rotation * Vector3::from(surface.positions[index as usize]);
+		for [a, b, c] in surface.triangles() {
+			let (a, b, c) = (world(a), world(b), world(c));
+			let cross = (b - a).cross(c - a);
+			let area = cross.magnitude() / 2.0;
+			if area <= 0.0 {
+				continue;
+			}
+
+			let normal = cross / (area * 2.0);
+			for _ in 0..self.random_count(area * options.density) {
+				// folding the square onto the triangle keeps points spread evenly
+				let (mut u, mut v) = (self.random(), self.random());
+				if u + v > 1.0 {
+					u = 1.0 - u;
+					v = 1.0 - v;
+				}
+				self.place(a + (b - a) * u + (c - a) * v, normal, options);
+			}
+		}
+	}
+
+	/// Scatters instances over a height field between two corners on the XZ plane, like a terrain. `height` returns the
+	/// ground's height at a point.
+	pub fn scatter_heightfield(
+		&mut self,
+		min: [f32; 2],
+		max: [f32; 2],
+		height: impl Fn(f32, f32) -> f32,
+		options: &ScatterOptions,
+	) {
+		let size = vec2(max[0] - min[0], max[1] - min[1]);
+		if size.x <= 0.0 || size.y <= 0.0 {
+			return;
+		}
+
+		// the normal comes from the slope across a small step each way
+		let step = size.x.min(size.y) * 0.001;
+		for _ in 0..self.random_count(size.x * size.y * options.density) {
+			let x = min[0] + self.random() * size.x;
+			let z = min[1] + self.random() * size.y;
+			let normal =
+				vec3(height(x - step, z) - height(x + step, z), 2.0 * step, height(x, z - step) - height(x, z + step))
+					.normalize();
+			self.place(vec3(x, height(x, z), z), normal, options);
+		}
+	}
+
+	/// Uploads the foliage, with an instance of `mesh` at each placement. Only the mesh's geometry is used, and its
+	/// tallest point is where the wind bends it the most.
+	pub fn build(
+		self,
+		window: &Window,
+		render_pass: Arc<MeshRenderPass>,
+		mesh: &MeshBuilder,
+	) -> Result<(Foliage, impl GpuFuture + Send + Sync + 'static), DeviceMemoryAllocError> {
+		assert!(!self.instances.is_empty(), "foliage needs at least one instance");
+		let queue = window.device().queue().clone();
+
+		let mesh_height = mesh.positions.iter().map(|p| p[1]).fold(0.0, f32::max).max(0.001);
+		let mesh_radius = mesh.positions.iter().map(|&p| Vector3::from(p).magnitude()).fold(0.0, f32::max);
+		let (instances, cells) = into_cells(self.instances, mesh_radius);
+		let instance_count = instances.len();
+		let triangle_count = mesh.triangles().len();
+
+		let vertex_count = mesh.positions.len();
+		let index_count = mesh.indices.len();
+		let (vertices, vertices_future) =
+			VertexBuffers::immutable(
+				queue.clone(),
+				mesh.vertex_format,
+				mesh.positions.clone(),
+				mesh.normals.clone(),
+				mesh.texcoords.clone(),
+			)?;
+		let (indices, indices_future) = IndexBuffer::immutable(queue.clone(), mesh.indices.clone(), vertex_count)?;
+		let (mut materials, materials_future) =
+			MeshBuilder::make_materials(&render_pass, &queue, &indices, &[(index_count, self.base_color)])?;
+		let (instances, instances_future) =
+			ImmutableBuffer::from_iter(instances.into_iter(), BufferUsage::vertex_buffer(), queue)?;
+
+		let bytes =
+			vertices.positions.size() +
+			vertices.normals.size() +
+			vertices.texcoords_main.size() +
+			indices.size() +
+			instances.size();
+		let device = render_pass.shaders.device_ctx.clone();
+		let memory = device.memory_stats().track(MemoryCategory::Meshes, "foliage", bytes as u64);
+		device.debug_names().name_buffer(&*instances, "foliage instances");
+
+		Ok((
+			Foliage {
+				render_pass: render_pass,
+				vertices: vertices,
+				indices: indices,
+				material: materials.pop().unwrap(),
+				topology: mesh.topology,
+				instances: instances,
+				cells: cells,
+				instance_count: instance_count,
+				triangle_count: triangle_count,
+				mesh_height: mesh_height,
+				wind: Wind::default(),
+				fade: None,
+				time: 0.0,
+				previous_time: 0.0,
+				visible: true,
+				memory: Some(memory),
+			},
+			vertices_future
+				.join(indices_future)
+				.join(materials_future)
+				.join(instances_future)
+		))
+	}
+
+	/// Places an instance at a point on a surface, if the options allow one there.
+	fn place(&mut self, point: Vector3<f32>, normal: Vector3<f32>, options: &ScatterOptions) {
+		let up = Vector3::unit_y();
+		if normal.dot(up) < options.max_slope.to_radians().cos() {
+			return;
+		}
+		// the density map thins out the instances placed at full density
+		if let Some(map) = &options.density_map {
+			if self.random() >= map.sample(point.x, point.z) {
+				return;
+			}
+		}
+
+		let yaw = Quaternion::from_angle_y(Rad(self.random() * 2.0 * PI));
+		let lean_to = (up + (normal - up) * options.align_to_normal.max(0.0).min(1.0)).normalize();
+		let lean = if lean_to == up { Quaternion::one() } else { Quaternion::from_arc(up, lean_to, None) };
+		let [min_scale, max_scale] = options.scale;
+		let scale = min_scale + (max_scale - min_scale) * self.random();
+		self.push_instance(point, lean * yaw, scale);
+	}
+
+	/// Rounds an expected count up or down at random, so small triangles still get their share of instances.
+	fn random_count(&mut self, expected: f32) -> usize {
+		let expected = expected.max(0.0);
+		expected as usize + (self.random() < expected.fract()) as usize
+	}
+
+	/// A random number from 0.0 to 1.0, from a splitmix64 sequence.
+	fn random(&mut self) -> f32 {
+		self.random = self.random.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		let mut z = self.random;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		z ^= z >> 31;
+		(z >> 40) as f32 / (1u64 << 24) as f32
+	}
+}
+impl Default for FoliageBuilder {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// How `FoliageBuilder` scatters instances over a surface.
+#[derive(Clone, Debug)]
+pub struct ScatterOptions {
+	/// Instances per square world unit, where the density map is 1.0.
+	pub density: f32,
+	/// Thins out the instances across the XZ plane. `None` scatters them evenly.
+	pub density_map: Option<Arc<DensityMap>>,
+	/// Each instance is scaled by a random amount between these.
+	pub scale: [f32; 2],
+	/// The steepest surface that gets instances, in degrees from flat.
+	pub max_slope: f32,
+	/// How much instances lean with the surface they stand on, from 0.0 for straight up to 1.0 for along its normal.
+	pub align_to_normal: f32,
+}
+impl Default for ScatterOptions {
+	fn default() -> Self {
+		Self { density: 4.0, density_map: None, scale: [0.8, 1.2], max_slope: 40.0, align_to_normal: 0.0 }
+	}
+}
+
+/// How densely foliage grows over a rectangle of the XZ plane, from 0.0 for none to 1.0 for full density. Outside the
+/// rectangle nothing grows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DensityMap {
+	width: u32,
+	height: u32,
+	values: Vec<f32>,
+	origin: [f32; 2],
+	size: [f32; 2],
+}
+impl DensityMap {
+	/// `values` are in rows along X, starting at `origin`. `size` is how far the map stretches along X and Z.
+	pub fn new(width: u32, height: u32, values: Vec<f32>, origin: [f32; 2], size: [f32; 2]) -> Self {
+		assert_eq!(values.len(), (width * height) as usize, "a density map needs a value for each texel");
+		Self { width: width, height: height, values: values, origin: origin, size: size }
+	}
+
+	/// Loads a density map from the red channel of an image.
+	pub fn from_file(path: impl AsRef<Path>, origin: [f32; 2], size: [f32; 2]) -> Result<Self, ImageError> {
+		let bytes = vfs::read(path.as_ref()).map_err(ImageError::IoError)?;
+		let image = images::decode(&bytes, images::format_from_path(path))?;
+		let (width, height) = (image.width, image.height);
+		let values = image.into_rgba8().chunks(4).map(|pixel| pixel[0] as f32 / 255.0).collect();
+		Ok(Self::new(width, height, values, origin, size))
+	}
+
+	/// The density at a point, blended between the nearest texels.
+	pub fn sample(&self, x: f32, z: f32) -> f32 {
+		let u = (x - self.origin[0]) / self.size[0];
+		let v = (z - self.origin[1]) / self.size[1];
+		if !(u >= 0.0 && u <= 1.0 && v >= 0.0 && v <= 1.0) {
+			return 0.0;
+		}
+
+		let tx = (u * self.width as f32 - 0.5).max(0.0);
+		let ty = (v * self.height as f32 - 0.5).max(0.0);
+		let x0 = (tx as u32).min(self.width - 1);
+		let y0 = (ty as u32).min(self.height - 1);
+		let x1 = (x0 + 1).min(self.width - 1);
+		let y1 = (y0 + 1).min(self.height - 1);
+		let value = |x: u32, y: u32| self.values[(y * self.width + x) as usize];
+		let (fx, fy) = (tx.fract(), ty.fract());
+		let top = value(x0, y0) + (value(x1, y0) - value(x0, y0)) * fx;
+		let bottom = value(x0, y1) + (value(x1, y1) - value(x0, y1)) * fx;
+		top + (bottom - top) * fy
+	}
+}
+
+/// How the wind moves foliage. Instances sway back and forth around a lean away from the wind, and gusts roll across
+/// the field in bands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Wind {
+	/// The direction the wind blows along X and Z. It doesn't need to be normalized.
+	pub direction: Vector2<f32>,
+	/// How far the top of an instance leans, in world units for an instance of scale 1.0.
+	pub strength: f32,
+	/// How much farther gusts push foliage over.
+	pub gust_strength: f32,
+	/// How fast gusts travel across the field, in world units per second.
+	pub gust_speed: f32,
+	/// The distance between gusts, in world units.
+	pub gust_spacing: f32,
+}
+impl Default for Wind {
+	fn default() -> Self {
+		Self { direction: vec2(1.0, 0.0), strength: 0.1, gust_strength: 0.2, gust_speed: 6.0, gust_spacing: 30.0 }
+	}
+}
+
+/// Many copies of a small mesh, drawn with instancing. The instances are sorted into cells, and cells that a camera
+/// can't see, or that are past the fade distance, aren't drawn.
+///
+/// Foliage can't be selected, and custom material shaders aren't supported.
+pub struct Foliage {
+	render_pass: Arc<MeshRenderPass>,
+	vertices: VertexBuffers,
+	indices: IndexBuffer,
+	material: Material,
+	topology: Topology,
+	instances: Arc<ImmutableBuffer<[FoliageInstance]>>,
+	cells: Vec<FoliageCell>,
+	instance_count: usize,
+	/// Triangles in one instance.
+	triangle_count: usize,
+	/// How tall the mesh is, so vs_foliage bends its top the most.
+	mesh_height: f32,
+	wind: Wind,
+	fade: Option<[f32; 2]>,
+	time: f32,
+	/// The time the last time the batch was recorded, for motion vectors.
+	previous_time: f32,
+	visible: bool,
+	/// Taken on drop, so the foliage's memory stays tracked until its buffers are actually released.
+	memory: Option<MemoryHandle>,
+}
+impl Foliage {
+	pub fn instance_count(&self) -> usize {
+		self.instance_count
+	}
+
+	pub fn set_visible(&mut self, visible: bool) {
+		self.visible = visible;
+	}
+
+	pub fn is_visible(&self) -> bool {
+		self.visible
+	}
+
+	pub fn set_wind(&mut self, wind: Wind) {
+		self.wind = wind;
+	}
+
+	pub fn wind(&self) -> Wind {
+		self.wind
+	}
+
+	/// Shrinks instances away between two distances from the camera, and stops drawing them past the second. `None`
+	/// draws them at any distance.
+	pub fn set_fade_distance(&mut self, fade: Option<[f32; 2]>) {
+		self.fade = fade.map(|[start, end]| [start.max(0.0).min(end), end.max(0.0)]);
+	}
+
+	pub fn fade_distance(&self) -> Option<[f32; 2]> {
+		self.fade
+	}
+
+	/// Moves the wind forward in time.
+	pub fn advance(&mut self, seconds: f32) {
+		self.time += seconds;
+	}
+
+	/// Replaces the textures of the foliage's material. The albedo's alpha is blended over the base color, like
+	/// other meshes.
+	pub fn set_textures(&mut self, albedo: &Texture, normal: &Texture) {
+		self.material.binding.update(&self.render_pass, |textures| {
+			textures.albedo = albedo.image().clone();
+			textures.normal = normal.image().clone();
+			textures.custom_textures = true;
+		});
+	}
+
+	/// Overrides how the foliage's textures are sampled.
+	pub fn set_sampler(&mut self, settings: &SamplerSettings) -> Result<(), SamplerCreationError> {
+		let sampler = settings.build(self.render_pass.shaders.queue.device())?;
+		self.material.binding.update(&self.render_pass, |textures| {
+			textures.sampler = sampler;
+			textures.custom_sampler = true;
+		});
+		Ok(())
+	}
+
+	/// Records the draws of the cells the camera can see inline into the batch's G-buffer subpass, or a shadow map.
+	pub(in crate::batch::mesh) fn add_commands(
+		&mut self,
+		mut cmd: AutoCommandBufferBuilder,
+		render_pass: &MeshRenderPass,
+		camera_desc: impl DescriptorSet + Clone + Send + Sync + 'static,
+		state: &DynamicState,
+		stage: GBufferStage,
+		camera: &Camera,
+	) -> AutoCommandBufferBuilder {
+		if !self.visible {
+			return cmd;
+		}
+
+		// neighboring visible cells are drawn together
+		let planes = camera.frustum_planes();
+		let eye = camera.position();
+		let max_distance = self.fade.map_or(std::f32::INFINITY, |[_, end]| end);
+		let mut ranges: Vec<(usize, usize)> = vec![];
+		for cell in &self.cells {
+			let center = cell.bounds.truncate();
+			let radius = cell.bounds.w;
+			let visible =
+				(center - eye).magnitude() - radius < max_distance &&
+				planes.iter().all(|plane| plane.truncate().dot(center) + plane.w >= -radius);
+			if !visible {
+				continue;
+			}
+			match ranges.last_mut() {
+				Some(range) if range.1 == cell.start => range.1 = cell.end,
+				_ => ranges.push((cell.start, cell.end)),
+			}
+		}
+		if ranges.is_empty() {
+			return cmd;
+		}
+
+		let pipeline = render_pass.pipeline_foliage_for(stage, self.topology, self.vertices.format).clone();
+		self.material.binding.refresh_sampler(render_pass);
+		let desc = self.material.binding.desc.take().unwrap();
+
+		let [ox, oy, oz] = self.vertices.position_offset;
+		let [sx, sy, sz] = self.vertices.position_scale;
+		let wind_direction =
+			if self.wind.direction.magnitude2() > 0.0 { self.wind.direction.normalize() } else { vec2(1.0, 0.0) };
+		let [fade_start, fade_end] = self.fade.unwrap_or([0.0, 0.0]);
+		let constants =
+			vs_foliage::ty::Foliage {
+				position_offset: [ox, oy, oz, 1.0 / self.mesh_height],
+				position_scale: [sx, sy, sz, self.material.emissive],
+				wind: [wind_direction.x, wind_direction.y, self.wind.strength, self.wind.gust_strength],
+				gust: [self.wind.gust_speed, self.wind.gust_spacing.max(0.001), self.time, self.previous_time],
+				fade: [fade_start, fade_end, 0.0, 0.0],
+			};
+
+		for (start, end) in ranges {
+			let instances = self.instances.clone().into_buffer_slice().slice(start..end).unwrap();
+			let vertex_buffers: Vec<Arc<BufferAccess + Send + Sync>> =
+				vec![
+					self.vertices.positions.clone(),
+					self.vertices.normals.clone(),
+					self.vertices.texcoords_main.clone(),
+					Arc::new(instances),
+				];
+			let sets = (camera_desc.clone(), desc.clone());
+			render_pass.shaders.device_ctx.render_stats().record_draw(self.triangle_count * (end - start));
+			cmd =
+				match &self.indices {
+					IndexBuffer::U16(indices) =>
+						cmd.draw_indexed(pipeline.clone(), state, vertex_buffers, indices.clone(), sets, constants),
+					IndexBuffer::U32(indices) =>
+						cmd.draw_indexed(pipeline.clone(), state, vertex_buffers, indices.clone(), sets, constants),
+				}
+				.unwrap();
+		}
+
+		self.material.binding.desc.set_if_none(desc);
+		cmd
+	}
+
+	pub(in crate::batch::mesh) fn end_frame(&mut self) {
+		self.previous_time = self.time;
+	}
+}
+impl Drop for Foliage {
+	fn drop(&mut self) {
+		// frames still on the GPU may draw this foliage, so its buffers and descriptors are kept until they finish
+		let resources = (
+			self.vertices.positions.clone(),
+			self.vertices.normals.clone(),
+			self.vertices.texcoords_main.clone(),
+			self.indices.clone(),
+			self.material.binding.clone(),
+			self.instances.clone(),
+			self.memory.take(),
+		);
+		self.render_pass.shaders.device_ctx.defer_destroy(resources);
+	}
+}
+
+/// Identifies foliage that has been added to a `MeshBatch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FoliageId(pub(in crate::batch::mesh) u64);
+
+/// An instance as vs_foliage reads it.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub(in crate::batch::mesh) struct FoliageInstance {
+	position_scale: [f32; 4],
+	/// An xyzw quaternion.
+	rotation: [f32; 4],
+}
+
+/// A range of instances that are near each other, and a world space sphere around them.
+#[derive(Clone, Debug)]
+struct FoliageCell {
+	start: usize,
+	end: usize,
+	bounds: Vector4<f32>,
+}
+
+/// Sorts the instances into cells on the XZ plane, so each cell's instances are next to each other.
+fn into_cells(instances: Vec<FoliageInstance>, mesh_radius: f32) -> (Vec<FoliageInstance>, Vec<FoliageCell>) {
+	let mut by_cell: HashMap<(i32, i32), Vec<FoliageInstance>> = HashMap::new();
+	for instance in instances {
+		let [x, _, z, _] = instance.position_scale;
+		let key = ((x / CELL_SIZE).floor() as i32, (z / CELL_SIZE).floor() as i32);
+		by_cell.entry(key).or_insert_with(Vec::new).push(instance);
+	}
+	let mut keys = by_cell.keys().cloned().collect::<Vec<_>>();
+	keys.sort();
+
+	let mut sorted = vec![];
+	let mut cells = vec![];
+	for key in keys {
+		let cell_instances = &by_cell[&key];
+		let mut min = Vector3::from([std::f32::INFINITY; 3]);
+		let mut max = Vector3::from([std::f32::NEG_INFINITY; 3]);
+		let mut max_scale: f32 = 0.0;
+		for instance in cell_instances {
+			let [x, y, z, scale] = instance.position_scale;
+			min = vec3(min.x.min(x), min.y.min(y), min.z.min(z));
+			max = vec3(max.x.max(x), max.y.max(y), max.z.max(z));
+			max_scale = max_scale.max(scale);
+		}
+		// leaves room around the instances for their meshes, and for the wind to push them around
+		let radius = (max - min).magnitude() / 2.0 + mesh_radius * max_scale * 2.0;
+		cells.push(FoliageCell {
+			start: sorted.len(),
+			end: sorted.len() + cell_instances.len(),
+			bounds: ((min + max) / 2.0).extend(radius),
+		});
+		sorted.extend_from_slice(cell_instances);
+	}
+	(sorted, cells)
+}
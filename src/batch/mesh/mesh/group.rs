@@ -0,0 +1,604 @@
+use crate::batch::mesh::{
+	MeshRenderPass,
+	mesh::{ IndexBuffer, Material, MeshBuilder, Topology, VertexBuffers, VertexFormat, codec },
+	render_pass::GBufferStage,
+	shaders::cs_draw_cull,
+};
+use crate::device::{ MemoryCategory, MemoryHandle };
+use crate::progress::LoadProgress;
+use crate::texture::{ ImmutableTexture, SamplerSettings, Texture };
+use crate::window::Window;
+use cgmath::{ InnerSpace, Matrix4, Quaternion, Vector3, Vector4 };
+use std::{
+	collections::HashMap,
+	error::Error,
+	fmt,
+	iter,
+	mem::{ self, size_of },
+	path::PathBuf,
+	sync::{ Arc, Mutex },
+};
+use vulkano::{
+	buffer::{ BufferAccess, BufferUsage, DeviceLocalBuffer, ImmutableBuffer, TypedBufferAccess },
+	command_buffer::{ AutoCommandBufferBuilder, DrawIndexedIndirectCommand, DynamicState },
+	descriptor::{ DescriptorSet, descriptor_set::PersistentDescriptorSet },
+	device::Queue,
+	memory::DeviceMemoryAllocError,
+	sampler::SamplerCreationError,
+	sync::{ self, GpuFuture },
+};
+
+/// How many objects cs_draw_cull tests in each work group. This must match its local size.
+const CULL_GROUP_SIZE: u32 = 64;
+
+/// Builds a `MeshGroup` out of many static objects that share a material.
+#[derive(Clone, Debug)]
+pub struct MeshGroupBuilder {
+	positions: Vec<[f32; 3]>,
+	normals: Vec<[f32; 3]>,
+	texcoords: Vec<[f32; 2]>,
+	indices: Vec<u32>,
+	objects: Vec<GroupObjectRange>,
+	/// Taken from the first object.
+	topology: Option<Topology>,
+	vertex_format: VertexFormat,
+	base_color: [f32; 3],
+	emissive: f32,
+	/// The albedo and normal textures, loaded in the background once the group is built.
+	textures: [Option<PathBuf>; 2],
+}
+impl MeshGroupBuilder {
+	pub fn new() -> Self {
+		Self {
+			positions: vec![],
+			normals: vec![],
+			texcoords: vec![],
+			indices: vec![],
+			objects: vec![],
+			topology: None,
+			vertex_format: VertexFormat::Full,
+			base_color: [1.0, 1.0, 1.0],
+			emissive: 0.0,
+			textures: [None, None],
+		}
+	}
+
+	/// Adds a copy of a mesh's geometry, placed in the world with the given transform, and returns its index in the
+	/// group. Submeshes are ignored, since the whole group is drawn with one material. Every object must have the same
+	/// topology.
+	pub fn push_object(&mut self, mesh: &MeshBuilder, position: Vector3<f32>, rotation: Quaternion<f32>) -> usize {
+		let topology = *self.topology.get_or_insert(mesh.topology);
+		assert_eq!(topology, mesh.topology, "every object in a mesh group must have the same topology");
+		self.push_geometry(&mesh.positions, &mesh.normals, &mesh.texcoords, &mesh.indices, position, rotation)
+	}
+
+	pub fn object_count(&self) -> usize {
+		self.objects.len()
+	}
+
+	/// Sets the format `build` stores the vertices in.
+	pub fn set_vertex_format(&mut self, vertex_format: VertexFormat) {
+		self.vertex_format = vertex_format;
+	}
+
+	/// Sets the linear base color of the group's material.
+	pub fn set_base_color(&mut self, base_color: [f32; 3]) {
+		self.base_color = base_color;
+	}
+
+	/// Makes every object glow, like `Mesh::set_submesh_emissive`. It's fixed once the group is built.
+	pub fn set_emissive(&mut self, intensity: f32) {
+		self.emissive = intensity;
+	}
+
+	/// Uploads the group. The device must support the `multi_draw_indirect` and `draw_indirect_first_instance`
+	/// features, which the engine requests by default.
+	pub fn build(
+		self,
+		window: &Window,
+		render_pass: Arc<MeshRenderPass>,
+	) -> Result<(MeshGroup, impl GpuFuture + Send + Sync + 'static), MeshGroupError> {
+		self.build_impl(window.device().queue().clone(), render_pass)
+	}
+
+	fn push_geometry(
+		&mut self,
+		positions: &[[f32; 3]],
+		normals: &[[f32; 3]],
+		texcoords: &[[f32; 2]],
+		indices: &[u32],
+		position: Vector3<f32>,
+		rotation: Quaternion<f32>,
+	) -> usize {
+		let range =
+			GroupObjectRange {
+				first_index: self.indices.len() as u32,
+				index_count: indices.len() as u32,
+				vertex_offset: self.positions.len() as u32,
+				vertex_count: positions.len() as u32,
+				model: Matrix4::from_translation(position) * Matrix4::from(rotation),
+				bounds: bounding_sphere(positions, position, rotation),
+			};
+		self.positions.extend_from_slice(positions);
+		self.normals.extend_from_slice(normals);
+		self.texcoords.extend_from_slice(texcoords);
+		self.indices.extend_from_slice(indices);
+		self.objects.push(range);
+		self.objects.len() - 1
+	}
+
+	fn build_impl(
+		self,
+		queue: Arc<Queue>,
+		render_pass: Arc<MeshRenderPass>,
+	) -> Result<(MeshGroup, impl GpuFuture + Send + Sync + 'static), MeshGroupError> {
+		assert!(!self.objects.is_empty(), "a mesh group needs at least one object");
+		if !supports_groups(&render_pass) {
+			return Err(MeshGroupError::Unsupported);
+		}
+
+		let topology = self.topology.unwrap();
+		let triangle_count = self.objects.iter()
+			.map(|object| match topology {
+				Topology::TriangleList => object.index_count as usize / 3,
+				Topology::TriangleStrip => (object.index_count as usize).saturating_sub(2),
+			})
+			.sum();
+
+		// indices are relative to each object's first vertex, so they only need to fit the largest object
+		let max_vertex_count = self.objects.iter().map(|object| object.vertex_count as usize).max().unwrap();
+		let index_count = self.indices.len();
+		let (vertices, vertices_future) =
+			VertexBuffers::immutable(queue.clone(), self.vertex_format, self.positions, self.normals, self.texcoords)?;
+		let (indices, indices_future) = IndexBuffer::immutable(queue.clone(), self.indices, max_vertex_count)?;
+		let (mut materials, materials_future) =
+			MeshBuilder::make_materials(&render_pass, &queue, &indices, &[(index_count, self.base_color)])?;
+
+		let [ox, oy, oz] = vertices.position_offset;
+		let [sx, sy, sz] = vertices.position_scale;
+		let objects = self.objects.iter()
+			.map(|object| GroupObject {
+				model: object.model.into(),
+				position_offset: [ox, oy, oz, 0.0],
+				position_scale: [sx, sy, sz, self.emissive],
+				bounds: object.bounds.into(),
+			})
+			.collect::<Vec<_>>();
+		let (objects, objects_future) =
+			ImmutableBuffer::from_iter(objects.into_iter(), BufferUsage::storage_buffer(), queue.clone())?;
+
+		// each draw's first instance is its object, so vs_gbuffers_indirect can find its transform
+		let draws = self.objects.iter()
+			.enumerate()
+			.map(|(i, object)| DrawIndexedIndirectCommand {
+				index_count: object.index_count,
+				instance_count: 1,
+				first_index: object.first_index,
+				vertex_offset: object.vertex_offset,
+				first_instance: i as u32,
+			})
+			.collect::<Vec<_>>();
+		let usage = BufferUsage { indirect_buffer: true, storage_buffer: true, .. BufferUsage::none() };
+		let (draws, draws_future) = ImmutableBuffer::from_iter(draws.into_iter(), usage, queue)?;
+
+		let objects_desc =
+			Arc::new(
+				PersistentDescriptorSet::start(
+					render_pass.pipeline_indirect_for(GBufferStage::GBuffers, topology, vertices.format).clone(),
+					2
+				)
+					.add_buffer(objects.clone())
+					.unwrap()
+					.build()
+					.unwrap()
+			);
+
+		let bytes =
+			vertices.positions.size() +
+			vertices.normals.size() +
+			vertices.texcoords_main.size() +
+			indices.size() +
+			objects.size() +
+			draws.size();
+		let device = render_pass.shaders.device_ctx.clone();
+		let memory = device.memory_stats().track(MemoryCategory::Meshes, "mesh group", bytes as u64);
+		let debug_names = device.debug_names();
+		debug_names.name_buffer(&*vertices.positions, "mesh group positions");
+		debug_names.name_buffer(&*vertices.normals, "mesh group normals");
+		debug_names.name_buffer(&*vertices.texcoords_main, "mesh group texcoords");
+		debug_names.name_buffer(&*objects, "mesh group objects");
+		debug_names.name_buffer(&*draws, "mesh group draws");
+
+		let material = materials.pop().unwrap();
+		let textures = Arc::new(Mutex::new(vec![]));
+		if self.textures.iter().any(Option::is_some) {
+			let binding = material.binding.clone();
+			codec::load_textures(&render_pass, binding, &self.textures, &textures, &LoadProgress::new());
+		}
+
+		Ok((
+			MeshGroup {
+				render_pass: render_pass,
+				vertices: vertices,
+				indices: indices,
+				material: material,
+				topology: topology,
+				object_count: self.objects.len(),
+				triangle_count: triangle_count,
+				objects: objects,
+				draws: draws,
+				objects_desc: objects_desc,
+				culled: vec![],
+				visible: true,
+				memory: Some(memory),
+				_textures: textures,
+			},
+			vertices_future
+				.join(indices_future)
+				.join(materials_future)
+				.join(objects_future)
+				.join(draws_future)
+		))
+	}
+}
+impl Default for MeshGroupBuilder {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Many static objects that share a material, drawn with a single indirect draw. Their geometry is stored together,
+/// and before each frame a compute shader tests every object against each camera and lists the draws of the visible
+/// ones, so the CPU records the same few commands no matter how many objects there are.
+///
+/// Objects can't be moved or selected once the group is built. Custom material shaders aren't supported.
+pub struct MeshGroup {
+	render_pass: Arc<MeshRenderPass>,
+	vertices: VertexBuffers,
+	indices: IndexBuffer,
+	material: Material,
+	topology: Topology,
+	object_count: usize,
+	triangle_count: usize,
+	objects: Arc<ImmutableBuffer<[GroupObject]>>,
+	/// Draws every object. It's what culling starts from, and it's used as is for shadows.
+	draws: Arc<ImmutableBuffer<[DrawIndexedIndirectCommand]>>,
+	objects_desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	/// The visible draws for each camera the group has been recorded with at once.
+	culled: Vec<CulledDraws>,
+	visible: bool,
+	/// Taken on drop, so the group's memory stays tracked until its buffers are actually released.
+	memory: Option<MemoryHandle>,
+	/// Textures loaded for the group's material, kept so their memory is tracked for as long as the group is alive.
+	_textures: Arc<Mutex<Vec<ImmutableTexture>>>,
+}
+impl MeshGroup {
+	pub fn object_count(&self) -> usize {
+		self.object_count
+	}
+
+	/// Hides or shows every object in the group.
+	pub fn set_visible(&mut self, visible: bool) {
+		self.visible = visible;
+	}
+
+	pub fn is_visible(&self) -> bool {
+		self.visible
+	}
+
+	/// Replaces the textures of the group's material.
+	pub fn set_textures(&mut self, albedo: &Texture, normal: &Texture) {
+		self.material.binding.update(&self.render_pass, |textures| {
+			textures.albedo = albedo.image().clone();
+			textures.normal = normal.image().clone();
+			textures.custom_textures = true;
+		});
+	}
+
+	/// Overrides how the group's textures are sampled.
+	pub fn set_sampler(&mut self, settings: &SamplerSettings) -> Result<(), SamplerCreationError> {
+		let sampler = settings.build(self.render_pass.shaders.queue.device())?;
+		self.material.binding.update(&self.render_pass, |textures| {
+			textures.sampler = sampler;
+			textures.custom_sampler = true;
+		});
+		Ok(())
+	}
+
+	/// Records the culling of the group's objects against a camera. This must be called outside of a render pass,
+	/// before the group is drawn for that camera.
+	pub(in crate::batch::mesh) fn record_cull(
+		&mut self,
+		cmd: AutoCommandBufferBuilder,
+		camera: usize,
+		planes: [Vector4<f32>; 6],
+	) -> Result<AutoCommandBufferBuilder, DeviceMemoryAllocError> {
+		if !self.visible {
+			return Ok(cmd);
+		}
+
+		while self.culled.len() <= camera {
+			let culled = self.make_culled()?;
+			self.culled.push(culled);
+		}
+		let culled = &self.culled[camera];
+
+		let plane = |i: usize| -> [f32; 4] { planes[i].into() };
+		let object_count = self.object_count as u32;
+		Ok(
+			cmd
+				.fill_buffer(culled.draws.clone(), 0)
+				.unwrap()
+				.fill_buffer(culled.count.clone(), 0)
+				.unwrap()
+				.dispatch(
+					[(object_count + CULL_GROUP_SIZE - 1) / CULL_GROUP_SIZE, 1, 1],
+					self.render_pass.pipeline_draw_cull.clone(),
+					culled.desc.clone(),
+					cs_draw_cull::ty::DrawCull {
+						planes: [plane(0), plane(1), plane(2), plane(3), plane(4), plane(5)],
+						object_count: object_count,
+					}
+				)
+				.unwrap()
+		)
+	}
+
+	/// Records the group's draw inline into the batch's G-buffer subpass. With a camera, only the objects its culling
+	/// found visible are drawn. Without one, every object is, as for shadows.
+	pub(in crate::batch::mesh) fn add_commands(
+		&mut self,
+		cmd: AutoCommandBufferBuilder,
+		render_pass: &MeshRenderPass,
+		camera_desc: impl DescriptorSet + Clone + Send + Sync + 'static,
+		state: &DynamicState,
+		stage: GBufferStage,
+		camera: Option<usize>,
+	) -> AutoCommandBufferBuilder {
+		if !self.visible {
+			return cmd;
+		}
+
+		let pipeline = render_pass.pipeline_indirect_for(stage, self.topology, self.vertices.format).clone();
+		let draws: Arc<TypedBufferAccess<Content = [DrawIndexedIndirectCommand]> + Send + Sync> =
+			match camera {
+				Some(camera) => self.culled[camera].draws.clone(),
+				None => self.draws.clone(),
+			};
+		let vertex_buffers =
+			vec![self.vertices.positions.clone(), self.vertices.normals.clone(), self.vertices.texcoords_main.clone()];
+		self.material.binding.refresh_sampler(render_pass);
+		let desc = self.material.binding.desc.take().unwrap();
+		let sets = (camera_desc, desc.clone(), self.objects_desc.clone());
+
+		// the CPU doesn't know how many objects were culled, so this counts all of them
+		render_pass.shaders.device_ctx.render_stats().record_draw(self.triangle_count);
+		let cmd =
+			match &self.indices {
+				IndexBuffer::U16(indices) =>
+					cmd.draw_indexed_indirect(pipeline, state, vertex_buffers, indices.clone(), draws, sets, ()),
+				IndexBuffer::U32(indices) =>
+					cmd.draw_indexed_indirect(pipeline, state, vertex_buffers, indices.clone(), draws, sets, ()),
+			}
+			.unwrap();
+
+		self.material.binding.desc.set_if_none(desc);
+		cmd
+	}
+
+	fn make_culled(&self) -> Result<CulledDraws, DeviceMemoryAllocError> {
+		let device = &self.render_pass.shaders.device_ctx;
+		let family = iter::once(device.queue().family());
+		let usage =
+			BufferUsage {
+				indirect_buffer: true,
+				storage_buffer: true,
+				transfer_destination: true,
+				.. BufferUsage::none()
+			};
+		let draws = DeviceLocalBuffer::array(device.device().clone(), self.object_count, usage, family.clone())?;
+		let usage = BufferUsage { storage_buffer: true, transfer_destination: true, .. BufferUsage::none() };
+		let count = DeviceLocalBuffer::new(device.device().clone(), usage, family)?;
+		device.debug_names().name_buffer(&*draws, "mesh group visible draws");
+		let bytes = (self.object_count * size_of::<DrawIndexedIndirectCommand>() + size_of::<u32>()) as u64;
+		let memory = device.memory_stats().track(MemoryCategory::Buffers, "mesh group visible draws", bytes);
+
+		let desc =
+			Arc::new(
+				PersistentDescriptorSet::start(self.render_pass.pipeline_draw_cull.clone(), 0)
+					.add_buffer(self.objects.clone())
+					.unwrap()
+					.add_buffer(self.draws.clone())
+					.unwrap()
+					.add_buffer(draws.clone())
+					.unwrap()
+					.add_buffer(count.clone())
+					.unwrap()
+					.build()
+					.unwrap()
+			);
+
+		Ok(CulledDraws { draws: draws, count: count, desc: desc, _memory: memory })
+	}
+}
+impl Drop for MeshGroup {
+	fn drop(&mut self) {
+		// frames still on the GPU may draw this group, so its buffers and descriptors are kept until they finish
+		let resources = (
+			self.vertices.positions.clone(),
+			self.vertices.normals.clone(),
+			self.vertices.texcoords_main.clone(),
+			self.indices.clone(),
+			self.material.binding.clone(),
+			self.objects.clone(),
+			self.draws.clone(),
+			self.objects_desc.clone(),
+			mem::replace(&mut self.culled, vec![]),
+			self._textures.clone(),
+			self.memory.take(),
+		);
+		self.render_pass.shaders.device_ctx.defer_destroy(resources);
+	}
+}
+
+#[derive(Debug)]
+pub enum MeshGroupError {
+	/// The device doesn't support the features indirect drawing needs.
+	Unsupported,
+	DeviceMemoryAllocError(DeviceMemoryAllocError),
+}
+impl fmt::Display for MeshGroupError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			MeshGroupError::Unsupported => write!(f, "the device doesn't support indirect drawing"),
+			MeshGroupError::DeviceMemoryAllocError(err) => write!(f, "device memory allocation failed: {}", err),
+		}
+	}
+}
+impl Error for MeshGroupError {
+	fn source(&self) -> Option<&(Error + 'static)> {
+		match self {
+			MeshGroupError::Unsupported => None,
+			MeshGroupError::DeviceMemoryAllocError(err) => Some(err),
+		}
+	}
+}
+impl From<DeviceMemoryAllocError> for MeshGroupError {
+	fn from(val: DeviceMemoryAllocError) -> Self {
+		MeshGroupError::DeviceMemoryAllocError(val)
+	}
+}
+
+/// Merges small static models into mesh groups as a scene loads, with a group for each distinct material. Each
+/// material of a model becomes an object in that material's group.
+pub(crate) struct StaticBatcher {
+	groups: Vec<(MaterialKey, MeshGroupBuilder)>,
+}
+impl StaticBatcher {
+	pub(crate) fn new() -> Self {
+		Self { groups: vec![] }
+	}
+
+	pub(crate) fn push(&mut self, model: &codec::ModelData, position: Vector3<f32>, rotation: Quaternion<f32>) {
+		let mut index_start = 0;
+		for material in &model.materials {
+			let indices = &model.indices[index_start..index_start + material.index_count];
+			index_start += material.index_count;
+
+			// only the vertices this material uses are copied, with the indices renumbered to match
+			let mut remap = HashMap::new();
+			let (mut positions, mut normals, mut texcoords) = (vec![], vec![], vec![]);
+			let indices = indices.iter()
+				.map(|&index| {
+					*remap.entry(index).or_insert_with(|| {
+						positions.push(model.positions[index as usize]);
+						normals.push(model.normals[index as usize]);
+						texcoords.push(model.texcoords_main[index as usize]);
+						positions.len() as u32 - 1
+					})
+				})
+				.collect::<Vec<_>>();
+
+			let uniform = &material.uniform;
+			let [r, g, b] = uniform.base_color;
+			let key =
+				MaterialKey {
+					base_color: [r.to_bits(), g.to_bits(), b.to_bits()],
+					emissive_brightness: uniform.emissive_brightness,
+					textures: material.textures.clone(),
+				};
+			let builder =
+				match self.groups.iter().position(|(group_key, _)| *group_key == key) {
+					Some(i) => &mut self.groups[i].1,
+					None => {
+						let mut builder = MeshGroupBuilder::new();
+						builder.topology = Some(Topology::TriangleList);
+						builder.set_base_color(uniform.base_color);
+						builder.set_emissive(material.emissive());
+						builder.textures = material.textures.clone();
+						self.groups.push((key, builder));
+						&mut self.groups.last_mut().unwrap().1
+					},
+				};
+			builder.push_geometry(&positions, &normals, &texcoords, &indices, position, rotation);
+		}
+	}
+
+	/// Uploads a group for each material that was pushed.
+	pub(crate) fn build(
+		self,
+		render_pass: &Arc<MeshRenderPass>,
+	) -> Result<(Vec<MeshGroup>, Box<GpuFuture + Send + Sync>), MeshGroupError> {
+		let mut groups = Vec::with_capacity(self.groups.len());
+		let mut future: Box<GpuFuture + Send + Sync> = Box::new(sync::now(render_pass.shaders.queue.device().clone()));
+		for (_, builder) in self.groups {
+			let (group, group_future) = builder.build_impl(render_pass.shaders.queue.clone(), render_pass.clone())?;
+			groups.push(group);
+			future = Box::new(future.join(group_future));
+		}
+		Ok((groups, future))
+	}
+}
+
+/// Whether the device can draw mesh groups.
+pub(crate) fn supports_groups(render_pass: &MeshRenderPass) -> bool {
+	let features = render_pass.shaders.device_ctx.enabled_features();
+	features.multi_draw_indirect && features.draw_indirect_first_instance
+}
+
+/// What makes two models' materials the same, so they can share a group.
+#[derive(Clone, Debug, PartialEq)]
+struct MaterialKey {
+	base_color: [u32; 3],
+	emissive_brightness: u32,
+	textures: [Option<PathBuf>; 2],
+}
+
+/// Where an object's geometry is in the group's buffers, and where it is in the world.
+#[derive(Clone, Debug)]
+struct GroupObjectRange {
+	first_index: u32,
+	index_count: u32,
+	vertex_offset: u32,
+	vertex_count: u32,
+	model: Matrix4<f32>,
+	/// A world space sphere around the object, as its center and radius.
+	bounds: Vector4<f32>,
+}
+
+/// An object as vs_gbuffers_indirect and cs_draw_cull read it.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct GroupObject {
+	model: [[f32; 4]; 4],
+	/// How the vertices' positions are decoded, as in `MeshModel`.
+	position_offset: [f32; 4],
+	/// The scale of the vertices' positions, then the emissive intensity.
+	position_scale: [f32; 4],
+	bounds: [f32; 4],
+}
+
+/// The visible draws of a group for one camera, compacted to the front, and the count the culling shader adds to.
+struct CulledDraws {
+	draws: Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>>,
+	count: Arc<DeviceLocalBuffer<u32>>,
+	desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	_memory: MemoryHandle,
+}
+
+/// The sphere around the box bounding the positions, moved to where the object is placed.
+fn bounding_sphere(positions: &[[f32; 3]], position: Vector3<f32>, rotation: Quaternion<f32>) -> Vector4<f32> {
+	if positions.is_empty() {
+		return position.extend(0.0);
+	}
+
+	let mut min = Vector3::from(positions[0]);
+	let mut max = min;
+	for &p in positions {
+		min = Vector3::new(min.x.min(p[0]), min.y.min(p[1]), min.z.min(p[2]));
+		max = Vector3::new(max.x.max(p[0]), max.y.max(p[1]), max.z.max(p[2]));
+	}
+	let center = (min + max) / 2.0;
+	let radius = positions.iter().map(|&p| (Vector3::from(p) - center).magnitude()).fold(0.0, f32::max);
+	(position + rotation * center).extend(radius)
+}
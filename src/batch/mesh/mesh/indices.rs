@@ -0,0 +1,85 @@
+use std::{ ops::Range, sync::Arc, u16, u32 };
+use vulkano::{
+	buffer::{ BufferAccess, BufferSlice, BufferUsage, ImmutableBuffer, TypedBufferAccess },
+	device::Queue,
+	memory::DeviceMemoryAllocError,
+	sync::GpuFuture,
+};
+
+/// An index buffer that uses 16-bit indices whenever the mesh has few enough vertices.
+#[derive(Clone)]
+pub(super) enum IndexBuffer {
+	U16(Arc<TypedBufferAccess<Content = [u16]> + Send + Sync>),
+	U32(Arc<TypedBufferAccess<Content = [u32]> + Send + Sync>),
+}
+impl IndexBuffer {
+	pub(super) fn immutable(
+		queue: Arc<Queue>,
+		indices: Vec<u32>,
+		vertex_count: usize,
+	) -> Result<(Self, impl GpuFuture + Send + Sync + 'static), DeviceMemoryAllocError> {
+		// an index past the vertices is a bug in the mesh, but it still has to reach the device intact
+		let narrowable = indices.iter().all(|&index| index < u16::MAX as u32 || index == u32::MAX);
+		if fits_u16(vertex_count) && narrowable {
+			let (buffer, future) =
+				ImmutableBuffer::from_iter(indices.into_iter().map(narrow), BufferUsage::index_buffer(), queue)?;
+			Ok((IndexBuffer::U16(buffer), future))
+		} else {
+			let (buffer, future) = ImmutableBuffer::from_iter(indices.into_iter(), BufferUsage::index_buffer(), queue)?;
+			Ok((IndexBuffer::U32(buffer), future))
+		}
+	}
+
+	/// Size of the whole buffer in bytes.
+	pub(super) fn size(&self) -> usize {
+		match self {
+			IndexBuffer::U16(buffer) => buffer.size(),
+			IndexBuffer::U32(buffer) => buffer.size(),
+		}
+	}
+
+	pub(super) fn slice(&self, range: Range<usize>) -> IndexSlice {
+		match self {
+			IndexBuffer::U16(buffer) => IndexSlice::U16(buffer.clone().into_buffer_slice().slice(range).unwrap()),
+			IndexBuffer::U32(buffer) => IndexSlice::U32(buffer.clone().into_buffer_slice().slice(range).unwrap()),
+		}
+	}
+}
+
+#[derive(Clone)]
+pub(super) enum IndexSlice {
+	U16(BufferSlice<[u16], Arc<TypedBufferAccess<Content = [u16]> + Send + Sync>>),
+	U32(BufferSlice<[u32], Arc<TypedBufferAccess<Content = [u32]> + Send + Sync>>),
+}
+impl IndexSlice {
+	pub(super) fn len(&self) -> usize {
+		match self {
+			IndexSlice::U16(slice) => slice.len(),
+			IndexSlice::U32(slice) => slice.len(),
+		}
+	}
+
+	pub(super) fn size(&self) -> usize {
+		self.buffer().size()
+	}
+
+	pub(super) fn buffer(&self) -> &BufferAccess {
+		match self {
+			IndexSlice::U16(slice) => slice,
+			IndexSlice::U32(slice) => slice,
+		}
+	}
+}
+
+/// Whether every index of a mesh with this many vertices fits in 16 bits, leaving the largest value free for primitive
+/// restart.
+pub(super) fn fits_u16(vertex_count: usize) -> bool {
+	vertex_count < u16::MAX as usize
+}
+
+/// Converts an index to 16 bits, keeping the primitive restart value intact. The index must be below `u16::MAX`, unless
+/// it's `u32::MAX`.
+pub(super) fn narrow(index: u32) -> u16 {
+	debug_assert!(index < u16::MAX as u32 || index == u32::MAX, "index {} doesn't fit in 16 bits", index);
+	if index == u32::MAX { u16::MAX } else { index as u16 }
+}
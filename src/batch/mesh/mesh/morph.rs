@@ -0,0 +1,172 @@
+use crate::batch::mesh::{ MeshRenderPass, shaders::cs_morph };
+use crate::device::{ MemoryCategory, MemoryHandle };
+use std::{ iter, mem::size_of, sync::Arc };
+use vulkano::{
+	buffer::{ BufferUsage, CpuBufferPool, DeviceLocalBuffer, ImmutableBuffer },
+	command_buffer::AutoCommandBufferBuilder,
+	descriptor::{ DescriptorSet, descriptor_set::{ FixedSizeDescriptorSetsPool, PersistentDescriptorSet } },
+	device::Queue,
+	memory::DeviceMemoryAllocError,
+	pipeline::ComputePipelineAbstract,
+	sync::GpuFuture,
+};
+
+/// How many vertices each workgroup of cs_morph blends. This must match cs_morph.
+const MORPH_GROUP_SIZE: u32 = 64;
+
+/// A shape a mesh can blend toward, like a smile or a blink, as offsets from the mesh's vertices. These map directly
+/// onto glTF morph targets.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MorphTarget {
+	pub name: String,
+	/// One offset for each of the mesh's vertices.
+	pub position_deltas: Vec<[f32; 3]>,
+	/// One offset for each of the mesh's vertices, or none if the target doesn't change the normals.
+	pub normal_deltas: Vec<[f32; 3]>,
+}
+impl MorphTarget {
+	pub fn new(name: impl Into<String>, position_deltas: Vec<[f32; 3]>, normal_deltas: Vec<[f32; 3]>) -> Self {
+		Self { name: name.into(), position_deltas: position_deltas, normal_deltas: normal_deltas }
+	}
+}
+
+/// The base vertices and morph targets of a mesh, and the device local vertex buffers they're blended into. The blend
+/// is recorded before the mesh is drawn, and only when the weights have changed.
+pub(super) struct MorphGeometry {
+	vertex_count: usize,
+	names: Vec<String>,
+	weights: Vec<f32>,
+	/// Whether the weights have changed since the vertices were last blended.
+	dirty: bool,
+	positions: Arc<DeviceLocalBuffer<[[f32; 3]]>>,
+	normals: Arc<DeviceLocalBuffer<[[f32; 3]]>>,
+	pipeline: Arc<ComputePipelineAbstract + Send + Sync + 'static>,
+	desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	weight_pool: CpuBufferPool<f32>,
+	weight_desc_pool: FixedSizeDescriptorSetsPool<Arc<ComputePipelineAbstract + Send + Sync + 'static>>,
+	_memory: MemoryHandle,
+}
+impl MorphGeometry {
+	pub(super) fn new(
+		render_pass: &MeshRenderPass,
+		queue: &Arc<Queue>,
+		positions: &[[f32; 3]],
+		normals: &[[f32; 3]],
+		targets: Vec<MorphTarget>,
+	) -> Result<(Self, impl GpuFuture + Send + Sync + 'static), DeviceMemoryAllocError> {
+		let device = queue.device();
+		let vertex_count = positions.len();
+		let storage = BufferUsage { storage_buffer: true, .. BufferUsage::none() };
+		let vertex_usage = BufferUsage { storage_buffer: true, vertex_buffer: true, .. BufferUsage::none() };
+
+		let (base_positions, base_positions_future) =
+			ImmutableBuffer::from_iter(positions.iter().cloned(), storage, queue.clone())?;
+		let (base_normals, base_normals_future) =
+			ImmutableBuffer::from_iter(normals.iter().cloned(), storage, queue.clone())?;
+		let position_deltas = targets.iter().flat_map(|target| target.position_deltas.iter().cloned());
+		let (position_deltas, position_deltas_future) =
+			ImmutableBuffer::from_iter(position_deltas, storage, queue.clone())?;
+		// targets without normal offsets leave the normals as they are
+		let normal_deltas = targets.iter()
+			.flat_map(|target| {
+				let zeros = if target.normal_deltas.is_empty() { vertex_count } else { 0 };
+				target.normal_deltas.iter().cloned().chain(iter::repeat([0.0; 3]).take(zeros))
+			});
+		let (normal_deltas, normal_deltas_future) = ImmutableBuffer::from_iter(normal_deltas, storage, queue.clone())?;
+
+		let family = iter::once(queue.family());
+		let blended_positions = DeviceLocalBuffer::array(device.clone(), vertex_count, vertex_usage, family.clone())?;
+		let blended_normals = DeviceLocalBuffer::array(device.clone(), vertex_count, vertex_usage, family)?;
+
+		let pipeline = render_pass.pipeline_morph.clone();
+		let desc =
+			Arc::new(
+				PersistentDescriptorSet::start(pipeline.clone(), 0)
+					.add_buffer(base_positions)
+					.unwrap()
+					.add_buffer(base_normals)
+					.unwrap()
+					.add_buffer(position_deltas)
+					.unwrap()
+					.add_buffer(normal_deltas)
+					.unwrap()
+					.add_buffer(blended_positions.clone())
+					.unwrap()
+					.add_buffer(blended_normals.clone())
+					.unwrap()
+					.build()
+					.unwrap()
+			);
+
+		// the base and blended vertices, and both offsets for each target
+		let bytes = (vertex_count * size_of::<[f32; 3]>() * (4 + 2 * targets.len())) as u64;
+		let device_ctx = &render_pass.shaders.device_ctx;
+		let memory = device_ctx.memory_stats().track(MemoryCategory::Meshes, "mesh morph targets", bytes);
+
+		Ok((
+			Self {
+				vertex_count: vertex_count,
+				weights: vec![0.0; targets.len()],
+				names: targets.into_iter().map(|target| target.name).collect(),
+				dirty: true,
+				positions: blended_positions,
+				normals: blended_normals,
+				weight_pool: CpuBufferPool::new(device.clone(), storage),
+				weight_desc_pool: FixedSizeDescriptorSetsPool::new(pipeline.clone(), 1),
+				pipeline: pipeline,
+				desc: desc,
+				_memory: memory,
+			},
+			base_positions_future.join(base_normals_future).join(position_deltas_future).join(normal_deltas_future)
+		))
+	}
+
+	pub(super) fn positions(&self) -> &Arc<DeviceLocalBuffer<[[f32; 3]]>> {
+		&self.positions
+	}
+
+	pub(super) fn normals(&self) -> &Arc<DeviceLocalBuffer<[[f32; 3]]>> {
+		&self.normals
+	}
+
+	pub(super) fn names(&self) -> &[String] {
+		&self.names
+	}
+
+	pub(super) fn weights(&self) -> &[f32] {
+		&self.weights
+	}
+
+	pub(super) fn set_weight(&mut self, target: usize, weight: f32) {
+		if self.weights[target] != weight {
+			self.weights[target] = weight;
+			self.dirty = true;
+		}
+	}
+
+	/// Blends the targets into the vertex buffers if the weights have changed. This must be recorded outside of a
+	/// render pass, before the mesh is drawn.
+	pub(super) fn record_blend(
+		&mut self,
+		cmd: AutoCommandBufferBuilder,
+	) -> Result<AutoCommandBufferBuilder, DeviceMemoryAllocError> {
+		if !self.dirty || self.vertex_count == 0 {
+			return Ok(cmd);
+		}
+		self.dirty = false;
+
+		let weights = self.weight_pool.chunk(self.weights.iter().cloned())?;
+		let weight_desc = self.weight_desc_pool.next().add_buffer(weights).unwrap().build().unwrap();
+		let vertex_count = self.vertex_count as u32;
+		Ok(
+			cmd
+				.dispatch(
+					[(vertex_count + MORPH_GROUP_SIZE - 1) / MORPH_GROUP_SIZE, 1, 1],
+					self.pipeline.clone(),
+					(self.desc.clone(), weight_desc),
+					cs_morph::ty::Morph { vertex_count: vertex_count, target_count: self.weights.len() as u32 },
+				)
+				.unwrap()
+		)
+	}
+}
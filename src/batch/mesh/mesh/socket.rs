@@ -0,0 +1,37 @@
+use cgmath::{ One, Quaternion, Vector3, Zero };
+
+/// A named point on a mesh that other things attach to, like "hand_r" for a held weapon or "muzzle" for a muzzle
+/// flash. It's placed relative to the mesh, so whatever follows it moves with the mesh.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Socket {
+	pub name: String,
+	/// In the mesh's space, before the mesh is moved and rotated.
+	pub position: Vector3<f32>,
+	pub rotation: Quaternion<f32>,
+}
+impl Socket {
+	pub fn new(name: impl Into<String>, position: Vector3<f32>, rotation: Quaternion<f32>) -> Self {
+		Self { name: name.into(), position: position, rotation: rotation }
+	}
+
+	/// A socket at the mesh's origin, facing the same way as the mesh.
+	pub fn at_origin(name: impl Into<String>) -> Self {
+		Self::new(name, Vector3::zero(), Quaternion::one())
+	}
+
+	/// Where the socket is once it's carried along by a mesh at the given transform.
+	pub fn transform(&self, mesh_position: Vector3<f32>, mesh_rotation: Quaternion<f32>) -> SocketTransform {
+		SocketTransform {
+			position: mesh_position + mesh_rotation * self.position,
+			rotation: mesh_rotation * self.rotation,
+		}
+	}
+}
+
+/// A socket's position and rotation in world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SocketTransform {
+	pub position: Vector3<f32>,
+	pub rotation: Quaternion<f32>,
+}
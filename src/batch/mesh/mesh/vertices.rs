@@ -0,0 +1,191 @@
+use std::{ f32, sync::Arc };
+use vulkano::{
+	buffer::{ BufferAccess, BufferUsage, ImmutableBuffer },
+	device::{ Device, Queue },
+	format::Format,
+	memory::DeviceMemoryAllocError,
+	sync::GpuFuture,
+};
+
+/// How a mesh's vertices are stored on the GPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VertexFormat {
+	/// 32-bit floats for every attribute.
+	Full,
+	/// Positions as 16-bit normalized values within the mesh's bounds, normals as 10-10-10-2, and texture coordinates as
+	/// half floats. Uses less than half the memory and bandwidth of `Full`, at some cost in precision. Devices that
+	/// can't read 10-10-10-2 vertices get 16-bit normals instead.
+	Compressed,
+}
+impl Default for VertexFormat {
+	fn default() -> Self {
+		VertexFormat::Full
+	}
+}
+
+/// The format of the normals in `VertexFormat::Compressed` vertex buffers. 10-10-10-2 isn't a format every device can
+/// read vertices in, so this falls back to 16-bit normals when it isn't supported.
+pub(super) fn compressed_normal_format(device: &Device) -> Format {
+	let packed = Format::A2B10G10R10SnormPack32;
+	if packed.properties(device.physical_device()).buffer_features.vertex_buffer {
+		packed
+	} else {
+		Format::R16G16B16A16Snorm
+	}
+}
+
+/// The vertex buffers of a mesh, along with the offset and scale that decode its positions.
+pub(super) struct VertexBuffers {
+	pub(super) format: VertexFormat,
+	pub(super) positions: Arc<BufferAccess + Send + Sync>,
+	pub(super) normals: Arc<BufferAccess + Send + Sync>,
+	pub(super) texcoords_main: Arc<BufferAccess + Send + Sync>,
+	pub(super) position_offset: [f32; 3],
+	pub(super) position_scale: [f32; 3],
+}
+impl VertexBuffers {
+	pub(super) fn immutable(
+		queue: Arc<Queue>,
+		format: VertexFormat,
+		positions: Vec<[f32; 3]>,
+		normals: Vec<[f32; 3]>,
+		texcoords_main: Vec<[f32; 2]>,
+	) -> Result<(Self, impl GpuFuture + Send + Sync + 'static), DeviceMemoryAllocError> {
+		let usage = BufferUsage::vertex_buffer();
+
+		match format {
+			VertexFormat::Full => {
+				let (positions, positions_future) = ImmutableBuffer::from_iter(positions.into_iter(), usage, queue.clone())?;
+				let (normals, normals_future) = ImmutableBuffer::from_iter(normals.into_iter(), usage, queue.clone())?;
+				let (texcoords_main, texcoords_main_future) =
+					ImmutableBuffer::from_iter(texcoords_main.into_iter(), usage, queue)?;
+
+				Ok((
+					Self::full(positions, normals, texcoords_main),
+					positions_future.join(normals_future).join(texcoords_main_future)
+				))
+			},
+			VertexFormat::Compressed => {
+				let (position_offset, position_scale) = bounds(&positions);
+
+				let (positions, positions_future) =
+					ImmutableBuffer::from_iter(
+						positions.into_iter().map(|position| quantize_position(position, position_offset, position_scale)),
+						usage,
+						queue.clone()
+					)?;
+				let (normals, normals_future) =
+					if compressed_normal_format(queue.device()) == Format::A2B10G10R10SnormPack32 {
+						let (normals, future) =
+							ImmutableBuffer::from_iter(normals.into_iter().map(pack_normal), usage, queue.clone())?;
+						(normals as Arc<BufferAccess + Send + Sync>, future)
+					} else {
+						let (normals, future) =
+							ImmutableBuffer::from_iter(normals.into_iter().map(snorm16_normal), usage, queue.clone())?;
+						(normals as Arc<BufferAccess + Send + Sync>, future)
+					};
+				let (texcoords_main, texcoords_main_future) =
+					ImmutableBuffer::from_iter(
+						texcoords_main.into_iter().map(|texcoord| [f32_to_f16(texcoord[0]), f32_to_f16(texcoord[1])]),
+						usage,
+						queue
+					)?;
+
+				Ok((
+					Self {
+						format: format,
+						positions: positions,
+						normals: normals,
+						texcoords_main: texcoords_main,
+						position_offset: position_offset,
+						position_scale: position_scale,
+					},
+					positions_future.join(normals_future).join(texcoords_main_future)
+				))
+			},
+		}
+	}
+
+	/// Wraps uncompressed buffers, which need no decoding.
+	pub(super) fn full(
+		positions: Arc<BufferAccess + Send + Sync>,
+		normals: Arc<BufferAccess + Send + Sync>,
+		texcoords_main: Arc<BufferAccess + Send + Sync>,
+	) -> Self {
+		Self {
+			format: VertexFormat::Full,
+			positions: positions,
+			normals: normals,
+			texcoords_main: texcoords_main,
+			position_offset: [0.0, 0.0, 0.0],
+			position_scale: [1.0, 1.0, 1.0],
+		}
+	}
+}
+
+/// Returns the minimum corner and the size of the box around the positions. Flat axes get a size of 1 so they can still
+/// be divided by.
+fn bounds(positions: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
+	let mut min = [f32::INFINITY; 3];
+	let mut max = [f32::NEG_INFINITY; 3];
+	for position in positions {
+		for axis in 0..3 {
+			min[axis] = min[axis].min(position[axis]);
+			max[axis] = max[axis].max(position[axis]);
+		}
+	}
+
+	let mut offset = [0.0; 3];
+	let mut scale = [1.0; 3];
+	for axis in 0..3 {
+		if min[axis] <= max[axis] {
+			offset[axis] = min[axis];
+			if max[axis] > min[axis] {
+				scale[axis] = max[axis] - min[axis];
+			}
+		}
+	}
+	(offset, scale)
+}
+
+/// Encodes a position as R16G16B16A16_UNORM. The fourth component is padding.
+fn quantize_position(position: [f32; 3], offset: [f32; 3], scale: [f32; 3]) -> [u16; 4] {
+	let mut out = [0; 4];
+	for axis in 0..3 {
+		let normalized = ((position[axis] - offset[axis]) / scale[axis]).max(0.0).min(1.0);
+		out[axis] = (normalized * 65535.0).round() as u16;
+	}
+	out
+}
+
+/// Encodes a normal as A2B10G10R10_SNORM_PACK32.
+fn pack_normal(normal: [f32; 3]) -> u32 {
+	let component = |value: f32| ((value.max(-1.0).min(1.0) * 511.0).round() as i32 as u32) & 0x3ff;
+	component(normal[0]) | component(normal[1]) << 10 | component(normal[2]) << 20
+}
+
+/// Encodes a normal as R16G16B16A16_SNORM, for devices without 10-10-10-2 vertices. The fourth component is padding.
+fn snorm16_normal(normal: [f32; 3]) -> [i16; 4] {
+	let component = |value: f32| (value.max(-1.0).min(1.0) * 32767.0).round() as i16;
+	[component(normal[0]), component(normal[1]), component(normal[2]), 0]
+}
+
+/// Converts to an IEEE half float, rounding to nearest. Values too large for a half become infinity.
+fn f32_to_f16(value: f32) -> u16 {
+	let bits = value.to_bits();
+	let sign = ((bits >> 16) & 0x8000) as u16;
+	let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+	let mantissa = bits & 0x7f_ffff;
+
+	if exponent >= 0x1f {
+		sign | 0x7c00
+	} else if exponent <= 0 {
+		if exponent < -10 {
+			sign
+		} else {
+			sign | ((mantissa | 0x80_0000) >> (14 - exponent)) as u16
+		}
+	} else {
+		sign | ((((exponent as u32) << 10) | (mantissa >> 13)) + ((mantissa >> 12) & 1)) as u16
+	}
+}
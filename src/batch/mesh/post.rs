@@ -0,0 +1,102 @@
+/// The built-in screen space effects a `MeshBatch` applies after lighting. They're applied in a fixed order: motion
+/// blur, chromatic aberration, depth of field, bloom, vignette, color grading, film grain, display calibration, and
+/// finally outlines. Motion blur and depth of field are set on each `Camera`, color grading and outlines have setters
+/// on the batch, and display calibration is set on the `MeshRenderPass`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct PostProcessChain {
+	pub vignette: Option<Vignette>,
+	pub film_grain: Option<FilmGrain>,
+	pub chromatic_aberration: Option<ChromaticAberration>,
+	pub bloom: Option<Bloom>,
+}
+
+/// Darkens the edges of the viewport.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Vignette {
+	/// How dark the corners get, from 0.0 to 1.0.
+	pub intensity: f32,
+	/// How far toward the center the darkening fades in, from 0.0 for a hard edge at the corners to 1.0.
+	pub smoothness: f32,
+}
+impl Default for Vignette {
+	fn default() -> Self {
+		Self { intensity: 0.4, smoothness: 0.6 }
+	}
+}
+
+/// Adds noise that changes every frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct FilmGrain {
+	/// The largest change to each channel, in sRGB encoded values from 0.0 to 1.0.
+	pub intensity: f32,
+}
+impl Default for FilmGrain {
+	fn default() -> Self {
+		Self { intensity: 0.04 }
+	}
+}
+
+/// Splits red and blue apart toward the edges of the viewport.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChromaticAberration {
+	/// How far red and blue are moved at the edges of the viewport, in pixels.
+	pub strength: f32,
+}
+impl Default for ChromaticAberration {
+	fn default() -> Self {
+		Self { strength: 2.0 }
+	}
+}
+
+/// Spreads light from the brightest parts of the image over their surroundings, so emissive surfaces and strong
+/// highlights glow.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bloom {
+	/// How bright a pixel must be to glow, from 0.0 to 1.0, after tonemapping. Only the brightness above it spreads.
+	pub threshold: f32,
+	/// How much of the glow is added back to the image.
+	pub intensity: f32,
+}
+impl Default for Bloom {
+	fn default() -> Self {
+		Self { threshold: 0.8, intensity: 0.6 }
+	}
+}
+
+/// Adjusts the final image for the player's display, for a brightness setting or calibration screen. It's applied to
+/// sRGB encoded colors after tonemapping and color grading, and before outlines. Sprite batches aren't affected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisplayCalibration {
+	/// Added to every channel, from -1.0 to 1.0. Raising it lifts shadows toward gray.
+	pub brightness: f32,
+	/// Scales each channel's distance from middle gray. 1.0 leaves colors unchanged.
+	pub contrast: f32,
+	/// Values above 1.0 brighten dark and middle tones without changing black or white.
+	pub gamma: f32,
+}
+impl DisplayCalibration {
+	/// Applies the calibration to an sRGB encoded color, the same way the renderer does. A calibration screen can use
+	/// this to draw its reference images with sprites, which aren't calibrated, as they'll look in the game.
+	pub fn apply(&self, color: [f32; 3]) -> [f32; 3] {
+		let channel = |value: f32| {
+			let value = ((value - 0.5) * self.contrast + 0.5 + self.brightness).max(0.0).min(1.0);
+			value.powf(1.0 / self.gamma.max(0.01))
+		};
+		[channel(color[0]), channel(color[1]), channel(color[2])]
+	}
+
+	pub(super) fn is_identity(&self) -> bool {
+		*self == Self::default()
+	}
+}
+impl Default for DisplayCalibration {
+	fn default() -> Self {
+		Self { brightness: 0.0, contrast: 1.0, gamma: 1.0 }
+	}
+}
@@ -0,0 +1,64 @@
+use super::light::SHADOW_ATLAS_SIZE;
+
+/// Options that trade image quality for speed, usually chosen in an options menu. Apply them with
+/// `MeshRenderPass::set_quality`, and every batch using the render pass picks them up on its next frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct QualitySettings {
+	/// The width and height of each batch's shadow atlas, which every shadowed light's shadow maps share. Smaller
+	/// atlases fit fewer shadows, at lower resolutions. 0 turns shadows off. `MeshRenderPass::set_quality` clamps it
+	/// to the device's largest 2D image size, so Ultra may fall back to a smaller atlas.
+	pub shadow_resolution: u32,
+	/// Added to the mip level material textures are sampled at. Positive values blur them, but read less memory. It
+	/// only affects textures with mipmaps.
+	pub texture_lod_bias: f32,
+	/// Maximum anisotropy for material textures. 1.0 disables anisotropic filtering.
+	pub anisotropy: f32,
+	/// Whether batches draw their `PostProcessChain`, depth of field, and motion blur. Color grading, display
+	/// calibration, and outlines are always drawn.
+	pub post_effects: bool,
+}
+impl QualitySettings {
+	pub fn preset(preset: QualityPreset) -> Self {
+		match preset {
+			QualityPreset::Low => Self {
+				shadow_resolution: 1024,
+				texture_lod_bias: 1.0,
+				anisotropy: 1.0,
+				post_effects: false,
+			},
+			QualityPreset::Medium => Self {
+				shadow_resolution: 2048,
+				texture_lod_bias: 0.0,
+				anisotropy: 4.0,
+				post_effects: true,
+			},
+			QualityPreset::High => Self {
+				shadow_resolution: SHADOW_ATLAS_SIZE,
+				texture_lod_bias: 0.0,
+				anisotropy: 8.0,
+				post_effects: true,
+			},
+			QualityPreset::Ultra => Self {
+				shadow_resolution: 8192,
+				texture_lod_bias: 0.0,
+				anisotropy: 16.0,
+				post_effects: true,
+			},
+		}
+	}
+}
+impl Default for QualitySettings {
+	fn default() -> Self {
+		Self::preset(QualityPreset::High)
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum QualityPreset {
+	Low,
+	Medium,
+	High,
+	Ultra,
+}
@@ -1,21 +1,143 @@
-use crate::batch::mesh::{ ALBEDO_FORMAT, NORMAL_FORMAT, DEPTH_FORMAT, MeshShaders, TargetVertex, mesh::MeshVertexDefinition };
-use std::sync::Arc;
+use crate::batch::mesh::{
+	ALBEDO_FORMAT,
+	NORMAL_FORMAT,
+	VELOCITY_FORMAT,
+	SELECTION_FORMAT,
+	EMISSIVE_FORMAT,
+	DEPTH_FORMAT,
+	REVERSE_Z_DEPTH_FORMAT,
+	SHADOW_FORMAT,
+	MeshShaders,
+	TargetVertex,
+	Topology,
+	DisplayCalibration,
+	InternalResolution,
+	QualitySettings,
+	UpscaleFilter,
+	VertexFormat,
+	mesh::MeshVertexDefinition,
+	material_shader::MaterialShader,
+	shaders::{ fs_forward, fs_target },
+	water::WaterVertex,
+};
+use crate::ObjectId;
+use crate::batch::{ StencilCache, StencilState, stencil_format };
+use crate::texture::{ SamplerSettings, is_srgb };
+use std::sync::{ Arc, Mutex, atomic::{ AtomicBool, AtomicUsize, Ordering } };
 use vulkano::{
 	ordered_passes_renderpass,
+	single_pass_renderpass,
+	device::Device,
 	format::Format,
 	framebuffer::{ RenderPassAbstract, Subpass },
-	pipeline::{ GraphicsPipeline, GraphicsPipelineAbstract },
+	pipeline::{
+		ComputePipeline,
+		ComputePipelineAbstract,
+		GraphicsPipeline,
+		GraphicsPipelineAbstract,
+		GraphicsPipelineCreationError,
+		blend::AttachmentBlend,
+		depth_stencil::{ Compare, DepthStencil, StencilOp },
+	},
+	sampler::{ Sampler, SamplerCreationError },
 };
 
+/// The render passes and pipelines used by every mesh batch for a target format. It's `Sync`, so it can be used by
+/// batches on different threads.
 pub struct MeshRenderPass {
 	pub(super) shaders: Arc<MeshShaders>,
 	pub(super) subpass_gbuffers: Subpass<Arc<RenderPassAbstract + Send + Sync>>,
 	pub(super) pipeline_gbuffers: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	pipelines_gbuffers: GBufferPipelines,
+	pipelines_depth: GBufferPipelines,
+	pipelines_gbuffers_after_depth: GBufferPipelines,
+	pipelines_shadow: GBufferPipelines,
+	/// The pipelines for each stage that draw mesh groups, whose transforms are read from a storage buffer.
+	pipelines_indirect: [GBufferPipelines; 4],
+	/// The pipelines for each stage that draw foliage, with an instance buffer after the mesh's vertex buffers.
+	pipelines_foliage: [GBufferPipelines; 4],
+	/// Pipelines for custom material shaders, created the first time a material uses the shader.
+	pipelines_custom: Mutex<Vec<Arc<MaterialShaderPipelines>>>,
+	/// Pipelines for stenciled meshes, created the first time a stencil state is drawn in a stage.
+	pipelines_stencil: Mutex<StencilCache<(GBufferStage, StencilState), GBufferPipelines>>,
+	/// The render pass and pipelines for `ForwardMeshBatch`, created the first time one is made.
+	forward: Mutex<Option<Arc<ForwardPipelines>>>,
+	format: Format,
+	depth_prepass: AtomicBool,
+	reverse_z: bool,
+	stencil: bool,
+	share_transient_attachments: bool,
+	internal_resolution: Mutex<InternalResolution>,
+	upscale_filter: Mutex<UpscaleFilter>,
+	display_calibration: Mutex<DisplayCalibration>,
 	pub(super) pipeline_history: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	/// Draws water over the history image, after the history pass.
+	pub(super) pipeline_water: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	/// Lists the lights that reach each cluster of a camera's view, for the history pass.
+	pub(super) pipeline_light_cull: Arc<ComputePipelineAbstract + Send + Sync + 'static>,
+	/// Lists the draws of each mesh group's objects that a camera can see.
+	pub(super) pipeline_draw_cull: Arc<ComputePipelineAbstract + Send + Sync + 'static>,
+	/// Blends the morph targets of meshes that have them into their vertex buffers.
+	pub(super) pipeline_morph: Arc<ComputePipelineAbstract + Send + Sync + 'static>,
+	/// Renders to an intermediate image, for the passes between the history pass and the target pass.
+	pub(super) render_pass_post: Arc<RenderPassAbstract + Send + Sync>,
+	pub(super) render_pass_target: Arc<RenderPassAbstract + Send + Sync>,
+	/// Renders the depth of shadowed lights' surroundings into a batch's shadow atlas.
+	pub(super) render_pass_shadow: Arc<RenderPassAbstract + Send + Sync>,
+	pub(super) pipeline_motion_blur: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	/// Blurs the history image horizontally for depth of field. The target pass blurs it vertically.
+	pub(super) pipeline_dof: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	/// Picks out the bright parts of the history image and blurs them horizontally. The target pass blurs them
+	/// vertically and adds them back.
+	pub(super) pipeline_bloom: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
 	pub(super) pipeline_target: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	/// Used for material textures, unless a material overrides it.
+	sampler: Mutex<Arc<Sampler>>,
+	/// Counts the times `sampler` has been replaced, so materials know to rebind it.
+	sampler_generation: AtomicUsize,
+	/// The settings `sampler` was created with, before quality settings are applied.
+	sampler_settings: SamplerSettings,
+	quality: Mutex<QualitySettings>,
 }
 impl MeshRenderPass {
 	pub fn new(shaders: Arc<MeshShaders>, format: Format) -> Arc<Self> {
+		let sampler = shaders.sampler.clone();
+		Self::with_sampler(shaders, format, sampler)
+	}
+
+	/// Like `new`, but material textures are sampled with the given settings.
+	pub fn with_sampler_settings(
+		shaders: Arc<MeshShaders>,
+		format: Format,
+		settings: &SamplerSettings,
+	) -> Result<Arc<Self>, SamplerCreationError> {
+		Self::with_options(shaders, format, &MeshRenderPassOptions { sampler: *settings, .. Default::default() })
+	}
+
+	pub fn with_options(
+		shaders: Arc<MeshShaders>,
+		format: Format,
+		options: &MeshRenderPassOptions,
+	) -> Result<Arc<Self>, SamplerCreationError> {
+		let sampler = options.sampler.build(shaders.queue.device())?;
+		Ok(Self::build(shaders, format, sampler, options))
+	}
+
+	fn with_sampler(shaders: Arc<MeshShaders>, format: Format, sampler: Arc<Sampler>) -> Arc<Self> {
+		Self::build(shaders, format, sampler, &MeshRenderPassOptions::default())
+	}
+
+	fn build(
+		shaders: Arc<MeshShaders>,
+		format: Format,
+		sampler: Arc<Sampler>,
+		options: &MeshRenderPassOptions,
+	) -> Arc<Self> {
+		let reverse_z = options.reverse_z;
+		let depth_format = depth_format(shaders.queue.device(), reverse_z, options.stencil);
+		let depth_test = depth_test(reverse_z);
+		let depth_test_after_prepass = depth_test_after_prepass(reverse_z);
+
 		let render_pass: Arc<RenderPassAbstract + Send + Sync> =
 			Arc::new(
 				ordered_passes_renderpass!(
@@ -23,44 +145,334 @@ impl MeshRenderPass {
 					attachments: {
 						albedo: { load: Clear, store: Store, format: ALBEDO_FORMAT, samples: 1, },
 						normal: { load: Clear, store: Store, format: NORMAL_FORMAT, samples: 1, },
-						depth: { load: Clear, store: Store, format: DEPTH_FORMAT, samples: 1, },
-						history: { load: DontCare, store: Store, format: format, samples: 1, },
-						out: { load: DontCare, store: Store, format: format, samples: 1, }
+						velocity: { load: Clear, store: Store, format: VELOCITY_FORMAT, samples: 1, },
+						selection: { load: Clear, store: Store, format: SELECTION_FORMAT, samples: 1, },
+						emissive: { load: Clear, store: Store, format: EMISSIVE_FORMAT, samples: 1, },
+						depth: { load: Clear, store: Store, format: depth_format, samples: 1, },
+						history: { load: DontCare, store: Store, format: format, samples: 1, }
 					},
 					passes: [
-						{ color: [albedo, normal], depth_stencil: {depth}, input: [] },
-						{ color: [history], depth_stencil: {}, input: [albedo, normal, depth] },
-						{ color: [out], depth_stencil: {}, input: [history] }
+						{ color: [albedo, normal, velocity, selection, emissive], depth_stencil: {depth}, input: [] },
+						{ color: [history], depth_stencil: {}, input: [albedo, normal, depth, emissive] },
+						{ color: [history], depth_stencil: {}, input: [depth] }
 					]
 				)
 				.unwrap()
 			);
 
+		// post processing samples neighboring pixels of the history image, so it needs render passes of its own
+		let render_pass_post: Arc<RenderPassAbstract + Send + Sync> =
+			Arc::new(
+				single_pass_renderpass!(
+					shaders.target_vertices.device().clone(),
+					attachments: { post: { load: DontCare, store: Store, format: format, samples: 1, } },
+					pass: { color: [post], depth_stencil: {} }
+				)
+				.unwrap()
+			);
+		let render_pass_target: Arc<RenderPassAbstract + Send + Sync> =
+			Arc::new(
+				single_pass_renderpass!(
+					shaders.target_vertices.device().clone(),
+					attachments: { out: { load: Load, store: Store, format: format, samples: 1, } },
+					pass: { color: [out], depth_stencil: {} }
+				)
+				.unwrap()
+			);
+
+		let render_pass_shadow: Arc<RenderPassAbstract + Send + Sync> =
+			Arc::new(
+				single_pass_renderpass!(
+					shaders.target_vertices.device().clone(),
+					attachments: { depth: { load: Clear, store: Store, format: SHADOW_FORMAT, samples: 1, } },
+					pass: { color: [], depth_stencil: {depth} }
+				)
+				.unwrap()
+			);
+
 		let subpass_gbuffers = Subpass::from(render_pass.clone(), 0).unwrap();
 
-		let pipeline_gbuffers =
+		let make_pipeline_gbuffers = |stage, topology, vertex_format| -> GBufferPipeline {
+			let builder =
+				GraphicsPipeline::start()
+					.vertex_input(MeshVertexDefinition::new(shaders.target_vertices.device(), vertex_format))
+					.vertex_shader(shaders.shader_gbuffers_vertex.main_entry_point(), ());
+			let builder =
+				match topology {
+					Topology::TriangleList => builder.triangle_list(),
+					Topology::TriangleStrip => builder.triangle_strip().primitive_restart(true),
+				};
+			let builder = builder.viewports_scissors_dynamic(1).render_pass(subpass_gbuffers.clone());
+			let device = shaders.target_vertices.device().clone();
+
+			match stage {
+				GBufferStage::GBuffers =>
+					Arc::new(
+						builder
+							.fragment_shader(shaders.shader_gbuffers_fragment.main_entry_point(), ())
+							.depth_stencil(depth_test.clone())
+							.build(device)
+							.expect("failed to create pipeline")
+					),
+				GBufferStage::DepthPrepass =>
+					Arc::new(
+						builder
+							.fragment_shader(shaders.shader_depth_fragment.main_entry_point(), ())
+							.depth_stencil(depth_test.clone())
+							.blend_collective(AttachmentBlend {
+								mask_red: false,
+								mask_green: false,
+								mask_blue: false,
+								mask_alpha: false,
+								.. AttachmentBlend::pass_through()
+							})
+							.build(device)
+							.expect("failed to create pipeline")
+					),
+				GBufferStage::GBuffersAfterPrepass =>
+					Arc::new(
+						builder
+							.fragment_shader(shaders.shader_gbuffers_fragment.main_entry_point(), ())
+							.depth_stencil(depth_test_after_prepass.clone())
+							.build(device)
+							.expect("failed to create pipeline")
+					),
+				GBufferStage::Shadow =>
+					Arc::new(
+						builder
+							.fragment_shader(shaders.shader_depth_fragment.main_entry_point(), ())
+							.depth_stencil(depth_test(false))
+							.render_pass(Subpass::from(render_pass_shadow.clone(), 0).unwrap())
+							.build(device)
+							.expect("failed to create pipeline")
+					),
+			}
+		};
+
+		// like make_pipeline_gbuffers, but with another vertex shader. it's a macro since each shader's entry point is
+		// its own type.
+		macro_rules! make_pipeline_with {
+			($vertex_input:expr, $vertex_shader:expr, $stage:expr, $topology:expr) => {{
+				let builder =
+					GraphicsPipeline::start()
+						.vertex_input($vertex_input)
+						.vertex_shader($vertex_shader.main_entry_point(), ());
+				let builder =
+					match $topology {
+						Topology::TriangleList => builder.triangle_list(),
+						Topology::TriangleStrip => builder.triangle_strip().primitive_restart(true),
+					};
+				let builder = builder.viewports_scissors_dynamic(1).render_pass(subpass_gbuffers.clone());
+				let device = shaders.target_vertices.device().clone();
+
+				let pipeline: GBufferPipeline =
+					match $stage {
+						GBufferStage::GBuffers =>
+							Arc::new(
+								builder
+									.fragment_shader(shaders.shader_gbuffers_fragment.main_entry_point(), ())
+									.depth_stencil(depth_test.clone())
+									.build(device)
+									.expect("failed to create pipeline")
+							),
+						GBufferStage::DepthPrepass =>
+							Arc::new(
+								builder
+									.fragment_shader(shaders.shader_depth_fragment.main_entry_point(), ())
+									.depth_stencil(depth_test.clone())
+									.blend_collective(AttachmentBlend {
+										mask_red: false,
+										mask_green: false,
+										mask_blue: false,
+										mask_alpha: false,
+										.. AttachmentBlend::pass_through()
+									})
+									.build(device)
+									.expect("failed to create pipeline")
+							),
+						GBufferStage::GBuffersAfterPrepass =>
+							Arc::new(
+								builder
+									.fragment_shader(shaders.shader_gbuffers_fragment.main_entry_point(), ())
+									.depth_stencil(depth_test_after_prepass.clone())
+									.build(device)
+									.expect("failed to create pipeline")
+							),
+						GBufferStage::Shadow =>
+							Arc::new(
+								builder
+									.fragment_shader(shaders.shader_depth_fragment.main_entry_point(), ())
+									.depth_stencil(depth_test(false))
+									.render_pass(Subpass::from(render_pass_shadow.clone(), 0).unwrap())
+									.build(device)
+									.expect("failed to create pipeline")
+							),
+					};
+				pipeline
+			}};
+		}
+		let make_pipeline_indirect = |stage, topology, vertex_format| {
+			make_pipeline_with!(
+				MeshVertexDefinition::new(shaders.target_vertices.device(), vertex_format),
+				shaders.shader_gbuffers_indirect_vertex,
+				stage,
+				topology
+			)
+		};
+		// instances are read from a vertex buffer, see foliage::FoliageInstance
+		let make_pipeline_foliage = |stage, topology, vertex_format| {
+			make_pipeline_with!(
+				MeshVertexDefinition::instanced(shaders.target_vertices.device(), vertex_format),
+				shaders.shader_foliage_vertex,
+				stage,
+				topology
+			)
+		};
+
+		let debug_names = shaders.device_ctx.debug_names();
+		let pipelines_gbuffers = GBufferPipelines::new(|topology, format| {
+			let pipeline = make_pipeline_gbuffers(GBufferStage::GBuffers, topology, format);
+			debug_names.name_pipeline(&*pipeline, &format!("mesh gbuffers ({:?}, {:?})", topology, format));
+			pipeline
+		});
+		let pipelines_depth = GBufferPipelines::new(|topology, format| {
+			let pipeline = make_pipeline_gbuffers(GBufferStage::DepthPrepass, topology, format);
+			debug_names.name_pipeline(&*pipeline, &format!("mesh depth prepass ({:?}, {:?})", topology, format));
+			pipeline
+		});
+		let pipelines_gbuffers_after_depth = GBufferPipelines::new(|topology, format| {
+			let pipeline = make_pipeline_gbuffers(GBufferStage::GBuffersAfterPrepass, topology, format);
+			debug_names
+				.name_pipeline(&*pipeline, &format!("mesh gbuffers after prepass ({:?}, {:?})", topology, format));
+			pipeline
+		});
+		let pipelines_shadow = GBufferPipelines::new(|topology, format| {
+			let pipeline = make_pipeline_gbuffers(GBufferStage::Shadow, topology, format);
+			debug_names.name_pipeline(&*pipeline, &format!("mesh shadow ({:?}, {:?})", topology, format));
+			pipeline
+		});
+
+		let make_pipelines_indirect = |stage| {
+			GBufferPipelines::new(|topology, format| {
+				let pipeline = make_pipeline_indirect(stage, topology, format);
+				debug_names
+					.name_pipeline(&*pipeline, &format!("mesh group {:?} ({:?}, {:?})", stage, topology, format));
+				pipeline
+			})
+		};
+		// in the order of GBufferStage, so a stage is its index
+		let pipelines_indirect = [
+			make_pipelines_indirect(GBufferStage::GBuffers),
+			make_pipelines_indirect(GBufferStage::DepthPrepass),
+			make_pipelines_indirect(GBufferStage::GBuffersAfterPrepass),
+			make_pipelines_indirect(GBufferStage::Shadow),
+		];
+
+		let make_pipelines_foliage = |stage| {
+			GBufferPipelines::new(|topology, format| {
+				let pipeline = make_pipeline_foliage(stage, topology, format);
+				debug_names
+					.name_pipeline(&*pipeline, &format!("mesh foliage {:?} ({:?}, {:?})", stage, topology, format));
+				pipeline
+			})
+		};
+		let pipelines_foliage = [
+			make_pipelines_foliage(GBufferStage::GBuffers),
+			make_pipelines_foliage(GBufferStage::DepthPrepass),
+			make_pipelines_foliage(GBufferStage::GBuffersAfterPrepass),
+			make_pipelines_foliage(GBufferStage::Shadow),
+		];
+
+		let pipeline_history =
 			Arc::new(
 				GraphicsPipeline::start()
-					.vertex_input(MeshVertexDefinition::new())
-					.vertex_shader(shaders.shader_gbuffers_vertex.main_entry_point(), ())
+					.vertex_input_single_buffer::<TargetVertex>()
+					.vertex_shader(shaders.shader_history_vertex.main_entry_point(), ())
 					.triangle_list()
-					.viewports_dynamic_scissors_irrelevant(1)
-					.fragment_shader(shaders.shader_gbuffers_fragment.main_entry_point(), ())
-					.render_pass(subpass_gbuffers.clone())
-					.depth_stencil_simple_depth()
+					.viewports_scissors_dynamic(1)
+					.fragment_shader(shaders.shader_history_fragment.main_entry_point(), ())
+					.render_pass(Subpass::from(render_pass.clone(), 1).unwrap())
 					.build(shaders.target_vertices.device().clone())
 					.expect("failed to create pipeline")
 			);
 
-		let pipeline_history =
+		// water is blended over the lit scene, and tests itself against the depth buffer since it reads it
+		let pipeline_water =
+			Arc::new(
+				GraphicsPipeline::start()
+					.vertex_input_single_buffer::<WaterVertex>()
+					.vertex_shader(shaders.shader_water_vertex.main_entry_point(), ())
+					.triangle_list()
+					.viewports_scissors_dynamic(1)
+					.fragment_shader(shaders.shader_water_fragment.main_entry_point(), ())
+					.blend_collective(AttachmentBlend { mask_alpha: false, .. AttachmentBlend::alpha_blending() })
+					.render_pass(Subpass::from(render_pass.clone(), 2).unwrap())
+					.build(shaders.target_vertices.device().clone())
+					.expect("failed to create pipeline")
+			);
+
+		let pipeline_light_cull =
+			Arc::new(
+				ComputePipeline::new(
+					shaders.target_vertices.device().clone(),
+					&shaders.shader_light_cull_compute.main_entry_point(),
+					&()
+				).expect("failed to create pipeline")
+			);
+
+		let pipeline_draw_cull =
+			Arc::new(
+				ComputePipeline::new(
+					shaders.target_vertices.device().clone(),
+					&shaders.shader_draw_cull_compute.main_entry_point(),
+					&()
+				).expect("failed to create pipeline")
+			);
+
+		let pipeline_morph =
+			Arc::new(
+				ComputePipeline::new(
+					shaders.target_vertices.device().clone(),
+					&shaders.shader_morph_compute.main_entry_point(),
+					&()
+				).expect("failed to create pipeline")
+			);
+
+		let pipeline_motion_blur =
 			Arc::new(
 				GraphicsPipeline::start()
 					.vertex_input_single_buffer::<TargetVertex>()
-					.vertex_shader(shaders.shader_history_vertex.main_entry_point(), ())
+					.vertex_shader(shaders.shader_target_vertex.main_entry_point(), ())
 					.triangle_list()
-					.viewports_dynamic_scissors_irrelevant(1)
-					.fragment_shader(shaders.shader_history_fragment.main_entry_point(), ())
-					.render_pass(Subpass::from(render_pass.clone(), 1).unwrap())
+					.viewports_scissors_dynamic(1)
+					.fragment_shader(shaders.shader_motion_blur_fragment.main_entry_point(), ())
+					.render_pass(Subpass::from(render_pass_post.clone(), 0).unwrap())
+					.build(shaders.target_vertices.device().clone())
+					.expect("failed to create pipeline")
+			);
+
+		let pipeline_dof =
+			Arc::new(
+				GraphicsPipeline::start()
+					.vertex_input_single_buffer::<TargetVertex>()
+					.vertex_shader(shaders.shader_target_vertex.main_entry_point(), ())
+					.triangle_list()
+					.viewports_scissors_dynamic(1)
+					.fragment_shader(shaders.shader_dof_fragment.main_entry_point(), ())
+					.render_pass(Subpass::from(render_pass_post.clone(), 0).unwrap())
+					.build(shaders.target_vertices.device().clone())
+					.expect("failed to create pipeline")
+			);
+
+		let pipeline_bloom =
+			Arc::new(
+				GraphicsPipeline::start()
+					.vertex_input_single_buffer::<TargetVertex>()
+					.vertex_shader(shaders.shader_target_vertex.main_entry_point(), ())
+					.triangle_list()
+					.viewports_scissors_dynamic(1)
+					.fragment_shader(shaders.shader_bloom_fragment.main_entry_point(), ())
+					.render_pass(Subpass::from(render_pass_post.clone(), 0).unwrap())
 					.build(shaders.target_vertices.device().clone())
 					.expect("failed to create pipeline")
 			);
@@ -71,23 +483,517 @@ impl MeshRenderPass {
 					.vertex_input_single_buffer::<TargetVertex>()
 					.vertex_shader(shaders.shader_target_vertex.main_entry_point(), ())
 					.triangle_list()
-					.viewports_dynamic_scissors_irrelevant(1)
-					.fragment_shader(shaders.shader_target_fragment.main_entry_point(), ())
-					.render_pass(Subpass::from(render_pass, 2).unwrap())
+					.viewports_scissors_dynamic(1)
+					.fragment_shader(
+						shaders.shader_target_fragment.main_entry_point(),
+						fs_target::SpecializationConstants { ENCODE_SRGB: !is_srgb(format) as u32 }
+					)
+					.render_pass(Subpass::from(render_pass_target.clone(), 0).unwrap())
 					.build(shaders.target_vertices.device().clone())
 					.expect("failed to create pipeline")
 			);
 
+		debug_names.name_pipeline(&*pipeline_history, "mesh history");
+		debug_names.name_pipeline(&*pipeline_water, "mesh water");
+		debug_names.name_compute_pipeline(&*pipeline_light_cull, "mesh light culling");
+		debug_names.name_compute_pipeline(&*pipeline_draw_cull, "mesh group culling");
+		debug_names.name_compute_pipeline(&*pipeline_morph, "mesh morph targets");
+		debug_names.name_pipeline(&*pipeline_motion_blur, "mesh motion blur");
+		debug_names.name_pipeline(&*pipeline_dof, "mesh depth of field");
+		debug_names.name_pipeline(&*pipeline_bloom, "mesh bloom");
+		debug_names.name_pipeline(&*pipeline_target, "mesh target");
+
 		Arc::new(Self {
 			shaders: shaders,
 			subpass_gbuffers: subpass_gbuffers,
-			pipeline_gbuffers: pipeline_gbuffers,
+			pipeline_gbuffers: pipelines_gbuffers.get(Topology::TriangleList, VertexFormat::Full).clone(),
+			pipelines_gbuffers: pipelines_gbuffers,
+			pipelines_depth: pipelines_depth,
+			pipelines_gbuffers_after_depth: pipelines_gbuffers_after_depth,
+			pipelines_shadow: pipelines_shadow,
+			pipelines_indirect: pipelines_indirect,
+			pipelines_foliage: pipelines_foliage,
+			pipelines_custom: Mutex::new(vec![]),
+			pipelines_stencil: Mutex::new(StencilCache::new()),
+			forward: Mutex::new(None),
+			format: format,
+			depth_prepass: AtomicBool::new(false),
+			reverse_z: reverse_z,
+			stencil: options.stencil,
+			share_transient_attachments: options.share_transient_attachments,
+			internal_resolution: Mutex::new(options.internal_resolution),
+			upscale_filter: Mutex::new(options.upscale_filter),
+			display_calibration: Mutex::new(DisplayCalibration::default()),
 			pipeline_history: pipeline_history,
+			pipeline_water: pipeline_water,
+			pipeline_light_cull: pipeline_light_cull,
+			pipeline_draw_cull: pipeline_draw_cull,
+			pipeline_morph: pipeline_morph,
+			render_pass_post: render_pass_post,
+			render_pass_target: render_pass_target,
+			render_pass_shadow: render_pass_shadow,
+			pipeline_motion_blur: pipeline_motion_blur,
+			pipeline_dof: pipeline_dof,
+			pipeline_bloom: pipeline_bloom,
 			pipeline_target: pipeline_target,
+			sampler: Mutex::new(sampler),
+			sampler_generation: AtomicUsize::new(0),
+			sampler_settings: options.sampler,
+			quality: Mutex::new(QualitySettings {
+				texture_lod_bias: options.sampler.lod_bias,
+				anisotropy: options.sampler.anisotropy,
+				.. QualitySettings::default()
+			}),
 		})
 	}
 
+	/// Enables or disables a depth-only pass before the gbuffer pass, for every batch using this render pass. With it
+	/// enabled, meshes are drawn twice, but the gbuffer fragment shader only runs once per pixel. This pays off when
+	/// meshes overlap a lot or materials are expensive.
+	pub fn set_depth_prepass(&self, enabled: bool) {
+		self.depth_prepass.store(enabled, Ordering::Relaxed);
+	}
+
+	pub fn depth_prepass(&self) -> bool {
+		self.depth_prepass.load(Ordering::Relaxed)
+	}
+
+	/// Whether depth is stored with 1.0 at the near plane and 0.0 at the far plane. Cameras used with this render pass
+	/// must have a matching setting.
+	pub fn reverse_z(&self) -> bool {
+		self.reverse_z
+	}
+
+	/// Whether the depth buffer has a stencil buffer, so meshes can be drawn with `Mesh::set_stencil`.
+	pub fn has_stencil(&self) -> bool {
+		self.stencil
+	}
+
+	/// Whether batches using this render pass share their intermediate images with other batches on the device. See
+	/// `MeshRenderPassOptions::share_transient_attachments`.
+	pub fn shares_transient_attachments(&self) -> bool {
+		self.share_transient_attachments
+	}
+
+	/// Changes the resolution that every batch using this render pass renders at, starting with their next frame.
+	pub fn set_internal_resolution(&self, resolution: InternalResolution) {
+		*self.internal_resolution.lock().unwrap() = resolution;
+	}
+
+	pub fn internal_resolution(&self) -> InternalResolution {
+		*self.internal_resolution.lock().unwrap()
+	}
+
+	pub fn set_upscale_filter(&self, filter: UpscaleFilter) {
+		*self.upscale_filter.lock().unwrap() = filter;
+	}
+
+	pub fn upscale_filter(&self) -> UpscaleFilter {
+		*self.upscale_filter.lock().unwrap()
+	}
+
+	/// Changes the display calibration for every batch using this render pass. It's cheap enough to call every frame
+	/// while a player drags a slider.
+	pub fn set_display_calibration(&self, calibration: DisplayCalibration) {
+		*self.display_calibration.lock().unwrap() = calibration;
+	}
+
+	pub fn display_calibration(&self) -> DisplayCalibration {
+		*self.display_calibration.lock().unwrap()
+	}
+
+	/// Changes the quality settings for every batch using this render pass, starting with their next frame. Changing
+	/// the anisotropy or LOD bias recreates the material sampler, and changing the shadow resolution recreates each
+	/// batch's shadow atlas, so this is best called from an options menu rather than every frame. The shadow
+	/// resolution is clamped to the largest image the device supports.
+	pub fn set_quality(&self, quality: &QualitySettings) -> Result<(), SamplerCreationError> {
+		let max_resolution = self.shaders.queue.device().physical_device().limits().max_image_dimension_2d();
+		let quality = &QualitySettings { shadow_resolution: quality.shadow_resolution.min(max_resolution), .. *quality };
+		let mut current = self.quality.lock().unwrap();
+		if quality.anisotropy != current.anisotropy || quality.texture_lod_bias != current.texture_lod_bias {
+			let settings =
+				SamplerSettings {
+					anisotropy: quality.anisotropy,
+					lod_bias: quality.texture_lod_bias,
+					.. self.sampler_settings
+				};
+			*self.sampler.lock().unwrap() = settings.build(self.shaders.queue.device())?;
+			self.sampler_generation.fetch_add(1, Ordering::Relaxed);
+		}
+		*current = *quality;
+		Ok(())
+	}
+
+	pub fn quality(&self) -> QualitySettings {
+		*self.quality.lock().unwrap()
+	}
+
+	/// The sampler for material textures, unless a material overrides it.
+	pub(super) fn sampler(&self) -> Arc<Sampler> {
+		self.sampler.lock().unwrap().clone()
+	}
+
+	pub(super) fn sampler_generation(&self) -> usize {
+		self.sampler_generation.load(Ordering::Relaxed)
+	}
+
+	pub(super) fn depth_format(&self) -> Format {
+		depth_format(self.shaders.queue.device(), self.reverse_z, self.stencil)
+	}
+
+	pub(super) fn pipeline_gbuffers_for(
+		&self,
+		stage: GBufferStage,
+		topology: Topology,
+		vertex_format: VertexFormat,
+	) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		match stage {
+			GBufferStage::GBuffers => self.pipelines_gbuffers.get(topology, vertex_format),
+			GBufferStage::DepthPrepass => self.pipelines_depth.get(topology, vertex_format),
+			GBufferStage::GBuffersAfterPrepass => self.pipelines_gbuffers_after_depth.get(topology, vertex_format),
+			GBufferStage::Shadow => self.pipelines_shadow.get(topology, vertex_format),
+		}
+	}
+
+	/// The pipeline that draws mesh groups in a stage.
+	pub(super) fn pipeline_indirect_for(
+		&self,
+		stage: GBufferStage,
+		topology: Topology,
+		vertex_format: VertexFormat,
+	) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		self.pipelines_indirect[stage as usize].get(topology, vertex_format)
+	}
+
+	/// The pipeline that draws foliage in a stage.
+	pub(super) fn pipeline_foliage_for(
+		&self,
+		stage: GBufferStage,
+		topology: Topology,
+		vertex_format: VertexFormat,
+	) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		self.pipelines_foliage[stage as usize].get(topology, vertex_format)
+	}
+
 	pub(crate) fn render_pass(&self) -> &Arc<RenderPassAbstract + Send + Sync> {
 		self.subpass_gbuffers.render_pass()
 	}
+
+	/// Returns the pipelines for a custom material shader, creating them if this is the first time it's been used.
+	pub(super) fn pipelines_for(
+		&self,
+		shader: &MaterialShader,
+	) -> Result<Arc<MaterialShaderPipelines>, GraphicsPipelineCreationError> {
+		let mut pipelines = self.pipelines_custom.lock().unwrap();
+		pipelines.retain(|pipelines| pipelines.shader_id.is_alive());
+		if let Some(pipelines) = pipelines.iter().find(|pipelines| pipelines.shader_id.is_child_of(shader.id_root())) {
+			return Ok(pipelines.clone());
+		}
+
+		let make_pipeline = |depth_stencil: DepthStencil, topology: Topology, vertex_format: VertexFormat|
+			-> Result<GBufferPipeline, GraphicsPipelineCreationError>
+		{
+			let builder =
+				GraphicsPipeline::start()
+					.vertex_input(MeshVertexDefinition::new(self.shaders.target_vertices.device(), vertex_format))
+					.vertex_shader(self.shaders.shader_gbuffers_vertex.main_entry_point(), ());
+			let builder =
+				match topology {
+					Topology::TriangleList => builder.triangle_list(),
+					Topology::TriangleStrip => builder.triangle_strip().primitive_restart(true),
+				};
+			let pipeline: GBufferPipeline =
+				Arc::new(
+					builder
+						.viewports_scissors_dynamic(1)
+						.fragment_shader(shader.entry_point(), ())
+						.depth_stencil(depth_stencil)
+						.render_pass(self.subpass_gbuffers.clone())
+						.build(self.shaders.target_vertices.device().clone())?
+				);
+			self.shaders.device_ctx.debug_names()
+				.name_pipeline(&*pipeline, &format!("mesh custom material ({:?}, {:?})", topology, vertex_format));
+			Ok(pipeline)
+		};
+
+		let created =
+			Arc::new(MaterialShaderPipelines {
+				shader_id: shader.id_root().make_id(),
+				gbuffers: GBufferPipelines::try_new(|topology, format| {
+					make_pipeline(depth_test(self.reverse_z), topology, format)
+				})?,
+				gbuffers_after_depth: GBufferPipelines::try_new(|topology, format| {
+					make_pipeline(depth_test_after_prepass(self.reverse_z), topology, format)
+				})?,
+			});
+		pipelines.push(created.clone());
+		Ok(created)
+	}
+
+	/// Returns the pipeline for a stenciled mesh in a stage, creating it if the stencil state hasn't been drawn in it
+	/// recently. After a depth prepass, the stencil is only tested, so it isn't changed twice. Shadows ignore the
+	/// stencil.
+	pub(super) fn pipeline_stencil_for(
+		&self,
+		stage: GBufferStage,
+		topology: Topology,
+		vertex_format: VertexFormat,
+		stencil: &StencilState,
+	) -> GBufferPipeline {
+		if stage == GBufferStage::Shadow {
+			return self.pipeline_gbuffers_for(stage, topology, vertex_format).clone();
+		}
+
+		let mut pipelines = self.pipelines_stencil.lock().unwrap();
+		pipelines.get_or_insert_with((stage, *stencil), || self.make_stencil_pipelines(stage, stencil))
+			.get(topology, vertex_format)
+			.clone()
+	}
+
+	fn make_stencil_pipelines(&self, stage: GBufferStage, stencil: &StencilState) -> GBufferPipelines {
+		let depth_stencil =
+			if stage == GBufferStage::GBuffersAfterPrepass {
+				let tested = StencilState { pass_op: StencilOp::Keep, fail_op: StencilOp::Keep, .. *stencil };
+				tested.depth_stencil(depth_test_after_prepass(self.reverse_z))
+			} else {
+				stencil.depth_stencil(depth_test(self.reverse_z))
+			};
+		// the prepass never writes color
+		let blend =
+			if stage == GBufferStage::DepthPrepass {
+				StencilState { write_color: false, .. *stencil }.blend(AttachmentBlend::pass_through())
+			} else {
+				stencil.blend(AttachmentBlend::pass_through())
+			};
+
+		GBufferPipelines::new(|topology, vertex_format| {
+			let builder =
+				GraphicsPipeline::start()
+					.vertex_input(MeshVertexDefinition::new(self.shaders.target_vertices.device(), vertex_format))
+					.vertex_shader(self.shaders.shader_gbuffers_vertex.main_entry_point(), ());
+			let builder =
+				match topology {
+					Topology::TriangleList => builder.triangle_list(),
+					Topology::TriangleStrip => builder.triangle_strip().primitive_restart(true),
+				};
+			let builder =
+				builder
+					.viewports_scissors_dynamic(1)
+					.depth_stencil(depth_stencil.clone())
+					.blend_collective(blend.clone())
+					.render_pass(self.subpass_gbuffers.clone());
+			let device = self.shaders.target_vertices.device().clone();
+			let pipeline: GBufferPipeline =
+				if stage == GBufferStage::DepthPrepass {
+					Arc::new(
+						builder
+							.fragment_shader(self.shaders.shader_depth_fragment.main_entry_point(), ())
+							.build(device)
+							.expect("failed to create pipeline")
+					)
+				} else {
+					Arc::new(
+						builder
+							.fragment_shader(self.shaders.shader_gbuffers_fragment.main_entry_point(), ())
+							.build(device)
+							.expect("failed to create pipeline")
+					)
+				};
+			self.shaders.device_ctx.debug_names()
+				.name_pipeline(&*pipeline, &format!("mesh stencil {:?} ({:?}, {:?})", stage, topology, vertex_format));
+			pipeline
+		})
+	}
+
+	/// Returns the forward render pass and its pipelines, creating them if this is the first time they've been used.
+	/// The pass ignores stencils, so its depth buffer never has one.
+	pub(super) fn forward(&self) -> Arc<ForwardPipelines> {
+		let mut forward = self.forward.lock().unwrap();
+		if let Some(forward) = &*forward {
+			return forward.clone();
+		}
+
+		let device = self.shaders.target_vertices.device().clone();
+		let depth_format = depth_format(&device, self.reverse_z, false);
+		let render_pass: Arc<RenderPassAbstract + Send + Sync> =
+			Arc::new(
+				single_pass_renderpass!(
+					device.clone(),
+					attachments: {
+						color: { load: Clear, store: Store, format: self.format, samples: 1, },
+						depth: { load: Clear, store: DontCare, format: depth_format, samples: 1, }
+					},
+					pass: { color: [color], depth_stencil: {depth} }
+				)
+				.unwrap()
+			);
+		let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+
+		let pipelines = GBufferPipelines::new(|topology, vertex_format| {
+			let builder =
+				GraphicsPipeline::start()
+					.vertex_input(MeshVertexDefinition::new(&device, vertex_format))
+					.vertex_shader(self.shaders.shader_gbuffers_vertex.main_entry_point(), ());
+			let builder =
+				match topology {
+					Topology::TriangleList => builder.triangle_list(),
+					Topology::TriangleStrip => builder.triangle_strip().primitive_restart(true),
+				};
+			let pipeline: GBufferPipeline =
+				Arc::new(
+					builder
+						.viewports_scissors_dynamic(1)
+						.fragment_shader(
+							self.shaders.shader_forward_fragment.main_entry_point(),
+							fs_forward::SpecializationConstants { ENCODE_SRGB: !is_srgb(self.format) as u32 },
+						)
+						.depth_stencil(depth_test(self.reverse_z))
+						.render_pass(subpass.clone())
+						.build(device.clone())
+						.expect("failed to create pipeline")
+				);
+			self.shaders.device_ctx.debug_names()
+				.name_pipeline(&*pipeline, &format!("mesh forward ({:?}, {:?})", topology, vertex_format));
+			pipeline
+		});
+
+		let created =
+			Arc::new(ForwardPipelines { render_pass: render_pass, depth_format: depth_format, pipelines: pipelines });
+		*forward = Some(created.clone());
+		created
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MeshRenderPassOptions {
+	/// How material textures are sampled, unless a material overrides it.
+	pub sampler: SamplerSettings,
+	/// Stores depth as 1.0 at the near plane and 0.0 at the far plane, in a float format. This spreads depth precision
+	/// much more evenly, which matters for distant far planes. Cameras must use `Camera::set_reverse_z` to match.
+	pub reverse_z: bool,
+	/// The resolution the 3D passes render at. This can be changed later with `set_internal_resolution`.
+	pub internal_resolution: InternalResolution,
+	pub upscale_filter: UpscaleFilter,
+	/// Gives the depth buffer a stencil buffer, so meshes can be drawn with `Mesh::set_stencil`.
+	pub stencil: bool,
+	/// Lets batches using this render pass share their G-buffers and post processing scratch images with every other
+	/// sharing batch on the device that renders at the same size and format, instead of each owning its own.
+	///
+	/// Sharing batches must have their commands submitted one after another in a single chain of futures, the way
+	/// `Frame::submit` does. If their futures are joined instead, executing the second batch fails with
+	/// `AccessError::AlreadyInUse` while the first is still on the GPU.
+	pub share_transient_attachments: bool,
+}
+
+/// The passes a mesh is drawn in, within the gbuffer subpass.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(super) enum GBufferStage {
+	GBuffers,
+	/// Writes only depth, so the following stage can skip hidden fragments.
+	DepthPrepass,
+	/// Like `GBuffers`, but tests against the depth written by `DepthPrepass` instead of writing it.
+	GBuffersAfterPrepass,
+	/// Writes only depth, into a shadow map. This is in its own render pass, not the gbuffer subpass.
+	Shadow,
+}
+impl GBufferStage {
+	/// Whether the stage only writes depth, so custom material shaders aren't used for it.
+	pub(super) fn is_depth_only(self) -> bool {
+		self == GBufferStage::DepthPrepass || self == GBufferStage::Shadow
+	}
+}
+
+type GBufferPipeline = Arc<GraphicsPipelineAbstract + Send + Sync + 'static>;
+
+/// One pipeline for each combination of topology and vertex format.
+struct GBufferPipelines {
+	list: GBufferPipeline,
+	strip: GBufferPipeline,
+	compressed_list: GBufferPipeline,
+	compressed_strip: GBufferPipeline,
+}
+impl GBufferPipelines {
+	fn new(mut make: impl FnMut(Topology, VertexFormat) -> GBufferPipeline) -> Self {
+		Self {
+			list: make(Topology::TriangleList, VertexFormat::Full),
+			strip: make(Topology::TriangleStrip, VertexFormat::Full),
+			compressed_list: make(Topology::TriangleList, VertexFormat::Compressed),
+			compressed_strip: make(Topology::TriangleStrip, VertexFormat::Compressed),
+		}
+	}
+
+	fn try_new<E>(mut make: impl FnMut(Topology, VertexFormat) -> Result<GBufferPipeline, E>) -> Result<Self, E> {
+		Ok(Self {
+			list: make(Topology::TriangleList, VertexFormat::Full)?,
+			strip: make(Topology::TriangleStrip, VertexFormat::Full)?,
+			compressed_list: make(Topology::TriangleList, VertexFormat::Compressed)?,
+			compressed_strip: make(Topology::TriangleStrip, VertexFormat::Compressed)?,
+		})
+	}
+
+	fn get(&self, topology: Topology, vertex_format: VertexFormat) -> &GBufferPipeline {
+		match (topology, vertex_format) {
+			(Topology::TriangleList, VertexFormat::Full) => &self.list,
+			(Topology::TriangleStrip, VertexFormat::Full) => &self.strip,
+			(Topology::TriangleList, VertexFormat::Compressed) => &self.compressed_list,
+			(Topology::TriangleStrip, VertexFormat::Compressed) => &self.compressed_strip,
+		}
+	}
+}
+
+/// The built-in pipelines for a stencil state in one stage.
+/// A single pass that lights meshes as they're drawn, with a color and a depth attachment. Custom materials and
+/// stencils aren't supported in it.
+pub(super) struct ForwardPipelines {
+	pub(super) render_pass: Arc<RenderPassAbstract + Send + Sync>,
+	pub(super) depth_format: Format,
+	pipelines: GBufferPipelines,
+}
+impl ForwardPipelines {
+	pub(super) fn get(&self, topology: Topology, vertex_format: VertexFormat) -> &GBufferPipeline {
+		self.pipelines.get(topology, vertex_format)
+	}
+}
+
+/// The gbuffer pipelines for a custom material shader. Depth only stages always use the built-in pipelines.
+pub(super) struct MaterialShaderPipelines {
+	shader_id: ObjectId,
+	gbuffers: GBufferPipelines,
+	gbuffers_after_depth: GBufferPipelines,
+}
+impl MaterialShaderPipelines {
+	pub(super) fn get(&self, stage: GBufferStage, topology: Topology, vertex_format: VertexFormat) -> &GBufferPipeline {
+		match stage {
+			GBufferStage::GBuffers => self.gbuffers.get(topology, vertex_format),
+			GBufferStage::GBuffersAfterPrepass => self.gbuffers_after_depth.get(topology, vertex_format),
+			GBufferStage::DepthPrepass | GBufferStage::Shadow => {
+				unreachable!("custom materials don't have depth only pipelines")
+			},
+		}
+	}
+}
+
+fn depth_format(device: &Device, reverse_z: bool, stencil: bool) -> Format {
+	if stencil {
+		stencil_format(device)
+	} else if reverse_z {
+		REVERSE_Z_DEPTH_FORMAT
+	} else {
+		DEPTH_FORMAT
+	}
+}
+
+fn depth_test(reverse_z: bool) -> DepthStencil {
+	DepthStencil {
+		depth_compare: if reverse_z { Compare::Greater } else { Compare::Less },
+		.. DepthStencil::simple_depth_test()
+	}
+}
+
+fn depth_test_after_prepass(reverse_z: bool) -> DepthStencil {
+	DepthStencil {
+		depth_write: false,
+		depth_compare: if reverse_z { Compare::GreaterOrEqual } else { Compare::LessOrEqual },
+		.. DepthStencil::simple_depth_test()
+	}
 }
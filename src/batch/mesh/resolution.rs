@@ -0,0 +1,137 @@
+use std::time::Instant;
+
+/// How much of the frame time average each new frame makes up.
+const SMOOTHING: f32 = 0.1;
+/// How far over budget frames can run before the scale drops, so noise doesn't cause constant changes.
+const HEADROOM: f32 = 1.05;
+/// How much of the difference between the current and ideal scale is closed each frame.
+const ADJUST_RATE: f32 = 0.1;
+/// How much the scale grows each frame while frames are within budget.
+const RECOVER_STEP: f32 = 0.002;
+/// Frames longer than this are hitches, like a level load, and aren't used to adjust the scale.
+const MAX_FRAME_TIME: f32 = 0.25;
+
+/// The resolution the 3D passes render at, before the target pass scales the image to the target. Cameras should keep
+/// the target's aspect ratio either way. If a fixed resolution has a different aspect ratio, its pixels are stretched
+/// to fill the target, so the scene still isn't distorted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum InternalResolution {
+	/// The target's resolution.
+	Native,
+	/// A multiple of the target's resolution. 2.0 supersamples, and 0.5 renders a quarter of the pixels.
+	Scale(f32),
+	/// The same width and height whatever the target's size, such as 1280x720 for a pixel art game.
+	Fixed([u32; 2]),
+}
+impl InternalResolution {
+	/// The internal size relative to the target's size, for each axis.
+	pub(super) fn scale(&self, target_dimensions: [f32; 2]) -> [f32; 2] {
+		match *self {
+			InternalResolution::Native => [1.0, 1.0],
+			InternalResolution::Scale(scale) => [scale.max(0.01), scale.max(0.01)],
+			InternalResolution::Fixed([width, height]) => [
+				width.max(1) as f32 / target_dimensions[0].max(1.0),
+				height.max(1) as f32 / target_dimensions[1].max(1.0),
+			],
+		}
+	}
+}
+impl Default for InternalResolution {
+	fn default() -> Self {
+		InternalResolution::Native
+	}
+}
+
+/// How the target pass samples the internal image when its resolution differs from the target's.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum UpscaleFilter {
+	/// Blocky pixels, for pixel art.
+	Nearest,
+	/// Bilinear filtering. This also averages pixels when supersampling.
+	Linear,
+}
+impl Default for UpscaleFilter {
+	fn default() -> Self {
+		UpscaleFilter::Linear
+	}
+}
+
+/// Lowers the resolution the 3D passes render at when frames take too long, and raises it again when there's time to
+/// spare. The scale applies on top of the render pass's `InternalResolution`. The final pass upscales to the target,
+/// so UI and the window stay sharp.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct DynamicResolution {
+	/// The frame rate to hold.
+	pub target_fps: f32,
+	/// The lowest scale of the target's size to render at, such as 0.5 for half the width and height.
+	pub min_scale: f32,
+	/// The highest scale to render at. The batch's buffers are allocated at this scale.
+	pub max_scale: f32,
+}
+impl DynamicResolution {
+	pub(super) fn scale_range(&self) -> (f32, f32) {
+		let max_scale = self.max_scale.max(0.01);
+		(self.min_scale.max(0.01).min(max_scale), max_scale)
+	}
+}
+impl Default for DynamicResolution {
+	fn default() -> Self {
+		Self { target_fps: 60.0, min_scale: 0.5, max_scale: 1.0 }
+	}
+}
+
+/// Tracks frame times for `DynamicResolution`. Frames are timed from one recording to the next. Once the GPU is the
+/// bottleneck, recording waits on it, so this follows the GPU's time per frame.
+pub(super) struct ResolutionController {
+	pub(super) settings: Option<DynamicResolution>,
+	pub(super) scale: f32,
+	frame_time: Option<f32>,
+	last_frame: Option<Instant>,
+}
+impl ResolutionController {
+	pub(super) fn new() -> Self {
+		Self { settings: None, scale: 1.0, frame_time: None, last_frame: None }
+	}
+
+	pub(super) fn set_settings(&mut self, settings: Option<DynamicResolution>) {
+		self.settings = settings;
+		self.scale = settings.map_or(1.0, |settings| settings.scale_range().1);
+		self.frame_time = None;
+		self.last_frame = None;
+	}
+
+	/// The scale the batch's buffers need to be allocated at.
+	pub(super) fn max_scale(&self) -> f32 {
+		self.settings.map_or(1.0, |settings| settings.scale_range().1)
+	}
+
+	/// Times the frame that just ended and adjusts the scale for the next one.
+	pub(super) fn tick(&mut self) {
+		let settings = match self.settings { Some(settings) => settings, None => return };
+		let now = Instant::now();
+		let last_frame = self.last_frame.replace(now);
+		let dt = match last_frame { Some(last_frame) => now.duration_since(last_frame), None => return };
+		let dt = dt.as_secs() as f32 + dt.subsec_nanos() as f32 / 1_000_000_000.0;
+		if dt > MAX_FRAME_TIME {
+			return;
+		}
+
+		let frame_time = self.frame_time.map_or(dt, |frame_time| frame_time + (dt - frame_time) * SMOOTHING);
+		self.frame_time = Some(frame_time);
+
+		// the cost of a frame is roughly proportional to its pixels, which go with the square of the scale
+		let budget = 1.0 / settings.target_fps.max(1.0);
+		let scale =
+			if frame_time > budget * HEADROOM {
+				let ideal = self.scale * (budget / frame_time).sqrt();
+				self.scale + (ideal - self.scale) * ADJUST_RATE
+			} else {
+				self.scale + RECOVER_STEP
+			};
+		let (min_scale, max_scale) = settings.scale_range();
+		self.scale = scale.max(min_scale).min(max_scale);
+	}
+}
@@ -1,30 +1,54 @@
-use crate::batch::mesh::{ TargetVertex };
+use crate::batch::mesh::{ TargetVertex, water::{ WATER_GRID, WaterVertex, water_grid } };
+use crate::device::{ DeviceCtx, ShaderLoadError, ShaderReflection };
+use crate::texture::identity_lut;
+use crate::vfs;
 use crate::window::Window;
-use std::sync::Arc;
+use log::{ info, log };
+use std::{ io, sync::Arc };
 use vulkano::{
 	OomError,
 	buffer::{ BufferUsage, ImmutableBuffer },
-	device::Queue,
+	descriptor::{ descriptor::ShaderStages, pipeline_layout::PipelineLayoutDesc },
+	device::{ Device, Queue },
 	format::Format,
 	image::{ Dimensions, ImageCreationError, ImageViewAccess, ImmutableImage },
 	memory::DeviceMemoryAllocError,
+	pipeline::shader::ShaderModule,
 	sampler::{ Filter, MipmapMode, Sampler, SamplerAddressMode, SamplerCreationError },
 	sync::GpuFuture,
 };
 
 pub struct MeshShaders {
 	pub(super) queue: Arc<Queue>,
+	pub(super) device_ctx: Arc<DeviceCtx>,
 	pub(super) target_vertices: Arc<ImmutableBuffer<[TargetVertex; 6]>>,
 	pub(super) shader_gbuffers_vertex: vs_gbuffers::Shader,
+	pub(super) shader_gbuffers_indirect_vertex: vs_gbuffers_indirect::Shader,
+	pub(super) shader_foliage_vertex: vs_foliage::Shader,
 	pub(super) shader_gbuffers_fragment: fs_gbuffers::Shader,
+	pub(super) shader_depth_fragment: fs_depth::Shader,
 	pub(super) shader_history_vertex: vs_history::Shader,
 	pub(super) shader_history_fragment: fs_history::Shader,
+	pub(super) shader_forward_fragment: fs_forward::Shader,
+	pub(super) shader_light_cull_compute: cs_light_cull::Shader,
+	pub(super) shader_draw_cull_compute: cs_draw_cull::Shader,
+	pub(super) shader_morph_compute: cs_morph::Shader,
 	pub(super) shader_target_vertex: vs_target::Shader,
+	pub(super) shader_motion_blur_fragment: fs_motion_blur::Shader,
+	pub(super) shader_dof_fragment: fs_dof::Shader,
+	pub(super) shader_bloom_fragment: fs_bloom::Shader,
 	pub(super) shader_target_fragment: fs_target::Shader,
+	pub(super) shader_water_vertex: vs_water::Shader,
+	pub(super) shader_water_fragment: fs_water::Shader,
+	pub(super) water_vertices: Arc<ImmutableBuffer<[WaterVertex]>>,
+	pub(super) water_indices: Arc<ImmutableBuffer<[u32]>>,
 	pub(super) black_pixel: Arc<ImageViewAccess + Send + Sync + 'static>,
 	pub(super) texture1_default: Arc<ImageViewAccess + Send + Sync + 'static>,
 	pub(super) texture2_default: Arc<ImageViewAccess + Send + Sync + 'static>,
 	pub(super) sampler: Arc<Sampler>,
+	pub(super) lut_identity: Arc<ImageViewAccess + Send + Sync + 'static>,
+	/// Clamps to the edge, for color luts and post processing.
+	pub(super) clamp_sampler: Arc<Sampler>,
 }
 impl MeshShaders {
 	pub fn new(window: &Window) -> Result<(Arc<Self>, impl GpuFuture), MeshShadersError> {
@@ -66,16 +90,66 @@ impl MeshShaders {
 					window.device().queue().clone(),
 				)?;
 
+		let (lut_identity, lut_identity_future) =
+				ImmutableImage::from_iter(
+					identity_lut().into_iter(),
+					Dimensions::Dim3d { width: 2, height: 2, depth: 2 },
+					Format::R8G8B8A8Unorm,
+					window.device().queue().clone(),
+				)?;
+
+		let (grid_vertices, grid_indices) = water_grid(WATER_GRID);
+		let (water_vertices, water_vertices_future) =
+			ImmutableBuffer::from_iter(
+				grid_vertices.into_iter(),
+				BufferUsage::vertex_buffer(),
+				window.device().queue().clone(),
+			)?;
+		let (water_indices, water_indices_future) =
+			ImmutableBuffer::from_iter(
+				grid_indices.into_iter(),
+				BufferUsage::index_buffer(),
+				window.device().queue().clone(),
+			)?;
+
+		// each shader is replaced by the file with its module's name in the shader path, if there is one
+		macro_rules! load {
+			($module:ident, $stage:ident) => {
+				load_shader(
+					window.device(),
+					stringify!($module),
+					$module::Layout(ShaderStages { $stage: true, .. ShaderStages::none() }),
+					$module::Shader::load,
+					$module::Shader::from_module,
+				)?
+			};
+		}
+
 		Ok((
 			Arc::new(Self {
 				queue: window.device().queue().clone(),
+				device_ctx: window.device().clone(),
 				target_vertices: target_vertices,
-				shader_gbuffers_vertex: vs_gbuffers::Shader::load(window.device().device().clone())?,
-				shader_gbuffers_fragment: fs_gbuffers::Shader::load(window.device().device().clone())?,
-				shader_history_vertex: vs_history::Shader::load(window.device().device().clone())?,
-				shader_history_fragment: fs_history::Shader::load(window.device().device().clone())?,
-				shader_target_vertex: vs_target::Shader::load(window.device().device().clone())?,
-				shader_target_fragment: fs_target::Shader::load(window.device().device().clone())?,
+				shader_gbuffers_vertex: load!(vs_gbuffers, vertex),
+				shader_gbuffers_indirect_vertex: load!(vs_gbuffers_indirect, vertex),
+				shader_foliage_vertex: load!(vs_foliage, vertex),
+				shader_gbuffers_fragment: load!(fs_gbuffers, fragment),
+				shader_depth_fragment: load!(fs_depth, fragment),
+				shader_history_vertex: load!(vs_history, vertex),
+				shader_history_fragment: load!(fs_history, fragment),
+				shader_forward_fragment: load!(fs_forward, fragment),
+				shader_light_cull_compute: load!(cs_light_cull, compute),
+				shader_draw_cull_compute: load!(cs_draw_cull, compute),
+				shader_morph_compute: load!(cs_morph, compute),
+				shader_target_vertex: load!(vs_target, vertex),
+				shader_motion_blur_fragment: load!(fs_motion_blur, fragment),
+				shader_dof_fragment: load!(fs_dof, fragment),
+				shader_bloom_fragment: load!(fs_bloom, fragment),
+				shader_target_fragment: load!(fs_target, fragment),
+				shader_water_vertex: load!(vs_water, vertex),
+				shader_water_fragment: load!(fs_water, fragment),
+				water_vertices: water_vertices,
+				water_indices: water_indices,
 				black_pixel: black_pixel,
 				texture1_default: texture1_default,
 				texture2_default: texture2_default,
@@ -89,18 +163,82 @@ impl MeshShaders {
 						SamplerAddressMode::Repeat,
 						0.0, 1.0, 0.0, 0.0
 					)?,
+				lut_identity: lut_identity,
+				clamp_sampler:
+					Sampler::new(
+						window.device().device().clone(),
+						Filter::Linear,
+						Filter::Linear, MipmapMode::Nearest,
+						SamplerAddressMode::ClampToEdge,
+						SamplerAddressMode::ClampToEdge,
+						SamplerAddressMode::ClampToEdge,
+						0.0, 1.0, 0.0, 0.0
+					)?,
 			}),
-			target_vertices_future.join(black_pixel_future).join(texture1_default_future).join(texture2_default_future)
+			target_vertices_future
+				.join(black_pixel_future)
+				.join(texture1_default_future)
+				.join(texture2_default_future)
+				.join(lut_identity_future)
+				.join(water_vertices_future)
+				.join(water_indices_future)
 		))
 	}
 }
 
+/// Lets a shader module's `Shader` be made from a replacement loaded at runtime.
+macro_rules! overridable {
+	() => {
+		impl Shader {
+			pub(in crate::batch::mesh) fn from_module(
+				shader: ::std::sync::Arc<::vulkano::pipeline::shader::ShaderModule>,
+			) -> Self {
+				Self { shader: shader }
+			}
+		}
+	};
+}
+
+/// Loads one of the engine's shaders, or its replacement named `<name>.spv` in the device's shader path. Replacements
+/// are checked against the built-in shader's layout, so they must declare the same descriptors and push constants, or
+/// a subset of them. The rest of their interface can't be checked here, so there's only a shader path when the game
+/// promised it matches, through the unsafe `ShaderPath::new`.
+fn load_shader<S>(
+	device: &DeviceCtx,
+	name: &str,
+	layout: impl PipelineLayoutDesc,
+	embedded: fn(Arc<Device>) -> Result<S, OomError>,
+	from_module: fn(Arc<ShaderModule>) -> S,
+) -> Result<S, MeshShadersError> {
+	let path =
+		match device.shader_path() {
+			Some(dir) => dir.join(name).with_extension("spv"),
+			None => return Ok(embedded(device.device().clone())?),
+		};
+	let spirv =
+		match vfs::read(&path) {
+			Ok(spirv) => spirv,
+			Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(embedded(device.device().clone())?),
+			Err(err) => return Err(MeshShadersError::IoError(err)),
+		};
+
+	let error = |err| MeshShadersError::ShaderOverride(name.to_owned(), err);
+	ShaderReflection::new(&spirv).and_then(|reflection| reflection.check_layout(&layout)).map_err(error)?;
+	info!("Replacing the {} shader with {}", name, path.display());
+	// safe because `ShaderPath::new` requires the replacement's interface to match the built-in shader's
+	Ok(from_module(unsafe { ShaderModule::new(device.device().clone(), &spirv)? }))
+}
+
 #[derive(Debug)]
 pub enum MeshShadersError {
 	DeviceMemoryAllocError(DeviceMemoryAllocError),
 	ImageCreationError(ImageCreationError),
+	/// A replacement shader in the shader path couldn't be read.
+	IoError(io::Error),
 	OomError(OomError),
 	SamplerCreationError(SamplerCreationError),
+	/// A replacement shader, named by its module, doesn't fit the built-in shader's layout.
+	ShaderOverride(String, ShaderLoadError),
 	TooManyObjects,
 }
 impl From<DeviceMemoryAllocError> for MeshShadersError {
@@ -124,7 +262,7 @@ impl From<SamplerCreationError> for MeshShadersError {
 	}
 }
 
-mod vs_gbuffers {
+pub(super) mod vs_gbuffers {
 	::vulkano_shaders::shader!{
 		ty: "vertex",
 		src: "#version 450
@@ -136,22 +274,38 @@ layout(location = 0) out vec3 out_position_cs;
 layout(location = 1) out vec3 out_normal_cs;
 layout(location = 2) out vec2 out_texcoord;
 layout(location = 3) out vec3 out_base_albedo;
+layout(location = 4) out vec4 out_position_clip;
+layout(location = 5) out vec4 out_prev_position_clip;
+layout(location = 6) flat out float out_selected;
+layout(location = 7) flat out float out_emissive;
 
 layout(set = 0, binding = 0) uniform CameraPos { vec3 camera_pos; };
 layout(set = 0, binding = 1) uniform CameraRot { vec4 camera_rot; };
-layout(set = 0, binding = 2) uniform CameraProj { vec4 camera_proj; };
+layout(set = 0, binding = 2) uniform CameraProj { vec4 camera_proj; vec4 camera_proj_offset; };
+layout(set = 0, binding = 3) uniform PrevCameraPos { vec3 prev_camera_pos; };
+layout(set = 0, binding = 4) uniform PrevCameraRot { vec4 prev_camera_rot; };
+layout(set = 0, binding = 5) uniform PrevCameraProj { vec4 prev_camera_proj; vec4 prev_camera_proj_offset; };
 
-layout(set = 1, binding = 0) uniform MeshPos { vec3 mesh_pos; };
-layout(set = 1, binding = 1) uniform MeshRot { vec4 mesh_rot; };
+// the previous transform is only a position and an xyzw rotation, to stay within the minimum push constant size. for
+// the same reason, position_offset.w is 1 for selected meshes, and position_scale.w is the submesh's emissive
+// intensity. the block fills all 128 bytes, so there's no room for a material index, and materials keep their own
+// descriptor set.
+layout(push_constant) uniform MeshModel {
+	mat4 model;
+	vec4 position_offset;
+	vec4 position_scale;
+	vec4 prev_position;
+	vec4 prev_rotation;
+};
 
-layout(set = 2, binding = 0) uniform Material {
+layout(set = 1, binding = 0) uniform Material {
 	uint light_penetration;
 	uint subsurface_scattering;
 	uint emissive_brightness;
 	vec3 base_albedo;
 };
-layout(set = 2, binding = 1) uniform sampler2D tex1;
-layout(set = 2, binding = 2) uniform sampler2D tex2;
+layout(set = 1, binding = 1) uniform sampler2D tex1;
+layout(set = 1, binding = 2) uniform sampler2D tex2;
 
 vec4 quat_inv(vec4 quat) {
 	return vec4(-quat.xyz, quat.w) / dot(quat, quat);
@@ -161,25 +315,231 @@ vec3 quat_mul(vec4 quat, vec3 vec) {
 	return cross(quat.xyz, cross(quat.xyz, vec) + vec * quat.w) * 2.0 + vec;
 }
 
-vec4 perspective(vec4 proj, vec3 pos) {
-	return vec4(pos.xy * proj.xy, pos.z * proj.z + proj.w, -pos.z);
+// the offset is nonzero for off-center projections, like a headset's
+vec4 perspective(vec4 proj, vec2 offset, vec3 pos) {
+	return vec4(pos.xy * proj.xy + pos.z * offset, pos.z * proj.z + proj.w, -pos.z);
 }
 
 void main() {
 	// stupid math library puts w first, so we flip it here
 	vec4 camera_rot = camera_rot.yzwx;
-	vec4 mesh_rot = mesh_rot.yzwx;
+	vec4 prev_camera_rot = prev_camera_rot.yzwx;
 
-	vec3 normal_ws = quat_mul(mesh_rot, normal_os);
+	vec3 normal_ws = normalize(transpose(inverse(mat3(model))) * normal_os);
 	out_normal_cs = quat_mul(quat_inv(camera_rot), normal_ws);
-	vec3 position_ws = quat_mul(mesh_rot, position_os) + mesh_pos;
+	vec3 position_ms = position_offset.xyz + position_os * position_scale.xyz;
+	vec3 position_ws = (model * vec4(position_ms, 1.0)).xyz;
+	out_position_cs = quat_mul(quat_inv(camera_rot), position_ws - camera_pos);
+	out_base_albedo = base_albedo;
+	out_texcoord = texcoord;
+	gl_Position = perspective(camera_proj, camera_proj_offset.xy, out_position_cs);
+
+	vec3 prev_position_ws = quat_mul(prev_rotation, position_ms) + prev_position.xyz;
+	vec3 prev_position_cs = quat_mul(quat_inv(prev_camera_rot), prev_position_ws - prev_camera_pos);
+	out_position_clip = gl_Position;
+	out_prev_position_clip = perspective(prev_camera_proj, prev_camera_proj_offset.xy, prev_position_cs);
+	out_selected = position_offset.w;
+	out_emissive = position_scale.w;
+}
+"
+	}
+	overridable!();
+}
+
+mod vs_gbuffers_indirect {
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		src: "#version 450
+layout(location = 0) in vec3 position_os;
+layout(location = 1) in vec3 normal_os;
+layout(location = 2) in vec2 texcoord;
+
+layout(location = 0) out vec3 out_position_cs;
+layout(location = 1) out vec3 out_normal_cs;
+layout(location = 2) out vec2 out_texcoord;
+layout(location = 3) out vec3 out_base_albedo;
+layout(location = 4) out vec4 out_position_clip;
+layout(location = 5) out vec4 out_prev_position_clip;
+layout(location = 6) flat out float out_selected;
+layout(location = 7) flat out float out_emissive;
+
+layout(set = 0, binding = 0) uniform CameraPos { vec3 camera_pos; };
+layout(set = 0, binding = 1) uniform CameraRot { vec4 camera_rot; };
+layout(set = 0, binding = 2) uniform CameraProj { vec4 camera_proj; vec4 camera_proj_offset; };
+layout(set = 0, binding = 3) uniform PrevCameraPos { vec3 prev_camera_pos; };
+layout(set = 0, binding = 4) uniform PrevCameraRot { vec4 prev_camera_rot; };
+layout(set = 0, binding = 5) uniform PrevCameraProj { vec4 prev_camera_proj; vec4 prev_camera_proj_offset; };
+
+layout(set = 1, binding = 0) uniform Material {
+	uint light_penetration;
+	uint subsurface_scattering;
+	uint emissive_brightness;
+	vec3 base_albedo;
+};
+layout(set = 1, binding = 1) uniform sampler2D tex1;
+layout(set = 1, binding = 2) uniform sampler2D tex2;
+
+// see mesh::group::GroupObject. each draw's first instance is the index of its object.
+struct GroupObject {
+	mat4 model;
+	vec4 position_offset;
+	vec4 position_scale;
+	vec4 bounds;
+};
+layout(set = 2, binding = 0) readonly buffer Objects { GroupObject objects[]; };
+
+vec4 quat_inv(vec4 quat) {
+	return vec4(-quat.xyz, quat.w) / dot(quat, quat);
+}
+
+vec3 quat_mul(vec4 quat, vec3 vec) {
+	return cross(quat.xyz, cross(quat.xyz, vec) + vec * quat.w) * 2.0 + vec;
+}
+
+vec4 perspective(vec4 proj, vec2 offset, vec3 pos) {
+	return vec4(pos.xy * proj.xy + pos.z * offset, pos.z * proj.z + proj.w, -pos.z);
+}
+
+void main() {
+	GroupObject object = objects[gl_InstanceIndex];
+
+	// stupid math library puts w first, so we flip it here
+	vec4 camera_rot = camera_rot.yzwx;
+	vec4 prev_camera_rot = prev_camera_rot.yzwx;
+
+	vec3 normal_ws = normalize(transpose(inverse(mat3(object.model))) * normal_os);
+	out_normal_cs = quat_mul(quat_inv(camera_rot), normal_ws);
+	vec3 position_ms = object.position_offset.xyz + position_os * object.position_scale.xyz;
+	vec3 position_ws = (object.model * vec4(position_ms, 1.0)).xyz;
+	out_position_cs = quat_mul(quat_inv(camera_rot), position_ws - camera_pos);
+	out_base_albedo = base_albedo;
+	out_texcoord = texcoord;
+	gl_Position = perspective(camera_proj, camera_proj_offset.xy, out_position_cs);
+
+	// grouped objects never move, so only the camera contributes to motion
+	vec3 prev_position_cs = quat_mul(quat_inv(prev_camera_rot), position_ws - prev_camera_pos);
+	out_position_clip = gl_Position;
+	out_prev_position_clip = perspective(prev_camera_proj, prev_camera_proj_offset.xy, prev_position_cs);
+	out_selected = object.position_offset.w;
+	out_emissive = object.position_scale.w;
+}
+"
+	}
+	overridable!();
+}
+
+pub(super) mod vs_foliage {
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		src: "#version 450
+layout(location = 0) in vec3 position_os;
+layout(location = 1) in vec3 normal_os;
+layout(location = 2) in vec2 texcoord;
+// per instance, see foliage::FoliageInstance
+layout(location = 3) in vec4 instance_position_scale;
+layout(location = 4) in vec4 instance_rotation;
+
+layout(location = 0) out vec3 out_position_cs;
+layout(location = 1) out vec3 out_normal_cs;
+layout(location = 2) out vec2 out_texcoord;
+layout(location = 3) out vec3 out_base_albedo;
+layout(location = 4) out vec4 out_position_clip;
+layout(location = 5) out vec4 out_prev_position_clip;
+layout(location = 6) flat out float out_selected;
+layout(location = 7) flat out float out_emissive;
+
+layout(set = 0, binding = 0) uniform CameraPos { vec3 camera_pos; };
+layout(set = 0, binding = 1) uniform CameraRot { vec4 camera_rot; };
+layout(set = 0, binding = 2) uniform CameraProj { vec4 camera_proj; vec4 camera_proj_offset; };
+layout(set = 0, binding = 3) uniform PrevCameraPos { vec3 prev_camera_pos; };
+layout(set = 0, binding = 4) uniform PrevCameraRot { vec4 prev_camera_rot; };
+layout(set = 0, binding = 5) uniform PrevCameraProj { vec4 prev_camera_proj; vec4 prev_camera_proj_offset; };
+
+// position_offset.w is one over the mesh's height, and position_scale.w is the emissive intensity. the wind is its
+// direction, strength, and gust strength, then gust is the gusts' speed and spacing, and the time this frame and the
+// last. fade is the distances instances shrink away between, or zero to never fade.
+layout(push_constant) uniform Foliage {
+	vec4 position_offset;
+	vec4 position_scale;
+	vec4 wind;
+	vec4 gust;
+	vec4 fade;
+};
+
+layout(set = 1, binding = 0) uniform Material {
+	uint light_penetration;
+	uint subsurface_scattering;
+	uint emissive_brightness;
+	vec3 base_albedo;
+};
+layout(set = 1, binding = 1) uniform sampler2D tex1;
+layout(set = 1, binding = 2) uniform sampler2D tex2;
+
+const float TAU = 6.2831853;
+
+vec4 quat_inv(vec4 quat) {
+	return vec4(-quat.xyz, quat.w) / dot(quat, quat);
+}
+
+vec3 quat_mul(vec4 quat, vec3 vec) {
+	return cross(quat.xyz, cross(quat.xyz, vec) + vec * quat.w) * 2.0 + vec;
+}
+
+vec4 perspective(vec4 proj, vec2 offset, vec3 pos) {
+	return vec4(pos.xy * proj.xy + pos.z * offset, pos.z * proj.z + proj.w, -pos.z);
+}
+
+// how far the wind pushes the top of an instance at its root, at a time
+vec3 sway(vec3 root, float time) {
+	// neighbors sway a little out of step
+	float phase = dot(root.xz, vec2(0.37, 0.61));
+	float flutter = sin(time * 1.7 + phase) * 0.6 + sin(time * 3.1 + phase * 1.3) * 0.3;
+	float gust_wave = sin((dot(root.xz, wind.xy) - time * gust.x) / gust.y * TAU);
+	float gusts = pow(max(gust_wave, 0.0), 4.0) * wind.w;
+	return vec3(wind.x, 0.0, wind.y) * (wind.z * (0.6 + 0.4 * flutter) + gusts);
+}
+
+vec3 place(vec3 position_ms, vec3 root, float scale, float time) {
+	vec3 position_ws = root + quat_mul(instance_rotation, position_ms * scale);
+
+	// the top bends the most and the root stays put, and bending lowers the top so it doesn't stretch
+	float bend = clamp(position_ms.y * position_offset.w, 0.0, 1.0);
+	vec3 offset = sway(root, time) * bend * bend * instance_position_scale.w;
+	float height = instance_position_scale.w / position_offset.w;
+	offset.y -= dot(offset, offset) * 0.5 / max(height, 0.001);
+	return position_ws + offset;
+}
+
+void main() {
+	// stupid math library puts w first, so we flip it here
+	vec4 camera_rot = camera_rot.yzwx;
+	vec4 prev_camera_rot = prev_camera_rot.yzwx;
+
+	vec3 root = instance_position_scale.xyz;
+	float scale = instance_position_scale.w;
+	if (fade.y > 0.0) {
+		scale *= 1.0 - smoothstep(fade.x, fade.y, distance(root, camera_pos));
+	}
+
+	vec3 position_ms = position_offset.xyz + position_os * position_scale.xyz;
+	vec3 position_ws = place(position_ms, root, scale, gust.z);
+	out_normal_cs = quat_mul(quat_inv(camera_rot), quat_mul(instance_rotation, normal_os));
 	out_position_cs = quat_mul(quat_inv(camera_rot), position_ws - camera_pos);
 	out_base_albedo = base_albedo;
 	out_texcoord = texcoord;
-	gl_Position = perspective(camera_proj, out_position_cs);
+	gl_Position = perspective(camera_proj, camera_proj_offset.xy, out_position_cs);
+
+	// the wind moves the instance between frames, so its previous position is swayed at the previous time
+	vec3 prev_position_ws = place(position_ms, root, scale, gust.w);
+	vec3 prev_position_cs = quat_mul(quat_inv(prev_camera_rot), prev_position_ws - prev_camera_pos);
+	out_position_clip = gl_Position;
+	out_prev_position_clip = perspective(prev_camera_proj, prev_camera_proj_offset.xy, prev_position_cs);
+	out_selected = 0.0;
+	out_emissive = position_scale.w;
 }
 "
 	}
+	overridable!();
 }
 
 mod fs_gbuffers {
@@ -190,12 +550,19 @@ layout(location = 0) in vec3 position_cs;
 layout(location = 1) in vec3 normal_cs;
 layout(location = 2) in vec2 texcoord;
 layout(location = 3) in vec3 base_albedo;
+layout(location = 4) in vec4 position_clip;
+layout(location = 5) in vec4 prev_position_clip;
+layout(location = 6) flat in float selected;
+layout(location = 7) flat in float emissive;
 
 layout(location = 0) out vec4 out_albedo;
 layout(location = 1) out vec4 out_normal_cs;
+layout(location = 2) out vec4 out_velocity;
+layout(location = 3) out vec4 out_selection;
+layout(location = 4) out vec4 out_emissive;
 
-layout(set = 2, binding = 1) uniform sampler2D tex_albedo;
-layout(set = 2, binding = 2) uniform sampler2D tex_normal;
+layout(set = 1, binding = 1) uniform sampler2D tex_albedo;
+layout(set = 1, binding = 2) uniform sampler2D tex_normal;
 
 mat3 tangent_frame(vec3 fWorldNormal, vec3 vPosition, vec2 vTexCoord) {
 	vec3 dxPosition = dFdx(vPosition);
@@ -220,9 +587,25 @@ void main() {
 	albedo.rgb = mix(base_albedo, albedo.rgb, albedo.a);
 	out_albedo = vec4(sqrt(albedo.rgb), 0);
 	out_normal_cs = vec4(normalize(normal_cs), 1);
+
+	// how far this fragment moved since the previous frame, in texture coordinates
+	out_velocity = vec4((position_clip.xy / position_clip.w - prev_position_clip.xy / prev_position_clip.w) * 0.5, 0, 0);
+	out_selection = vec4(selected);
+	out_emissive = vec4(emissive);
 }
 "
 	}
+	overridable!();
+}
+
+mod fs_depth {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+void main() {}
+"
+	}
+	overridable!();
 }
 
 mod vs_history {
@@ -236,9 +619,10 @@ void main() {
 }
 "
 	}
+	overridable!();
 }
 
-mod fs_history {
+pub(super) mod fs_history {
 	::vulkano_shaders::shader!{
 		ty: "fragment",
 		src: "#version 450
@@ -249,20 +633,84 @@ layout(set = 0, binding = 1) uniform sampler2D prevOut;
 layout(set = 0, binding = 2, input_attachment_index = 0) uniform subpassInput albedo;
 layout(set = 0, binding = 3, input_attachment_index = 1) uniform subpassInput normal;
 layout(set = 0, binding = 4, input_attachment_index = 2) uniform subpassInput depth;
+layout(set = 0, binding = 5, input_attachment_index = 3) uniform subpassInput emissive;
 layout(set = 1, binding = 0) uniform CameraPos { vec3 camera_pos; };
 layout(set = 1, binding = 1) uniform CameraRot { vec4 camera_rot; };
-layout(set = 1, binding = 2) uniform CameraProj { vec4 camera_proj; };
+layout(set = 1, binding = 2) uniform CameraProj { vec4 camera_proj; vec4 camera_proj_offset; };
+
+// these must match light::CLUSTER_GRID and light::MAX_CLUSTER_LIGHTS
+const uvec3 CLUSTER_GRID = uvec3(16, 9, 24);
+const uint MAX_CLUSTER_LIGHTS = 63;
+
+// see light::LightData
+struct PointLight {
+	vec4 position_range;
+	vec4 color;
+	vec4 shadow_rect;
+	vec4 shadow_depth;
+};
+layout(set = 2, binding = 0) readonly buffer Lights { PointLight lights[]; };
+layout(set = 2, binding = 1) uniform sampler2D shadow_atlas;
+layout(set = 2, binding = 2) readonly buffer Clusters { uint clusters[]; };
+
+layout(push_constant) uniform ViewportInfo {
+	vec2 origin;
+	vec2 inv_size;
+	vec2 depth_to_ndc;
+	vec2 cluster_slices;
+	float focus_distance;
+	float aperture;
+} viewport;
+
+// the largest circle of confusion, in pixels. this must match fs_dof and fs_target.
+const float MAX_COC = 16.0;
 
 vec3 quat_mul(vec4 q, vec3 v) {
 	return cross(q.xyz, cross(q.xyz, v) + v * q.w) * 2.0 + v;
 }
 
+// 1.0 where the light reaches the fragment. the cube face the direction points through, and where, follow the cube
+// map face table in the vulkan spec, like CubeFace::camera.
+float point_shadow(PointLight light, vec3 light_to_fragment) {
+	vec3 d = light_to_fragment;
+	vec3 a = abs(d);
+	float ma;
+	float face;
+	vec2 sc_tc;
+	if (a.x >= a.y && a.x >= a.z) {
+		ma = a.x;
+		face = d.x > 0.0 ? 0.0 : 1.0;
+		sc_tc = d.x > 0.0 ? vec2(-d.z, -d.y) : vec2(d.z, -d.y);
+	} else if (a.y >= a.z) {
+		ma = a.y;
+		face = d.y > 0.0 ? 2.0 : 3.0;
+		sc_tc = d.y > 0.0 ? vec2(d.x, d.z) : vec2(d.x, -d.z);
+	} else {
+		ma = a.z;
+		face = d.z > 0.0 ? 4.0 : 5.0;
+		sc_tc = d.z > 0.0 ? vec2(d.x, -d.y) : vec2(-d.x, -d.y);
+	}
+
+	float resolution = light.shadow_rect.z;
+	vec2 face_texel = clamp((sc_tc / ma * 0.5 + 0.5) * resolution, vec2(0.5), vec2(resolution - 0.5));
+	vec2 texel = light.shadow_rect.xy + vec2(mod(face, 3.0), floor(face / 3.0)) * resolution + face_texel;
+	float depth = texelFetch(shadow_atlas, ivec2(texel), 0).r;
+
+	// the distance along the face's axis to the nearest surface, undoing the shadow camera's projection
+	float occluder = light.shadow_depth.y / (depth + light.shadow_depth.x);
+	return ma - light.shadow_rect.w > occluder ? 0.0 : 1.0;
+}
+
 void main() {
 	// stupid math library puts w first, so we flip it here
 	vec4 camera_rot = camera_rot.yzwx;
 
-	vec3 g_position_ds = vec3(gl_FragCoord.xy * resolution.zw, 2.0 * subpassLoad(depth).x) - 1.0;
-	vec3 g_position_cs = vec3(g_position_ds.xy / camera_proj.xy, -1.0) * camera_proj.w / (g_position_ds.z + camera_proj.z);
+	vec2 g_position_ds_xy = (gl_FragCoord.xy - viewport.origin) * viewport.inv_size * 2.0 - 1.0;
+	float g_position_ds_z = subpassLoad(depth).x * viewport.depth_to_ndc.x + viewport.depth_to_ndc.y;
+	vec3 g_position_ds = vec3(g_position_ds_xy, g_position_ds_z);
+	vec3 g_position_cs =
+		vec3((g_position_ds.xy + camera_proj_offset.xy) / camera_proj.xy, -1.0) * camera_proj.w /
+			(g_position_ds.z + camera_proj.z);
 	vec3 g_position_ws = quat_mul(camera_rot, g_position_cs) + camera_pos;
 
 	vec3 g_normal_cs = subpassLoad(normal).xyz;
@@ -278,26 +726,294 @@ void main() {
 	vec3 sunDir = normalize(vec3(-1, -4, 2));
 	light += sunColor * max(0, dot(g_normal_ws, sunDir));
 
-	// point light
-	float lightRadius = 5.0;
-	vec3 lightColor = vec3(0.7, 0.85, 1.0) * sqrt(lightRadius);
-	vec3 lightPos = vec3(14.5, -11, -28.5);
-	float lightDistance = distance(lightPos, g_position_ws);
-	vec3 lightDir = normalize(lightPos - g_position_ws);
-	float lightIntensity = max(0, dot(g_normal_ws, lightDir));
-	lightIntensity *= sqrt(max(0, (lightRadius - lightDistance) / lightRadius));
-	light += lightColor * lightIntensity / (lightDistance * lightDistance);
+	// point lights, from the list cs_light_cull made for the cluster this fragment is in
+	uvec2 tile = min(uvec2((g_position_ds.xy * 0.5 + 0.5) * vec2(CLUSTER_GRID.xy)), CLUSTER_GRID.xy - 1u);
+	float slice = log(-g_position_cs.z) * viewport.cluster_slices.x + viewport.cluster_slices.y;
+	uint cluster_z = uint(clamp(slice, 0.0, float(CLUSTER_GRID.z - 1u)));
+	uint cluster = ((cluster_z * CLUSTER_GRID.y + tile.y) * CLUSTER_GRID.x + tile.x) * (MAX_CLUSTER_LIGHTS + 1u);
+	for (uint i = 0; i < clusters[cluster]; i++) {
+		PointLight point = lights[clusters[cluster + 1u + i]];
+		float range = point.position_range.w;
+		vec3 to_light = point.position_range.xyz - g_position_ws;
+		float distance = length(to_light);
+		float intensity = max(0, dot(g_normal_ws, to_light / distance));
+		intensity *= sqrt(max(0, (range - distance) / range));
+		if (intensity > 0 && point.color.w > 0) {
+			intensity *= point_shadow(point, -to_light);
+		}
+		light += point.color.rgb * intensity / (distance * distance);
+	}
 
 	// ambient
 	light = max(light, 0.001);
 
+	// emissive surfaces glow in their own albedo, so they're added after the ambient floor
+	light += subpassLoad(emissive).r;
+
 	float exposure = 1.618;
 	vec3 out_hdr = g_albedo * light * exposure;
 	vec3 out_tonemapped = out_hdr / (1 + out_hdr);
-	out_color = vec4(out_tonemapped, 1);
+
+	// the circle of confusion is kept in alpha for the depth of field passes
+	float coc = viewport.aperture * abs(1.0 - viewport.focus_distance / -g_position_cs.z) / viewport.inv_size.y;
+	out_color = vec4(out_tonemapped, clamp(coc / MAX_COC, 0.0, 1.0));
 }
 "
 	}
+	overridable!();
+}
+
+pub(super) mod fs_forward {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+layout(location = 0) in vec3 position_cs;
+layout(location = 1) in vec3 normal_cs;
+layout(location = 2) in vec2 texcoord;
+layout(location = 3) in vec3 base_albedo;
+layout(location = 7) flat in float emissive;
+
+layout(location = 0) out vec4 out_color;
+
+layout(set = 1, binding = 1) uniform sampler2D tex_albedo;
+layout(set = 1, binding = 2) uniform sampler2D tex_normal;
+
+// this must match forward::MAX_FORWARD_LIGHTS
+const uint MAX_FORWARD_LIGHTS = 8;
+
+// everything is in camera space, so the fragment shader doesn't need the camera
+layout(set = 2, binding = 0) uniform ForwardLights {
+	vec4 light_position_range[MAX_FORWARD_LIGHTS];
+	vec4 light_color[MAX_FORWARD_LIGHTS];
+	vec3 sun_direction;
+	uint light_count;
+	vec3 sun_color;
+	vec3 ambient;
+};
+
+// set when the target format is linear, so the hardware won't encode to srgb for us
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+
+mat3 tangent_frame(vec3 fWorldNormal, vec3 vPosition, vec2 vTexCoord) {
+	vec3 dxPosition = dFdx(vPosition);
+	vec3 dyPosition = dFdy(vPosition);
+	vec2 dxTexCoord = dFdx(vTexCoord);
+	vec2 dyTexCoord = dFdy(vTexCoord);
+	if (dot(dxTexCoord, dxTexCoord) == 0) dxTexCoord = vec2(1, 0);
+	if (dot(dyTexCoord, dyTexCoord) == 0) dyTexCoord = vec2(0, -1);
+	vec3 dxPosPerp = cross(fWorldNormal, dxPosition);
+	vec3 dyPosPerp = cross(dyPosition, fWorldNormal);
+	vec3 fTangent = dxPosPerp * dyTexCoord.x + dyPosPerp * dxTexCoord.x;
+	vec3 fBitangent = dxPosPerp * dyTexCoord.y + dyPosPerp * dxTexCoord.y;
+	float tangentScale = inversesqrt(max(dot(fTangent, fTangent), dot(fBitangent, fBitangent)));
+	return mat3(fTangent * tangentScale, fBitangent * tangentScale, fWorldNormal);
+}
+
+void main() {
+	vec4 albedo = texture(tex_albedo, texcoord);
+	vec3 normal_ts = texture(tex_normal, texcoord).xyz * 2.0 - 1.0;
+	mat3 tbn = tangent_frame(normalize(normal_cs), position_cs, texcoord);
+	vec3 normal = normalize(tbn * normal_ts);
+	albedo.rgb = mix(base_albedo, albedo.rgb, albedo.a);
+
+	vec3 light = sun_color * max(0, dot(normal, sun_direction));
+	for (uint i = 0; i < light_count; i++) {
+		float range = light_position_range[i].w;
+		vec3 to_light = light_position_range[i].xyz - position_cs;
+		float distance = length(to_light);
+		float intensity = max(0, dot(normal, to_light / distance));
+		intensity *= sqrt(max(0, (range - distance) / range));
+		light += light_color[i].rgb * intensity / (distance * distance);
+	}
+	light = max(light, ambient);
+	light += emissive;
+
+	// the same exposure and tonemapping as fs_history, so a scene looks about the same in either batch
+	float exposure = 1.618;
+	vec3 hdr = albedo.rgb * light * exposure;
+	vec3 color = hdr / (1 + hdr);
+	out_color = vec4(ENCODE_SRGB ? pow(color, vec3(1.0 / 2.2)) : color, 1);
+}
+"
+	}
+	overridable!();
+}
+
+pub(super) mod cs_light_cull {
+	::vulkano_shaders::shader!{
+		ty: "compute",
+		src: "#version 450
+// these must match light::CLUSTER_GRID and light::MAX_CLUSTER_LIGHTS
+const uvec3 CLUSTER_GRID = uvec3(16, 9, 24);
+const uint MAX_CLUSTER_LIGHTS = 63;
+
+// clusters split a camera's view across, down, and in depth. this lists the lights that reach each one, so the history
+// pass only shades each pixel with those.
+layout(local_size_x = 16, local_size_y = 9, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) uniform CameraPos { vec3 camera_pos; };
+layout(set = 0, binding = 1) uniform CameraRot { vec4 camera_rot; };
+layout(set = 0, binding = 2) uniform CameraProj { vec4 camera_proj; vec4 camera_proj_offset; };
+
+// see light::LightData
+struct PointLight {
+	vec4 position_range;
+	vec4 color;
+	vec4 shadow_rect;
+	vec4 shadow_depth;
+};
+layout(set = 0, binding = 3) readonly buffer Lights { PointLight lights[]; };
+layout(set = 0, binding = 4) writeonly buffer Clusters { uint clusters[]; };
+
+layout(push_constant) uniform Cull {
+	vec2 slices;
+	uint light_count;
+} cull;
+
+vec3 quat_mul(vec4 q, vec3 v) {
+	return cross(q.xyz, cross(q.xyz, v) + v * q.w) * 2.0 + v;
+}
+
+void main() {
+	uvec3 id = gl_GlobalInvocationID;
+	uint cluster = ((id.z * CLUSTER_GRID.y + id.y) * CLUSTER_GRID.x + id.x) * (MAX_CLUSTER_LIGHTS + 1u);
+
+	// the cluster's bounds in camera space. slices are spaced so that slice = log(depth) * slices.x + slices.y.
+	float near = exp((float(id.z) - cull.slices.y) / cull.slices.x);
+	float far = exp((float(id.z + 1u) - cull.slices.y) / cull.slices.x);
+	vec2 tile_min = (vec2(id.xy) / vec2(CLUSTER_GRID.xy) * 2.0 - 1.0 + camera_proj_offset.xy) / camera_proj.xy;
+	vec2 tile_max = (vec2(id.xy + 1u) / vec2(CLUSTER_GRID.xy) * 2.0 - 1.0 + camera_proj_offset.xy) / camera_proj.xy;
+	vec3 box_min = vec3(min(tile_min * near, tile_min * far), -far);
+	vec3 box_max = vec3(max(tile_max * near, tile_max * far), -near);
+
+	// stupid math library puts w first, so we flip it here, and invert it to go from world space to camera space
+	vec4 camera_rot = camera_rot.yzwx;
+	vec4 inv_camera_rot = vec4(-camera_rot.xyz, camera_rot.w);
+
+	// lights are sorted nearest first, so a full cluster drops the farthest ones
+	uint count = 0;
+	for (uint i = 0; i < cull.light_count && count < MAX_CLUSTER_LIGHTS; i++) {
+		vec4 position_range = lights[i].position_range;
+		vec3 center = quat_mul(inv_camera_rot, position_range.xyz - camera_pos);
+		vec3 offset = center - clamp(center, box_min, box_max);
+		if (dot(offset, offset) < position_range.w * position_range.w) {
+			clusters[cluster + 1u + count] = i;
+			count++;
+		}
+	}
+	clusters[cluster] = count;
+}
+"
+	}
+	overridable!();
+}
+
+pub(super) mod cs_draw_cull {
+	::vulkano_shaders::shader!{
+		ty: "compute",
+		src: "#version 450
+// tests each object of a mesh group against a camera's frustum, and copies the draws of the visible ones to the front
+// of the visible list. the rest of the list was zeroed, so its draws have no instances.
+layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+// see mesh::group::GroupObject
+struct GroupObject {
+	mat4 model;
+	vec4 position_offset;
+	vec4 position_scale;
+	vec4 bounds;
+};
+struct DrawCommand {
+	uint index_count;
+	uint instance_count;
+	uint first_index;
+	uint vertex_offset;
+	uint first_instance;
+};
+layout(set = 0, binding = 0) readonly buffer Objects { GroupObject objects[]; };
+layout(set = 0, binding = 1) readonly buffer Draws { DrawCommand draws[]; };
+layout(set = 0, binding = 2) writeonly buffer Visible { DrawCommand visible[]; };
+layout(set = 0, binding = 3) buffer VisibleCount { uint visible_count; };
+
+// the planes are in the order of Camera::frustum_planes, with their normals pointing inward
+layout(push_constant) uniform DrawCull {
+	vec4 planes[6];
+	uint object_count;
+} cull;
+
+void main() {
+	uint i = gl_GlobalInvocationID.x;
+	if (i >= cull.object_count) {
+		return;
+	}
+
+	// bounds are a world space sphere
+	vec4 bounds = objects[i].bounds;
+	for (int plane = 0; plane < 6; plane++) {
+		if (dot(cull.planes[plane].xyz, bounds.xyz) + cull.planes[plane].w < -bounds.w) {
+			return;
+		}
+	}
+
+	visible[atomicAdd(visible_count, 1u)] = draws[i];
+}
+"
+	}
+	overridable!();
+}
+
+pub(super) mod cs_morph {
+	::vulkano_shaders::shader!{
+		ty: "compute",
+		src: "#version 450
+// this must match morph::MORPH_GROUP_SIZE
+layout(local_size_x = 64) in;
+
+// vec3 arrays would be padded to 16 bytes in a storage buffer, so the tightly packed vertex data is read as floats
+layout(set = 0, binding = 0) readonly buffer BasePositions { float base_positions[]; };
+layout(set = 0, binding = 1) readonly buffer BaseNormals { float base_normals[]; };
+// every vertex of the first target, then every vertex of the second, and so on
+layout(set = 0, binding = 2) readonly buffer PositionDeltas { float position_deltas[]; };
+layout(set = 0, binding = 3) readonly buffer NormalDeltas { float normal_deltas[]; };
+layout(set = 0, binding = 4) writeonly buffer Positions { float positions[]; };
+layout(set = 0, binding = 5) writeonly buffer Normals { float normals[]; };
+layout(set = 1, binding = 0) readonly buffer Weights { float weights[]; };
+
+layout(push_constant) uniform Morph {
+	uint vertex_count;
+	uint target_count;
+} morph;
+
+void main() {
+	uint vertex = gl_GlobalInvocationID.x;
+	if (vertex >= morph.vertex_count) {
+		return;
+	}
+
+	uint i = vertex * 3u;
+	vec3 position = vec3(base_positions[i], base_positions[i + 1u], base_positions[i + 2u]);
+	vec3 normal = vec3(base_normals[i], base_normals[i + 1u], base_normals[i + 2u]);
+	for (uint target = 0; target < morph.target_count; target++) {
+		float weight = weights[target];
+		if (weight == 0.0) {
+			continue;
+		}
+		uint delta = (target * morph.vertex_count + vertex) * 3u;
+		position += weight * vec3(position_deltas[delta], position_deltas[delta + 1u], position_deltas[delta + 2u]);
+		normal += weight * vec3(normal_deltas[delta], normal_deltas[delta + 1u], normal_deltas[delta + 2u]);
+	}
+	normal = normalize(normal);
+
+	positions[i] = position.x;
+	positions[i + 1u] = position.y;
+	positions[i + 2u] = position.z;
+	normals[i] = normal.x;
+	normals[i + 1u] = normal.y;
+	normals[i + 2u] = normal.z;
+}
+"
+	}
+	overridable!();
 }
 
 mod vs_target {
@@ -311,19 +1027,484 @@ void main() {
 }
 "
 	}
+	overridable!();
 }
 
-mod fs_target {
+pub(super) mod fs_target {
 	::vulkano_shaders::shader!{
 		ty: "fragment",
 		src: "#version 450
 layout(location = 0) out vec4 out_color;
 
-layout(set = 0, binding = 0, input_attachment_index = 0) uniform subpassInput color;
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D dof;
+layout(set = 0, binding = 2) uniform sampler2D selection;
+layout(set = 0, binding = 3) uniform sampler2D bloom;
+layout(set = 1, binding = 0) uniform sampler3D lut;
+
+layout(push_constant) uniform TargetInfo {
+	// the viewport's origin in pixels, then one over its size
+	vec4 viewport;
+	vec4 outline_color;
+	// the size of the gbuffers' rendered area relative to the target
+	vec2 render_scale;
+	uint upscale_nearest;
+	float grading_blend;
+	uint depth_of_field;
+	float outline_thickness;
+	float chromatic_aberration;
+	float vignette_intensity;
+	float vignette_smoothness;
+	float grain_intensity;
+	uint grain_seed;
+	uint calibrate;
+	float brightness;
+	float contrast;
+	float inv_gamma;
+	float bloom_intensity;
+} info;
 
+// set when the target format is linear, so the hardware won't encode to srgb for us
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+
+const int MAX_COC = 16;
+// these must match fs_bloom
+const int BLOOM_RADIUS = 16;
+const float BLOOM_SIGMA = 6.0;
+
+// where this pixel is in the gbuffers, kept half a texel inside the viewport's rendered area so filtering doesn't
+// pick up pixels from outside it
+vec2 source_position() {
+	vec2 viewport_min = info.viewport.xy * info.render_scale;
+	vec2 viewport_max = (info.viewport.xy + 1.0 / info.viewport.zw) * info.render_scale;
+	return clamp(gl_FragCoord.xy * info.render_scale, viewport_min + 0.5, viewport_max - 0.5);
+}
+
+// the vertical half of the depth of field blur. fs_dof has already blurred each row.
+vec3 depth_of_field(vec4 value) {
+	ivec2 size = textureSize(dof, 0);
+	ivec2 coord = ivec2(source_position());
+
+	vec3 sum = vec3(0);
+	float weight = 0;
+	for (int i = -MAX_COC; i <= MAX_COC; i++) {
+		vec4 s = texelFetch(dof, ivec2(coord.x, clamp(coord.y + i, 0, size.y - 1)), 0);
+		float w = step(float(abs(i)), s.a * MAX_COC);
+		sum += s.rgb * w;
+		weight += w;
+	}
+
+	return mix(value.rgb, sum / max(weight, 1.0), clamp(value.a * MAX_COC, 0.0, 1.0));
+}
+
+// the vertical half of the bloom blur. fs_bloom has already picked out the bright parts of the image and blurred each
+// row.
+vec3 glow() {
+	ivec2 size = textureSize(bloom, 0);
+	ivec2 coord = ivec2(source_position());
+
+	vec3 sum = vec3(0);
+	float weight = 0;
+	for (int i = -BLOOM_RADIUS; i <= BLOOM_RADIUS; i++) {
+		float w = exp(-float(i * i) / (2.0 * BLOOM_SIGMA * BLOOM_SIGMA));
+		sum += texelFetch(bloom, ivec2(coord.x, clamp(coord.y + i, 0, size.y - 1)), 0).rgb * w;
+		weight += w;
+	}
+
+	return sum / weight;
+}
+
+// how much of the outline covers this pixel. pixels outside selected meshes are covered when a selected pixel is
+// within the outline's thickness.
+float outline() {
+	ivec2 size = textureSize(selection, 0);
+	ivec2 coord = ivec2(source_position());
+	if (texelFetch(selection, coord, 0).r > 0.5) {
+		return 0.0;
+	}
+
+	int radius = int(ceil(info.outline_thickness));
+	for (int y = -radius; y <= radius; y++) {
+		for (int x = -radius; x <= radius; x++) {
+			if (length(vec2(x, y)) <= info.outline_thickness) {
+				ivec2 neighbor = clamp(coord + ivec2(x, y), ivec2(0), size - 1);
+				if (texelFetch(selection, neighbor, 0).r > 0.5) {
+					return info.outline_color.a;
+				}
+			}
+		}
+	}
+	return 0.0;
+}
+
+// -1 to 1 across the viewport
+vec2 viewport_position() {
+	return (gl_FragCoord.xy - info.viewport.xy) * info.viewport.zw * 2.0 - 1.0;
+}
+
+// red and blue are pushed apart toward the edges of the viewport, like a cheap lens
+vec3 chromatic_aberration(vec3 rgb) {
+	vec2 offset = viewport_position() * info.chromatic_aberration / vec2(textureSize(color, 0));
+	vec2 uv = source_position() / vec2(textureSize(color, 0));
+	return vec3(texture(color, uv + offset).r, rgb.g, texture(color, uv - offset).b);
+}
+
+float vignette() {
+	float distance = length(viewport_position()) / sqrt(2.0);
+	float smoothness = max(info.vignette_smoothness, 0.001);
+	return 1.0 - info.vignette_intensity * smoothstep(1.0 - smoothness, 1.0, distance);
+}
+
+float grain() {
+	vec2 seed = gl_FragCoord.xy + float(info.grain_seed % 1024u) * vec2(17.0, 31.0);
+	return fract(sin(dot(seed, vec2(12.9898, 78.233))) * 43758.5453) - 0.5;
+}
+
+void main() {
+	// at native resolution, both of these read the texel under the pixel
+	vec4 value =
+		info.upscale_nearest != 0
+			? texelFetch(color, ivec2(source_position()), 0)
+			: texture(color, source_position() / vec2(textureSize(color, 0)));
+	if (info.chromatic_aberration > 0.0) {
+		value.rgb = chromatic_aberration(value.rgb);
+	}
+	vec3 rgb = info.depth_of_field != 0 ? depth_of_field(value) : value.rgb;
+	if (info.bloom_intensity > 0.0) {
+		rgb += glow() * info.bloom_intensity;
+	}
+	if (info.vignette_intensity > 0.0) {
+		rgb *= vignette();
+	}
+
+	// luts are authored for display colors, so grading happens in srgb
+	vec3 encoded = pow(rgb, vec3(1.0 / 2.2));
+	if (info.grading_blend > 0.0) {
+		float size = float(textureSize(lut, 0).x);
+		vec3 graded = texture(lut, encoded * (size - 1.0) / size + 0.5 / size).rgb;
+		encoded = mix(encoded, graded, info.grading_blend);
+	}
+
+	encoded += grain() * info.grain_intensity;
+
+	// this must match DisplayCalibration::apply
+	if (info.calibrate != 0) {
+		encoded = clamp((encoded - 0.5) * info.contrast + 0.5 + info.brightness, 0.0, 1.0);
+		encoded = pow(encoded, vec3(info.inv_gamma));
+	}
+
+	// outlines are drawn last, so their color is exact
+	if (info.outline_thickness > 0.0) {
+		encoded = mix(encoded, info.outline_color.rgb, outline());
+	}
+
+	out_color = vec4(ENCODE_SRGB ? encoded : pow(encoded, vec3(2.2)), 1.0);
+}
+"
+	}
+	overridable!();
+}
+
+pub(super) mod fs_motion_blur {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+layout(location = 0) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D velocity;
+
+layout(push_constant) uniform MotionBlur {
+	uint samples;
+	float shutter_scale;
+} blur;
+
+// samples along the path each pixel moved since the previous frame, centered on where it is now. alpha holds the
+// circle of confusion for the depth of field passes, so it's passed through.
 void main() {
-	out_color = subpassLoad(color);
+	ivec2 coord = ivec2(gl_FragCoord.xy);
+	vec4 center = texelFetch(color, coord, 0);
+	if (blur.samples <= 1) {
+		out_color = center;
+		return;
+	}
+
+	vec2 uv = gl_FragCoord.xy / vec2(textureSize(color, 0));
+	vec2 path = texelFetch(velocity, coord, 0).xy * blur.shutter_scale;
+
+	vec3 sum = vec3(0);
+	for (uint i = 0; i < blur.samples; i++) {
+		sum += texture(color, uv + path * (float(i) / float(blur.samples - 1) - 0.5)).rgb;
+	}
+
+	out_color = vec4(sum / float(blur.samples), center.a);
+}
+"
+	}
+	overridable!();
+}
+
+mod fs_dof {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+layout(location = 0) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+
+const int MAX_COC = 16;
+
+// the horizontal half of the depth of field blur. each sample spreads over its own circle of confusion, which is kept
+// in alpha, so sharp pixels don't get smeared over their neighbors.
+void main() {
+	ivec2 size = textureSize(color, 0);
+	ivec2 coord = ivec2(gl_FragCoord.xy);
+
+	vec3 sum = vec3(0);
+	float weight = 0;
+	for (int i = -MAX_COC; i <= MAX_COC; i++) {
+		vec4 s = texelFetch(color, ivec2(clamp(coord.x + i, 0, size.x - 1), coord.y), 0);
+		float w = step(float(abs(i)), s.a * MAX_COC);
+		sum += s.rgb * w;
+		weight += w;
+	}
+
+	out_color = vec4(sum / max(weight, 1.0), texelFetch(color, coord, 0).a);
+}
+"
+	}
+	overridable!();
+}
+
+mod fs_bloom {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+layout(location = 0) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+
+layout(push_constant) uniform Bloom {
+	float threshold;
+} bloom;
+
+// these must match fs_target
+const int BLOOM_RADIUS = 16;
+const float BLOOM_SIGMA = 6.0;
+
+// the part of a color that's brighter than the threshold, which is the part that glows
+vec3 bright(vec3 rgb) {
+	float brightness = max(rgb.r, max(rgb.g, rgb.b));
+	return rgb * max(brightness - bloom.threshold, 0.0) / max(brightness, 0.0001);
+}
+
+// the horizontal half of the bloom blur. fs_target blurs each column and adds the result.
+void main() {
+	ivec2 size = textureSize(color, 0);
+	ivec2 coord = ivec2(gl_FragCoord.xy);
+
+	vec3 sum = vec3(0);
+	float weight = 0;
+	for (int i = -BLOOM_RADIUS; i <= BLOOM_RADIUS; i++) {
+		float w = exp(-float(i * i) / (2.0 * BLOOM_SIGMA * BLOOM_SIGMA));
+		sum += bright(texelFetch(color, ivec2(clamp(coord.x + i, 0, size.x - 1), coord.y), 0).rgb) * w;
+		weight += w;
+	}
+
+	out_color = vec4(sum / weight, 1.0);
+}
+"
+	}
+	overridable!();
+}
+
+mod vs_water {
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		src: "#version 450
+layout(location = 0) in vec2 position;
+
+layout(location = 0) out vec3 out_position_ws;
+layout(location = 1) out vec3 out_normal_ws;
+
+layout(set = 0, binding = 0) uniform CameraPos { vec3 camera_pos; };
+layout(set = 0, binding = 1) uniform CameraRot { vec4 camera_rot; };
+layout(set = 0, binding = 2) uniform CameraProj { vec4 camera_proj; vec4 camera_proj_offset; };
+
+// see water::Water::uniform_bytes. each wave is its direction, steepness, and wavelength, and waves with no length are
+// unused. the length of waves must match water::MAX_WAVES.
+layout(set = 1, binding = 0) uniform WaterParams {
+	vec4 waves[4];
+	vec3 center;
+	float time;
+	vec2 size;
+	float fog_density;
+	float foam_distance;
+	vec3 shallow_color;
+	uint has_reflection;
+	vec3 deep_color;
+	vec3 sky_color;
+};
+
+const float PI = 3.14159265;
+const float GRAVITY = 9.8;
+
+vec4 quat_inv(vec4 quat) {
+	return vec4(-quat.xyz, quat.w) / dot(quat, quat);
+}
+
+vec3 quat_mul(vec4 quat, vec3 vec) {
+	return cross(quat.xyz, cross(quat.xyz, vec) + vec * quat.w) * 2.0 + vec;
+}
+
+vec4 perspective(vec4 proj, vec2 offset, vec3 pos) {
+	return vec4(pos.xy * proj.xy + pos.z * offset, pos.z * proj.z + proj.w, -pos.z);
+}
+
+void main() {
+	// stupid math library puts w first, so we flip it here
+	vec4 camera_rot = camera_rot.yzwx;
+
+	vec3 position_ws = center + vec3(position.x * size.x, 0.0, position.y * size.y);
+	vec2 rest = position_ws.xz;
+	vec3 tangent = vec3(1, 0, 0);
+	vec3 bitangent = vec3(0, 0, 1);
+
+	// each wave moves the surface in a circle, and the tangents follow from the derivatives of that
+	for (int i = 0; i < 4; i++) {
+		vec4 wave = waves[i];
+		if (wave.w <= 0.0) {
+			continue;
+		}
+		vec2 dir = wave.xy;
+		float steepness = wave.z;
+		float k = 2.0 * PI / wave.w;
+		float speed = sqrt(GRAVITY / k);
+		float f = k * (dot(dir, rest) - speed * time);
+		float amplitude = steepness / k;
+		position_ws += vec3(dir.x * amplitude * cos(f), amplitude * sin(f), dir.y * amplitude * cos(f));
+		float s = steepness * sin(f);
+		float c = steepness * cos(f);
+		tangent += vec3(-dir.x * dir.x * s, dir.x * c, -dir.x * dir.y * s);
+		bitangent += vec3(-dir.x * dir.y * s, dir.y * c, -dir.y * dir.y * s);
+	}
+
+	out_position_ws = position_ws;
+	out_normal_ws = normalize(cross(bitangent, tangent));
+	vec3 position_cs = quat_mul(quat_inv(camera_rot), position_ws - camera_pos);
+	gl_Position = perspective(camera_proj, camera_proj_offset.xy, position_cs);
+}
+"
+	}
+	overridable!();
+}
+
+pub(super) mod fs_water {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+layout(location = 0) in vec3 position_ws;
+layout(location = 1) in vec3 normal_ws;
+
+layout(location = 0) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform CameraPos { vec3 camera_pos; };
+layout(set = 0, binding = 1) uniform CameraRot { vec4 camera_rot; };
+layout(set = 0, binding = 2) uniform CameraProj { vec4 camera_proj; vec4 camera_proj_offset; };
+
+// see vs_water
+layout(set = 1, binding = 0) uniform WaterParams {
+	vec4 waves[4];
+	vec3 center;
+	float time;
+	vec2 size;
+	float fog_density;
+	float foam_distance;
+	vec3 shallow_color;
+	uint has_reflection;
+	vec3 deep_color;
+	vec3 sky_color;
+};
+layout(set = 1, binding = 1, input_attachment_index = 0) uniform subpassInput depth;
+layout(set = 1, binding = 2) uniform sampler2D reflection;
+
+layout(push_constant) uniform ViewportInfo {
+	vec2 origin;
+	vec2 inv_size;
+	vec2 depth_to_ndc;
+} viewport;
+
+// how far waves push the reflection around, in texture coordinates
+const float REFLECTION_DISTORTION = 0.03;
+
+vec4 quat_inv(vec4 quat) {
+	return vec4(-quat.xyz, quat.w) / dot(quat, quat);
+}
+
+vec3 quat_mul(vec4 quat, vec3 vec) {
+	return cross(quat.xyz, cross(quat.xyz, vec) + vec * quat.w) * 2.0 + vec;
+}
+
+// the same exposure and curve fs_history uses, so the water sits in with the lit scene
+vec3 tonemap(vec3 color) {
+	vec3 hdr = color * 1.618;
+	return hdr / (1 + hdr);
+}
+
+void main() {
+	// stupid math library puts w first, so we flip it here
+	vec4 camera_rot = camera_rot.yzwx;
+
+	// the depth buffer is only read in this subpass, so the water is depth tested here
+	vec2 uv = (gl_FragCoord.xy - viewport.origin) * viewport.inv_size;
+	float scene_ds_z = subpassLoad(depth).x * viewport.depth_to_ndc.x + viewport.depth_to_ndc.y;
+	float scene_depth = camera_proj.w / (scene_ds_z + camera_proj.z);
+	vec3 view_ws = position_ws - camera_pos;
+	float water_depth = -quat_mul(quat_inv(camera_rot), view_ws).z;
+	if (water_depth > scene_depth) {
+		discard;
+	}
+
+	// how much water the view passes through before it reaches whatever is behind it
+	float distance = length(view_ws);
+	float thickness = distance * (scene_depth / water_depth - 1.0);
+
+	vec3 normal = normalize(normal_ws);
+	if (dot(normal, view_ws) > 0.0) {
+		normal = -normal;
+	}
+	// schlick's approximation, with water reflecting 2% of light head on
+	float fresnel = 0.02 + 0.98 * pow(1.0 - max(dot(normal, -view_ws / distance), 0.0), 5.0);
+
+	float fog = 1.0 - exp(-fog_density * thickness);
+	vec3 body = tonemap(mix(shallow_color, deep_color, fog));
+
+	// reflections are rendered upside down by Water::reflection_camera, and already tonemapped
+	vec3 reflected = tonemap(sky_color);
+	if (has_reflection != 0) {
+		vec2 reflection_uv = vec2(uv.x, 1.0 - uv.y) + normal.xz * REFLECTION_DISTORTION;
+		reflected = texture(reflection, clamp(reflection_uv, 0.0, 1.0)).rgb;
+	}
+
+	vec3 color = mix(body, reflected, fresnel);
+	float alpha = mix(fog, 1.0, fresnel);
+
+	// foam gathers where the water thins out, in bands that wash in toward the shore
+	if (foam_distance > 0.0) {
+		float shore = 1.0 - clamp(thickness / foam_distance, 0.0, 1.0);
+		float bands = sin(thickness / foam_distance * 12.0 - time * 2.0 + dot(position_ws.xz, vec2(0.7, 0.5)));
+		float foam = shore * smoothstep(0.2, 0.8, bands * 0.5 + 0.5 + shore * 0.5);
+		color = mix(color, vec3(0.9), foam);
+		alpha = max(alpha, foam);
+	}
+
+	// fades the line where the water meets the ground
+	alpha *= clamp(thickness * 20.0, 0.0, 1.0);
+
+	// alpha is masked off when blending, so the circle of confusion underneath is kept
+	out_color = vec4(color, alpha);
 }
 "
 	}
+	overridable!();
 }
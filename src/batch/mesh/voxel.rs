@@ -0,0 +1,442 @@
+use crate::batch::mesh::{ Mesh, MeshBuildError, MeshBuilder, MeshRenderPass, render_pass::GBufferStage };
+use crate::window::Window;
+use cgmath::{ vec3, InnerSpace, Vector3 };
+use std::{ collections::HashMap, sync::Arc };
+use vulkano::{
+	OomError,
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::DescriptorSet,
+	memory::DeviceMemoryAllocError,
+	sync::{ self, GpuFuture },
+};
+
+/// How many voxels a chunk has along each side. Each chunk is remeshed as a whole.
+pub const CHUNK_SIZE: usize = 16;
+const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+/// A kind of block. `Voxel::AIR` is empty space, and every other value is solid.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Voxel(pub u16);
+impl Voxel {
+	pub const AIR: Voxel = Voxel(0);
+
+	pub fn is_air(self) -> bool {
+		self == Voxel::AIR
+	}
+}
+
+/// A grid of voxels, split into chunks that are each drawn as one mesh. Chunks that are entirely air take no memory.
+///
+/// Editing a voxel marks its chunk dirty, along with the chunks next to it if the voxel is on their border, and
+/// `remesh` rebuilds the meshes of dirty chunks. Faces between solid voxels are left out, and neighboring faces of the
+/// same kind are merged into larger quads, so a flat floor of one kind is a couple of triangles per chunk.
+pub struct VoxelWorld {
+	render_pass: Arc<MeshRenderPass>,
+	voxel_size: f32,
+	/// Linear RGB for each kind of voxel, indexed by its value.
+	colors: Vec<[f32; 3]>,
+	chunks: HashMap<[i32; 3], Chunk>,
+	visible: bool,
+}
+impl VoxelWorld {
+	/// Creates an empty world. Voxel `[0, 0, 0]` spans from the origin to `voxel_size` along each axis.
+	pub fn new(render_pass: Arc<MeshRenderPass>, voxel_size: f32) -> Self {
+		Self { render_pass: render_pass, voxel_size: voxel_size, colors: vec![], chunks: HashMap::new(), visible: true }
+	}
+
+	pub fn voxel_size(&self) -> f32 {
+		self.voxel_size
+	}
+
+	/// Sets the linear base color of a kind of voxel. Kinds without a color are white. Chunks that have already been
+	/// meshed keep their colors until they're remeshed, so call `mark_all_dirty` to recolor them.
+	pub fn set_color(&mut self, voxel: Voxel, color: [f32; 3]) {
+		let index = voxel.0 as usize;
+		if self.colors.len() <= index {
+			self.colors.resize(index + 1, [1.0, 1.0, 1.0]);
+		}
+		self.colors[index] = color;
+	}
+
+	pub fn color(&self, voxel: Voxel) -> [f32; 3] {
+		self.colors.get(voxel.0 as usize).cloned().unwrap_or([1.0, 1.0, 1.0])
+	}
+
+	pub fn set_visible(&mut self, visible: bool) {
+		self.visible = visible;
+	}
+
+	pub fn is_visible(&self) -> bool {
+		self.visible
+	}
+
+	pub fn get(&self, position: [i32; 3]) -> Voxel {
+		let (key, local) = split(position);
+		self.chunks.get(&key).map_or(Voxel::AIR, |chunk| chunk.voxels[index(local)])
+	}
+
+	pub fn set(&mut self, position: [i32; 3], voxel: Voxel) {
+		let (key, local) = split(position);
+		if !self.chunks.contains_key(&key) {
+			if voxel.is_air() {
+				return;
+			}
+			self.chunks.insert(key, Chunk::new());
+		}
+
+		let chunk = self.chunks.get_mut(&key).unwrap();
+		let old = chunk.voxels[index(local)];
+		if old == voxel {
+			return;
+		}
+		chunk.voxels[index(local)] = voxel;
+		if old.is_air() {
+			chunk.solid_count += 1;
+		} else if voxel.is_air() {
+			chunk.solid_count -= 1;
+		}
+		chunk.dirty = true;
+
+		// the faces of neighboring chunks' border voxels depend on this one
+		for axis in 0..3 {
+			let mut neighbor = key;
+			if local[axis] == 0 {
+				neighbor[axis] -= 1;
+			} else if local[axis] == CHUNK_SIZE - 1 {
+				neighbor[axis] += 1;
+			} else {
+				continue;
+			}
+			if let Some(chunk) = self.chunks.get_mut(&neighbor) {
+				chunk.dirty = true;
+			}
+		}
+	}
+
+	/// Sets every voxel from `min` to `max`, inclusive.
+	pub fn fill(&mut self, min: [i32; 3], max: [i32; 3], voxel: Voxel) {
+		for z in min[2]..=max[2] {
+			for y in min[1]..=max[1] {
+				for x in min[0]..=max[0] {
+					self.set([x, y, z], voxel);
+				}
+			}
+		}
+	}
+
+	/// The voxel that contains a point in world space.
+	pub fn voxel_at(&self, point: Vector3<f32>) -> [i32; 3] {
+		let point = point / self.voxel_size;
+		[point.x.floor() as i32, point.y.floor() as i32, point.z.floor() as i32]
+	}
+
+	pub fn chunk_count(&self) -> usize {
+		self.chunks.len()
+	}
+
+	/// How many chunks have been edited since they were last meshed.
+	pub fn dirty_chunk_count(&self) -> usize {
+		self.chunks.values().filter(|chunk| chunk.dirty).count()
+	}
+
+	pub fn mark_all_dirty(&mut self) {
+		for chunk in self.chunks.values_mut() {
+			chunk.dirty = true;
+		}
+	}
+
+	/// Rebuilds the meshes of up to `max_chunks` dirty chunks, nearest to `focus` first, so edits near the player show
+	/// up before ones far away. The new meshes are drawn from the next frame on, so join the returned future, which
+	/// uploads them, with that frame's.
+	pub fn remesh(
+		&mut self,
+		window: &Window,
+		focus: Vector3<f32>,
+		max_chunks: usize,
+	) -> Result<Box<GpuFuture + Send + Sync>, DeviceMemoryAllocError> {
+		let chunk_extent = CHUNK_SIZE as f32 * self.voxel_size;
+		let mut dirty =
+			self.chunks.iter()
+				.filter(|(_, chunk)| chunk.dirty)
+				.map(|(&key, _)| {
+					let center = vec3(key[0] as f32 + 0.5, key[1] as f32 + 0.5, key[2] as f32 + 0.5) * chunk_extent;
+					(key, (center - focus).magnitude2())
+				})
+				.collect::<Vec<_>>();
+		dirty.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+
+		let mut future: Box<GpuFuture + Send + Sync> = Box::new(sync::now(window.device().device().clone()));
+		for (key, _) in dirty.into_iter().take(max_chunks) {
+			if self.chunks[&key].solid_count == 0 {
+				self.chunks.remove(&key);
+				continue;
+			}
+
+			let mesh =
+				match self.mesh_chunk(key).build(window, self.render_pass.clone()) {
+					Ok((mut mesh, mesh_future)) => {
+						let [x, y, z] = key;
+						mesh.set_position(vec3(x as f32, y as f32, z as f32) * chunk_extent);
+						mesh.reset_motion();
+						future = Box::new(future.join(mesh_future));
+						Some(mesh)
+					},
+					// buried chunks have no faces to draw
+					Err(MeshBuildError::Empty) => None,
+					Err(MeshBuildError::DeviceMemoryAllocError(err)) => return Err(err),
+				};
+
+			let chunk = self.chunks.get_mut(&key).unwrap();
+			chunk.mesh = mesh;
+			chunk.dirty = false;
+		}
+
+		Ok(future)
+	}
+
+	/// Greedily meshes a chunk into one submesh for each kind of voxel it has, in chunk space. The world remeshes
+	/// chunks itself with `remesh`, but this can be used to build collision or export a chunk.
+	pub fn mesh_chunk(&self, key: [i32; 3]) -> MeshBuilder {
+		let mut builder = MeshBuilder::new();
+		let chunk =
+			match self.chunks.get(&key) {
+				Some(chunk) => chunk,
+				None => return builder,
+			};
+
+		let mut quads = vec![];
+		let mut mask = vec![None; CHUNK_SIZE * CHUNK_SIZE];
+		for axis in 0..3 {
+			let u = (axis + 1) % 3;
+			let v = (axis + 2) % 3;
+			for &facing in &[1, -1] {
+				for slice in 0..CHUNK_SIZE {
+					// the voxels in this slice whose face on this side is uncovered
+					for j in 0..CHUNK_SIZE {
+						for i in 0..CHUNK_SIZE {
+							let mut local = [0; 3];
+							local[axis] = slice;
+							local[u] = i;
+							local[v] = j;
+							let voxel = chunk.voxels[index(local)];
+							let mut neighbor = [0; 3];
+							for a in 0..3 {
+								neighbor[a] = key[a] * CHUNK_SIZE as i32 + local[a] as i32;
+							}
+							neighbor[axis] += facing;
+							mask[j * CHUNK_SIZE + i] =
+								if !voxel.is_air() && self.get_near(chunk, key, neighbor).is_air() {
+									Some(voxel)
+								} else {
+									None
+								};
+						}
+					}
+
+					// merge runs along u, then grow them along v while the whole run matches
+					for j in 0..CHUNK_SIZE {
+						let mut i = 0;
+						while i < CHUNK_SIZE {
+							let voxel =
+								match mask[j * CHUNK_SIZE + i] {
+									Some(voxel) => voxel,
+									None => {
+										i += 1;
+										continue;
+									},
+								};
+							let mut width = 1;
+							while i + width < CHUNK_SIZE && mask[j * CHUNK_SIZE + i + width] == Some(voxel) {
+								width += 1;
+							}
+							let mut height = 1;
+							while j + height < CHUNK_SIZE &&
+								(i..i + width).all(|i| mask[(j + height) * CHUNK_SIZE + i] == Some(voxel))
+							{
+								height += 1;
+							}
+							for row in j..j + height {
+								for cell in &mut mask[row * CHUNK_SIZE + i..row * CHUNK_SIZE + i + width] {
+									*cell = None;
+								}
+							}
+
+							let mut origin = [0.0; 3];
+							origin[axis] = (slice + if facing > 0 { 1 } else { 0 }) as f32;
+							origin[u] = i as f32;
+							origin[v] = j as f32;
+							quads.push(Quad {
+								voxel: voxel,
+								origin: origin,
+								axes: [axis, u, v],
+								facing: facing,
+								size: [width as f32, height as f32],
+							});
+							i += width;
+						}
+					}
+				}
+			}
+		}
+
+		quads.sort_by_key(|quad| quad.voxel);
+		for (i, quad) in quads.iter().enumerate() {
+			quad.push_to(&mut builder, self.voxel_size);
+			if quads.get(i + 1).map_or(true, |next| next.voxel != quad.voxel) {
+				builder.end_submesh(self.color(quad.voxel));
+			}
+		}
+		builder
+	}
+
+	/// Finds the first solid voxel along a ray in world space, stepping through the grid a voxel at a time.
+	pub fn raycast(&self, origin: Vector3<f32>, direction: Vector3<f32>, max_distance: f32) -> Option<VoxelHit> {
+		if direction.magnitude2() == 0.0 {
+			return None;
+		}
+		let direction = direction.normalize();
+		let start = origin / self.voxel_size;
+		let mut position = self.voxel_at(origin);
+		let mut step = [0; 3];
+		// how far along the ray the next boundary on each axis is, and how far apart the boundaries are
+		let mut next = [std::f32::INFINITY; 3];
+		let mut spacing = [std::f32::INFINITY; 3];
+		for axis in 0..3 {
+			if direction[axis] > 0.0 {
+				step[axis] = 1;
+				spacing[axis] = 1.0 / direction[axis];
+				next[axis] = (position[axis] as f32 + 1.0 - start[axis]) * spacing[axis];
+			} else if direction[axis] < 0.0 {
+				step[axis] = -1;
+				spacing[axis] = -1.0 / direction[axis];
+				next[axis] = (start[axis] - position[axis] as f32) * spacing[axis];
+			}
+		}
+
+		let max_distance = max_distance / self.voxel_size;
+		let mut distance = 0.0;
+		let mut normal = [0; 3];
+		while distance <= max_distance {
+			if !self.get(position).is_air() {
+				return Some(VoxelHit { position: position, normal: normal, distance: distance * self.voxel_size });
+			}
+
+			let axis = (0..3).min_by(|&a, &b| next[a].partial_cmp(&next[b]).unwrap()).unwrap();
+			distance = next[axis];
+			next[axis] += spacing[axis];
+			position[axis] += step[axis];
+			normal = [0; 3];
+			normal[axis] = -step[axis];
+		}
+		None
+	}
+
+	/// Records the chunks' draws into the batch's G-buffer subpass.
+	pub(super) fn add_commands(
+		&mut self,
+		mut cmd: AutoCommandBufferBuilder,
+		render_pass: &MeshRenderPass,
+		camera_desc: impl DescriptorSet + Clone + Send + Sync + 'static,
+		state: &DynamicState,
+		stage: GBufferStage,
+	) -> Result<AutoCommandBufferBuilder, OomError> {
+		if !self.visible {
+			return Ok(cmd);
+		}
+
+		for mesh in self.chunks.values_mut().filter_map(|chunk| chunk.mesh.as_mut()) {
+			cmd = mesh.add_commands(cmd, render_pass, camera_desc.clone(), state, stage)?;
+		}
+		Ok(cmd)
+	}
+
+	/// Reads a voxel next to `chunk`, which is usually in the chunk itself.
+	fn get_near(&self, chunk: &Chunk, key: [i32; 3], position: [i32; 3]) -> Voxel {
+		let (neighbor_key, local) = split(position);
+		if neighbor_key == key { chunk.voxels[index(local)] } else { self.get(position) }
+	}
+}
+
+/// Where a ray hit a voxel world.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelHit {
+	/// The voxel that was hit.
+	pub position: [i32; 3],
+	/// The side of the voxel the ray entered through, pointing out of it. It's all zeroes if the ray started inside the
+	/// voxel. Adding it to `position` gives the voxel a block placed against the face would go in.
+	pub normal: [i32; 3],
+	/// In world units.
+	pub distance: f32,
+}
+
+/// Identifies a voxel world that has been added to a `MeshBatch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VoxelWorldId(pub(super) u64);
+
+struct Chunk {
+	voxels: Vec<Voxel>,
+	/// How many voxels aren't air, so empty chunks can be dropped.
+	solid_count: usize,
+	dirty: bool,
+	mesh: Option<Mesh>,
+}
+impl Chunk {
+	fn new() -> Self {
+		Self { voxels: vec![Voxel::AIR; CHUNK_VOLUME], solid_count: 0, dirty: true, mesh: None }
+	}
+}
+
+/// A merged face, in voxels from the chunk's corner.
+struct Quad {
+	voxel: Voxel,
+	origin: [f32; 3],
+	/// The axis the face points along, then the axes of its width and height.
+	axes: [usize; 3],
+	facing: i32,
+	size: [f32; 2],
+}
+impl Quad {
+	fn push_to(&self, builder: &mut MeshBuilder, voxel_size: f32) {
+		let [axis, u, v] = self.axes;
+		let mut normal = [0.0; 3];
+		normal[axis] = self.facing as f32;
+
+		let corner = |du: f32, dv: f32| {
+			let mut position = self.origin;
+			position[u] += du;
+			position[v] += dv;
+			[position[0] * voxel_size, position[1] * voxel_size, position[2] * voxel_size]
+		};
+		let [width, height] = self.size;
+		// texcoords count voxels, so a repeating texture tiles once per voxel
+		let a = builder.push_vertex(corner(0.0, 0.0), normal, [0.0, 0.0]);
+		let b = builder.push_vertex(corner(width, 0.0), normal, [width, 0.0]);
+		let c = builder.push_vertex(corner(width, height), normal, [width, height]);
+		let d = builder.push_vertex(corner(0.0, height), normal, [0.0, height]);
+
+		// u cross v is the axis, so this winds counterclockwise seen from the front of faces that point along it
+		if self.facing > 0 {
+			builder.push_indices(&[a, b, c, a, c, d]);
+		} else {
+			builder.push_indices(&[a, c, b, a, d, c]);
+		}
+	}
+}
+
+/// The chunk a voxel is in, and where it is in the chunk.
+fn split(position: [i32; 3]) -> ([i32; 3], [usize; 3]) {
+	let size = CHUNK_SIZE as i32;
+	(
+		[position[0].div_euclid(size), position[1].div_euclid(size), position[2].div_euclid(size)],
+		[
+			position[0].rem_euclid(size) as usize,
+			position[1].rem_euclid(size) as usize,
+			position[2].rem_euclid(size) as usize,
+		],
+	)
+}
+
+fn index(local: [usize; 3]) -> usize {
+	local[0] + CHUNK_SIZE * (local[1] + CHUNK_SIZE * local[2])
+}
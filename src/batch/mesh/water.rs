@@ -0,0 +1,159 @@
+use crate::camera::Camera;
+use crate::texture::Texture;
+use crate::uniform::{ LayoutRules, UniformWriter };
+use cgmath::{ vec2, vec3, InnerSpace, Quaternion, Vector2, Vector3 };
+use std::sync::Arc;
+use vulkano::{ impl_vertex, image::ImageViewAccess };
+
+/// The most waves a water plane can have. This must match vs_water.
+pub const MAX_WAVES: usize = 4;
+/// How many quads the water grid has along each side. Waves move the grid's vertices, so waves much shorter than a
+/// plane's size divided by this are lost between them.
+pub(super) const WATER_GRID: u32 = 128;
+
+/// A body of water, drawn as a plane of moving waves over the rest of the scene. It's drawn after the meshes are lit,
+/// so it isn't lit by the batch's lights, and its colors are as they appear in full light.
+///
+/// The water is transparent where it's shallow, so the ground shows through at shorelines, and fogs over toward
+/// `deep_color` the deeper the ground behind it is. Foam gathers where it meets the ground or objects standing in it.
+#[derive(Clone)]
+pub struct Water {
+	/// The center of the plane. Waves rise and fall around its height.
+	pub position: Vector3<f32>,
+	/// The width and length of the plane, along X and Z.
+	pub size: [f32; 2],
+	/// Only the first `MAX_WAVES` are used.
+	pub waves: Vec<GerstnerWave>,
+	/// Linear RGB, seen where the water is thin over the ground.
+	pub shallow_color: [f32; 3],
+	/// Linear RGB, seen where the ground is far below.
+	pub deep_color: [f32; 3],
+	/// How quickly the water turns from clear to `deep_color` the farther the ground is behind it, per world unit.
+	pub fog_density: f32,
+	/// How deep the water is still foamy at shorelines, in world units. 0.0 disables foam.
+	pub foam_distance: f32,
+	/// Linear RGB, reflected when the water has no reflection texture.
+	pub sky_color: [f32; 3],
+	/// Seconds of animation. Advance it every frame with `advance`.
+	pub time: f32,
+	reflection: Option<Arc<ImageViewAccess + Send + Sync + 'static>>,
+}
+impl Water {
+	pub fn new(position: Vector3<f32>, size: [f32; 2]) -> Self {
+		Self {
+			position: position,
+			size: size,
+			waves: vec![
+				GerstnerWave::new(vec2(1.0, 0.0), 8.0, 0.2),
+				GerstnerWave::new(vec2(0.6, 0.8), 5.0, 0.15),
+				GerstnerWave::new(vec2(-0.3, 1.0), 2.5, 0.1),
+			],
+			shallow_color: [0.1, 0.35, 0.35],
+			deep_color: [0.01, 0.05, 0.1],
+			fog_density: 0.5,
+			foam_distance: 0.3,
+			sky_color: [0.5, 0.65, 0.8],
+			time: 0.0,
+			reflection: None,
+		}
+	}
+
+	/// Moves the waves forward in time.
+	pub fn advance(&mut self, seconds: f32) {
+		self.time += seconds;
+	}
+
+	/// Reflects a texture instead of `sky_color`, usually a `TargetTexture` the scene was rendered into with
+	/// `reflection_camera`. It's read at the same place on screen as the water, so it should be the size of the target
+	/// the water is drawn to.
+	pub fn set_reflection(&mut self, texture: &Texture) {
+		self.reflection = Some(texture.image().clone());
+	}
+
+	/// Goes back to reflecting `sky_color`.
+	pub fn clear_reflection(&mut self) {
+		self.reflection = None;
+	}
+
+	/// A camera mirrored under the water's surface, for rendering planar reflections. Render the scene with it into a
+	/// `TargetTexture`, usually from a second batch holding the meshes worth reflecting but not the water, and pass
+	/// that to `set_reflection`. Anything under the water is reflected too, so it's best left out of that batch.
+	pub fn reflection_camera(&self, camera: &Camera) -> Camera {
+		let mut reflected = *camera;
+		let position = camera.position();
+		reflected.set_position(vec3(position.x, 2.0 * self.position.y - position.y, position.z));
+		// a rotation can't mirror, so this camera sees the reflection upside down, and fs_water flips it back
+		let rotation = camera.rotation();
+		reflected.set_rotation(Quaternion::from_sv(rotation.s, vec3(-rotation.v.x, rotation.v.y, -rotation.v.z)));
+		reflected
+	}
+
+	pub(super) fn reflection(&self) -> Option<&Arc<ImageViewAccess + Send + Sync + 'static>> {
+		self.reflection.as_ref()
+	}
+
+	/// The water's parameters, laid out like WaterParams in vs_water and fs_water.
+	pub(super) fn uniform_bytes(&self) -> Vec<u8> {
+		let mut waves = [[0.0; 4]; MAX_WAVES];
+		for (packed, wave) in waves.iter_mut().zip(&self.waves) {
+			let direction = if wave.direction.magnitude2() > 0.0 { wave.direction.normalize() } else { vec2(1.0, 0.0) };
+			*packed = [direction.x, direction.y, wave.steepness.max(0.0).min(1.0), wave.wavelength.max(0.0)];
+		}
+
+		UniformWriter::new(LayoutRules::Std140)
+			.array(&waves)
+			.field(&self.position)
+			.field(&self.time)
+			.field(&self.size)
+			.field(&self.fog_density.max(0.0))
+			.field(&self.foam_distance.max(0.0))
+			.field(&self.shallow_color)
+			.field(&(self.reflection.is_some() as u32))
+			.field(&self.deep_color)
+			.field(&self.sky_color)
+			.finish()
+	}
+}
+
+/// A wave that moves the water's surface in circles as it passes, bunching it up into sharp crests over flat troughs.
+/// Waves travel at the speed of deep water waves of their length.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct GerstnerWave {
+	/// The direction the wave travels along X and Z. It doesn't need to be normalized.
+	pub direction: Vector2<f32>,
+	/// The distance between crests, in world units.
+	pub wavelength: f32,
+	/// How sharp the crests are, from 0.0 for a flat surface to 1.0 for crests that come to a point. The height of the
+	/// wave is this times its wavelength divided by 2π. If the waves' steepness adds up to more than 1.0, crests can
+	/// loop over themselves.
+	pub steepness: f32,
+}
+impl GerstnerWave {
+	pub fn new(direction: Vector2<f32>, wavelength: f32, steepness: f32) -> Self {
+		Self { direction: direction, wavelength: wavelength, steepness: steepness }
+	}
+}
+
+/// Identifies a water plane that has been added to a `MeshBatch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WaterId(pub(super) u64);
+
+/// A point on the water grid, from -0.5 to 0.5 along each side. vs_water scales it to the plane.
+#[derive(Debug, Clone)]
+pub(super) struct WaterVertex { position: [f32; 2] }
+impl_vertex!(WaterVertex, position);
+
+/// The vertices and triangle list indices of a grid with `quads` quads along each side.
+pub(super) fn water_grid(quads: u32) -> (Vec<WaterVertex>, Vec<u32>) {
+	let vertices = (0..=quads)
+		.flat_map(|z| (0..=quads).map(move |x| (x, z)))
+		.map(|(x, z)| WaterVertex { position: [x as f32 / quads as f32 - 0.5, z as f32 / quads as f32 - 0.5] })
+		.collect();
+	let row = quads + 1;
+	let indices = (0..quads)
+		.flat_map(|z| (0..quads).map(move |x| z * row + x))
+		.flat_map(|i| vec![i, i + row, i + 1, i + 1, i + row, i + row + 1])
+		.collect();
+	(vertices, indices)
+}
@@ -1,40 +1,81 @@
+mod clip;
+mod console;
 mod font;
+mod lighting;
+mod material;
+mod node;
+mod panel;
+mod parallax;
 mod shaders;
+mod shapes;
 mod shared;
 mod sprite;
+mod sprite_buffer;
+mod stats_overlay;
+mod text;
+mod textures;
+mod ui_overlay;
 
+pub use self::clip::ClipGroup;
+pub use self::console::Console;
 pub use self::font::Font;
-pub use self::shaders::SpriteBatchShaders;
-pub use self::shared::SpriteBatchShared;
+pub use self::lighting::{ Light2D, LightCone, Lighting2D, MAX_OCCLUDER_SEGMENTS, Occluder2D };
+pub use self::material::{ SpriteMaterial, SpriteShader };
+pub use self::node::{ Inherited2D, Node2D };
+pub use self::panel::{ DropShadow, Panel, PanelFill };
+pub use self::parallax::{ ParallaxBackground, ParallaxLayer };
+pub use self::shaders::{ SpriteBatchShaders, UiVertex };
+pub use self::shapes::Shapes;
+pub use self::shared::{ BlendMode, SpriteBatchShared, SpriteCreationError };
 pub use self::sprite::Sprite;
+pub use self::sprite_buffer::SpriteBuffer;
+pub use self::stats_overlay::StatsOverlay;
+pub use self::text::{ DynamicText, StaticText };
+pub use self::textures::TextureSlot;
+pub use self::ui_overlay::{ UiInput, UiMesh, UiOverlay };
+pub(crate) use self::shaders::{ TileVertex, tile_vs };
 use crate::{ ImageFramebuffer, ObjectId, RenderTarget, window::Window };
-use std::sync::Arc;
+use self::lighting::LightBuffers;
+use crate::batch::{ BatchError, Region, StencilState, dynamic_state, stencil_format };
+use crate::uniform::{ LayoutRules, UniformWriter };
+use std::{ mem, sync::Arc };
 use vulkano::{
-	OomError,
 	buffer::{ BufferUsage, ImmutableBuffer },
-	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError },
+	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, DynamicState },
 	descriptor::{ DescriptorSet, PipelineLayoutAbstract, descriptor_set::PersistentDescriptorSet },
 	device::Queue,
+	format::ClearValue,
 	framebuffer::{ Framebuffer, FramebufferAbstract, FramebufferCreationError },
-	image::ImageViewAccess,
-	instance::QueueFamily,
+	image::{ AttachmentImage, ImageViewAccess },
 	memory::DeviceMemoryAllocError,
 	sync::GpuFuture,
 };
 
+/// Draws `Drawable2D`s to a render target. The per-frame state lives here and the pipelines live in the shared
+/// `SpriteBatchShared`, so batches are `Send` and can each be recorded on their own thread.
 pub struct SpriteBatch {
 	shared: Arc<SpriteBatchShared>,
 	sprites: Vec<Box<Drawable2D>>,
+	/// The variant of `shared` each sprite is drawn with, if it was added with a stencil state.
+	stencils: Vec<Option<Arc<SpriteBatchShared>>>,
 	framebuffers: Vec<ImageFramebuffer>,
+	/// Only used if the shared render pass has a stencil buffer.
+	stencil_image: Option<Arc<AttachmentImage>>,
 	target_id: ObjectId,
 	target_desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	target_size: [u32; 2],
+	viewport: Region,
+	scissor: Option<Region>,
+	lighting: Option<Lighting2D>,
+	light_buffers: Option<LightBuffers>,
+	clear_color: [f32; 4],
 }
 impl SpriteBatch {
 	pub fn new(
 		window: &Window,
 		target: &RenderTarget,
 		shared: Arc<SpriteBatchShared>
-	) -> Result<(Self, impl GpuFuture), DeviceMemoryAllocError> {
+	) -> Result<(Self, impl GpuFuture), BatchError> {
 		let dimensions = target.images()[0].dimensions();
 		let (target_descs, future) =
 			Self::make_target_desc(
@@ -44,17 +85,22 @@ impl SpriteBatch {
 				dimensions.height()
 			)?;
 
+		let stencil_image =
+			if shared.has_stencil() {
+				Some(AttachmentImage::transient(
+					window.device().device().clone(),
+					[dimensions.width(), dimensions.height()],
+					stencil_format(window.device().device())
+				)?)
+			} else {
+				None
+			};
+
 		let framebuffers =
 			target.images().iter()
 				.map(|image| {
-					Framebuffer::start(shared.subpass().render_pass().clone())
-						.add(image.clone())
-						.and_then(|fb| fb.build())
-						.map(|fb| ImageFramebuffer::new(Arc::downgrade(&image), Arc::new(fb)))
-						.map_err(|err| match err {
-							FramebufferCreationError::OomError(err) => err,
-							err => unreachable!("{:?}", err),
-						})
+					Self::make_framebuffer(&shared, image, stencil_image.as_ref())
+						.map(|fb| ImageFramebuffer::new(Arc::downgrade(&image), fb))
 				})
 				.collect::<Result<Vec<_>, _>>()?;
 
@@ -62,9 +108,17 @@ impl SpriteBatch {
 			Self {
 				shared: shared,
 				sprites: vec![],
+				stencils: vec![],
 				framebuffers: framebuffers,
+				stencil_image: stencil_image,
 				target_id: target.id_root().make_id(),
 				target_desc: target_descs,
+				target_size: [dimensions.width(), dimensions.height()],
+				viewport: Region::full(),
+				scissor: None,
+				lighting: None,
+				light_buffers: None,
+				clear_color: [0.1, 0.1, 0.1, 1.0],
 			},
 			future
 		))
@@ -72,6 +126,60 @@ impl SpriteBatch {
 
 	pub fn add_sprite(&mut self, sprite: Box<Drawable2D>) {
 		self.sprites.push(sprite);
+		self.stencils.push(None);
+	}
+
+	/// Adds a sprite that's drawn with a stencil test, or that writes a mask for later sprites. The stencil buffer is
+	/// cleared to 0 each frame, and sprites are drawn in the order they're added. This draws the sprite normally if the
+	/// batch's `SpriteBatchShared` wasn't made with `with_stencil`.
+	pub fn add_sprite_with_stencil(&mut self, sprite: Box<Drawable2D>, stencil: StencilState) {
+		self.sprites.push(sprite);
+		self.stencils.push(self.shared.stencil_variant(&stencil));
+	}
+
+	/// Sets the region of the target that sprite coordinates are relative to. Sprite positions are still in pixels.
+	pub fn set_viewport(&mut self, viewport: Region) {
+		self.viewport = viewport;
+	}
+
+	/// Restricts drawing to a region of the target. `None` allows drawing to the whole target.
+	pub fn set_scissor(&mut self, scissor: Option<Region>) {
+		self.scissor = scissor;
+	}
+
+	/// The color the target is cleared to before the sprites are drawn. Clear to transparent black to draw over a
+	/// transparent window's desktop.
+	pub fn set_clear_color(&mut self, color: [f32; 4]) {
+		self.clear_color = color;
+	}
+
+	pub fn clear_color(&self) -> [f32; 4] {
+		self.clear_color
+	}
+
+	/// Lights the sprites with 2D lights and shadows. `None` turns lighting off, which is the default.
+	pub fn set_lighting(&mut self, lighting: Option<Lighting2D>) {
+		if lighting.is_none() {
+			self.light_buffers = None;
+		}
+		self.lighting = lighting;
+	}
+
+	/// The lighting, so lights and occluders can be moved each frame.
+	pub fn lighting_mut(&mut self) -> Option<&mut Lighting2D> {
+		self.lighting.as_mut()
+	}
+
+	fn make_framebuffer(
+		shared: &SpriteBatchShared,
+		image: &Arc<ImageViewAccess + Send + Sync + 'static>,
+		stencil_image: Option<&Arc<AttachmentImage>>,
+	) -> Result<Arc<FramebufferAbstract + Send + Sync + 'static>, FramebufferCreationError> {
+		let framebuffer = Framebuffer::start(shared.subpass().render_pass().clone()).add(image.clone())?;
+		Ok(match stencil_image {
+			Some(stencil_image) => Arc::new(framebuffer.add(stencil_image.clone())?.build()?),
+			None => Arc::new(framebuffer.build()?),
+		})
 	}
 
 	fn make_target_desc(
@@ -80,7 +188,8 @@ impl SpriteBatch {
 		width: u32,
 		height: u32
 	) -> Result<(Arc<DescriptorSet + Send + Sync + 'static>, impl GpuFuture), DeviceMemoryAllocError> {
-		let (target_size, future) = ImmutableBuffer::from_data([width, height], BufferUsage::uniform_buffer(), queue)?;
+		let bytes = target_uniform([width, height], 1.0);
+		let (target_size, future) = ImmutableBuffer::from_iter(bytes.into_iter(), BufferUsage::uniform_buffer(), queue)?;
 
 		Ok((
 			Arc::new(
@@ -94,12 +203,13 @@ impl SpriteBatch {
 		))
 	}
 
+	/// Records the batch's commands. This only needs the batch and the target, so it can run on a worker thread, with a
+	/// `WindowTarget` standing in for the window.
 	pub fn commands(
 		&mut self,
-		window: &Window,
 		target: &RenderTarget,
 		image_num: usize,
-	) -> Result<(AutoCommandBuffer, Option<impl GpuFuture>), DeviceMemoryAllocError> {
+	) -> Result<(AutoCommandBuffer, Option<impl GpuFuture>), BatchError> {
 		assert!(self.target_id.is_child_of(target.id_root()));
 
 		let framebuffer = self.framebuffers[image_num].image
@@ -108,66 +218,161 @@ impl SpriteBatch {
 			.filter(|old_image| Arc::ptr_eq(&target.images()[image_num], &old_image))
 			.next()
 			.map(|_| self.framebuffers[image_num].framebuffer.clone());
-		let (framebuffer, future) =
+		let framebuffer =
 			if let Some(framebuffer) = framebuffer {
-				(framebuffer, None)
+				framebuffer
 			} else {
-				let framebuffer = Framebuffer::start(self.shared.subpass().render_pass().clone())
-					.add(target.images()[image_num].clone())
-					.and_then(|fb| fb.build())
-					.map(|fb| Arc::new(fb))
-					.map_err(|err| {
-						match err { FramebufferCreationError::OomError(err) => err, err => unreachable!("{:?}", err) }
-					})?;
-				self.framebuffers[image_num] =
-					ImageFramebuffer::new(Arc::downgrade(&target.images()[image_num]), framebuffer.clone());
+				let image = &target.images()[image_num];
+				let image_size = [image.dimensions().width(), image.dimensions().height()];
+				let old_stencil_image =
+					match &self.stencil_image {
+						Some(stencil_image) if stencil_image.dimensions() != image_size => {
+							let stencil_image =
+								AttachmentImage::transient(
+									self.shared.shaders().device().clone(),
+									image_size,
+									stencil_format(self.shared.shaders().device())
+								)?;
+							mem::replace(&mut self.stencil_image, Some(stencil_image))
+						},
+						_ => None,
+					};
+				self.shared.shaders().device_ctx().defer_destroy(old_stencil_image);
+
+				let framebuffer = Self::make_framebuffer(&self.shared, image, self.stencil_image.as_ref())?;
+				self.framebuffers[image_num] = ImageFramebuffer::new(Arc::downgrade(image), framebuffer.clone());
+
+				framebuffer
+			};
+
+		let dimensions = [framebuffer.width() as f32, framebuffer.height() as f32];
+		let state = dynamic_state(&self.viewport, self.scissor.as_ref(), dimensions);
 
+		let (_, viewport_dimensions) = self.viewport.pixels(dimensions);
+		let target_size = [viewport_dimensions[0] as u32, viewport_dimensions[1] as u32];
+		let future =
+			if target_size != self.target_size {
 				let (target_desc, future) =
 					Self::make_target_desc(
-						window.device().queue().clone(),
+						self.shared.shaders().device_ctx().queue().clone(),
 						self.shared.pipeline_sprite().clone(),
-						framebuffer.width(),
-						framebuffer.height()
+						target_size[0],
+						target_size[1]
 					)?;
 
-				self.target_desc = target_desc;
+				let old_target_desc = mem::replace(&mut self.target_desc, target_desc);
+				self.shared.shaders().device_ctx().defer_destroy(old_target_desc);
+				self.target_size = target_size;
 
-				(framebuffer as _, Some(future))
+				Some(future)
+			} else {
+				None
 			};
 
-		let dimensions = [framebuffer.width() as f32, framebuffer.height() as f32];
+		// anything added to the batch directly has no parent, even if it used to
+		for sprite in &mut self.sprites {
+			sprite.inherit(Inherited2D::default());
+		}
 
+		// drawables off the viewport or too small to see aren't recorded at all
+		let visible = self.sprites.iter().map(|sprite| is_visible(&**sprite, viewport_dimensions)).collect::<Vec<_>>();
+
+		let device = self.shared.shaders().device_ctx();
 		let mut command_buffer =
-			AutoCommandBufferBuilder::primary_one_time_submit(self.shared.shaders().device().clone(), window.device().queue().family())?
-				.begin_render_pass(framebuffer, true, vec![[0.1, 0.1, 0.1, 1.0].into()])
-				.unwrap();
+			AutoCommandBufferBuilder::primary_one_time_submit(device.device().clone(), device.queue().family())?;
 
-		for sprite in &mut self.sprites {
-			command_buffer =
-				unsafe {
-					command_buffer
-						.execute_commands(
-							sprite.make_commands(&self.shared, &self.target_desc, window.device().queue().family(), dimensions)?
-						)
-						.unwrap()
+		if let Some(lighting) = &self.lighting {
+			let framebuffer_size = [framebuffer.width(), framebuffer.height()];
+			let mut light_buffers =
+				match self.light_buffers.take() {
+					Some(buffers) if buffers.dimensions() == framebuffer_size => buffers,
+					old_buffers => {
+						device.defer_destroy(old_buffers);
+						LightBuffers::new(&self.shared, framebuffer_size)?
+					},
 				};
+			command_buffer = light_buffers.record(
+				command_buffer,
+				&self.shared,
+				&self.target_desc,
+				&mut self.sprites,
+				&visible,
+				lighting,
+				&state
+			)?;
+			self.light_buffers = Some(light_buffers);
 		}
 
-		Ok((
-			command_buffer.end_render_pass().unwrap()
-				.build()
-				.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?,
-			future
-		))
+		let mut clear_values = vec![self.clear_color.into()];
+		if self.stencil_image.is_some() {
+			clear_values.push(ClearValue::DepthStencil((1.0, 0)));
+		}
+		command_buffer = command_buffer.begin_render_pass(framebuffer, false, clear_values).unwrap();
+
+		let sprites = self.sprites.iter_mut().zip(&self.stencils).zip(&visible).filter(|(_, &visible)| visible);
+		for ((sprite, stencil), _) in sprites {
+			let shared = stencil.as_ref().unwrap_or(&self.shared);
+			command_buffer = sprite.add_commands(shared, &self.target_desc, command_buffer, &state)?;
+		}
+
+		if let Some(light_buffers) = &self.light_buffers {
+			command_buffer = light_buffers.draw_composite(command_buffer, &self.shared, &state);
+		}
+
+		let command_buffer = command_buffer.end_render_pass().unwrap().build()?;
+		device.debug_names().name_command_buffer(&command_buffer, "sprite batch");
+
+		Ok((command_buffer, future))
 	}
 }
 
-pub trait Drawable2D {
-	fn make_commands(
+/// Something a `SpriteBatch` can draw. Drawables move between threads with their batch, so they have to be `Send`.
+pub trait Drawable2D: Send {
+	/// Records draw commands inline into the batch's render pass.
+	fn add_commands(
 		&mut self,
 		shared: &SpriteBatchShared,
 		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
-		queue_family: QueueFamily,
-		dimensions: [f32; 2],
-	) -> Result<AutoCommandBuffer, OomError>;
+		cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError>;
+
+	/// Records normals for 2D lighting, outside of the batch's render pass. Drawables without normals don't need this.
+	fn add_normal_commands(
+		&mut self,
+		_shared: &SpriteBatchShared,
+		_target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		cmds: AutoCommandBufferBuilder,
+		_state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		Ok(cmds)
+	}
+
+	/// The rectangle the drawable covers, as its minimum and maximum corners in pixels, like sprite positions. The
+	/// batch skips drawables that are entirely outside its viewport, or smaller than a pixel. `None`, the default, is
+	/// always drawn.
+	fn bounds(&self) -> Option<[[f32; 2]; 2]> {
+		None
+	}
+
+	/// Called by a parent `Node2D` before each draw, with the scale and opacity it and its ancestors apply. Drawables
+	/// that hold other drawables should pass it on to them.
+	fn inherit(&mut self, _inherited: Inherited2D) {}
+}
+
+/// The Target block the sprite shaders read from set 0: the viewport's size in pixels, and the opacity drawables are
+/// faded by.
+pub(crate) fn target_uniform(size: [u32; 2], opacity: f32) -> Vec<u8> {
+	UniformWriter::new(LayoutRules::Std140).field(&size[0]).field(&size[1]).field(&opacity).finish()
+}
+
+/// Whether a drawable might cover a pixel of a viewport with the given size.
+fn is_visible(drawable: &Drawable2D, viewport_size: [f32; 2]) -> bool {
+	match drawable.bounds() {
+		Some([min, max]) => {
+			let on_screen = max[0] > 0.0 && max[1] > 0.0 && min[0] < viewport_size[0] && min[1] < viewport_size[1];
+			on_screen && (max[0] - min[0] >= 1.0 || max[1] - min[1] >= 1.0)
+		},
+		None => true,
+	}
 }
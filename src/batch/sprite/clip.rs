@@ -0,0 +1,132 @@
+use super::{ Drawable2D, Inherited2D };
+use super::shared::SpriteBatchShared;
+use super::ui_overlay::clip_state;
+use crate::batch::BatchError;
+use std::sync::Arc;
+use vulkano::{
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::DescriptorSet,
+};
+
+/// Draws other drawables clipped to a rectangle, like the contents of a scrolling list or a text box. Children are
+/// positioned relative to the same viewport as the group, moved by the scroll offset.
+///
+/// Groups can be children of other groups. A nested group's rectangle is relative to its parent's scrolled contents,
+/// and it's clipped to its parent's rectangle too. Clipping uses the scissor, so it's only rectangular; for other
+/// shapes, add sprites to the batch with a `StencilState`.
+pub struct ClipGroup {
+	/// The left, top, right and bottom edges, in pixels.
+	clip_rect: [f32; 4],
+	scroll: [f32; 2],
+	children: Vec<Box<Drawable2D>>,
+	/// The scale of the `Node2D`s the group is in, which the clip rectangle is scaled by too.
+	scale: f32,
+}
+impl ClipGroup {
+	pub fn new(clip_rect: [f32; 4]) -> Self {
+		Self { clip_rect: clip_rect, scroll: [0.0, 0.0], children: vec![], scale: 1.0 }
+	}
+
+	/// Children are drawn in the order they're added, after the ones before them.
+	pub fn add(&mut self, child: Box<Drawable2D>) {
+		self.children.push(child);
+	}
+
+	pub fn children(&self) -> &[Box<Drawable2D>] {
+		&self.children
+	}
+
+	pub fn children_mut(&mut self) -> &mut Vec<Box<Drawable2D>> {
+		&mut self.children
+	}
+
+	pub fn set_clip_rect(&mut self, clip_rect: [f32; 4]) {
+		self.clip_rect = clip_rect;
+	}
+
+	pub fn clip_rect(&self) -> [f32; 4] {
+		self.clip_rect
+	}
+
+	/// Moves the children up and left by this many pixels, so scrolling down a list means increasing the y offset.
+	pub fn set_scroll(&mut self, scroll: [f32; 2]) {
+		self.scroll = scroll;
+	}
+
+	pub fn scroll(&self) -> [f32; 2] {
+		self.scroll
+	}
+
+	/// The state children are drawn with, or `None` if the group is clipped away entirely.
+	fn child_state(&self, state: &DynamicState) -> Option<DynamicState> {
+		let mut state = clip_state(state, self.clip_rect, self.scale)?;
+		if let Some(viewports) = &mut state.viewports {
+			for viewport in viewports {
+				viewport.origin = [
+					viewport.origin[0] - self.scroll[0] * self.scale,
+					viewport.origin[1] - self.scroll[1] * self.scale,
+				];
+			}
+		}
+		Some(state)
+	}
+
+	/// Whether a child might show through the clip rectangle. Children without bounds always might.
+	fn is_child_visible(&self, child: &Drawable2D) -> bool {
+		match child.bounds() {
+			Some([min, max]) => {
+				let [left, top, right, bottom] = self.clip_rect;
+				max[0] - self.scroll[0] > left &&
+					max[1] - self.scroll[1] > top &&
+					min[0] - self.scroll[0] < right &&
+					min[1] - self.scroll[1] < bottom
+			},
+			None => true,
+		}
+	}
+}
+impl Drawable2D for ClipGroup {
+	fn add_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		mut cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		let state = match self.child_state(state) { Some(state) => state, None => return Ok(cmds) };
+		for i in 0..self.children.len() {
+			if self.is_child_visible(&*self.children[i]) {
+				cmds = self.children[i].add_commands(shared, target_desc, cmds, &state)?;
+			}
+		}
+		Ok(cmds)
+	}
+
+	fn add_normal_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		mut cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		let state = match self.child_state(state) { Some(state) => state, None => return Ok(cmds) };
+		for i in 0..self.children.len() {
+			if self.is_child_visible(&*self.children[i]) {
+				cmds = self.children[i].add_normal_commands(shared, target_desc, cmds, &state)?;
+			}
+		}
+		Ok(cmds)
+	}
+
+	fn bounds(&self) -> Option<[[f32; 2]; 2]> {
+		let [left, top, right, bottom] = self.clip_rect;
+		Some([[left, top], [right, bottom]])
+	}
+
+	fn inherit(&mut self, inherited: Inherited2D) {
+		self.scale = inherited.scale;
+		for child in &mut self.children {
+			child.inherit(inherited);
+		}
+	}
+}
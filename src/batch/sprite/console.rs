@@ -0,0 +1,309 @@
+use super::Drawable2D;
+use super::font::{ Font, TextSprite };
+use super::shared::SpriteBatchShared;
+use crate::batch::BatchError;
+use crate::window::{ Event, WindowEvent };
+use log::{ Log, Metadata, Record };
+use std::{ collections::{ BTreeMap, VecDeque }, mem, sync::{ Arc, Mutex } };
+use vulkano::{
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::DescriptorSet,
+};
+use winit::{ ElementState, KeyboardInput, VirtualKeyCode };
+
+const SCROLLBACK: usize = 500;
+
+type Command = Box<FnMut(&[&str]) -> String + Send>;
+
+/// A drop-down console for typing commands while the game runs. Add a clone of it to a `SpriteBatch` to draw it, and
+/// pass window events to `handle_event`. It's also a logger, so it can show log output with `log::set_boxed_logger`.
+#[derive(Clone)]
+pub struct Console {
+	inner: Arc<Mutex<ConsoleInner>>,
+}
+impl Console {
+	pub fn new(font: Arc<Font>) -> Self {
+		let inner =
+			ConsoleInner {
+				font: font,
+				toggle_key: VirtualKeyCode::Grave,
+				visible_lines: 16,
+				open: false,
+				lines: VecDeque::new(),
+				input: String::new(),
+				history: vec![],
+				history_index: None,
+				commands: BTreeMap::new(),
+				skip_char: false,
+				submitted: None,
+				sprites: None,
+			};
+		Self { inner: Arc::new(Mutex::new(inner)) }
+	}
+
+	/// Registers a command. The closure gets the words typed after the command name, and what it returns is printed.
+	pub fn register(&self, name: impl Into<String>, command: impl FnMut(&[&str]) -> String + Send + 'static) {
+		self.inner.lock().unwrap().commands.insert(name.into(), Box::new(command));
+	}
+
+	/// The key that opens and closes the console. Defaults to the grave accent key.
+	pub fn set_toggle_key(&self, key: VirtualKeyCode) {
+		self.inner.lock().unwrap().toggle_key = key;
+	}
+
+	/// How many lines of output are shown above the input line.
+	pub fn set_visible_lines(&self, lines: usize) {
+		let mut inner = self.inner.lock().unwrap();
+		inner.visible_lines = lines;
+		inner.sprites = None;
+	}
+
+	pub fn is_open(&self) -> bool {
+		self.inner.lock().unwrap().open
+	}
+
+	pub fn set_open(&self, open: bool) {
+		let mut inner = self.inner.lock().unwrap();
+		inner.open = open;
+		inner.sprites = None;
+	}
+
+	pub fn print(&self, text: &str) {
+		self.inner.lock().unwrap().print(text);
+	}
+
+	/// Runs a command as if it was typed.
+	pub fn execute(&self, line: &str) {
+		let mut inner = self.inner.lock().unwrap();
+		inner.print(&format!("> {}", line));
+
+		let mut words = line.split_whitespace();
+		let name = match words.next() { Some(name) => name, None => return };
+		let args = words.collect::<Vec<_>>();
+
+		if name == "help" {
+			let names = inner.commands.keys().map(|name| name.as_str()).collect::<Vec<_>>();
+			let help = format!("commands: clear, help, {}", names.join(", "));
+			inner.print(&help);
+		} else if name == "clear" {
+			inner.lines.clear();
+			inner.sprites = None;
+		} else if let Some(mut command) = inner.commands.remove(name) {
+			// the command runs unlocked, so it can log or use the console itself
+			drop(inner);
+			let output = command(&args);
+
+			let mut inner = self.inner.lock().unwrap();
+			inner.commands.entry(name.to_string()).or_insert(command);
+			inner.print(&output);
+		} else {
+			inner.print(&format!("unknown command: {}", name));
+		}
+	}
+
+	/// Handles typing and the toggle key. Returns true if the event was used by the console, in which case the game
+	/// should usually ignore it.
+	pub fn handle_event(&self, event: &Event) -> bool {
+		let (used, submitted) = {
+			let mut inner = self.inner.lock().unwrap();
+			let used =
+				match event {
+					Event::WindowEvent { event, .. } => match event {
+						WindowEvent::KeyboardInput { input, .. } => inner.handle_key(input),
+						WindowEvent::ReceivedCharacter(ch) => inner.handle_char(*ch),
+						_ => false,
+					},
+					_ => false,
+				};
+			(used, inner.submitted.take())
+		};
+
+		if let Some(line) = submitted {
+			self.execute(&line);
+		}
+		used
+	}
+}
+impl Drawable2D for Console {
+	fn add_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		mut cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		let mut inner = self.inner.lock().unwrap();
+		if !inner.open {
+			return Ok(cmds);
+		}
+
+		if inner.sprites.is_none() {
+			let sprites = inner.make_sprites(shared)?;
+			inner.sprites = Some(sprites);
+		}
+
+		for sprite in inner.sprites.as_mut().unwrap() {
+			cmds = sprite.add_commands(shared, target_desc, cmds, state)?;
+		}
+
+		Ok(cmds)
+	}
+}
+impl Log for Console {
+	fn enabled(&self, _metadata: &Metadata) -> bool {
+		true
+	}
+
+	fn log(&self, record: &Record) {
+		self.print(&format!("[{}] {}", record.level(), record.args()));
+	}
+
+	fn flush(&self) {}
+}
+
+struct ConsoleInner {
+	font: Arc<Font>,
+	toggle_key: VirtualKeyCode,
+	visible_lines: usize,
+	open: bool,
+	lines: VecDeque<String>,
+	input: String,
+	history: Vec<String>,
+	/// The history entry being edited, if the player has pressed up since the last command.
+	history_index: Option<usize>,
+	commands: BTreeMap<String, Command>,
+	/// Set when the toggle key was pressed, so the character it types doesn't end up in the input.
+	skip_char: bool,
+	/// A line the player entered, to be executed once the lock is released.
+	submitted: Option<String>,
+	/// Text for the visible lines, rebuilt whenever they change.
+	sprites: Option<Vec<TextSprite>>,
+}
+impl ConsoleInner {
+	fn print(&mut self, text: &str) {
+		for line in text.lines() {
+			if self.lines.len() == SCROLLBACK {
+				self.lines.pop_front();
+			}
+			self.lines.push_back(line.to_string());
+		}
+		self.sprites = None;
+	}
+
+	fn handle_key(&mut self, input: &KeyboardInput) -> bool {
+		let key =
+			match (input.state, input.virtual_keycode) {
+				(ElementState::Pressed, Some(key)) => key,
+				_ => return false,
+			};
+
+		if key == self.toggle_key {
+			self.open = !self.open;
+			self.skip_char = true;
+			self.sprites = None;
+			return true;
+		}
+
+		if !self.open {
+			return false;
+		}
+
+		match key {
+			VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => {
+				let line = mem::replace(&mut self.input, String::new());
+				if !line.trim().is_empty() {
+					self.history.push(line.clone());
+				}
+				self.history_index = None;
+				self.submitted = Some(line);
+			},
+			VirtualKeyCode::Back => {
+				self.input.pop();
+			},
+			VirtualKeyCode::Up => {
+				let index = self.history_index.map_or(self.history.len(), |index| index).saturating_sub(1);
+				if let Some(line) = self.history.get(index) {
+					self.input = line.clone();
+					self.history_index = Some(index);
+				}
+			},
+			VirtualKeyCode::Down => {
+				if let Some(index) = self.history_index {
+					if index + 1 < self.history.len() {
+						self.input = self.history[index + 1].clone();
+						self.history_index = Some(index + 1);
+					} else {
+						self.input.clear();
+						self.history_index = None;
+					}
+				}
+			},
+			VirtualKeyCode::Tab => self.complete(),
+			_ => (),
+		}
+
+		self.sprites = None;
+		true
+	}
+
+	fn handle_char(&mut self, ch: char) -> bool {
+		if mem::replace(&mut self.skip_char, false) {
+			return true;
+		}
+
+		if !self.open {
+			return false;
+		}
+
+		if !ch.is_control() {
+			self.input.push(ch);
+			self.sprites = None;
+		}
+		true
+	}
+
+	/// Completes the command name being typed as far as it's unambiguous, and lists the options if there are several.
+	fn complete(&mut self) {
+		if self.input.contains(char::is_whitespace) {
+			return;
+		}
+
+		let matches =
+			["clear", "help"].iter()
+				.map(|name| name.to_string())
+				.chain(self.commands.keys().cloned())
+				.filter(|name| name.starts_with(&self.input))
+				.collect::<Vec<_>>();
+
+		if let Some(first) = matches.first() {
+			let mut prefix = first.clone();
+			for name in &matches[1..] {
+				while !name.starts_with(&prefix) {
+					prefix.pop();
+				}
+			}
+
+			if matches.len() == 1 {
+				prefix.push(' ');
+			} else if prefix == self.input {
+				let options = matches.join(", ");
+				self.print(&options);
+			}
+			self.input = prefix;
+		}
+	}
+
+	fn make_sprites(&self, shared: &SpriteBatchShared) -> Result<Vec<TextSprite>, BatchError> {
+		let line_height = self.font.line_height();
+		let skip = self.lines.len().saturating_sub(self.visible_lines);
+		let prompt = format!("> {}_", self.input);
+
+		self.lines.iter()
+			.skip(skip)
+			.map(|line| line.as_str())
+			.chain(Some(prompt.as_str()))
+			.enumerate()
+			.map(|(i, line)| self.font.make_sprite(line, shared, [8.0, line_height * (i + 1) as f32]))
+			.collect()
+	}
+}
@@ -1,211 +1,220 @@
-use crate::batch::sprite::{ Drawable2D, SpriteBatchShared };
-use crate::texture::{ Texture, ImmutableTexture };
-use rusttype::{ Font as RtFont, GlyphId, Point, Scale };
-use std::{ collections::HashMap, fs::File, io::{ self, prelude::* }, path::Path, sync::{ Arc, Mutex } };
+mod atlas;
+mod bidi;
+
+use self::atlas::{ GlyphAtlas, PageImage };
+use crate::batch::{ BatchError, sprite::{ Drawable2D, SpriteBatchShared, shaders::{ GlyphVertex, text_vs } } };
+use crate::device::MemoryStats;
+use crate::vfs;
+use rusttype::{ Font as RtFont, Point, Scale };
+use std::{ collections::HashMap, io, path::Path, sync::{ Arc, Mutex } };
 use vulkano::{
-	OomError,
-	buffer::{ BufferUsage, ImmutableBuffer },
-	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, CommandBufferExecFuture, DynamicState },
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
 	descriptor::{ DescriptorSet, descriptor_set::PersistentDescriptorSet },
 	device::Queue,
-	format::Format,
-	image::{ Dimensions, ImageCreationError, ImmutableImage },
-	instance::QueueFamily,
-	memory::DeviceMemoryAllocError,
-	pipeline::viewport::Viewport,
-	sync::{ FenceSignalFuture, FlushError, GpuFuture, JoinFuture, NowFuture },
+	sync::FlushError,
 };
 
+/// A font at one size. Its glyphs are rasterized as they're first used and packed into atlas pages, which are added as
+/// needed up to a limit. Past the limit, the glyphs on the least recently used page are evicted to make room.
 pub struct Font {
 	queue: Arc<Queue>,
+	memory: Arc<MemoryStats>,
+	name: String,
 	scale: f32,
 	font: RtFont<'static>,
-	glyphs: Mutex<HashMap<GlyphId, Option<Glyph>>>,
-	futures: Mutex<HashMap<GlyphId, Arc<FenceSignalFuture<GlyphFuture>>>>,
+	atlas: Mutex<GlyphAtlas>,
 }
 impl Font {
+	/// Lays out a string to be drawn one glyph at a time. Text that's drawn every frame is much cheaper as a
+	/// `StaticText` or `DynamicText`.
 	pub fn make_sprite(
 		&self,
 		text: &str,
 		shared: &SpriteBatchShared,
-		[x, y]: [f32; 2],
-	) -> Result<TextSprite, DeviceMemoryAllocError> {
-		self.load_chars(text.chars())?;
-
-		let mut positions = vec![];
-
-		let mut static_descs = HashMap::new();
-		let mut glyph_futures = HashMap::new();
-		let glyphs = self.glyphs.lock().unwrap();
-		let futures = self.futures.lock().unwrap();
+		position: [f32; 2],
+	) -> Result<TextSprite, BatchError> {
+		Ok(TextSprite { layout: self.layout(text, shared, position)? })
+	}
 
-		for glyph in self.font.layout(text, Scale::uniform(self.scale), Point { x: x, y: y }) {
-			let id = glyph.id();
+	/// How many atlas pages the font keeps before it evicts glyphs. Each page takes 256 KiB. Text keeps the pages it
+	/// was laid out with, so this doesn't limit the memory of text that's still drawn.
+	pub fn set_max_atlas_pages(&self, pages: usize) {
+		self.atlas.lock().unwrap().set_max_pages(pages);
+	}
 
-			let point = glyph.position();
-			let (position, pos_future) =
-				ImmutableBuffer::from_data([point.x, point.y], BufferUsage::uniform_buffer(), self.queue.clone())?;
-			positions.push((id, position, Some(pos_future.then_signal_fence_and_flush().unwrap())));
+	pub fn max_atlas_pages(&self) -> usize {
+		self.atlas.lock().unwrap().max_pages()
+	}
 
-			if let Some(glyph) = glyphs.get(&id).unwrap() {
-				static_descs.entry(id)
-					.or_insert_with(|| Arc::new(
-						PersistentDescriptorSet::start(shared.pipeline_text().clone(), 2)
-							.add_buffer(glyph.offset.clone())
-							.unwrap()
-							.add_sampled_image(glyph.texture.image().clone(), shared.shaders().text_sampler().clone())
-							.unwrap()
-							.build()
-							.unwrap()
-					) as Arc<DescriptorSet + Send + Sync + 'static>);
+	/// The distance between the baselines of two lines of text, in pixels.
+	pub fn line_height(&self) -> f32 {
+		let metrics = self.font.v_metrics(Scale::uniform(self.scale));
+		metrics.ascent - metrics.descent + metrics.line_gap
+	}
 
-				if let Some(fut) = futures.get(&id) {
-					glyph_futures.insert(id, fut.clone());
+	/// Positions a string's glyphs and places them in the atlas. Each paragraph is a line, put in visual order so right
+	/// to left text isn't reversed. Glyphs aren't shaped, so Arabic should use its presentation forms.
+	pub(super) fn layout(
+		&self,
+		text: &str,
+		shared: &SpriteBatchShared,
+		[x, y]: [f32; 2],
+	) -> Result<TextLayout, BatchError> {
+		let mut atlas = self.atlas.lock().unwrap();
+		atlas.begin_use();
+
+		let mut glyphs = vec![];
+		let line_height = self.line_height();
+		for (i, line) in bidi::visual_lines(text).iter().enumerate() {
+			let origin = Point { x: x, y: y + line_height * i as f32 };
+			for glyph in self.font.layout(line, Scale::uniform(self.scale), origin) {
+				let point = glyph.position();
+				let atlas_glyph = atlas.glyph(glyph.id(), || {
+					let glyph = glyph.unpositioned().clone().positioned(Point { x: 0.0, y: 0.0 });
+					let bb = glyph.pixel_bounding_box()?;
+					let mut pixels = vec![0; bb.width() as usize * bb.height() as usize];
+					glyph.draw(|x, y, v| pixels[y as usize * bb.width() as usize + x as usize] = (255.0 * v) as u8);
+					Some((pixels, [bb.width() as u32, bb.height() as u32], [bb.min.x, bb.min.y]))
+				});
+				if let Some(atlas_glyph) = atlas_glyph {
+					glyphs.push(([point.x, point.y], atlas_glyph));
 				}
 			}
 		}
 
-		Ok(TextSprite { static_descs: static_descs, positions: positions, futures: glyph_futures })
+		// glyphs can be evicted while a string is laid out, so pages are only uploaded once it's done
+		atlas.upload(&self.queue, &self.memory, &self.name)?;
+
+		let mut pages = vec![];
+		let mut page_indices = HashMap::new();
+		let quads = glyphs.into_iter()
+			.map(|(point, glyph)| {
+				let page = *page_indices.entry(glyph.page).or_insert_with(|| {
+					let image = atlas.page_image(glyph.page).clone();
+					let desc = Arc::new(
+						PersistentDescriptorSet::start(shared.pipeline_text().clone(), 1)
+							.add_sampled_image(image.image.clone(), shared.shaders().text_sampler().clone())
+							.unwrap()
+							.build()
+							.unwrap()
+					) as Arc<DescriptorSet + Send + Sync + 'static>;
+					pages.push((image, desc));
+					pages.len() - 1
+				});
+
+				let page_size = pages[page].0.size() as f32;
+				let draw = text_vs::ty::GlyphDraw {
+					pos: [point[0] + glyph.offset[0] as f32, point[1] + glyph.offset[1] as f32],
+					size: [glyph.size[0] as f32, glyph.size[1] as f32],
+					uv_origin: [glyph.origin[0] as f32 / page_size, glyph.origin[1] as f32 / page_size],
+					uv_size: [glyph.size[0] as f32 / page_size, glyph.size[1] as f32 / page_size],
+				};
+				(page, draw)
+			})
+			.collect();
+
+		Ok(TextLayout { pages: pages, quads: quads })
 	}
 
-	pub(crate) fn from_file<P: AsRef<Path>>(queue: Arc<Queue>, path: P, scale: f32) -> Result<Arc<Self>, io::Error> {
-		let mut bytes = vec![];
-		File::open(path)?.read_to_end(&mut bytes)?;
+	pub(crate) fn from_file<P: AsRef<Path>>(
+		queue: Arc<Queue>,
+		memory: &Arc<MemoryStats>,
+		path: P,
+		scale: f32,
+	) -> Result<Arc<Self>, io::Error> {
+		let bytes = vfs::read(&path)?;
 
 		let font = RtFont::from_bytes(bytes).unwrap();
 
 		Ok(Arc::new(Self {
 			queue: queue,
+			memory: memory.clone(),
+			name: format!("{} at {}", path.as_ref().display(), scale),
 			font: font,
-			glyphs: Mutex::default(),
-			futures: Mutex::default(),
-			scale: scale
+			atlas: Mutex::new(GlyphAtlas::new()),
+			scale: scale,
 		}))
 	}
+}
 
-	fn load_chars(&self, chars: impl Iterator<Item = char>) -> Result<(), DeviceMemoryAllocError> {
-		let mut glyphs = self.glyphs.lock().unwrap();
-		let mut futures = self.futures.lock().unwrap();
-
-		for ch in chars {
-			let id = self.font.glyph(ch).id();
-
-			if !glyphs.contains_key(&id) {
-				let glyph = self.font.glyph(id).scaled(Scale::uniform(self.scale)).positioned(Point { x: 0.0, y: 0.0 });
-
-				if let Some(bb) = glyph.pixel_bounding_box() {
-					let bblen = bb.width() as usize * bb.height() as usize;
-					let mut pixels = Vec::with_capacity(bblen);
-					unsafe { pixels.set_len(bblen); }
-
-					glyph.draw(|x, y, v| {
-						pixels[y as usize * bb.width() as usize + x as usize] = (255.0 * v) as u8;
-					});
-
-					let (position, pos_future) =
-						ImmutableBuffer::from_data([bb.min.x, bb.min.y], BufferUsage::uniform_buffer(), self.queue.clone())?;
-
-					let (image, image_future) =
-						ImmutableImage
-							::from_iter(
-								pixels.into_iter(),
-								Dimensions::Dim2d { width: bb.width() as u32, height: bb.height() as u32 },
-								Format::R8Unorm,
-								self.queue.clone(),
-							)
-							.map_err(|err| match err {
-								ImageCreationError::AllocError(err) => err,
-								_ => unreachable!(),
-							})?;
-
-					glyphs.insert(id, Some(Glyph { texture: ImmutableTexture::from_image(image), offset: position }));
-					futures.insert(id, Arc::new(pos_future.join(image_future).then_signal_fence_and_flush().unwrap()));
-				} else {
-					glyphs.insert(id, None);
-				}
+/// A string's glyphs, positioned and placed in a font's atlas. It keeps the atlas pages it was laid out with, so it
+/// isn't affected when the font evicts its glyphs.
+pub(super) struct TextLayout {
+	pages: Vec<(Arc<PageImage>, Arc<DescriptorSet + Send + Sync + 'static>)>,
+	/// The index of each glyph's page in `pages`, and how it's drawn.
+	quads: Vec<(usize, text_vs::ty::GlyphDraw)>,
+}
+impl TextLayout {
+	/// The descriptor set of each page the text uses.
+	pub(super) fn page_descs(&self) -> impl Iterator<Item = &Arc<DescriptorSet + Send + Sync + 'static>> {
+		self.pages.iter().map(|(_, desc)| desc)
+	}
+
+	/// Whether each page has finished uploading. Glyphs on pages that haven't are skipped until they're ready.
+	pub(super) fn uploaded_pages(&self) -> Result<Vec<bool>, FlushError> {
+		self.pages.iter().map(|(image, _)| image.is_uploaded()).collect()
+	}
+
+	/// The rectangle the glyphs cover, or `None` if there are none.
+	pub(super) fn bounds(&self) -> Option<[[f32; 2]; 2]> {
+		let mut quads = self.quads.iter().map(|(_, draw)| (draw.pos, draw.size));
+		let (pos, size) = quads.next()?;
+		Some(quads.fold([pos, [pos[0] + size[0], pos[1] + size[1]]], |[min, max], (pos, size)| [
+			[min[0].min(pos[0]), min[1].min(pos[1])],
+			[max[0].max(pos[0] + size[0]), max[1].max(pos[1] + size[1])],
+		]))
+	}
+
+	/// Two triangles for each glyph, grouped by page.
+	pub(super) fn page_vertices(&self) -> Vec<Vec<GlyphVertex>> {
+		let mut vertices = vec![vec![]; self.pages.len()];
+		for (page, draw) in &self.quads {
+			for &[x, y] in &[[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [0.0, 1.0], [1.0, 0.0], [1.0, 1.0]] {
+				vertices[*page].push(GlyphVertex {
+					position: [draw.pos[0] + draw.size[0] * x, draw.pos[1] + draw.size[1] * y],
+					tex_coords: [draw.uv_origin[0] + draw.uv_size[0] * x, draw.uv_origin[1] + draw.uv_size[1] * y],
+				});
 			}
 		}
-
-		Ok(())
+		vertices
 	}
 }
 
+/// A string laid out by `Font::make_sprite`, drawn with a draw call for each glyph.
 pub struct TextSprite {
-	static_descs: HashMap<GlyphId, Arc<DescriptorSet + Send + Sync + 'static>>,
-	positions: Vec<(
-		GlyphId,
-		Arc<ImmutableBuffer<[f32; 2]>>,
-		Option<FenceSignalFuture<CommandBufferExecFuture<NowFuture, AutoCommandBuffer>>>
-	)>,
-	futures: HashMap<GlyphId, Arc<FenceSignalFuture<GlyphFuture>>>,
+	layout: TextLayout,
 }
 impl Drawable2D for TextSprite {
-	fn make_commands(
+	fn add_commands(
 		&mut self,
 		shared: &SpriteBatchShared,
 		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
-		queue_family: QueueFamily,
-		dimensions: [f32; 2],
-	) -> Result<AutoCommandBuffer, OomError> {
-		let mut cmds = AutoCommandBufferBuilder::secondary_graphics_one_time_submit(shared.shaders().device().clone(), queue_family, shared.subpass().clone())?;
-
-		let state =
-			DynamicState {
-				line_width: None,
-				viewports: Some(vec![Viewport { origin: [0.0, 0.0], dimensions: dimensions, depth_range: 0.0..1.0 }]),
-				scissors: None,
-			};
-
-		for (id, pos, future) in &mut self.positions {
-			if let Some(inner) = future.take() {
-				match inner.wait(Some(Default::default())) {
-					Ok(()) => (),
-					Err(FlushError::Timeout) => { *future = Some(inner); },
-					Err(err) => panic!(err),
-				}
-			}
-
-			if let Some(future) = self.futures.get(&id).map(|f| f.clone()) {
-				match future.wait(Some(Default::default())) {
-					Ok(()) => { self.futures.remove(&id); },
-					Err(FlushError::Timeout) => { continue; },
-					Err(err) => panic!(err),
-				}
+		mut cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		let uploaded = self.layout.uploaded_pages()?;
+
+		for &(page, draw) in &self.layout.quads {
+			if !uploaded[page] {
+				continue;
 			}
 
-			if let Some(static_desc) = self.static_descs.get(id) {
-				cmds = cmds
-					.draw(
-						shared.pipeline_text().clone(),
-						&state,
-						vec![shared.shaders().vertices().clone()],
-						(
-							target_desc.clone(),
-							shared.sprite_desc_pool().lock().unwrap()
-								.next()
-								.add_buffer(pos.clone())
-								.unwrap()
-								.build()
-								.unwrap(),
-							static_desc.clone(),
-						),
-						()
-					)
-					.unwrap();
-			}
+			shared.shaders().device_ctx().render_stats().record_draw(2);
+			cmds = cmds
+				.draw(
+					shared.pipeline_text().clone(),
+					state,
+					vec![shared.shaders().vertices().clone()],
+					(target_desc.clone(), self.layout.pages[page].1.clone()),
+					draw
+				)
+				.unwrap();
 		}
 
-		Ok(cmds.build().map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?)
+		Ok(cmds)
 	}
-}
 
-type GlyphFuture =
-	JoinFuture<
-		CommandBufferExecFuture<NowFuture, AutoCommandBuffer>,
-		CommandBufferExecFuture<NowFuture, AutoCommandBuffer>
-	>;
-
-struct Glyph {
-	texture: ImmutableTexture,
-	offset: Arc<ImmutableBuffer<[i32; 2]>>,
+	fn bounds(&self) -> Option<[[f32; 2]; 2]> {
+		self.layout.bounds()
+	}
 }
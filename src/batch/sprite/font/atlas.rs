@@ -0,0 +1,258 @@
+use crate::batch::BatchError;
+use crate::device::{ MemoryCategory, MemoryHandle, MemoryStats };
+use rusttype::GlyphId;
+use std::{ collections::HashMap, sync::{ Arc, Mutex } };
+use vulkano::{
+	command_buffer::{ AutoCommandBuffer, CommandBufferExecFuture },
+	device::Queue,
+	format::Format,
+	image::{ Dimensions, ImmutableImage },
+	sync::{ FenceSignalFuture, FlushError, GpuFuture, NowFuture },
+};
+
+/// The width and height of a page of a font's glyph atlas. Glyphs too big for a page get a page of their own.
+const PAGE_SIZE: u32 = 512;
+/// Empty texels left after each glyph, so filtering doesn't blend in its neighbors.
+const PADDING: u32 = 1;
+/// How many pages a font keeps by default before it starts evicting glyphs.
+const DEFAULT_MAX_PAGES: usize = 4;
+
+/// A font's glyphs, packed into pages. Pages are added as glyphs are needed, up to a limit. Past that, the page that
+/// was used least recently is cleared to make room.
+///
+/// Pages are immutable images, so a page with new glyphs is uploaded again as a new image. Text sprites keep the images
+/// they were made with, so they draw correctly even after their glyphs are evicted.
+pub(super) struct GlyphAtlas {
+	pages: Vec<AtlasPage>,
+	/// `None` for glyphs that have no pixels, like spaces.
+	glyphs: HashMap<GlyphId, Option<AtlasGlyph>>,
+	max_pages: usize,
+	/// Counts calls to `begin_use`, so pages can be ordered by when they were last used.
+	tick: u64,
+}
+impl GlyphAtlas {
+	pub(super) fn new() -> Self {
+		Self { pages: vec![], glyphs: HashMap::new(), max_pages: DEFAULT_MAX_PAGES, tick: 0 }
+	}
+
+	pub(super) fn set_max_pages(&mut self, max_pages: usize) {
+		self.max_pages = max_pages.max(1);
+	}
+
+	pub(super) fn max_pages(&self) -> usize {
+		self.max_pages
+	}
+
+	/// Starts laying out a string. Pages used from here until the next call aren't evicted.
+	pub(super) fn begin_use(&mut self) {
+		self.tick += 1;
+	}
+
+	/// Finds a glyph, or rasterizes it with `rasterize` if it isn't in the atlas. `rasterize` returns the glyph's
+	/// pixels, their width and height, and their offset from the glyph's position, or `None` if it has no pixels.
+	pub(super) fn glyph(
+		&mut self,
+		id: GlyphId,
+		rasterize: impl FnOnce() -> Option<(Vec<u8>, [u32; 2], [i32; 2])>,
+	) -> Option<AtlasGlyph> {
+		let tick = self.tick;
+		if let Some(glyph) = self.glyphs.get(&id) {
+			if let Some(glyph) = glyph {
+				self.pages[glyph.page].last_used = tick;
+			}
+			return *glyph;
+		}
+
+		let glyph = rasterize().map(|(pixels, size, offset)| {
+			let (page, origin) = self.allocate(size);
+			self.pages[page].write(origin, size, &pixels);
+			AtlasGlyph { page: page, origin: origin, size: size, offset: offset }
+		});
+		self.glyphs.insert(id, glyph);
+		glyph
+	}
+
+	/// Uploads the pages that have changed since they were last uploaded.
+	pub(super) fn upload(
+		&mut self,
+		queue: &Arc<Queue>,
+		memory: &Arc<MemoryStats>,
+		name: &str,
+	) -> Result<(), BatchError> {
+		for (i, page) in self.pages.iter_mut().enumerate().filter(|(_, page)| page.dirty) {
+			let (image, future) =
+				ImmutableImage
+					::from_iter(
+						page.pixels.iter().cloned(),
+						Dimensions::Dim2d { width: page.size, height: page.size },
+						Format::R8Unorm,
+						queue.clone(),
+					)?;
+
+			let bytes = page.pixels.len() as u64;
+			page.image = Some(Arc::new(PageImage {
+				image: image,
+				future: Mutex::new(Some(Arc::new(future.then_signal_fence_and_flush()?))),
+				_memory: memory.track(MemoryCategory::Fonts, format!("{} page {}", name, i), bytes),
+			}));
+			page.dirty = false;
+		}
+
+		Ok(())
+	}
+
+	/// The current image of a page. Only valid after `upload`.
+	pub(super) fn page_image(&self, page: usize) -> &Arc<PageImage> {
+		self.pages[page].image.as_ref().unwrap()
+	}
+
+	/// Finds space for a glyph, adding a page or evicting one if none of the pages have room.
+	fn allocate(&mut self, size: [u32; 2]) -> (usize, [u32; 2]) {
+		let padded = [size[0] + PADDING, size[1] + PADDING];
+		let found = self.pages.iter_mut()
+			.enumerate()
+			.filter_map(|(i, page)| page.allocate(padded).map(|origin| (i, origin)))
+			.next();
+		if let Some(found) = found {
+			return found;
+		}
+
+		// pages used by the string being laid out are kept, so it may need more pages than the limit
+		let tick = self.tick;
+		let page_size = PAGE_SIZE.max(padded[0].max(padded[1]).next_power_of_two());
+		let evict = self.pages.iter()
+			.enumerate()
+			.filter(|(_, page)| page.last_used != tick && page.size >= page_size)
+			.min_by_key(|(_, page)| page.last_used)
+			.map(|(i, _)| i)
+			.filter(|_| self.pages.len() >= self.max_pages);
+		let page =
+			if let Some(page) = evict {
+				self.glyphs.retain(|_, glyph| glyph.map_or(true, |glyph| glyph.page != page));
+				self.pages[page].clear();
+				page
+			} else {
+				self.pages.push(AtlasPage::new(page_size));
+				self.pages.len() - 1
+			};
+
+		self.pages[page].last_used = tick;
+		let origin = self.pages[page].allocate(padded).unwrap();
+		(page, origin)
+	}
+}
+
+/// Where a glyph is in the atlas.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct AtlasGlyph {
+	pub(super) page: usize,
+	pub(super) origin: [u32; 2],
+	pub(super) size: [u32; 2],
+	/// The offset of the glyph's pixels from its position, in pixels.
+	pub(super) offset: [i32; 2],
+}
+
+/// A page's image as of one upload. The image is being uploaded until its future is signaled.
+pub(super) struct PageImage {
+	pub(super) image: Arc<ImmutableImage<Format>>,
+	future: Mutex<Option<Arc<FenceSignalFuture<PageFuture>>>>,
+	_memory: MemoryHandle,
+}
+impl PageImage {
+	/// Whether the upload has finished, without waiting for it. Fails if the upload itself failed.
+	pub(super) fn is_uploaded(&self) -> Result<bool, FlushError> {
+		let mut future = self.future.lock().unwrap();
+		if let Some(fut) = future.as_ref() {
+			match fut.wait(Some(Default::default())) {
+				Ok(()) => *future = None,
+				Err(FlushError::Timeout) => return Ok(false),
+				Err(err) => return Err(err),
+			}
+		}
+		Ok(true)
+	}
+
+	pub(super) fn size(&self) -> u32 {
+		match self.image.dimensions() {
+			Dimensions::Dim2d { width, .. } => width,
+			_ => unreachable!(),
+		}
+	}
+}
+
+type PageFuture = CommandBufferExecFuture<NowFuture, AutoCommandBuffer>;
+
+struct AtlasPage {
+	size: u32,
+	pixels: Vec<u8>,
+	/// Rows of glyphs, packed left to right.
+	shelves: Vec<Shelf>,
+	/// The top of the space below the last shelf.
+	next_shelf: u32,
+	last_used: u64,
+	/// Whether `pixels` has changed since `image` was uploaded.
+	dirty: bool,
+	image: Option<Arc<PageImage>>,
+}
+impl AtlasPage {
+	fn new(size: u32) -> Self {
+		Self {
+			size: size,
+			pixels: vec![0; size as usize * size as usize],
+			shelves: vec![],
+			next_shelf: 0,
+			last_used: 0,
+			dirty: true,
+			image: None,
+		}
+	}
+
+	fn allocate(&mut self, [width, height]: [u32; 2]) -> Option<[u32; 2]> {
+		if width > self.size {
+			return None;
+		}
+
+		// shelves much taller than the glyph are skipped, so small glyphs don't waste the space tall ones need
+		let size = self.size;
+		let shelf = self.shelves.iter_mut()
+			.find(|shelf| height <= shelf.height && shelf.height <= height + height / 2 + 1 && shelf.x + width <= size);
+		if let Some(shelf) = shelf {
+			let origin = [shelf.x, shelf.y];
+			shelf.x += width;
+			return Some(origin);
+		}
+
+		if self.next_shelf + height > self.size {
+			return None;
+		}
+		let origin = [0, self.next_shelf];
+		self.shelves.push(Shelf { y: self.next_shelf, height: height, x: width });
+		self.next_shelf += height;
+		Some(origin)
+	}
+
+	fn write(&mut self, origin: [u32; 2], size: [u32; 2], pixels: &[u8]) {
+		for row in 0..size[1] as usize {
+			let start = (origin[1] as usize + row) * self.size as usize + origin[0] as usize;
+			self.pixels[start..start + size[0] as usize]
+				.copy_from_slice(&pixels[row * size[0] as usize..(row + 1) * size[0] as usize]);
+		}
+		self.dirty = true;
+	}
+
+	fn clear(&mut self) {
+		for pixel in &mut self.pixels {
+			*pixel = 0;
+		}
+		self.shelves.clear();
+		self.next_shelf = 0;
+		self.dirty = true;
+	}
+}
+
+struct Shelf {
+	y: u32,
+	height: u32,
+	/// The left edge of the free space at the end of the shelf.
+	x: u32,
+}
@@ -0,0 +1,71 @@
+use unicode_bidi::BidiInfo;
+
+/// Splits text into paragraphs, one per line, and puts each in the order it's displayed, from left to right. Right to
+/// left runs, like Arabic or Hebrew words, are reversed and their brackets mirrored, following the Unicode
+/// bidirectional algorithm.
+pub(super) fn visual_lines(text: &str) -> Vec<String> {
+	let bidi = BidiInfo::new(text, None);
+	bidi.paragraphs.iter()
+		.map(|paragraph| {
+			let content = text[paragraph.range.clone()].trim_end_matches(is_paragraph_separator);
+			let line = paragraph.range.start..paragraph.range.start + content.len();
+			let levels = bidi.reordered_levels(paragraph, line.clone());
+			let mut chars = content.char_indices()
+				.map(|(i, ch)| {
+					let level = levels[line.start + i];
+					(if level.is_rtl() { mirror(ch) } else { ch }, level.number())
+				})
+				.collect::<Vec<_>>();
+			reverse_runs(&mut chars);
+			chars.into_iter().map(|(ch, _)| ch).collect()
+		})
+		.collect()
+}
+
+/// Reverses every run of characters at each level or higher, from the highest level down to the lowest odd level.
+fn reverse_runs(chars: &mut [(char, u8)]) {
+	let highest = chars.iter().map(|&(_, level)| level).max().unwrap_or(0);
+	let lowest_odd = match chars.iter().map(|&(_, level)| level).filter(|level| level % 2 == 1).min() {
+		Some(level) => level,
+		None => return,
+	};
+
+	for level in (lowest_odd..=highest).rev() {
+		let mut i = 0;
+		while i < chars.len() {
+			if chars[i].1 < level {
+				i += 1;
+				continue;
+			}
+
+			let start = i;
+			while i < chars.len() && chars[i].1 >= level {
+				i += 1;
+			}
+			chars[start..i].reverse();
+		}
+	}
+}
+
+fn is_paragraph_separator(ch: char) -> bool {
+	ch == '\n' || ch == '\r' || ch == '\u{85}' || ch == '\u{2029}'
+}
+
+/// The mirrored form of a character in a right to left run, for the common paired brackets.
+fn mirror(ch: char) -> char {
+	match ch {
+		'(' => ')',
+		')' => '(',
+		'[' => ']',
+		']' => '[',
+		'{' => '}',
+		'}' => '{',
+		'<' => '>',
+		'>' => '<',
+		'«' => '»',
+		'»' => '«',
+		'‹' => '›',
+		'›' => '‹',
+		_ => ch,
+	}
+}
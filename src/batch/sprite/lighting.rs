@@ -0,0 +1,359 @@
+use super::Drawable2D;
+use super::shaders::{ SpriteBatchShaders, SpriteVertex, light_vs };
+use super::shared::SpriteBatchShared;
+use crate::batch::BatchError;
+use std::{ f32::consts::PI, sync::Arc };
+use vulkano::{
+	single_pass_renderpass,
+	buffer::{ BufferUsage, CpuBufferPool },
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::{
+		DescriptorSet,
+		descriptor_set::{ FixedSizeDescriptorSetsPool, PersistentDescriptorSet },
+	},
+	format::Format,
+	framebuffer::{ Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass },
+	image::AttachmentImage,
+	pipeline::{
+		GraphicsPipeline,
+		GraphicsPipelineAbstract,
+		blend::{ AttachmentBlend, BlendFactor, BlendOp },
+	},
+};
+
+/// Must match the array size in the light shader. Occluder segments past this are ignored.
+pub const MAX_OCCLUDER_SEGMENTS: usize = 256;
+
+const NORMALS_FORMAT: Format = Format::R8G8B8A8Unorm;
+const LIGHTS_FORMAT: Format = Format::R16G16B16A16Sfloat;
+
+/// Lights and shadow casters for a `SpriteBatch`. Positions are in pixels relative to the batch's viewport, like
+/// sprite positions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lighting2D {
+	/// The light everything gets, even outside the range of every light.
+	pub ambient: [f32; 3],
+	pub lights: Vec<Light2D>,
+	pub occluders: Vec<Occluder2D>,
+}
+impl Lighting2D {
+	pub fn new(ambient: [f32; 3]) -> Self {
+		Self { ambient: ambient, lights: vec![], occluders: vec![] }
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light2D {
+	pub position: [f32; 2],
+	/// Values above 1 overbrighten what the light touches.
+	pub color: [f32; 3],
+	/// How far the light reaches, in pixels. It fades out smoothly up to this distance.
+	pub range: f32,
+	/// How far above the sprites the light is, in pixels. Lower lights make normal maps stand out more.
+	pub height: f32,
+	/// The radius of the light's source, in pixels. 0 casts hard shadows, and larger sources cast softer ones.
+	pub source_radius: f32,
+	pub casts_shadows: bool,
+	/// Restricts the light to a cone. `None` lights every direction.
+	pub cone: Option<LightCone>,
+}
+impl Light2D {
+	pub fn point(position: [f32; 2], color: [f32; 3], range: f32) -> Self {
+		Self {
+			position: position,
+			color: color,
+			range: range,
+			height: 64.0,
+			source_radius: 0.0,
+			casts_shadows: true,
+			cone: None,
+		}
+	}
+
+	/// A light that only shines within `angle` radians either side of `direction`. See `LightCone`.
+	pub fn cone(position: [f32; 2], color: [f32; 3], range: f32, direction: f32, angle: f32) -> Self {
+		Self {
+			cone: Some(LightCone { direction: direction, angle: angle, softness: 0.1 }),
+			.. Self::point(position, color, range)
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightCone {
+	/// The direction the cone points, in radians clockwise from the right, since pixels go down.
+	pub direction: f32,
+	/// Half of the cone's width, in radians.
+	pub angle: f32,
+	/// How far past `angle` the light takes to fade out, in radians.
+	pub softness: f32,
+}
+
+/// A shape that casts shadows, made of line segments between its points.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Occluder2D {
+	pub points: Vec<[f32; 2]>,
+	/// Whether the last point connects back to the first.
+	pub closed: bool,
+}
+impl Occluder2D {
+	pub fn polygon(points: Vec<[f32; 2]>) -> Self {
+		Self { points: points, closed: true }
+	}
+
+	pub fn polyline(points: Vec<[f32; 2]>) -> Self {
+		Self { points: points, closed: false }
+	}
+
+	fn segments<'a>(&'a self) -> impl Iterator<Item = [f32; 4]> + 'a {
+		// pairs each point with the one before it, starting from the last point if the shape is closed
+		let closing = if self.closed && self.points.len() > 2 { self.points.last().cloned() } else { None };
+		let skip = if closing.is_some() { 0 } else { 1 };
+		closing.into_iter()
+			.chain(self.points.iter().cloned())
+			.zip(self.points.iter().skip(skip))
+			.map(|([x0, y0], &[x1, y1])| [x0, y0, x1, y1])
+	}
+}
+
+/// The pipelines and render passes for 2D lighting, shared by every batch.
+pub(crate) struct LightingPipelines {
+	render_pass_normals: Arc<RenderPassAbstract + Send + Sync>,
+	render_pass_lights: Arc<RenderPassAbstract + Send + Sync>,
+	pub(crate) pipeline_normal: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	pipeline_light: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	pipeline_composite: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+}
+impl LightingPipelines {
+	pub(crate) fn new(shaders: &SpriteBatchShaders, subpass: Subpass<Arc<RenderPassAbstract + Send + Sync>>) -> Self {
+		let render_pass_normals: Arc<RenderPassAbstract + Send + Sync> =
+			Arc::new(
+				single_pass_renderpass!(
+					shaders.device().clone(),
+					attachments: { normals: { load: Clear, store: Store, format: NORMALS_FORMAT, samples: 1, } },
+					pass: { color: [normals], depth_stencil: {} }
+				).expect("failed to create render pass")
+			);
+		let render_pass_lights: Arc<RenderPassAbstract + Send + Sync> =
+			Arc::new(
+				single_pass_renderpass!(
+					shaders.device().clone(),
+					attachments: { lights: { load: Clear, store: Store, format: LIGHTS_FORMAT, samples: 1, } },
+					pass: { color: [lights], depth_stencil: {} }
+				).expect("failed to create render pass")
+			);
+
+		let pipeline_normal = Arc::new(
+			GraphicsPipeline::start()
+				.vertex_input_single_buffer::<SpriteVertex>()
+				.vertex_shader(shaders.normal_vertex_shader().main_entry_point(), ())
+				.triangle_list()
+				.viewports_scissors_dynamic(1)
+				.fragment_shader(shaders.normal_fragment_shader().main_entry_point(), ())
+				.render_pass(Subpass::from(render_pass_normals.clone(), 0).unwrap())
+				.build(shaders.device().clone())
+				.expect("failed to create pipeline")
+		);
+
+		let pipeline_light = Arc::new(
+			GraphicsPipeline::start()
+				.vertex_input_single_buffer::<SpriteVertex>()
+				.vertex_shader(shaders.light_vertex_shader().main_entry_point(), ())
+				.triangle_list()
+				.viewports_scissors_dynamic(1)
+				.fragment_shader(shaders.light_fragment_shader().main_entry_point(), ())
+				.render_pass(Subpass::from(render_pass_lights.clone(), 0).unwrap())
+				.blend_collective(AttachmentBlend {
+					enabled: true,
+					color_op: BlendOp::Add,
+					color_source: BlendFactor::One,
+					color_destination: BlendFactor::One,
+					.. AttachmentBlend::pass_through()
+				})
+				.build(shaders.device().clone())
+				.expect("failed to create pipeline")
+		);
+
+		// multiplies the sprites by the light that reaches them
+		let pipeline_composite = Arc::new(
+			GraphicsPipeline::start()
+				.vertex_input_single_buffer::<SpriteVertex>()
+				.vertex_shader(shaders.light_composite_vertex_shader().main_entry_point(), ())
+				.triangle_list()
+				.viewports_scissors_dynamic(1)
+				.fragment_shader(shaders.light_composite_fragment_shader().main_entry_point(), ())
+				.render_pass(subpass)
+				.blend_collective(AttachmentBlend {
+					enabled: true,
+					color_op: BlendOp::Add,
+					color_source: BlendFactor::DstColor,
+					color_destination: BlendFactor::Zero,
+					alpha_op: BlendOp::Add,
+					alpha_source: BlendFactor::Zero,
+					alpha_destination: BlendFactor::One,
+					.. AttachmentBlend::pass_through()
+				})
+				.build(shaders.device().clone())
+				.expect("failed to create pipeline")
+		);
+
+		let debug_names = shaders.device_ctx().debug_names();
+		debug_names.name_pipeline(&*pipeline_normal, "sprite normals");
+		debug_names.name_pipeline(&*pipeline_light, "2d light");
+		debug_names.name_pipeline(&*pipeline_composite, "2d light composite");
+
+		Self {
+			render_pass_normals: render_pass_normals,
+			render_pass_lights: render_pass_lights,
+			pipeline_normal: pipeline_normal,
+			pipeline_light: pipeline_light,
+			pipeline_composite: pipeline_composite,
+		}
+	}
+}
+
+/// A batch's normal buffer and light map, sized to its target.
+pub(crate) struct LightBuffers {
+	dimensions: [u32; 2],
+	normals_framebuffer: Arc<FramebufferAbstract + Send + Sync>,
+	lights_framebuffer: Arc<FramebufferAbstract + Send + Sync>,
+	normals_desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	composite_desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	segments_pool: CpuBufferPool<[f32; 4]>,
+	segments_desc_pool: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+}
+impl LightBuffers {
+	pub(crate) fn new(shared: &SpriteBatchShared, dimensions: [u32; 2]) -> Result<Self, BatchError> {
+		let device = shared.shaders().device();
+		let pipelines = shared.lighting();
+		let sampler = shared.shaders().sprite_sampler();
+
+		let normals = AttachmentImage::sampled(device.clone(), dimensions, NORMALS_FORMAT)?;
+		let lights = AttachmentImage::sampled(device.clone(), dimensions, LIGHTS_FORMAT)?;
+
+		let make_framebuffer = |render_pass: &Arc<RenderPassAbstract + Send + Sync>, image: &Arc<AttachmentImage>| {
+			Framebuffer::start(render_pass.clone())
+				.add(image.clone())
+				.and_then(|fb| fb.build())
+				.map(|fb| Arc::new(fb) as Arc<FramebufferAbstract + Send + Sync>)
+		};
+
+		Ok(Self {
+			dimensions: dimensions,
+			normals_framebuffer: make_framebuffer(&pipelines.render_pass_normals, &normals)?,
+			lights_framebuffer: make_framebuffer(&pipelines.render_pass_lights, &lights)?,
+			normals_desc:
+				Arc::new(
+					PersistentDescriptorSet::start(pipelines.pipeline_light.clone(), 1)
+						.add_sampled_image(normals, sampler.clone())
+						.unwrap()
+						.build()
+						.unwrap()
+				),
+			composite_desc:
+				Arc::new(
+					PersistentDescriptorSet::start(pipelines.pipeline_composite.clone(), 0)
+						.add_sampled_image(lights, sampler.clone())
+						.unwrap()
+						.build()
+						.unwrap()
+				),
+			segments_pool: CpuBufferPool::new(device.clone(), BufferUsage::uniform_buffer()),
+			segments_desc_pool: FixedSizeDescriptorSetsPool::new(pipelines.pipeline_light.clone(), 2),
+		})
+	}
+
+	pub(crate) fn dimensions(&self) -> [u32; 2] {
+		self.dimensions
+	}
+
+	/// Records the normal and light passes. This must be called outside of a render pass, before the sprites are drawn.
+	pub(crate) fn record(
+		&mut self,
+		mut cmd: AutoCommandBufferBuilder,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		sprites: &mut [Box<Drawable2D>],
+		visible: &[bool],
+		lighting: &Lighting2D,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		// sprites without normal maps are lit as if they're flat
+		let flat = [0.5, 0.5, 1.0, 1.0];
+		cmd = cmd.begin_render_pass(self.normals_framebuffer.clone(), false, vec![flat.into()]).unwrap();
+		for (sprite, _) in sprites.iter_mut().zip(visible).filter(|(_, &visible)| visible) {
+			cmd = sprite.add_normal_commands(shared, target_desc, cmd, state)?;
+		}
+		cmd = cmd.end_render_pass().unwrap();
+
+		let mut segments =
+			lighting.occluders.iter()
+				.flat_map(|occluder| occluder.segments())
+				.take(MAX_OCCLUDER_SEGMENTS)
+				.collect::<Vec<_>>();
+		let segment_count = segments.len() as u32;
+		segments.resize(MAX_OCCLUDER_SEGMENTS, [0.0; 4]);
+		let segments = self.segments_pool.chunk(segments.into_iter())?;
+		let segments_desc = Arc::new(self.segments_desc_pool.next().add_buffer(segments).unwrap().build().unwrap());
+
+		let [r, g, b] = lighting.ambient;
+		cmd = cmd.begin_render_pass(self.lights_framebuffer.clone(), false, vec![[r, g, b, 1.0].into()]).unwrap();
+		for light in &lighting.lights {
+			let (cone_direction, cone_cos, cone_softness) =
+				match light.cone {
+					Some(cone) => {
+						let outer = (cone.angle + cone.softness).min(PI).cos();
+						let inner = cone.angle.min(PI).cos();
+						([cone.direction.cos(), cone.direction.sin()], outer, (inner - outer).max(0.0001))
+					},
+					None => ([1.0, 0.0], -2.0, 0.0001),
+				};
+
+			let draw =
+				light_vs::ty::LightDraw {
+					position: light.position,
+					cone_direction: cone_direction,
+					color: light.color,
+					range: light.range,
+					height: light.height,
+					source_radius: light.source_radius,
+					cone_cos: cone_cos,
+					cone_softness: cone_softness,
+					segment_count: if light.casts_shadows { segment_count } else { 0 },
+				};
+
+			shared.shaders().device_ctx().render_stats().record_draw(2);
+			cmd = cmd
+				.draw(
+					shared.lighting().pipeline_light.clone(),
+					state,
+					vec![shared.shaders().vertices().clone()],
+					(target_desc.clone(), self.normals_desc.clone(), segments_desc.clone()),
+					draw,
+				)
+				.unwrap();
+		}
+
+		Ok(cmd.end_render_pass().unwrap())
+	}
+
+	/// Multiplies everything drawn so far by the light map. This is recorded in the sprite render pass, after every
+	/// sprite.
+	pub(crate) fn draw_composite(
+		&self,
+		cmd: AutoCommandBufferBuilder,
+		shared: &SpriteBatchShared,
+		state: &DynamicState,
+	) -> AutoCommandBufferBuilder {
+		shared.shaders().device_ctx().render_stats().record_draw(2);
+		cmd
+			.draw(
+				shared.lighting().pipeline_composite.clone(),
+				state,
+				vec![shared.shaders().vertices().clone()],
+				self.composite_desc.clone(),
+				(),
+			)
+			.unwrap()
+	}
+}
@@ -0,0 +1,245 @@
+use super::shared::SpriteBatchShared;
+use super::textures::MAX_SPRITE_TEXTURES;
+use crate::{ ObjectId, ObjectIdRoot };
+use crate::device::{
+	BlockLayout,
+	DeviceCtx,
+	MaterialLayout,
+	MaterialSet,
+	ShaderLoadError,
+	ShaderParams,
+	ShaderReflection,
+};
+#[cfg(feature = "shader-compiler")]
+use crate::device::{ ShaderCompileError, ShaderKind };
+use std::{ borrow::Cow, ffi::CStr, option::IntoIter as OptionIntoIter, sync::Arc };
+use vulkano::{
+	OomError,
+	descriptor::{
+		descriptor::{
+			DescriptorDesc,
+			DescriptorDescTy,
+			DescriptorImageDesc,
+			DescriptorImageDescArray,
+			DescriptorImageDescDimensions,
+			ShaderStages,
+		},
+		pipeline_layout::{ PipelineLayoutDesc, PipelineLayoutDescPcRange },
+	},
+	format::Format,
+	pipeline::{
+		GraphicsPipelineAbstract,
+		GraphicsPipelineCreationError,
+		shader::{ GraphicsEntryPoint, GraphicsShaderType, ShaderInterfaceDef, ShaderInterfaceDefEntry, ShaderModule },
+	},
+};
+
+/// A user fragment shader for sprites, loaded from SPIR-V. It replaces the built-in sprite fragment shader, and must
+/// use this interface:
+///
+/// ```glsl
+/// layout(location = 0) in vec2 tex_coords;
+/// layout(location = 0) out vec4 f_color;
+///
+/// layout(set = 1, binding = 0) uniform sampler2D textures[64];
+/// layout(set = 2, binding = 0) uniform Params { ... } params;
+/// // any number of textures of the material's own, set by name with `ShaderParams::set_texture`
+/// layout(set = 2, binding = 1) uniform sampler2D mask;
+///
+/// layout(push_constant) uniform SpriteDraw {
+/// 	vec2 pos;
+/// 	uint texture_index;
+/// } draw;
+///
+/// // set when the target format is linear, so the shader must encode to srgb itself
+/// layout(constant_id = 0) const bool ENCODE_SRGB = false;
+/// ```
+///
+/// Any of these can be left out if the shader doesn't use them, and `Params` can contain anything. Set 2 belongs to the
+/// material, and its layout is built from what the shader declares.
+pub struct SpriteShader {
+	module: Arc<ShaderModule>,
+	reflection: ShaderReflection,
+	layout: MaterialLayout<SpriteShaderLayout>,
+	id_root: ObjectIdRoot,
+}
+impl SpriteShader {
+	/// # Safety
+	///
+	/// The bytes must be a valid SPIR-V fragment shader with a `main` entry point, using the interface above. The
+	/// shader's descriptors and push constants are read from the SPIR-V and checked against the interface, and loading
+	/// fails if they don't match, but its inputs and outputs aren't checked, so a mismatch there is undefined behavior.
+	pub unsafe fn from_spirv(device: &DeviceCtx, spirv: &[u8]) -> Result<Arc<Self>, ShaderLoadError> {
+		let reflection = ShaderReflection::new(spirv)?;
+		let stages = ShaderStages { fragment: true, .. ShaderStages::none() };
+		let layout = reflection.material_layout(SpriteShaderLayout, 2, stages)?;
+
+		Ok(Arc::new(Self {
+			module: ShaderModule::new(device.device().clone(), spirv)?,
+			reflection: reflection,
+			layout: layout,
+			id_root: ObjectIdRoot::new(),
+		}))
+	}
+
+	/// The descriptors and push constants the shader declares.
+	pub fn reflection(&self) -> &ShaderReflection {
+		&self.reflection
+	}
+
+	/// The layout of the shader's `Params` block, if it has one.
+	pub fn params_layout(&self) -> Option<&BlockLayout> {
+		self.reflection.descriptor(2, 0).and_then(|desc| desc.block.as_ref())
+	}
+
+	/// Compiles GLSL source with `DeviceCtx::shader_compiler` and loads the result.
+	///
+	/// # Safety
+	///
+	/// The shader must use the interface above, as with `from_spirv`.
+	#[cfg(feature = "shader-compiler")]
+	pub unsafe fn from_glsl(device: &DeviceCtx, source: &str) -> Result<Arc<Self>, ShaderCompileError> {
+		let spirv = device.shader_compiler().compile_glsl(ShaderKind::Fragment, source, "sprite shader")?;
+		Ok(Self::from_spirv(device, &spirv)?)
+	}
+
+	pub(crate) fn entry_point(
+		&self,
+	) -> GraphicsEntryPoint<(), SpriteShaderInterface, SpriteShaderInterface, MaterialLayout<SpriteShaderLayout>> {
+		let input =
+			SpriteShaderInterface(ShaderInterfaceDefEntry {
+				location: 0..1,
+				format: Format::R32G32Sfloat,
+				name: Some(Cow::Borrowed("tex_coords")),
+			});
+		let output =
+			SpriteShaderInterface(ShaderInterfaceDefEntry {
+				location: 0..1,
+				format: Format::R32G32B32A32Sfloat,
+				name: Some(Cow::Borrowed("f_color")),
+			});
+
+		unsafe {
+			self.module.graphics_entry_point(
+				CStr::from_bytes_with_nul_unchecked(b"main\0"),
+				input,
+				output,
+				self.layout.clone(),
+				GraphicsShaderType::Fragment,
+			)
+		}
+	}
+
+	pub(crate) fn id_root(&self) -> &ObjectIdRoot {
+		&self.id_root
+	}
+}
+
+/// A `SpriteShader` and the parameters it's drawn with. Give it to `Sprite::set_material`.
+pub struct SpriteMaterial {
+	shader: Arc<SpriteShader>,
+	pipeline: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	params: ShaderParams,
+}
+impl SpriteMaterial {
+	/// Fails if the shader doesn't match the interface documented on `SpriteShader`.
+	pub fn new(shared: &SpriteBatchShared, shader: Arc<SpriteShader>) -> Result<Self, GraphicsPipelineCreationError> {
+		let pipeline = shared.pipeline_for(&shader)?;
+		let (default_texture, sampler) = (shared.shaders().white_pixel(), shared.shaders().sprite_sampler().clone());
+		let params = ShaderParams::new(&pipeline, &shader.reflection, 2, default_texture, sampler);
+
+		Ok(Self { shader: shader, pipeline: pipeline, params: params })
+	}
+
+	pub fn shader(&self) -> &Arc<SpriteShader> {
+		&self.shader
+	}
+
+	pub fn params(&self) -> &ShaderParams {
+		&self.params
+	}
+
+	/// The shader's `Params` block and textures, for setting their contents.
+	pub fn params_mut(&mut self) -> &mut ShaderParams {
+		&mut self.params
+	}
+
+	pub(crate) fn pipeline(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		&self.pipeline
+	}
+
+	pub(crate) fn params_desc(&self) -> Result<MaterialSet, OomError> {
+		self.params.descriptor_set()
+	}
+}
+
+/// A pipeline created for a `SpriteShader`, cached by `SpriteBatchShared`.
+pub(crate) struct SpriteShaderPipeline {
+	pub(crate) shader_id: ObjectId,
+	pub(crate) pipeline: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+}
+
+/// A shader interface with a single entry, which is all the sprite shaders have.
+#[derive(Clone, Debug)]
+pub(crate) struct SpriteShaderInterface(ShaderInterfaceDefEntry);
+unsafe impl ShaderInterfaceDef for SpriteShaderInterface {
+	type Iter = OptionIntoIter<ShaderInterfaceDefEntry>;
+
+	fn elements(&self) -> Self::Iter {
+		Some(self.0.clone()).into_iter()
+	}
+}
+
+/// The descriptors and push constants the engine binds for a custom sprite fragment shader. The material's own set
+/// comes from the shader's reflection.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SpriteShaderLayout;
+unsafe impl PipelineLayoutDesc for SpriteShaderLayout {
+	fn num_sets(&self) -> usize {
+		2
+	}
+
+	fn num_bindings_in_set(&self, set: usize) -> Option<usize> {
+		match set {
+			0 => Some(0),
+			1 => Some(1),
+			_ => None,
+		}
+	}
+
+	fn descriptor(&self, set: usize, binding: usize) -> Option<DescriptorDesc> {
+		let stages = ShaderStages { fragment: true, .. ShaderStages::none() };
+		match (set, binding) {
+			(1, 0) =>
+				Some(DescriptorDesc {
+					ty: DescriptorDescTy::CombinedImageSampler(DescriptorImageDesc {
+						sampled: true,
+						dimensions: DescriptorImageDescDimensions::TwoDimensional,
+						format: None,
+						multisampled: false,
+						array_layers: DescriptorImageDescArray::NonArrayed,
+					}),
+					array_count: MAX_SPRITE_TEXTURES as u32,
+					stages: stages,
+					readonly: true,
+				}),
+			_ => None,
+		}
+	}
+
+	fn num_push_constants_ranges(&self) -> usize {
+		1
+	}
+
+	fn push_constants_range(&self, num: usize) -> Option<PipelineLayoutDescPcRange> {
+		if num == 0 {
+			Some(PipelineLayoutDescPcRange {
+				offset: 0,
+				size: 12,
+				stages: ShaderStages { fragment: true, .. ShaderStages::none() },
+			})
+		} else {
+			None
+		}
+	}
+}
@@ -0,0 +1,227 @@
+use super::{ Drawable2D, target_uniform };
+use super::shared::SpriteBatchShared;
+use crate::batch::BatchError;
+use std::sync::Arc;
+use vulkano::{
+	OomError,
+	buffer::{ BufferUsage, CpuBufferPool },
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::{ DescriptorSet, descriptor_set::FixedSizeDescriptorSetsPool },
+	memory::DeviceMemoryAllocError,
+	pipeline::GraphicsPipelineAbstract,
+};
+
+/// The scale and opacity a drawable's ancestors apply to it, passed down by each `Node2D` before it draws its
+/// children.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Inherited2D {
+	pub scale: f32,
+	pub opacity: f32,
+}
+impl Default for Inherited2D {
+	fn default() -> Self {
+		Self { scale: 1.0, opacity: 1.0 }
+	}
+}
+
+/// A node in a tree of drawables, like a UI panel and its contents. Children are positioned relative to the node, so
+/// moving it moves them, and they're scaled and faded along with it. Nodes can be children of other nodes, and their
+/// transforms and opacities multiply down the tree.
+///
+/// The transform is applied to the viewport, so it can move and scale children but not rotate them. Opacity fades
+/// the built-in drawables, but sprites with a custom `SpriteMaterial` ignore it.
+pub struct Node2D {
+	position: [f32; 2],
+	scale: f32,
+	opacity: f32,
+	visible: bool,
+	children: Vec<Box<Drawable2D>>,
+	inherited: Inherited2D,
+	/// Created the first time the node is drawn faded, since that's the first time it sees the device.
+	target_pool: Option<CpuBufferPool<u8>>,
+	target_desc_pool: Option<FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>>,
+}
+impl Node2D {
+	pub fn new(position: [f32; 2]) -> Self {
+		Self {
+			position: position,
+			scale: 1.0,
+			opacity: 1.0,
+			visible: true,
+			children: vec![],
+			inherited: Inherited2D::default(),
+			target_pool: None,
+			target_desc_pool: None,
+		}
+	}
+
+	/// Children are drawn in the order they're added, after the ones before them.
+	pub fn add(&mut self, child: Box<Drawable2D>) {
+		self.children.push(child);
+	}
+
+	pub fn children(&self) -> &[Box<Drawable2D>] {
+		&self.children
+	}
+
+	pub fn children_mut(&mut self) -> &mut Vec<Box<Drawable2D>> {
+		&mut self.children
+	}
+
+	/// Where the children's origin is, in the parent's pixels.
+	pub fn set_position(&mut self, position: [f32; 2]) {
+		self.position = position;
+	}
+
+	pub fn position(&self) -> [f32; 2] {
+		self.position
+	}
+
+	/// Scales the children around the node's position.
+	pub fn set_scale(&mut self, scale: f32) {
+		self.scale = scale;
+	}
+
+	pub fn scale(&self) -> f32 {
+		self.scale
+	}
+
+	/// From 0.0 for invisible to 1.0 for opaque.
+	pub fn set_opacity(&mut self, opacity: f32) {
+		self.opacity = opacity.max(0.0).min(1.0);
+	}
+
+	pub fn opacity(&self) -> f32 {
+		self.opacity
+	}
+
+	/// Hides the node and all of its children.
+	pub fn set_visible(&mut self, visible: bool) {
+		self.visible = visible;
+	}
+
+	pub fn is_visible(&self) -> bool {
+		self.visible
+	}
+
+	/// What the children inherit from this node and its ancestors.
+	fn passed_down(&self) -> Inherited2D {
+		Inherited2D { scale: self.inherited.scale * self.scale, opacity: self.inherited.opacity * self.opacity }
+	}
+
+	/// Moves and scales the viewport, so the children's pixels land where the node puts them.
+	fn child_state(&self, state: &DynamicState) -> DynamicState {
+		let mut state = state.clone();
+		if let Some(viewports) = &mut state.viewports {
+			for viewport in viewports {
+				viewport.origin = [
+					viewport.origin[0] + self.position[0] * self.inherited.scale,
+					viewport.origin[1] + self.position[1] * self.inherited.scale,
+				];
+				viewport.dimensions = [viewport.dimensions[0] * self.scale, viewport.dimensions[1] * self.scale];
+			}
+		}
+		state
+	}
+
+	/// A copy of the batch's target descriptor with this node's opacity.
+	fn faded_target_desc(
+		&mut self,
+		shared: &SpriteBatchShared,
+		state: &DynamicState,
+		opacity: f32,
+	) -> Result<Arc<DescriptorSet + Send + Sync + 'static>, OomError> {
+		// the viewport is scaled by the ancestors, but the shaders still need the size the batch gave them
+		let dimensions = state.viewports.as_ref().map_or([1.0, 1.0], |viewports| viewports[0].dimensions);
+		let size = [
+			(dimensions[0] / self.inherited.scale).round() as u32,
+			(dimensions[1] / self.inherited.scale).round() as u32,
+		];
+
+		let device = shared.shaders().device();
+		let pool =
+			self.target_pool.get_or_insert_with(|| CpuBufferPool::new(device.clone(), BufferUsage::uniform_buffer()));
+		let target =
+			pool.chunk(target_uniform(size, opacity).into_iter()).map_err(|err| match err {
+				DeviceMemoryAllocError::OomError(err) => err,
+				_ => OomError::OutOfDeviceMemory,
+			})?;
+
+		let desc_pool =
+			self.target_desc_pool
+				.get_or_insert_with(|| FixedSizeDescriptorSetsPool::new(shared.pipeline_sprite().clone(), 0));
+		Ok(Arc::new(desc_pool.next().add_buffer(target).unwrap().build().unwrap()))
+	}
+}
+impl Drawable2D for Node2D {
+	fn add_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		mut cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		let inherited = self.passed_down();
+		if !self.visible || inherited.opacity <= 0.0 {
+			return Ok(cmds);
+		}
+
+		let child_state = self.child_state(state);
+		let target_desc =
+			if self.opacity < 1.0 {
+				self.faded_target_desc(shared, state, inherited.opacity)?
+			} else {
+				// an ancestor has already faded the target, if anything has
+				target_desc.clone()
+			};
+
+		for child in &mut self.children {
+			child.inherit(inherited);
+			cmds = child.add_commands(shared, &target_desc, cmds, &child_state)?;
+		}
+		Ok(cmds)
+	}
+
+	fn add_normal_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		mut cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		let inherited = self.passed_down();
+		if !self.visible || inherited.opacity <= 0.0 {
+			return Ok(cmds);
+		}
+
+		let child_state = self.child_state(state);
+		for child in &mut self.children {
+			child.inherit(inherited);
+			cmds = child.add_normal_commands(shared, target_desc, cmds, &child_state)?;
+		}
+		Ok(cmds)
+	}
+
+	fn bounds(&self) -> Option<[[f32; 2]; 2]> {
+		let mut bounds: Option<[[f32; 2]; 2]> = None;
+		for child in &self.children {
+			let [min, max] = child.bounds()?;
+			let [min, max] = [
+				[self.position[0] + min[0] * self.scale, self.position[1] + min[1] * self.scale],
+				[self.position[0] + max[0] * self.scale, self.position[1] + max[1] * self.scale],
+			];
+			bounds = Some(match bounds {
+				Some([old_min, old_max]) => [
+					[old_min[0].min(min[0]), old_min[1].min(min[1])],
+					[old_max[0].max(max[0]), old_max[1].max(max[1])],
+				],
+				None => [min, max],
+			});
+		}
+		bounds
+	}
+
+	fn inherit(&mut self, inherited: Inherited2D) {
+		self.inherited = inherited;
+	}
+}
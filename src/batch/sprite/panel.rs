@@ -0,0 +1,131 @@
+use super::Drawable2D;
+use super::shaders::panel_vs;
+use super::shared::SpriteBatchShared;
+use crate::batch::BatchError;
+use std::sync::Arc;
+use vulkano::{
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::DescriptorSet,
+};
+
+/// A rectangle with rounded corners, a solid or gradient fill, and an optional drop shadow, drawn entirely on the GPU
+/// so it stays crisp at any size. Colors are sRGB encoded, as in most image editors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Panel {
+	position: [f32; 2],
+	size: [f32; 2],
+	radii: [f32; 4],
+	fill: PanelFill,
+	shadow: Option<DropShadow>,
+}
+impl Panel {
+	pub fn new(position: [f32; 2], size: [f32; 2], fill: PanelFill) -> Self {
+		Self { position: position, size: size, radii: [0.0; 4], fill: fill, shadow: None }
+	}
+
+	pub fn set_position(&mut self, position: [f32; 2]) {
+		self.position = position;
+	}
+
+	pub fn set_size(&mut self, size: [f32; 2]) {
+		self.size = size;
+	}
+
+	/// Rounds all four corners by the same radius, in pixels.
+	pub fn set_corner_radius(&mut self, radius: f32) {
+		self.radii = [radius; 4];
+	}
+
+	/// Rounds each corner separately, clockwise from the top left. Radii larger than half the panel's shortest side are
+	/// reduced to fit.
+	pub fn set_corner_radii(&mut self, radii: [f32; 4]) {
+		self.radii = radii;
+	}
+
+	pub fn set_fill(&mut self, fill: PanelFill) {
+		self.fill = fill;
+	}
+
+	pub fn set_shadow(&mut self, shadow: Option<DropShadow>) {
+		self.shadow = shadow;
+	}
+}
+impl Drawable2D for Panel {
+	fn add_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		let max_radius = self.size[0].min(self.size[1]).max(0.0) / 2.0;
+		let radius = |i: usize| self.radii[i].max(0.0).min(max_radius);
+
+		let (fill_kind, color0, color1, gradient) =
+			match self.fill {
+				PanelFill::Solid(color) => (0, color, color, [0.0; 4]),
+				PanelFill::LinearGradient { start, end, start_color, end_color } =>
+					(1, start_color, end_color, [start[0], start[1], end[0], end[1]]),
+				PanelFill::RadialGradient { center, radius, inner_color, outer_color } =>
+					(2, inner_color, outer_color, [center[0], center[1], radius[0], radius[1]]),
+			};
+		let shadow = self.shadow.unwrap_or(DropShadow { offset: [0.0, 0.0], blur: 0.0, color: [0.0; 4] });
+
+		let draw =
+			panel_vs::ty::PanelDraw {
+				rect: [self.position[0], self.position[1], self.size[0], self.size[1]],
+				radii: [radius(0), radius(1), radius(2), radius(3)],
+				color0: color0,
+				color1: color1,
+				gradient: gradient,
+				shadow_color: shadow.color,
+				shadow_offset: shadow.offset,
+				shadow_blur: shadow.blur.max(0.0),
+				fill_kind: fill_kind,
+			};
+
+		shared.shaders().device_ctx().render_stats().record_draw(2);
+		Ok(
+			cmds
+				.draw(
+					shared.pipeline_panel().clone(),
+					state,
+					vec![shared.shaders().vertices().clone()],
+					target_desc.clone(),
+					draw,
+				)
+				.unwrap()
+		)
+	}
+
+	fn bounds(&self) -> Option<[[f32; 2]; 2]> {
+		let [x, y] = self.position;
+		let mut bounds = [[x, y], [x + self.size[0], y + self.size[1]]];
+		if let Some(shadow) = &self.shadow {
+			let blur = shadow.blur.max(0.0);
+			for axis in 0..2 {
+				bounds[0][axis] = bounds[0][axis].min(bounds[0][axis] + shadow.offset[axis] - blur);
+				bounds[1][axis] = bounds[1][axis].max(bounds[1][axis] + shadow.offset[axis] + blur);
+			}
+		}
+		Some(bounds)
+	}
+}
+
+/// How a `Panel` is filled. Gradient positions are in pixels, relative to the panel's top left corner.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PanelFill {
+	Solid([f32; 4]),
+	/// Blends from `start_color` at `start` to `end_color` at `end`.
+	LinearGradient { start: [f32; 2], end: [f32; 2], start_color: [f32; 4], end_color: [f32; 4] },
+	/// Blends from `inner_color` at `center` to `outer_color` on the ellipse with the given radii.
+	RadialGradient { center: [f32; 2], radius: [f32; 2], inner_color: [f32; 4], outer_color: [f32; 4] },
+}
+
+/// A blurred copy of a panel's shape drawn behind it. `blur` is the width of the soft edge, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DropShadow {
+	pub offset: [f32; 2],
+	pub blur: f32,
+	pub color: [f32; 4],
+}
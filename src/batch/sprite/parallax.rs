@@ -0,0 +1,131 @@
+use super::Drawable2D;
+use super::shaders::parallax_vs;
+use super::shared::{ SpriteBatchShared, SpriteCreationError };
+use super::textures::TextureSlot;
+use crate::batch::BatchError;
+use crate::texture::Texture;
+use std::sync::Arc;
+use vulkano::{
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::DescriptorSet,
+};
+
+/// A texture that scrolls at a fraction of the scroll position, for backgrounds that look further away than the scene
+/// in front of them. It can repeat in either direction to cover the whole viewport.
+pub struct ParallaxLayer {
+	texture: TextureSlot,
+	factor: [f32; 2],
+	offset: [f32; 2],
+	repeat: [bool; 2],
+	scroll: [f32; 2],
+}
+impl ParallaxLayer {
+	/// `factor` is how far the layer moves for each pixel of scrolling. 1 moves with the scene, and 0 doesn't move at
+	/// all. The texture repeats in both directions until `set_repeat` says otherwise.
+	pub fn new(shared: &SpriteBatchShared, texture: &Texture, factor: [f32; 2]) -> Result<Self, SpriteCreationError> {
+		Ok(Self {
+			texture: shared.texture_index(texture.image())?,
+			factor: factor,
+			offset: [0.0, 0.0],
+			repeat: [true, true],
+			scroll: [0.0, 0.0],
+		})
+	}
+
+	pub fn set_factor(&mut self, factor: [f32; 2]) {
+		self.factor = factor;
+	}
+
+	/// Where the texture's top left corner is when the scroll position is zero, in pixels.
+	pub fn set_offset(&mut self, offset: [f32; 2]) {
+		self.offset = offset;
+	}
+
+	/// Whether the texture repeats horizontally and vertically. A layer that doesn't repeat is drawn once.
+	pub fn set_repeat(&mut self, repeat_x: bool, repeat_y: bool) {
+		self.repeat = [repeat_x, repeat_y];
+	}
+
+	/// The point of the scene, in pixels, that's drawn at the top left of the viewport, as with `TileMap::set_scroll`.
+	pub fn set_scroll(&mut self, scroll: [f32; 2]) {
+		self.scroll = scroll;
+	}
+}
+impl Drawable2D for ParallaxLayer {
+	fn add_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		shared.shaders().device_ctx().render_stats().record_draw(2);
+		let draw =
+			parallax_vs::ty::ParallaxDraw {
+				// whole pixels, so the texture doesn't shimmer as it scrolls
+				origin: [
+					(self.offset[0] - self.scroll[0] * self.factor[0]).round(),
+					(self.offset[1] - self.scroll[1] * self.factor[1]).round(),
+				],
+				texture_index: self.texture.index(),
+				repeat: self.repeat[0] as u32 | (self.repeat[1] as u32) << 1,
+			};
+
+		Ok(
+			cmds
+				.draw(
+					shared.pipeline_parallax().clone(),
+					state,
+					vec![shared.shaders().vertices().clone()],
+					(target_desc.clone(), shared.texture_desc()?),
+					draw,
+				)
+				.unwrap()
+		)
+	}
+}
+
+/// Parallax layers that scroll together, drawn back to front in the order they were added.
+#[derive(Default)]
+pub struct ParallaxBackground {
+	layers: Vec<ParallaxLayer>,
+	scroll: [f32; 2],
+}
+impl ParallaxBackground {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds a layer in front of the existing ones, and returns its index.
+	pub fn add_layer(&mut self, mut layer: ParallaxLayer) -> usize {
+		layer.set_scroll(self.scroll);
+		self.layers.push(layer);
+		self.layers.len() - 1
+	}
+
+	pub fn layer_mut(&mut self, layer: usize) -> &mut ParallaxLayer {
+		&mut self.layers[layer]
+	}
+
+	/// Scrolls every layer.
+	pub fn set_scroll(&mut self, scroll: [f32; 2]) {
+		self.scroll = scroll;
+		for layer in &mut self.layers {
+			layer.set_scroll(scroll);
+		}
+	}
+}
+impl Drawable2D for ParallaxBackground {
+	fn add_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		mut cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		for layer in &mut self.layers {
+			cmds = layer.add_commands(shared, target_desc, cmds, state)?;
+		}
+		Ok(cmds)
+	}
+}
@@ -1,10 +1,13 @@
+use crate::device::DeviceCtx;
 use crate::window::Window;
 use std::sync::Arc;
 use vulkano::{
 	impl_vertex,
 	OomError,
 	buffer::{ BufferUsage, ImmutableBuffer },
-	device::{ Device, Queue },
+	device::Device,
+	format::Format,
+	image::{ Dimensions, ImageCreationError, ImageViewAccess, ImmutableImage },
 	memory::DeviceMemoryAllocError,
 	sampler::{ BorderColor, Filter, MipmapMode, Sampler, SamplerAddressMode, SamplerCreationError },
 	sync::GpuFuture,
@@ -12,14 +15,33 @@ use vulkano::{
 
 pub struct SpriteBatchShaders {
 	device: Arc<Device>,
-	queue: Arc<Queue>,
+	device_ctx: Arc<DeviceCtx>,
 	vertices: Arc<ImmutableBuffer<[SpriteVertex; 6]>>,
+	/// Stands in for custom material textures that haven't been set.
+	white_pixel: Arc<ImageViewAccess + Send + Sync + 'static>,
 	sprite_vertex_shader: sprite_vs::Shader,
 	sprite_fragment_shader: sprite_fs::Shader,
 	sprite_sampler: Arc<Sampler>,
 	text_vertex_shader: text_vs::Shader,
 	text_fragment_shader: text_fs::Shader,
+	glyph_vertex_shader: glyph_vs::Shader,
 	text_sampler: Arc<Sampler>,
+	tile_vertex_shader: tile_vs::Shader,
+	tile_fragment_shader: tile_fs::Shader,
+	parallax_vertex_shader: parallax_vs::Shader,
+	parallax_fragment_shader: parallax_fs::Shader,
+	normal_vertex_shader: normal_vs::Shader,
+	normal_fragment_shader: normal_fs::Shader,
+	light_vertex_shader: light_vs::Shader,
+	light_fragment_shader: light_fs::Shader,
+	light_composite_vertex_shader: light_composite_vs::Shader,
+	light_composite_fragment_shader: light_composite_fs::Shader,
+	shape_vertex_shader: shape_vs::Shader,
+	shape_fragment_shader: shape_fs::Shader,
+	panel_vertex_shader: panel_vs::Shader,
+	panel_fragment_shader: panel_fs::Shader,
+	ui_vertex_shader: ui_vs::Shader,
+	ui_fragment_shader: ui_fs::Shader,
 }
 impl SpriteBatchShaders {
 	pub fn new(window: &mut Window) -> Result<(Arc<Self>, impl GpuFuture), SpriteBatchShadersError> {
@@ -37,11 +59,20 @@ impl SpriteBatchShaders {
 				window.device().queue().clone(),
 			)?;
 
+		let (white_pixel, white_pixel_future) =
+			ImmutableImage::from_iter(
+				vec![(255u8, 255u8, 255u8, 255u8)].into_iter(),
+				Dimensions::Dim2d { width: 1, height: 1 },
+				Format::R8G8B8A8Unorm,
+				window.device().queue().clone(),
+			)?;
+
 		Ok((
 			Arc::new(Self {
 				device: window.device().device().clone(),
-				queue: window.device().queue().clone(),
+				device_ctx: window.device().clone(),
 				vertices: vertices,
+				white_pixel: white_pixel,
 				sprite_vertex_shader: sprite_vs::Shader::load(window.device().device().clone())?,
 				sprite_fragment_shader: sprite_fs::Shader::load(window.device().device().clone())?,
 				sprite_sampler:
@@ -56,6 +87,7 @@ impl SpriteBatchShaders {
 					)?,
 				text_vertex_shader: text_vs::Shader::load(window.device().device().clone())?,
 				text_fragment_shader: text_fs::Shader::load(window.device().device().clone())?,
+				glyph_vertex_shader: glyph_vs::Shader::load(window.device().device().clone())?,
 				text_sampler:
 					Sampler::new(
 						window.device().device().clone(),
@@ -66,8 +98,24 @@ impl SpriteBatchShaders {
 						SamplerAddressMode::ClampToBorder(BorderColor::FloatTransparentBlack),
 						0.0, 1.0, 0.0, 0.0
 					)?,
+				tile_vertex_shader: tile_vs::Shader::load(window.device().device().clone())?,
+				tile_fragment_shader: tile_fs::Shader::load(window.device().device().clone())?,
+				parallax_vertex_shader: parallax_vs::Shader::load(window.device().device().clone())?,
+				parallax_fragment_shader: parallax_fs::Shader::load(window.device().device().clone())?,
+				normal_vertex_shader: normal_vs::Shader::load(window.device().device().clone())?,
+				normal_fragment_shader: normal_fs::Shader::load(window.device().device().clone())?,
+				light_vertex_shader: light_vs::Shader::load(window.device().device().clone())?,
+				light_fragment_shader: light_fs::Shader::load(window.device().device().clone())?,
+				light_composite_vertex_shader: light_composite_vs::Shader::load(window.device().device().clone())?,
+				light_composite_fragment_shader: light_composite_fs::Shader::load(window.device().device().clone())?,
+				shape_vertex_shader: shape_vs::Shader::load(window.device().device().clone())?,
+				shape_fragment_shader: shape_fs::Shader::load(window.device().device().clone())?,
+				panel_vertex_shader: panel_vs::Shader::load(window.device().device().clone())?,
+				panel_fragment_shader: panel_fs::Shader::load(window.device().device().clone())?,
+				ui_vertex_shader: ui_vs::Shader::load(window.device().device().clone())?,
+				ui_fragment_shader: ui_fs::Shader::load(window.device().device().clone())?,
 			}),
-			future
+			future.join(white_pixel_future)
 		))
 	}
 
@@ -75,14 +123,18 @@ impl SpriteBatchShaders {
 		&self.device
 	}
 
-	pub(crate) fn queue(&self) -> &Arc<Queue> {
-		&self.queue
+	pub(crate) fn device_ctx(&self) -> &Arc<DeviceCtx> {
+		&self.device_ctx
 	}
 
 	pub(crate) fn vertices(&self) -> &Arc<ImmutableBuffer<[SpriteVertex; 6]>> {
 		&self.vertices
 	}
 
+	pub(crate) fn white_pixel(&self) -> &Arc<ImageViewAccess + Send + Sync + 'static> {
+		&self.white_pixel
+	}
+
 	pub(crate) fn sprite_vertex_shader(&self) -> &sprite_vs::Shader {
 		&self.sprite_vertex_shader
 	}
@@ -99,6 +151,74 @@ impl SpriteBatchShaders {
 		&self.text_fragment_shader
 	}
 
+	pub(crate) fn glyph_vertex_shader(&self) -> &glyph_vs::Shader {
+		&self.glyph_vertex_shader
+	}
+
+	pub(crate) fn tile_vertex_shader(&self) -> &tile_vs::Shader {
+		&self.tile_vertex_shader
+	}
+
+	pub(crate) fn tile_fragment_shader(&self) -> &tile_fs::Shader {
+		&self.tile_fragment_shader
+	}
+
+	pub(crate) fn parallax_vertex_shader(&self) -> &parallax_vs::Shader {
+		&self.parallax_vertex_shader
+	}
+
+	pub(crate) fn parallax_fragment_shader(&self) -> &parallax_fs::Shader {
+		&self.parallax_fragment_shader
+	}
+
+	pub(crate) fn normal_vertex_shader(&self) -> &normal_vs::Shader {
+		&self.normal_vertex_shader
+	}
+
+	pub(crate) fn normal_fragment_shader(&self) -> &normal_fs::Shader {
+		&self.normal_fragment_shader
+	}
+
+	pub(crate) fn light_vertex_shader(&self) -> &light_vs::Shader {
+		&self.light_vertex_shader
+	}
+
+	pub(crate) fn light_fragment_shader(&self) -> &light_fs::Shader {
+		&self.light_fragment_shader
+	}
+
+	pub(crate) fn light_composite_vertex_shader(&self) -> &light_composite_vs::Shader {
+		&self.light_composite_vertex_shader
+	}
+
+	pub(crate) fn light_composite_fragment_shader(&self) -> &light_composite_fs::Shader {
+		&self.light_composite_fragment_shader
+	}
+
+	pub(crate) fn shape_vertex_shader(&self) -> &shape_vs::Shader {
+		&self.shape_vertex_shader
+	}
+
+	pub(crate) fn shape_fragment_shader(&self) -> &shape_fs::Shader {
+		&self.shape_fragment_shader
+	}
+
+	pub(crate) fn panel_vertex_shader(&self) -> &panel_vs::Shader {
+		&self.panel_vertex_shader
+	}
+
+	pub(crate) fn panel_fragment_shader(&self) -> &panel_fs::Shader {
+		&self.panel_fragment_shader
+	}
+
+	pub(crate) fn ui_vertex_shader(&self) -> &ui_vs::Shader {
+		&self.ui_vertex_shader
+	}
+
+	pub(crate) fn ui_fragment_shader(&self) -> &ui_fs::Shader {
+		&self.ui_fragment_shader
+	}
+
 	pub(crate) fn sprite_sampler(&self) -> &Arc<Sampler> {
 		&self.sprite_sampler
 	}
@@ -111,6 +231,7 @@ impl SpriteBatchShaders {
 #[derive(Debug)]
 pub enum SpriteBatchShadersError {
 	DeviceMemoryAllocError(DeviceMemoryAllocError),
+	ImageCreationError(ImageCreationError),
 	OomError(OomError),
 	TooManyObjects,
 }
@@ -119,6 +240,11 @@ impl From<DeviceMemoryAllocError> for SpriteBatchShadersError {
 		SpriteBatchShadersError::DeviceMemoryAllocError(val)
 	}
 }
+impl From<ImageCreationError> for SpriteBatchShadersError {
+	fn from(val: ImageCreationError) -> Self {
+		SpriteBatchShadersError::ImageCreationError(val)
+	}
+}
 impl From<OomError> for SpriteBatchShadersError {
 	fn from(val: OomError) -> Self {
 		SpriteBatchShadersError::OomError(val)
@@ -138,7 +264,42 @@ impl From<SamplerCreationError> for SpriteBatchShadersError {
 pub(crate) struct SpriteVertex { position: [f32; 2] }
 impl_vertex!(SpriteVertex, position);
 
-mod sprite_vs {
+/// A corner of a glyph in text that's drawn with one draw per atlas page. `position` is in pixels.
+#[derive(Debug, Clone)]
+pub(crate) struct GlyphVertex {
+	pub(crate) position: [f32; 2],
+	pub(crate) tex_coords: [f32; 2],
+}
+impl_vertex!(GlyphVertex, position, tex_coords);
+
+/// A corner of a tile in a tile map chunk. `animation` is the frame count and the seconds per frame.
+#[derive(Debug, Clone)]
+pub(crate) struct TileVertex {
+	pub(crate) position: [f32; 2],
+	pub(crate) corner: [f32; 2],
+	pub(crate) tile: u32,
+	pub(crate) animation: [f32; 2],
+}
+impl_vertex!(TileVertex, position, corner, tile, animation);
+
+/// A vertex of a tessellated shape. Colors are sRGB encoded, with alpha fading out across anti-aliased edges.
+#[derive(Debug, Clone)]
+pub(crate) struct ShapeVertex {
+	pub(crate) position: [f32; 2],
+	pub(crate) color: [f32; 4],
+}
+impl_vertex!(ShapeVertex, position, color);
+
+/// A vertex of a mesh from an immediate mode UI library. `color` is sRGB encoded and not premultiplied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UiVertex {
+	pub position: [f32; 2],
+	pub tex_coords: [f32; 2],
+	pub color: [f32; 4],
+}
+impl_vertex!(UiVertex, position, tex_coords, color);
+
+pub(super) mod sprite_vs {
 	::vulkano_shaders::shader!{
 		ty: "vertex",
 		src: "#version 450
@@ -147,53 +308,97 @@ layout(location = 0) out vec2 tex_coords;
 
 layout(set = 0, binding = 0) uniform Target {
 	uvec2 size;
+	float opacity;
 } target;
 
-layout(set = 1, binding = 0) uniform SpriteDynamic {
-	vec2 pos;
-} sprite_dynamic;
+layout(set = 1, binding = 0) uniform sampler2D textures[64];
 
-layout(set = 2, binding = 0) uniform sampler2D tex;
+layout(push_constant) uniform SpriteDraw {
+	vec2 pos;
+	uint texture_index;
+} draw;
 
 void main() {
 	tex_coords = position;
-	gl_Position = vec4(2 * (sprite_dynamic.pos + textureSize(tex, 0) * position) / target.size - 1, 0.0, 1.0);
+	vec2 size = textureSize(textures[draw.texture_index], 0);
+	gl_Position = vec4(2 * (draw.pos + size * position) / target.size - 1, 0.0, 1.0);
 }
 "
 	}
 }
 
-mod sprite_fs {
+pub(super) mod sprite_fs {
 	::vulkano_shaders::shader!{
 		ty: "fragment",
 		src: "#version 450
 layout(location = 0) in vec2 tex_coords;
 layout(location = 0) out vec4 f_color;
 
-layout(set = 2, binding = 0) uniform sampler2D tex;
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+
+layout(set = 1, binding = 0) uniform sampler2D textures[64];
+
+layout(push_constant) uniform SpriteDraw {
+	vec2 pos;
+	uint texture_index;
+} draw;
+
+// set when the target format is linear, so the hardware won't encode to srgb for us
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+// a BlendMode. additive and screen blend premultiplied colors, and multiply fades toward white instead of clear
+layout(constant_id = 1) const uint BLEND_MODE = 0;
 
 void main() {
-	f_color = texture(tex, tex_coords);
+	vec4 color = texture(textures[draw.texture_index], tex_coords);
+	f_color = ENCODE_SRGB ? vec4(pow(color.rgb, vec3(1.0 / 2.2)), color.a) : color;
+	f_color.a *= target.opacity;
+	if (BLEND_MODE == 1 || BLEND_MODE == 3) {
+		f_color.rgb *= f_color.a;
+	} else if (BLEND_MODE == 2) {
+		f_color.rgb = mix(vec3(1), f_color.rgb, f_color.a);
+	}
 }
 "
 	}
 }
 
-mod text_vs {
+pub(super) mod text_vs {
 	::vulkano_shaders::shader!{
 		ty: "vertex",
 		src: "#version 450
 layout(location = 0) in vec2 position;
 layout(location = 0) out vec2 tex_coords;
 
-layout(set = 0, binding = 0) uniform Target { uvec2 size; } target;
-layout(set = 1, binding = 0) uniform SpriteDynamic { vec2 pos; } sprite_dynamic;
-layout(set = 2, binding = 0) uniform GlyphStatic { ivec2 pos; } glyph_static;
-layout(set = 2, binding = 1) uniform sampler2D tex;
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+layout(push_constant) uniform GlyphDraw {
+	vec2 pos;
+	vec2 size;
+	// the glyph's rectangle in its atlas page, in texture coordinates
+	vec2 uv_origin;
+	vec2 uv_size;
+} glyph;
 
 void main() {
-	tex_coords = position;
-	gl_Position = vec4(2 * (sprite_dynamic.pos + glyph_static.pos + textureSize(tex, 0) * position) / target.size - 1, 0.0, 1.0);
+	tex_coords = glyph.uv_origin + glyph.uv_size * position;
+	gl_Position = vec4(2 * (glyph.pos + glyph.size * position) / target.size - 1, 0.0, 1.0);
+}
+"
+	}
+}
+
+pub(super) mod glyph_vs {
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		src: "#version 450
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 tex_coords;
+layout(location = 0) out vec2 out_tex_coords;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+
+void main() {
+	out_tex_coords = tex_coords;
+	gl_Position = vec4(2 * position / target.size - 1, 0.0, 1.0);
 }
 "
 	}
@@ -206,10 +411,547 @@ mod text_fs {
 layout(location = 0) in vec2 tex_coords;
 layout(location = 0) out vec4 f_color;
 
-layout(set = 2, binding = 1) uniform sampler2D tex;
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+
+layout(set = 1, binding = 0) uniform sampler2D tex;
 
 void main() {
 	f_color = vec4(1, 1, 1, texture(tex, tex_coords).r);
+	f_color.a *= target.opacity;
+}
+"
+	}
+}
+
+pub(crate) mod tile_vs {
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		src: "#version 450
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 corner;
+layout(location = 2) in uint tile;
+layout(location = 3) in vec2 animation;
+layout(location = 0) out vec2 tex_coords;
+layout(location = 1) flat out vec4 tile_rect;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+layout(set = 1, binding = 0) uniform sampler2D textures[64];
+
+layout(push_constant) uniform TileDraw {
+	vec2 offset;
+	vec2 tile_size;
+	uint texture_index;
+	uint columns;
+	float margin;
+	float spacing;
+	float time;
+} draw;
+
+void main() {
+	// animated tiles step through consecutive tiles in the tileset
+	uint frame = animation.x > 1 ? uint(draw.time / animation.y) % uint(animation.x) : 0;
+	uint index = tile + frame;
+	vec2 cell = vec2(index % draw.columns, index / draw.columns);
+	vec2 origin = draw.margin + cell * (draw.tile_size + draw.spacing);
+	vec2 texture_size = textureSize(textures[draw.texture_index], 0);
+
+	// half a texel in from the edges, so filtering doesn't bleed in the neighbouring tiles
+	tile_rect = vec4(origin + 0.5, origin + draw.tile_size - 0.5) / texture_size.xyxy;
+	tex_coords = (origin + corner * draw.tile_size) / texture_size;
+	gl_Position = vec4(2 * (draw.offset + position) / target.size - 1, 0.0, 1.0);
+}
+"
+	}
+}
+
+pub(super) mod tile_fs {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 1) flat in vec4 tile_rect;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+
+layout(set = 1, binding = 0) uniform sampler2D textures[64];
+
+layout(push_constant) uniform TileDraw {
+	vec2 offset;
+	vec2 tile_size;
+	uint texture_index;
+	uint columns;
+	float margin;
+	float spacing;
+	float time;
+} draw;
+
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+
+void main() {
+	vec4 color = texture(textures[draw.texture_index], clamp(tex_coords, tile_rect.xy, tile_rect.zw));
+	f_color = ENCODE_SRGB ? vec4(pow(color.rgb, vec3(1.0 / 2.2)), color.a) : color;
+	f_color.a *= target.opacity;
+}
+"
+	}
+}
+
+pub(super) mod parallax_vs {
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		src: "#version 450
+layout(location = 0) in vec2 position;
+layout(location = 0) out vec2 tex_coords;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+layout(set = 1, binding = 0) uniform sampler2D textures[64];
+
+layout(push_constant) uniform ParallaxDraw {
+	vec2 origin;
+	uint texture_index;
+	uint repeat;
+} draw;
+
+void main() {
+	// the quad covers the whole viewport, and the texture is positioned by its texture coordinates
+	tex_coords = (position * target.size - draw.origin) / textureSize(textures[draw.texture_index], 0);
+	gl_Position = vec4(2 * position - 1, 0.0, 1.0);
+}
+"
+	}
+}
+
+pub(super) mod parallax_fs {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+
+layout(set = 1, binding = 0) uniform sampler2D textures[64];
+
+layout(push_constant) uniform ParallaxDraw {
+	vec2 origin;
+	uint texture_index;
+	uint repeat;
+} draw;
+
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+
+void main() {
+	bvec2 repeat = bvec2((draw.repeat & 1) != 0, (draw.repeat & 2) != 0);
+	bvec2 outside = bvec2(tex_coords.x < 0 || tex_coords.x > 1, tex_coords.y < 0 || tex_coords.y > 1);
+	if ((outside.x && !repeat.x) || (outside.y && !repeat.y)) {
+		discard;
+	}
+
+	vec2 coords = mix(tex_coords, fract(tex_coords), repeat);
+	vec4 color = textureGrad(textures[draw.texture_index], coords, dFdx(tex_coords), dFdy(tex_coords));
+	f_color = ENCODE_SRGB ? vec4(pow(color.rgb, vec3(1.0 / 2.2)), color.a) : color;
+	f_color.a *= target.opacity;
+}
+"
+	}
+}
+
+pub(super) mod normal_vs {
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		src: "#version 450
+layout(location = 0) in vec2 position;
+layout(location = 0) out vec2 tex_coords;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+layout(set = 1, binding = 0) uniform sampler2D textures[64];
+
+layout(push_constant) uniform NormalDraw {
+	vec2 pos;
+	uint texture_index;
+	uint normal_index;
+} draw;
+
+void main() {
+	tex_coords = position;
+	vec2 size = textureSize(textures[draw.texture_index], 0);
+	gl_Position = vec4(2 * (draw.pos + size * position) / target.size - 1, 0.0, 1.0);
+}
+"
+	}
+}
+
+mod normal_fs {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 0) out vec4 f_normal;
+
+layout(set = 1, binding = 0) uniform sampler2D textures[64];
+
+layout(push_constant) uniform NormalDraw {
+	vec2 pos;
+	uint texture_index;
+	uint normal_index;
+} draw;
+
+void main() {
+	// the sprite's own alpha decides where its normals are written, so normal maps don't need transparency
+	if (texture(textures[draw.texture_index], tex_coords).a < 0.5) {
+		discard;
+	}
+	f_normal = texture(textures[draw.normal_index], tex_coords);
+}
+"
+	}
+}
+
+pub(super) mod light_vs {
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		src: "#version 450
+layout(location = 0) in vec2 position;
+layout(location = 0) out vec2 pixel;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+
+layout(push_constant) uniform LightDraw {
+	vec2 position;
+	vec2 cone_direction;
+	vec3 color;
+	float range;
+	float height;
+	float source_radius;
+	float cone_cos;
+	float cone_softness;
+	uint segment_count;
+} light;
+
+void main() {
+	// a quad covering everything in range
+	pixel = light.position + (2 * position - 1) * light.range;
+	gl_Position = vec4(2 * pixel / target.size - 1, 0.0, 1.0);
+}
+"
+	}
+}
+
+mod light_fs {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+layout(location = 0) in vec2 pixel;
+layout(location = 0) out vec4 f_light;
+
+layout(set = 1, binding = 0) uniform sampler2D normals;
+layout(set = 2, binding = 0) uniform Occluders { vec4 segments[256]; } occluders;
+
+layout(push_constant) uniform LightDraw {
+	vec2 position;
+	vec2 cone_direction;
+	vec3 color;
+	float range;
+	float height;
+	float source_radius;
+	float cone_cos;
+	float cone_softness;
+	uint segment_count;
+} light;
+
+const int SOFT_SAMPLES = 5;
+
+bool intersects(vec2 p0, vec2 p1, vec2 q0, vec2 q1) {
+	vec2 r = p1 - p0;
+	vec2 s = q1 - q0;
+	float denom = r.x * s.y - r.y * s.x;
+	if (abs(denom) < 1e-6) {
+		return false;
+	}
+
+	vec2 d = q0 - p0;
+	float t = (d.x * s.y - d.y * s.x) / denom;
+	float u = (d.x * r.y - d.y * r.x) / denom;
+	return t > 0 && t < 1 && u >= 0 && u <= 1;
+}
+
+float lit(vec2 target) {
+	for (uint i = 0; i < light.segment_count; i++) {
+		vec4 segment = occluders.segments[i];
+		if (intersects(pixel, target, segment.xy, segment.zw)) {
+			return 0;
+		}
+	}
+	return 1;
+}
+
+float visibility() {
+	if (light.segment_count == 0) {
+		return 1;
+	} else if (light.source_radius <= 0) {
+		return lit(light.position);
+	}
+
+	// soft shadows sample points across the light source, perpendicular to the ray
+	vec2 dir = normalize(light.position - pixel);
+	vec2 side = vec2(-dir.y, dir.x) * light.source_radius;
+	float sum = 0;
+	for (int i = 0; i < SOFT_SAMPLES; i++) {
+		sum += lit(light.position + side * (2 * float(i) / (SOFT_SAMPLES - 1) - 1));
+	}
+	return sum / SOFT_SAMPLES;
+}
+
+void main() {
+	vec2 to_light = light.position - pixel;
+	float falloff = clamp(1 - length(to_light) / light.range, 0, 1);
+	falloff *= falloff;
+
+	// normal maps point y up, but pixels go down
+	vec3 normal = normalize(texelFetch(normals, ivec2(gl_FragCoord.xy), 0).xyz * 2 - 1);
+	vec3 dir = normalize(vec3(to_light.x, -to_light.y, light.height));
+	float diffuse = max(dot(normal, dir), 0);
+
+	float angle_cos = dot(normalize(-to_light), light.cone_direction);
+	float cone = smoothstep(light.cone_cos, light.cone_cos + light.cone_softness, angle_cos);
+
+	f_light = vec4(light.color * falloff * diffuse * cone * visibility(), 0);
+}
+"
+	}
+}
+
+pub(super) mod light_composite_vs {
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		src: "#version 450
+layout(location = 0) in vec2 position;
+
+void main() {
+	gl_Position = vec4(2 * position - 1, 0.0, 1.0);
+}
+"
+	}
+}
+
+mod light_composite_fs {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D light_map;
+
+void main() {
+	f_color = vec4(texelFetch(light_map, ivec2(gl_FragCoord.xy), 0).rgb, 1);
+}
+"
+	}
+}
+
+pub(super) mod shape_vs {
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		src: "#version 450
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec4 color;
+layout(location = 0) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+
+void main() {
+	out_color = color;
+	gl_Position = vec4(2 * position / target.size - 1, 0.0, 1.0);
+}
+"
+	}
+}
+
+pub(super) mod shape_fs {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+layout(location = 0) in vec4 color;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+layout(constant_id = 1) const uint BLEND_MODE = 0;
+
+void main() {
+	// colors are given in srgb, so they only need decoding when the hardware will encode them again
+	f_color = ENCODE_SRGB ? color : vec4(pow(color.rgb, vec3(2.2)), color.a);
+	f_color.a *= target.opacity;
+	if (BLEND_MODE == 1 || BLEND_MODE == 3) {
+		f_color.rgb *= f_color.a;
+	} else if (BLEND_MODE == 2) {
+		f_color.rgb = mix(vec3(1), f_color.rgb, f_color.a);
+	}
+}
+"
+	}
+}
+
+pub(super) mod panel_vs {
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		src: "#version 450
+layout(location = 0) in vec2 position;
+layout(location = 0) out vec2 pixel;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+
+layout(push_constant) uniform PanelDraw {
+	vec4 rect;
+	vec4 radii;
+	vec4 color0;
+	vec4 color1;
+	vec4 gradient;
+	vec4 shadow_color;
+	vec2 shadow_offset;
+	float shadow_blur;
+	uint fill_kind;
+} panel;
+
+void main() {
+	// a quad covering the panel and its shadow, with a pixel to spare for anti-aliasing
+	vec2 shadow_pos = panel.rect.xy + panel.shadow_offset;
+	vec2 lo = min(panel.rect.xy, shadow_pos - panel.shadow_blur) - 1;
+	vec2 hi = max(panel.rect.xy + panel.rect.zw, shadow_pos + panel.rect.zw + panel.shadow_blur) + 1;
+	pixel = mix(lo, hi, position);
+	gl_Position = vec4(2 * pixel / target.size - 1, 0.0, 1.0);
+}
+"
+	}
+}
+
+pub(super) mod panel_fs {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+layout(location = 0) in vec2 pixel;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+
+layout(push_constant) uniform PanelDraw {
+	vec4 rect;
+	vec4 radii;
+	vec4 color0;
+	vec4 color1;
+	vec4 gradient;
+	vec4 shadow_color;
+	vec2 shadow_offset;
+	float shadow_blur;
+	uint fill_kind;
+} panel;
+
+const uint FILL_SOLID = 0;
+const uint FILL_LINEAR = 1;
+const uint FILL_RADIAL = 2;
+
+// signed distance to the edge of the panel, negative inside. radii go clockwise from the top left.
+float panel_distance(vec2 p) {
+	vec2 half_size = panel.rect.zw / 2;
+	p -= panel.rect.xy + half_size;
+	float radius = p.x < 0 ? (p.y < 0 ? panel.radii.x : panel.radii.w) : (p.y < 0 ? panel.radii.y : panel.radii.z);
+	vec2 q = abs(p) - half_size + radius;
+	return min(max(q.x, q.y), 0) + length(max(q, 0)) - radius;
+}
+
+vec4 fill_color() {
+	vec2 p = pixel - panel.rect.xy;
+	float t = 0;
+	if (panel.fill_kind == FILL_LINEAR) {
+		vec2 dir = panel.gradient.zw - panel.gradient.xy;
+		t = dot(p - panel.gradient.xy, dir) / max(dot(dir, dir), 1e-6);
+	} else if (panel.fill_kind == FILL_RADIAL) {
+		t = length((p - panel.gradient.xy) / max(panel.gradient.zw, 1e-6));
+	}
+	return mix(panel.color0, panel.color1, clamp(t, 0, 1));
+}
+
+void main() {
+	vec4 color = fill_color();
+	color.a *= clamp(0.5 - panel_distance(pixel), 0, 1);
+
+	float blur = max(panel.shadow_blur, 1);
+	float shadow_alpha =
+		panel.shadow_color.a * (1 - smoothstep(-blur / 2, blur / 2, panel_distance(pixel - panel.shadow_offset)));
+
+	// the panel goes over its own shadow
+	float alpha = color.a + shadow_alpha * (1 - color.a);
+	vec3 rgb = color.rgb * color.a + panel.shadow_color.rgb * shadow_alpha * (1 - color.a);
+	rgb = alpha > 0 ? rgb / alpha : vec3(0);
+
+	// colors are given in srgb, so they only need decoding when the hardware will encode them again
+	f_color = ENCODE_SRGB ? vec4(rgb, alpha) : vec4(pow(rgb, vec3(2.2)), alpha);
+	f_color.a *= target.opacity;
+}
+"
+	}
+}
+
+pub(super) mod ui_vs {
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		src: "#version 450
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 tex_coords;
+layout(location = 2) in vec4 color;
+layout(location = 0) out vec2 out_tex_coords;
+layout(location = 1) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+
+layout(push_constant) uniform UiDraw {
+	float scale;
+	uint texture_index;
+} draw;
+
+void main() {
+	out_tex_coords = tex_coords;
+	out_color = color;
+	gl_Position = vec4(2 * position * draw.scale / target.size - 1, 0.0, 1.0);
+}
+"
+	}
+}
+
+pub(super) mod ui_fs {
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		src: "#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 1) in vec4 color;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; float opacity; } target;
+
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+layout(constant_id = 1) const uint BLEND_MODE = 0;
+
+layout(set = 1, binding = 0) uniform sampler2D textures[64];
+
+layout(push_constant) uniform UiDraw {
+	float scale;
+	uint texture_index;
+} draw;
+
+void main() {
+	// vertex colors are srgb, but textures are decoded when they're sampled
+	vec4 linear = texture(textures[draw.texture_index], tex_coords) * vec4(pow(color.rgb, vec3(2.2)), color.a);
+	f_color = ENCODE_SRGB ? vec4(pow(linear.rgb, vec3(1.0 / 2.2)), linear.a) : linear;
+	f_color.a *= target.opacity;
+	if (BLEND_MODE == 1 || BLEND_MODE == 3) {
+		f_color.rgb *= f_color.a;
+	} else if (BLEND_MODE == 2) {
+		f_color.rgb = mix(vec3(1), f_color.rgb, f_color.a);
+	}
 }
 "
 	}
@@ -0,0 +1,287 @@
+use super::Drawable2D;
+use super::shaders::ShapeVertex;
+use super::shared::{ BlendMode, SpriteBatchShared };
+use crate::batch::BatchError;
+use std::{ f32::consts::PI, sync::Arc };
+use vulkano::{
+	buffer::{ BufferAccess, BufferUsage, CpuBufferPool },
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::DescriptorSet,
+};
+
+/// Longest miter, as a multiple of half the line's thickness, before sharp corners are cut short.
+const MITER_LIMIT: f32 = 4.0;
+
+/// Rectangles, circles, polygons and lines, tessellated on the CPU and drawn with anti-aliased edges. Positions are in
+/// pixels, like sprites, and colors are sRGB encoded, as in most image editors.
+///
+/// Shapes stay until `clear` is called, so a debug overlay would usually clear and redraw its shapes every frame.
+pub struct Shapes {
+	vertices: Vec<ShapeVertex>,
+	pool: CpuBufferPool<ShapeVertex>,
+	blend_mode: BlendMode,
+}
+impl Shapes {
+	pub fn new(shared: &SpriteBatchShared) -> Self {
+		let pool = CpuBufferPool::new(shared.shaders().device().clone(), BufferUsage::vertex_buffer());
+		Self { vertices: vec![], pool: pool, blend_mode: BlendMode::Alpha }
+	}
+
+	pub fn clear(&mut self) {
+		self.vertices.clear();
+	}
+
+	/// Changes how all of the shapes are blended with what's under them, like `Multiply` for a darkening overlay.
+	pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+		self.blend_mode = blend_mode;
+	}
+
+	pub fn blend_mode(&self) -> BlendMode {
+		self.blend_mode
+	}
+
+	pub fn fill_rect(&mut self, origin: [f32; 2], size: [f32; 2], color: [f32; 4]) {
+		self.fill_polygon(&rect_points(origin, size), color);
+	}
+
+	pub fn stroke_rect(&mut self, origin: [f32; 2], size: [f32; 2], thickness: f32, color: [f32; 4]) {
+		self.stroke(&rect_points(origin, size), true, thickness, color);
+	}
+
+	pub fn fill_circle(&mut self, center: [f32; 2], radius: f32, color: [f32; 4]) {
+		self.fill_polygon(&circle_points(center, radius), color);
+	}
+
+	pub fn stroke_circle(&mut self, center: [f32; 2], radius: f32, thickness: f32, color: [f32; 4]) {
+		self.stroke(&circle_points(center, radius), true, thickness, color);
+	}
+
+	/// Fills a simple polygon, which may be concave but must not intersect itself.
+	pub fn fill_polygon(&mut self, points: &[[f32; 2]], color: [f32; 4]) {
+		let points = dedup(points, true);
+		if points.len() < 3 {
+			return;
+		}
+
+		// the inner edge is half a pixel inside the outline, and the fringe fades out to half a pixel outside it
+		let normals = miter_normals(&points, true, outward_sign(&points));
+		let inner = offset_points(&points, &normals, -0.5);
+		let outer = offset_points(&points, &normals, 0.5);
+		let transparent = [color[0], color[1], color[2], 0.0];
+
+		for [a, b, c] in triangulate(&points) {
+			self.push_triangle([inner[a], inner[b], inner[c]], [color; 3]);
+		}
+		self.push_strip(&inner, &outer, true, color, transparent);
+	}
+
+	pub fn stroke_polygon(&mut self, points: &[[f32; 2]], thickness: f32, color: [f32; 4]) {
+		self.stroke(points, true, thickness, color);
+	}
+
+	/// Draws connected line segments through the points, with square ends.
+	pub fn polyline(&mut self, points: &[[f32; 2]], thickness: f32, color: [f32; 4]) {
+		self.stroke(points, false, thickness, color);
+	}
+
+	pub fn line(&mut self, from: [f32; 2], to: [f32; 2], thickness: f32, color: [f32; 4]) {
+		self.stroke(&[from, to], false, thickness, color);
+	}
+
+	fn stroke(&mut self, points: &[[f32; 2]], closed: bool, thickness: f32, color: [f32; 4]) {
+		let points = dedup(points, closed);
+		if points.len() < 2 || thickness <= 0.0 {
+			return;
+		}
+
+		// lines thinner than a pixel are drawn a pixel wide, but fainter
+		let color = [color[0], color[1], color[2], color[3] * thickness.min(1.0)];
+		let transparent = [color[0], color[1], color[2], 0.0];
+		let half = thickness.max(1.0) / 2.0;
+
+		let normals = miter_normals(&points, closed, 1.0);
+		let outer_edge = offset_points(&points, &normals, half + 0.5);
+		let outer_core = offset_points(&points, &normals, half - 0.5);
+		let inner_core = offset_points(&points, &normals, 0.5 - half);
+		let inner_edge = offset_points(&points, &normals, -0.5 - half);
+
+		self.push_strip(&outer_core, &outer_edge, closed, color, transparent);
+		self.push_strip(&inner_core, &outer_core, closed, color, color);
+		self.push_strip(&inner_core, &inner_edge, closed, color, transparent);
+	}
+
+	fn push_triangle(&mut self, positions: [[f32; 2]; 3], colors: [[f32; 4]; 3]) {
+		for i in 0..3 {
+			self.vertices.push(ShapeVertex { position: positions[i], color: colors[i] });
+		}
+	}
+
+	/// Joins two rings or lines of points with quads, fading from one color to the other.
+	fn push_strip(
+		&mut self,
+		from: &[[f32; 2]],
+		to: &[[f32; 2]],
+		closed: bool,
+		from_color: [f32; 4],
+		to_color: [f32; 4],
+	) {
+		let count = if closed { from.len() } else { from.len() - 1 };
+		for i in 0..count {
+			let j = (i + 1) % from.len();
+			self.push_triangle([from[i], to[i], from[j]], [from_color, to_color, from_color]);
+			self.push_triangle([from[j], to[i], to[j]], [from_color, to_color, to_color]);
+		}
+	}
+}
+impl Drawable2D for Shapes {
+	fn add_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		if self.vertices.is_empty() {
+			return Ok(cmds);
+		}
+
+		let vertices = self.pool.chunk(self.vertices.iter().cloned())?;
+
+		shared.shaders().device_ctx().render_stats().record_draw(self.vertices.len() / 3);
+		Ok(
+			cmds
+				.draw(
+					shared.pipeline_shape_blend(self.blend_mode).clone(),
+					state,
+					vec![Arc::new(vertices) as Arc<BufferAccess + Send + Sync>],
+					target_desc.clone(),
+					(),
+				)
+				.unwrap()
+		)
+	}
+
+	fn bounds(&self) -> Option<[[f32; 2]; 2]> {
+		let first = self.vertices.first()?.position;
+		Some(self.vertices.iter().fold([first, first], |[min, max], vertex| {
+			let [x, y] = vertex.position;
+			[[min[0].min(x), min[1].min(y)], [max[0].max(x), max[1].max(y)]]
+		}))
+	}
+}
+
+fn rect_points([x, y]: [f32; 2], [width, height]: [f32; 2]) -> [[f32; 2]; 4] {
+	[[x, y], [x + width, y], [x + width, y + height], [x, y + height]]
+}
+
+/// Enough points that the segments are about 4 pixels long.
+fn circle_points([x, y]: [f32; 2], radius: f32) -> Vec<[f32; 2]> {
+	let count = (2.0 * PI * radius / 4.0).max(12.0).min(256.0) as usize;
+	(0..count)
+		.map(|i| {
+			let angle = 2.0 * PI * i as f32 / count as f32;
+			[x + radius * angle.cos(), y + radius * angle.sin()]
+		})
+		.collect()
+}
+
+/// Removes repeated points, which have no direction to offset along.
+fn dedup(points: &[[f32; 2]], closed: bool) -> Vec<[f32; 2]> {
+	let mut ret = points.to_vec();
+	ret.dedup();
+	if closed && ret.len() > 1 && ret.first() == ret.last() {
+		ret.pop();
+	}
+	ret
+}
+
+/// 1 if a polygon's edge normals, as calculated by `miter_normals`, point outward, or -1 if they point inward.
+fn outward_sign(points: &[[f32; 2]]) -> f32 {
+	let area =
+		(0..points.len())
+			.map(|i| {
+				let ([x0, y0], [x1, y1]) = (points[i], points[(i + 1) % points.len()]);
+				x0 * y1 - x1 * y0
+			})
+			.sum::<f32>();
+	if area > 0.0 { 1.0 } else { -1.0 }
+}
+
+/// The direction to move each point to offset the outline by one unit, with sharp corners limited by `MITER_LIMIT`.
+fn miter_normals(points: &[[f32; 2]], closed: bool, sign: f32) -> Vec<[f32; 2]> {
+	let len = points.len();
+	let edge_normal = |from: [f32; 2], to: [f32; 2]| {
+		let [dx, dy] = [to[0] - from[0], to[1] - from[1]];
+		let length = (dx * dx + dy * dy).sqrt();
+		[sign * dy / length, -sign * dx / length]
+	};
+
+	(0..len)
+		.map(|i| {
+			let prev = if i > 0 { Some(i - 1) } else if closed { Some(len - 1) } else { None };
+			let next = if i + 1 < len { Some(i + 1) } else if closed { Some(0) } else { None };
+			let before = prev.map(|prev| edge_normal(points[prev], points[i]));
+			let after = next.map(|next| edge_normal(points[i], points[next]));
+
+			match (before, after) {
+				(Some(before), Some(after)) => {
+					let [x, y] = [before[0] + after[0], before[1] + after[1]];
+					let length = (x * x + y * y).sqrt();
+					if length < 0.0001 {
+						return after;
+					}
+
+					let [x, y] = [x / length, y / length];
+					let scale = (1.0 / (x * after[0] + y * after[1])).min(MITER_LIMIT);
+					[x * scale, y * scale]
+				},
+				(Some(normal), None) | (None, Some(normal)) => normal,
+				(None, None) => [0.0, 0.0],
+			}
+		})
+		.collect()
+}
+
+fn offset_points(points: &[[f32; 2]], normals: &[[f32; 2]], distance: f32) -> Vec<[f32; 2]> {
+	points.iter()
+		.zip(normals)
+		.map(|(point, normal)| [point[0] + normal[0] * distance, point[1] + normal[1] * distance])
+		.collect()
+}
+
+/// Splits a simple polygon into triangles by ear clipping, returning indices into `points`.
+fn triangulate(points: &[[f32; 2]]) -> Vec<[usize; 3]> {
+	// work in counterclockwise order, in y-up terms, so ears are the convex corners
+	let mut remaining = (0..points.len()).collect::<Vec<_>>();
+	if outward_sign(points) < 0.0 {
+		remaining.reverse();
+	}
+
+	let cross =
+		|a: [f32; 2], b: [f32; 2], c: [f32; 2]| (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+	let mut triangles = Vec::with_capacity(points.len() - 2);
+
+	while remaining.len() > 3 {
+		let len = remaining.len();
+		let ear =
+			(0..len).find(|&i| {
+				let [a, b, c] = [remaining[(i + len - 1) % len], remaining[i], remaining[(i + 1) % len]];
+				let [pa, pb, pc] = [points[a], points[b], points[c]];
+				cross(pa, pb, pc) > 0.0 &&
+					remaining.iter()
+						.filter(|&&j| j != a && j != b && j != c)
+						.all(|&j| {
+							let p = points[j];
+							cross(pa, pb, p) < 0.0 || cross(pb, pc, p) < 0.0 || cross(pc, pa, p) < 0.0
+						})
+			});
+
+		// a degenerate polygon may have no ears left, so the rest is clipped anyway rather than looping forever
+		let i = ear.unwrap_or(0);
+		triangles.push([remaining[(i + len - 1) % len], remaining[i], remaining[(i + 1) % len]]);
+		remaining.remove(i);
+	}
+	triangles.push([remaining[0], remaining[1], remaining[2]]);
+
+	triangles
+}
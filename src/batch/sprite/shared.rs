@@ -1,69 +1,133 @@
-use crate::texture::Texture;
-use super::shaders::{ SpriteBatchShaders, SpriteVertex };
+use crate::batch::{ StencilCache, StencilState, stencil_format };
+use crate::debug::DebugNames;
+use crate::texture::{ SamplerSettings, Texture, is_srgb };
+use super::lighting::LightingPipelines;
+use super::material::{ SpriteShader, SpriteShaderPipeline };
+use super::shaders::{
+	GlyphVertex,
+	SpriteBatchShaders,
+	SpriteVertex,
+	ShapeVertex,
+	TileVertex,
+	UiVertex,
+	panel_fs,
+	parallax_fs,
+	shape_fs,
+	sprite_fs,
+	tile_fs,
+	ui_fs,
+};
 use super::sprite::Sprite;
+use super::sprite_buffer::SpriteBuffer;
+use super::textures::{ TextureSlot, TextureTable, TextureTableSet };
 use std::sync::{ Arc, Mutex };
 use vulkano::{
 	single_pass_renderpass,
-	descriptor::descriptor_set::FixedSizeDescriptorSetsPool,
+	OomError,
 	format::Format,
 	framebuffer::{ RenderPassAbstract, Subpass },
-	memory::DeviceMemoryAllocError,
-	pipeline::{ GraphicsPipeline, GraphicsPipelineAbstract },
-	sync::GpuFuture,
+	pipeline::{
+		GraphicsPipeline,
+		GraphicsPipelineAbstract,
+		GraphicsPipelineCreationError,
+		blend::{ AttachmentBlend, BlendFactor, BlendOp },
+		depth_stencil::DepthStencil,
+	},
+	image::ImageViewAccess,
+	sampler::{ Sampler, SamplerCreationError },
 };
 
+/// The pipelines and texture table used by every sprite batch for a target format. It's only changed behind locks, so
+/// it's `Sync` and can be used by batches on different threads.
 pub struct SpriteBatchShared {
 	shaders: Arc<SpriteBatchShaders>,
 	subpass: Subpass<Arc<RenderPassAbstract + Send + Sync>>,
-	pipeline_sprite: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
-	pipeline_text: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
-	sprite_desc_pool: Mutex<FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>>,
+	pipelines: BuiltinPipelines,
+	lighting: Arc<LightingPipelines>,
+	/// Pipelines for custom sprite shaders, created the first time a material uses the shader.
+	pipelines_custom: Mutex<Vec<SpriteShaderPipeline>>,
+	format: Format,
+	/// Shared with stencil variants, so sprites created with any of them can be drawn with the others.
+	textures: Arc<Mutex<TextureTable>>,
+	has_stencil: bool,
+	/// The stencil state this variant's pipelines draw with.
+	stencil: Option<StencilState>,
+	/// Copies of this with the same render pass and textures, but pipelines for a stencil state, created the first
+	/// time a drawable is added with the state.
+	stencil_variants: Mutex<StencilCache<StencilState, Arc<SpriteBatchShared>>>,
 }
 impl SpriteBatchShared {
 	pub fn new(shaders: Arc<SpriteBatchShaders>, format: Format) -> Arc<Self> {
-		let subpass =
-			Subpass::from(
+		let sprite_sampler = shaders.sprite_sampler().clone();
+		Self::with_sampler(shaders, format, sprite_sampler)
+	}
+
+	/// Like `new`, but sprite textures are sampled with the given settings. Text is unaffected.
+	pub fn with_sampler_settings(
+		shaders: Arc<SpriteBatchShaders>,
+		format: Format,
+		settings: &SamplerSettings,
+	) -> Result<Arc<Self>, SamplerCreationError> {
+		let sprite_sampler = settings.build(shaders.device())?;
+		Ok(Self::with_sampler(shaders, format, sprite_sampler))
+	}
+
+	/// Like `new`, but the render pass has a stencil buffer, so drawables can be added with a `StencilState` to mask
+	/// each other, like for clipping a scrolling panel's contents.
+	pub fn with_stencil(shaders: Arc<SpriteBatchShaders>, format: Format) -> Arc<Self> {
+		let sprite_sampler = shaders.sprite_sampler().clone();
+		Self::create(shaders, format, sprite_sampler, true)
+	}
+
+	fn with_sampler(shaders: Arc<SpriteBatchShaders>, format: Format, sprite_sampler: Arc<Sampler>) -> Arc<Self> {
+		Self::create(shaders, format, sprite_sampler, false)
+	}
+
+	fn create(
+		shaders: Arc<SpriteBatchShaders>,
+		format: Format,
+		sprite_sampler: Arc<Sampler>,
+		has_stencil: bool,
+	) -> Arc<Self> {
+		let render_pass: Arc<RenderPassAbstract + Send + Sync> =
+			if has_stencil {
+				let stencil_format = stencil_format(shaders.device());
+				Arc::new(
+					single_pass_renderpass!(
+						shaders.device().clone(),
+						attachments: {
+							color: { load: Clear, store: Store, format: format, samples: 1, },
+							stencil: { load: Clear, store: DontCare, format: stencil_format, samples: 1, }
+						},
+						pass: { color: [color], depth_stencil: {stencil} }
+					).expect("failed to create render pass")
+				)
+			} else {
 				Arc::new(
 					single_pass_renderpass!(
 						shaders.device().clone(),
 						attachments: { color: { load: Clear, store: Store, format: format, samples: 1, } },
 						pass: { color: [color], depth_stencil: {} }
 					).expect("failed to create render pass")
-				) as Arc<RenderPassAbstract + Send + Sync>,
-				0
-			).expect("failed to create subpass");
-
-		let pipeline_sprite = Arc::new(
-			GraphicsPipeline::start()
-				.vertex_input_single_buffer::<SpriteVertex>()
-				.vertex_shader(shaders.sprite_vertex_shader().main_entry_point(), ())
-				.triangle_list()
-				.viewports_dynamic_scissors_irrelevant(1)
-				.fragment_shader(shaders.sprite_fragment_shader().main_entry_point(), ())
-				.render_pass(subpass.clone())
-				.build(shaders.device().clone())
-				.expect("failed to create pipeline")
-		);
+				)
+			};
+		let subpass = Subpass::from(render_pass, 0).expect("failed to create subpass");
 
-		let pipeline_text = Arc::new(
-			GraphicsPipeline::start()
-				.vertex_input_single_buffer::<SpriteVertex>()
-				.vertex_shader(shaders.text_vertex_shader().main_entry_point(), ())
-				.triangle_list()
-				.viewports_dynamic_scissors_irrelevant(1)
-				.fragment_shader(shaders.text_fragment_shader().main_entry_point(), ())
-				.render_pass(subpass.clone())
-				.blend_alpha_blending()
-				.build(shaders.device().clone())
-				.expect("failed to create pipeline")
-		);
+		let pipelines = BuiltinPipelines::new(&shaders, format, &subpass, None);
+		let lighting = Arc::new(LightingPipelines::new(&shaders, subpass.clone()));
+		let textures = TextureTable::new(sprite_sampler, shaders.white_pixel().clone());
 
 		Arc::new(Self {
 			shaders: shaders,
 			subpass: subpass,
-			pipeline_sprite: pipeline_sprite.clone(),
-			pipeline_text: pipeline_text,
-			sprite_desc_pool: Mutex::new(FixedSizeDescriptorSetsPool::new(pipeline_sprite, 1)),
+			pipelines: pipelines,
+			lighting: lighting,
+			pipelines_custom: Mutex::new(vec![]),
+			format: format,
+			textures: Arc::new(Mutex::new(textures)),
+			has_stencil: has_stencil,
+			stencil: None,
+			stencil_variants: Mutex::new(StencilCache::new()),
 		})
 	}
 
@@ -71,14 +135,20 @@ impl SpriteBatchShared {
 		&self,
 		texture: &Texture,
 		position: [f32; 2],
-	) -> Result<(Sprite, impl GpuFuture), DeviceMemoryAllocError> {
-		Sprite::new(
-			self.shaders.queue().clone(),
-			self.pipeline_sprite.clone(),
-			self.shaders.sprite_sampler().clone(),
-			texture,
-			position,
-		)
+	) -> Result<Sprite, SpriteCreationError> {
+		let dimensions = texture.image().dimensions();
+		let size = [dimensions.width() as f32, dimensions.height() as f32];
+		Ok(Sprite::new(self.texture_index(texture.image())?, size, position))
+	}
+
+	/// Creates a buffer for drawing many sprites from the texture with one draw call.
+	pub fn create_sprite_buffer(&self, texture: &Texture) -> Result<SpriteBuffer, SpriteCreationError> {
+		SpriteBuffer::new(self, texture)
+	}
+
+	/// Whether the render pass has a stencil buffer, from `with_stencil`.
+	pub fn has_stencil(&self) -> bool {
+		self.has_stencil
 	}
 
 	pub(crate) fn shaders(&self) -> &Arc<SpriteBatchShaders> {
@@ -90,16 +160,402 @@ impl SpriteBatchShared {
 	}
 
 	pub(crate) fn pipeline_sprite(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
-		&self.pipeline_sprite
+		self.pipelines.sprite.get(BlendMode::Alpha)
+	}
+
+	pub(crate) fn pipeline_sprite_blend(
+		&self,
+		mode: BlendMode,
+	) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		self.pipelines.sprite.get(mode)
 	}
 
 	pub(crate) fn pipeline_text(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
-		&self.pipeline_text
+		&self.pipelines.text
+	}
+
+	pub(crate) fn pipeline_glyphs(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		&self.pipelines.glyphs
+	}
+
+	pub(crate) fn pipeline_tile(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		&self.pipelines.tile
+	}
+
+	/// Returns the pipeline for a custom sprite shader, creating it if this is the first time it's been used.
+	pub(crate) fn pipeline_for(
+		&self,
+		shader: &SpriteShader,
+	) -> Result<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>, GraphicsPipelineCreationError> {
+		let mut pipelines = self.pipelines_custom.lock().unwrap();
+		pipelines.retain(|pipeline| pipeline.shader_id.is_alive());
+		if let Some(pipeline) = pipelines.iter().find(|pipeline| pipeline.shader_id.is_child_of(shader.id_root())) {
+			return Ok(pipeline.pipeline.clone());
+		}
+
+		let pipeline: Arc<GraphicsPipelineAbstract + Send + Sync + 'static> =
+			Arc::new(
+				GraphicsPipeline::start()
+					.vertex_input_single_buffer::<SpriteVertex>()
+					.vertex_shader(self.shaders.sprite_vertex_shader().main_entry_point(), ())
+					.triangle_list()
+					.viewports_scissors_dynamic(1)
+					.fragment_shader(
+						shader.entry_point(),
+						sprite_fs::SpecializationConstants { ENCODE_SRGB: !is_srgb(self.format) as u32, BLEND_MODE: 0 }
+					)
+					.depth_stencil(depth_stencil(self.stencil.as_ref()))
+					.blend_collective(blend(self.stencil.as_ref(), AttachmentBlend::pass_through()))
+					.render_pass(self.subpass.clone())
+					.build(self.shaders.device().clone())?
+			);
+		self.shaders.device_ctx().debug_names().name_pipeline(&*pipeline, "sprite custom");
+
+		pipelines.push(SpriteShaderPipeline { shader_id: shader.id_root().make_id(), pipeline: pipeline.clone() });
+		Ok(pipeline)
+	}
+
+	pub(crate) fn pipeline_parallax(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		&self.pipelines.parallax
+	}
+
+	pub(crate) fn pipeline_shape(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		self.pipelines.shape.get(BlendMode::Alpha)
+	}
+
+	pub(crate) fn pipeline_shape_blend(
+		&self,
+		mode: BlendMode,
+	) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		self.pipelines.shape.get(mode)
+	}
+
+	pub(crate) fn pipeline_panel(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		&self.pipelines.panel
+	}
+
+	pub(crate) fn pipeline_ui(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		self.pipelines.ui.get(BlendMode::Alpha)
+	}
+
+	pub(crate) fn pipeline_ui_blend(&self, mode: BlendMode) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		self.pipelines.ui.get(mode)
+	}
+
+	pub(crate) fn lighting(&self) -> &LightingPipelines {
+		&self.lighting
+	}
+
+	/// Returns a copy of this that draws with the given stencil state, creating it if the state hasn't been used
+	/// recently. Returns `None` if the render pass has no stencil buffer.
+	pub(crate) fn stencil_variant(&self, stencil: &StencilState) -> Option<Arc<Self>> {
+		if !self.has_stencil {
+			return None;
+		}
+
+		let mut variants = self.stencil_variants.lock().unwrap();
+		let variant = variants.get_or_insert_with(*stencil, || {
+			Arc::new(Self {
+				shaders: self.shaders.clone(),
+				subpass: self.subpass.clone(),
+				pipelines: BuiltinPipelines::new(&self.shaders, self.format, &self.subpass, Some(stencil)),
+				lighting: self.lighting.clone(),
+				pipelines_custom: Mutex::new(vec![]),
+				format: self.format,
+				textures: self.textures.clone(),
+				has_stencil: true,
+				stencil: Some(*stencil),
+				stencil_variants: Mutex::new(StencilCache::new()),
+			})
+		});
+		Some(variant.clone())
+	}
+
+	/// Returns the image's slot in the texture table, adding it if it isn't there yet.
+	pub(crate) fn texture_index(
+		&self,
+		image: &Arc<ImageViewAccess + Send + Sync + 'static>,
+	) -> Result<TextureSlot, SpriteCreationError> {
+		self.textures.lock().unwrap().index_of(image).ok_or(SpriteCreationError::TooManyTextures)
+	}
+
+	/// Whether `index` is the index of a texture table slot that's still held.
+	pub(crate) fn has_texture(&self, index: u32) -> bool {
+		self.textures.lock().unwrap().is_live(index)
+	}
+
+	pub(crate) fn texture_desc(&self) -> Result<Arc<TextureTableSet>, OomError> {
+		self.textures.lock().unwrap().desc(self.pipeline_sprite(), 1)
 	}
+}
+
+/// How a drawable's colors are combined with what's already been drawn under it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlendMode {
+	/// Drawn over what's under it, showing through where it's transparent.
+	Alpha = 0,
+	/// Added to what's under it, so it can only brighten. Good for glows, fire, and sparks.
+	Additive = 1,
+	/// Multiplied with what's under it, so it can only darken. Good for shadows and darkening overlays.
+	Multiply = 2,
+	/// The inverse of multiplying the inverses, so it brightens like `Additive` but never past white.
+	Screen = 3,
+}
+impl BlendMode {
+	const ALL: [BlendMode; 4] = [BlendMode::Alpha, BlendMode::Additive, BlendMode::Multiply, BlendMode::Screen];
+
+	/// The fragment shaders premultiply alpha for the modes that need it, so none of these use the source alpha.
+	pub(crate) fn attachment_blend(self) -> AttachmentBlend {
+		let color = |src, dst| AttachmentBlend {
+			color_op: BlendOp::Add,
+			color_source: src,
+			color_destination: dst,
+			alpha_op: BlendOp::Add,
+			alpha_source: BlendFactor::Zero,
+			alpha_destination: BlendFactor::One,
+			.. AttachmentBlend::alpha_blending()
+		};
+
+		match self {
+			BlendMode::Alpha => AttachmentBlend::alpha_blending(),
+			BlendMode::Additive => color(BlendFactor::One, BlendFactor::One),
+			BlendMode::Multiply => color(BlendFactor::DstColor, BlendFactor::Zero),
+			BlendMode::Screen => color(BlendFactor::One, BlendFactor::OneMinusSrcColor),
+		}
+	}
+}
+impl Default for BlendMode {
+	fn default() -> Self {
+		BlendMode::Alpha
+	}
+}
 
-	pub(crate) fn sprite_desc_pool(
-		&self
-	) -> &Mutex<FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>> {
-		&self.sprite_desc_pool
+/// One pipeline for each `BlendMode`, indexed by the mode.
+struct BlendPipelines([Arc<GraphicsPipelineAbstract + Send + Sync + 'static>; 4]);
+impl BlendPipelines {
+	fn new(mut make: impl FnMut(BlendMode) -> Arc<GraphicsPipelineAbstract + Send + Sync + 'static>) -> Self {
+		BlendPipelines([
+			make(BlendMode::Alpha),
+			make(BlendMode::Additive),
+			make(BlendMode::Multiply),
+			make(BlendMode::Screen),
+		])
 	}
+
+	fn get(&self, mode: BlendMode) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		&self.0[mode as usize]
+	}
+
+	fn name(&self, debug_names: &DebugNames, name: &str) {
+		for &mode in &BlendMode::ALL {
+			debug_names.name_pipeline(&**self.get(mode), &format!("{} {:?}", name, mode));
+		}
+	}
+}
+
+/// The pipelines for the built-in drawables, for one stencil state.
+struct BuiltinPipelines {
+	sprite: BlendPipelines,
+	text: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	glyphs: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	tile: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	parallax: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	shape: BlendPipelines,
+	panel: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	ui: BlendPipelines,
+}
+impl BuiltinPipelines {
+	fn new(
+		shaders: &SpriteBatchShaders,
+		format: Format,
+		subpass: &Subpass<Arc<RenderPassAbstract + Send + Sync>>,
+		stencil: Option<&StencilState>,
+	) -> Self {
+		let alpha_blending = blend(stencil, AttachmentBlend::alpha_blending());
+
+		let sprite = BlendPipelines::new(|mode| {
+			Arc::new(
+				GraphicsPipeline::start()
+					.vertex_input_single_buffer::<SpriteVertex>()
+					.vertex_shader(shaders.sprite_vertex_shader().main_entry_point(), ())
+					.triangle_list()
+					.viewports_scissors_dynamic(1)
+					.fragment_shader(
+						shaders.sprite_fragment_shader().main_entry_point(),
+						sprite_fs::SpecializationConstants {
+							ENCODE_SRGB: !is_srgb(format) as u32,
+							BLEND_MODE: mode as u32,
+						}
+					)
+					.depth_stencil(depth_stencil(stencil))
+					.blend_collective(blend(stencil, mode.attachment_blend()))
+					.render_pass(subpass.clone())
+					.build(shaders.device().clone())
+					.expect("failed to create pipeline")
+			)
+		});
+
+		let text = Arc::new(
+			GraphicsPipeline::start()
+				.vertex_input_single_buffer::<SpriteVertex>()
+				.vertex_shader(shaders.text_vertex_shader().main_entry_point(), ())
+				.triangle_list()
+				.viewports_scissors_dynamic(1)
+				.fragment_shader(shaders.text_fragment_shader().main_entry_point(), ())
+				.depth_stencil(depth_stencil(stencil))
+				.blend_collective(alpha_blending.clone())
+				.render_pass(subpass.clone())
+				.build(shaders.device().clone())
+				.expect("failed to create pipeline")
+		);
+
+		let glyphs = Arc::new(
+			GraphicsPipeline::start()
+				.vertex_input_single_buffer::<GlyphVertex>()
+				.vertex_shader(shaders.glyph_vertex_shader().main_entry_point(), ())
+				.triangle_list()
+				.viewports_scissors_dynamic(1)
+				.fragment_shader(shaders.text_fragment_shader().main_entry_point(), ())
+				.depth_stencil(depth_stencil(stencil))
+				.blend_collective(alpha_blending.clone())
+				.render_pass(subpass.clone())
+				.build(shaders.device().clone())
+				.expect("failed to create pipeline")
+		);
+
+		let tile = Arc::new(
+			GraphicsPipeline::start()
+				.vertex_input_single_buffer::<TileVertex>()
+				.vertex_shader(shaders.tile_vertex_shader().main_entry_point(), ())
+				.triangle_list()
+				.viewports_scissors_dynamic(1)
+				.fragment_shader(
+					shaders.tile_fragment_shader().main_entry_point(),
+					tile_fs::SpecializationConstants { ENCODE_SRGB: !is_srgb(format) as u32 }
+				)
+				.depth_stencil(depth_stencil(stencil))
+				.blend_collective(alpha_blending.clone())
+				.render_pass(subpass.clone())
+				.build(shaders.device().clone())
+				.expect("failed to create pipeline")
+		);
+
+		let parallax = Arc::new(
+			GraphicsPipeline::start()
+				.vertex_input_single_buffer::<SpriteVertex>()
+				.vertex_shader(shaders.parallax_vertex_shader().main_entry_point(), ())
+				.triangle_list()
+				.viewports_scissors_dynamic(1)
+				.fragment_shader(
+					shaders.parallax_fragment_shader().main_entry_point(),
+					parallax_fs::SpecializationConstants { ENCODE_SRGB: !is_srgb(format) as u32 }
+				)
+				.depth_stencil(depth_stencil(stencil))
+				.blend_collective(alpha_blending.clone())
+				.render_pass(subpass.clone())
+				.build(shaders.device().clone())
+				.expect("failed to create pipeline")
+		);
+
+		let shape = BlendPipelines::new(|mode| {
+			Arc::new(
+				GraphicsPipeline::start()
+					.vertex_input_single_buffer::<ShapeVertex>()
+					.vertex_shader(shaders.shape_vertex_shader().main_entry_point(), ())
+					.triangle_list()
+					.viewports_scissors_dynamic(1)
+					.fragment_shader(
+						shaders.shape_fragment_shader().main_entry_point(),
+						shape_fs::SpecializationConstants {
+							ENCODE_SRGB: !is_srgb(format) as u32,
+							BLEND_MODE: mode as u32,
+						}
+					)
+					.depth_stencil(depth_stencil(stencil))
+					.blend_collective(blend(stencil, mode.attachment_blend()))
+					.render_pass(subpass.clone())
+					.build(shaders.device().clone())
+					.expect("failed to create pipeline")
+			)
+		});
+
+		let panel = Arc::new(
+			GraphicsPipeline::start()
+				.vertex_input_single_buffer::<SpriteVertex>()
+				.vertex_shader(shaders.panel_vertex_shader().main_entry_point(), ())
+				.triangle_list()
+				.viewports_scissors_dynamic(1)
+				.fragment_shader(
+					shaders.panel_fragment_shader().main_entry_point(),
+					panel_fs::SpecializationConstants { ENCODE_SRGB: !is_srgb(format) as u32 }
+				)
+				.depth_stencil(depth_stencil(stencil))
+				.blend_collective(alpha_blending.clone())
+				.render_pass(subpass.clone())
+				.build(shaders.device().clone())
+				.expect("failed to create pipeline")
+		);
+
+		let ui = BlendPipelines::new(|mode| {
+			Arc::new(
+				GraphicsPipeline::start()
+					.vertex_input_single_buffer::<UiVertex>()
+					.vertex_shader(shaders.ui_vertex_shader().main_entry_point(), ())
+					.triangle_list()
+					.viewports_scissors_dynamic(1)
+					.fragment_shader(
+						shaders.ui_fragment_shader().main_entry_point(),
+						ui_fs::SpecializationConstants { ENCODE_SRGB: !is_srgb(format) as u32, BLEND_MODE: mode as u32 }
+					)
+					.depth_stencil(depth_stencil(stencil))
+					.blend_collective(blend(stencil, mode.attachment_blend()))
+					.render_pass(subpass.clone())
+					.build(shaders.device().clone())
+					.expect("failed to create pipeline")
+			)
+		});
+
+		let debug_names = shaders.device_ctx().debug_names();
+		sprite.name(debug_names, "sprite");
+		debug_names.name_pipeline(&*text, "text");
+		debug_names.name_pipeline(&*glyphs, "glyphs");
+		debug_names.name_pipeline(&*tile, "tile");
+		debug_names.name_pipeline(&*parallax, "parallax");
+		shape.name(debug_names, "shape");
+		debug_names.name_pipeline(&*panel, "panel");
+		ui.name(debug_names, "ui");
+
+		Self {
+			sprite: sprite,
+			text: text,
+			glyphs: glyphs,
+			tile: tile,
+			parallax: parallax,
+			shape: shape,
+			panel: panel,
+			ui: ui,
+		}
+	}
+}
+
+/// Sprites never test or write depth, so this only has the stencil test, if there is one.
+fn depth_stencil(stencil: Option<&StencilState>) -> DepthStencil {
+	match stencil {
+		Some(stencil) => stencil.depth_stencil(DepthStencil::disabled()),
+		None => DepthStencil::disabled(),
+	}
+}
+
+fn blend(stencil: Option<&StencilState>, blend: AttachmentBlend) -> AttachmentBlend {
+	match stencil {
+		Some(stencil) => stencil.blend(blend),
+		None => blend,
+	}
+}
+
+#[derive(Debug)]
+pub enum SpriteCreationError {
+	/// Every slot in the sprite texture table is taken by another texture.
+	TooManyTextures,
 }
@@ -1,84 +1,149 @@
 use super::Drawable2D;
-use super::shared::SpriteBatchShared;
-use crate::texture::Texture;
+use super::material::SpriteMaterial;
+use super::shaders::{ normal_vs, sprite_vs };
+use super::shared::{ BlendMode, SpriteBatchShared, SpriteCreationError };
+use super::textures::TextureSlot;
+use crate::batch::BatchError;
+use crate::texture::{ TargetTexture, Texture };
 use std::sync::Arc;
 use vulkano::{
-	OomError,
-	buffer::{ BufferUsage, ImmutableBuffer },
-	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, DynamicState },
-	descriptor::{ DescriptorSet, descriptor_set::PersistentDescriptorSet },
-	device::Queue,
-	instance::QueueFamily,
-	memory::DeviceMemoryAllocError,
-	pipeline::{ GraphicsPipelineAbstract, viewport::Viewport },
-	sampler::Sampler,
-	sync::GpuFuture,
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::DescriptorSet,
 };
 
 pub struct Sprite {
-	static_desc: Arc<DescriptorSet + Send + Sync + 'static>,
-	position: Arc<ImmutableBuffer<[f32; 2]>>,
+	texture: TextureSlot,
+	/// The texture's size, which the sprite is drawn at.
+	size: [f32; 2],
+	position: [f32; 2],
+	material: Option<SpriteMaterial>,
+	normal_map: Option<TextureSlot>,
+	blend_mode: BlendMode,
 }
 impl Sprite {
-	pub(crate) fn new(
-		queue: Arc<Queue>,
-		pipeline: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
-		sampler: Arc<Sampler>,
-		texture: &Texture,
-		position: [f32; 2]
-	) -> Result<(Self, impl GpuFuture), DeviceMemoryAllocError> {
-		let (position, future) = ImmutableBuffer::from_data(position, BufferUsage::uniform_buffer(), queue)?;
+	pub(crate) fn new(texture: TextureSlot, size: [f32; 2], position: [f32; 2]) -> Self {
+		Self {
+			texture: texture,
+			size: size,
+			position: position,
+			material: None,
+			normal_map: None,
+			blend_mode: BlendMode::Alpha,
+		}
+	}
+
+	/// Creates a sprite showing whatever was last rendered into the target, like a 3D character preview inside a UI
+	/// panel. Vulkano moves the image between its attachment and sampled layouts as each command buffer needs it, so
+	/// the target can be rendered into every frame while the sprite keeps showing it. Include the target in the sprite
+	/// pass's `reads` when adding it to a `Frame`, so the pass rendering into the target is submitted first.
+	///
+	/// Every sprite texture is bound for every draw, so sprites drawn into the target itself need a different
+	/// `SpriteBatchShared`, or the target would be read and written by the same render pass.
+	pub fn from_render_target(
+		shared: &SpriteBatchShared,
+		target: &TargetTexture,
+		position: [f32; 2],
+	) -> Result<Self, SpriteCreationError> {
+		shared.create_sprite(target, position)
+	}
+
+	/// Moves the sprite. The position is pushed with each draw, so this is cheap to call every frame.
+	pub fn set_position(&mut self, position: [f32; 2]) {
+		self.position = position;
+	}
+
+	/// Draws the sprite with a custom shader. `None` goes back to the built-in shader.
+	pub fn set_material(&mut self, material: Option<SpriteMaterial>) {
+		self.material = material;
+	}
+
+	pub fn material_mut(&mut self) -> Option<&mut SpriteMaterial> {
+		self.material.as_mut()
+	}
 
-		Ok((
-			Self {
-				static_desc:
-					Arc::new(
-						PersistentDescriptorSet::start(pipeline, 2)
-							.add_sampled_image(texture.image().clone(), sampler)
-							.unwrap()
-							.build()
-							.unwrap()
-					),
-				position: position
-			},
-			future
-		))
+	/// Changes how the sprite is blended with what's under it. Sprites with a material ignore this.
+	pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+		self.blend_mode = blend_mode;
+	}
+
+	pub fn blend_mode(&self) -> BlendMode {
+		self.blend_mode
+	}
+
+	/// Gives the sprite a normal map for 2D lighting. It should be the same size as the sprite's texture, and use
+	/// `ColorEncoding::Linear`. `None` lights the sprite as if it's flat.
+	pub fn set_normal_map(
+		&mut self,
+		shared: &SpriteBatchShared,
+		normal_map: Option<&Texture>,
+	) -> Result<(), SpriteCreationError> {
+		self.normal_map = normal_map.map(|normal_map| shared.texture_index(normal_map.image())).transpose()?;
+		Ok(())
 	}
 }
 impl Drawable2D for Sprite {
-	fn make_commands(
+	fn add_commands(
 		&mut self,
 		shared: &SpriteBatchShared,
 		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
-		queue_family: QueueFamily,
-		dimensions: [f32; 2],
-	) -> Result<AutoCommandBuffer, OomError> {
+		cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		shared.shaders().device_ctx().render_stats().record_draw(2);
+		let draw = sprite_vs::ty::SpriteDraw { pos: self.position, texture_index: self.texture.index() };
+		let vertices = vec![shared.shaders().vertices().clone()];
+
 		Ok(
-			AutoCommandBufferBuilder::secondary_graphics_one_time_submit(shared.shaders().device().clone(), queue_family, shared.subpass().clone())?
+			match &mut self.material {
+				Some(material) => {
+					let sets = (target_desc.clone(), shared.texture_desc()?, material.params_desc()?);
+					cmds.draw(material.pipeline().clone(), state, vertices, sets, draw).unwrap()
+				},
+				None =>
+					cmds
+						.draw(
+							shared.pipeline_sprite_blend(self.blend_mode).clone(),
+							state,
+							vertices,
+							(target_desc.clone(), shared.texture_desc()?),
+							draw
+						)
+						.unwrap(),
+			}
+		)
+	}
+
+	fn add_normal_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		let normal_index = match &self.normal_map { Some(slot) => slot.index(), None => return Ok(cmds) };
+		let draw =
+			normal_vs::ty::NormalDraw {
+				pos: self.position,
+				texture_index: self.texture.index(),
+				normal_index: normal_index,
+			};
+
+		shared.shaders().device_ctx().render_stats().record_draw(2);
+		Ok(
+			cmds
 				.draw(
-					shared.pipeline_sprite().clone(),
-					&DynamicState {
-						line_width: None,
-						viewports:
-							Some(vec![Viewport { origin: [0.0, 0.0], dimensions: dimensions, depth_range: 0.0..1.0 }]),
-						scissors: None,
-					},
+					shared.lighting().pipeline_normal.clone(),
+					state,
 					vec![shared.shaders().vertices().clone()],
-					(
-						target_desc.clone(),
-						shared.sprite_desc_pool().lock().unwrap()
-							.next()
-							.add_buffer(self.position.clone())
-							.unwrap()
-							.build()
-							.unwrap(),
-						self.static_desc.clone(),
-					),
-					()
+					(target_desc.clone(), shared.texture_desc()?),
+					draw,
 				)
 				.unwrap()
-				.build()
-				.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?
 		)
 	}
+
+	fn bounds(&self) -> Option<[[f32; 2]; 2]> {
+		let [x, y] = self.position;
+		Some([[x, y], [x + self.size[0], y + self.size[1]]])
+	}
 }
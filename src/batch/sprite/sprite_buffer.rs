@@ -0,0 +1,136 @@
+use super::Drawable2D;
+use super::shaders::{ UiVertex, ui_vs };
+use super::shared::{ BlendMode, SpriteBatchShared, SpriteCreationError };
+use super::textures::TextureSlot;
+use crate::batch::BatchError;
+use crate::texture::Texture;
+use std::sync::Arc;
+use vulkano::{
+	buffer::{ BufferAccess, BufferUsage, CpuBufferPool },
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::DescriptorSet,
+};
+
+/// Many sprites from one texture or atlas, written into a single vertex buffer each frame and drawn with one draw
+/// call. This is much cheaper than a `Sprite` each for particles and other large numbers of small sprites, but they
+/// can't have materials or normal maps, and they're all drawn at the buffer's place in the batch.
+///
+/// Sprites stay until `clear` is called, so a particle system would usually clear and push its particles every frame.
+pub struct SpriteBuffer {
+	texture: TextureSlot,
+	texture_size: [f32; 2],
+	vertices: Vec<UiVertex>,
+	pool: CpuBufferPool<UiVertex>,
+	blend_mode: BlendMode,
+}
+impl SpriteBuffer {
+	pub(crate) fn new(shared: &SpriteBatchShared, texture: &Texture) -> Result<Self, SpriteCreationError> {
+		let dimensions = texture.image().dimensions();
+		Ok(Self {
+			texture: shared.texture_index(texture.image())?,
+			texture_size: [dimensions.width() as f32, dimensions.height() as f32],
+			vertices: vec![],
+			pool: CpuBufferPool::new(shared.shaders().device().clone(), BufferUsage::vertex_buffer()),
+			blend_mode: BlendMode::Alpha,
+		})
+	}
+
+	pub fn clear(&mut self) {
+		self.vertices.clear();
+	}
+
+	/// How many sprites have been pushed since the last `clear`.
+	pub fn len(&self) -> usize {
+		self.vertices.len() / 6
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.vertices.is_empty()
+	}
+
+	/// Changes how every sprite in the buffer is blended with what's under it, like `Additive` for glowing particles.
+	pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+		self.blend_mode = blend_mode;
+	}
+
+	pub fn blend_mode(&self) -> BlendMode {
+		self.blend_mode
+	}
+
+	/// Adds a sprite of the whole texture at its size, like a `Sprite`.
+	pub fn push(&mut self, position: [f32; 2]) {
+		let size = self.texture_size;
+		self.push_region(position, size, [[0.0, 0.0], size], 0.0, [1.0; 4]);
+	}
+
+	/// Adds a sprite of part of the texture, like a frame from an atlas. `source` is the minimum and maximum corners of
+	/// the part, in pixels of the texture, and it's stretched to `size`. The sprite is rotated clockwise by `rotation`
+	/// radians around its center, and its color is multiplied by `color`, which is sRGB encoded, so `[1.0; 4]` leaves
+	/// it as it is.
+	pub fn push_region(
+		&mut self,
+		position: [f32; 2],
+		size: [f32; 2],
+		source: [[f32; 2]; 2],
+		rotation: f32,
+		color: [f32; 4],
+	) {
+		let [width, height] = self.texture_size;
+		let [[u0, v0], [u1, v1]] = source;
+		let [u0, v0, u1, v1] = [u0 / width, v0 / height, u1 / width, v1 / height];
+
+		let center = [position[0] + size[0] / 2.0, position[1] + size[1] / 2.0];
+		let (sin, cos) = rotation.sin_cos();
+		let corner = |x: f32, y: f32, u: f32, v: f32| {
+			let [x, y] = [(x - 0.5) * size[0], (y - 0.5) * size[1]];
+			UiVertex {
+				position: [center[0] + x * cos - y * sin, center[1] + x * sin + y * cos],
+				tex_coords: [u, v],
+				color: color,
+			}
+		};
+
+		let top_left = corner(0.0, 0.0, u0, v0);
+		let top_right = corner(1.0, 0.0, u1, v0);
+		let bottom_left = corner(0.0, 1.0, u0, v1);
+		let bottom_right = corner(1.0, 1.0, u1, v1);
+		self.vertices.extend_from_slice(&[top_left, top_right, bottom_left, bottom_left, top_right, bottom_right]);
+	}
+}
+impl Drawable2D for SpriteBuffer {
+	fn add_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		if self.vertices.is_empty() {
+			return Ok(cmds);
+		}
+
+		let vertices = self.pool.chunk(self.vertices.iter().cloned())?;
+		let draw = ui_vs::ty::UiDraw { scale: 1.0, texture_index: self.texture.index() };
+
+		shared.shaders().device_ctx().render_stats().record_draw(self.vertices.len() / 3);
+		Ok(
+			cmds
+				.draw(
+					shared.pipeline_ui_blend(self.blend_mode).clone(),
+					state,
+					vec![Arc::new(vertices) as Arc<BufferAccess + Send + Sync>],
+					(target_desc.clone(), shared.texture_desc()?),
+					draw,
+				)
+				.unwrap()
+		)
+	}
+
+	fn bounds(&self) -> Option<[[f32; 2]; 2]> {
+		let first = self.vertices.first()?.position;
+		Some(self.vertices.iter().fold([first, first], |[min, max], vertex| {
+			let [x, y] = vertex.position;
+			[[min[0].min(x), min[1].min(y)], [max[0].max(x), max[1].max(y)]]
+		}))
+	}
+}
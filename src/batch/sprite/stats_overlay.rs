@@ -0,0 +1,138 @@
+use super::Drawable2D;
+use super::font::{ Font, TextSprite };
+use super::shared::SpriteBatchShared;
+use crate::batch::BatchError;
+use crate::device::{ DeviceCtx, DrawCounts };
+use std::{ collections::VecDeque, sync::{ Arc, Mutex }, time::{ Duration, Instant } };
+use vulkano::{
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::DescriptorSet,
+};
+
+const GRAPH_SAMPLES: usize = 60;
+const GRAPH_BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// How often the text is rebuilt. Rebuilding it every frame would make the numbers unreadable anyway.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Shows the frame rate, a graph of recent frame times, and the draw calls and triangles recorded on the device. Add a
+/// clone of it to a `SpriteBatch` to draw it, and call `tick` once per frame before recording commands.
+#[derive(Clone)]
+pub struct StatsOverlay {
+	inner: Arc<Mutex<StatsOverlayInner>>,
+}
+impl StatsOverlay {
+	pub fn new(font: Arc<Font>, device: Arc<DeviceCtx>, position: [f32; 2]) -> Self {
+		let inner =
+			StatsOverlayInner {
+				font: font,
+				device: device,
+				position: position,
+				visible: true,
+				last_tick: None,
+				last_refresh: None,
+				frame_times: VecDeque::with_capacity(GRAPH_SAMPLES),
+				counts: DrawCounts::default(),
+				sprites: None,
+			};
+		Self { inner: Arc::new(Mutex::new(inner)) }
+	}
+
+	/// Records the time since the last tick as a frame, and takes the device's draw counts for the previous frame.
+	pub fn tick(&self) {
+		let mut inner = self.inner.lock().unwrap();
+		let now = Instant::now();
+
+		if let Some(last_tick) = inner.last_tick {
+			let dt = now.duration_since(last_tick);
+			if inner.frame_times.len() == GRAPH_SAMPLES {
+				inner.frame_times.pop_front();
+			}
+			inner.frame_times.push_back(dt.as_secs() as f32 + dt.subsec_nanos() as f32 / 1_000_000_000.0);
+		}
+		inner.last_tick = Some(now);
+		inner.counts = inner.device.render_stats().take();
+
+		if inner.last_refresh.map_or(true, |last_refresh| now.duration_since(last_refresh) >= REFRESH_INTERVAL) {
+			inner.last_refresh = Some(now);
+			inner.sprites = None;
+		}
+	}
+
+	pub fn is_visible(&self) -> bool {
+		self.inner.lock().unwrap().visible
+	}
+
+	pub fn set_visible(&self, visible: bool) {
+		self.inner.lock().unwrap().visible = visible;
+	}
+
+	pub fn toggle(&self) {
+		let mut inner = self.inner.lock().unwrap();
+		inner.visible = !inner.visible;
+	}
+}
+impl Drawable2D for StatsOverlay {
+	fn add_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		mut cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		let mut inner = self.inner.lock().unwrap();
+		if !inner.visible {
+			return Ok(cmds);
+		}
+
+		if inner.sprites.is_none() {
+			let sprites = inner.make_sprites(shared)?;
+			inner.sprites = Some(sprites);
+		}
+
+		for sprite in inner.sprites.as_mut().unwrap() {
+			cmds = sprite.add_commands(shared, target_desc, cmds, state)?;
+		}
+
+		Ok(cmds)
+	}
+}
+
+struct StatsOverlayInner {
+	font: Arc<Font>,
+	device: Arc<DeviceCtx>,
+	position: [f32; 2],
+	visible: bool,
+	last_tick: Option<Instant>,
+	last_refresh: Option<Instant>,
+	/// Seconds per frame, oldest first.
+	frame_times: VecDeque<f32>,
+	counts: DrawCounts,
+	sprites: Option<Vec<TextSprite>>,
+}
+impl StatsOverlayInner {
+	fn make_sprites(&self, shared: &SpriteBatchShared) -> Result<Vec<TextSprite>, BatchError> {
+		let average = self.frame_times.iter().sum::<f32>() / self.frame_times.len().max(1) as f32;
+		let fps = if average > 0.0 { 1.0 / average } else { 0.0 };
+
+		// bars are scaled to the slowest frame, but never less than 30 fps so a steady 60 doesn't look like a spike
+		let max = self.frame_times.iter().cloned().fold(1.0 / 30.0, f32::max);
+		let graph =
+			self.frame_times.iter()
+				.map(|time| GRAPH_BARS[((time / max * GRAPH_BARS.len() as f32) as usize).min(GRAPH_BARS.len() - 1)])
+				.collect::<String>();
+
+		let lines = [
+			format!("{:.0} fps ({:.2} ms)", fps, average * 1000.0),
+			graph,
+			format!("{} draw calls, {} triangles", self.counts.draw_calls, self.counts.triangles),
+		];
+
+		let line_height = self.font.line_height();
+		let [x, y] = self.position;
+		lines.iter()
+			.enumerate()
+			.map(|(i, line)| self.font.make_sprite(line, shared, [x, y + line_height * (i + 1) as f32]))
+			.collect()
+	}
+}
@@ -0,0 +1,256 @@
+use super::Drawable2D;
+use super::font::{ Font, TextLayout };
+use super::shaders::GlyphVertex;
+use super::shared::SpriteBatchShared;
+use crate::batch::BatchError;
+use crate::localization::Localization;
+use std::{ mem, sync::Arc };
+use vulkano::{
+	buffer::{ BufferAccess, BufferUsage, CpuBufferPool, ImmutableBuffer },
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::DescriptorSet,
+	sync::{ FenceSignalFuture, FlushError, GpuFuture },
+};
+
+/// Text that's laid out and uploaded to device local memory once, then drawn with a draw call for each atlas page
+/// it uses. Drawing it costs almost nothing, but changing it waits for a new upload, so it suits labels and menus.
+pub struct StaticText {
+	text: TextState,
+	pages: Vec<(Arc<BufferAccess + Send + Sync>, usize)>,
+	upload: Option<Arc<FenceSignalFuture<Box<GpuFuture + Send + Sync>>>>,
+}
+impl StaticText {
+	pub fn new(font: Arc<Font>, text: impl Into<String>, position: [f32; 2]) -> Self {
+		Self { text: TextState::new(font, text.into(), position), pages: vec![], upload: None }
+	}
+
+	/// Text that shows the string for `key` in the localization's current language, and follows it when the language
+	/// changes.
+	pub fn localized(font: Arc<Font>, localization: &Arc<Localization>, key: &str, position: [f32; 2]) -> Self {
+		let mut text = Self::new(font, String::new(), position);
+		text.text.set_key(localization, key);
+		text
+	}
+
+	/// Changes the string. Nothing is laid out again if it's the same as before. Localized text stops following its
+	/// key.
+	pub fn set_text(&mut self, text: &str) {
+		self.text.set_text(text);
+	}
+
+	pub fn text(&self) -> &str {
+		&self.text.text
+	}
+
+	pub fn set_position(&mut self, position: [f32; 2]) {
+		self.text.set_position(position);
+	}
+
+	pub fn position(&self) -> [f32; 2] {
+		self.text.position
+	}
+}
+impl Drawable2D for StaticText {
+	fn add_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		if self.text.update_layout(shared)? {
+			let device = shared.shaders().device_ctx();
+			let mut pages = vec![];
+			let mut futures: Option<Box<GpuFuture + Send + Sync>> = None;
+			for vertices in self.text.layout.as_ref().unwrap().page_vertices() {
+				let count = vertices.len();
+				let usage = BufferUsage::vertex_buffer();
+				let (buffer, future) =
+					ImmutableBuffer::from_iter(vertices.into_iter(), usage, device.queue().clone())?;
+				pages.push((buffer as Arc<BufferAccess + Send + Sync>, count));
+				futures =
+					Some(match futures { Some(futures) => Box::new(futures.join(future)), None => Box::new(future) });
+			}
+			device.defer_destroy(mem::replace(&mut self.pages, pages));
+			self.upload =
+				match futures {
+					Some(future) => Some(Arc::new(future.then_signal_fence_and_flush()?)),
+					None => None,
+				};
+		}
+
+		if let Some(upload) = self.upload.clone() {
+			match upload.wait(Some(Default::default())) {
+				Ok(()) => self.upload = None,
+				Err(FlushError::Timeout) => return Ok(cmds),
+				Err(err) => return Err(err.into()),
+			}
+		}
+
+		self.text.draw(shared, target_desc, cmds, state, &self.pages)
+	}
+
+	fn bounds(&self) -> Option<[[f32; 2]; 2]> {
+		self.text.bounds()
+	}
+}
+
+/// Text that changes often, like a frame rate counter. Changing it lays it out again and writes its vertices to host
+/// visible memory, with no upload to wait for. It's drawn from the same vertices until it changes again.
+pub struct DynamicText {
+	text: TextState,
+	pool: Option<CpuBufferPool<GlyphVertex>>,
+	pages: Vec<(Arc<BufferAccess + Send + Sync>, usize)>,
+}
+impl DynamicText {
+	pub fn new(font: Arc<Font>, text: impl Into<String>, position: [f32; 2]) -> Self {
+		Self { text: TextState::new(font, text.into(), position), pool: None, pages: vec![] }
+	}
+
+	/// Text that shows the string for `key` in the localization's current language, and follows it when the language
+	/// changes.
+	pub fn localized(font: Arc<Font>, localization: &Arc<Localization>, key: &str, position: [f32; 2]) -> Self {
+		let mut text = Self::new(font, String::new(), position);
+		text.text.set_key(localization, key);
+		text
+	}
+
+	/// Changes the string. Nothing is laid out again if it's the same as before. Localized text stops following its
+	/// key.
+	pub fn set_text(&mut self, text: &str) {
+		self.text.set_text(text);
+	}
+
+	pub fn text(&self) -> &str {
+		&self.text.text
+	}
+
+	pub fn set_position(&mut self, position: [f32; 2]) {
+		self.text.set_position(position);
+	}
+
+	pub fn position(&self) -> [f32; 2] {
+		self.text.position
+	}
+}
+impl Drawable2D for DynamicText {
+	fn add_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		if self.text.update_layout(shared)? {
+			let device = shared.shaders().device();
+			let pool =
+				self.pool.get_or_insert_with(|| CpuBufferPool::new(device.clone(), BufferUsage::vertex_buffer()));
+			let mut pages = vec![];
+			for vertices in self.text.layout.as_ref().unwrap().page_vertices() {
+				let count = vertices.len();
+				let chunk = pool.chunk(vertices.into_iter())?;
+				pages.push((Arc::new(chunk) as Arc<BufferAccess + Send + Sync>, count));
+			}
+			shared.shaders().device_ctx().defer_destroy(mem::replace(&mut self.pages, pages));
+		}
+
+		self.text.draw(shared, target_desc, cmds, state, &self.pages)
+	}
+
+	fn bounds(&self) -> Option<[[f32; 2]; 2]> {
+		self.text.bounds()
+	}
+}
+
+/// The string, position, and layout shared by `StaticText` and `DynamicText`.
+struct TextState {
+	font: Arc<Font>,
+	text: String,
+	position: [f32; 2],
+	/// The localization and key the string comes from, and the localization's generation when it was looked up.
+	localized: Option<(Arc<Localization>, String, u64)>,
+	/// `None` when the text has changed since it was last laid out.
+	layout: Option<TextLayout>,
+}
+impl TextState {
+	fn new(font: Arc<Font>, text: String, position: [f32; 2]) -> Self {
+		Self { font: font, text: text, position: position, localized: None, layout: None }
+	}
+
+	fn set_key(&mut self, localization: &Arc<Localization>, key: &str) {
+		self.text = localization.get(key);
+		self.localized = Some((localization.clone(), key.to_string(), localization.generation()));
+		self.layout = None;
+	}
+
+	fn set_text(&mut self, text: &str) {
+		self.localized = None;
+		if self.text != text {
+			self.text.clear();
+			self.text.push_str(text);
+			self.layout = None;
+		}
+	}
+
+	fn set_position(&mut self, position: [f32; 2]) {
+		if self.position != position {
+			self.position = position;
+			self.layout = None;
+		}
+	}
+
+	/// Lays out the text if it has changed. Returns whether it did, so the caller can rebuild its vertices.
+	fn update_layout(&mut self, shared: &SpriteBatchShared) -> Result<bool, BatchError> {
+		if let Some((localization, key, generation)) = &mut self.localized {
+			if *generation != localization.generation() {
+				*generation = localization.generation();
+				let text = localization.get(key);
+				if self.text != text {
+					self.text = text;
+					self.layout = None;
+				}
+			}
+		}
+
+		if self.layout.is_some() {
+			return Ok(false);
+		}
+		self.layout = Some(self.font.layout(&self.text, shared, self.position)?);
+		Ok(true)
+	}
+
+	/// The laid out glyphs' bounds. Text that hasn't been laid out yet has none, so it's drawn and laid out.
+	fn bounds(&self) -> Option<[[f32; 2]; 2]> {
+		self.layout.as_ref().and_then(|layout| layout.bounds())
+	}
+
+	/// Draws each page's vertices, skipping pages whose atlas images are still uploading.
+	fn draw(
+		&self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		mut cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+		pages: &[(Arc<BufferAccess + Send + Sync>, usize)],
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		let layout = self.layout.as_ref().unwrap();
+		let uploaded = layout.uploaded_pages()?;
+		for (((vertices, count), desc), uploaded) in pages.iter().zip(layout.page_descs()).zip(uploaded) {
+			if !uploaded {
+				continue;
+			}
+
+			shared.shaders().device_ctx().render_stats().record_draw(count / 3);
+			cmds = cmds
+				.draw(
+					shared.pipeline_glyphs().clone(),
+					state,
+					vec![vertices.clone()],
+					(target_desc.clone(), desc.clone()),
+					()
+				)
+				.unwrap();
+		}
+		Ok(cmds)
+	}
+}
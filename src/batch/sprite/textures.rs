@@ -0,0 +1,188 @@
+use std::sync::{ Arc, Weak };
+use vulkano::{
+	OomError,
+	buffer::BufferAccess,
+	descriptor::{
+		DescriptorSet,
+		PipelineLayoutAbstract,
+		descriptor::DescriptorDesc,
+		descriptor_set::{
+			DescriptorPool,
+			DescriptorPoolAlloc,
+			DescriptorSetDesc,
+			DescriptorWrite,
+			StdDescriptorPoolAlloc,
+			UnsafeDescriptorSet,
+			UnsafeDescriptorSetLayout,
+		},
+	},
+	device::{ Device, DeviceOwned },
+	image::ImageViewAccess,
+	pipeline::GraphicsPipelineAbstract,
+	sampler::Sampler,
+};
+
+/// Must match the array size in the sprite shaders.
+pub(crate) const MAX_SPRITE_TEXTURES: usize = 64;
+
+/// A texture's place in the sprite texture table. Sprites, tiles and the like hold on to one for as long as they draw
+/// the texture, and the slot goes back to the table once every clone of it is dropped.
+#[derive(Clone, Debug)]
+pub struct TextureSlot {
+	index: u32,
+	live: Arc<()>,
+}
+impl TextureSlot {
+	/// The index to push for shaders to find the texture in the table.
+	pub fn index(&self) -> u32 {
+		self.index
+	}
+}
+
+/// Every sprite texture in one descriptor set, so sprites only differ by the index they push.
+pub(crate) struct TextureTable {
+	slots: Vec<Option<TableEntry>>,
+	sampler: Arc<Sampler>,
+	/// Fills the slots no texture is using, since every element of the array has to be valid.
+	unused: Arc<ImageViewAccess + Send + Sync + 'static>,
+	desc: Option<Arc<TextureTableSet>>,
+}
+impl TextureTable {
+	pub(crate) fn new(sampler: Arc<Sampler>, unused: Arc<ImageViewAccess + Send + Sync + 'static>) -> Self {
+		Self { slots: vec![], sampler: sampler, unused: unused, desc: None }
+	}
+
+	/// Returns the texture's slot in the table, adding it if it isn't there yet. Returns `None` if every slot is held
+	/// by another texture.
+	pub(crate) fn index_of(&mut self, image: &Arc<ImageViewAccess + Send + Sync + 'static>) -> Option<TextureSlot> {
+		self.release_dead();
+
+		let existing =
+			self.slots.iter()
+				.enumerate()
+				.filter_map(|(i, slot)| slot.as_ref().map(|slot| (i, slot)))
+				.find(|(_, slot)| Arc::ptr_eq(&slot.image, image))
+				.and_then(|(i, slot)| slot.live.upgrade().map(|live| TextureSlot { index: i as u32, live: live }));
+		if existing.is_some() {
+			return existing;
+		}
+
+		let index =
+			match self.slots.iter().position(|slot| slot.is_none()) {
+				Some(index) => index,
+				None if self.slots.len() < MAX_SPRITE_TEXTURES => {
+					self.slots.push(None);
+					self.slots.len() - 1
+				},
+				None => return None,
+			};
+		let live = Arc::new(());
+		self.slots[index] = Some(TableEntry { image: image.clone(), live: Arc::downgrade(&live) });
+		self.desc = None;
+		Some(TextureSlot { index: index as u32, live: live })
+	}
+
+	/// Whether a `TextureSlot` with this index is still held, so the slot has its texture in it.
+	pub(crate) fn is_live(&self, index: u32) -> bool {
+		self.slots.get(index as usize)
+			.and_then(Option::as_ref)
+			.map_or(false, |entry| entry.live.upgrade().is_some())
+	}
+
+	/// Frees the slots of textures that nothing draws anymore, so the next descriptor set stops holding on to them.
+	fn release_dead(&mut self) {
+		for slot in &mut self.slots {
+			if slot.as_ref().map_or(false, |entry| entry.live.upgrade().is_none()) {
+				*slot = None;
+				self.desc = None;
+			}
+		}
+	}
+
+	/// Returns the descriptor set for the table, rebuilding it if slots were taken or freed since the last call. Sets
+	/// that are replaced stay alive as long as the command buffers that used them.
+	pub(crate) fn desc(
+		&mut self,
+		pipeline: &Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+		set: usize,
+	) -> Result<Arc<TextureTableSet>, OomError> {
+		self.release_dead();
+		if let Some(desc) = &self.desc {
+			return Ok(desc.clone());
+		}
+
+		let layout = pipeline.descriptor_set_layout(set).expect("missing texture table layout").clone();
+		let mut inner = layout.device().standard_descriptor_pool().alloc(&layout)?;
+
+		let images =
+			(0..MAX_SPRITE_TEXTURES)
+				.map(|i| match self.slots.get(i) {
+					Some(Some(entry)) => entry.image.clone(),
+					_ => self.unused.clone(),
+				})
+				.collect::<Vec<_>>();
+		unsafe {
+			inner.inner_mut().write(
+				layout.device(),
+				images.iter()
+					.enumerate()
+					.map(|(i, image)| DescriptorWrite::combined_image_sampler(0, i as u32, &self.sampler, &**image))
+			);
+		}
+
+		let desc =
+			Arc::new(TextureTableSet { inner: inner, layout: layout, images: images, _sampler: self.sampler.clone() });
+		self.desc = Some(desc.clone());
+		Ok(desc)
+	}
+}
+
+struct TableEntry {
+	image: Arc<ImageViewAccess + Send + Sync + 'static>,
+	/// Dead once every `TextureSlot` for this entry is dropped.
+	live: Weak<()>,
+}
+
+/// A descriptor set holding an array of combined image samplers, which `PersistentDescriptorSet` can't build when the
+/// number of textures is only known at runtime.
+pub(crate) struct TextureTableSet {
+	inner: StdDescriptorPoolAlloc,
+	layout: Arc<UnsafeDescriptorSetLayout>,
+	images: Vec<Arc<ImageViewAccess + Send + Sync + 'static>>,
+	_sampler: Arc<Sampler>,
+}
+unsafe impl DescriptorSet for TextureTableSet {
+	fn inner(&self) -> &UnsafeDescriptorSet {
+		self.inner.inner()
+	}
+
+	fn num_buffers(&self) -> usize {
+		0
+	}
+
+	fn buffer(&self, _index: usize) -> Option<(&BufferAccess, u32)> {
+		None
+	}
+
+	fn num_images(&self) -> usize {
+		self.images.len()
+	}
+
+	fn image(&self, index: usize) -> Option<(&ImageViewAccess, u32)> {
+		self.images.get(index).map(|image| (&**image as &ImageViewAccess, 0))
+	}
+}
+unsafe impl DescriptorSetDesc for TextureTableSet {
+	fn num_bindings(&self) -> usize {
+		self.layout.num_bindings()
+	}
+
+	fn descriptor(&self, binding: usize) -> Option<DescriptorDesc> {
+		self.layout.descriptor(binding)
+	}
+}
+unsafe impl DeviceOwned for TextureTableSet {
+	fn device(&self) -> &Arc<Device> {
+		self.layout.device()
+	}
+}
@@ -0,0 +1,264 @@
+use super::Drawable2D;
+use super::shaders::{ UiVertex, ui_vs };
+use super::shared::{ SpriteBatchShared, SpriteCreationError };
+use super::textures::TextureSlot;
+use crate::batch::BatchError;
+use crate::texture::Texture;
+use crate::window::{ Event, MouseButton, WindowEvent };
+use std::{ mem, sync::{ Arc, Mutex } };
+use vulkano::{
+	buffer::{ BufferAccess, BufferUsage, CpuBufferPool },
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::DescriptorSet,
+	pipeline::viewport::Scissor,
+};
+use winit::{ ElementState, ModifiersState, MouseScrollDelta, VirtualKeyCode };
+
+/// Touchpads scroll in pixels, but UI libraries expect lines.
+const PIXELS_PER_LINE: f32 = 20.0;
+
+/// Draws the output of an immediate mode UI library, like egui or Dear ImGui, and collects the window input it needs.
+///
+/// Add a clone of it last to a `SpriteBatch` that draws to the window, and pass window events to `handle_event`. Each
+/// frame, give `take_input` to the UI library, run the UI, convert its draw lists to `UiMesh`es, and pass them to
+/// `set_meshes`. The engine doesn't depend on any UI library, so that conversion is left to the game.
+#[derive(Clone)]
+pub struct UiOverlay {
+	inner: Arc<Mutex<UiOverlayInner>>,
+}
+impl UiOverlay {
+	pub fn new() -> Self {
+		let inner =
+			UiOverlayInner {
+				scale: 1.0,
+				meshes: vec![],
+				input: UiInput::default(),
+				wants_pointer: false,
+				wants_keyboard: false,
+				vertex_pool: None,
+				index_pool: None,
+			};
+		Self { inner: Arc::new(Mutex::new(inner)) }
+	}
+
+	/// Makes a texture available to UI meshes, such as the library's font atlas. The slot's `index` goes in
+	/// `UiMesh::texture`, and the texture stays registered until the slot is dropped, so keep it for as long as meshes
+	/// use it. Font atlases with only an alpha channel need to be expanded to white RGBA first.
+	pub fn register_texture(
+		&self,
+		shared: &SpriteBatchShared,
+		texture: &Texture,
+	) -> Result<TextureSlot, SpriteCreationError> {
+		shared.texture_index(texture.image())
+	}
+
+	/// Sets the window's DPI factor. Meshes and input are in logical pixels, and this converts them to and from
+	/// physical ones.
+	pub fn set_scale(&self, scale: f32) {
+		self.inner.lock().unwrap().scale = scale;
+	}
+
+	/// Replaces what's drawn, usually once per frame.
+	pub fn set_meshes(&self, meshes: Vec<UiMesh>) {
+		self.inner.lock().unwrap().meshes = meshes;
+	}
+
+	/// Tells the overlay whether the UI is using the mouse or keyboard, as reported by the UI library. While it is,
+	/// `handle_event` returns true for those events.
+	pub fn set_wants_input(&self, pointer: bool, keyboard: bool) {
+		let mut inner = self.inner.lock().unwrap();
+		inner.wants_pointer = pointer;
+		inner.wants_keyboard = keyboard;
+	}
+
+	/// Returns the input collected since the last call. The pointer, buttons and modifiers carry over to the next call,
+	/// since they're state rather than events.
+	pub fn take_input(&self) -> UiInput {
+		let mut inner = self.inner.lock().unwrap();
+		let next =
+			UiInput {
+				pointer: inner.input.pointer,
+				buttons: inner.input.buttons,
+				modifiers: inner.input.modifiers,
+				..UiInput::default()
+			};
+		mem::replace(&mut inner.input, next)
+	}
+
+	/// Records input for the UI. Returns true if the UI wants the event, in which case the game should usually ignore
+	/// it.
+	pub fn handle_event(&self, event: &Event) -> bool {
+		let mut inner = self.inner.lock().unwrap();
+		let event = match event { Event::WindowEvent { event, .. } => event, _ => return false };
+
+		match event {
+			WindowEvent::CursorMoved { position, modifiers, .. } => {
+				inner.input.pointer = Some([position.x as f32, position.y as f32]);
+				inner.input.modifiers = *modifiers;
+				inner.wants_pointer
+			},
+			WindowEvent::CursorLeft { .. } => {
+				inner.input.pointer = None;
+				false
+			},
+			WindowEvent::MouseInput { state, button, modifiers, .. } => {
+				let index =
+					match button {
+						MouseButton::Left => Some(0),
+						MouseButton::Right => Some(1),
+						MouseButton::Middle => Some(2),
+						MouseButton::Other(_) => None,
+					};
+				if let Some(index) = index {
+					inner.input.buttons[index] = *state == ElementState::Pressed;
+				}
+				inner.input.modifiers = *modifiers;
+				inner.wants_pointer
+			},
+			WindowEvent::MouseWheel { delta, modifiers, .. } => {
+				let [x, y] =
+					match delta {
+						MouseScrollDelta::LineDelta(x, y) => [*x, *y],
+						MouseScrollDelta::PixelDelta(pos) =>
+							[pos.x as f32 / PIXELS_PER_LINE, pos.y as f32 / PIXELS_PER_LINE],
+					};
+				inner.input.scroll[0] += x;
+				inner.input.scroll[1] += y;
+				inner.input.modifiers = *modifiers;
+				inner.wants_pointer
+			},
+			WindowEvent::KeyboardInput { input, .. } => {
+				if let Some(key) = input.virtual_keycode {
+					inner.input.keys.push((key, input.state == ElementState::Pressed));
+				}
+				inner.input.modifiers = input.modifiers;
+				inner.wants_keyboard
+			},
+			WindowEvent::ReceivedCharacter(ch) => {
+				if !ch.is_control() {
+					inner.input.text.push(*ch);
+				}
+				inner.wants_keyboard
+			},
+			_ => false,
+		}
+	}
+}
+impl Drawable2D for UiOverlay {
+	fn add_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		mut cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		let mut inner = self.inner.lock().unwrap();
+		let inner = &mut *inner;
+		if inner.meshes.is_empty() {
+			return Ok(cmds);
+		}
+
+		let device = shared.shaders().device();
+		let vertex_pool =
+			inner.vertex_pool.get_or_insert_with(|| CpuBufferPool::new(device.clone(), BufferUsage::vertex_buffer()));
+		let index_pool =
+			inner.index_pool.get_or_insert_with(|| CpuBufferPool::new(device.clone(), BufferUsage::index_buffer()));
+		for mesh in &inner.meshes {
+			// an index that was never registered could read past the texture array, and a dropped one another texture
+			if !shared.has_texture(mesh.texture) {
+				continue;
+			}
+			let mesh_state =
+				match clip_state(state, mesh.clip_rect, inner.scale) {
+					Some(mesh_state) if !mesh.indices.is_empty() => mesh_state,
+					_ => continue,
+				};
+
+			let vertices = vertex_pool.chunk(mesh.vertices.iter().cloned())?;
+			let indices = index_pool.chunk(mesh.indices.iter().cloned())?;
+			let draw = ui_vs::ty::UiDraw { scale: inner.scale, texture_index: mesh.texture };
+
+			shared.shaders().device_ctx().render_stats().record_draw(mesh.indices.len() / 3);
+			cmds =
+				cmds
+					.draw_indexed(
+						shared.pipeline_ui().clone(),
+						&mesh_state,
+						vec![Arc::new(vertices) as Arc<BufferAccess + Send + Sync>],
+						indices,
+						(target_desc.clone(), shared.texture_desc()?),
+						draw,
+					)
+					.unwrap();
+		}
+
+		Ok(cmds)
+	}
+}
+
+/// A triangle list from a UI library's draw list. Positions are in logical pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UiMesh {
+	pub vertices: Vec<UiVertex>,
+	pub indices: Vec<u32>,
+	/// Pixels outside this rectangle aren't drawn: the left, top, right and bottom edges, in logical pixels.
+	pub clip_rect: [f32; 4],
+	/// The index of a slot returned by `UiOverlay::register_texture`. Meshes whose slot has been dropped, or that use an
+	/// index that was never returned, aren't drawn.
+	pub texture: u32,
+}
+
+/// Window input for a UI library, in the same logical pixels as the meshes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UiInput {
+	/// The cursor position, or `None` if it's outside the window.
+	pub pointer: Option<[f32; 2]>,
+	/// Whether the left, right and middle mouse buttons are held.
+	pub buttons: [bool; 3],
+	/// Scroll wheel movement in lines, horizontal and then vertical.
+	pub scroll: [f32; 2],
+	/// Characters typed, for text fields.
+	pub text: String,
+	/// Keys pressed (true) or released (false), in order.
+	pub keys: Vec<(VirtualKeyCode, bool)>,
+	pub modifiers: ModifiersState,
+}
+
+struct UiOverlayInner {
+	scale: f32,
+	meshes: Vec<UiMesh>,
+	input: UiInput,
+	wants_pointer: bool,
+	wants_keyboard: bool,
+	/// Created on the first draw, since that's the first time the overlay sees the device.
+	vertex_pool: Option<CpuBufferPool<UiVertex>>,
+	index_pool: Option<CpuBufferPool<u32>>,
+}
+
+/// The batch's dynamic state with the scissor narrowed to a clip rectangle, relative to the viewport and multiplied by
+/// `scale`, or `None` if nothing is left.
+pub(super) fn clip_state(state: &DynamicState, clip_rect: [f32; 4], scale: f32) -> Option<DynamicState> {
+	let origin = state.viewports.as_ref().map(|viewports| viewports[0].origin).unwrap_or([0.0, 0.0]);
+	let mut min = [origin[0] + clip_rect[0] * scale, origin[1] + clip_rect[1] * scale];
+	let mut max = [origin[0] + clip_rect[2] * scale, origin[1] + clip_rect[3] * scale];
+
+	if let Some(scissor) = state.scissors.as_ref().map(|scissors| &scissors[0]) {
+		for i in 0..2 {
+			min[i] = min[i].max(scissor.origin[i] as f32);
+			max[i] = max[i].min(scissor.origin[i] as f32 + scissor.dimensions[i] as f32);
+		}
+	}
+
+	let min = [min[0].floor().max(0.0), min[1].floor().max(0.0)];
+	let max = [max[0].ceil(), max[1].ceil()];
+	if max[0] <= min[0] || max[1] <= min[1] {
+		return None;
+	}
+
+	let scissor =
+		Scissor {
+			origin: [min[0] as i32, min[1] as i32],
+			dimensions: [(max[0] - min[0]) as u32, (max[1] - min[1]) as u32],
+		};
+	Some(DynamicState { scissors: Some(vec![scissor]), ..state.clone() })
+}
@@ -0,0 +1,76 @@
+mod loader;
+mod map;
+mod tileset;
+
+pub use self::map::TileMap;
+pub use self::tileset::{ TileAnimation, Tileset };
+use crate::batch::sprite::SpriteCreationError;
+use image::ImageError;
+use std::{ error::Error, fmt, io };
+use vulkano::{ image::ImageCreationError, memory::DeviceMemoryAllocError };
+
+#[derive(Debug)]
+pub enum TileMapError {
+	IoError(io::Error),
+	ImageError(ImageError),
+	DeviceMemoryAllocError(DeviceMemoryAllocError),
+	/// A tileset image couldn't be created, such as when it's larger than the device allows.
+	ImageCreationError(ImageCreationError),
+	/// The file isn't a valid Tiled map, or uses a feature that isn't supported. This describes the problem.
+	InvalidMap(String),
+	/// Every slot in the sprite texture table is taken by another texture.
+	TooManyTextures,
+}
+impl fmt::Display for TileMapError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			TileMapError::IoError(err) => write!(f, "i/o error: {}", err),
+			TileMapError::ImageError(err) => write!(f, "tileset image decoding failed: {}", err),
+			TileMapError::DeviceMemoryAllocError(err) => write!(f, "device memory allocation failed: {}", err),
+			TileMapError::ImageCreationError(err) => write!(f, "tileset image creation failed: {}", err),
+			TileMapError::InvalidMap(msg) => write!(f, "invalid map: {}", msg),
+			TileMapError::TooManyTextures => write!(f, "the sprite texture table is full"),
+		}
+	}
+}
+impl Error for TileMapError {
+	fn source(&self) -> Option<&(Error + 'static)> {
+		match self {
+			TileMapError::IoError(err) => Some(err),
+			TileMapError::ImageError(err) => Some(err),
+			TileMapError::DeviceMemoryAllocError(err) => Some(err),
+			TileMapError::ImageCreationError(err) => Some(err),
+			TileMapError::InvalidMap(_) | TileMapError::TooManyTextures => None,
+		}
+	}
+}
+impl From<io::Error> for TileMapError {
+	fn from(val: io::Error) -> Self {
+		TileMapError::IoError(val)
+	}
+}
+impl From<ImageError> for TileMapError {
+	fn from(val: ImageError) -> Self {
+		TileMapError::ImageError(val)
+	}
+}
+impl From<DeviceMemoryAllocError> for TileMapError {
+	fn from(val: DeviceMemoryAllocError) -> Self {
+		TileMapError::DeviceMemoryAllocError(val)
+	}
+}
+impl From<ImageCreationError> for TileMapError {
+	fn from(val: ImageCreationError) -> Self {
+		match val {
+			ImageCreationError::AllocError(err) => TileMapError::DeviceMemoryAllocError(err),
+			val => TileMapError::ImageCreationError(val),
+		}
+	}
+}
+impl From<SpriteCreationError> for TileMapError {
+	fn from(val: SpriteCreationError) -> Self {
+		match val {
+			SpriteCreationError::TooManyTextures => TileMapError::TooManyTextures,
+		}
+	}
+}
@@ -0,0 +1,298 @@
+use super::{ TileAnimation, TileMap, TileMapError, Tileset };
+use crate::batch::sprite::SpriteBatchShared;
+use crate::device::{ DeviceCtx, MemoryCategory, image_bytes };
+use crate::images;
+use crate::texture::{ ColorEncoding, ImmutableTexture };
+use crate::vfs;
+use log::{ log, warn };
+use serde::Deserialize;
+use std::{ collections::HashMap, io::Cursor, path::Path, sync::Arc };
+use tiled::PropertyValue;
+use vulkano::{
+	image::{ Dimensions, ImmutableImage },
+	sync::{ self, GpuFuture },
+};
+
+/// A Tiled map read from disk, before anything is uploaded.
+pub(super) struct MapData {
+	tile_size: [u32; 2],
+	tilesets: Vec<TilesetData>,
+	layers: Vec<LayerData>,
+}
+
+struct TilesetData {
+	first_gid: u32,
+	tile_size: [u32; 2],
+	margin: u32,
+	spacing: u32,
+	image_name: String,
+	image: Vec<u8>,
+	animations: Vec<(u32, TileAnimation)>,
+}
+
+struct LayerData {
+	name: String,
+	width: u32,
+	tiles: Vec<u32>,
+	visible: bool,
+	offset: [f32; 2],
+	parallax: [f32; 2],
+}
+
+pub(super) fn read(path: &Path) -> Result<MapData, TileMapError> {
+	let dir = path.parent().unwrap_or(Path::new(""));
+	let is_json = path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("json"));
+	let mut map = if is_json { read_json(path)? } else { read_tmx(path)? };
+
+	for tileset in &mut map.tilesets {
+		tileset.image = vfs::read(dir.join(&tileset.image_name))?;
+	}
+
+	Ok(map)
+}
+
+pub(super) fn build(
+	device: &Arc<DeviceCtx>,
+	shared: &SpriteBatchShared,
+	data: MapData,
+) -> Result<(TileMap, Box<GpuFuture + Send + Sync>), TileMapError> {
+	let mut future: Box<GpuFuture + Send + Sync> = Box::new(sync::now(device.device().clone()));
+	let mut textures = vec![];
+	let mut memory = vec![];
+	let mut tilesets = vec![];
+	let mut first_gids = vec![];
+
+	for data in data.tilesets {
+		let img = images::decode(&data.image, None)?;
+		let (width, height) = (img.width, img.height);
+		let format = ColorEncoding::Srgb.rgba8_format();
+		let (image, image_future) =
+			ImmutableImage::from_iter(
+				img.into_rgba8().into_iter(),
+				Dimensions::Dim2d { width: width, height: height },
+				format,
+				device.queue().clone(),
+			)?;
+		device.debug_names().name_image(&*image, &data.image_name);
+		memory.push(
+			device.memory_stats().track(MemoryCategory::Textures, data.image_name, image_bytes([width, height], format))
+		);
+		future = Box::new(future.join(image_future));
+
+		let texture = ImmutableTexture::from_image(image);
+		let mut tileset = Tileset::with_spacing(shared, &texture, data.tile_size, data.margin, data.spacing)?;
+		for (tile, animation) in data.animations {
+			tileset.set_animation(tile, animation);
+		}
+
+		textures.push(texture);
+		tilesets.push(tileset);
+		first_gids.push(data.first_gid);
+	}
+
+	let mut map = TileMap::with_first_gids(data.tile_size, tilesets, first_gids);
+	map.add_textures(textures, memory);
+
+	for layer in data.layers {
+		let index = map.layer_count();
+		let layer_future = map.add_layer_impl(device, layer.name, layer.width, &layer.tiles)?;
+		future = Box::new(future.join(layer_future));
+
+		map.set_layer_visible(index, layer.visible);
+		map.set_layer_offset(index, layer.offset);
+		map.set_layer_parallax(index, layer.parallax);
+	}
+
+	Ok((map, future))
+}
+
+fn read_tmx(path: &Path) -> Result<MapData, TileMapError> {
+	let map =
+		tiled::parse_with_path(Cursor::new(vfs::read(path)?), path)
+			.map_err(|err| TileMapError::InvalidMap(format!("{:?}", err)))?;
+
+	let tilesets =
+		map.tilesets.iter()
+			.map(|tileset| {
+				let image =
+					tileset.images.first()
+						.ok_or_else(|| TileMapError::InvalidMap(format!("tileset {} has no image", tileset.name)))?;
+				let animations =
+					tileset.tiles.iter()
+						.filter_map(|tile| {
+							let frames = tile.animation.as_ref()?.iter().map(|frame| (frame.tile_id, frame.duration));
+							convert_animation(tile.id, frames.collect()).map(|animation| (tile.id, animation))
+						})
+						.collect();
+
+				Ok(TilesetData {
+					first_gid: tileset.first_gid,
+					tile_size: [tileset.tile_width, tileset.tile_height],
+					margin: tileset.margin,
+					spacing: tileset.spacing,
+					image_name: image.source.clone(),
+					image: vec![],
+					animations: animations,
+				})
+			})
+			.collect::<Result<Vec<_>, TileMapError>>()?;
+
+	let property = |layer: &tiled::Layer, name: &str| match layer.properties.get(name) {
+		Some(PropertyValue::FloatValue(value)) => *value,
+		Some(PropertyValue::IntValue(value)) => *value as f32,
+		_ => 1.0,
+	};
+	let layers =
+		map.layers.iter()
+			.map(|layer| LayerData {
+				name: layer.name.clone(),
+				width: map.width,
+				tiles: layer.tiles.iter().flat_map(|row| row.iter().cloned()).collect(),
+				visible: layer.visible,
+				offset: [0.0, 0.0],
+				parallax: [property(layer, "parallaxx"), property(layer, "parallaxy")],
+			})
+			.collect();
+
+	Ok(MapData { tile_size: [map.tile_width, map.tile_height], tilesets: tilesets, layers: layers })
+}
+
+fn read_json(path: &Path) -> Result<MapData, TileMapError> {
+	let map: JsonMap =
+		serde_json::from_slice(&vfs::read(path)?).map_err(|err| TileMapError::InvalidMap(err.to_string()))?;
+
+	let tilesets =
+		map.tilesets.into_iter()
+			.map(|tileset| {
+				if tileset.source.is_some() {
+					return Err(TileMapError::InvalidMap("external tilesets must be embedded in JSON maps".into()));
+				}
+
+				let animations =
+					tileset.tiles.iter()
+						.filter(|tile| !tile.animation.is_empty())
+						.filter_map(|tile| {
+							let frames = tile.animation.iter().map(|frame| (frame.tileid, frame.duration)).collect();
+							convert_animation(tile.id, frames).map(|animation| (tile.id, animation))
+						})
+						.collect();
+
+				Ok(TilesetData {
+					first_gid: tileset.firstgid,
+					tile_size: [tileset.tilewidth, tileset.tileheight],
+					margin: tileset.margin,
+					spacing: tileset.spacing,
+					image_name: tileset.image,
+					image: vec![],
+					animations: animations,
+				})
+			})
+			.collect::<Result<Vec<_>, TileMapError>>()?;
+
+	let layers =
+		map.layers.into_iter()
+			.filter(|layer| layer.ty == "tilelayer")
+			.map(|layer| {
+				let tiles =
+					serde_json::from_value(layer.data).map_err(|_| {
+						TileMapError::InvalidMap("only finite maps with CSV layers are supported".into())
+					})?;
+
+				Ok(LayerData {
+					name: layer.name,
+					width: layer.width,
+					tiles: tiles,
+					visible: layer.visible,
+					offset: [layer.offsetx, layer.offsety],
+					parallax: [layer.parallaxx, layer.parallaxy],
+				})
+			})
+			.collect::<Result<Vec<_>, TileMapError>>()?;
+
+	Ok(MapData { tile_size: [map.tilewidth, map.tileheight], tilesets: tilesets, layers: layers })
+}
+
+/// Tiled animations can jump between any tiles, but tile maps only support runs of consecutive tiles with the same
+/// duration. Other animations show their first frame.
+fn convert_animation(tile: u32, frames: Vec<(u32, u32)>) -> Option<TileAnimation> {
+	let (first_tile, duration) = *frames.first()?;
+	let consecutive = frames.iter().enumerate().all(|(i, &frame)| frame == (first_tile + i as u32, duration));
+	if !consecutive {
+		warn!("tile {} has an animation that isn't a run of consecutive tiles with the same duration", tile);
+	}
+
+	Some(TileAnimation {
+		first_tile: first_tile,
+		frames: if consecutive { frames.len() as u32 } else { 1 },
+		frame_duration: duration as f32 / 1000.0,
+	})
+}
+
+#[derive(Deserialize)]
+struct JsonMap {
+	tilewidth: u32,
+	tileheight: u32,
+	tilesets: Vec<JsonTileset>,
+	layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonTileset {
+	firstgid: u32,
+	#[serde(default)]
+	source: Option<String>,
+	#[serde(default)]
+	image: String,
+	#[serde(default)]
+	tilewidth: u32,
+	#[serde(default)]
+	tileheight: u32,
+	#[serde(default)]
+	margin: u32,
+	#[serde(default)]
+	spacing: u32,
+	#[serde(default)]
+	tiles: Vec<JsonTile>,
+}
+
+#[derive(Deserialize)]
+struct JsonTile {
+	id: u32,
+	#[serde(default)]
+	animation: Vec<JsonFrame>,
+}
+
+#[derive(Deserialize)]
+struct JsonFrame {
+	tileid: u32,
+	duration: u32,
+}
+
+#[derive(Deserialize)]
+struct JsonLayer {
+	name: String,
+	#[serde(rename = "type")]
+	ty: String,
+	#[serde(default)]
+	width: u32,
+	#[serde(default)]
+	data: serde_json::Value,
+	#[serde(default = "default_true")]
+	visible: bool,
+	#[serde(default)]
+	offsetx: f32,
+	#[serde(default)]
+	offsety: f32,
+	#[serde(default = "default_one")]
+	parallaxx: f32,
+	#[serde(default = "default_one")]
+	parallaxy: f32,
+}
+
+fn default_true() -> bool {
+	true
+}
+
+fn default_one() -> f32 {
+	1.0
+}
@@ -0,0 +1,325 @@
+use super::TileMapError;
+use super::loader;
+use super::tileset::Tileset;
+use crate::batch::{ BatchError, sprite::{ Drawable2D, SpriteBatchShared, TileVertex, tile_vs } };
+use crate::cpu_pool::{ spawn_cpu, spawn_fs };
+use crate::device::{ DeviceCtx, MemoryCategory, MemoryHandle };
+use crate::texture::ImmutableTexture;
+use crate::window::Window;
+use futures::prelude::*;
+use std::{ collections::BTreeMap, mem::size_of, path::Path, sync::Arc };
+use vulkano::{
+	buffer::{ BufferUsage, ImmutableBuffer },
+	command_buffer::{ AutoCommandBufferBuilder, DynamicState },
+	descriptor::DescriptorSet,
+	memory::DeviceMemoryAllocError,
+	sync::{ self, GpuFuture },
+};
+
+/// The width and height of a chunk, in tiles. Each chunk is drawn with one draw call per tileset it uses.
+const CHUNK_SIZE: u32 = 32;
+
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+
+/// A grid of tiles drawn in layers, as in Tiled. Add it to a `SpriteBatch` to draw it.
+///
+/// Layers refer to tiles by global ID, like Tiled: 0 is an empty cell, the first tileset's tiles start at 1, and each
+/// tileset's IDs start right after the previous tileset's. The top three bits of an ID flip the tile, as in Tiled.
+pub struct TileMap {
+	tile_size: [u32; 2],
+	tilesets: Vec<Tileset>,
+	first_gids: Vec<u32>,
+	layers: Vec<TileLayer>,
+	scroll: [f32; 2],
+	time: f32,
+	/// Tileset textures loaded with the map, kept so they stay alive and their memory stays tracked.
+	_textures: Vec<ImmutableTexture>,
+	_memory: Vec<MemoryHandle>,
+}
+impl TileMap {
+	/// Creates an empty map. `tile_size` is the size of a grid cell in pixels. Tiles that are taller than a cell extend
+	/// upward from the bottom of it.
+	pub fn new(tile_size: [u32; 2], tilesets: Vec<Tileset>) -> Self {
+		let mut first_gid = 1;
+		let first_gids =
+			tilesets.iter()
+				.map(|tileset| {
+					let ret = first_gid;
+					first_gid += tileset.tile_count;
+					ret
+				})
+				.collect();
+
+		Self::with_first_gids(tile_size, tilesets, first_gids)
+	}
+
+	/// Loads a Tiled map, in the `.tmx` or `.json` format, along with its tileset images. Layers with `parallaxx` or
+	/// `parallaxy` properties get those parallax factors.
+	pub fn from_file<P>(
+		window: &Window,
+		shared: Arc<SpriteBatchShared>,
+		path: P,
+	) -> impl Future<Output = Result<(Self, impl GpuFuture + Send + Sync + 'static), TileMapError>>
+	where P: AsRef<Path> + Send + 'static {
+		let device = window.device().clone();
+		spawn_fs(move || loader::read(path.as_ref()))
+			.then(move |data: Result<_, TileMapError>| spawn_cpu(move || loader::build(&device, &shared, data?)))
+	}
+
+	pub(super) fn with_first_gids(tile_size: [u32; 2], tilesets: Vec<Tileset>, first_gids: Vec<u32>) -> Self {
+		Self {
+			tile_size: tile_size,
+			tilesets: tilesets,
+			first_gids: first_gids,
+			layers: vec![],
+			scroll: [0.0, 0.0],
+			time: 0.0,
+			_textures: vec![],
+			_memory: vec![],
+		}
+	}
+
+	/// The global ID of a tileset's first tile.
+	pub fn first_gid(&self, tileset: usize) -> u32 {
+		self.first_gids[tileset]
+	}
+
+	/// Adds a layer on top of the existing ones. `tiles` holds global tile IDs, row by row, `width` to a row.
+	pub fn add_layer(
+		&mut self,
+		window: &Window,
+		name: impl Into<String>,
+		width: u32,
+		tiles: &[u32],
+	) -> Result<impl GpuFuture, DeviceMemoryAllocError> {
+		self.add_layer_impl(window.device(), name.into(), width, tiles)
+	}
+
+	pub(super) fn add_layer_impl(
+		&mut self,
+		device: &Arc<DeviceCtx>,
+		name: String,
+		width: u32,
+		tiles: &[u32],
+	) -> Result<Box<GpuFuture + Send + Sync>, DeviceMemoryAllocError> {
+		let (chunks, future) = self.make_chunks(device, width, tiles)?;
+		let bytes = chunks.iter().map(|chunk| chunk.vertex_count * size_of::<TileVertex>()).sum::<usize>();
+		let memory = device.memory_stats().track(MemoryCategory::Buffers, name.clone(), bytes as u64);
+
+		self.layers.push(TileLayer {
+			name: name,
+			visible: true,
+			offset: [0.0, 0.0],
+			parallax: [1.0, 1.0],
+			chunks: chunks,
+			_memory: memory,
+		});
+		Ok(future)
+	}
+
+	pub fn layer_count(&self) -> usize {
+		self.layers.len()
+	}
+
+	/// Finds the first layer with the given name.
+	pub fn layer_index(&self, name: &str) -> Option<usize> {
+		self.layers.iter().position(|layer| layer.name == name)
+	}
+
+	pub fn set_layer_visible(&mut self, layer: usize, visible: bool) {
+		self.layers[layer].visible = visible;
+	}
+
+	/// Moves a layer relative to the others, in pixels.
+	pub fn set_layer_offset(&mut self, layer: usize, offset: [f32; 2]) {
+		self.layers[layer].offset = offset;
+	}
+
+	/// How fast a layer moves with the scroll position. 1 moves with the map, values below 1 make the layer look
+	/// further away, and 0 keeps it fixed on the screen.
+	pub fn set_layer_parallax(&mut self, layer: usize, parallax: [f32; 2]) {
+		self.layers[layer].parallax = parallax;
+	}
+
+	/// The point of the map, in pixels, that's drawn at the top left of the viewport.
+	pub fn set_scroll(&mut self, scroll: [f32; 2]) {
+		self.scroll = scroll;
+	}
+
+	pub fn scroll(&self) -> [f32; 2] {
+		self.scroll
+	}
+
+	/// Advances animated tiles by `dt` seconds. Pass `Time::delta`, so tiles stop while the game is paused.
+	pub fn update(&mut self, dt: f32) {
+		self.time += dt;
+	}
+
+	pub(super) fn add_textures(&mut self, textures: Vec<ImmutableTexture>, memory: Vec<MemoryHandle>) {
+		self._textures.extend(textures);
+		self._memory.extend(memory);
+	}
+
+	/// Finds the tileset a global ID belongs to, and the tile's index within it.
+	fn find_tile(&self, gid: u32) -> Option<(usize, u32)> {
+		let tileset = self.first_gids.iter().rposition(|&first_gid| first_gid <= gid)?;
+		let tile = gid - self.first_gids[tileset];
+		if tile < self.tilesets[tileset].tile_count { Some((tileset, tile)) } else { None }
+	}
+
+	fn make_chunks(
+		&self,
+		device: &DeviceCtx,
+		width: u32,
+		tiles: &[u32],
+	) -> Result<(Vec<TileChunk>, Box<GpuFuture + Send + Sync>), DeviceMemoryAllocError> {
+		let queue = device.queue();
+		let width = width.max(1);
+		let height = (tiles.len() as u32 + width - 1) / width;
+		let mut chunks = vec![];
+		let mut future: Box<GpuFuture + Send + Sync> = Box::new(sync::now(queue.device().clone()));
+
+		for chunk_y in (0..height).step_by(CHUNK_SIZE as usize) {
+			for chunk_x in (0..width).step_by(CHUNK_SIZE as usize) {
+				let mut vertices = BTreeMap::<usize, Vec<TileVertex>>::new();
+
+				for y in chunk_y..(chunk_y + CHUNK_SIZE).min(height) {
+					for x in chunk_x..(chunk_x + CHUNK_SIZE).min(width) {
+						let gid = match tiles.get((y * width + x) as usize) { Some(&gid) => gid, None => continue };
+						let flags = gid & (FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY);
+						let (tileset_index, tile) =
+							match self.find_tile(gid & !flags) { Some(tile) => tile, None => continue };
+						let tileset = &self.tilesets[tileset_index];
+						let (tile, animation) =
+							match tileset.animations.get(&tile) {
+								Some(anim) => (anim.first_tile, [anim.frames as f32, anim.frame_duration.max(0.001)]),
+								None => (tile, [1.0, 1.0]),
+							};
+
+						let [tw, th] = [tileset.tile_size[0] as f32, tileset.tile_size[1] as f32];
+						let left = (x * self.tile_size[0]) as f32;
+						let top = ((y + 1) * self.tile_size[1]) as f32 - th;
+						let tile_vertices = vertices.entry(tileset_index).or_insert_with(Vec::new);
+						for &[cx, cy] in &[[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [0.0, 1.0], [1.0, 0.0], [1.0, 1.0]] {
+							let [mut u, mut v] = [cx, cy];
+							if flags & FLIPPED_DIAGONALLY != 0 {
+								std::mem::swap(&mut u, &mut v);
+							}
+							if flags & FLIPPED_HORIZONTALLY != 0 {
+								u = 1.0 - u;
+							}
+							if flags & FLIPPED_VERTICALLY != 0 {
+								v = 1.0 - v;
+							}
+
+							tile_vertices.push(TileVertex {
+								position: [left + cx * tw, top + cy * th],
+								corner: [u, v],
+								tile: tile,
+								animation: animation,
+							});
+						}
+					}
+				}
+
+				for (tileset, vertices) in vertices {
+					let bounds =
+						vertices.iter().fold(
+							[std::f32::MAX, std::f32::MAX, std::f32::MIN, std::f32::MIN],
+							|[x0, y0, x1, y1], vertex| {
+								let [x, y] = vertex.position;
+								[x0.min(x), y0.min(y), x1.max(x), y1.max(y)]
+							},
+						);
+					let vertex_count = vertices.len();
+					let (vertices, vertices_future) =
+						ImmutableBuffer::from_iter(vertices.into_iter(), BufferUsage::vertex_buffer(), queue.clone())?;
+					future = Box::new(future.join(vertices_future));
+					chunks.push(TileChunk {
+						tileset: tileset,
+						bounds: bounds,
+						vertices: vertices,
+						vertex_count: vertex_count,
+					});
+				}
+			}
+		}
+
+		Ok((chunks, future))
+	}
+}
+impl Drawable2D for TileMap {
+	fn add_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		mut cmds: AutoCommandBufferBuilder,
+		state: &DynamicState,
+	) -> Result<AutoCommandBufferBuilder, BatchError> {
+		let viewport = state.viewports.as_ref().and_then(|viewports| viewports.first()).map(|vp| vp.dimensions);
+		let texture_desc = shared.texture_desc()?;
+
+		for layer in self.layers.iter().filter(|layer| layer.visible) {
+			// whole pixels, so tiles don't shimmer as the map scrolls
+			let offset = [
+				(layer.offset[0] - self.scroll[0] * layer.parallax[0]).round(),
+				(layer.offset[1] - self.scroll[1] * layer.parallax[1]).round(),
+			];
+
+			for chunk in &layer.chunks {
+				let [x0, y0, x1, y1] = chunk.bounds;
+				if let Some([width, height]) = viewport {
+					let [x, y] = offset;
+					if x + x1 < 0.0 || y + y1 < 0.0 || x + x0 > width || y + y0 > height {
+						continue;
+					}
+				}
+
+				let tileset = &self.tilesets[chunk.tileset];
+				let draw =
+					tile_vs::ty::TileDraw {
+						offset: offset,
+						tile_size: [tileset.tile_size[0] as f32, tileset.tile_size[1] as f32],
+						texture_index: tileset.texture.index(),
+						columns: tileset.columns,
+						margin: tileset.margin as f32,
+						spacing: tileset.spacing as f32,
+						time: self.time,
+					};
+
+				shared.shaders().device_ctx().render_stats().record_draw(chunk.vertex_count / 3);
+				cmds = cmds
+					.draw(
+						shared.pipeline_tile().clone(),
+						state,
+						vec![chunk.vertices.clone()],
+						(target_desc.clone(), texture_desc.clone()),
+						draw,
+					)
+					.unwrap();
+			}
+		}
+
+		Ok(cmds)
+	}
+}
+
+struct TileLayer {
+	name: String,
+	visible: bool,
+	offset: [f32; 2],
+	parallax: [f32; 2],
+	chunks: Vec<TileChunk>,
+	_memory: MemoryHandle,
+}
+
+/// The tiles in one chunk of a layer that use the same tileset.
+struct TileChunk {
+	tileset: usize,
+	/// The area covered by the tiles, relative to the layer, as min x, min y, max x, max y.
+	bounds: [f32; 4],
+	vertices: Arc<ImmutableBuffer<[TileVertex]>>,
+	vertex_count: usize,
+}
@@ -0,0 +1,71 @@
+use crate::batch::sprite::{ SpriteBatchShared, SpriteCreationError, TextureSlot };
+use crate::texture::Texture;
+use std::collections::HashMap;
+
+/// A texture divided into a grid of equally sized tiles. Tiles are numbered from 0, left to right and then top to
+/// bottom.
+pub struct Tileset {
+	pub(super) texture: TextureSlot,
+	pub(super) tile_size: [u32; 2],
+	pub(super) columns: u32,
+	pub(super) tile_count: u32,
+	pub(super) margin: u32,
+	pub(super) spacing: u32,
+	pub(super) animations: HashMap<u32, TileAnimation>,
+}
+impl Tileset {
+	/// Fails if the sprite texture table is full.
+	pub fn new(
+		shared: &SpriteBatchShared,
+		texture: &Texture,
+		tile_size: [u32; 2],
+	) -> Result<Self, SpriteCreationError> {
+		Self::with_spacing(shared, texture, tile_size, 0, 0)
+	}
+
+	/// Like `new`, for tilesets with a border of `margin` pixels around the image and `spacing` pixels between tiles.
+	pub fn with_spacing(
+		shared: &SpriteBatchShared,
+		texture: &Texture,
+		tile_size: [u32; 2],
+		margin: u32,
+		spacing: u32,
+	) -> Result<Self, SpriteCreationError> {
+		let dimensions = texture.image().dimensions();
+		let count = |size: u32, tile_size: u32| (size.saturating_sub(2 * margin) + spacing) / (tile_size + spacing);
+		let columns = count(dimensions.width(), tile_size[0]).max(1);
+		let rows = count(dimensions.height(), tile_size[1]);
+
+		Ok(Self {
+			texture: shared.texture_index(texture.image())?,
+			tile_size: tile_size,
+			columns: columns,
+			tile_count: columns * rows,
+			margin: margin,
+			spacing: spacing,
+			animations: HashMap::new(),
+		})
+	}
+
+	/// Animates a tile wherever it's used in a map. Maps created before this is called aren't affected.
+	pub fn set_animation(&mut self, tile: u32, animation: TileAnimation) {
+		self.animations.insert(tile, animation);
+	}
+
+	pub fn tile_size(&self) -> [u32; 2] {
+		self.tile_size
+	}
+
+	pub fn tile_count(&self) -> u32 {
+		self.tile_count
+	}
+}
+
+/// Cycles a tile through `frames` consecutive tiles, starting at `first_tile`, spending `frame_duration` seconds on
+/// each.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileAnimation {
+	pub first_tile: u32,
+	pub frames: u32,
+	pub frame_duration: f32,
+}
@@ -0,0 +1,204 @@
+use crate::camera::Camera;
+use crate::timeline::{ Timeline, TimelinePlayer };
+use crate::window::Window;
+use serde::{ Deserialize, Serialize };
+use std::{ fs::File, io::{ self, BufWriter, Write }, path::Path, time::{ Duration, Instant } };
+
+/// Flies a camera along a timeline's camera track and times every frame, for measuring performance on real content.
+///
+/// The timeline advances by a fixed step each frame instead of the real clock, so every run draws the same frames.
+/// Each frame, call `begin_frame` before updating and recording, and `end_frame` right after presenting. `end_frame`
+/// waits for the GPU to finish the frame, so frames are timed one at a time rather than overlapping like they would in
+/// the game. It also takes the device's draw counts, so it can't be used with a `StatsOverlay` at the same time.
+///
+/// GPU time isn't measured directly, since command buffers can't write timestamp queries through vulkano yet. The
+/// closest it gets is `FrameSample::gpu_wait_ms`.
+pub struct Benchmark {
+	player: TimelinePlayer,
+	step: f32,
+	warmup_frames: u32,
+	frame: u32,
+	frame_start: Option<Instant>,
+	last_frame: Option<Instant>,
+	samples: Vec<FrameSample>,
+}
+impl Benchmark {
+	pub fn new(timeline: Timeline) -> Self {
+		let mut player = TimelinePlayer::new(timeline);
+		player.play();
+		Self {
+			player: player,
+			step: 1.0 / 60.0,
+			warmup_frames: 30,
+			frame: 0,
+			frame_start: None,
+			last_frame: None,
+			samples: vec![],
+		}
+	}
+
+	/// How far the timeline advances each frame, in seconds. The default is 1/60.
+	pub fn set_step(&mut self, step: f32) {
+		self.step = step.max(0.0);
+	}
+
+	/// Frames drawn at the start of the timeline before it starts moving, and left out of the report, so pipeline
+	/// creation and uploads don't count. The default is 30.
+	pub fn set_warmup_frames(&mut self, frames: u32) {
+		self.warmup_frames = frames;
+	}
+
+	/// The player, for applying the timeline's mesh and light tracks to the scene.
+	pub fn player(&self) -> &TimelinePlayer {
+		&self.player
+	}
+
+	/// Starts timing a frame, and moves the camera to where it is this frame. Returns false once the timeline has
+	/// finished, when the report is ready.
+	pub fn begin_frame(&mut self, camera: &mut Camera) -> bool {
+		if self.player.is_finished() {
+			return false;
+		}
+		if self.frame >= self.warmup_frames {
+			self.player.advance(self.step);
+		}
+		self.player.apply_camera(camera);
+		self.frame_start = Some(Instant::now());
+		true
+	}
+
+	/// Finishes timing the frame started by `begin_frame`.
+	pub fn end_frame(&mut self, window: &mut Window) {
+		let frame_start = match self.frame_start.take() { Some(frame_start) => frame_start, None => return };
+		let submitted = Instant::now();
+		window.wait_for_gpu();
+		let finished = Instant::now();
+		let counts = window.device().render_stats().take();
+
+		let last_frame = self.last_frame.replace(finished);
+		self.frame += 1;
+		if self.frame <= self.warmup_frames {
+			return;
+		}
+
+		self.samples.push(FrameSample {
+			time: self.player.time(),
+			frame_ms: last_frame.map_or(0.0, |last_frame| millis(finished.duration_since(last_frame))),
+			cpu_ms: millis(submitted.duration_since(frame_start)),
+			gpu_wait_ms: millis(finished.duration_since(submitted)),
+			draw_calls: counts.draw_calls,
+			triangles: counts.triangles,
+		});
+	}
+
+	pub fn is_finished(&self) -> bool {
+		self.player.is_finished()
+	}
+
+	/// The frames timed so far, with a summary. `window` is used for the device's name.
+	pub fn report(&self, window: &Window) -> BenchmarkReport {
+		BenchmarkReport {
+			device: window.device().diagnostics().name,
+			summary: BenchmarkSummary::new(&self.samples),
+			frames: self.samples.clone(),
+		}
+	}
+}
+
+/// How long one frame took. Times are in milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FrameSample {
+	/// Where the timeline was, in seconds.
+	pub time: f32,
+	/// The time since the previous frame finished.
+	pub frame_ms: f32,
+	/// From `begin_frame` until the frame was presented: updating, recording, and submitting. The GPU is idle
+	/// throughout, so this leaves out time the game would spend blocked on the GPU, such as waiting for a swapchain
+	/// image.
+	pub cpu_ms: f32,
+	/// Wall clock time from the frame being presented until the GPU finished it. This is the GPU work left after
+	/// submission plus any wait for vertical blank with vsync, not the time the GPU spent rendering.
+	pub gpu_wait_ms: f32,
+	pub draw_calls: usize,
+	pub triangles: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BenchmarkSummary {
+	pub frames: usize,
+	pub average_fps: f32,
+	pub average_frame_ms: f32,
+	pub min_frame_ms: f32,
+	pub max_frame_ms: f32,
+	/// 99% of frames were at least this fast.
+	pub p99_frame_ms: f32,
+	pub average_cpu_ms: f32,
+	pub average_gpu_wait_ms: f32,
+}
+impl BenchmarkSummary {
+	fn new(samples: &[FrameSample]) -> Self {
+		if samples.is_empty() {
+			return Self::default();
+		}
+
+		let count = samples.len() as f32;
+		let average = |value: fn(&FrameSample) -> f32| samples.iter().map(value).sum::<f32>() / count;
+		let mut frame_times = samples.iter().map(|sample| sample.frame_ms).collect::<Vec<_>>();
+		frame_times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+		let average_frame_ms = average(|sample| sample.frame_ms);
+
+		Self {
+			frames: samples.len(),
+			average_fps: if average_frame_ms > 0.0 { 1000.0 / average_frame_ms } else { 0.0 },
+			average_frame_ms: average_frame_ms,
+			min_frame_ms: frame_times[0],
+			max_frame_ms: *frame_times.last().unwrap(),
+			p99_frame_ms: frame_times[((frame_times.len() - 1) as f32 * 0.99).round() as usize],
+			average_cpu_ms: average(|sample| sample.cpu_ms),
+			average_gpu_wait_ms: average(|sample| sample.gpu_wait_ms),
+		}
+	}
+}
+
+/// The results of a benchmark run.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BenchmarkReport {
+	/// The name of the GPU it ran on.
+	pub device: String,
+	pub summary: BenchmarkSummary,
+	pub frames: Vec<FrameSample>,
+}
+impl BenchmarkReport {
+	/// Writes the report as JSON if the extension is `.json`, or as CSV otherwise.
+	pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+		let ext = path.as_ref().extension().and_then(|ext| ext.to_str()).unwrap_or("");
+		let writer = BufWriter::new(File::create(path.as_ref())?);
+		if ext.eq_ignore_ascii_case("json") { self.write_json(writer) } else { self.write_csv(writer) }
+	}
+
+	pub fn write_json(&self, writer: impl Write) -> io::Result<()> {
+		serde_json::to_writer_pretty(writer, self).map_err(io::Error::from)
+	}
+
+	/// Writes one row per frame. The summary isn't included, since it can be worked out from the rows.
+	pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+		writeln!(writer, "time,frame_ms,cpu_ms,gpu_wait_ms,draw_calls,triangles")?;
+		for frame in &self.frames {
+			writeln!(
+				writer,
+				"{},{},{},{},{},{}",
+				frame.time,
+				frame.frame_ms,
+				frame.cpu_ms,
+				frame.gpu_wait_ms,
+				frame.draw_calls,
+				frame.triangles,
+			)?;
+		}
+		writer.flush()
+	}
+}
+
+fn millis(duration: Duration) -> f32 {
+	duration.as_secs() as f32 * 1000.0 + duration.subsec_nanos() as f32 / 1_000_000.0
+}
@@ -1,70 +1,281 @@
-use crate::window::Window;
-use cgmath::{ vec4, Quaternion, Vector3, Vector4 };
-use std::{ f32::consts::PI, sync::Arc };
-use vulkano::{
-	buffer::{ CpuBufferPool, cpu_pool::CpuBufferPoolSubbuffer },
-	memory::{ DeviceMemoryAllocError, pool::StdMemoryPool },
-};
+pub mod controllers;
 
+use cgmath::{ prelude::*, vec3, vec4, Matrix3, Matrix4, Quaternion, Vector3, Vector4 };
+use std::f32::consts::PI;
+
+/// A perspective camera. This is plain state, so it's cheap to copy, and one batch can be drawn from any number of
+/// cameras.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct Camera {
-	position_pool: CpuBufferPool<Vector3<f32>>,
-	rotation_pool: CpuBufferPool<Quaternion<f32>>,
-	projection_pool: CpuBufferPool<Vector4<f32>>,
-	pub(crate) position_buffer: CpuBufferPoolSubbuffer<Vector3<f32>, Arc<StdMemoryPool>>,
-	pub(crate) rotation_buffer: CpuBufferPoolSubbuffer<Quaternion<f32>, Arc<StdMemoryPool>>,
-	pub(crate) projection_buffer: CpuBufferPoolSubbuffer<Vector4<f32>, Arc<StdMemoryPool>>,
+	position: Vector3<f32>,
+	rotation: Quaternion<f32>,
+	aspect: f32,
+	fovx: f32,
+	znear: f32,
+	zfar: f32,
+	/// Replaces the field of view and aspect ratio when set.
+	asymmetric_fov: Option<AsymmetricFov>,
+	reverse_z: bool,
+	focus_distance: f32,
+	aperture: f32,
+	motion_blur_samples: u32,
+	shutter_scale: f32,
 }
 impl Camera {
 	pub fn new(
-		window: &Window,
 		position: Vector3<f32>,
 		rotation: Quaternion<f32>,
 		aspect: f32,
 		fovx: f32,
 		znear: f32,
 		zfar: f32,
-	) -> Result<Self, DeviceMemoryAllocError> {
-		let position_pool = CpuBufferPool::uniform_buffer(window.device().device().clone());
-		let rotation_pool = CpuBufferPool::uniform_buffer(window.device().device().clone());
-		let projection_pool = CpuBufferPool::uniform_buffer(window.device().device().clone());
+	) -> Self {
+		Self {
+			position: position,
+			rotation: rotation,
+			aspect: aspect,
+			fovx: fovx,
+			znear: znear,
+			zfar: zfar,
+			asymmetric_fov: None,
+			reverse_z: false,
+			focus_distance: 10.0,
+			aperture: 0.0,
+			motion_blur_samples: 0,
+			shutter_scale: 0.5,
+		}
+	}
+
+	/// Creates a camera from a view matrix and a perspective projection matrix in the layout returned by `view` and
+	/// `projection`. The view matrix must not contain scale.
+	pub fn from_matrices(view: Matrix4<f32>, projection: Matrix4<f32>) -> Self {
+		let transform = view.invert().expect("view matrix is not invertible");
+		let position = transform.w.truncate();
+		let rotation =
+			Quaternion::from(
+				Matrix3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate())
+			);
+
+		let (scale_x, scale_y, scale_z, offset_z) = (projection.x.x, projection.y.y, projection.z.z, projection.w.z);
+		let reverse_z = scale_z > 0.0;
+		let (znear, zfar) =
+			if reverse_z {
+				(offset_z / (scale_z + 1.0), offset_z / scale_z)
+			} else {
+				(offset_z / (scale_z - 1.0), offset_z / (scale_z + 1.0))
+			};
+		let fovx = (1.0 / scale_y).atan() * 360.0 / PI;
 
-		let position_buffer = position_pool.next(position)?;
-		let rotation_buffer = rotation_pool.next(rotation)?;
-		let projection_buffer = projection_pool.next(Self::projection(aspect, fovx, znear, zfar))?;
+		// off-center projections, like a headset's, have x and y terms in the z column
+		let (offset_x, offset_y) = (projection.z.x, projection.z.y);
+		let asymmetric_fov =
+			if offset_x != 0.0 || offset_y != 0.0 {
+				Some(AsymmetricFov {
+					left: (offset_x - 1.0) / scale_x,
+					right: (offset_x + 1.0) / scale_x,
+					down: -(offset_y + 1.0) / scale_y,
+					up: (1.0 - offset_y) / scale_y,
+				})
+			} else {
+				None
+			};
 
-		Ok(Self {
-			position_pool: position_pool,
-			rotation_pool: rotation_pool,
-			projection_pool: projection_pool,
-			position_buffer: position_buffer,
-			rotation_buffer: rotation_buffer,
-			projection_buffer: projection_buffer,
-		})
+		Self {
+			reverse_z: reverse_z,
+			asymmetric_fov: asymmetric_fov,
+			.. Self::new(position, rotation, scale_y / scale_x, fovx, znear, zfar)
+		}
 	}
 
-	pub fn set_position(&mut self, position: Vector3<f32>) -> Result<(), DeviceMemoryAllocError> {
-		self.position_buffer = self.position_pool.next(position)?;
-		Ok(())
+	pub fn set_position(&mut self, position: Vector3<f32>) {
+		self.position = position;
 	}
 
-	pub fn set_projection(
-		&mut self,
-		aspect: f32,
-		fovx: f32,
-		znear: f32,
-		zfar: f32
-	) -> Result<(), DeviceMemoryAllocError> {
-		self.projection_buffer = self.projection_pool.next(Self::projection(aspect, fovx, znear, zfar))?;
-		Ok(())
+	pub fn set_projection(&mut self, aspect: f32, fovx: f32, znear: f32, zfar: f32) {
+		self.aspect = aspect;
+		self.fovx = fovx;
+		self.znear = znear;
+		self.zfar = zfar;
+		self.asymmetric_fov = None;
+	}
+
+	/// Uses an off-center field of view, such as one eye's view from a VR headset, until `set_projection` is called.
+	/// The near and far planes are kept.
+	pub fn set_asymmetric_fov(&mut self, fov: AsymmetricFov) {
+		self.asymmetric_fov = Some(fov);
+	}
+
+	pub fn asymmetric_fov(&self) -> Option<AsymmetricFov> {
+		self.asymmetric_fov
+	}
+
+	/// Maps the near plane to a depth of 1.0 and the far plane to 0.0. This must match the `reverse_z` option of the
+	/// render pass the camera is used with.
+	pub fn set_reverse_z(&mut self, reverse_z: bool) {
+		self.reverse_z = reverse_z;
+	}
+
+	pub fn reverse_z(&self) -> bool {
+		self.reverse_z
+	}
+
+	/// Blurs what's out of focus. The aperture is how blurry something infinitely far away is, as a fraction of the
+	/// viewport's height, so 0.0 disables depth of field. Things nearer than the focus distance blur too, and the blur
+	/// radius is limited to 16 pixels either way.
+	pub fn set_depth_of_field(&mut self, focus_distance: f32, aperture: f32) {
+		self.focus_distance = focus_distance;
+		self.aperture = aperture;
+	}
+
+	pub fn focus_distance(&self) -> f32 {
+		self.focus_distance
+	}
+
+	pub fn aperture(&self) -> f32 {
+		self.aperture
 	}
 
-	pub fn set_rotation(&mut self, rotation: Quaternion<f32>) -> Result<(), DeviceMemoryAllocError> {
-		self.rotation_buffer = self.rotation_pool.next(rotation)?;
-		Ok(())
+	/// Blurs things along the path they moved since the previous frame. The shutter scale is how much of that path is
+	/// blurred, where 1.0 is the whole frame. Fewer than 2 samples disables motion blur.
+	pub fn set_motion_blur(&mut self, samples: u32, shutter_scale: f32) {
+		self.motion_blur_samples = samples;
+		self.shutter_scale = shutter_scale;
 	}
 
-	fn projection(aspect: f32, fovx: f32, znear: f32, zfar: f32) -> Vector4<f32> {
-		let f = 1.0 / (fovx * (PI / 360.0)).tan();
-		vec4(f / aspect, f, (zfar + znear) / (znear - zfar), 2.0 * zfar * znear / (znear - zfar))
+	pub fn motion_blur_samples(&self) -> u32 {
+		self.motion_blur_samples
+	}
+
+	pub fn shutter_scale(&self) -> f32 {
+		self.shutter_scale
+	}
+
+	pub fn set_rotation(&mut self, rotation: Quaternion<f32>) {
+		self.rotation = rotation;
+	}
+
+	pub fn position(&self) -> Vector3<f32> {
+		self.position
+	}
+
+	pub fn rotation(&self) -> Quaternion<f32> {
+		self.rotation
+	}
+
+	/// Transforms world space to camera space, where the camera looks down -Z.
+	pub fn view(&self) -> Matrix4<f32> {
+		Matrix4::from(self.rotation.invert()) * Matrix4::from_translation(-self.position)
+	}
+
+	/// Transforms camera space to Vulkan clip space. This is the same projection the mesh shaders use.
+	pub fn projection(&self) -> Matrix4<f32> {
+		let terms = self.projection_terms();
+		let offset = self.projection_offset();
+		Matrix4::new(
+			terms.x, 0.0, 0.0, 0.0,
+			0.0, terms.y, 0.0, 0.0,
+			offset[0], offset[1], terms.z, -1.0,
+			0.0, 0.0, terms.w, 0.0,
+		)
+	}
+
+	pub fn view_projection(&self) -> Matrix4<f32> {
+		self.projection() * self.view()
+	}
+
+	/// The world space ray through a pixel, as the camera's position and a normalized direction. `viewport_size` is the
+	/// size in pixels of the viewport the camera is drawn to.
+	pub fn screen_ray(&self, point: [f32; 2], viewport_size: [f32; 2]) -> (Vector3<f32>, Vector3<f32>) {
+		let terms = self.projection_terms();
+		let offset = self.projection_offset();
+		let ndc = [2.0 * point[0] / viewport_size[0] - 1.0, 2.0 * point[1] / viewport_size[1] - 1.0];
+		let direction = self.rotation * vec3((ndc[0] + offset[0]) / terms.x, (ndc[1] + offset[1]) / terms.y, -1.0);
+		(self.position, direction.normalize())
+	}
+
+	/// The pixel a world space point is drawn at, or `None` if it's behind the camera.
+	pub fn world_to_screen(&self, point: Vector3<f32>, viewport_size: [f32; 2]) -> Option<[f32; 2]> {
+		let clip = self.view_projection() * point.extend(1.0);
+		if clip.w <= 0.0 {
+			return None;
+		}
+		Some([(clip.x / clip.w + 1.0) * viewport_size[0] / 2.0, (clip.y / clip.w + 1.0) * viewport_size[1] / 2.0])
+	}
+
+	/// The world space planes bounding what the camera can see, in the order left, right, bottom, top, near, far. Each
+	/// plane is `(normal, distance)` with the normal pointing inward and normalized, so a point `p` is inside the plane
+	/// when `normal.dot(p) + distance >= 0`.
+	pub fn frustum_planes(&self) -> [Vector4<f32>; 6] {
+		let m = self.view_projection().transpose();
+		let normalize = |plane: Vector4<f32>| plane / plane.truncate().magnitude();
+
+		// vulkan clips to 0 <= z <= w, so the near and far planes come from those two inequalities
+		let (near, far) = if self.reverse_z { (m.w - m.z, m.z) } else { (m.z, m.w - m.z) };
+
+		[
+			normalize(m.w + m.x),
+			normalize(m.w - m.x),
+			normalize(m.w + m.y),
+			normalize(m.w - m.y),
+			normalize(near),
+			normalize(far),
+		]
+	}
+
+	/// The diagonal terms of the projection matrix: x and y scale, then the z scale and offset.
+	pub(crate) fn projection_terms(&self) -> Vector4<f32> {
+		let (znear, zfar) = (self.znear, self.zfar);
+		let (scale_x, scale_y) =
+			match self.asymmetric_fov {
+				Some(fov) => (2.0 / (fov.right - fov.left), 2.0 / (fov.up - fov.down)),
+				None => {
+					let f = 1.0 / (self.fovx * (PI / 360.0)).tan();
+					(f / self.aspect, f)
+				},
+			};
+		if self.reverse_z {
+			vec4(scale_x, scale_y, znear / (zfar - znear), zfar * znear / (zfar - znear))
+		} else {
+			vec4(scale_x, scale_y, (zfar + znear) / (znear - zfar), 2.0 * zfar * znear / (znear - zfar))
+		}
+	}
+
+	/// The near and far planes.
+	pub(crate) fn depth_range(&self) -> (f32, f32) {
+		(self.znear, self.zfar)
+	}
+
+	/// How far an asymmetric field of view shifts the view center, in normalized device coordinates. It's the x and y
+	/// terms of the projection matrix's z column.
+	pub(crate) fn projection_offset(&self) -> [f32; 2] {
+		match self.asymmetric_fov {
+			// screen space y points down, so the top of the view has the lowest y
+			Some(fov) => [(fov.right + fov.left) / (fov.right - fov.left), -(fov.up + fov.down) / (fov.up - fov.down)],
+			None => [0.0, 0.0],
+		}
+	}
+
+	/// The projection terms, then the offset, as the shaders receive them.
+	pub(crate) fn projection_uniform(&self) -> [Vector4<f32>; 2] {
+		let offset = self.projection_offset();
+		[self.projection_terms(), vec4(offset[0], offset[1], 0.0, 0.0)]
+	}
+}
+
+/// An off-center field of view, as the tangents of the angles from the view direction to each edge of the view. Left
+/// and down are usually negative. Headset runtimes report each eye's field of view this way, before taking tangents.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct AsymmetricFov {
+	pub left: f32,
+	pub right: f32,
+	pub down: f32,
+	pub up: f32,
+}
+impl AsymmetricFov {
+	/// Converts angles in radians, like OpenXR's `XrFovf`.
+	pub fn from_angles(left: f32, right: f32, down: f32, up: f32) -> Self {
+		Self { left: left.tan(), right: right.tan(), down: down.tan(), up: up.tan() }
 	}
 }
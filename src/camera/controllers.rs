@@ -0,0 +1,232 @@
+use super::Camera;
+use cgmath::{ prelude::*, vec3, Quaternion, Rad, Vector2, Vector3 };
+use std::f32::consts::PI;
+
+/// Input for a single tick, already mapped from whatever devices the game reads.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct ControllerInput {
+	/// Movement relative to the camera's heading: x right, y up, z backward. Each component is usually -1, 0 or 1.
+	pub movement: Vector3<f32>,
+	/// Rotation since the last tick, in radians. Positive x turns right and positive y looks down, like mouse movement.
+	pub look: Vector2<f32>,
+	/// Positive values move the camera closer to what it's looking at. Ignored by `FlyController`.
+	pub zoom: f32,
+}
+impl Default for ControllerInput {
+	fn default() -> Self {
+		Self { movement: Vector3::zero(), look: Vector2::zero(), zoom: 0.0 }
+	}
+}
+
+/// Turns input into a camera position and rotation each tick.
+pub trait CameraController {
+	/// Advances the controller by `dt` seconds.
+	fn update(&mut self, input: &ControllerInput, dt: f32);
+
+	fn position(&self) -> Vector3<f32>;
+
+	fn rotation(&self) -> Quaternion<f32>;
+
+	/// Copies the controller's position and rotation to the camera.
+	fn apply(&self, camera: &mut Camera) {
+		camera.set_position(self.position());
+		camera.set_rotation(self.rotation());
+	}
+}
+
+/// A free-flying camera that moves relative to its heading, like a noclip mode.
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct FlyController {
+	/// Units per second.
+	pub speed: f32,
+	/// Seconds it takes to cover most of the distance to the target position. 0.0 disables smoothing.
+	pub smoothing: f32,
+	target: Vector3<f32>,
+	position: Vector3<f32>,
+	yaw: f32,
+	pitch: f32,
+}
+impl FlyController {
+	pub fn new(position: Vector3<f32>) -> Self {
+		Self { speed: 6.0, smoothing: 0.0, target: position, position: position, yaw: 0.0, pitch: 0.0 }
+	}
+
+	/// Moves the camera immediately, without smoothing.
+	pub fn set_position(&mut self, position: Vector3<f32>) {
+		self.target = position;
+		self.position = position;
+	}
+}
+impl CameraController for FlyController {
+	fn update(&mut self, input: &ControllerInput, dt: f32) {
+		turn(&mut self.yaw, &mut self.pitch, input.look);
+
+		let movement = input.movement * self.speed * dt;
+		self.target += heading(self.yaw).rotate_vector(vec3(movement.x, 0.0, movement.z)) - vec3(0.0, movement.y, 0.0);
+		self.position = self.position.lerp(self.target, smoothing_factor(self.smoothing, dt));
+	}
+
+	fn position(&self) -> Vector3<f32> {
+		self.position
+	}
+
+	fn rotation(&self) -> Quaternion<f32> {
+		look_rotation(self.yaw, self.pitch)
+	}
+}
+
+/// Circles a focus point, for editors and model viewers. Movement pans the focus point in the camera's view plane.
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrbitController {
+	/// Units per second that the focus point pans.
+	pub pan_speed: f32,
+	/// Fraction of the distance covered per unit of zoom input.
+	pub zoom_speed: f32,
+	pub min_distance: f32,
+	pub max_distance: f32,
+	/// Seconds it takes to cover most of the distance to the target distance. 0.0 disables smoothing.
+	pub smoothing: f32,
+	focus: Vector3<f32>,
+	distance: f32,
+	target_distance: f32,
+	yaw: f32,
+	pitch: f32,
+}
+impl OrbitController {
+	pub fn new(focus: Vector3<f32>, distance: f32) -> Self {
+		Self {
+			pan_speed: 2.0,
+			zoom_speed: 0.1,
+			min_distance: 0.1,
+			max_distance: 1000.0,
+			smoothing: 0.1,
+			focus: focus,
+			distance: distance,
+			target_distance: distance,
+			yaw: 0.0,
+			pitch: 0.0,
+		}
+	}
+
+	pub fn set_focus(&mut self, focus: Vector3<f32>) {
+		self.focus = focus;
+	}
+
+	pub fn focus(&self) -> Vector3<f32> {
+		self.focus
+	}
+}
+impl CameraController for OrbitController {
+	fn update(&mut self, input: &ControllerInput, dt: f32) {
+		turn(&mut self.yaw, &mut self.pitch, input.look);
+
+		let pan = input.movement * self.pan_speed * dt;
+		self.focus += self.rotation().rotate_vector(vec3(pan.x, -pan.y, pan.z));
+
+		self.target_distance =
+			(self.target_distance * (1.0 - input.zoom * self.zoom_speed)).max(self.min_distance).min(self.max_distance);
+		self.distance += (self.target_distance - self.distance) * smoothing_factor(self.smoothing, dt);
+	}
+
+	fn position(&self) -> Vector3<f32> {
+		self.focus + self.rotation().rotate_vector(vec3(0.0, 0.0, self.distance))
+	}
+
+	fn rotation(&self) -> Quaternion<f32> {
+		look_rotation(self.yaw, self.pitch)
+	}
+}
+
+/// A third-person camera on a spring arm behind a target. Set the target every tick, then update the controller. The
+/// camera lags behind the arm's end, so sudden moves of the target are softened.
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct FollowController {
+	/// Offset from the target that the arm pivots around, such as the height of a character's head.
+	pub pivot_offset: Vector3<f32>,
+	/// Fraction of the arm length covered per unit of zoom input.
+	pub zoom_speed: f32,
+	pub min_arm_length: f32,
+	pub max_arm_length: f32,
+	/// Seconds it takes the camera to cover most of the distance to the end of the arm. 0.0 makes the arm rigid.
+	pub stiffness: f32,
+	target: Vector3<f32>,
+	arm_length: f32,
+	position: Vector3<f32>,
+	yaw: f32,
+	pitch: f32,
+}
+impl FollowController {
+	pub fn new(target: Vector3<f32>, arm_length: f32) -> Self {
+		let mut controller =
+			Self {
+				pivot_offset: Vector3::zero(),
+				zoom_speed: 0.1,
+				min_arm_length: 0.5,
+				max_arm_length: 50.0,
+				stiffness: 0.15,
+				target: target,
+				arm_length: arm_length,
+				position: target,
+				yaw: 0.0,
+				pitch: 0.0,
+			};
+		controller.position = controller.arm_end();
+		controller
+	}
+
+	pub fn set_target(&mut self, target: Vector3<f32>) {
+		self.target = target;
+	}
+
+	/// The direction the camera faces, ignoring pitch. Useful for moving the target relative to the camera.
+	pub fn heading(&self) -> Quaternion<f32> {
+		heading(self.yaw)
+	}
+
+	fn arm_end(&self) -> Vector3<f32> {
+		self.target + self.pivot_offset + self.rotation().rotate_vector(vec3(0.0, 0.0, self.arm_length))
+	}
+}
+impl CameraController for FollowController {
+	fn update(&mut self, input: &ControllerInput, dt: f32) {
+		turn(&mut self.yaw, &mut self.pitch, input.look);
+
+		self.arm_length =
+			(self.arm_length * (1.0 - input.zoom * self.zoom_speed)).max(self.min_arm_length).min(self.max_arm_length);
+		self.position = self.position.lerp(self.arm_end(), smoothing_factor(self.stiffness, dt));
+	}
+
+	fn position(&self) -> Vector3<f32> {
+		self.position
+	}
+
+	fn rotation(&self) -> Quaternion<f32> {
+		look_rotation(self.yaw, self.pitch)
+	}
+}
+
+/// Applies look input, keeping yaw in -PI..PI and pitch short of straight up or down.
+fn turn(yaw: &mut f32, pitch: &mut f32, look: Vector2<f32>) {
+	*yaw -= look.x;
+	if *yaw > PI {
+		*yaw -= 2.0 * PI;
+	} else if *yaw < -PI {
+		*yaw += 2.0 * PI;
+	}
+
+	*pitch = (*pitch + look.y).max(-PI / 2.0).min(PI / 2.0);
+}
+
+fn heading(yaw: f32) -> Quaternion<f32> {
+	Quaternion::from_angle_y(Rad(yaw))
+}
+
+fn look_rotation(yaw: f32, pitch: f32) -> Quaternion<f32> {
+	heading(yaw) * Quaternion::from_angle_x(Rad(pitch))
+}
+
+/// How far to move toward a target this tick, for smoothing that doesn't depend on the frame rate.
+fn smoothing_factor(smoothing: f32, dt: f32) -> f32 {
+	if smoothing <= 0.0 { 1.0 } else { 1.0 - (-dt / smoothing).exp() }
+}
@@ -0,0 +1,108 @@
+use std::{ ffi::CString, mem::transmute, os::raw::{ c_char, c_void }, ptr, sync::Arc };
+use vulkano::{
+	VulkanObject,
+	buffer::BufferAccess,
+	command_buffer::CommandBuffer,
+	device::Device,
+	image::ImageViewAccess,
+	instance::{ Instance, loader::auto_loader },
+	pipeline::{ ComputePipelineAbstract, GraphicsPipelineAbstract },
+};
+
+pub(crate) const DEBUG_UTILS_EXTENSION: &str = "VK_EXT_debug_utils";
+
+const STRUCTURE_TYPE_DEBUG_UTILS_OBJECT_NAME_INFO: u32 = 1000128000;
+const OBJECT_TYPE_DEVICE: u32 = 3;
+const OBJECT_TYPE_COMMAND_BUFFER: u32 = 6;
+const OBJECT_TYPE_BUFFER: u32 = 9;
+const OBJECT_TYPE_IMAGE: u32 = 10;
+const OBJECT_TYPE_PIPELINE: u32 = 19;
+
+#[repr(C)]
+struct DebugUtilsObjectNameInfo {
+	s_type: u32,
+	p_next: *const c_void,
+	object_type: u32,
+	object_handle: u64,
+	p_object_name: *const c_char,
+}
+
+type SetDebugUtilsObjectName = extern "system" fn(device: usize, info: *const DebugUtilsObjectNameInfo) -> i32;
+/// The real signature of `vkGetInstanceProcAddr`, whose result is null for functions that aren't available. The
+/// loader's own wrapper returns a non-nullable function pointer, so it's only used to look up this function, which
+/// always exists.
+type GetInstanceProcAddr = extern "system" fn(instance: usize, name: *const c_char) -> Option<extern "system" fn()>;
+
+/// Gives Vulkan objects names that show up in graphics debuggers like RenderDoc. Does nothing unless the context was
+/// created with `ContextOptions::debug_names`, or if the driver doesn't provide `VK_EXT_debug_utils`.
+///
+/// Only objects are named. Labeled regions around the mesh batch's subpasses aren't supported: they would need
+/// `vkCmdBeginDebugUtilsLabelEXT` recorded between vulkano's commands, inside the render pass, and vulkano 0.11's
+/// `AutoCommandBufferBuilder` has no way to record a raw command or expose its command buffer while recording. Until
+/// it does, each batch's command buffer is named as a whole, and draws can be told apart in a capture by their named
+/// pipelines, which each belong to a single subpass.
+pub(crate) struct DebugNames {
+	device: Arc<Device>,
+	set_object_name: Option<SetDebugUtilsObjectName>,
+}
+impl DebugNames {
+	/// `enabled` must only be true if `VK_EXT_debug_utils` was enabled on the instance.
+	pub(crate) fn new(instance: &Instance, device: Arc<Device>, enabled: bool) -> Self {
+		let set_object_name =
+			if enabled {
+				auto_loader().ok().and_then(|loader| {
+					let get_proc_name = CString::new("vkGetInstanceProcAddr").unwrap();
+					let get_proc = loader.get_instance_proc_addr(instance.internal_object(), get_proc_name.as_ptr());
+					let get_proc = unsafe { transmute::<_, GetInstanceProcAddr>(get_proc) };
+
+					// stays None if the extension isn't supported, rather than becoming a null function pointer
+					let name = CString::new("vkSetDebugUtilsObjectNameEXT").unwrap();
+					get_proc(instance.internal_object(), name.as_ptr())
+						.map(|func| unsafe { transmute::<_, SetDebugUtilsObjectName>(func) })
+				})
+			} else {
+				None
+			};
+
+		Self { device: device, set_object_name: set_object_name }
+	}
+
+	pub(crate) fn name_device(&self, name: &str) {
+		self.set_name(OBJECT_TYPE_DEVICE, self.device.internal_object() as u64, name);
+	}
+
+	pub(crate) fn name_buffer(&self, buffer: &BufferAccess, name: &str) {
+		self.set_name(OBJECT_TYPE_BUFFER, buffer.inner().buffer.internal_object(), name);
+	}
+
+	pub(crate) fn name_image(&self, image: &ImageViewAccess, name: &str) {
+		self.set_name(OBJECT_TYPE_IMAGE, image.parent().inner().image.internal_object(), name);
+	}
+
+	pub(crate) fn name_pipeline(&self, pipeline: &GraphicsPipelineAbstract, name: &str) {
+		self.set_name(OBJECT_TYPE_PIPELINE, pipeline.inner().internal_object(), name);
+	}
+
+	pub(crate) fn name_compute_pipeline(&self, pipeline: &ComputePipelineAbstract, name: &str) {
+		self.set_name(OBJECT_TYPE_PIPELINE, pipeline.inner().internal_object(), name);
+	}
+
+	pub(crate) fn name_command_buffer(&self, command_buffer: &impl CommandBuffer, name: &str) {
+		self.set_name(OBJECT_TYPE_COMMAND_BUFFER, command_buffer.inner().internal_object() as u64, name);
+	}
+
+	fn set_name(&self, object_type: u32, object_handle: u64, name: &str) {
+		if let Some(set_object_name) = self.set_object_name {
+			let name = CString::new(name.replace('\0', "")).unwrap();
+			let info =
+				DebugUtilsObjectNameInfo {
+					s_type: STRUCTURE_TYPE_DEBUG_UTILS_OBJECT_NAME_INFO,
+					p_next: ptr::null(),
+					object_type: object_type,
+					object_handle: object_handle,
+					p_object_name: name.as_ptr(),
+				};
+			set_object_name(self.device.internal_object(), &info);
+		}
+	}
+}
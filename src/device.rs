@@ -1,12 +1,60 @@
+mod deferred;
+mod features;
+mod memory;
+mod reflection;
+mod render_stats;
+mod transient;
+#[cfg(feature = "shader-compiler")]
+mod shader_compiler;
+
+pub use self::features::RequestedFeatures;
+pub use self::memory::{ AssetMemory, MemoryCategory, MemoryStats };
+pub use self::reflection::{
+	BlockLayout,
+	BlockMember,
+	DescriptorKind,
+	ParamType,
+	ReflectedDescriptor,
+	ShaderLoadError,
+	ShaderParam,
+	ShaderParams,
+	ShaderReflection,
+};
+pub use self::render_stats::{ DrawCounts, RenderStats };
+#[cfg(feature = "shader-compiler")]
+pub use self::shader_compiler::{ ShaderCompileError, ShaderCompiler, ShaderKind };
+pub(crate) use self::memory::{ MemoryHandle, image_bytes };
+pub(crate) use self::reflection::{ MaterialLayout, MaterialSet };
+pub(crate) use self::transient::{ TransientAttachment, TransientUsage };
+use self::deferred::DeferredDrops;
+use self::transient::TransientAttachments;
+
 use crate::batch::sprite::Font;
+use crate::debug::DebugNames;
+use crate::diagnostics::DeviceDiagnostics;
+use crate::texture::TextureCompression;
 use decorum::R32;
-use std::{ collections::HashMap, fs, io, path::{ Path, PathBuf }, sync::{ Arc, Mutex, Weak } };
-use vulkano::device::{ Device, Queue };
+use std::{ any::Any, collections::HashMap, fs, io, path::{ Path, PathBuf }, sync::{ Arc, Mutex, Weak } };
+use vulkano::{
+	device::{ Device, Queue },
+	format::Format,
+	image::ImageCreationError,
+	instance::Features,
+};
 
 pub struct DeviceCtx {
 	device: Arc<Device>,
 	queue: Arc<Queue>,
 	fonts: Mutex<HashMap<(PathBuf, R32), Weak<Font>>>,
+	memory: Arc<MemoryStats>,
+	render_stats: RenderStats,
+	debug_names: DebugNames,
+	deferred_drops: DeferredDrops,
+	transient_attachments: TransientAttachments,
+	texture_compression: Mutex<TextureCompression>,
+	shader_path: Option<ShaderPath>,
+	#[cfg(feature = "shader-compiler")]
+	shader_compiler: ShaderCompiler,
 }
 impl DeviceCtx {
 	pub fn get_font<P: AsRef<Path>>(&self, path: P, scale: f32) -> Result<Arc<Font>, io::Error> {
@@ -18,7 +66,7 @@ impl DeviceCtx {
 			.and_then(|font| font.upgrade())
 			.map(|font| Ok(font))
 			.unwrap_or_else(|| {
-				let ret = Font::from_file(self.queue.clone(), &path_scale.0, scale);
+				let ret = Font::from_file(self.queue.clone(), &self.memory, &path_scale.0, scale);
 				if let Ok(ret) = &ret {
 					fonts.insert(path_scale, Arc::downgrade(ret));
 				}
@@ -26,8 +74,26 @@ impl DeviceCtx {
 			})
 	}
 
-	pub(crate) fn new(device: Arc<Device>, queue: Arc<Queue>) -> Arc<Self> {
-		Arc::new(Self { device: device, queue: queue, fonts: Mutex::default() })
+	pub(crate) fn new(
+		device: Arc<Device>,
+		queue: Arc<Queue>,
+		debug_names: DebugNames,
+		shader_path: Option<ShaderPath>,
+	) -> Arc<Self> {
+		Arc::new(Self {
+			device: device,
+			queue: queue,
+			fonts: Mutex::default(),
+			memory: Arc::default(),
+			render_stats: RenderStats::default(),
+			debug_names: debug_names,
+			deferred_drops: DeferredDrops::default(),
+			transient_attachments: TransientAttachments::default(),
+			texture_compression: Mutex::default(),
+			shader_path: shader_path,
+			#[cfg(feature = "shader-compiler")]
+			shader_compiler: ShaderCompiler::new(),
+		})
 	}
 
 	pub(crate) fn device(&self) -> &Arc<Device> {
@@ -37,4 +103,102 @@ impl DeviceCtx {
 	pub fn queue(&self) -> &Arc<Queue> {
 		&self.queue
 	}
+
+	/// The features that were enabled when the device was created, including optional features that turned out to be
+	/// supported.
+	pub fn enabled_features(&self) -> &Features {
+		self.device.enabled_features()
+	}
+
+	pub(crate) fn debug_names(&self) -> &DebugNames {
+		&self.debug_names
+	}
+
+	/// Keeps `resource` alive until every frame submitted so far has finished on the GPU, then drops it. Use it for
+	/// buffers, images, and descriptor sets that are replaced or removed while recorded frames might still use them.
+	pub fn defer_destroy(&self, resource: impl Any + Send) {
+		self.deferred_drops.push(resource);
+	}
+
+	/// Where windows report when frames are submitted and when they finish, for `defer_destroy`.
+	pub(crate) fn deferred_drops(&self) -> &DeferredDrops {
+		&self.deferred_drops
+	}
+
+	/// An attachment that's only needed within one command buffer. If `shared` is true, it's shared with every other
+	/// batch on the device that asks for a shared attachment with the same role, size, and format.
+	pub(crate) fn transient_attachment(
+		&self,
+		role: &'static str,
+		dimensions: [u32; 2],
+		format: Format,
+		usage: TransientUsage,
+		shared: bool,
+	) -> Result<Arc<TransientAttachment>, ImageCreationError> {
+		self.transient_attachments.get(
+			&self.device,
+			&self.memory,
+			&self.debug_names,
+			role,
+			dimensions,
+			format,
+			usage,
+			shared,
+		)
+	}
+
+	/// Whether textures loaded from now on are compressed at load time. The default is `TextureCompression::None`.
+	pub fn set_texture_compression(&self, compression: TextureCompression) {
+		*self.texture_compression.lock().unwrap() = compression;
+	}
+
+	pub fn texture_compression(&self) -> TextureCompression {
+		*self.texture_compression.lock().unwrap()
+	}
+
+	/// Where replacements for the engine's shaders are looked for. See `ContextOptions::shader_path`.
+	pub fn shader_path(&self) -> Option<&Path> {
+		self.shader_path.as_ref().map(|path| path.0.as_path())
+	}
+
+	/// GPU memory allocated on this device, by category and by asset.
+	pub fn memory_stats(&self) -> &Arc<MemoryStats> {
+		&self.memory
+	}
+
+	/// The chosen device, its queue families, limits, and features, for bug reports.
+	pub fn diagnostics(&self) -> DeviceDiagnostics {
+		DeviceDiagnostics::new(self)
+	}
+
+	/// Draw calls and triangles recorded by the batches on this device.
+	pub fn render_stats(&self) -> &RenderStats {
+		&self.render_stats
+	}
+
+	#[cfg(feature = "shader-compiler")]
+	pub fn shader_compiler(&self) -> &ShaderCompiler {
+		&self.shader_compiler
+	}
+}
+
+/// A directory of SPIR-V files that replace the engine's built-in mesh shaders, named like `fs_history.spv`, so a game
+/// or its mods can change how the deferred renderer shades without recompiling the engine. Relative paths are looked
+/// up through the `vfs` mounts.
+#[derive(Clone, Debug)]
+pub struct ShaderPath(PathBuf);
+impl ShaderPath {
+	/// # Safety
+	///
+	/// Replacements are only checked against the built-in shaders' descriptors and push constant ranges. Their vertex
+	/// inputs, outputs, and push constant members must match the built-in shaders' exactly, or drawing with them is
+	/// undefined behavior. Only point this at shaders you trust as much as the game's own code, and keep in mind that
+	/// relative paths can be filled in by any mounted archive.
+	pub unsafe fn new(path: impl Into<PathBuf>) -> Self {
+		ShaderPath(path.into())
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.0
+	}
 }
@@ -0,0 +1,51 @@
+use std::{ any::Any, collections::{ BTreeSet, VecDeque }, sync::Mutex };
+
+/// Holds GPU resources that were released while frames that might use them were still in flight, and drops them once
+/// those frames finish. Windows report when each frame is submitted and when its fence is signaled.
+#[derive(Default)]
+pub(crate) struct DeferredDrops {
+	state: Mutex<DeferredState>,
+}
+impl DeferredDrops {
+	/// Drops `value` once every frame submitted so far has finished, or right away if none are in flight.
+	pub(crate) fn push(&self, value: impl Any + Send) {
+		let mut state = self.state.lock().unwrap();
+		if state.in_flight.is_empty() {
+			return;
+		}
+
+		let frame = state.next_frame;
+		state.values.push_back((frame, Box::new(value)));
+	}
+
+	/// Called as a frame is submitted. Returns the id to pass to `end_frame` once its fence is signaled.
+	pub(crate) fn begin_frame(&self) -> u64 {
+		let mut state = self.state.lock().unwrap();
+		let frame = state.next_frame;
+		state.next_frame += 1;
+		state.in_flight.insert(frame);
+		frame
+	}
+
+	pub(crate) fn end_frame(&self, frame: u64) {
+		let released = {
+			let mut state = self.state.lock().unwrap();
+			state.in_flight.remove(&frame);
+
+			// values wait for every frame before the one that was next when they were pushed
+			let oldest = state.in_flight.iter().next().cloned().unwrap_or(state.next_frame);
+			let count = state.values.iter().take_while(|&&(pushed, _)| pushed <= oldest).count();
+			state.values.drain(..count).collect::<Vec<_>>()
+		};
+
+		// dropped outside the lock, since dropping a resource can release others
+		drop(released);
+	}
+}
+
+#[derive(Default)]
+struct DeferredState {
+	next_frame: u64,
+	in_flight: BTreeSet<u64>,
+	values: VecDeque<(u64, Box<Any + Send>)>,
+}
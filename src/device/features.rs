@@ -0,0 +1,73 @@
+use vulkano::instance::Features;
+
+/// The device features to enable, split into features the application can't run without and features it can use when
+/// they're available. Each feature is named for logging, and set by a function such as
+/// `|f| f.fill_mode_non_solid = true`.
+#[derive(Clone, Debug)]
+pub struct RequestedFeatures {
+	required: Vec<(&'static str, fn(&mut Features))>,
+	optional: Vec<(&'static str, fn(&mut Features))>,
+}
+impl RequestedFeatures {
+	/// Requests nothing, not even the features the engine uses by default.
+	pub fn none() -> Self {
+		Self { required: vec![], optional: vec![] }
+	}
+
+	/// Only devices that support the feature are used.
+	pub fn require(mut self, name: &'static str, set: fn(&mut Features)) -> Self {
+		self.required.push((name, set));
+		self
+	}
+
+	/// Enables the feature if the device supports it. Use `DeviceCtx::enabled_features` to check at runtime.
+	pub fn request(mut self, name: &'static str, set: fn(&mut Features)) -> Self {
+		self.optional.push((name, set));
+		self
+	}
+
+	pub(crate) fn is_supported_by(&self, supported: &Features) -> bool {
+		self.required.iter().all(|(_, set)| supported.superset_of(&feature(*set)))
+	}
+
+	/// Returns the required features plus every optional feature that's supported, and the names of the optional
+	/// features that aren't.
+	pub(crate) fn negotiate(&self, supported: &Features) -> (Features, Vec<&'static str>) {
+		let mut enabled = Features::none();
+		for (_, set) in &self.required {
+			set(&mut enabled);
+		}
+
+		let mut missing = vec![];
+		for (name, set) in &self.optional {
+			if supported.superset_of(&feature(*set)) {
+				set(&mut enabled);
+			} else {
+				missing.push(*name);
+			}
+		}
+
+		(enabled, missing)
+	}
+}
+impl Default for RequestedFeatures {
+	/// The features the engine uses when they're available.
+	fn default() -> Self {
+		Self::none()
+			.request("sampler_anisotropy", |f| f.sampler_anisotropy = true)
+			.request(
+				"shader_sampled_image_array_dynamic_indexing",
+				|f| f.shader_sampled_image_array_dynamic_indexing = true,
+			)
+			.request("multi_draw_indirect", |f| f.multi_draw_indirect = true)
+			.request("draw_indirect_first_instance", |f| f.draw_indirect_first_instance = true)
+			.request("texture_compression_bc", |f| f.texture_compression_bc = true)
+			.request("texture_compression_etc2", |f| f.texture_compression_etc2 = true)
+	}
+}
+
+fn feature(set: fn(&mut Features)) -> Features {
+	let mut features = Features::none();
+	set(&mut features);
+	features
+}
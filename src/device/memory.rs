@@ -0,0 +1,90 @@
+use crate::texture::block_bytes;
+use std::{ collections::HashMap, sync::{ Arc, Mutex, Weak } };
+use vulkano::format::Format;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MemoryCategory {
+	Meshes,
+	Textures,
+	Fonts,
+	Attachments,
+	Buffers,
+}
+
+/// Tracks how much GPU memory the engine has allocated on a device, and what for. Sizes are the sizes of the buffers and
+/// images themselves, so driver padding and alignment are not included.
+#[derive(Default)]
+pub struct MemoryStats {
+	allocations: Mutex<Allocations>,
+}
+impl MemoryStats {
+	pub fn total(&self) -> u64 {
+		self.allocations.lock().unwrap().entries.values().map(|asset| asset.bytes).sum()
+	}
+
+	pub fn category_total(&self, category: MemoryCategory) -> u64 {
+		self.allocations.lock().unwrap().entries.values()
+			.filter(|asset| asset.category == category)
+			.map(|asset| asset.bytes)
+			.sum()
+	}
+
+	/// Returns every live asset, largest first.
+	pub fn breakdown(&self) -> Vec<AssetMemory> {
+		let mut assets = self.allocations.lock().unwrap().entries.values().cloned().collect::<Vec<_>>();
+		assets.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+		assets
+	}
+
+	/// Records an allocation. It is removed from the stats when the returned handle is dropped.
+	pub(crate) fn track(self: &Arc<Self>, category: MemoryCategory, name: impl Into<String>, bytes: u64) -> MemoryHandle {
+		let mut allocations = self.allocations.lock().unwrap();
+		let id = allocations.next_id;
+		allocations.next_id += 1;
+		allocations.entries.insert(id, AssetMemory { category: category, name: name.into(), bytes: bytes });
+
+		MemoryHandle { stats: Arc::downgrade(self), id: id }
+	}
+}
+
+#[derive(Default)]
+struct Allocations {
+	next_id: u64,
+	entries: HashMap<u64, AssetMemory>,
+}
+
+#[derive(Clone, Debug)]
+pub struct AssetMemory {
+	pub category: MemoryCategory,
+	pub name: String,
+	pub bytes: u64,
+}
+
+pub(crate) struct MemoryHandle {
+	stats: Weak<MemoryStats>,
+	id: u64,
+}
+impl MemoryHandle {
+	/// Adds to the size of the allocation, for assets that grow over time.
+	pub(crate) fn add_bytes(&self, bytes: u64) {
+		if let Some(stats) = self.stats.upgrade() {
+			if let Some(asset) = stats.allocations.lock().unwrap().entries.get_mut(&self.id) {
+				asset.bytes += bytes;
+			}
+		}
+	}
+}
+impl Drop for MemoryHandle {
+	fn drop(&mut self) {
+		if let Some(stats) = self.stats.upgrade() {
+			stats.allocations.lock().unwrap().entries.remove(&self.id);
+		}
+	}
+}
+
+pub(crate) fn image_bytes(dimensions: [u32; 2], format: Format) -> u64 {
+	match block_bytes(format) {
+		Some(bytes) => ((dimensions[0] as u64 + 3) / 4) * ((dimensions[1] as u64 + 3) / 4) * bytes,
+		None => dimensions[0] as u64 * dimensions[1] as u64 * format.size().unwrap_or(0) as u64,
+	}
+}
@@ -0,0 +1,851 @@
+use crate::uniform::Uniform;
+use cgmath::{ Matrix4, Vector2, Vector3, Vector4 };
+use std::{ collections::HashMap, error::Error, fmt, iter, mem::size_of, slice, sync::Arc };
+use vulkano::{
+	OomError,
+	buffer::{ BufferAccess, BufferUsage, CpuBufferPool, cpu_pool::CpuBufferPoolChunk },
+	descriptor::{
+		DescriptorSet,
+		PipelineLayoutAbstract,
+		descriptor::{
+			DescriptorBufferDesc,
+			DescriptorDesc,
+			DescriptorDescTy,
+			DescriptorImageDesc,
+			DescriptorImageDescArray,
+			DescriptorImageDescDimensions,
+			ShaderStages,
+		},
+		descriptor_set::{
+			DescriptorPool,
+			DescriptorPoolAlloc,
+			DescriptorSetDesc,
+			DescriptorWrite,
+			StdDescriptorPoolAlloc,
+			UnsafeDescriptorSet,
+			UnsafeDescriptorSetLayout,
+		},
+		pipeline_layout::{ PipelineLayoutDesc, PipelineLayoutDescPcRange },
+	},
+	device::{ Device, DeviceOwned },
+	image::ImageViewAccess,
+	memory::{ DeviceMemoryAllocError, pool::StdMemoryPool },
+	pipeline::GraphicsPipelineAbstract,
+	sampler::Sampler,
+};
+
+const MAGIC: u32 = 0x0723_0203;
+
+const OP_NAME: u32 = 5;
+const OP_MEMBER_NAME: u32 = 6;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+/// The descriptors and push constants a SPIR-V module declares, read from the module itself. Custom shaders are checked
+/// against the engine's layouts with this when they're loaded, so a shader that declares a binding the engine doesn't
+/// provide fails with an error instead of drawing garbage, and the descriptor set their material owns is laid out the
+/// way the shader declares it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShaderReflection {
+	descriptors: Vec<ReflectedDescriptor>,
+	push_constants: Option<BlockLayout>,
+}
+impl ShaderReflection {
+	pub fn new(spirv: &[u8]) -> Result<Self, ShaderLoadError> {
+		if spirv.len() % 4 != 0 || spirv.len() < 20 {
+			return Err(ShaderLoadError::InvalidSpirv("the module is truncated".to_owned()));
+		}
+		let mut words =
+			spirv.chunks(4)
+				.map(|w| w[0] as u32 | (w[1] as u32) << 8 | (w[2] as u32) << 16 | (w[3] as u32) << 24)
+				.collect::<Vec<_>>();
+		if words[0] == MAGIC.swap_bytes() {
+			words.iter_mut().for_each(|word| *word = word.swap_bytes());
+		} else if words[0] != MAGIC {
+			return Err(ShaderLoadError::InvalidSpirv("the magic number is wrong".to_owned()));
+		}
+
+		Module::parse(&words[5..])?.reflect()
+	}
+
+	/// Every descriptor the shader declares, sorted by set and binding.
+	pub fn descriptors(&self) -> &[ReflectedDescriptor] {
+		&self.descriptors
+	}
+
+	pub fn descriptor(&self, set: u32, binding: u32) -> Option<&ReflectedDescriptor> {
+		self.descriptors.iter().find(|desc| desc.set == set && desc.binding == binding)
+	}
+
+	/// The shader's push constant block, if it has one.
+	pub fn push_constants(&self) -> Option<&BlockLayout> {
+		self.push_constants.as_ref()
+	}
+
+	/// Checks that every descriptor the shader declares is in `layout` with the same type, and that its push constants
+	/// fit in the layout's first range.
+	pub(crate) fn check_layout(&self, layout: &impl PipelineLayoutDesc) -> Result<(), ShaderLoadError> {
+		for desc in &self.descriptors {
+			let expected =
+				layout.descriptor(desc.set as usize, desc.binding as usize)
+					.and_then(|expected| DescriptorKind::of(&expected.ty).map(|kind| (kind, expected.array_count)));
+			let (kind, array_count) =
+				match expected {
+					Some(expected) => expected,
+					None => return Err(ShaderLoadError::UnknownDescriptor(desc.clone())),
+				};
+			if kind != desc.kind {
+				return Err(ShaderLoadError::DescriptorMismatch { descriptor: desc.clone(), expected: kind });
+			}
+			if desc.array_count > array_count {
+				return Err(ShaderLoadError::ArrayTooLarge { descriptor: desc.clone(), max: array_count });
+			}
+		}
+
+		if let Some(push_constants) = &self.push_constants {
+			let max = layout.push_constants_range(0).map_or(0, |range| range.size as u32);
+			if push_constants.size > max {
+				return Err(ShaderLoadError::PushConstantsTooLarge { size: push_constants.size, max: max });
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Builds the pipeline layout for a custom material shader. The sets the engine binds are taken from `engine`, after
+	/// checking the shader against them like `check_layout`. Set `set` belongs to the material and is laid out the way
+	/// the shader declares it, with its `Params` block at binding 0 and a texture at each other binding it uses.
+	pub(crate) fn material_layout<L: PipelineLayoutDesc>(
+		&self,
+		engine: L,
+		set: u32,
+		stages: ShaderStages,
+	) -> Result<MaterialLayout<L>, ShaderLoadError> {
+		let (own, engine_descriptors): (Vec<_>, Vec<_>) =
+			self.descriptors.iter().cloned().partition(|desc| desc.set == set);
+		let engine_reflection =
+			ShaderReflection { descriptors: engine_descriptors, push_constants: self.push_constants.clone() };
+		engine_reflection.check_layout(&engine)?;
+
+		// the engine always binds the params buffer, so it's in the layout even if the shader doesn't use it
+		let params =
+			DescriptorDesc {
+				ty: DescriptorDescTy::Buffer(DescriptorBufferDesc { dynamic: Some(false), storage: false }),
+				array_count: 1,
+				stages: stages.clone(),
+				readonly: true,
+			};
+		let mut material = vec![Some(params)];
+		for desc in own {
+			match (desc.binding, desc.kind) {
+				(0, DescriptorKind::UniformBuffer) => continue,
+				(1 ..= MAX_MATERIAL_BINDING, DescriptorKind::CombinedImageSampler) => (),
+				_ => return Err(ShaderLoadError::UnsupportedMaterialDescriptor(desc)),
+			}
+			if desc.array_count > 1 {
+				return Err(ShaderLoadError::ArrayTooLarge { descriptor: desc, max: 1 });
+			}
+
+			let binding = desc.binding as usize;
+			if material.len() <= binding {
+				material.resize(binding + 1, None);
+			}
+			material[binding] =
+				Some(DescriptorDesc {
+					ty: DescriptorDescTy::CombinedImageSampler(DescriptorImageDesc {
+						sampled: true,
+						dimensions: DescriptorImageDescDimensions::TwoDimensional,
+						format: None,
+						multisampled: false,
+						array_layers: DescriptorImageDescArray::NonArrayed,
+					}),
+					array_count: 1,
+					stages: stages.clone(),
+					readonly: true,
+				});
+		}
+
+		Ok(MaterialLayout { engine: engine, set: set as usize, material: material })
+	}
+}
+
+/// The highest binding a material's textures can use in its set.
+const MAX_MATERIAL_BINDING: u32 = 16;
+
+/// A custom material shader's pipeline layout, from `ShaderReflection::material_layout`.
+#[derive(Clone, Debug)]
+pub(crate) struct MaterialLayout<L> {
+	engine: L,
+	set: usize,
+	/// The material's set, by binding.
+	material: Vec<Option<DescriptorDesc>>,
+}
+unsafe impl<L: PipelineLayoutDesc> PipelineLayoutDesc for MaterialLayout<L> {
+	fn num_sets(&self) -> usize {
+		self.engine.num_sets().max(self.set + 1)
+	}
+
+	fn num_bindings_in_set(&self, set: usize) -> Option<usize> {
+		if set == self.set { Some(self.material.len()) } else { self.engine.num_bindings_in_set(set) }
+	}
+
+	fn descriptor(&self, set: usize, binding: usize) -> Option<DescriptorDesc> {
+		if set == self.set {
+			self.material.get(binding).cloned().and_then(|desc| desc)
+		} else {
+			self.engine.descriptor(set, binding)
+		}
+	}
+
+	fn num_push_constants_ranges(&self) -> usize {
+		self.engine.num_push_constants_ranges()
+	}
+
+	fn push_constants_range(&self, num: usize) -> Option<PipelineLayoutDescPcRange> {
+		self.engine.push_constants_range(num)
+	}
+}
+
+/// A descriptor declared by a shader.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReflectedDescriptor {
+	pub set: u32,
+	pub binding: u32,
+	/// The variable's name, or the block's name for buffers. Empty if the module was stripped of names.
+	pub name: String,
+	pub kind: DescriptorKind,
+	/// 1 unless the descriptor is an array.
+	pub array_count: u32,
+	/// The members of a uniform or storage buffer.
+	pub block: Option<BlockLayout>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DescriptorKind {
+	UniformBuffer,
+	StorageBuffer,
+	/// A `sampler2D` or similar.
+	CombinedImageSampler,
+	/// A `texture2D` or similar, without a sampler.
+	SampledImage,
+	StorageImage,
+	Sampler,
+}
+impl DescriptorKind {
+	fn of(ty: &DescriptorDescTy) -> Option<Self> {
+		match ty {
+			DescriptorDescTy::Sampler => Some(DescriptorKind::Sampler),
+			DescriptorDescTy::CombinedImageSampler(_) => Some(DescriptorKind::CombinedImageSampler),
+			DescriptorDescTy::Image(desc) =>
+				Some(if desc.sampled { DescriptorKind::SampledImage } else { DescriptorKind::StorageImage }),
+			DescriptorDescTy::Buffer(desc) =>
+				Some(if desc.storage { DescriptorKind::StorageBuffer } else { DescriptorKind::UniformBuffer }),
+			_ => None,
+		}
+	}
+}
+
+/// The layout of a uniform, storage, or push constant block, as the shader's compiler laid it out.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlockLayout {
+	/// Bytes, up to the end of the last member.
+	pub size: u32,
+	pub members: Vec<BlockMember>,
+}
+impl BlockLayout {
+	pub fn member(&self, name: &str) -> Option<&BlockMember> {
+		self.members.iter().find(|member| member.name == name)
+	}
+
+	/// Copies `value` into `bytes` where the member named `name` is, growing `bytes` if it's too short. Returns false
+	/// if there's no member with that name and type.
+	pub(crate) fn write_param<T: ShaderParam>(&self, bytes: &mut Vec<u8>, name: &str, value: &T) -> bool {
+		let member =
+			match self.member(name) {
+				Some(member) if member.ty == T::param_type() => member,
+				_ => return false,
+			};
+		let start = member.offset as usize;
+		let end = start + size_of::<T>();
+		if bytes.len() < end {
+			bytes.resize(end, 0);
+		}
+		let value = unsafe { slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+		bytes[start..end].copy_from_slice(value);
+		true
+	}
+}
+
+/// The contents of a custom shader's material set: its `Params` block, which is uploaded with each draw, and its
+/// textures. Custom sprite and mesh materials hand this out with `params_mut`.
+pub struct ShaderParams {
+	layout: Option<BlockLayout>,
+	bytes: Vec<u8>,
+	pool: CpuBufferPool<u8>,
+	textures: Vec<MaterialTexture>,
+	sampler: Arc<Sampler>,
+	set_layout: Arc<UnsafeDescriptorSetLayout>,
+}
+impl ShaderParams {
+	/// `pipeline` must have been created with the shader's `material_layout`. Textures start out as `default_texture`.
+	pub(crate) fn new(
+		pipeline: &Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+		reflection: &ShaderReflection,
+		set: u32,
+		default_texture: &Arc<ImageViewAccess + Send + Sync + 'static>,
+		sampler: Arc<Sampler>,
+	) -> Self {
+		let layout = reflection.descriptor(set, 0).and_then(|desc| desc.block.clone());
+		// a uniform buffer can't be empty, so shaders without parameters still get a few bytes
+		let size = layout.as_ref().map_or(0, |layout| layout.size as usize).max(16);
+		let textures =
+			reflection.descriptors()
+				.iter()
+				.filter(|desc| desc.set == set && desc.kind == DescriptorKind::CombinedImageSampler)
+				.map(|desc| MaterialTexture {
+					binding: desc.binding,
+					name: desc.name.clone(),
+					image: default_texture.clone(),
+				})
+				.collect();
+
+		Self {
+			layout: layout,
+			bytes: vec![0; size],
+			pool: CpuBufferPool::new(pipeline.device().clone(), BufferUsage::uniform_buffer()),
+			textures: textures,
+			sampler: sampler,
+			set_layout: pipeline.descriptor_set_layout(set as usize).expect("missing material set layout").clone(),
+		}
+	}
+
+	/// The layout of the shader's `Params` block, if it has one.
+	pub fn layout(&self) -> Option<&BlockLayout> {
+		self.layout.as_ref()
+	}
+
+	pub fn bytes(&self) -> &[u8] {
+		&self.bytes
+	}
+
+	/// Sets the whole block. `T` must be laid out the way the shader expects, following std140 rules. This is cheap
+	/// enough to call every frame.
+	///
+	/// # Safety
+	///
+	/// `T` must have no padding bytes, since every byte of it is read. Pad it with explicit fields instead, or use
+	/// `set_uniform`, which is safe.
+	pub unsafe fn set<T: Copy + 'static>(&mut self, params: &T) {
+		let bytes = slice::from_raw_parts(params as *const T as *const u8, size_of::<T>());
+		self.bytes.clear();
+		self.bytes.extend_from_slice(bytes);
+	}
+
+	/// Sets the whole block from a struct that writes its fields in the order the block declares them, so the std140
+	/// padding is added for it.
+	pub fn set_uniform(&mut self, params: &impl Uniform) {
+		self.bytes = params.to_std140();
+		let len = self.bytes.len().max(16);
+		self.bytes.resize(len, 0);
+	}
+
+	/// Sets one member by name, at the offset the shader's compiler gave it. Returns false if the block has no member
+	/// with that name and type.
+	pub fn set_param<T: ShaderParam>(&mut self, name: &str, value: T) -> bool {
+		match &self.layout {
+			Some(layout) => layout.write_param(&mut self.bytes, name, &value),
+			None => false,
+		}
+	}
+
+	/// Binds a texture by the name of its `sampler2D` in the shader's material set. Returns false if the shader has no
+	/// such texture.
+	pub fn set_texture(&mut self, name: &str, image: Arc<ImageViewAccess + Send + Sync + 'static>) -> bool {
+		match self.textures.iter_mut().find(|texture| texture.name == name) {
+			Some(texture) => {
+				texture.image = image;
+				true
+			},
+			None => false,
+		}
+	}
+
+	/// The names of the textures the shader declares in its material set.
+	pub fn texture_names(&self) -> impl Iterator<Item = &str> {
+		self.textures.iter().map(|texture| texture.name.as_str())
+	}
+
+	/// Copies the block into a buffer and makes the material's descriptor set for the next draw.
+	pub(crate) fn descriptor_set(&self) -> Result<MaterialSet, OomError> {
+		let params =
+			self.pool.chunk(self.bytes.iter().cloned()).map_err(|err| match err {
+				DeviceMemoryAllocError::OomError(err) => err,
+				_ => OomError::OutOfDeviceMemory,
+			})?;
+
+		let device = self.set_layout.device();
+		let mut inner = device.standard_descriptor_pool().alloc(&self.set_layout)?;
+		unsafe {
+			let textures =
+				self.textures.iter().map(|texture| {
+					DescriptorWrite::combined_image_sampler(texture.binding, 0, &self.sampler, &*texture.image)
+				});
+			inner.inner_mut().write(device, iter::once(DescriptorWrite::uniform_buffer(0, 0, &params)).chain(textures));
+		}
+
+		Ok(MaterialSet {
+			inner: inner,
+			layout: self.set_layout.clone(),
+			params: params,
+			images: self.textures.iter().map(|texture| (texture.image.clone(), texture.binding)).collect(),
+			_sampler: self.sampler.clone(),
+		})
+	}
+}
+
+struct MaterialTexture {
+	binding: u32,
+	name: String,
+	image: Arc<ImageViewAccess + Send + Sync + 'static>,
+}
+
+/// A material's descriptor set for one draw. Like the sprite texture table, it's written directly, since the number of
+/// textures is only known once the shader is loaded.
+pub(crate) struct MaterialSet {
+	inner: StdDescriptorPoolAlloc,
+	layout: Arc<UnsafeDescriptorSetLayout>,
+	params: CpuBufferPoolChunk<u8, Arc<StdMemoryPool>>,
+	images: Vec<(Arc<ImageViewAccess + Send + Sync + 'static>, u32)>,
+	_sampler: Arc<Sampler>,
+}
+unsafe impl DescriptorSet for MaterialSet {
+	fn inner(&self) -> &UnsafeDescriptorSet {
+		self.inner.inner()
+	}
+
+	fn num_buffers(&self) -> usize {
+		1
+	}
+
+	fn buffer(&self, index: usize) -> Option<(&BufferAccess, u32)> {
+		if index == 0 { Some((&self.params as &BufferAccess, 0)) } else { None }
+	}
+
+	fn num_images(&self) -> usize {
+		self.images.len()
+	}
+
+	fn image(&self, index: usize) -> Option<(&ImageViewAccess, u32)> {
+		self.images.get(index).map(|&(ref image, binding)| (&**image as &ImageViewAccess, binding))
+	}
+}
+unsafe impl DescriptorSetDesc for MaterialSet {
+	fn num_bindings(&self) -> usize {
+		self.layout.num_bindings()
+	}
+
+	fn descriptor(&self, binding: usize) -> Option<DescriptorDesc> {
+		self.layout.descriptor(binding)
+	}
+}
+unsafe impl DeviceOwned for MaterialSet {
+	fn device(&self) -> &Arc<Device> {
+		self.layout.device()
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockMember {
+	pub name: String,
+	/// Bytes from the start of the block.
+	pub offset: u32,
+	pub size: u32,
+	pub ty: ParamType,
+}
+
+/// The type of a block member.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParamType {
+	Float,
+	Int,
+	Uint,
+	Bool,
+	Vector(Box<ParamType>, u32),
+	/// A float matrix. Columns are 16 bytes apart in std140 blocks, so a `mat3` takes up as much room as a `mat3x4`.
+	Matrix { columns: u32, rows: u32 },
+	Array(Box<ParamType>, u32),
+	Struct,
+	Other,
+}
+
+/// Rust types that can be copied into a block member of the matching `ParamType`, for setting shader parameters by
+/// name. Values are copied byte for byte, so this is sealed to types without padding.
+pub trait ShaderParam: Copy + 'static + sealed::Sealed {
+	fn param_type() -> ParamType;
+}
+mod sealed {
+	use cgmath::{ Matrix4, Vector2, Vector3, Vector4 };
+
+	pub trait Sealed {}
+	impl Sealed for f32 {}
+	impl Sealed for i32 {}
+	impl Sealed for u32 {}
+	impl Sealed for [f32; 2] {}
+	impl Sealed for [f32; 3] {}
+	impl Sealed for [f32; 4] {}
+	impl Sealed for Vector2<f32> {}
+	impl Sealed for Vector3<f32> {}
+	impl Sealed for Vector4<f32> {}
+	impl Sealed for Matrix4<f32> {}
+}
+impl ShaderParam for f32 {
+	fn param_type() -> ParamType {
+		ParamType::Float
+	}
+}
+impl ShaderParam for i32 {
+	fn param_type() -> ParamType {
+		ParamType::Int
+	}
+}
+impl ShaderParam for u32 {
+	fn param_type() -> ParamType {
+		ParamType::Uint
+	}
+}
+impl ShaderParam for [f32; 2] {
+	fn param_type() -> ParamType {
+		ParamType::Vector(Box::new(ParamType::Float), 2)
+	}
+}
+impl ShaderParam for [f32; 3] {
+	fn param_type() -> ParamType {
+		ParamType::Vector(Box::new(ParamType::Float), 3)
+	}
+}
+impl ShaderParam for [f32; 4] {
+	fn param_type() -> ParamType {
+		ParamType::Vector(Box::new(ParamType::Float), 4)
+	}
+}
+impl ShaderParam for Vector2<f32> {
+	fn param_type() -> ParamType {
+		<[f32; 2]>::param_type()
+	}
+}
+impl ShaderParam for Vector3<f32> {
+	fn param_type() -> ParamType {
+		<[f32; 3]>::param_type()
+	}
+}
+impl ShaderParam for Vector4<f32> {
+	fn param_type() -> ParamType {
+		<[f32; 4]>::param_type()
+	}
+}
+impl ShaderParam for Matrix4<f32> {
+	fn param_type() -> ParamType {
+		ParamType::Matrix { columns: 4, rows: 4 }
+	}
+}
+
+#[derive(Debug)]
+pub enum ShaderLoadError {
+	/// The bytes aren't a SPIR-V module the reflection understands.
+	InvalidSpirv(String),
+	/// The shader declares a descriptor the engine doesn't bind.
+	UnknownDescriptor(ReflectedDescriptor),
+	/// The engine binds a different kind of descriptor here.
+	DescriptorMismatch { descriptor: ReflectedDescriptor, expected: DescriptorKind },
+	/// The shader's descriptor array is longer than the one the engine binds.
+	ArrayTooLarge { descriptor: ReflectedDescriptor, max: u32 },
+	PushConstantsTooLarge { size: u32, max: u32 },
+	/// A descriptor in the material's set that materials can't bind. They bind the `Params` uniform block at binding 0
+	/// and a `sampler2D` at each other binding up to 16.
+	UnsupportedMaterialDescriptor(ReflectedDescriptor),
+	OomError(OomError),
+}
+impl fmt::Display for ShaderLoadError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ShaderLoadError::InvalidSpirv(msg) => write!(f, "invalid SPIR-V: {}", msg),
+			ShaderLoadError::UnknownDescriptor(desc) =>
+				write!(f, "`{}` (set {}, binding {}) isn't provided by the engine", desc.name, desc.set, desc.binding),
+			ShaderLoadError::DescriptorMismatch { descriptor: desc, expected } =>
+				write!(
+					f,
+					"`{}` (set {}, binding {}) is a {:?}, but the engine binds a {:?}",
+					desc.name,
+					desc.set,
+					desc.binding,
+					desc.kind,
+					expected,
+				),
+			ShaderLoadError::ArrayTooLarge { descriptor: desc, max } =>
+				write!(
+					f,
+					"`{}` (set {}, binding {}) has {} elements, but the engine binds at most {}",
+					desc.name,
+					desc.set,
+					desc.binding,
+					desc.array_count,
+					max,
+				),
+			ShaderLoadError::PushConstantsTooLarge { size, max } =>
+				write!(f, "push constants are {} bytes, but the engine provides {}", size, max),
+			ShaderLoadError::UnsupportedMaterialDescriptor(desc) =>
+				write!(
+					f,
+					"`{}` (set {}, binding {}) is a {:?}, which materials can't bind there",
+					desc.name,
+					desc.set,
+					desc.binding,
+					desc.kind,
+				),
+			ShaderLoadError::OomError(err) => write!(f, "out of memory: {}", err),
+		}
+	}
+}
+impl Error for ShaderLoadError {
+	fn source(&self) -> Option<&(Error + 'static)> {
+		match self {
+			ShaderLoadError::OomError(err) => Some(err),
+			_ => None,
+		}
+	}
+}
+impl From<OomError> for ShaderLoadError {
+	fn from(val: OomError) -> Self {
+		ShaderLoadError::OomError(val)
+	}
+}
+
+enum Type {
+	Bool,
+	Int { signed: bool },
+	Float,
+	Vector(u32, u32),
+	Matrix(u32, u32),
+	Image { storage: bool },
+	Sampler,
+	SampledImage,
+	Array(u32, u32),
+	RuntimeArray(u32),
+	Struct(Vec<u32>),
+	Pointer(u32, u32),
+}
+
+/// The parts of a module the reflection needs, indexed by result id.
+#[derive(Default)]
+struct Module {
+	names: HashMap<u32, String>,
+	member_names: HashMap<(u32, u32), String>,
+	decorations: HashMap<(u32, u32), u32>,
+	member_decorations: HashMap<(u32, u32, u32), u32>,
+	types: HashMap<u32, Type>,
+	constants: HashMap<u32, u32>,
+	/// The id, pointer type, and storage class of each global variable.
+	variables: Vec<(u32, u32, u32)>,
+}
+impl Module {
+	fn parse(mut words: &[u32]) -> Result<Self, ShaderLoadError> {
+		let mut module = Self::default();
+		while !words.is_empty() {
+			let count = (words[0] >> 16) as usize;
+			if count == 0 || count > words.len() {
+				return Err(ShaderLoadError::InvalidSpirv("an instruction is truncated".to_owned()));
+			}
+			let (op, args) = (words[0] & 0xFFFF, &words[1..count]);
+			words = &words[count..];
+
+			let arg = |i: usize| args.get(i).cloned().unwrap_or(0);
+			match op {
+				OP_NAME => { module.names.insert(arg(0), string(&args[1..])); },
+				OP_MEMBER_NAME => { module.member_names.insert((arg(0), arg(1)), string(&args[2..])); },
+				OP_DECORATE => { module.decorations.insert((arg(0), arg(1)), arg(2)); },
+				OP_MEMBER_DECORATE => { module.member_decorations.insert((arg(0), arg(1), arg(2)), arg(3)); },
+				OP_TYPE_BOOL => { module.types.insert(arg(0), Type::Bool); },
+				OP_TYPE_INT => { module.types.insert(arg(0), Type::Int { signed: arg(2) != 0 }); },
+				OP_TYPE_FLOAT => { module.types.insert(arg(0), Type::Float); },
+				OP_TYPE_VECTOR => { module.types.insert(arg(0), Type::Vector(arg(1), arg(2))); },
+				OP_TYPE_MATRIX => { module.types.insert(arg(0), Type::Matrix(arg(1), arg(2))); },
+				OP_TYPE_IMAGE => { module.types.insert(arg(0), Type::Image { storage: arg(6) == 2 }); },
+				OP_TYPE_SAMPLER => { module.types.insert(arg(0), Type::Sampler); },
+				OP_TYPE_SAMPLED_IMAGE => { module.types.insert(arg(0), Type::SampledImage); },
+				OP_TYPE_ARRAY => { module.types.insert(arg(0), Type::Array(arg(1), arg(2))); },
+				OP_TYPE_RUNTIME_ARRAY => { module.types.insert(arg(0), Type::RuntimeArray(arg(1))); },
+				OP_TYPE_STRUCT => { module.types.insert(arg(0), Type::Struct(args[1..].to_vec())); },
+				OP_TYPE_POINTER => { module.types.insert(arg(0), Type::Pointer(arg(1), arg(2))); },
+				OP_CONSTANT => { module.constants.insert(arg(1), arg(2)); },
+				OP_VARIABLE => module.variables.push((arg(1), arg(0), arg(2))),
+				_ => (),
+			}
+		}
+		Ok(module)
+	}
+
+	fn reflect(&self) -> Result<ShaderReflection, ShaderLoadError> {
+		let mut reflection = ShaderReflection::default();
+		for &(id, pointer, storage) in &self.variables {
+			let ty =
+				match self.types.get(&pointer) {
+					Some(Type::Pointer(_, ty)) => *ty,
+					_ => return Err(ShaderLoadError::InvalidSpirv("a variable's type isn't a pointer".to_owned())),
+				};
+			if storage == STORAGE_PUSH_CONSTANT {
+				reflection.push_constants = Some(self.block(ty));
+				continue;
+			}
+			if storage != STORAGE_UNIFORM_CONSTANT && storage != STORAGE_UNIFORM && storage != STORAGE_STORAGE_BUFFER {
+				continue;
+			}
+
+			let (set, binding) =
+				match (
+					self.decorations.get(&(id, DECORATION_DESCRIPTOR_SET)),
+					self.decorations.get(&(id, DECORATION_BINDING)),
+				) {
+					(Some(&set), Some(&binding)) => (set, binding),
+					_ => continue,
+				};
+
+			// arrays of descriptors are arrays around the descriptor's type
+			let (element, array_count) =
+				match self.types.get(&ty) {
+					Some(Type::Array(element, length)) => (*element, self.constants.get(length).cloned().unwrap_or(1)),
+					Some(Type::RuntimeArray(element)) => (*element, 0),
+					_ => (ty, 1),
+				};
+
+			let (kind, block) =
+				match self.types.get(&element) {
+					Some(Type::Struct(_)) => {
+						let storage_buffer =
+							storage == STORAGE_STORAGE_BUFFER ||
+							self.decorations.contains_key(&(element, DECORATION_BUFFER_BLOCK));
+						let kind =
+							if storage_buffer { DescriptorKind::StorageBuffer } else { DescriptorKind::UniformBuffer };
+						(kind, Some(self.block(element)))
+					},
+					Some(Type::SampledImage) => (DescriptorKind::CombinedImageSampler, None),
+					Some(Type::Image { storage: true }) => (DescriptorKind::StorageImage, None),
+					Some(Type::Image { storage: false }) => (DescriptorKind::SampledImage, None),
+					Some(Type::Sampler) => (DescriptorKind::Sampler, None),
+					_ => continue,
+				};
+			let name =
+				self.names.get(&id).filter(|name| !name.is_empty())
+					.or_else(|| self.names.get(&element))
+					.cloned()
+					.unwrap_or_default();
+
+			reflection.descriptors.push(ReflectedDescriptor {
+				set: set,
+				binding: binding,
+				name: name,
+				kind: kind,
+				array_count: array_count,
+				block: block,
+			});
+		}
+
+		reflection.descriptors.sort_by_key(|desc| (desc.set, desc.binding));
+		Ok(reflection)
+	}
+
+	fn block(&self, ty: u32) -> BlockLayout {
+		let members =
+			match self.types.get(&ty) {
+				Some(Type::Struct(members)) => members,
+				_ => return BlockLayout::default(),
+			};
+
+		let members =
+			members.iter().enumerate()
+				.map(|(i, &member_ty)| {
+					let i = i as u32;
+					let matrix_stride = self.member_decorations.get(&(ty, i, DECORATION_MATRIX_STRIDE)).cloned();
+					BlockMember {
+						name: self.member_names.get(&(ty, i)).cloned().unwrap_or_default(),
+						offset: self.member_decorations.get(&(ty, i, DECORATION_OFFSET)).cloned().unwrap_or(0),
+						size: self.size(member_ty, matrix_stride),
+						ty: self.param_type(member_ty),
+					}
+				})
+				.collect::<Vec<_>>();
+		let size = members.iter().map(|member| member.offset + member.size).max().unwrap_or(0);
+		BlockLayout { size: size, members: members }
+	}
+
+	fn size(&self, ty: u32, matrix_stride: Option<u32>) -> u32 {
+		match self.types.get(&ty) {
+			Some(Type::Bool) | Some(Type::Int { .. }) | Some(Type::Float) => 4,
+			Some(Type::Vector(component, count)) => self.size(*component, None) * count,
+			Some(Type::Matrix(column, count)) =>
+				matrix_stride.unwrap_or_else(|| (self.size(*column, None) + 15) / 16 * 16) * count,
+			Some(Type::Array(element, length)) => {
+				let length = self.constants.get(length).cloned().unwrap_or(0);
+				let stride =
+					self.decorations.get(&(ty, DECORATION_ARRAY_STRIDE)).cloned()
+						.unwrap_or_else(|| self.size(*element, matrix_stride));
+				stride * length
+			},
+			Some(Type::Struct(_)) => self.block(ty).size,
+			_ => 0,
+		}
+	}
+
+	fn param_type(&self, ty: u32) -> ParamType {
+		match self.types.get(&ty) {
+			Some(Type::Bool) => ParamType::Bool,
+			Some(Type::Int { signed: true }) => ParamType::Int,
+			Some(Type::Int { signed: false }) => ParamType::Uint,
+			Some(Type::Float) => ParamType::Float,
+			Some(Type::Vector(component, count)) => ParamType::Vector(Box::new(self.param_type(*component)), *count),
+			Some(Type::Matrix(column, columns)) => {
+				let rows = match self.types.get(column) { Some(Type::Vector(_, rows)) => *rows, _ => 0 };
+				ParamType::Matrix { columns: *columns, rows: rows }
+			},
+			Some(Type::Array(element, length)) =>
+				ParamType::Array(Box::new(self.param_type(*element)), self.constants.get(length).cloned().unwrap_or(0)),
+			Some(Type::Struct(_)) => ParamType::Struct,
+			_ => ParamType::Other,
+		}
+	}
+}
+
+/// Reads a nul-terminated string packed into words.
+fn string(words: &[u32]) -> String {
+	let bytes =
+		words.iter()
+			.flat_map(|word| (0..4).map(move |i| (word >> (i * 8)) as u8))
+			.take_while(|&byte| byte != 0)
+			.collect::<Vec<_>>();
+	String::from_utf8_lossy(&bytes).into_owned()
+}
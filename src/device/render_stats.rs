@@ -0,0 +1,29 @@
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+/// Counts the draws recorded by every batch on a device. Batches record their commands every frame, so taking the
+/// counts once per frame gives per-frame numbers.
+#[derive(Default)]
+pub struct RenderStats {
+	draw_calls: AtomicUsize,
+	triangles: AtomicUsize,
+}
+impl RenderStats {
+	/// Returns the counts since the last call, and resets them.
+	pub fn take(&self) -> DrawCounts {
+		DrawCounts {
+			draw_calls: self.draw_calls.swap(0, Ordering::Relaxed),
+			triangles: self.triangles.swap(0, Ordering::Relaxed),
+		}
+	}
+
+	pub(crate) fn record_draw(&self, triangles: usize) {
+		self.draw_calls.fetch_add(1, Ordering::Relaxed);
+		self.triangles.fetch_add(triangles, Ordering::Relaxed);
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawCounts {
+	pub draw_calls: usize,
+	pub triangles: usize,
+}
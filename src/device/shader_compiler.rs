@@ -0,0 +1,72 @@
+use crate::device::ShaderLoadError;
+use log::{ log, warn };
+use shaderc::{ Compiler, ShaderKind as ShadercKind };
+use std::sync::Mutex;
+use vulkano::OomError;
+
+/// Compiles GLSL to SPIR-V at runtime, so custom shaders can be loaded from source instead of being compiled offline.
+/// Only available with the `shader-compiler` feature.
+pub struct ShaderCompiler {
+	/// Created on first use, since shaderc's setup isn't free and most applications never compile at runtime.
+	compiler: Mutex<Option<Compiler>>,
+}
+impl ShaderCompiler {
+	pub(crate) fn new() -> Self {
+		Self { compiler: Mutex::new(None) }
+	}
+
+	/// Compiles GLSL source with a `main` entry point. `name` is only used in error messages.
+	pub fn compile_glsl(&self, kind: ShaderKind, source: &str, name: &str) -> Result<Vec<u8>, ShaderCompileError> {
+		let mut compiler = self.compiler.lock().unwrap();
+		if compiler.is_none() {
+			*compiler = Some(Compiler::new().ok_or(ShaderCompileError::Unavailable)?);
+		}
+
+		let kind =
+			match kind {
+				ShaderKind::Vertex => ShadercKind::Vertex,
+				ShaderKind::Fragment => ShadercKind::Fragment,
+				ShaderKind::Compute => ShadercKind::Compute,
+			};
+		let artifact =
+			compiler.as_mut().unwrap()
+				.compile_into_spirv(source, kind, name, "main", None)
+				.map_err(|err| ShaderCompileError::Compilation(err.to_string()))?;
+		if artifact.get_num_warnings() > 0 {
+			warn!("{}", artifact.get_warning_messages());
+		}
+
+		Ok(artifact.as_binary_u8().to_vec())
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderKind {
+	Vertex,
+	Fragment,
+	Compute,
+}
+
+#[derive(Debug)]
+pub enum ShaderCompileError {
+	/// shaderc couldn't be initialized.
+	Unavailable,
+	/// The source didn't compile. This holds the compiler's messages.
+	Compilation(String),
+	/// The shader compiled, but doesn't match the interface it was compiled for.
+	Load(ShaderLoadError),
+	OomError(OomError),
+}
+impl From<ShaderLoadError> for ShaderCompileError {
+	fn from(val: ShaderLoadError) -> Self {
+		match val {
+			ShaderLoadError::OomError(err) => ShaderCompileError::OomError(err),
+			err => ShaderCompileError::Load(err),
+		}
+	}
+}
+impl From<OomError> for ShaderCompileError {
+	fn from(val: OomError) -> Self {
+		ShaderCompileError::OomError(val)
+	}
+}
@@ -0,0 +1,107 @@
+use super::{ MemoryCategory, MemoryHandle, MemoryStats, image_bytes };
+use crate::debug::DebugNames;
+use std::sync::{ Arc, Mutex, Weak };
+use vulkano::{ device::Device, format::Format, image::{ AttachmentImage, ImageCreationError } };
+
+/// How a pooled attachment is used. Images made for one usage can't stand in for the other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TransientUsage {
+	/// Only read as an input attachment by the subpass after the one that writes it, so the driver may never give it
+	/// real memory.
+	InputAttachment,
+	/// Sampled by a later render pass in the same command buffer.
+	Sampled,
+}
+
+/// Attachments whose contents are only needed within a single command buffer, like G-buffers and the scratch images
+/// of blur passes. Every batch asking for a shared image with the same role at the same size and format gets the same
+/// image, so several mesh batches drawing to same-sized targets share one set of intermediates instead of each owning
+/// its own.
+///
+/// Nothing here checks that sharing batches run one after another. Their command buffers must be submitted in a
+/// single chain of futures, so the GPU locks vulkano takes on each image are released before the next batch takes
+/// them. That's why sharing is opt in, with `MeshRenderPassOptions::share_transient_attachments`. Images a batch
+/// doesn't share are still made here, so they're counted the same way.
+///
+/// Images aren't aliased in memory. Within one batch every image is either an attachment of the same render pass or
+/// read by the final pass alongside the others, so none of their lifetimes are disjoint. Aliasing across batches would
+/// mean binding several images to one allocation, which vulkano's safe images don't allow, so sharing whole images is
+/// how batches save memory here.
+///
+/// Images are freed, and stop being counted in `MemoryStats`, once the last batch using them drops them.
+#[derive(Default)]
+pub(crate) struct TransientAttachments {
+	images: Mutex<Vec<PooledAttachment>>,
+}
+impl TransientAttachments {
+	pub(crate) fn get(
+		&self,
+		device: &Arc<Device>,
+		memory: &Arc<MemoryStats>,
+		debug_names: &DebugNames,
+		role: &'static str,
+		dimensions: [u32; 2],
+		format: Format,
+		usage: TransientUsage,
+		shared: bool,
+	) -> Result<Arc<TransientAttachment>, ImageCreationError> {
+		let mut images = self.images.lock().unwrap();
+		images.retain(|pooled| pooled.attachment.upgrade().is_some());
+
+		let existing = images.iter()
+			.filter(|pooled| pooled.shared && shared)
+			.filter(|pooled| {
+				pooled.role == role && pooled.dimensions == dimensions && pooled.format == format && pooled.usage == usage
+			})
+			.filter_map(|pooled| pooled.attachment.upgrade())
+			.next();
+		if let Some(attachment) = existing {
+			return Ok(attachment);
+		}
+
+		let image =
+			match usage {
+				TransientUsage::InputAttachment =>
+					AttachmentImage::transient_input_attachment(device.clone(), dimensions, format)?,
+				TransientUsage::Sampled => AttachmentImage::sampled(device.clone(), dimensions, format)?,
+			};
+		debug_names.name_image(&*image, role);
+
+		let attachment =
+			Arc::new(TransientAttachment {
+				image: image,
+				_memory: memory.track(MemoryCategory::Attachments, role, image_bytes(dimensions, format)),
+			});
+		images.push(PooledAttachment {
+			role: role,
+			dimensions: dimensions,
+			format: format,
+			usage: usage,
+			shared: shared,
+			attachment: Arc::downgrade(&attachment),
+		});
+		Ok(attachment)
+	}
+}
+
+/// An image from `TransientAttachments`. Batches keep this for as long as they use the image, and its memory stops
+/// being counted as soon as the last one is dropped.
+pub(crate) struct TransientAttachment {
+	image: Arc<AttachmentImage>,
+	_memory: MemoryHandle,
+}
+impl TransientAttachment {
+	pub(crate) fn image(&self) -> &Arc<AttachmentImage> {
+		&self.image
+	}
+}
+
+struct PooledAttachment {
+	role: &'static str,
+	dimensions: [u32; 2],
+	format: Format,
+	usage: TransientUsage,
+	/// Whether other batches may be given this image.
+	shared: bool,
+	attachment: Weak<TransientAttachment>,
+}
@@ -0,0 +1,133 @@
+use crate::device::DeviceCtx;
+use crate::window::Window;
+use vulkano::{
+	device::DeviceExtensions,
+	format::Format,
+	instance::{ Features, PhysicalDevice, PhysicalDeviceType, Version },
+	swapchain::{ ColorSpace, PresentMode },
+};
+
+/// What the engine chose to run on, and what that device can do. This is logged at debug level when a device is
+/// created, and is meant to be attached to bug reports.
+#[derive(Clone, Debug)]
+pub struct DeviceDiagnostics {
+	pub name: String,
+	pub ty: PhysicalDeviceType,
+	pub api_version: Version,
+	pub driver_version: u32,
+	pub vendor_id: u32,
+	pub device_id: u32,
+	pub queue_families: Vec<QueueFamilyDiagnostics>,
+	/// The queue family the engine submits to.
+	pub queue_family: u32,
+	pub limits: LimitsDiagnostics,
+	pub supported_features: Features,
+	pub enabled_features: Features,
+	pub enabled_extensions: DeviceExtensions,
+}
+impl DeviceDiagnostics {
+	pub(crate) fn new(device: &DeviceCtx) -> Self {
+		let pdevice = device.device().physical_device();
+
+		Self {
+			name: pdevice.name(),
+			ty: pdevice.ty(),
+			api_version: pdevice.api_version(),
+			driver_version: pdevice.driver_version(),
+			vendor_id: pdevice.pci_vendor_id(),
+			device_id: pdevice.pci_device_id(),
+			queue_families: pdevice.queue_families()
+				.map(|family| QueueFamilyDiagnostics {
+					id: family.id(),
+					queues: family.queues_count(),
+					graphics: family.supports_graphics(),
+					compute: family.supports_compute(),
+					transfers: family.explicitly_supports_transfers(),
+					sparse_binding: family.supports_sparse_binding(),
+				})
+				.collect(),
+			queue_family: device.queue().family().id(),
+			limits: LimitsDiagnostics::new(pdevice),
+			supported_features: pdevice.supported_features().clone(),
+			enabled_features: device.device().enabled_features().clone(),
+			enabled_extensions: device.device().loaded_extensions().clone(),
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct QueueFamilyDiagnostics {
+	pub id: u32,
+	pub queues: usize,
+	pub graphics: bool,
+	pub compute: bool,
+	pub transfers: bool,
+	pub sparse_binding: bool,
+}
+
+/// The device limits the engine depends on, or that are likely to explain problems on unusual hardware.
+#[derive(Clone, Debug)]
+pub struct LimitsDiagnostics {
+	pub max_image_dimension_2d: u32,
+	pub max_push_constants_size: u32,
+	pub max_bound_descriptor_sets: u32,
+	pub max_per_stage_descriptor_samplers: u32,
+	pub max_per_stage_descriptor_sampled_images: u32,
+	pub max_descriptor_set_uniform_buffers: u32,
+	pub max_color_attachments: u32,
+	pub max_sampler_anisotropy: f32,
+	pub max_viewports: u32,
+	pub min_uniform_buffer_offset_alignment: usize,
+	pub timestamp_period: f32,
+}
+impl LimitsDiagnostics {
+	fn new(pdevice: PhysicalDevice) -> Self {
+		let limits = pdevice.limits();
+
+		Self {
+			max_image_dimension_2d: limits.max_image_dimension_2d(),
+			max_push_constants_size: limits.max_push_constants_size(),
+			max_bound_descriptor_sets: limits.max_bound_descriptor_sets(),
+			max_per_stage_descriptor_samplers: limits.max_per_stage_descriptor_samplers(),
+			max_per_stage_descriptor_sampled_images: limits.max_per_stage_descriptor_sampled_images(),
+			max_descriptor_set_uniform_buffers: limits.max_descriptor_set_uniform_buffers(),
+			max_color_attachments: limits.max_color_attachments(),
+			max_sampler_anisotropy: limits.max_sampler_anisotropy(),
+			max_viewports: limits.max_viewports(),
+			min_uniform_buffer_offset_alignment: limits.min_uniform_buffer_offset_alignment() as usize,
+			timestamp_period: limits.timestamp_period(),
+		}
+	}
+}
+
+/// A window's device, what its surface supports, and the swapchain configuration that was chosen.
+#[derive(Clone, Debug)]
+pub struct WindowDiagnostics {
+	pub device: DeviceDiagnostics,
+	pub surface_formats: Vec<(Format, ColorSpace)>,
+	pub present_modes: Vec<PresentMode>,
+	pub min_image_count: u32,
+	pub max_image_count: Option<u32>,
+	pub swapchain_format: Format,
+	pub swapchain_dimensions: [u32; 2],
+	pub swapchain_image_count: usize,
+	pub vsync: bool,
+}
+impl WindowDiagnostics {
+	pub(crate) fn new(window: &Window) -> Self {
+		let caps = window.surface_capabilities();
+		let swapchain = window.swapchain();
+
+		Self {
+			device: DeviceDiagnostics::new(window.device()),
+			surface_formats: caps.supported_formats.clone(),
+			present_modes: caps.present_modes.iter().collect(),
+			min_image_count: caps.min_image_count,
+			max_image_count: caps.max_image_count,
+			swapchain_format: swapchain.format(),
+			swapchain_dimensions: swapchain.dimensions(),
+			swapchain_image_count: swapchain.num_images() as usize,
+			vsync: window.vsync(),
+		}
+	}
+}
@@ -0,0 +1,126 @@
+use crate::batch::{ BatchError, mesh::MeshFromFileError, tilemap::TileMapError };
+use crate::scene::SceneError;
+use crate::texture::TextureError;
+use crate::window::WindowError;
+use std::{ error::Error, fmt };
+
+/// Why an asset file couldn't be loaded, for games that handle every kind of asset the same way.
+#[derive(Debug)]
+pub enum AssetError {
+	Texture(TextureError),
+	Mesh(MeshFromFileError),
+	Scene(SceneError),
+	TileMap(TileMapError),
+}
+impl AssetError {
+	/// Whether the load stopped because its `CancelToken` was cancelled, rather than failing.
+	pub fn is_cancelled(&self) -> bool {
+		match self {
+			AssetError::Texture(TextureError::Cancelled) => true,
+			AssetError::Mesh(MeshFromFileError::Cancelled) => true,
+			AssetError::Scene(SceneError::Cancelled) => true,
+			_ => false,
+		}
+	}
+}
+impl fmt::Display for AssetError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			AssetError::Texture(err) => write!(f, "texture load failed: {}", err),
+			AssetError::Mesh(err) => write!(f, "mesh load failed: {}", err),
+			AssetError::Scene(err) => write!(f, "scene load failed: {}", err),
+			AssetError::TileMap(err) => write!(f, "tile map load failed: {}", err),
+		}
+	}
+}
+impl Error for AssetError {
+	fn source(&self) -> Option<&(Error + 'static)> {
+		match self {
+			AssetError::Texture(err) => Some(err),
+			AssetError::Mesh(err) => Some(err),
+			AssetError::Scene(err) => Some(err),
+			AssetError::TileMap(err) => Some(err),
+		}
+	}
+}
+impl From<TextureError> for AssetError {
+	fn from(val: TextureError) -> Self {
+		AssetError::Texture(val)
+	}
+}
+impl From<MeshFromFileError> for AssetError {
+	fn from(val: MeshFromFileError) -> Self {
+		AssetError::Mesh(val)
+	}
+}
+impl From<SceneError> for AssetError {
+	fn from(val: SceneError) -> Self {
+		AssetError::Scene(val)
+	}
+}
+impl From<TileMapError> for AssetError {
+	fn from(val: TileMapError) -> Self {
+		AssetError::TileMap(val)
+	}
+}
+
+/// Any error from the engine, so a game's main loop can use `?` on everything and still match on the cause.
+#[derive(Debug)]
+pub enum NiceGameError {
+	Window(WindowError),
+	Batch(BatchError),
+	Asset(AssetError),
+}
+impl fmt::Display for NiceGameError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			NiceGameError::Window(err) => err.fmt(f),
+			NiceGameError::Batch(err) => err.fmt(f),
+			NiceGameError::Asset(err) => err.fmt(f),
+		}
+	}
+}
+impl Error for NiceGameError {
+	fn source(&self) -> Option<&(Error + 'static)> {
+		match self {
+			NiceGameError::Window(err) => Some(err),
+			NiceGameError::Batch(err) => Some(err),
+			NiceGameError::Asset(err) => Some(err),
+		}
+	}
+}
+impl From<WindowError> for NiceGameError {
+	fn from(val: WindowError) -> Self {
+		NiceGameError::Window(val)
+	}
+}
+impl From<BatchError> for NiceGameError {
+	fn from(val: BatchError) -> Self {
+		NiceGameError::Batch(val)
+	}
+}
+impl From<AssetError> for NiceGameError {
+	fn from(val: AssetError) -> Self {
+		NiceGameError::Asset(val)
+	}
+}
+impl From<TextureError> for NiceGameError {
+	fn from(val: TextureError) -> Self {
+		NiceGameError::Asset(val.into())
+	}
+}
+impl From<MeshFromFileError> for NiceGameError {
+	fn from(val: MeshFromFileError) -> Self {
+		NiceGameError::Asset(val.into())
+	}
+}
+impl From<SceneError> for NiceGameError {
+	fn from(val: SceneError) -> Self {
+		NiceGameError::Asset(val.into())
+	}
+}
+impl From<TileMapError> for NiceGameError {
+	fn from(val: TileMapError) -> Self {
+		NiceGameError::Asset(val.into())
+	}
+}
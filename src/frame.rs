@@ -0,0 +1,137 @@
+use crate::RenderTarget;
+use crate::window::Window;
+use std::{ collections::HashMap, sync::Arc };
+use vulkano::{
+	command_buffer::{ AutoCommandBuffer, CommandBufferExecError },
+	device::Queue,
+	sync::GpuFuture,
+};
+
+/// Collects the command buffers for a single frame, and submits them in an order that satisfies the targets each pass
+/// reads and writes.
+///
+/// This isn't a render graph that records every pass into one command buffer. Batches build their own primary command
+/// buffers, often on other threads, and each one begins and ends its own render passes, which a secondary command
+/// buffer can't do. So each pass stays its own submission, and the passes are chained on the queue with a semaphore
+/// between each one. The semaphore makes the writes of every earlier pass visible to the next, which is what a barrier
+/// between them would do in a single command buffer, at the cost of a submission per pass.
+pub struct Frame {
+	queue: Arc<Queue>,
+	passes: Vec<FramePass>,
+}
+impl Frame {
+	pub fn new(window: &Window) -> Self {
+		Self { queue: window.device().queue().clone(), passes: vec![] }
+	}
+
+	/// Adds a pass to the frame. `commands` is the pair returned by the batches' `commands` methods. Passes that read a
+	/// target are always submitted after every pass that writes it, with a semaphore in between.
+	pub fn add_pass<F>(
+		&mut self,
+		name: &str,
+		commands: (AutoCommandBuffer, Option<F>),
+		reads: &[&RenderTarget],
+		writes: &[&RenderTarget],
+	) -> &mut Self
+	where F: GpuFuture + 'static {
+		let (commands, future) = commands;
+
+		self.passes.push(FramePass {
+			name: name.to_owned(),
+			commands: commands,
+			future: future.map(|future| Box::new(future) as _),
+			reads: reads.iter().map(|target| target.id_root().key()).collect(),
+			writes: writes.iter().map(|target| target.id_root().key()).collect(),
+		});
+
+		self
+	}
+
+	/// Executes the passes after `future` and every pass's own future, one submission per pass in dependency order.
+	/// Passes that don't depend on each other are still chained, so the order they were added in is kept where it can
+	/// be.
+	pub fn submit(self, future: Box<GpuFuture>) -> Result<Box<GpuFuture>, FrameError> {
+		let order = self.order()?;
+		let mut passes = self.passes.into_iter().map(Some).collect::<Vec<_>>();
+
+		let mut future = future;
+		for pass in &mut passes {
+			if let Some(pass_future) = pass.as_mut().and_then(|pass| pass.future.take()) {
+				future = Box::new(future.join(pass_future));
+			}
+		}
+
+		for (i, index) in order.into_iter().enumerate() {
+			let pass = passes[index].take().unwrap();
+			if i != 0 {
+				future = Box::new(future.then_signal_semaphore());
+			}
+			future = Box::new(future.then_execute(self.queue.clone(), pass.commands)?);
+		}
+
+		Ok(future)
+	}
+
+	fn order(&self) -> Result<Vec<usize>, FrameError> {
+		let mut writers = HashMap::<usize, Vec<usize>>::new();
+		for (i, pass) in self.passes.iter().enumerate() {
+			for &target in &pass.writes {
+				writers.entry(target).or_insert_with(Vec::new).push(i);
+			}
+		}
+
+		let mut dependencies = vec![vec![]; self.passes.len()];
+		for (i, pass) in self.passes.iter().enumerate() {
+			for target in &pass.reads {
+				for &writer in writers.get(target).into_iter().flatten() {
+					if writer != i {
+						dependencies[i].push(writer);
+					}
+				}
+			}
+
+			// passes writing the same target keep the order they were added in
+			for target in &pass.writes {
+				for &writer in writers[target].iter().take_while(|&&writer| writer < i) {
+					dependencies[i].push(writer);
+				}
+			}
+		}
+
+		let mut order = Vec::with_capacity(self.passes.len());
+		let mut done = vec![false; self.passes.len()];
+		while order.len() < self.passes.len() {
+			let next = (0..self.passes.len())
+				.find(|&i| !done[i] && dependencies[i].iter().all(|&dep| done[dep]))
+				.ok_or_else(|| {
+					let stuck = (0..self.passes.len()).find(|&i| !done[i]).unwrap();
+					FrameError::Cycle(self.passes[stuck].name.clone())
+				})?;
+
+			done[next] = true;
+			order.push(next);
+		}
+
+		Ok(order)
+	}
+}
+
+struct FramePass {
+	name: String,
+	commands: AutoCommandBuffer,
+	future: Option<Box<GpuFuture>>,
+	reads: Vec<usize>,
+	writes: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+	/// The reads and writes of the named pass can't be satisfied by any order.
+	Cycle(String),
+	CommandBufferExecError(CommandBufferExecError),
+}
+impl From<CommandBufferExecError> for FrameError {
+	fn from(val: CommandBufferExecError) -> Self {
+		FrameError::CommandBufferExecError(val)
+	}
+}
@@ -0,0 +1,308 @@
+use crate::batch::sprite::Shapes;
+use crate::camera::Camera;
+use cgmath::{ prelude::*, vec3, Quaternion, Rad, Vector3 };
+use std::f32::consts::PI;
+
+/// How close the cursor has to be to a handle to grab it, in pixels.
+const GRAB_DISTANCE: f32 = 6.0;
+const CIRCLE_SEGMENTS: usize = 48;
+const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 0.85, 0.0, 1.0];
+
+/// Translate, rotate and scale handles for moving objects around with the mouse, as in an editor. The handles are drawn
+/// with `Shapes` over the scene, stay the same size on screen, and turn mouse drags into transform deltas.
+///
+/// Pass cursor movement to `hover` while not dragging, clicks to `begin_drag`, movement to `drag` while dragging, and
+/// releases to `end_drag`. Cursor positions are in pixels relative to the camera's viewport.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gizmo {
+	mode: GizmoMode,
+	position: Vector3<f32>,
+	rotation: Quaternion<f32>,
+	local: bool,
+	size: f32,
+	hovered: Option<GizmoAxis>,
+	drag: Option<Drag>,
+}
+impl Gizmo {
+	pub fn new(mode: GizmoMode) -> Self {
+		Self {
+			mode: mode,
+			position: Vector3::zero(),
+			rotation: Quaternion::one(),
+			local: false,
+			size: 100.0,
+			hovered: None,
+			drag: None,
+		}
+	}
+
+	/// Switches the kind of handles. Any drag in progress is ended.
+	pub fn set_mode(&mut self, mode: GizmoMode) {
+		self.mode = mode;
+		self.drag = None;
+	}
+
+	pub fn mode(&self) -> GizmoMode {
+		self.mode
+	}
+
+	/// Moves the gizmo to the selected object. The gizmo follows its own drags, so this is only needed when the
+	/// selection changes or the object is moved some other way.
+	pub fn set_transform(&mut self, position: Vector3<f32>, rotation: Quaternion<f32>) {
+		self.position = position;
+		self.rotation = rotation;
+	}
+
+	/// Aligns the translate and rotate handles with the object's rotation instead of the world axes. Scale handles are
+	/// always aligned with the object.
+	pub fn set_local(&mut self, local: bool) {
+		self.local = local;
+	}
+
+	/// Sets the length of the handles on screen, in pixels.
+	pub fn set_size(&mut self, size: f32) {
+		self.size = size;
+	}
+
+	pub fn hovered(&self) -> Option<GizmoAxis> {
+		self.hovered
+	}
+
+	pub fn is_dragging(&self) -> bool {
+		self.drag.is_some()
+	}
+
+	/// Highlights the handle under the cursor, if any, and returns it.
+	pub fn hover(&mut self, camera: &Camera, viewport_size: [f32; 2], cursor: [f32; 2]) -> Option<GizmoAxis> {
+		self.hovered =
+			GizmoAxis::ALL.iter()
+				.cloned()
+				.filter_map(|axis| {
+					let points = self.handle_points(axis, camera, viewport_size)?;
+					let closed = self.mode == GizmoMode::Rotate;
+					Some((axis, polyline_distance(&points, closed, cursor)))
+				})
+				.filter(|&(_, distance)| distance <= GRAB_DISTANCE)
+				.min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+				.map(|(axis, _)| axis);
+		self.hovered
+	}
+
+	/// Starts dragging the handle under the cursor. Returns false if there isn't one, so the click can be used to
+	/// select something instead.
+	pub fn begin_drag(&mut self, camera: &Camera, viewport_size: [f32; 2], cursor: [f32; 2]) -> bool {
+		let drag =
+			self.hover(camera, viewport_size, cursor)
+				.and_then(|axis| {
+					let last = self.grab_point(axis, camera, viewport_size, cursor)?;
+					Some(Drag { axis: axis, last: last })
+				});
+		self.drag = drag;
+		self.drag.is_some()
+	}
+
+	/// Continues a drag, returning how the object should change since the last call. Returns `None` when nothing is
+	/// being dragged, or when the cursor's ray can't be projected onto the handle, such as when the handle points
+	/// straight at the camera.
+	pub fn drag(&mut self, camera: &Camera, viewport_size: [f32; 2], cursor: [f32; 2]) -> Option<GizmoDelta> {
+		let Drag { axis, last } = self.drag.clone()?;
+		let point = self.grab_point(axis, camera, viewport_size, cursor)?;
+		let dir = self.axis_dir(axis);
+		self.drag = Some(Drag { axis: axis, last: point });
+
+		Some(match self.mode {
+			GizmoMode::Translate => {
+				let delta = point - last;
+				self.position += delta;
+				GizmoDelta::Translate(delta)
+			},
+			GizmoMode::Rotate => {
+				let (from, to) = (last - self.position, point - self.position);
+				let angle = dir.dot(from.cross(to)).atan2(from.dot(to));
+				let delta = Quaternion::from_axis_angle(dir, Rad(angle));
+				self.rotation = delta * self.rotation;
+				GizmoDelta::Rotate(delta)
+			},
+			GizmoMode::Scale => {
+				let factor = 1.0 + (point - last).dot(dir) / self.world_length(camera, viewport_size);
+				let mut scale = vec3(1.0, 1.0, 1.0);
+				scale[axis.index()] = factor;
+				GizmoDelta::Scale(scale)
+			},
+		})
+	}
+
+	pub fn end_drag(&mut self) {
+		self.drag = None;
+	}
+
+	/// Adds the handles to a set of shapes. The shapes should be drawn over the scene, with the camera's viewport.
+	pub fn draw(&self, camera: &Camera, viewport_size: [f32; 2], shapes: &mut Shapes) {
+		let center = match camera.world_to_screen(self.position, viewport_size) { Some(c) => c, None => return };
+		let active = self.drag.as_ref().map(|drag| drag.axis).or(self.hovered);
+
+		for &axis in &GizmoAxis::ALL {
+			let points = match self.handle_points(axis, camera, viewport_size) { Some(p) => p, None => continue };
+			let color = if active == Some(axis) { HIGHLIGHT_COLOR } else { axis.color() };
+
+			match self.mode {
+				GizmoMode::Rotate => shapes.stroke_polygon(&points, 2.0, color),
+				GizmoMode::Translate | GizmoMode::Scale => {
+					let tip = points[1];
+					shapes.line(center, tip, 2.0, color);
+
+					let (dx, dy) = (tip[0] - center[0], tip[1] - center[1]);
+					let length = (dx * dx + dy * dy).sqrt();
+					if length < 1.0 {
+						continue;
+					}
+					let (dx, dy) = (dx / length, dy / length);
+
+					if self.mode == GizmoMode::Translate {
+						let head = [tip[0] + dx * 12.0, tip[1] + dy * 12.0];
+						let left = [tip[0] - dy * 5.0, tip[1] + dx * 5.0];
+						let right = [tip[0] + dy * 5.0, tip[1] - dx * 5.0];
+						shapes.fill_polygon(&[head, left, right], color);
+					} else {
+						shapes.fill_rect([tip[0] - 4.0, tip[1] - 4.0], [8.0, 8.0], color);
+					}
+				},
+			}
+		}
+	}
+
+	fn axis_dir(&self, axis: GizmoAxis) -> Vector3<f32> {
+		let mut dir = Vector3::zero();
+		dir[axis.index()] = 1.0;
+		if self.local || self.mode == GizmoMode::Scale { self.rotation * dir } else { dir }
+	}
+
+	/// The world space length of the handles, so they're `size` pixels long on screen.
+	fn world_length(&self, camera: &Camera, viewport_size: [f32; 2]) -> f32 {
+		let depth = -(camera.view() * self.position.extend(1.0)).z;
+		self.size * 2.0 * depth.max(0.001) / (camera.projection().y.y * viewport_size[1])
+	}
+
+	/// The handle on screen: the axis line for translate and scale, or the circle around the axis for rotate.
+	fn handle_points(&self, axis: GizmoAxis, camera: &Camera, viewport_size: [f32; 2]) -> Option<Vec<[f32; 2]>> {
+		let length = self.world_length(camera, viewport_size);
+		let world_points =
+			match self.mode {
+				GizmoMode::Translate | GizmoMode::Scale =>
+					vec![self.position, self.position + self.axis_dir(axis) * length],
+				GizmoMode::Rotate => {
+					let u = self.axis_dir(GizmoAxis::ALL[(axis.index() + 1) % 3]) * length;
+					let v = self.axis_dir(GizmoAxis::ALL[(axis.index() + 2) % 3]) * length;
+					(0..CIRCLE_SEGMENTS)
+						.map(|i| {
+							let angle = 2.0 * PI * i as f32 / CIRCLE_SEGMENTS as f32;
+							self.position + u * angle.cos() + v * angle.sin()
+						})
+						.collect()
+				},
+			};
+
+		world_points.into_iter().map(|point| camera.world_to_screen(point, viewport_size)).collect()
+	}
+
+	/// Where the cursor's ray meets the handle: the closest point on the axis line, or the intersection with the plane
+	/// of the rotation circle.
+	fn grab_point(
+		&self,
+		axis: GizmoAxis,
+		camera: &Camera,
+		viewport_size: [f32; 2],
+		cursor: [f32; 2],
+	) -> Option<Vector3<f32>> {
+		let (origin, ray) = camera.screen_ray(cursor, viewport_size);
+		let dir = self.axis_dir(axis);
+		let to_gizmo = self.position - origin;
+
+		match self.mode {
+			GizmoMode::Translate | GizmoMode::Scale => {
+				let cos = dir.dot(ray);
+				let denom = 1.0 - cos * cos;
+				if denom < 0.0001 {
+					return None;
+				}
+				let along = (cos * ray.dot(to_gizmo) - dir.dot(to_gizmo)) / denom;
+				Some(self.position + dir * along)
+			},
+			GizmoMode::Rotate => {
+				let facing = ray.dot(dir);
+				if facing.abs() < 0.0001 {
+					return None;
+				}
+				let distance = to_gizmo.dot(dir) / facing;
+				if distance < 0.0 { None } else { Some(origin + ray * distance) }
+			},
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GizmoMode {
+	Translate,
+	Rotate,
+	Scale,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GizmoAxis {
+	X,
+	Y,
+	Z,
+}
+impl GizmoAxis {
+	const ALL: [GizmoAxis; 3] = [GizmoAxis::X, GizmoAxis::Y, GizmoAxis::Z];
+
+	fn index(self) -> usize {
+		match self {
+			GizmoAxis::X => 0,
+			GizmoAxis::Y => 1,
+			GizmoAxis::Z => 2,
+		}
+	}
+
+	fn color(self) -> [f32; 4] {
+		match self {
+			GizmoAxis::X => [0.9, 0.2, 0.2, 1.0],
+			GizmoAxis::Y => [0.2, 0.8, 0.2, 1.0],
+			GizmoAxis::Z => [0.2, 0.4, 0.95, 1.0],
+		}
+	}
+}
+
+/// A change made by dragging a gizmo, since the previous call to `Gizmo::drag`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GizmoDelta {
+	/// A world space offset to add to the object's position.
+	Translate(Vector3<f32>),
+	/// A world space rotation to apply to the object, as `delta * rotation`.
+	Rotate(Quaternion<f32>),
+	/// Factors to multiply the object's scale by, along its own axes.
+	Scale(Vector3<f32>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Drag {
+	axis: GizmoAxis,
+	/// Where the cursor last met the handle, in world space.
+	last: Vector3<f32>,
+}
+
+/// The distance in pixels from a point to the nearest segment of a line through `points`.
+fn polyline_distance(points: &[[f32; 2]], closed: bool, point: [f32; 2]) -> f32 {
+	let count = if closed { points.len() } else { points.len() - 1 };
+	(0..count)
+		.map(|i| {
+			let (a, b) = (points[i], points[(i + 1) % points.len()]);
+			let (abx, aby) = (b[0] - a[0], b[1] - a[1]);
+			let (apx, apy) = (point[0] - a[0], point[1] - a[1]);
+			let length_sq = abx * abx + aby * aby;
+			let t = if length_sq > 0.0 { ((apx * abx + apy * aby) / length_sq).max(0.0).min(1.0) } else { 0.0 };
+			let (dx, dy) = (apx - abx * t, apy - aby * t);
+			(dx * dx + dy * dy).sqrt()
+		})
+		.fold(std::f32::INFINITY, f32::min)
+}
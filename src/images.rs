@@ -0,0 +1,125 @@
+#[cfg(feature = "exr")]
+mod exr;
+
+use crate::texture::ColorEncoding;
+use image::{ self, ImageError };
+use std::path::Path;
+use vulkano::format::Format;
+
+pub use image::ImageFormat;
+
+/// The pixels of a decoded image. Every image is expanded to four channels, in RGBA order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pixels {
+	Rgba8(Vec<u8>),
+	/// From 16-bit PNGs.
+	Rgba16(Vec<u16>),
+	/// From HDR and EXR files. Alpha is 1.0 unless an EXR file has an alpha channel.
+	Rgba32F(Vec<f32>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedImage {
+	pub width: u32,
+	pub height: u32,
+	pub pixels: Pixels,
+}
+impl DecodedImage {
+	/// Converts the pixels to 8 bits per channel. Float values are clamped to 0.0 to 1.0.
+	pub fn into_rgba8(self) -> Vec<u8> {
+		match self.pixels {
+			Pixels::Rgba8(pixels) => pixels,
+			Pixels::Rgba16(pixels) => pixels.into_iter().map(|value| (value >> 8) as u8).collect(),
+			Pixels::Rgba32F(pixels) =>
+				pixels.into_iter().map(|value| (value.max(0.0).min(1.0) * 255.0).round() as u8).collect(),
+		}
+	}
+
+	/// The format to upload the pixels in. The encoding only matters for 8-bit images, since deeper images are always
+	/// linear.
+	pub(crate) fn format(&self, encoding: ColorEncoding) -> Format {
+		match self.pixels {
+			Pixels::Rgba8(_) => encoding.rgba8_format(),
+			Pixels::Rgba16(_) => Format::R16G16B16A16Unorm,
+			Pixels::Rgba32F(_) => Format::R32G32B32A32Sfloat,
+		}
+	}
+}
+
+/// Decodes an image file. Without a format, it's guessed from the file's contents. Every image the engine loads goes
+/// through here, so the formats available are the same everywhere, and depend on the codec features.
+///
+/// `ImageFormat` has no variant for EXR, so EXR files are recognized by their contents, whatever format is given.
+pub fn decode(bytes: &[u8], format: Option<ImageFormat>) -> Result<DecodedImage, ImageError> {
+	#[cfg(feature = "exr")]
+	{
+		if exr::is_exr(bytes) {
+			return exr::decode(bytes);
+		}
+	}
+
+	let format = match format { Some(format) => format, None => image::guess_format(bytes)? };
+	match format {
+		#[cfg(feature = "png")]
+		ImageFormat::PNG if is_16_bit_png(bytes) => decode_png_16(bytes),
+		#[cfg(feature = "hdr")]
+		ImageFormat::HDR => decode_hdr(bytes),
+		format => {
+			let img = image::load_from_memory_with_format(bytes, format)?.to_rgba();
+			let (width, height) = img.dimensions();
+			Ok(DecodedImage { width: width, height: height, pixels: Pixels::Rgba8(img.into_raw()) })
+		},
+	}
+}
+
+/// The format an image file is in, from its extension.
+pub fn format_from_path(path: impl AsRef<Path>) -> Option<ImageFormat> {
+	let ext = path.as_ref().extension().and_then(|ext| ext.to_str()).unwrap_or("").to_ascii_lowercase();
+	match &ext[..] {
+		"png" => Some(ImageFormat::PNG),
+		"jpg" | "jpeg" => Some(ImageFormat::JPEG),
+		"hdr" => Some(ImageFormat::HDR),
+		"bmp" => Some(ImageFormat::BMP),
+		"tga" => Some(ImageFormat::TGA),
+		"gif" => Some(ImageFormat::GIF),
+		_ => None,
+	}
+}
+
+/// Reads the bit depth from the header, which always starts with the IHDR chunk.
+#[cfg(feature = "png")]
+fn is_16_bit_png(bytes: &[u8]) -> bool {
+	bytes.get(24) == Some(&16)
+}
+
+#[cfg(feature = "png")]
+fn decode_png_16(bytes: &[u8]) -> Result<DecodedImage, ImageError> {
+	use png_decoder::{ ColorType, Decoder };
+
+	let (info, mut reader) = Decoder::new(bytes).read_info().map_err(|err| ImageError::FormatError(err.to_string()))?;
+	let mut buf = vec![0; info.buffer_size()];
+	reader.next_frame(&mut buf).map_err(|err| ImageError::FormatError(err.to_string()))?;
+
+	// samples are big endian
+	let samples = buf.chunks(2).map(|sample| (sample[0] as u16) << 8 | sample[1] as u16).collect::<Vec<_>>();
+	let pixels =
+		match info.color_type {
+			ColorType::Grayscale => samples.iter().flat_map(|&l| vec![l, l, l, 0xFFFF]).collect(),
+			ColorType::GrayscaleAlpha => samples.chunks(2).flat_map(|p| vec![p[0], p[0], p[0], p[1]]).collect(),
+			ColorType::RGB => samples.chunks(3).flat_map(|p| vec![p[0], p[1], p[2], 0xFFFF]).collect(),
+			ColorType::RGBA => samples,
+			ColorType::Indexed => return Err(ImageError::FormatError("16-bit indexed PNG".to_owned())),
+		};
+	Ok(DecodedImage { width: info.width, height: info.height, pixels: Pixels::Rgba16(pixels) })
+}
+
+#[cfg(feature = "hdr")]
+fn decode_hdr(bytes: &[u8]) -> Result<DecodedImage, ImageError> {
+	let decoder = image::hdr::HDRDecoder::new(bytes)?;
+	let metadata = decoder.metadata();
+	let pixels = decoder.read_image_hdr()?
+		.into_iter()
+		.flat_map(|pixel| vec![pixel.data[0], pixel.data[1], pixel.data[2], 1.0])
+		.collect();
+	Ok(DecodedImage { width: metadata.width, height: metadata.height, pixels: Pixels::Rgba32F(pixels) })
+}
@@ -0,0 +1,270 @@
+use super::{ DecodedImage, Pixels };
+use byteorder::{ LE, ReadBytesExt };
+use flate2::read::ZlibDecoder;
+use image::ImageError;
+use std::io::{ Cursor, Read };
+
+const MAGIC_NUMBER: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const TILED: u32 = 0x200;
+const DEEP: u32 = 0x800;
+const MULTIPART: u32 = 0x1000;
+
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_RLE: u8 = 1;
+const COMPRESSION_ZIPS: u8 = 2;
+const COMPRESSION_ZIP: u8 = 3;
+
+pub(super) fn is_exr(bytes: &[u8]) -> bool {
+	bytes.starts_with(&MAGIC_NUMBER)
+}
+
+/// Decodes a single part, scanline OpenEXR image with no compression, or RLE, ZIPS, or ZIP compression, which covers
+/// what most tools write for environment maps. Tiled, deep, and multipart files, and the lossy and wavelet
+/// compressions, are rejected.
+///
+/// Pixels are decoded to 32-bit floats. Missing color channels are 0.0, and missing alpha is 1.0. Luminance-only
+/// images fill all three color channels.
+pub(super) fn decode(bytes: &[u8]) -> Result<DecodedImage, ImageError> {
+	let mut reader = Cursor::new(bytes);
+	reader.set_position(4);
+	let flags = reader.read_u32::<LE>().map_err(truncated)?;
+	if flags & (TILED | DEEP | MULTIPART) != 0 {
+		return Err(ImageError::UnsupportedError("tiled, deep, and multipart EXR images aren't supported".to_owned()));
+	}
+
+	let header = Header::read(&mut reader)?;
+	let width = (header.data_window[2] - header.data_window[0] + 1) as usize;
+	let height = (header.data_window[3] - header.data_window[1] + 1) as usize;
+	let lines_per_block =
+		match header.compression {
+			COMPRESSION_NONE | COMPRESSION_RLE | COMPRESSION_ZIPS => 1,
+			COMPRESSION_ZIP => 16,
+			compression => {
+				let msg = format!("EXR compression {} isn't supported", compression);
+				return Err(ImageError::UnsupportedError(msg));
+			},
+		};
+	let pixel_size = header.channels.iter().map(|channel| channel.sample_size()).sum::<usize>();
+
+	let mut pixels = vec![0.0; width * height * 4];
+	for pixel in pixels.chunks_mut(4) {
+		pixel[3] = 1.0;
+	}
+
+	// the offset table isn't needed, since the blocks follow it in order
+	let block_count = (height + lines_per_block - 1) / lines_per_block;
+	reader.set_position(reader.position() + 8 * block_count as u64);
+	for _ in 0..block_count {
+		let y = reader.read_i32::<LE>().map_err(truncated)?;
+		let size = reader.read_i32::<LE>().map_err(truncated)? as usize;
+		let start = reader.position() as usize;
+		let data = bytes.get(start..start + size).ok_or_else(|| truncated(()))?;
+		reader.set_position((start + size) as u64);
+
+		let first_line = (y - header.data_window[1]) as usize;
+		let lines = lines_per_block.min(height.saturating_sub(first_line));
+		let expected = lines * width * pixel_size;
+		// blocks that compression wouldn't shrink are stored as they are
+		let data =
+			if size == expected || header.compression == COMPRESSION_NONE {
+				data.to_owned()
+			} else if header.compression == COMPRESSION_RLE {
+				reconstruct(decompress_rle(data, expected)?)
+			} else {
+				let mut inflated = Vec::with_capacity(expected);
+				ZlibDecoder::new(data)
+					.read_to_end(&mut inflated)
+					.map_err(|err| ImageError::FormatError(err.to_string()))?;
+				reconstruct(inflated)
+			};
+		if data.len() < expected {
+			return Err(truncated(()));
+		}
+
+		// each line has every sample of the first channel, then every sample of the next, and so on
+		let mut samples = Cursor::new(data);
+		for line in first_line..first_line + lines {
+			for channel in &header.channels {
+				let row = &mut pixels[line * width * 4..(line + 1) * width * 4];
+				for pixel in row.chunks_mut(4) {
+					let value = channel.read_sample(&mut samples)?;
+					match &channel.name[..] {
+						"R" => pixel[0] = value,
+						"G" => pixel[1] = value,
+						"B" => pixel[2] = value,
+						"A" => pixel[3] = value,
+						"Y" => {
+							pixel[0] = value;
+							pixel[1] = value;
+							pixel[2] = value;
+						},
+						_ => (),
+					}
+				}
+			}
+		}
+	}
+
+	Ok(DecodedImage { width: width as u32, height: height as u32, pixels: Pixels::Rgba32F(pixels) })
+}
+
+struct Header {
+	/// Sorted by name, which is the order their samples are stored in.
+	channels: Vec<Channel>,
+	compression: u8,
+	/// The min x, min y, max x, and max y of the pixels in the file, inclusive.
+	data_window: [i32; 4],
+}
+impl Header {
+	fn read(reader: &mut Cursor<&[u8]>) -> Result<Self, ImageError> {
+		let mut channels = None;
+		let mut compression = None;
+		let mut data_window = None;
+		loop {
+			let name = read_string(reader)?;
+			if name.is_empty() {
+				break;
+			}
+			let _ty = read_string(reader)?;
+			let size = reader.read_i32::<LE>().map_err(truncated)? as u64;
+			let end = reader.position() + size;
+			match &name[..] {
+				"channels" => channels = Some(read_channels(reader)?),
+				"compression" => compression = Some(reader.read_u8().map_err(truncated)?),
+				"dataWindow" => {
+					let mut window = [0; 4];
+					for value in &mut window {
+						*value = reader.read_i32::<LE>().map_err(truncated)?;
+					}
+					data_window = Some(window);
+				},
+				_ => (),
+			}
+			reader.set_position(end);
+		}
+
+		let missing = |name: &str| ImageError::FormatError(format!("EXR header has no {} attribute", name));
+		let data_window = data_window.ok_or_else(|| missing("dataWindow"))?;
+		if data_window[2] < data_window[0] || data_window[3] < data_window[1] {
+			return Err(ImageError::DimensionError);
+		}
+		Ok(Self {
+			channels: channels.ok_or_else(|| missing("channels"))?,
+			compression: compression.ok_or_else(|| missing("compression"))?,
+			data_window: data_window,
+		})
+	}
+}
+
+struct Channel {
+	name: String,
+	/// 0 for u32, 1 for half floats, and 2 for floats.
+	pixel_type: i32,
+}
+impl Channel {
+	fn sample_size(&self) -> usize {
+		if self.pixel_type == 1 { 2 } else { 4 }
+	}
+
+	fn read_sample(&self, reader: &mut Cursor<Vec<u8>>) -> Result<f32, ImageError> {
+		match self.pixel_type {
+			0 => reader.read_u32::<LE>().map(|value| value as f32),
+			1 => reader.read_u16::<LE>().map(half_to_f32),
+			_ => reader.read_f32::<LE>(),
+		}.map_err(truncated)
+	}
+}
+
+fn read_channels(reader: &mut Cursor<&[u8]>) -> Result<Vec<Channel>, ImageError> {
+	let mut channels = vec![];
+	loop {
+		let name = read_string(reader)?;
+		if name.is_empty() {
+			return Ok(channels);
+		}
+		let pixel_type = reader.read_i32::<LE>().map_err(truncated)?;
+		// linearity and padding
+		reader.set_position(reader.position() + 4);
+		let x_sampling = reader.read_i32::<LE>().map_err(truncated)?;
+		let y_sampling = reader.read_i32::<LE>().map_err(truncated)?;
+		if pixel_type < 0 || pixel_type > 2 {
+			return Err(ImageError::FormatError(format!("invalid EXR pixel type {}", pixel_type)));
+		}
+		if x_sampling != 1 || y_sampling != 1 {
+			return Err(ImageError::UnsupportedError("subsampled EXR channels aren't supported".to_owned()));
+		}
+		channels.push(Channel { name: name, pixel_type: pixel_type });
+	}
+}
+
+fn read_string(reader: &mut Cursor<&[u8]>) -> Result<String, ImageError> {
+	let mut bytes = vec![];
+	loop {
+		match reader.read_u8().map_err(truncated)? {
+			0 => return String::from_utf8(bytes).map_err(|err| ImageError::FormatError(err.to_string())),
+			byte => bytes.push(byte),
+		}
+	}
+}
+
+/// Each run starts with a signed count. Negative counts are followed by that many literal bytes, and other counts by
+/// one byte to repeat one more time than the count.
+fn decompress_rle(data: &[u8], expected: usize) -> Result<Vec<u8>, ImageError> {
+	let mut out = Vec::with_capacity(expected);
+	let mut i = 0;
+	while i < data.len() {
+		let count = data[i] as i8;
+		i += 1;
+		if count < 0 {
+			let len = -(count as isize) as usize;
+			out.extend_from_slice(data.get(i..i + len).ok_or_else(|| truncated(()))?);
+			i += len;
+		} else {
+			let byte = *data.get(i).ok_or_else(|| truncated(()))?;
+			out.extend(std::iter::repeat(byte).take(count as usize + 1));
+			i += 1;
+		}
+	}
+	Ok(out)
+}
+
+/// Undoes the delta encoding and byte splitting that RLE and ZIP compression apply before compressing. The encoder
+/// stores every byte as the difference from the one before it, with the even bytes in the first half and the odd
+/// bytes in the second.
+fn reconstruct(mut data: Vec<u8>) -> Vec<u8> {
+	for i in 1..data.len() {
+		data[i] = data[i - 1].wrapping_add(data[i]).wrapping_sub(128);
+	}
+
+	let half = (data.len() + 1) / 2;
+	let mut out = Vec::with_capacity(data.len());
+	for i in 0..half {
+		out.push(data[i]);
+		if let Some(&odd) = data.get(half + i) {
+			out.push(odd);
+		}
+	}
+	out
+}
+
+fn half_to_f32(half: u16) -> f32 {
+	let sign = ((half >> 15) as u32) << 31;
+	let exponent = ((half >> 10) & 0x1f) as u32;
+	let mantissa = (half & 0x3ff) as u32;
+	let bits =
+		match exponent {
+			0 if mantissa == 0 => sign,
+			// subnormals are normalized, since they're well within the range of f32
+			0 => {
+				let shift = mantissa.leading_zeros() - 21;
+				sign | (113 - shift) << 23 | (mantissa << shift & 0x3ff) << 13
+			},
+			0x1f => sign | 0x7f80_0000 | mantissa << 13,
+			_ => sign | (exponent + 112) << 23 | mantissa << 13,
+		};
+	f32::from_bits(bits)
+}
+
+fn truncated<T>(_: T) -> ImageError {
+	ImageError::FormatError("EXR file is truncated".to_owned())
+}
@@ -0,0 +1,398 @@
+use crate::EventsLoop;
+use crate::camera::controllers::ControllerInput;
+use crate::window::{ Event, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent };
+use byteorder::{ LE, ReadBytesExt, WriteBytesExt };
+use cgmath::{ vec2, vec3 };
+use std::{
+	fs::File,
+	io::{ self, prelude::*, BufReader, BufWriter, ErrorKind },
+	path::Path,
+};
+use winit::ElementState;
+
+const MAGIC_NUMBER: &[u8; 4] = b"ninp";
+const VERSION: u32 = 2;
+
+const CHANGED_MOVEMENT: u8 = 1;
+const CHANGED_LOOK: u8 = 2;
+const CHANGED_ZOOM: u8 = 4;
+const CHANGED_STATE: u8 = 8;
+
+/// Mouse, scroll and touch state built from window events, for code that polls input once a frame instead of handling
+/// events, like scrolling a GUI list or pinch-zooming a 2D camera. Positions are in logical pixels.
+///
+/// Pass every event to `handle_event`, read the state while updating the frame, then call `end_frame`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputState {
+	cursor: Option<[f32; 2]>,
+	/// Left, right and middle.
+	buttons: [bool; 3],
+	scroll_lines: [f32; 2],
+	scroll_pixels: [f32; 2],
+	touches: Vec<TouchPoint>,
+}
+impl InputState {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn handle_event(&mut self, event: &Event) {
+		let event = match event { Event::WindowEvent { event, .. } => event, _ => return };
+		match event {
+			WindowEvent::CursorMoved { position, .. } => self.cursor = Some([position.x as f32, position.y as f32]),
+			WindowEvent::CursorLeft { .. } => self.cursor = None,
+			WindowEvent::MouseInput { state, button, .. } => {
+				let index =
+					match button {
+						MouseButton::Left => 0,
+						MouseButton::Right => 1,
+						MouseButton::Middle => 2,
+						MouseButton::Other(_) => return,
+					};
+				self.buttons[index] = *state == ElementState::Pressed;
+			},
+			WindowEvent::MouseWheel { delta, .. } => {
+				match delta {
+					MouseScrollDelta::LineDelta(x, y) => {
+						self.scroll_lines[0] += x;
+						self.scroll_lines[1] += y;
+					},
+					MouseScrollDelta::PixelDelta(delta) => {
+						self.scroll_pixels[0] += delta.x as f32;
+						self.scroll_pixels[1] += delta.y as f32;
+					},
+				}
+			},
+			WindowEvent::Touch(touch) => {
+				let position = [touch.location.x as f32, touch.location.y as f32];
+				match self.touches.iter_mut().find(|point| point.id == touch.id) {
+					Some(point) => {
+						point.position = position;
+						point.phase = touch.phase;
+					},
+					None => self.touches.push(TouchPoint {
+						id: touch.id,
+						position: position,
+						previous: position,
+						start: position,
+						phase: touch.phase,
+					}),
+				}
+			},
+			_ => (),
+		}
+	}
+
+	/// The cursor position, or `None` if it's outside the window.
+	pub fn cursor(&self) -> Option<[f32; 2]> {
+		self.cursor
+	}
+
+	pub fn is_button_down(&self, button: MouseButton) -> bool {
+		match button {
+			MouseButton::Left => self.buttons[0],
+			MouseButton::Right => self.buttons[1],
+			MouseButton::Middle => self.buttons[2],
+			MouseButton::Other(_) => false,
+		}
+	}
+
+	/// Scrolling this frame from mouse wheels, which scroll in lines, horizontal and then vertical. Positive y scrolls
+	/// up.
+	pub fn scroll_lines(&self) -> [f32; 2] {
+		self.scroll_lines
+	}
+
+	/// Scrolling this frame from touchpads, which scroll in pixels.
+	pub fn scroll_pixels(&self) -> [f32; 2] {
+		self.scroll_pixels
+	}
+
+	/// All scrolling this frame in pixels, with lines converted at the given height.
+	pub fn scroll(&self, pixels_per_line: f32) -> [f32; 2] {
+		[
+			self.scroll_pixels[0] + self.scroll_lines[0] * pixels_per_line,
+			self.scroll_pixels[1] + self.scroll_lines[1] * pixels_per_line,
+		]
+	}
+
+	/// Touches that are down, and ones that were lifted or cancelled this frame, in the order they started.
+	pub fn touches(&self) -> &[TouchPoint] {
+		&self.touches
+	}
+
+	pub fn touch(&self, id: u64) -> Option<&TouchPoint> {
+		self.touches.iter().find(|point| point.id == id)
+	}
+
+	/// How much the first two touches that are down spread apart this frame, as the ratio of their distances, for
+	/// pinch zooming. 1.0 if they didn't move, and `None` if fewer than two touches are down.
+	pub fn pinch(&self) -> Option<f32> {
+		let mut down = self.touches.iter().filter(|point| point.is_down());
+		let (a, b) = (down.next()?, down.next()?);
+		let distance = |a: [f32; 2], b: [f32; 2]| ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt();
+		let previous = distance(a.previous, b.previous);
+		if previous == 0.0 {
+			return Some(1.0);
+		}
+		Some(distance(a.position, b.position) / previous)
+	}
+
+	/// Clears this frame's scrolling and finished touches.
+	pub fn end_frame(&mut self) {
+		self.scroll_lines = [0.0, 0.0];
+		self.scroll_pixels = [0.0, 0.0];
+		self.touches.retain(|point| point.is_down());
+		for point in &mut self.touches {
+			point.previous = point.position;
+		}
+	}
+}
+
+/// A finger on a touchscreen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TouchPoint {
+	/// Stays the same from when the finger goes down until it's lifted.
+	pub id: u64,
+	pub position: [f32; 2],
+	/// The position at the end of the previous frame, or where the touch started if it started this frame.
+	pub previous: [f32; 2],
+	/// Where the finger went down.
+	pub start: [f32; 2],
+	/// `Started` or `Moved` while the finger is down, and `Ended` or `Cancelled` for the frame it was lifted in.
+	pub phase: TouchPhase,
+}
+impl TouchPoint {
+	pub fn is_down(&self) -> bool {
+		self.phase == TouchPhase::Started || self.phase == TouchPhase::Moved
+	}
+}
+
+/// One tick of recorded input: how long the tick was, what the controller received, and the mouse and touch state
+/// built from window events.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct InputFrame {
+	/// Seconds since the previous tick.
+	pub dt: f32,
+	pub input: ControllerInput,
+	/// The `InputState` as it was read during the tick, before `end_frame`.
+	#[cfg_attr(feature = "serialize", serde(skip))]
+	pub state: InputState,
+}
+
+/// Writes the input of every tick to a file, so a session can be played back exactly with `InputPlayback`. Only the
+/// fields that changed since the previous tick are stored.
+pub struct InputRecorder<W: Write> {
+	writer: W,
+	previous: ControllerInput,
+	previous_state: InputState,
+}
+impl InputRecorder<BufWriter<File>> {
+	pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+		Self::new(BufWriter::new(File::create(path)?))
+	}
+}
+impl<W: Write> InputRecorder<W> {
+	pub fn new(mut writer: W) -> io::Result<Self> {
+		writer.write_all(MAGIC_NUMBER)?;
+		writer.write_u32::<LE>(VERSION)?;
+		Ok(Self { writer: writer, previous: ControllerInput::default(), previous_state: InputState::default() })
+	}
+
+	pub fn record(&mut self, frame: &InputFrame) -> io::Result<()> {
+		let input = &frame.input;
+		let changed =
+			if input.movement != self.previous.movement { CHANGED_MOVEMENT } else { 0 } |
+			if input.look != self.previous.look { CHANGED_LOOK } else { 0 } |
+			if input.zoom != self.previous.zoom { CHANGED_ZOOM } else { 0 } |
+			if frame.state != self.previous_state { CHANGED_STATE } else { 0 };
+
+		self.writer.write_f32::<LE>(frame.dt)?;
+		self.writer.write_u8(changed)?;
+		if changed & CHANGED_MOVEMENT != 0 {
+			self.writer.write_f32::<LE>(input.movement.x)?;
+			self.writer.write_f32::<LE>(input.movement.y)?;
+			self.writer.write_f32::<LE>(input.movement.z)?;
+		}
+		if changed & CHANGED_LOOK != 0 {
+			self.writer.write_f32::<LE>(input.look.x)?;
+			self.writer.write_f32::<LE>(input.look.y)?;
+		}
+		if changed & CHANGED_ZOOM != 0 {
+			self.writer.write_f32::<LE>(input.zoom)?;
+		}
+		if changed & CHANGED_STATE != 0 {
+			write_state(&mut self.writer, &frame.state)?;
+		}
+
+		self.previous = *input;
+		if changed & CHANGED_STATE != 0 {
+			self.previous_state = frame.state.clone();
+		}
+		Ok(())
+	}
+
+	/// Flushes any buffered frames. Call this before the recorder is dropped, since errors can't be reported on drop.
+	pub fn finish(mut self) -> io::Result<W> {
+		self.writer.flush()?;
+		Ok(self.writer)
+	}
+}
+
+/// Reads back a recording made by `InputRecorder`, one tick at a time. Feeding each frame's `dt` to `Time::advance`
+/// and its input and state to the game, instead of the real clock and devices, reproduces the recorded session. Poll
+/// window events with `InputPlayback::poll_events` while replaying, so live mouse, touch, and keyboard events don't mix
+/// with the recorded ones.
+pub struct InputPlayback<R: Read> {
+	reader: R,
+	previous: ControllerInput,
+	previous_state: InputState,
+}
+impl InputPlayback<BufReader<File>> {
+	pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+		Self::new(BufReader::new(File::open(path)?))
+	}
+}
+impl<R: Read> InputPlayback<R> {
+	pub fn new(mut reader: R) -> io::Result<Self> {
+		let mut magic_number = [0; 4];
+		reader.read_exact(&mut magic_number)?;
+		if &magic_number != MAGIC_NUMBER {
+			return Err(io::Error::new(ErrorKind::InvalidData, "not an input recording"));
+		}
+
+		let version = reader.read_u32::<LE>()?;
+		if version != VERSION {
+			let msg = format!("unsupported input recording version {}", version);
+			return Err(io::Error::new(ErrorKind::InvalidData, msg));
+		}
+
+		Ok(Self { reader: reader, previous: ControllerInput::default(), previous_state: InputState::default() })
+	}
+
+	/// Passes window events to the callback like `EventsLoop::poll_events`, but leaves out the mouse, touch, and
+	/// keyboard events that the recording replaces. Resizing, focus, and close requests still come from the window.
+	pub fn poll_events(&self, events: &mut EventsLoop, mut callback: impl FnMut(Event)) {
+		events.poll_events(|event| if !is_recorded_event(&event) { callback(event) })
+	}
+
+	/// Returns the next tick, or `None` at the end of the recording.
+	pub fn next_frame(&mut self) -> io::Result<Option<InputFrame>> {
+		let dt =
+			match self.reader.read_f32::<LE>() {
+				Ok(dt) => dt,
+				Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+				Err(err) => return Err(err),
+			};
+
+		let changed = self.reader.read_u8()?;
+		let mut input = self.previous;
+		if changed & CHANGED_MOVEMENT != 0 {
+			input.movement =
+				vec3(self.reader.read_f32::<LE>()?, self.reader.read_f32::<LE>()?, self.reader.read_f32::<LE>()?);
+		}
+		if changed & CHANGED_LOOK != 0 {
+			input.look = vec2(self.reader.read_f32::<LE>()?, self.reader.read_f32::<LE>()?);
+		}
+		if changed & CHANGED_ZOOM != 0 {
+			input.zoom = self.reader.read_f32::<LE>()?;
+		}
+		if changed & CHANGED_STATE != 0 {
+			self.previous_state = read_state(&mut self.reader)?;
+		}
+
+		self.previous = input;
+		Ok(Some(InputFrame { dt: dt, input: input, state: self.previous_state.clone() }))
+	}
+}
+
+/// Whether the event is one that recorded input stands in for during playback.
+fn is_recorded_event(event: &Event) -> bool {
+	match event {
+		Event::DeviceEvent { .. } => true,
+		Event::WindowEvent { event, .. } => match event {
+			WindowEvent::CursorMoved { .. } => true,
+			WindowEvent::CursorEntered { .. } => true,
+			WindowEvent::CursorLeft { .. } => true,
+			WindowEvent::MouseInput { .. } => true,
+			WindowEvent::MouseWheel { .. } => true,
+			WindowEvent::Touch(_) => true,
+			WindowEvent::KeyboardInput { .. } => true,
+			WindowEvent::ReceivedCharacter(_) => true,
+			_ => false,
+		},
+		_ => false,
+	}
+}
+
+fn write_state(writer: &mut impl Write, state: &InputState) -> io::Result<()> {
+	match state.cursor {
+		Some(cursor) => {
+			writer.write_u8(1)?;
+			writer.write_f32::<LE>(cursor[0])?;
+			writer.write_f32::<LE>(cursor[1])?;
+		},
+		None => writer.write_u8(0)?,
+	}
+	let buttons = state.buttons.iter().enumerate().fold(0, |acc, (i, &down)| acc | (down as u8) << i);
+	writer.write_u8(buttons)?;
+	for &value in state.scroll_lines.iter().chain(&state.scroll_pixels) {
+		writer.write_f32::<LE>(value)?;
+	}
+	writer.write_u32::<LE>(state.touches.len() as u32)?;
+	for point in &state.touches {
+		writer.write_u64::<LE>(point.id)?;
+		for &value in point.position.iter().chain(&point.previous).chain(&point.start) {
+			writer.write_f32::<LE>(value)?;
+		}
+		let phase =
+			match point.phase {
+				TouchPhase::Started => 0,
+				TouchPhase::Moved => 1,
+				TouchPhase::Ended => 2,
+				TouchPhase::Cancelled => 3,
+			};
+		writer.write_u8(phase)?;
+	}
+	Ok(())
+}
+
+fn read_point(reader: &mut impl Read) -> io::Result<[f32; 2]> {
+	Ok([reader.read_f32::<LE>()?, reader.read_f32::<LE>()?])
+}
+
+fn read_state(reader: &mut impl Read) -> io::Result<InputState> {
+	let cursor = if reader.read_u8()? != 0 { Some(read_point(reader)?) } else { None };
+	let buttons = reader.read_u8()?;
+	let scroll_lines = read_point(reader)?;
+	let scroll_pixels = read_point(reader)?;
+	let touch_count = reader.read_u32::<LE>()?;
+	let mut touches = Vec::new();
+	for _ in 0..touch_count {
+		let id = reader.read_u64::<LE>()?;
+		let position = read_point(reader)?;
+		let previous = read_point(reader)?;
+		let start = read_point(reader)?;
+		let phase =
+			match reader.read_u8()? {
+				0 => TouchPhase::Started,
+				1 => TouchPhase::Moved,
+				2 => TouchPhase::Ended,
+				3 => TouchPhase::Cancelled,
+				phase => {
+					let msg = format!("invalid touch phase {}", phase);
+					return Err(io::Error::new(ErrorKind::InvalidData, msg));
+				},
+			};
+		touches.push(TouchPoint { id: id, position: position, previous: previous, start: start, phase: phase });
+	}
+
+	Ok(InputState {
+		cursor: cursor,
+		buttons: [buttons & 1 != 0, buttons & 2 != 0, buttons & 4 != 0],
+		scroll_lines: scroll_lines,
+		scroll_pixels: scroll_pixels,
+		touches: touches,
+	})
+}
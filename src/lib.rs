@@ -3,22 +3,52 @@
 pub mod camera;
 pub mod cpu_pool;
 pub mod batch;
+pub mod benchmark;
+mod debug;
+pub mod diagnostics;
 pub mod device;
+pub mod error;
+pub mod frame;
+pub mod gizmo;
+pub mod images;
+pub mod input;
+pub mod localization;
+pub mod nav;
+pub mod progress;
+pub mod scene;
+pub mod settings;
+pub mod spatial;
+pub mod stereo;
 pub mod texture;
+pub mod time;
+pub mod timeline;
+pub mod tween;
+pub mod uniform;
+pub mod vfs;
 pub mod window;
 
 pub use vulkano::{ command_buffer::CommandBuffer, instance::Version, sync::GpuFuture };
 
-use self::device::DeviceCtx;
-use self::window::Window;
-use log::{ info, log };
-use std::{ collections::HashMap, sync::{ Arc, Weak, atomic::{ AtomicBool, Ordering } } };
+use self::debug::{ DEBUG_UTILS_EXTENSION, DebugNames };
+use self::device::{ DeviceCtx, RequestedFeatures, ShaderPath };
+use self::window::{ Window, WindowError, WindowOptions };
+use log::{ debug, error, info, log, warn };
+use std::{ collections::HashMap, ffi::CString, sync::{ Arc, Weak, atomic::{ AtomicBool, Ordering } } };
 use vulkano::{
-	device::{ Device, DeviceExtensions, Features },
+	device::{ Device, DeviceExtensions },
 	format::Format,
 	framebuffer::FramebufferAbstract,
 	image::ImageViewAccess,
-	instance::{ ApplicationInfo, Instance, InstanceCreationError, PhysicalDevice },
+	instance::{
+		ApplicationInfo,
+		Instance,
+		InstanceCreationError,
+		InstanceExtensions,
+		PhysicalDevice,
+		RawInstanceExtensions,
+		debug::{ DebugCallback, Message, MessageTypes },
+		layers_list,
+	},
 	swapchain::Surface,
 };
 use vulkano_win::VkSurfaceBuild;
@@ -29,102 +59,237 @@ pub struct Context {
 	events: EventsLoop,
 	instance: Arc<Instance>,
 	devices: Vec<Arc<DeviceCtx>>,
+	_debug_callback: Option<DebugCallback>,
+	debug_names: bool,
+	features: RequestedFeatures,
+	shader_path: Option<ShaderPath>,
 }
 impl Context {
 	pub fn new(name: Option<&str>, version: Option<Version>) -> Result<Self, InstanceCreationError> {
+		Self::new_with_options(name, version, ContextOptions::default())
+	}
+
+	pub fn new_with_options(
+		name: Option<&str>,
+		version: Option<Version>,
+		options: ContextOptions,
+	) -> Result<Self, InstanceCreationError> {
+		let validation =
+			options.validation_layers && {
+				let available = layers_list()
+					.map(|mut layers| layers.any(|layer| layer.name() == VALIDATION_LAYER))
+					.unwrap_or(false);
+				if !available {
+					warn!("Validation layers were requested, but {} is not installed", VALIDATION_LAYER);
+				}
+				available
+			};
+
+		let debug_utils_extension = CString::new(DEBUG_UTILS_EXTENSION).unwrap();
+		let debug_names =
+			options.debug_names && {
+				let available = RawInstanceExtensions::supported_by_core()
+					.map(|extensions| extensions.contains(&debug_utils_extension))
+					.unwrap_or(false);
+				if !available {
+					warn!("Debug names were requested, but {} is not supported", DEBUG_UTILS_EXTENSION);
+				}
+				available
+			};
+
+		let extensions =
+			if validation {
+				InstanceExtensions { ext_debug_report: true, .. vulkano_win::required_extensions() }
+			} else {
+				vulkano_win::required_extensions()
+			};
+		let mut extensions = RawInstanceExtensions::from(&extensions);
+		if debug_names {
+			extensions.insert(debug_utils_extension);
+		}
+		let layers = if validation { vec![VALIDATION_LAYER] } else { vec![] };
+
+		let instance =
+			Instance::new(
+				Some(&ApplicationInfo {
+					application_name: name.map(|x| x.into()),
+					application_version: version,
+					engine_name: Some("nIce Game".into()),
+					engine_version: Some(Version {
+						major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+						minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+						patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+					}),
+				}),
+				&extensions,
+				layers.iter()
+			)?;
+
+		let debug_callback =
+			if validation {
+				let types =
+					MessageTypes { error: true, warning: true, performance_warning: true, information: true, debug: true };
+				DebugCallback::new(&instance, types, log_message)
+					.map_err(|err| warn!("Failed to register the debug callback: {}", err))
+					.ok()
+			} else {
+				None
+			};
+
 		Ok(Self {
 			events: EventsLoop::new(),
-			instance:
-				Instance::new(
-					Some(&ApplicationInfo {
-						application_name: name.map(|x| x.into()),
-						application_version: version,
-						engine_name: Some("nIce Game".into()),
-						engine_version: Some(Version {
-							major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
-							minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
-							patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
-						}),
-					}),
-					&vulkano_win::required_extensions(),
-					None
-				)?,
+			instance: instance,
 			devices: vec![],
+			_debug_callback: debug_callback,
+			debug_names: debug_names,
+			features: options.features,
+			shader_path: options.shader_path,
 		})
 	}
 
-	pub fn create_window<T: Into<String>>(&mut self, title: T) -> Window {
+	pub fn create_window<T: Into<String>>(&mut self, title: T) -> Result<Window, WindowError> {
+		self.create_window_with_options(title, &WindowOptions::default())
+	}
+
+	pub fn create_window_with_options<T: Into<String>>(
+		&mut self,
+		title: T,
+		options: &WindowOptions,
+	) -> Result<Window, WindowError> {
 		let surface = winit::WindowBuilder::new()
 			.with_title(title)
-			.build_vk_surface(&self.events.events, self.instance.clone())
-			.expect("failed to create window");
+			.with_transparency(options.transparent)
+			.with_decorations(options.decorations)
+			.build_vk_surface(&self.events.events, self.instance.clone())?;
 
-		let device = self.get_device_for_surface(&surface);
+		let device = self.get_device_for_surface(&surface)?;
 
 		let resized = Arc::<AtomicBool>::default();
 		self.events.resized.insert(surface.window().id(), resized.clone());
+		let minimized = Arc::<AtomicBool>::default();
+		self.events.minimized.insert(surface.window().id(), minimized.clone());
 
-		Window::new(surface, device, resized)
+		let window = Window::new(surface, device, resized, minimized, options)?;
+		debug!("Window surface: {:#?}", window.diagnostics());
+		Ok(window)
 	}
 
+	/// Passes window events to the callback, followed by `Event::Suspended` when a window is minimized or restored.
 	pub fn poll_events<F: FnMut(Event)>(&mut self, callback: F) {
 		self.events.poll_events(callback)
 	}
 
-	fn get_device_for_surface<T>(&mut self, surface: &Surface<T>) -> Arc<DeviceCtx> {
+	fn get_device_for_surface<T>(&mut self, surface: &Surface<T>) -> Result<Arc<DeviceCtx>, WindowError> {
 		for device in &self.devices {
 			let qfam = device.queue().family();
-			if qfam.supports_graphics() && surface.is_supported(qfam).unwrap() {
-				return device.clone();
+			if qfam.supports_graphics() && surface.is_supported(qfam)? {
+				return Ok(device.clone());
 			}
 		}
 
-		let pdevice = PhysicalDevice::enumerate(&self.instance).next().expect("no device available");
+		let (pdevice, qfam) =
+			PhysicalDevice::enumerate(&self.instance)
+				.filter(|pdevice| self.features.is_supported_by(pdevice.supported_features()))
+				.filter_map(|pdevice| {
+					pdevice.queue_families()
+						.find(|&q| q.supports_graphics() && surface.is_supported(q).unwrap_or(false))
+						.map(|qfam| (pdevice, qfam))
+				})
+				.next()
+				.ok_or(WindowError::NoSuitableDevice)?;
 		info!("Using device: {} ({:?})", pdevice.name(), pdevice.ty());
 
-		let qfam = pdevice.queue_families()
-			.find(|&q| q.supports_graphics() && surface.is_supported(q).unwrap())
-			.expect("failed to find a graphical queue family");
+		let (features, missing) = self.features.negotiate(pdevice.supported_features());
+		for name in missing {
+			info!("Optional device feature {} is not supported", name);
+		}
+		if !features.shader_sampled_image_array_dynamic_indexing {
+			warn!("device doesn't support dynamic texture array indexing; sprites may not render correctly");
+		}
 
 		let (device, mut queues) =
 			Device::new(
 				pdevice,
-				&Features::none(),
+				&features,
 				&DeviceExtensions { khr_swapchain: true, .. DeviceExtensions::none() },
 				[(qfam, 1.0)].iter().cloned()
-			)
-			.expect("failed to create device");
+			)?;
 		let queue = queues.next().unwrap();
 
-		let ret = DeviceCtx::new(device, queue);
+		let debug_names = DebugNames::new(&self.instance, device.clone(), self.debug_names);
+		debug_names.name_device(pdevice.name());
+
+		let ret = DeviceCtx::new(device, queue, debug_names, self.shader_path.clone());
+		debug!("{:#?}", ret.diagnostics());
 		self.devices.push(ret.clone());
-		ret
+		Ok(ret)
+	}
+}
+
+const VALIDATION_LAYER: &str = "VK_LAYER_LUNARG_standard_validation";
+
+#[derive(Clone, Debug, Default)]
+pub struct ContextOptions {
+	/// Enables the Vulkan validation layers if they're installed, and forwards their messages to the `log` crate.
+	pub validation_layers: bool,
+	/// Names buffers, images, pipelines, and command buffers after the assets they belong to, so captures in graphics
+	/// debuggers like RenderDoc are easier to navigate.
+	pub debug_names: bool,
+	/// Device features to enable. Devices without the required features are skipped.
+	pub features: RequestedFeatures,
+	/// Where to look for replacements for the engine's built-in mesh shaders. None by default, so only the embedded
+	/// shaders are used.
+	pub shader_path: Option<ShaderPath>,
+}
+
+fn log_message(msg: &Message) {
+	if msg.ty.error {
+		error!("[{}] {}", msg.layer_prefix, msg.description);
+	} else if msg.ty.warning || msg.ty.performance_warning {
+		warn!("[{}] {}", msg.layer_prefix, msg.description);
+	} else if msg.ty.information {
+		info!("[{}] {}", msg.layer_prefix, msg.description);
+	} else {
+		debug!("[{}] {}", msg.layer_prefix, msg.description);
 	}
 }
 
 pub struct EventsLoop {
 	events: winit::EventsLoop,
 	resized: HashMap<WindowId, Arc<AtomicBool>>,
+	minimized: HashMap<WindowId, Arc<AtomicBool>>,
 }
 impl EventsLoop {
 	pub fn new() -> Self {
-		Self { events: winit::EventsLoop::new(), resized: HashMap::new() }
+		Self { events: winit::EventsLoop::new(), resized: HashMap::new(), minimized: HashMap::new() }
 	}
 
+	/// Passes window events to the callback. When a window is minimized or restored, this is followed by
+	/// `Event::Suspended(true)` or `Event::Suspended(false)`, so the game can pause while nothing is being drawn.
 	pub fn poll_events(&mut self, mut callback: impl FnMut(Event)) {
 		let resized = &mut self.resized;
+		let minimized = &mut self.minimized;
 		self.events.poll_events(|event| {
+			let mut suspended = None;
 			match event {
 				Event::WindowEvent { event: WindowEvent::CloseRequested, window_id } => {
 					resized.remove(&window_id);
+					minimized.remove(&window_id);
 				},
-				Event::WindowEvent { event: WindowEvent::Resized(_), window_id } => {
+				Event::WindowEvent { event: WindowEvent::Resized(size), window_id } => {
 					resized[&window_id].store(true, Ordering::Relaxed);
+					let is_minimized = size.width == 0.0 || size.height == 0.0;
+					if minimized[&window_id].swap(is_minimized, Ordering::Relaxed) != is_minimized {
+						suspended = Some(is_minimized);
+					}
 				},
 				_ => (),
 			}
 
 			callback(event);
+			if let Some(suspended) = suspended {
+				callback(Event::Suspended(suspended));
+			}
 		});
 	}
 }
@@ -136,19 +301,35 @@ impl ObjectId {
 	pub fn is_child_of(&self, root: &ObjectIdRoot) -> bool {
 		self.val.upgrade().map_or(false, |val| Arc::ptr_eq(&val, &root.val))
 	}
+
+	/// Whether the root this was made from still exists.
+	pub(crate) fn is_alive(&self) -> bool {
+		self.val.upgrade().is_some()
+	}
 }
 
+#[derive(Default)]
 pub struct ObjectIdRoot {
 	val: Arc<()>,
 }
 impl ObjectIdRoot {
-	fn new() -> Self {
+	/// Creates a root for a render target implemented outside the engine, like a headset's `StereoTarget`.
+	pub fn new() -> Self {
 		Self { val: Arc::default() }
 	}
 
+	/// Another root with the same identity, so objects made for the original also accept the copy.
+	fn share(&self) -> Self {
+		Self { val: self.val.clone() }
+	}
+
 	pub fn make_id(&self) -> ObjectId {
 		ObjectId { val: Arc::downgrade(&self.val) }
 	}
+
+	pub(crate) fn key(&self) -> usize {
+		&*self.val as *const () as usize
+	}
 }
 
 #[derive(Clone)]
@@ -0,0 +1,116 @@
+use crate::vfs;
+use ron::de;
+use std::{
+	collections::HashMap,
+	error::Error,
+	fmt,
+	io,
+	path::Path,
+	sync::{ RwLock, atomic::{ AtomicU64, Ordering } },
+};
+
+/// A game's strings in each language it ships in, looked up by key. The language can be switched at runtime, and text
+/// made with `StaticText::localized` or `DynamicText::localized` is laid out again on the next frame.
+///
+/// Keys missing from the current language fall back to the fallback language, then to the key itself, so untranslated
+/// strings still show something.
+pub struct Localization {
+	inner: RwLock<LocalizationInner>,
+	/// Changes whenever the language or its strings change, so text can tell when to look its string up again.
+	generation: AtomicU64,
+}
+impl Localization {
+	/// Creates an empty localization that uses `fallback` until `set_language` is called.
+	pub fn new(fallback: impl Into<String>) -> Self {
+		let fallback = fallback.into();
+		Self {
+			inner: RwLock::new(LocalizationInner {
+				languages: HashMap::new(),
+				language: fallback.clone(),
+				fallback: fallback,
+			}),
+			generation: AtomicU64::new(0),
+		}
+	}
+
+	/// Adds or replaces a language's strings.
+	pub fn add_language(&self, language: impl Into<String>, strings: HashMap<String, String>) {
+		self.inner.write().unwrap().languages.insert(language.into(), strings);
+		self.generation.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Loads a language's strings from a RON map of keys to strings, through the virtual file system.
+	pub fn load_language(&self, language: impl Into<String>, path: impl AsRef<Path>) -> Result<(), LocalizationError> {
+		let strings = de::from_reader(vfs::open(path)?)?;
+		self.add_language(language, strings);
+		Ok(())
+	}
+
+	/// Switches the language strings are looked up in. The language doesn't need to be added yet.
+	pub fn set_language(&self, language: impl Into<String>) {
+		self.inner.write().unwrap().language = language.into();
+		self.generation.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn language(&self) -> String {
+		self.inner.read().unwrap().language.clone()
+	}
+
+	/// The languages that have been added, in no particular order.
+	pub fn languages(&self) -> Vec<String> {
+		self.inner.read().unwrap().languages.keys().cloned().collect()
+	}
+
+	/// Looks up a string in the current language.
+	pub fn get(&self, key: &str) -> String {
+		let inner = self.inner.read().unwrap();
+		[&inner.language, &inner.fallback].iter()
+			.filter_map(|language| inner.languages.get(*language))
+			.filter_map(|strings| strings.get(key))
+			.next()
+			.cloned()
+			.unwrap_or_else(|| key.to_string())
+	}
+
+	pub(crate) fn generation(&self) -> u64 {
+		self.generation.load(Ordering::Relaxed)
+	}
+}
+
+struct LocalizationInner {
+	languages: HashMap<String, HashMap<String, String>>,
+	language: String,
+	fallback: String,
+}
+
+#[derive(Debug)]
+pub enum LocalizationError {
+	Io(io::Error),
+	Parse(de::Error),
+}
+impl fmt::Display for LocalizationError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			LocalizationError::Io(err) => err.fmt(f),
+			LocalizationError::Parse(err) => err.fmt(f),
+		}
+	}
+}
+impl Error for LocalizationError {
+	fn source(&self) -> Option<&(Error + 'static)> {
+		match self {
+			LocalizationError::Io(err) => Some(err),
+			LocalizationError::Parse(err) => Some(err),
+		}
+	}
+}
+impl From<io::Error> for LocalizationError {
+	fn from(val: io::Error) -> Self {
+		LocalizationError::Io(val)
+	}
+}
+impl From<de::Error> for LocalizationError {
+	fn from(val: de::Error) -> Self {
+		LocalizationError::Parse(val)
+	}
+}
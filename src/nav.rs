@@ -0,0 +1,382 @@
+mod path;
+mod voxel;
+
+use self::voxel::{ Heightfield, OpenField };
+use crate::batch::mesh::Mesh;
+use crate::spatial::Aabb;
+use cgmath::{ prelude::*, Deg, Quaternion, Vector3 };
+use std::collections::HashMap;
+
+/// The largest polygon side, in cells. Larger polygons make shorter corridors, but paths through them hug the edges
+/// of the walkable area less.
+const MAX_POLYGON_CELLS: usize = 32;
+
+/// The size of the agents a navigation mesh is built for, and how finely the level is voxelized. Distances are in world
+/// units.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NavSettings {
+	/// The width and depth of a voxel. Smaller cells follow the level more closely, but take longer to build.
+	pub cell_size: f32,
+	/// The height of a voxel.
+	pub cell_height: f32,
+	/// How much clearance an agent needs above the floor.
+	pub agent_height: f32,
+	/// How far an agent keeps from walls and ledges.
+	pub agent_radius: f32,
+	/// The tallest step an agent can walk up or down.
+	pub max_climb: f32,
+	/// The steepest floor an agent can walk on.
+	pub max_slope: Deg<f32>,
+}
+impl Default for NavSettings {
+	fn default() -> Self {
+		Self {
+			cell_size: 0.3,
+			cell_height: 0.2,
+			agent_height: 2.0,
+			agent_radius: 0.6,
+			max_climb: 0.9,
+			max_slope: Deg(45.0),
+		}
+	}
+}
+
+/// Collects level geometry to build a `NavMesh` from.
+///
+/// Building voxelizes the triangles into columns of solid space, keeps the tops of the columns an agent can stand on
+/// and walk between, shrinks that area by the agent's radius, and covers what's left with rectangles that become the
+/// mesh's polygons. It can take a while for large levels, so it's best done with `cpu_pool::spawn_cpu` or ahead of
+/// time.
+#[derive(Clone, Debug)]
+pub struct NavMeshBuilder {
+	settings: NavSettings,
+	triangles: Vec<[Vector3<f32>; 3]>,
+}
+impl NavMeshBuilder {
+	pub fn new(settings: NavSettings) -> Self {
+		Self { settings: settings, triangles: vec![] }
+	}
+
+	/// Adds a triangle list, moved to the given transform.
+	pub fn add_triangles(
+		&mut self,
+		positions: &[[f32; 3]],
+		indices: &[u32],
+		position: Vector3<f32>,
+		rotation: Quaternion<f32>,
+	) {
+		let transform = |i: u32| rotation * Vector3::from(positions[i as usize]) + position;
+		for tri in indices.chunks(3).filter(|tri| tri.len() == 3) {
+			self.triangles.push([transform(tri[0]), transform(tri[1]), transform(tri[2])]);
+		}
+	}
+
+	/// Adds a mesh's triangles at its current transform, from its collision data or its bounding volume hierarchy. Other
+	/// meshes don't have their triangles on the CPU, so this returns false and adds nothing for them.
+	pub fn add_mesh(&mut self, mesh: &Mesh) -> bool {
+		let (position, rotation) = (mesh.position(), mesh.rotation());
+		let transform = |triangle: [Vector3<f32>; 3]| {
+			let [a, b, c] = triangle;
+			[rotation * a + position, rotation * b + position, rotation * c + position]
+		};
+		if let Some(collision) = mesh.collision_data() {
+			self.triangles.extend(collision.triangles().map(transform));
+		} else if let Some(bvh) = mesh.bvh() {
+			self.triangles.extend(bvh.triangles().map(transform));
+		} else {
+			return false;
+		}
+		true
+	}
+
+	pub fn build(&self) -> NavMesh {
+		let mut bounds = Aabb::empty();
+		for &vertex in self.triangles.iter().flat_map(|triangle| triangle.iter()) {
+			bounds.grow(vertex);
+		}
+		if bounds.is_empty() {
+			return NavMesh { polygons: vec![] };
+		}
+
+		let mut heightfield = Heightfield::new(&bounds, &self.settings);
+		for &triangle in &self.triangles {
+			heightfield.rasterize(triangle);
+		}
+		heightfield.filter();
+
+		let mut field = OpenField::new(&heightfield);
+		field.erode((self.settings.agent_radius / self.settings.cell_size).ceil() as u32);
+
+		build_polygons(&field, &heightfield)
+	}
+}
+
+/// Walkable areas of a level, as convex polygons connected by the edges they share. Paths are found with `find_path`.
+#[derive(Clone, Debug)]
+pub struct NavMesh {
+	polygons: Vec<NavPolygon>,
+}
+impl NavMesh {
+	pub fn polygons(&self) -> &[NavPolygon] {
+		&self.polygons
+	}
+
+	/// The closest point on the mesh to `point`, preferring polygons directly above or below it.
+	pub fn nearest_point(&self, point: Vector3<f32>) -> Option<Vector3<f32>> {
+		self.nearest_polygon(point).map(|(_, point)| point)
+	}
+
+	/// Finds a path between two points, as the corners an agent should walk through in order, starting and ending with
+	/// the points moved onto the mesh. Returns `None` if either point is off the mesh or there's no way between them.
+	pub fn find_path(&self, start: Vector3<f32>, end: Vector3<f32>) -> Option<Vec<Vector3<f32>>> {
+		let (start_polygon, start) = self.nearest_polygon(start)?;
+		let (end_polygon, end) = self.nearest_polygon(end)?;
+		let corridor = path::find_corridor(&self.polygons, start_polygon, end_polygon, end)?;
+
+		let portals = corridor.windows(2)
+			.map(|pair| {
+				let polygon = &self.polygons[pair[0]];
+				let portal = polygon.portals.iter().find(|portal| portal.neighbor == pair[1]).unwrap();
+				// the funnel needs each portal's ends in the same order relative to the direction of travel
+				if path::side(polygon.center(), portal.a, portal.b) > 0.0 {
+					(portal.b, portal.a)
+				} else {
+					(portal.a, portal.b)
+				}
+			})
+			.collect::<Vec<_>>();
+		Some(path::string_pull(start, end, &portals))
+	}
+
+	fn nearest_polygon(&self, point: Vector3<f32>) -> Option<(usize, Vector3<f32>)> {
+		let mut best: Option<(usize, Vector3<f32>, (bool, f32))> = None;
+		for (i, polygon) in self.polygons.iter().enumerate() {
+			let clamped = polygon.clamp(point);
+			// polygons under or over the point always win over ones off to the side
+			let beside = clamped.x != point.x || clamped.z != point.z;
+			let score = (beside, (clamped - point).magnitude2());
+			if best.map_or(true, |(_, _, best)| score < best) {
+				best = Some((i, clamped, score));
+			}
+		}
+		best.map(|(i, point, _)| (i, point))
+	}
+}
+
+/// A rectangle of walkable floor. It may slope, but it's flat enough that an agent can walk straight across it.
+#[derive(Clone, Debug)]
+pub struct NavPolygon {
+	/// The corners with the lowest x and z, the highest x, the highest x and z, then the highest z.
+	vertices: [Vector3<f32>; 4],
+	portals: Vec<Portal>,
+}
+impl NavPolygon {
+	pub fn vertices(&self) -> &[Vector3<f32>; 4] {
+		&self.vertices
+	}
+
+	/// The indices of the polygons that share an edge with this one.
+	pub fn neighbors(&self) -> impl Iterator<Item = usize> + '_ {
+		self.portals.iter().map(|portal| portal.neighbor)
+	}
+
+	pub fn center(&self) -> Vector3<f32> {
+		(self.vertices[0] + self.vertices[1] + self.vertices[2] + self.vertices[3]) / 4.0
+	}
+
+	/// The point on the polygon closest to `point` when looking down.
+	fn clamp(&self, point: Vector3<f32>) -> Vector3<f32> {
+		let [v0, v1, v2, v3] = self.vertices;
+		let u = ((point.x - v0.x) / (v1.x - v0.x)).max(0.0).min(1.0);
+		let w = ((point.z - v0.z) / (v3.z - v0.z)).max(0.0).min(1.0);
+		let near = v0.y + (v1.y - v0.y) * u;
+		let far = v3.y + (v2.y - v3.y) * u;
+		Vector3::new(v0.x + (v1.x - v0.x) * u, near + (far - near) * w, v0.z + (v3.z - v0.z) * w)
+	}
+}
+
+/// An edge shared with a neighboring polygon.
+#[derive(Clone, Copy, Debug)]
+struct Portal {
+	neighbor: usize,
+	a: Vector3<f32>,
+	b: Vector3<f32>,
+}
+
+/// Covers the open field with rectangles of cells, and connects rectangles whose cells are connected.
+fn build_polygons(field: &OpenField, heightfield: &Heightfield) -> NavMesh {
+	let settings = heightfield.settings();
+	let climb = (settings.max_climb / settings.cell_height).floor() as i32;
+	let cells = &field.cells;
+	let corner = |x: usize, z: usize, floor: i32| {
+		let (cell_size, cell_height) = (settings.cell_size, settings.cell_height);
+		heightfield.origin + Vector3::new(x as f32 * cell_size, floor as f32 * cell_height, z as f32 * cell_size)
+	};
+
+	// cells are in rows of increasing z, so each rectangle grows from its corner with the lowest x and z
+	let mut owner = vec![None; cells.len()];
+	let mut polygons = vec![];
+	for start in 0..cells.len() {
+		if owner[start].is_some() {
+			continue;
+		}
+
+		let rows = {
+			let base = cells[start].floor;
+			let fits = |i: usize| owner[i].is_none() && (cells[i].floor - base).abs() <= climb;
+			let connected =
+				|row: &[usize]| row.windows(2).all(|pair| cells[pair[0]].links[voxel::POSITIVE_X] == Some(pair[1]));
+
+			let mut row = vec![start];
+			while row.len() < MAX_POLYGON_CELLS {
+				match cells[*row.last().unwrap()].links[voxel::POSITIVE_X] {
+					Some(next) if fits(next) => row.push(next),
+					_ => break,
+				}
+			}
+
+			let mut rows = vec![row];
+			while rows.len() < MAX_POLYGON_CELLS {
+				let next = rows.last().unwrap().iter()
+					.map(|&i| cells[i].links[voxel::POSITIVE_Z].filter(|&next| fits(next)))
+					.collect::<Option<Vec<_>>>();
+				// the new row's cells have to connect to each other too
+				match next {
+					Some(next) if connected(&next) => rows.push(next),
+					_ => break,
+				}
+			}
+			rows
+		};
+
+		for &i in rows.iter().flat_map(|row| row.iter()) {
+			owner[i] = Some(polygons.len());
+		}
+
+		let (first, last) = (&rows[0], rows.last().unwrap());
+		let (x0, z0) = (cells[first[0]].x, cells[first[0]].z);
+		let (x1, z1) = (x0 + first.len(), z0 + rows.len());
+		polygons.push(NavPolygon {
+			vertices: [
+				corner(x0, z0, cells[first[0]].floor),
+				corner(x1, z0, cells[*first.last().unwrap()].floor),
+				corner(x1, z1, cells[*last.last().unwrap()].floor),
+				corner(x0, z1, cells[last[0]].floor),
+			],
+			portals: vec![],
+		});
+	}
+
+	// each pair of neighboring rectangles shares one straight edge, which grows to cover every connected cell along it
+	let mut portals: HashMap<(usize, usize), Portal> = HashMap::new();
+	for (i, cell) in cells.iter().enumerate() {
+		let polygon = owner[i].unwrap();
+		for (direction, link) in cell.links.iter().enumerate() {
+			let (link, neighbor) = match link.and_then(|link| owner[link].map(|neighbor| (link, neighbor))) {
+				Some((link, neighbor)) if neighbor != polygon => (link, neighbor),
+				_ => continue,
+			};
+
+			let floor = cell.floor.max(cells[link].floor);
+			let ((ax, az), (bx, bz)) = voxel::edge(cell.x, cell.z, direction);
+			let (a, b) = (corner(ax, az, floor), corner(bx, bz, floor));
+			portals.entry((polygon, neighbor))
+				.and_modify(|portal| {
+					let axis = if ax == bx { 2 } else { 0 };
+					if a[axis] < portal.a[axis] {
+						portal.a = a;
+					}
+					if b[axis] > portal.b[axis] {
+						portal.b = b;
+					}
+				})
+				.or_insert(Portal { neighbor: neighbor, a: a, b: b });
+		}
+	}
+	for ((polygon, _), portal) in portals {
+		polygons[polygon].portals.push(portal);
+	}
+
+	NavMesh { polygons: polygons }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A unit square with its lowest corner at `x`, `z`, connected to the given neighbors.
+	fn square(x: f32, z: f32, portals: Vec<Portal>) -> NavPolygon {
+		NavPolygon {
+			vertices: [
+				Vector3::new(x, 0.0, z),
+				Vector3::new(x + 1.0, 0.0, z),
+				Vector3::new(x + 1.0, 0.0, z + 1.0),
+				Vector3::new(x, 0.0, z + 1.0),
+			],
+			portals: portals,
+		}
+	}
+
+	/// Two squares side by side, and a third off on its own.
+	fn islands() -> NavMesh {
+		let (a, b) = (Vector3::new(1.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 1.0));
+		NavMesh {
+			polygons: vec![
+				square(0.0, 0.0, vec![Portal { neighbor: 1, a: a, b: b }]),
+				square(1.0, 0.0, vec![Portal { neighbor: 0, a: a, b: b }]),
+				square(5.0, 0.0, vec![]),
+			],
+		}
+	}
+
+	#[test]
+	fn corridor() {
+		let mesh = islands();
+		let end = Vector3::new(1.5, 0.0, 0.5);
+		assert_eq!(path::find_corridor(&mesh.polygons, 0, 1, end), Some(vec![0, 1]));
+		assert_eq!(path::find_corridor(&mesh.polygons, 1, 1, end), Some(vec![1]));
+	}
+
+	#[test]
+	fn unreachable_corridor() {
+		let mesh = islands();
+		assert_eq!(path::find_corridor(&mesh.polygons, 0, 2, Vector3::new(5.5, 0.0, 0.5)), None);
+	}
+
+	#[test]
+	fn straight_path() {
+		let (start, end) = (Vector3::new(0.5, 0.0, 0.5), Vector3::new(1.5, 0.0, 0.5));
+		assert_eq!(islands().find_path(start, end), Some(vec![start, end]));
+	}
+
+	#[test]
+	fn unreachable_path() {
+		assert_eq!(islands().find_path(Vector3::new(0.5, 0.0, 0.5), Vector3::new(5.5, 0.0, 0.5)), None);
+	}
+
+	#[test]
+	fn empty_mesh() {
+		let mesh = NavMeshBuilder::new(NavSettings::default()).build();
+		assert!(mesh.polygons().is_empty());
+		assert_eq!(mesh.nearest_point(Vector3::new(0.0, 0.0, 0.0)), None);
+		assert_eq!(mesh.find_path(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 1.0)), None);
+	}
+
+	#[test]
+	fn separate_floors() {
+		let positions = [[0.0, 0.0, 0.0], [10.0, 0.0, 0.0], [10.0, 0.0, 10.0], [0.0, 0.0, 10.0]];
+		let indices = [0, 1, 2, 0, 2, 3];
+		let mut builder = NavMeshBuilder::new(NavSettings::default());
+		builder.add_triangles(&positions, &indices, Vector3::new(0.0, 0.0, 0.0), Quaternion::one());
+		builder.add_triangles(&positions, &indices, Vector3::new(20.0, 0.0, 0.0), Quaternion::one());
+		let mesh = builder.build();
+		assert!(!mesh.polygons().is_empty());
+
+		let path = mesh.find_path(Vector3::new(3.0, 0.0, 3.0), Vector3::new(7.0, 0.0, 7.0)).unwrap();
+		let (first, last) = (path[0], path[path.len() - 1]);
+		assert!((first.x - 3.0).abs() < 1e-4 && (first.z - 3.0).abs() < 1e-4);
+		assert!((last.x - 7.0).abs() < 1e-4 && (last.z - 7.0).abs() < 1e-4);
+
+		assert_eq!(mesh.find_path(Vector3::new(5.0, 0.0, 5.0), Vector3::new(25.0, 0.0, 5.0)), None);
+	}
+}
@@ -0,0 +1,136 @@
+use super::NavPolygon;
+use cgmath::{ prelude::*, Vector3 };
+use std::{ cmp::Ordering, collections::BinaryHeap, f32 };
+
+/// Finds the polygons a path from `start` to `goal` passes through, with A* between polygon centers.
+pub(super) fn find_corridor(
+	polygons: &[NavPolygon],
+	start: usize,
+	goal: usize,
+	end: Vector3<f32>,
+) -> Option<Vec<usize>> {
+	let heuristic = |polygon: usize| (polygons[polygon].center() - end).magnitude();
+
+	let mut costs = vec![f32::INFINITY; polygons.len()];
+	let mut came_from = vec![None; polygons.len()];
+	let mut closed = vec![false; polygons.len()];
+	let mut open = BinaryHeap::new();
+	costs[start] = 0.0;
+	open.push(Open { estimate: heuristic(start), polygon: start });
+
+	while let Some(Open { polygon, .. }) = open.pop() {
+		if polygon == goal {
+			let mut corridor = vec![goal];
+			while let Some(previous) = came_from[*corridor.last().unwrap()] {
+				corridor.push(previous);
+			}
+			corridor.reverse();
+			return Some(corridor);
+		}
+		if closed[polygon] {
+			continue;
+		}
+		closed[polygon] = true;
+
+		let center = polygons[polygon].center();
+		for neighbor in polygons[polygon].neighbors() {
+			let cost = costs[polygon] + (polygons[neighbor].center() - center).magnitude();
+			if cost < costs[neighbor] {
+				costs[neighbor] = cost;
+				came_from[neighbor] = Some(polygon);
+				open.push(Open { estimate: cost + heuristic(neighbor), polygon: neighbor });
+			}
+		}
+	}
+
+	None
+}
+
+/// Pulls a path through a corridor's portals tight, with the simple stupid funnel algorithm. Each portal is given as
+/// its (left, right) ends, as seen when walking through it.
+pub(super) fn string_pull(
+	start: Vector3<f32>,
+	end: Vector3<f32>,
+	portals: &[(Vector3<f32>, Vector3<f32>)],
+) -> Vec<Vector3<f32>> {
+	let mut all = Vec::with_capacity(portals.len() + 2);
+	all.push((start, start));
+	all.extend_from_slice(portals);
+	all.push((end, end));
+
+	let mut path = vec![start];
+	let (mut apex, mut left, mut right) = (start, start, start);
+	let (mut left_i, mut right_i) = (0, 0);
+	let mut i = 1;
+	while i < all.len() {
+		let (next_left, next_right) = all[i];
+
+		// narrow the right side of the funnel, unless it would cross the left side, which becomes a corner
+		if side(apex, right, next_right) >= 0.0 {
+			if apex == right || side(apex, left, next_right) < 0.0 {
+				right = next_right;
+				right_i = i;
+			} else {
+				apex = left;
+				right = apex;
+				right_i = left_i;
+				if path.last() != Some(&apex) {
+					path.push(apex);
+				}
+				i = left_i + 1;
+				continue;
+			}
+		}
+
+		// the same for the left side
+		if side(apex, left, next_left) <= 0.0 {
+			if apex == left || side(apex, right, next_left) > 0.0 {
+				left = next_left;
+				left_i = i;
+			} else {
+				apex = right;
+				left = apex;
+				left_i = right_i;
+				if path.last() != Some(&apex) {
+					path.push(apex);
+				}
+				i = right_i + 1;
+				continue;
+			}
+		}
+
+		i += 1;
+	}
+
+	if path.last() != Some(&end) {
+		path.push(end);
+	}
+	path
+}
+
+/// Twice the signed area of a triangle, looking down. Its sign tells which side of the line from `a` to `b` `c` is on.
+pub(super) fn side(a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>) -> f32 {
+	(b.x - a.x) * (c.z - a.z) - (b.z - a.z) * (c.x - a.x)
+}
+
+/// A polygon waiting to be expanded, ordered so the heap pops the lowest estimate first.
+struct Open {
+	estimate: f32,
+	polygon: usize,
+}
+impl PartialEq for Open {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+impl Eq for Open {}
+impl PartialOrd for Open {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+impl Ord for Open {
+	fn cmp(&self, other: &Self) -> Ordering {
+		other.estimate.partial_cmp(&self.estimate).unwrap_or(Ordering::Equal)
+	}
+}
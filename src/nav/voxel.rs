@@ -0,0 +1,294 @@
+use super::NavSettings;
+use crate::spatial::Aabb;
+use cgmath::{ prelude::*, Vector3 };
+use std::{ collections::VecDeque, f32, i32 };
+
+/// Offsets to a cell's neighbors, indexed the same as `Cell::links`.
+const DIRECTIONS: [(isize, isize); 4] = [(-1, 0), (0, 1), (1, 0), (0, -1)];
+pub(super) const POSITIVE_Z: usize = 1;
+pub(super) const POSITIVE_X: usize = 2;
+
+/// Stands in for unbounded heights, leaving room to subtract without overflowing.
+const OPEN: i32 = i32::MAX / 2;
+
+/// Solid space as columns of spans on a grid, from rasterizing triangles. Heights are counted in cells.
+pub(super) struct Heightfield {
+	pub(super) origin: Vector3<f32>,
+	width: usize,
+	depth: usize,
+	/// Sorted from bottom to top, and never overlapping.
+	columns: Vec<Vec<Span>>,
+	settings: NavSettings,
+}
+impl Heightfield {
+	pub(super) fn new(bounds: &Aabb, settings: &NavSettings) -> Self {
+		let size = bounds.size();
+		let width = (size.x / settings.cell_size).ceil().max(1.0) as usize;
+		let depth = (size.z / settings.cell_size).ceil().max(1.0) as usize;
+		Self {
+			origin: bounds.min,
+			width: width,
+			depth: depth,
+			columns: vec![vec![]; width * depth],
+			settings: *settings,
+		}
+	}
+
+	pub(super) fn settings(&self) -> &NavSettings {
+		&self.settings
+	}
+
+	/// Adds the columns a triangle passes through. Its top is walkable if it isn't too steep.
+	pub(super) fn rasterize(&mut self, triangle: [Vector3<f32>; 3]) {
+		let normal = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]);
+		if normal.magnitude2() == 0.0 {
+			return;
+		}
+		// either winding counts, since levels aren't always consistent about it
+		let walkable = normal.normalize().y.abs() >= self.settings.max_slope.cos();
+
+		let mut bounds = Aabb::empty();
+		for &vertex in &triangle {
+			bounds.grow(vertex);
+		}
+		let cell_size = self.settings.cell_size;
+		let cell = |value: f32, origin: f32, count: usize| {
+			(((value - origin) / cell_size).floor().max(0.0) as usize).min(count - 1)
+		};
+		let (x0, x1) = (cell(bounds.min.x, self.origin.x, self.width), cell(bounds.max.x, self.origin.x, self.width));
+		let (z0, z1) = (cell(bounds.min.z, self.origin.z, self.depth), cell(bounds.max.z, self.origin.z, self.depth));
+		let climb = (self.settings.max_climb / self.settings.cell_height).floor() as i32;
+
+		for z in z0..=z1 {
+			let row_z = self.origin.z + z as f32 * cell_size;
+			let row = clip(&clip(&triangle, 2, row_z, true), 2, row_z + cell_size, false);
+			if row.len() < 3 {
+				continue;
+			}
+
+			for x in x0..=x1 {
+				let cell_x = self.origin.x + x as f32 * cell_size;
+				let polygon = clip(&clip(&row, 0, cell_x, true), 0, cell_x + cell_size, false);
+				if polygon.len() < 3 {
+					continue;
+				}
+
+				let (bottom, top) =
+					polygon.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), v| (lo.min(v.y), hi.max(v.y)));
+				let min = ((bottom - self.origin.y) / self.settings.cell_height).floor() as i32;
+				let max = (((top - self.origin.y) / self.settings.cell_height).ceil() as i32).max(min + 1);
+				self.add_span(x + z * self.width, Span { min: min, max: max, walkable: walkable }, climb);
+			}
+		}
+	}
+
+	/// Merges a span into a column. Where spans' tops are within `climb` of each other, the top is walkable if either
+	/// was, so a floor with a slightly raised rug stays walkable.
+	fn add_span(&mut self, column: usize, mut span: Span, climb: i32) {
+		let column = &mut self.columns[column];
+		let mut i = 0;
+		while i < column.len() {
+			let other = column[i];
+			if other.min > span.max {
+				break;
+			}
+			if other.max < span.min {
+				i += 1;
+				continue;
+			}
+
+			span.min = span.min.min(other.min);
+			if (other.max - span.max).abs() <= climb {
+				span.walkable |= other.walkable;
+			} else if other.max > span.max {
+				span.walkable = other.walkable;
+			}
+			span.max = span.max.max(other.max);
+			column.remove(i);
+		}
+		column.insert(i, span);
+	}
+
+	/// Marks spans unwalkable if an agent wouldn't fit on top of them, or if they're next to a drop too tall to climb.
+	pub(super) fn filter(&mut self) {
+		let height = (self.settings.agent_height / self.settings.cell_height).ceil() as i32;
+		let climb = (self.settings.max_climb / self.settings.cell_height).floor() as i32;
+
+		let mut ledges = vec![];
+		for z in 0..self.depth {
+			for x in 0..self.width {
+				let column = &self.columns[x + z * self.width];
+				for (i, span) in column.iter().enumerate().filter(|(_, span)| span.walkable) {
+					let (floor, ceiling) = (span.max, column.get(i + 1).map_or(OPEN, |above| above.min));
+					if self.is_ledge(x, z, floor, ceiling, height, climb) {
+						ledges.push((x + z * self.width, i));
+					}
+				}
+			}
+		}
+
+		for column in &mut self.columns {
+			for i in 0..column.len() {
+				let ceiling = column.get(i + 1).map_or(OPEN, |above| above.min);
+				if ceiling - column[i].max < height {
+					column[i].walkable = false;
+				}
+			}
+		}
+		for (column, i) in ledges {
+			self.columns[column][i].walkable = false;
+		}
+	}
+
+	/// Whether there's a gap an agent fits through next to the span that drops further than it can climb down.
+	fn is_ledge(&self, x: usize, z: usize, floor: i32, ceiling: i32, height: i32, climb: i32) -> bool {
+		DIRECTIONS.iter().any(|&(dx, dz)| {
+			let (nx, nz) = (x as isize + dx, z as isize + dz);
+			if nx < 0 || nz < 0 || nx as usize >= self.width || nz as usize >= self.depth {
+				return true;
+			}
+
+			// the gap under the lowest span, then the gap over each span
+			let neighbor = &self.columns[nx as usize + nz as usize * self.width];
+			let below = (-OPEN, neighbor.first().map_or(OPEN, |span| span.min));
+			let above = neighbor.iter()
+				.enumerate()
+				.map(|(i, span)| (span.max, neighbor.get(i + 1).map_or(OPEN, |above| above.min)));
+			Some(below).into_iter().chain(above).any(|(gap_floor, gap_ceiling)| {
+				gap_ceiling.min(ceiling) - gap_floor.max(floor) >= height && gap_floor < floor - climb
+			})
+		})
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Span {
+	min: i32,
+	max: i32,
+	/// Whether the top of the span can be walked on.
+	walkable: bool,
+}
+
+/// The walkable tops of a heightfield's spans, linked to the neighbors an agent can step to.
+pub(super) struct OpenField {
+	/// In rows of increasing z, each in order of increasing x.
+	pub(super) cells: Vec<Cell>,
+}
+impl OpenField {
+	pub(super) fn new(heightfield: &Heightfield) -> Self {
+		let settings = heightfield.settings;
+		let height = (settings.agent_height / settings.cell_height).ceil() as i32;
+		let climb = (settings.max_climb / settings.cell_height).floor() as i32;
+
+		// the first cell in each column, and how many there are
+		let mut columns = Vec::with_capacity(heightfield.columns.len());
+		let mut cells = vec![];
+		for z in 0..heightfield.depth {
+			for x in 0..heightfield.width {
+				let column = &heightfield.columns[x + z * heightfield.width];
+				let start = cells.len();
+				for (i, span) in column.iter().enumerate().filter(|(_, span)| span.walkable) {
+					let ceiling = column.get(i + 1).map_or(OPEN, |above| above.min);
+					cells.push(Cell { x: x, z: z, floor: span.max, ceiling: ceiling, links: [None; 4] });
+				}
+				columns.push((start, cells.len() - start));
+			}
+		}
+
+		for i in 0..cells.len() {
+			let cell = cells[i];
+			for (direction, &(dx, dz)) in DIRECTIONS.iter().enumerate() {
+				let (nx, nz) = (cell.x as isize + dx, cell.z as isize + dz);
+				if nx < 0 || nz < 0 || nx as usize >= heightfield.width || nz as usize >= heightfield.depth {
+					continue;
+				}
+
+				let (start, count) = columns[nx as usize + nz as usize * heightfield.width];
+				cells[i].links[direction] = (start..start + count).find(|&j| {
+					let other = &cells[j];
+					(other.floor - cell.floor).abs() <= climb &&
+						other.ceiling.min(cell.ceiling) - other.floor.max(cell.floor) >= height
+				});
+			}
+		}
+
+		Self { cells: cells }
+	}
+
+	/// Removes cells closer than `radius` cells to an edge of the walkable area, so agents keep their distance from
+	/// walls and ledges.
+	pub(super) fn erode(&mut self, radius: u32) {
+		if radius == 0 {
+			return;
+		}
+
+		let mut distances = vec![u32::max_value(); self.cells.len()];
+		let mut queue = VecDeque::new();
+		for (i, cell) in self.cells.iter().enumerate() {
+			if cell.links.iter().any(|link| link.is_none()) {
+				distances[i] = 0;
+				queue.push_back(i);
+			}
+		}
+		while let Some(i) = queue.pop_front() {
+			for &link in self.cells[i].links.iter().flatten() {
+				if distances[link] > distances[i] + 1 {
+					distances[link] = distances[i] + 1;
+					queue.push_back(link);
+				}
+			}
+		}
+
+		let mut remap = vec![None; self.cells.len()];
+		let mut cells = vec![];
+		for (i, cell) in self.cells.iter().enumerate() {
+			if distances[i] >= radius {
+				remap[i] = Some(cells.len());
+				cells.push(*cell);
+			}
+		}
+		for cell in &mut cells {
+			for link in &mut cell.links {
+				*link = link.and_then(|link| remap[link]);
+			}
+		}
+		self.cells = cells;
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Cell {
+	pub(super) x: usize,
+	pub(super) z: usize,
+	pub(super) floor: i32,
+	ceiling: i32,
+	/// The neighboring cells an agent can step to, in the order of `DIRECTIONS`.
+	pub(super) links: [Option<usize>; 4],
+}
+
+/// The grid corners at either end of the side of a cell facing `direction`, in order of increasing x or z.
+pub(super) fn edge(x: usize, z: usize, direction: usize) -> ((usize, usize), (usize, usize)) {
+	match direction {
+		0 => ((x, z), (x, z + 1)),
+		1 => ((x, z + 1), (x + 1, z + 1)),
+		2 => ((x + 1, z), (x + 1, z + 1)),
+		_ => ((x, z), (x + 1, z)),
+	}
+}
+
+/// Cuts off the part of a polygon on one side of a plane along `axis`, keeping the side above `value` if `keep_above`.
+fn clip(polygon: &[Vector3<f32>], axis: usize, value: f32, keep_above: bool) -> Vec<Vector3<f32>> {
+	let side = |v: Vector3<f32>| if keep_above { v[axis] - value } else { value - v[axis] };
+	let mut clipped = Vec::with_capacity(polygon.len() + 2);
+	for (i, &a) in polygon.iter().enumerate() {
+		let b = polygon[(i + 1) % polygon.len()];
+		let (da, db) = (side(a), side(b));
+		if da >= 0.0 {
+			clipped.push(a);
+		}
+		if (da >= 0.0) != (db >= 0.0) {
+			clipped.push(a + (b - a) * (da / (da - db)));
+		}
+	}
+	clipped
+}
@@ -0,0 +1,161 @@
+use std::{ error::Error, fmt, sync::{ Arc, Mutex, atomic::{ AtomicBool, AtomicUsize, Ordering } } };
+
+/// How much of the load is done once the file has been read. Parsing and uploading share the rest.
+const READ_SHARE: f32 = 0.6;
+const UPLOAD_START: f32 = 0.8;
+
+/// Aborts asset loads that are still in flight. One token can be shared by every load for a level, so a level change
+/// can cancel them all at once.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+	cancelled: Arc<AtomicBool>,
+}
+impl CancelToken {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Makes every load using this token stop at its next step and return a `Cancelled` error, dropping anything it
+	/// allocated. Loads that already finished aren't affected.
+	pub fn cancel(&self) {
+		self.cancelled.store(true, Ordering::Relaxed);
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.cancelled.load(Ordering::Relaxed)
+	}
+}
+
+/// The progress of an asset load, for loading screens. It's updated from the loader's threads, and clones refer to the
+/// same load, so it can be polled every frame while the load's future is pending.
+#[derive(Clone, Default)]
+pub struct LoadProgress {
+	inner: Arc<ProgressInner>,
+	cancel: CancelToken,
+}
+impl LoadProgress {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Creates the progress of a load that can be cancelled with the given token.
+	pub fn with_cancel_token(cancel: CancelToken) -> Self {
+		Self { inner: Arc::default(), cancel: cancel }
+	}
+
+	pub fn cancel_token(&self) -> &CancelToken {
+		&self.cancel
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.cancel.is_cancelled()
+	}
+
+	/// Returns `Err(Cancelled)` if the load's token has been cancelled, for loaders to check between steps. Each
+	/// loader's error type converts it into its own `Cancelled` variant.
+	pub fn check_cancelled(&self) -> Result<(), Cancelled> {
+		if self.is_cancelled() { Err(Cancelled) } else { Ok(()) }
+	}
+
+	pub fn stage(&self) -> LoadStage {
+		match self.inner.stage.load(Ordering::Relaxed) {
+			0 => LoadStage::Queued,
+			1 => LoadStage::Reading,
+			2 => LoadStage::Parsing,
+			3 => LoadStage::Uploading,
+			_ => LoadStage::Done,
+		}
+	}
+
+	pub fn bytes_read(&self) -> usize {
+		self.inner.bytes_read.load(Ordering::Relaxed)
+	}
+
+	/// The size of the file being read, once reading has started.
+	pub fn total_bytes(&self) -> Option<usize> {
+		match self.inner.total_bytes.load(Ordering::Relaxed) {
+			0 => None,
+			total => Some(total),
+		}
+	}
+
+	/// An estimate of how much of the load is done, from 0 to 100. Loads made of several files, like scenes, average
+	/// the progress of each file. GPU uploads may still be running when this reaches 100; wait on the load's
+	/// `GpuFuture` for those.
+	pub fn percent(&self) -> f32 {
+		let own = self.fraction();
+		let children = self.inner.children.lock().unwrap();
+		let total = children.iter().fold(own, |sum, child| sum + child.percent() / 100.0);
+		100.0 * total / (children.len() + 1) as f32
+	}
+
+	pub(crate) fn set_stage(&self, stage: LoadStage) {
+		let index =
+			match stage {
+				LoadStage::Queued => 0,
+				LoadStage::Reading => 1,
+				LoadStage::Parsing => 2,
+				LoadStage::Uploading => 3,
+				LoadStage::Done => 4,
+			};
+		self.inner.stage.store(index, Ordering::Relaxed);
+	}
+
+	pub(crate) fn start_reading(&self, total_bytes: usize) {
+		self.inner.total_bytes.store(total_bytes, Ordering::Relaxed);
+		self.inner.bytes_read.store(0, Ordering::Relaxed);
+		self.set_stage(LoadStage::Reading);
+	}
+
+	pub(crate) fn add_bytes(&self, bytes: usize) {
+		self.inner.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+	}
+
+	/// Creates the progress of a file that's part of this load.
+	pub(crate) fn add_child(&self) -> LoadProgress {
+		let child = LoadProgress::with_cancel_token(self.cancel.clone());
+		self.inner.children.lock().unwrap().push(child.clone());
+		child
+	}
+
+	fn fraction(&self) -> f32 {
+		match self.stage() {
+			LoadStage::Queued => 0.0,
+			LoadStage::Reading => {
+				let total = self.total_bytes().unwrap_or(0);
+				if total == 0 { 0.0 } else { READ_SHARE * (self.bytes_read() as f32 / total as f32).min(1.0) }
+			},
+			LoadStage::Parsing => READ_SHARE,
+			LoadStage::Uploading => UPLOAD_START,
+			LoadStage::Done => 1.0,
+		}
+	}
+}
+
+/// The error from `LoadProgress::check_cancelled`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled;
+impl fmt::Display for Cancelled {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "the load was cancelled")
+	}
+}
+impl Error for Cancelled {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadStage {
+	/// Waiting for the loader's thread.
+	Queued,
+	Reading,
+	Parsing,
+	Uploading,
+	Done,
+}
+
+#[derive(Default)]
+struct ProgressInner {
+	stage: AtomicUsize,
+	bytes_read: AtomicUsize,
+	total_bytes: AtomicUsize,
+	children: Mutex<Vec<LoadProgress>>,
+}
@@ -0,0 +1,399 @@
+use crate::batch::mesh::{
+	Mesh,
+	MeshBatch,
+	MeshFromFileError,
+	MeshGroup,
+	MeshGroupError,
+	MeshId,
+	MeshRenderPass,
+	StaticBatcher,
+	VertexFormat,
+	supports_groups,
+};
+use crate::camera::Camera;
+use crate::cpu_pool::spawn_fs;
+use crate::progress::{ CancelToken, Cancelled, LoadProgress, LoadStage };
+use crate::images::{ self, ImageFormat };
+use crate::texture::{ ColorEncoding, ImmutableTexture, TextureError };
+use crate::vfs;
+use crate::window::Window;
+use cgmath::Quaternion;
+use futures::prelude::*;
+use serde::{ Deserialize, Serialize };
+use std::{ collections::HashMap, error::Error, fmt, io, path::{ Path, PathBuf }, sync::Arc };
+use vulkano::sync::{ self, GpuFuture };
+
+/// A level loaded from a scene file: meshes with their transforms, cameras, lights, and a skybox.
+///
+/// Scene files are RON, or JSON if the extension is `.json`, and contain a `SceneDesc`. Asset paths are relative to the
+/// scene file.
+pub struct Scene {
+	meshes: Vec<(Option<String>, Mesh)>,
+	/// Static meshes merged by `SceneOptions::merge_static_meshes`.
+	groups: Vec<MeshGroup>,
+	mesh_ids: HashMap<String, MeshId>,
+	cameras: Vec<(String, Camera)>,
+	lights: Vec<SceneLight>,
+	skybox: Option<ImmutableTexture>,
+}
+impl Scene {
+	/// Loads a scene file and every mesh and texture it references. Cameras get the window's current aspect ratio.
+	pub fn from_file<P>(
+		window: &Window,
+		render_pass: Arc<MeshRenderPass>,
+		path: P,
+	) -> impl Future<Output = Result<(Self, Box<GpuFuture + Send + Sync>), SceneError>>
+	where P: AsRef<Path> + Send + 'static {
+		Self::from_file_with_progress(window, render_pass, path, &CancelToken::new()).1
+	}
+
+	/// Like `from_file`, but also returns a handle for showing how far along the load is. The progress covers the
+	/// scene file and every asset in it. Cancelling the token stops every asset load and drops the assets that already
+	/// finished, and the future returns `SceneError::Cancelled`.
+	pub fn from_file_with_progress<P>(
+		window: &Window,
+		render_pass: Arc<MeshRenderPass>,
+		path: P,
+		cancel: &CancelToken,
+	) -> (LoadProgress, impl Future<Output = Result<(Self, Box<GpuFuture + Send + Sync>), SceneError>>)
+	where P: AsRef<Path> + Send + 'static {
+		Self::from_file_with_options(window, render_pass, path, SceneOptions::default(), cancel)
+	}
+
+	/// Like `from_file_with_progress`, with options for how the scene is loaded.
+	pub fn from_file_with_options<P>(
+		window: &Window,
+		render_pass: Arc<MeshRenderPass>,
+		path: P,
+		options: SceneOptions,
+		cancel: &CancelToken,
+	) -> (LoadProgress, impl Future<Output = Result<(Self, Box<GpuFuture + Send + Sync>), SceneError>>)
+	where P: AsRef<Path> + Send + 'static {
+		let progress = LoadProgress::with_cancel_token(cancel.clone());
+		let future_progress = progress.clone();
+		let device = window.device().clone();
+		let aspect =
+			window.get_inner_size()
+				.filter(|size| size.height > 0.0)
+				.map(|size| (size.width / size.height) as f32)
+				.unwrap_or(16.0 / 9.0);
+
+		let future = async move {
+			let progress = future_progress;
+			let dir = path.as_ref().parent().map(Path::to_owned).unwrap_or_default();
+			let read_progress = progress.clone();
+			let desc = await!(spawn_fs(move || SceneDesc::read(path, &read_progress)));
+			progress.check_cancelled()?;
+			let desc = desc?;
+			// the scene file is done, but the scene isn't until its assets are
+			progress.set_stage(LoadStage::Uploading);
+
+			// static meshes that may be merged are only read, since they aren't uploaded until they're grouped
+			let max_merged_vertices = options.merge_static_meshes.filter(|_| supports_groups(&render_pass));
+			let merged =
+				desc.meshes.iter()
+					.map(|mesh| max_merged_vertices.is_some() && mesh.is_static && mesh.name.is_none())
+					.collect::<Vec<_>>();
+
+			// start every load before waiting on any, so they can run at the same time
+			let mut mesh_futures = vec![];
+			let mut model_futures = vec![];
+			for (mesh, &merge) in desc.meshes.iter().zip(&merged) {
+				if merge {
+					let model_progress = progress.add_child();
+					model_futures.push((model_progress.clone(), Mesh::read_model(dir.join(&mesh.path), model_progress)));
+				} else {
+					mesh_futures.push(
+						Mesh::from_file_impl(
+							device.queue().clone(),
+							render_pass.clone(),
+							dir.join(&mesh.path),
+							VertexFormat::Full,
+							progress.add_child(),
+						)
+					);
+				}
+			}
+			let skybox_future =
+				desc.skybox.as_ref().map(|skybox| {
+					ImmutableTexture::from_file_with_format_impl(
+						device.clone(),
+						dir.join(skybox),
+						images::format_from_path(skybox).unwrap_or(ImageFormat::PNG),
+						ColorEncoding::Srgb,
+						progress.add_child(),
+					)
+				});
+
+			let mut future: Box<GpuFuture + Send + Sync> = Box::new(sync::now(device.device().clone()));
+			let mut meshes = Vec::with_capacity(mesh_futures.len());
+			let mut batcher = StaticBatcher::new();
+			let mut mesh_futures = mesh_futures.into_iter();
+			let mut model_futures = model_futures.into_iter();
+			for (mesh_desc, merge) in desc.meshes.into_iter().zip(merged) {
+				let position = mesh_desc.position.into();
+				let rotation = quaternion(mesh_desc.rotation);
+				if merge {
+					let (model_progress, model_future) = model_futures.next().unwrap();
+					let model = await!(model_future)?;
+					progress.check_cancelled()?;
+					if model.vertex_count() <= max_merged_vertices.unwrap() {
+						batcher.push(&model, position, rotation);
+						model_progress.set_stage(LoadStage::Done);
+						continue;
+					}
+
+					// too big to be worth merging, so it's uploaded on its own
+					let (mut mesh, mesh_future) =
+						Mesh::from_model(
+							device.queue().clone(),
+							render_pass.clone(),
+							model,
+							VertexFormat::Full,
+							&model_progress,
+						)?;
+					mesh.set_position(position);
+					mesh.set_rotation(rotation);
+					future = Box::new(future.join(mesh_future));
+					meshes.push((mesh_desc.name, mesh));
+				} else {
+					let (mut mesh, mesh_future) = await!(mesh_futures.next().unwrap())?;
+					progress.check_cancelled()?;
+					mesh.set_position(position);
+					mesh.set_rotation(rotation);
+					future = Box::new(future.join(mesh_future));
+					meshes.push((mesh_desc.name, mesh));
+				}
+			}
+
+			let (groups, groups_future) = batcher.build(&render_pass)?;
+			future = Box::new(future.join(groups_future));
+
+			let skybox =
+				match skybox_future {
+					Some(skybox_future) => {
+						let (skybox, skybox_future) = await!(skybox_future)?;
+						progress.check_cancelled()?;
+						future = Box::new(future.join(skybox_future));
+						Some(skybox)
+					},
+					None => None,
+				};
+
+			let cameras =
+				desc.cameras.into_iter()
+					.map(|camera| {
+						let position = camera.position.into();
+						let rotation = quaternion(camera.rotation);
+						(camera.name, Camera::new(position, rotation, aspect, camera.fovx, camera.znear, camera.zfar))
+					})
+					.collect();
+
+			let scene =
+				Scene {
+					meshes: meshes,
+					groups: groups,
+					mesh_ids: HashMap::new(),
+					cameras: cameras,
+					lights: desc.lights,
+					skybox: skybox,
+				};
+			progress.set_stage(LoadStage::Done);
+			Ok::<_, SceneError>((scene, future))
+		};
+		(progress, future)
+	}
+
+	/// Moves the scene's meshes and mesh groups into a batch. Named meshes can be found afterward with `mesh_id`.
+	pub fn populate(&mut self, batch: &mut MeshBatch) {
+		for (name, mesh) in self.meshes.drain(..) {
+			let id = batch.add_mesh(mesh);
+			if let Some(name) = name {
+				self.mesh_ids.insert(name, id);
+			}
+		}
+		for group in self.groups.drain(..) {
+			batch.add_group(group);
+		}
+	}
+
+	pub fn mesh_id(&self, name: &str) -> Option<MeshId> {
+		self.mesh_ids.get(name).cloned()
+	}
+
+	pub fn camera(&self, name: &str) -> Option<&Camera> {
+		self.cameras.iter().find(|(camera_name, _)| camera_name == name).map(|(_, camera)| camera)
+	}
+
+	pub fn cameras(&self) -> &[(String, Camera)] {
+		&self.cameras
+	}
+
+	/// The mesh renderer's lighting isn't configurable yet, so these are only loaded for the game to use.
+	pub fn lights(&self) -> &[SceneLight] {
+		&self.lights
+	}
+
+	/// The renderer doesn't draw skyboxes yet, so this is only loaded for the game to use.
+	pub fn skybox(&self) -> Option<&ImmutableTexture> {
+		self.skybox.as_ref()
+	}
+}
+
+/// How a scene is loaded.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SceneOptions {
+	/// Merges static, unnamed meshes with at most this many vertices into mesh groups, one for each material, so a map
+	/// decorated with thousands of small props is drawn with a few indirect draws. It's ignored if the device can't
+	/// draw mesh groups.
+	pub merge_static_meshes: Option<usize>,
+}
+
+/// The contents of a scene file. Rotations are quaternions in `[x, y, z, w]` order.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneDesc {
+	pub meshes: Vec<SceneMeshDesc>,
+	pub cameras: Vec<SceneCameraDesc>,
+	pub lights: Vec<SceneLight>,
+	/// A texture file for the sky.
+	pub skybox: Option<PathBuf>,
+}
+impl SceneDesc {
+	fn read(path: impl AsRef<Path>, progress: &LoadProgress) -> Result<Self, SceneError> {
+		let bytes = vfs::read_with_progress(path.as_ref(), progress)?;
+		progress.set_stage(LoadStage::Parsing);
+		let ext = path.as_ref().extension().and_then(|ext| ext.to_str()).unwrap_or("");
+		if ext.eq_ignore_ascii_case("json") {
+			serde_json::from_slice(&bytes).map_err(|err| SceneError::Parse(err.to_string()))
+		} else {
+			ron::de::from_bytes(&bytes).map_err(|err| SceneError::Parse(err.to_string()))
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneMeshDesc {
+	/// Lets the game find the mesh with `Scene::mesh_id`.
+	#[serde(default)]
+	pub name: Option<String>,
+	/// A `.nmdl` file.
+	pub path: PathBuf,
+	#[serde(default)]
+	pub position: [f32; 3],
+	#[serde(default = "identity")]
+	pub rotation: [f32; 4],
+	/// Whether the mesh never moves, so it can be merged with others. See `SceneOptions::merge_static_meshes`.
+	#[serde(default, rename = "static")]
+	pub is_static: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneCameraDesc {
+	pub name: String,
+	#[serde(default)]
+	pub position: [f32; 3],
+	#[serde(default = "identity")]
+	pub rotation: [f32; 4],
+	/// Horizontal field of view, in degrees.
+	pub fovx: f32,
+	pub znear: f32,
+	pub zfar: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneLight {
+	pub kind: SceneLightKind,
+	#[serde(default)]
+	pub position: [f32; 3],
+	/// The direction the light points, for directional and spot lights.
+	#[serde(default)]
+	pub direction: [f32; 3],
+	pub color: [f32; 3],
+	pub intensity: f32,
+	/// How far point and spot lights reach.
+	#[serde(default)]
+	pub range: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SceneLightKind {
+	Directional,
+	Point,
+	/// A cone of light. The angle is the cone's half angle, in degrees.
+	Spot(f32),
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+	Io(io::Error),
+	/// The scene file isn't valid RON or JSON, or doesn't match `SceneDesc`.
+	Parse(String),
+	Mesh(MeshFromFileError),
+	MeshGroup(MeshGroupError),
+	Texture(TextureError),
+	/// The load's `CancelToken` was cancelled.
+	Cancelled,
+}
+impl fmt::Display for SceneError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SceneError::Io(err) => write!(f, "i/o error: {}", err),
+			SceneError::Parse(msg) => write!(f, "invalid scene file: {}", msg),
+			SceneError::Mesh(err) => write!(f, "mesh load failed: {}", err),
+			SceneError::MeshGroup(err) => write!(f, "merging static meshes failed: {}", err),
+			SceneError::Texture(err) => write!(f, "texture load failed: {}", err),
+			SceneError::Cancelled => write!(f, "the load was cancelled"),
+		}
+	}
+}
+impl Error for SceneError {
+	fn source(&self) -> Option<&(Error + 'static)> {
+		match self {
+			SceneError::Io(err) => Some(err),
+			SceneError::Mesh(err) => Some(err),
+			SceneError::MeshGroup(err) => Some(err),
+			SceneError::Texture(err) => Some(err),
+			SceneError::Parse(_) | SceneError::Cancelled => None,
+		}
+	}
+}
+impl From<io::Error> for SceneError {
+	fn from(val: io::Error) -> Self {
+		SceneError::Io(val)
+	}
+}
+impl From<MeshFromFileError> for SceneError {
+	fn from(val: MeshFromFileError) -> Self {
+		match val {
+			MeshFromFileError::Cancelled => SceneError::Cancelled,
+			val => SceneError::Mesh(val),
+		}
+	}
+}
+impl From<MeshGroupError> for SceneError {
+	fn from(val: MeshGroupError) -> Self {
+		SceneError::MeshGroup(val)
+	}
+}
+impl From<Cancelled> for SceneError {
+	fn from(_: Cancelled) -> Self {
+		SceneError::Cancelled
+	}
+}
+impl From<TextureError> for SceneError {
+	fn from(val: TextureError) -> Self {
+		match val {
+			TextureError::Cancelled => SceneError::Cancelled,
+			val => SceneError::Texture(val),
+		}
+	}
+}
+
+fn identity() -> [f32; 4] {
+	[0.0, 0.0, 0.0, 1.0]
+}
+
+fn quaternion([x, y, z, w]: [f32; 4]) -> Quaternion<f32> {
+	Quaternion::new(w, x, y, z)
+}
@@ -0,0 +1,190 @@
+use crate::batch::mesh::{
+	DisplayCalibration,
+	MeshRenderPass,
+	MeshRenderPassOptions,
+	QualityPreset,
+	QualitySettings,
+};
+use crate::texture::SamplerSettings;
+use crate::window::{ LogicalSize, Window };
+use ron::{ de, ser::{ self, PrettyConfig } };
+use serde::{ Deserialize, Serialize };
+use std::{
+	collections::BTreeMap,
+	fs::{ self, File },
+	io,
+	path::Path,
+	sync::{ Arc, mpsc::{ channel, Receiver, Sender } },
+};
+use vulkano::sampler::SamplerCreationError;
+
+/// Engine settings that players usually change from an options menu, stored as RON. Missing fields use their
+/// defaults, so files written by older versions still load.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+	/// Window size in logical pixels. `None` leaves the size up to the platform.
+	pub resolution: Option<[f64; 2]>,
+	pub fullscreen: bool,
+	pub vsync: bool,
+	/// The most frames to render per second. `None` renders as fast as possible, or at the refresh rate with vsync.
+	pub fps_limit: Option<f32>,
+	/// The size of mesh batches' shadow atlases.
+	pub shadow_quality: ShadowQuality,
+	/// Maximum anisotropy for mesh material textures.
+	pub anisotropy: f32,
+	pub depth_prepass: bool,
+	/// The display calibration. See `DisplayCalibration` for what these do.
+	pub brightness: f32,
+	pub contrast: f32,
+	pub gamma: f32,
+	/// Action names mapped to key names. The engine doesn't interpret these, so games can use whatever names they like.
+	pub key_bindings: BTreeMap<String, String>,
+}
+impl Settings {
+	pub fn load(path: impl AsRef<Path>) -> Result<Self, SettingsError> {
+		Ok(de::from_reader(File::open(path)?)?)
+	}
+
+	/// Like `load`, but returns the default settings if the file doesn't exist yet.
+	pub fn load_or_default(path: impl AsRef<Path>) -> Result<Self, SettingsError> {
+		match Self::load(path) {
+			Err(SettingsError::Io(ref err)) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+			result => result,
+		}
+	}
+
+	pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SettingsError> {
+		fs::write(path, ser::to_string_pretty(self, PrettyConfig::default())?)?;
+		Ok(())
+	}
+
+	pub fn apply_to_window(&self, window: &mut Window) {
+		if let Some([width, height]) = self.resolution {
+			window.set_inner_size(LogicalSize::new(width, height));
+		}
+		window.set_fullscreen(self.fullscreen);
+		window.set_vsync(self.vsync);
+		window.set_target_fps(self.fps_limit);
+	}
+
+	/// Applies the settings that can change after the render pass is created. This only fails if the material sampler
+	/// can't be recreated.
+	pub fn apply_to_mesh_render_pass(&self, render_pass: &MeshRenderPass) -> Result<(), SamplerCreationError> {
+		render_pass.set_depth_prepass(self.depth_prepass);
+		render_pass.set_display_calibration(self.display_calibration());
+		render_pass.set_quality(&self.quality_settings())
+	}
+
+	/// The quality settings these map to. Settings without a field here use their defaults.
+	pub fn quality_settings(&self) -> QualitySettings {
+		let shadow_resolution =
+			match self.shadow_quality {
+				ShadowQuality::Off => 0,
+				ShadowQuality::Low => QualitySettings::preset(QualityPreset::Low).shadow_resolution,
+				ShadowQuality::Medium => QualitySettings::preset(QualityPreset::Medium).shadow_resolution,
+				ShadowQuality::High => QualitySettings::preset(QualityPreset::High).shadow_resolution,
+			};
+		QualitySettings {
+			shadow_resolution: shadow_resolution,
+			anisotropy: self.anisotropy,
+			.. QualitySettings::default()
+		}
+	}
+
+	pub fn display_calibration(&self) -> DisplayCalibration {
+		DisplayCalibration { brightness: self.brightness, contrast: self.contrast, gamma: self.gamma }
+	}
+
+	/// Options for creating a mesh render pass with these settings.
+	pub fn mesh_render_pass_options(&self) -> MeshRenderPassOptions {
+		MeshRenderPassOptions {
+			sampler: SamplerSettings { anisotropy: self.anisotropy, .. Default::default() },
+			.. Default::default()
+		}
+	}
+}
+impl Default for Settings {
+	fn default() -> Self {
+		Self {
+			resolution: None,
+			fullscreen: false,
+			vsync: true,
+			fps_limit: None,
+			shadow_quality: ShadowQuality::Medium,
+			anisotropy: 1.0,
+			depth_prepass: false,
+			brightness: 0.0,
+			contrast: 1.0,
+			gamma: 1.0,
+			key_bindings: BTreeMap::new(),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShadowQuality {
+	Off,
+	Low,
+	Medium,
+	High,
+}
+
+/// Holds the current settings and tells subscribers when they change, so an options menu can apply graphics settings
+/// while the game is running.
+pub struct SettingsStore {
+	settings: Arc<Settings>,
+	subscribers: Vec<Sender<Arc<Settings>>>,
+}
+impl SettingsStore {
+	pub fn new(settings: Settings) -> Self {
+		Self { settings: Arc::new(settings), subscribers: vec![] }
+	}
+
+	pub fn get(&self) -> &Arc<Settings> {
+		&self.settings
+	}
+
+	/// Changes the settings, and sends the new settings to every subscriber if anything actually changed.
+	pub fn update(&mut self, f: impl FnOnce(&mut Settings)) {
+		let mut settings = (*self.settings).clone();
+		f(&mut settings);
+		if settings == *self.settings {
+			return;
+		}
+
+		self.settings = Arc::new(settings);
+		let settings = &self.settings;
+		self.subscribers.retain(|subscriber| subscriber.send(settings.clone()).is_ok());
+	}
+
+	/// Returns a receiver that gets the new settings after every change. Poll it once per frame and apply what it
+	/// receives with `Settings::apply_to_window` and `Settings::apply_to_mesh_render_pass`.
+	pub fn subscribe(&mut self) -> Receiver<Arc<Settings>> {
+		let (sender, receiver) = channel();
+		self.subscribers.push(sender);
+		receiver
+	}
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+	Io(io::Error),
+	Parse(de::Error),
+	Serialize(ser::Error),
+}
+impl From<io::Error> for SettingsError {
+	fn from(val: io::Error) -> Self {
+		SettingsError::Io(val)
+	}
+}
+impl From<de::Error> for SettingsError {
+	fn from(val: de::Error) -> Self {
+		SettingsError::Parse(val)
+	}
+}
+impl From<ser::Error> for SettingsError {
+	fn from(val: ser::Error) -> Self {
+		SettingsError::Serialize(val)
+	}
+}
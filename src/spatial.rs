@@ -0,0 +1,164 @@
+mod bvh;
+
+pub use self::bvh::Bvh;
+
+use cgmath::{ prelude::*, Quaternion, Vector3, Vector4 };
+
+/// A ray, or a sphere moving along a ray, to test against geometry. Sphere casts find where a moving object of that
+/// radius would first touch something, which makes them a cheap stand-in for collision on character movement or thick
+/// projectiles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cast {
+	pub origin: Vector3<f32>,
+	/// Normalized.
+	pub direction: Vector3<f32>,
+	/// 0.0 for a ray.
+	pub radius: f32,
+	/// How far along the direction to look.
+	pub max_distance: f32,
+}
+impl Cast {
+	/// A ray. The direction is normalized here. Rays from `Camera::screen_ray` can be passed as they are.
+	pub fn ray(origin: Vector3<f32>, direction: Vector3<f32>, max_distance: f32) -> Self {
+		Self { origin: origin, direction: direction.normalize(), radius: 0.0, max_distance: max_distance }
+	}
+
+	/// A sphere of the given radius moving from `origin` along `direction`.
+	pub fn sphere(origin: Vector3<f32>, direction: Vector3<f32>, radius: f32, max_distance: f32) -> Self {
+		Self { radius: radius.max(0.0), .. Self::ray(origin, direction, max_distance) }
+	}
+
+	/// The same cast in the space of an object at the given transform.
+	pub(crate) fn to_local(&self, position: Vector3<f32>, rotation: Quaternion<f32>) -> Self {
+		let inverse = rotation.invert();
+		Self { origin: inverse * (self.origin - position), direction: inverse * self.direction, .. *self }
+	}
+}
+
+/// Where a cast first touches geometry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+	/// How far the cast traveled before touching. 0.0 if a sphere cast started overlapping the geometry.
+	pub distance: f32,
+	/// The point on the geometry that was touched.
+	pub position: Vector3<f32>,
+	/// Points away from the geometry, toward the cast. For rays, this is the triangle's normal.
+	pub normal: Vector3<f32>,
+	/// The index of the triangle that was hit, in the order the triangles were given.
+	pub triangle: usize,
+}
+impl Hit {
+	/// The same hit in world space, for a cast made in the space of an object at the given transform.
+	pub(crate) fn to_world(&self, position: Vector3<f32>, rotation: Quaternion<f32>) -> Self {
+		Self { position: rotation * self.position + position, normal: rotation * self.normal, .. *self }
+	}
+}
+
+/// A mesh's triangles kept on the CPU, for systems like physics, navigation, or audio occlusion. Positions are in the
+/// mesh's own space.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CollisionData {
+	pub positions: Vec<[f32; 3]>,
+	/// A triangle list.
+	pub indices: Vec<u32>,
+}
+impl CollisionData {
+	pub fn triangle_count(&self) -> usize {
+		self.indices.len() / 3
+	}
+
+	pub fn triangles(&self) -> impl Iterator<Item = [Vector3<f32>; 3]> + '_ {
+		self.indices.chunks(3)
+			.filter(|tri| tri.len() == 3)
+			.map(move |tri| {
+				let vertex = |i: u32| Vector3::from(self.positions[i as usize]);
+				[vertex(tri[0]), vertex(tri[1]), vertex(tri[2])]
+			})
+	}
+
+	pub fn build_bvh(&self) -> Bvh {
+		Bvh::new(&self.positions, &self.indices)
+	}
+}
+
+/// An axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+	pub min: Vector3<f32>,
+	pub max: Vector3<f32>,
+}
+impl Aabb {
+	/// A box containing nothing, which grows to fit whatever it's joined with.
+	pub fn empty() -> Self {
+		Self {
+			min: Vector3::new(std::f32::INFINITY, std::f32::INFINITY, std::f32::INFINITY),
+			max: Vector3::new(std::f32::NEG_INFINITY, std::f32::NEG_INFINITY, std::f32::NEG_INFINITY),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+	}
+
+	pub fn grow(&mut self, point: Vector3<f32>) {
+		self.min = Vector3::new(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z));
+		self.max = Vector3::new(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z));
+	}
+
+	pub fn join(&self, other: &Aabb) -> Aabb {
+		let mut joined = *self;
+		joined.grow(other.min);
+		joined.grow(other.max);
+		joined
+	}
+
+	pub fn center(&self) -> Vector3<f32> {
+		(self.min + self.max) / 2.0
+	}
+
+	pub fn size(&self) -> Vector3<f32> {
+		self.max - self.min
+	}
+
+	/// Whether the box is in front of every plane, like the ones from `Camera::frustum_planes`. Boxes just outside a
+	/// frustum's corners can still pass, so this is only good for culling.
+	pub fn intersects_planes(&self, planes: &[Vector4<f32>]) -> bool {
+		planes.iter().all(|plane| {
+			let farthest =
+				Vector3::new(
+					if plane.x >= 0.0 { self.max.x } else { self.min.x },
+					if plane.y >= 0.0 { self.max.y } else { self.min.y },
+					if plane.z >= 0.0 { self.max.z } else { self.min.z },
+				);
+			plane.truncate().dot(farthest) + plane.w >= 0.0
+		})
+	}
+
+	/// The distance along the cast where it enters the box, grown by the cast's radius, or `None` if it misses within
+	/// `max_distance`. Casts starting inside the box enter it at 0.0.
+	pub(crate) fn cast(&self, cast: &Cast, max_distance: f32) -> Option<f32> {
+		let radius = Vector3::new(cast.radius, cast.radius, cast.radius);
+		let (min, max) = (self.min - radius, self.max + radius);
+
+		let mut near = 0.0f32;
+		let mut far = max_distance;
+		for axis in 0..3 {
+			let inv = 1.0 / cast.direction[axis];
+			let (mut t0, mut t1) = ((min[axis] - cast.origin[axis]) * inv, (max[axis] - cast.origin[axis]) * inv);
+			if t0 > t1 {
+				std::mem::swap(&mut t0, &mut t1);
+			}
+			// a zero direction gives NaN when the origin is on the slab's edge, which shouldn't count as a miss
+			if !t0.is_nan() {
+				near = near.max(t0);
+			}
+			if !t1.is_nan() {
+				far = far.min(t1);
+			}
+			if near > far {
+				return None;
+			}
+		}
+		Some(near)
+	}
+}
@@ -0,0 +1,427 @@
+use super::{ Aabb, Cast, Hit };
+use cgmath::{ prelude::*, Vector3 };
+
+/// The most triangles in a leaf. Smaller leaves make deeper trees that are slower to build but faster to query.
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+/// A bounding volume hierarchy over a mesh's triangles, for casting rays and spheres against it without testing every
+/// triangle. It's built once on the CPU and never changes, so it suits static geometry like maps.
+#[derive(Clone, Debug)]
+pub struct Bvh {
+	nodes: Vec<Node>,
+	/// Ordered so each leaf's triangles are contiguous.
+	triangles: Vec<Triangle>,
+}
+impl Bvh {
+	/// Builds a hierarchy over a triangle list. Indices past the end of `positions` are skipped.
+	pub fn new(positions: &[[f32; 3]], indices: &[u32]) -> Self {
+		let mut triangles = indices.chunks(3)
+			.enumerate()
+			.filter(|(_, tri)| tri.len() == 3 && tri.iter().all(|&i| (i as usize) < positions.len()))
+			.map(|(i, tri)| Triangle {
+				vertices: [
+					Vector3::from(positions[tri[0] as usize]),
+					Vector3::from(positions[tri[1] as usize]),
+					Vector3::from(positions[tri[2] as usize]),
+				],
+				index: i,
+			})
+			.collect::<Vec<_>>();
+
+		let mut nodes = vec![];
+		if !triangles.is_empty() {
+			build(&mut nodes, &mut triangles, 0);
+		}
+		Self { nodes: nodes, triangles: triangles }
+	}
+
+	/// The box around every triangle.
+	pub fn bounds(&self) -> Aabb {
+		self.nodes.first().map_or(Aabb::empty(), |node| node.bounds)
+	}
+
+	pub fn triangle_count(&self) -> usize {
+		self.triangles.len()
+	}
+
+	/// The triangles' vertices, in no particular order.
+	pub fn triangles(&self) -> impl Iterator<Item = [Vector3<f32>; 3]> + '_ {
+		self.triangles.iter().map(|triangle| triangle.vertices)
+	}
+
+	/// The first triangle the cast touches.
+	pub fn cast(&self, cast: &Cast) -> Option<Hit> {
+		let mut closest: Option<Hit> = None;
+		self.traverse(cast, |triangle, max_distance| {
+			if let Some(hit) = triangle.cast(cast, max_distance) {
+				closest = Some(hit);
+				return Some(hit.distance);
+			}
+			None
+		});
+		closest
+	}
+
+	/// Whether the cast touches anything. This stops at the first triangle found, so it's cheaper than `cast` for line
+	/// of sight checks.
+	pub fn cast_any(&self, cast: &Cast) -> bool {
+		let mut found = false;
+		self.traverse(cast, |triangle, max_distance| {
+			if triangle.cast(cast, max_distance).is_some() {
+				found = true;
+				// nothing can be closer than the start, so this ends the traversal
+				return Some(-1.0);
+			}
+			None
+		});
+		found
+	}
+
+	/// Calls `test` with each triangle in a leaf the cast reaches, and the distance it can reach. `test` returns a new,
+	/// shorter distance when it finds a hit.
+	fn traverse(&self, cast: &Cast, mut test: impl FnMut(&Triangle, f32) -> Option<f32>) {
+		if self.nodes.is_empty() {
+			return;
+		}
+
+		let mut max_distance = cast.max_distance;
+		let mut stack = vec![0];
+		while let Some(i) = stack.pop() {
+			let node = &self.nodes[i];
+			if max_distance < 0.0 || node.bounds.cast(cast, max_distance).is_none() {
+				continue;
+			}
+
+			if node.count > 0 {
+				for triangle in &self.triangles[node.start..node.start + node.count] {
+					if let Some(distance) = test(triangle, max_distance) {
+						max_distance = distance;
+					}
+				}
+			} else {
+				// the first child follows its parent
+				stack.push(node.start);
+				stack.push(i + 1);
+			}
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+	bounds: Aabb,
+	/// The first triangle of a leaf, or the second child of an interior node.
+	start: usize,
+	/// 0 for interior nodes.
+	count: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Triangle {
+	vertices: [Vector3<f32>; 3],
+	/// The triangle's position in the original index list.
+	index: usize,
+}
+impl Triangle {
+	fn bounds(&self) -> Aabb {
+		let mut bounds = Aabb::empty();
+		for &vertex in &self.vertices {
+			bounds.grow(vertex);
+		}
+		bounds
+	}
+
+	fn centroid(&self) -> Vector3<f32> {
+		(self.vertices[0] + self.vertices[1] + self.vertices[2]) / 3.0
+	}
+
+	fn cast(&self, cast: &Cast, max_distance: f32) -> Option<Hit> {
+		if cast.radius > 0.0 { self.sphere_cast(cast, max_distance) } else { self.ray_cast(cast, max_distance) }
+	}
+
+	/// Möller-Trumbore, hitting both sides.
+	fn ray_cast(&self, cast: &Cast, max_distance: f32) -> Option<Hit> {
+		let [a, b, c] = self.vertices;
+		let (ab, ac) = (b - a, c - a);
+		let p = cast.direction.cross(ac);
+		let det = ab.dot(p);
+		if det.abs() < 1e-8 {
+			return None;
+		}
+
+		let inv_det = 1.0 / det;
+		let s = cast.origin - a;
+		let u = s.dot(p) * inv_det;
+		if u < 0.0 || u > 1.0 {
+			return None;
+		}
+		let q = s.cross(ab);
+		let v = cast.direction.dot(q) * inv_det;
+		if v < 0.0 || u + v > 1.0 {
+			return None;
+		}
+		let t = ac.dot(q) * inv_det;
+		if t < 0.0 || t > max_distance {
+			return None;
+		}
+
+		let normal = ab.cross(ac).normalize();
+		Some(Hit {
+			distance: t,
+			position: cast.origin + cast.direction * t,
+			normal: if normal.dot(cast.direction) > 0.0 { -normal } else { normal },
+			triangle: self.index,
+		})
+	}
+
+	/// Finds where the sphere first touches the triangle's face, one of its edges, or one of its corners.
+	fn sphere_cast(&self, cast: &Cast, max_distance: f32) -> Option<Hit> {
+		let r = cast.radius;
+
+		// a sphere that starts overlapping hits immediately
+		let closest = self.closest_point(cast.origin);
+		let offset = cast.origin - closest;
+		if offset.magnitude2() <= r * r {
+			let normal = if offset.magnitude2() > 0.0 { offset.normalize() } else { -cast.direction };
+			return Some(Hit { distance: 0.0, position: closest, normal: normal, triangle: self.index });
+		}
+
+		let [a, b, c] = self.vertices;
+		let mut best: Option<(f32, Vector3<f32>)> = None;
+		let mut consider = |t: f32, contact: Vector3<f32>| {
+			if t >= 0.0 && t <= max_distance && best.map_or(true, |(best, _)| t < best) {
+				best = Some((t, contact));
+			}
+		};
+
+		// the face, from the side the sphere starts on
+		let normal = (b - a).cross(c - a);
+		if normal.magnitude2() > 0.0 {
+			let mut normal = normal.normalize();
+			if (cast.origin - a).dot(normal) < 0.0 {
+				normal = -normal;
+			}
+			let distance = (cast.origin - a).dot(normal);
+			let approach = cast.direction.dot(normal);
+			if approach < 0.0 {
+				let t = (distance - r) / -approach;
+				let contact = cast.origin + cast.direction * t - normal * r;
+				if self.contains(contact) {
+					consider(t, contact);
+				}
+			}
+		}
+
+		// the edges, as cylinders
+		for &(p0, p1) in &[(a, b), (b, c), (c, a)] {
+			let e = p1 - p0;
+			let m = cast.origin - p0;
+			let (ee, ed, em) = (e.dot(e), e.dot(cast.direction), e.dot(m));
+			let qa = ee - ed * ed;
+			let qb = ee * cast.direction.dot(m) - em * ed;
+			let qc = ee * (m.dot(m) - r * r) - em * em;
+			if qa.abs() < 1e-8 {
+				continue;
+			}
+			let disc = qb * qb - qa * qc;
+			if disc < 0.0 {
+				continue;
+			}
+			let t = (-qb - disc.sqrt()) / qa;
+			let s = (em + t * ed) / ee;
+			if s >= 0.0 && s <= 1.0 {
+				consider(t, p0 + e * s);
+			}
+		}
+
+		// the corners, as spheres
+		for &vertex in &self.vertices {
+			let m = cast.origin - vertex;
+			let half_b = m.dot(cast.direction);
+			let disc = half_b * half_b - (m.dot(m) - r * r);
+			if disc >= 0.0 {
+				consider(-half_b - disc.sqrt(), vertex);
+			}
+		}
+
+		best.map(|(t, contact)| {
+			let center = cast.origin + cast.direction * t;
+			Hit { distance: t, position: contact, normal: (center - contact).normalize(), triangle: self.index }
+		})
+	}
+
+	/// Whether a point on the triangle's plane is inside it.
+	fn contains(&self, point: Vector3<f32>) -> bool {
+		let [a, b, c] = self.vertices;
+		let normal = (b - a).cross(c - a);
+		(b - a).cross(point - a).dot(normal) >= 0.0 &&
+			(c - b).cross(point - b).dot(normal) >= 0.0 &&
+			(a - c).cross(point - c).dot(normal) >= 0.0
+	}
+
+	/// The point on the triangle nearest to `point`, from Real-Time Collision Detection.
+	fn closest_point(&self, point: Vector3<f32>) -> Vector3<f32> {
+		let [a, b, c] = self.vertices;
+		let (ab, ac, ap) = (b - a, c - a, point - a);
+		let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+		if d1 <= 0.0 && d2 <= 0.0 {
+			return a;
+		}
+
+		let bp = point - b;
+		let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+		if d3 >= 0.0 && d4 <= d3 {
+			return b;
+		}
+
+		let vc = d1 * d4 - d3 * d2;
+		if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+			return a + ab * (d1 / (d1 - d3));
+		}
+
+		let cp = point - c;
+		let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+		if d6 >= 0.0 && d5 <= d6 {
+			return c;
+		}
+
+		let vb = d5 * d2 - d1 * d6;
+		if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+			return a + ac * (d2 / (d2 - d6));
+		}
+
+		let va = d3 * d6 - d5 * d4;
+		if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+			return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+		}
+
+		let denom = va + vb + vc;
+		if denom.abs() < 1e-12 {
+			return a;
+		}
+		a + ab * (vb / denom) + ac * (vc / denom)
+	}
+}
+
+/// Adds the node for `triangles` and everything below it, splitting at the median centroid along the longest axis.
+/// `offset` is where `triangles` starts in the whole list. Returns the node's index.
+fn build(nodes: &mut Vec<Node>, triangles: &mut [Triangle], offset: usize) -> usize {
+	let bounds = triangles.iter().fold(Aabb::empty(), |bounds, triangle| bounds.join(&triangle.bounds()));
+	let index = nodes.len();
+	nodes.push(Node { bounds: bounds, start: offset, count: triangles.len() });
+	if triangles.len() <= MAX_LEAF_TRIANGLES {
+		return index;
+	}
+
+	let mut centroids = Aabb::empty();
+	for triangle in triangles.iter() {
+		centroids.grow(triangle.centroid());
+	}
+	let size = centroids.size();
+	let axis = if size.x >= size.y && size.x >= size.z { 0 } else if size.y >= size.z { 1 } else { 2 };
+	triangles.sort_by(|a, b| {
+		a.centroid()[axis].partial_cmp(&b.centroid()[axis]).unwrap_or(std::cmp::Ordering::Equal)
+	});
+
+	let mid = triangles.len() / 2;
+	let (left, right) = triangles.split_at_mut(mid);
+	build(nodes, left, offset);
+	let second = build(nodes, right, offset + mid);
+	nodes[index].start = second;
+	nodes[index].count = 0;
+	index
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use cgmath::vec3;
+
+	/// A 4x4 floor of quads at y = 0, and a quad at y = 5 over the floor's (1, 1) quad. Each quad is two triangles,
+	/// with the one on the low z side of its diagonal first.
+	fn level() -> Bvh {
+		let mut positions = vec![];
+		for z in 0..5 {
+			for x in 0..5 {
+				positions.push([x as f32, 0.0, z as f32]);
+			}
+		}
+		let mut indices = vec![];
+		for z in 0..4 {
+			for x in 0..4 {
+				let corner = x + z * 5;
+				indices.extend_from_slice(&[corner, corner + 1, corner + 6, corner, corner + 6, corner + 5]);
+			}
+		}
+
+		let platform = positions.len() as u32;
+		positions.extend_from_slice(&[[1.0, 5.0, 1.0], [2.0, 5.0, 1.0], [2.0, 5.0, 2.0], [1.0, 5.0, 2.0]]);
+		indices.extend_from_slice(&[platform, platform + 1, platform + 2, platform, platform + 2, platform + 3]);
+		Bvh::new(&positions, &indices)
+	}
+
+	fn down(x: f32, z: f32, max_distance: f32) -> Cast {
+		Cast::ray(vec3(x, 10.0, z), vec3(0.0, -1.0, 0.0), max_distance)
+	}
+
+	#[test]
+	fn ray_hits_floor() {
+		let hit = level().cast(&down(0.75, 0.25, 100.0)).unwrap();
+		assert!((hit.distance - 10.0).abs() < 1e-5);
+		assert!((hit.position - vec3(0.75, 0.0, 0.25)).magnitude() < 1e-5);
+		assert!((hit.normal - vec3(0.0, 1.0, 0.0)).magnitude() < 1e-5);
+		assert_eq!(hit.triangle, 0);
+
+		assert_eq!(level().cast(&down(3.25, 2.75, 100.0)).unwrap().triangle, 2 * (3 + 2 * 4) + 1);
+	}
+
+	#[test]
+	fn ray_hits_closest() {
+		let hit = level().cast(&down(1.75, 1.25, 100.0)).unwrap();
+		assert!((hit.distance - 5.0).abs() < 1e-5);
+		assert_eq!(hit.triangle, 32);
+	}
+
+	#[test]
+	fn ray_hits_back_faces() {
+		let bvh = level();
+		let hit = bvh.cast(&Cast::ray(vec3(0.75, -1.0, 0.25), vec3(0.0, 1.0, 0.0), 100.0)).unwrap();
+		assert!((hit.distance - 1.0).abs() < 1e-5);
+		assert!((hit.normal - vec3(0.0, -1.0, 0.0)).magnitude() < 1e-5);
+	}
+
+	#[test]
+	fn ray_misses() {
+		let bvh = level();
+		let misses = [
+			// beside the floor
+			down(6.5, 2.5, 100.0),
+			// pointing away
+			Cast::ray(vec3(2.5, 10.0, 2.5), vec3(0.0, 1.0, 0.0), 100.0),
+			// along the floor's plane, above it
+			Cast::ray(vec3(-1.0, 1.0, 2.5), vec3(1.0, 0.0, 0.0), 100.0),
+			// stopping short
+			down(2.5, 2.5, 9.0),
+		];
+		for cast in &misses {
+			assert!(bvh.cast(cast).is_none(), "{:?} hit", cast);
+			assert!(!bvh.cast_any(cast), "{:?} hit", cast);
+		}
+	}
+
+	#[test]
+	fn sphere_hits_floor() {
+		let hit = level().cast(&Cast::sphere(vec3(2.5, 10.0, 2.5), vec3(0.0, -1.0, 0.0), 0.5, 100.0)).unwrap();
+		assert!((hit.distance - 9.5).abs() < 1e-4);
+		assert!((hit.normal - vec3(0.0, 1.0, 0.0)).magnitude() < 1e-4);
+	}
+
+	#[test]
+	fn empty() {
+		let bvh = Bvh::new(&[], &[]);
+		assert!(bvh.bounds().is_empty());
+		assert_eq!(bvh.triangle_count(), 0);
+		assert!(bvh.cast(&down(0.5, 0.5, 100.0)).is_none());
+		assert!(!bvh.cast_any(&down(0.5, 0.5, 100.0)));
+	}
+}
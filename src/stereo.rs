@@ -0,0 +1,47 @@
+use crate::RenderTarget;
+use crate::camera::{ AsymmetricFov, Camera };
+use cgmath::{ Quaternion, Vector3 };
+use vulkano::sync::GpuFuture;
+
+/// Experimental. A headset's swapchain, for VR integrations to implement. Its images are double wide, with the left
+/// eye in the left half and the right eye in the right half, so `MeshBatch::commands_stereo` can draw both eyes in
+/// one pass. A frame goes like this:
+///
+/// 1. `begin_frame` waits for the headset and returns the image to draw to and where the eyes are.
+/// 2. The game makes cameras for the eyes with `StereoFrame::cameras` and records its batches into the image.
+/// 3. `submit` gets the frame and the future for its commands, and hands the image to the headset once they finish.
+pub trait StereoTarget: RenderTarget {
+	/// Returns `None` when the headset doesn't want a frame drawn, such as while it isn't being worn.
+	fn begin_frame(&mut self) -> Option<StereoFrame>;
+	fn submit(&mut self, frame: StereoFrame, future: Box<GpuFuture>);
+}
+
+/// The image to draw a headset's frame to, and the eye poses it should be drawn from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StereoFrame {
+	pub image_num: usize,
+	/// The left eye, then the right eye.
+	pub eyes: [EyePose; 2],
+}
+impl StereoFrame {
+	/// Makes a camera for each eye. The poses are relative to `base`, which places the headset's tracking space in the
+	/// world. The eye cameras keep the rest of its settings, like its depth planes and effects.
+	pub fn cameras(&self, base: &Camera) -> [Camera; 2] {
+		let camera = |eye: &EyePose| {
+			let mut camera = *base;
+			camera.set_position(base.position() + base.rotation() * eye.position);
+			camera.set_rotation(base.rotation() * eye.rotation);
+			camera.set_asymmetric_fov(eye.fov);
+			camera
+		};
+		[camera(&self.eyes[0]), camera(&self.eyes[1])]
+	}
+}
+
+/// Where an eye is in the headset's tracking space, and its field of view.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EyePose {
+	pub position: Vector3<f32>,
+	pub rotation: Quaternion<f32>,
+	pub fov: AsymmetricFov,
+}
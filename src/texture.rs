@@ -1,12 +1,65 @@
+mod compress;
 mod immutable;
+mod lut;
+mod sampler;
+mod streaming;
 mod target;
 
+pub use self::compress::TextureCompression;
+pub(crate) use self::compress::{ block_bytes, compress_rgba8 };
 pub use self::immutable::{ ImmutableTexture, TextureError };
-pub use self::target::TargetTexture;
-pub use image::ImageFormat;
+pub use self::lut::ColorLut;
+pub(crate) use self::lut::identity_lut;
+pub use self::sampler::SamplerSettings;
+pub use self::streaming::{ StreamingTexture, StreamingTextureError };
+pub use self::target::{ CubeFace, TargetCubemap, TargetTexture, TargetTextureArray };
+pub use crate::images::ImageFormat;
+pub use vulkano::sampler::{ BorderColor, Filter, MipmapMode, SamplerAddressMode };
 use std::sync::Arc;
-use vulkano::image::ImageViewAccess;
+use vulkano::{ format::Format, image::ImageViewAccess };
 
 pub trait Texture {
 	fn image(&self) -> &Arc<ImageViewAccess + Send + Sync + 'static>;
 }
+
+/// How the color values in an image file are encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorEncoding {
+	/// Values are gamma encoded for display, as in most color textures and UI art. They're decoded to linear when
+	/// sampled.
+	Srgb,
+	/// Values are used as they are, as in normal maps, masks, and textures authored in linear space.
+	Linear,
+}
+impl ColorEncoding {
+	pub(crate) fn rgba8_format(self) -> Format {
+		match self {
+			ColorEncoding::Srgb => Format::R8G8B8A8Srgb,
+			ColorEncoding::Linear => Format::R8G8B8A8Unorm,
+		}
+	}
+}
+
+/// Whether the hardware converts between sRGB and linear when reading and writing images of this format.
+pub fn is_srgb(format: Format) -> bool {
+	match format {
+		Format::R8Srgb |
+		Format::R8G8Srgb |
+		Format::R8G8B8Srgb |
+		Format::B8G8R8Srgb |
+		Format::R8G8B8A8Srgb |
+		Format::B8G8R8A8Srgb |
+		Format::A8B8G8R8SrgbPack32 => true,
+		_ => false,
+	}
+}
+
+/// The sRGB version of a linear 8-bit color format, so images rendered and then sampled are decoded consistently.
+pub(crate) fn srgb_equivalent(format: Format) -> Format {
+	match format {
+		Format::R8G8B8A8Unorm => Format::R8G8B8A8Srgb,
+		Format::B8G8R8A8Unorm => Format::B8G8R8A8Srgb,
+		Format::A8B8G8R8UnormPack32 => Format::A8B8G8R8SrgbPack32,
+		format => format,
+	}
+}
@@ -0,0 +1,582 @@
+use crate::device::DeviceCtx;
+use crate::texture::ColorEncoding;
+use vulkano::format::Format;
+
+/// Whether 8-bit textures are compressed when they're loaded, set with `DeviceCtx::set_texture_compression`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureCompression {
+	/// Textures are uploaded as they're decoded, at 4 bytes per pixel.
+	None,
+	/// Textures are compressed to the best block format the device supports. Opaque images use BC1, at half a byte per
+	/// pixel, and images with any transparency use BC3, at one byte per pixel. Devices without BC support, like most
+	/// mobile GPUs, get ETC2 at the same sizes instead. Devices with neither fall back to uncompressed textures.
+	///
+	/// Encoding is a quick single pass over each 4x4 block, so load times grow with the size of the image and nothing
+	/// else, and the format chosen only depends on the device and the image's alpha channel. 16-bit and HDR images are
+	/// never compressed. ETC2 encoding tries a few more options per block than BC, so it's somewhat slower.
+	Auto,
+}
+impl Default for TextureCompression {
+	fn default() -> Self {
+		TextureCompression::None
+	}
+}
+
+/// Compresses RGBA8 pixels if the device is set to and supports it. Returns the blocks and their format, or `None` to
+/// upload the pixels as they are.
+pub(crate) fn compress_rgba8(
+	device: &DeviceCtx,
+	width: u32,
+	height: u32,
+	pixels: &[u8],
+	encoding: ColorEncoding,
+) -> Option<(Vec<u8>, Format)> {
+	if device.texture_compression() == TextureCompression::None {
+		return None;
+	}
+
+	let opaque = pixels.chunks(4).all(|pixel| pixel[3] == 255);
+	let format = block_format(device, opaque, encoding)?;
+	Some((encode(width, height, pixels, format), format))
+}
+
+/// Bytes per 4x4 block of the block formats `compress_rgba8` produces.
+pub(crate) fn block_bytes(format: Format) -> Option<u64> {
+	match format {
+		Format::BC1_RGBUnormBlock | Format::BC1_RGBSrgbBlock => Some(8),
+		Format::BC3UnormBlock | Format::BC3SrgbBlock => Some(16),
+		Format::ETC2_R8G8B8UnormBlock | Format::ETC2_R8G8B8SrgbBlock => Some(8),
+		Format::ETC2_R8G8B8A8UnormBlock | Format::ETC2_R8G8B8A8SrgbBlock => Some(16),
+		_ => None,
+	}
+}
+
+/// The first block format for the image that the device has enabled and can sample from, trying BC before ETC2.
+fn block_format(device: &DeviceCtx, opaque: bool, encoding: ColorEncoding) -> Option<Format> {
+	let candidates =
+		match (opaque, encoding) {
+			(true, ColorEncoding::Srgb) => [Format::BC1_RGBSrgbBlock, Format::ETC2_R8G8B8SrgbBlock],
+			(true, ColorEncoding::Linear) => [Format::BC1_RGBUnormBlock, Format::ETC2_R8G8B8UnormBlock],
+			(false, ColorEncoding::Srgb) => [Format::BC3SrgbBlock, Format::ETC2_R8G8B8A8SrgbBlock],
+			(false, ColorEncoding::Linear) => [Format::BC3UnormBlock, Format::ETC2_R8G8B8A8UnormBlock],
+		};
+	let features = device.enabled_features();
+	let physical_device = device.device().physical_device();
+	candidates.iter().cloned()
+		.filter(|&format| {
+			if is_etc2(format) { features.texture_compression_etc2 } else { features.texture_compression_bc }
+		})
+		.find(|format| format.properties(physical_device).optimal_tiling_features.sampled_image)
+}
+
+fn is_etc2(format: Format) -> bool {
+	match format {
+		Format::ETC2_R8G8B8UnormBlock |
+		Format::ETC2_R8G8B8SrgbBlock |
+		Format::ETC2_R8G8B8A8UnormBlock |
+		Format::ETC2_R8G8B8A8SrgbBlock => true,
+		_ => false,
+	}
+}
+
+/// Encodes RGBA8 pixels as one of the formats `block_format` picks. Formats with alpha write the alpha block before
+/// the color block, for both BC3 and ETC2.
+fn encode(width: u32, height: u32, pixels: &[u8], format: Format) -> Vec<u8> {
+	let etc2 = is_etc2(format);
+	let bytes = block_bytes(format).unwrap();
+	let alpha = bytes == 16;
+	let blocks_x = (width + 3) / 4;
+	let blocks_y = (height + 3) / 4;
+	let mut data = Vec::with_capacity((blocks_x * blocks_y) as usize * bytes as usize);
+	for by in 0..blocks_y {
+		for bx in 0..blocks_x {
+			let block = read_block(width, height, pixels, bx * 4, by * 4);
+			if etc2 {
+				if alpha {
+					encode_etc2_alpha(&block, &mut data);
+				}
+				encode_etc2_color(&block, &mut data);
+			} else {
+				if alpha {
+					encode_alpha(&block, &mut data);
+				}
+				encode_color(&block, &mut data);
+			}
+		}
+	}
+	data
+}
+
+/// Copies a 4x4 block of pixels. Blocks past the edge of the image repeat its last row and column.
+fn read_block(width: u32, height: u32, pixels: &[u8], x: u32, y: u32) -> [[u8; 4]; 16] {
+	let mut block = [[0; 4]; 16];
+	for (i, texel) in block.iter_mut().enumerate() {
+		let px = (x + i as u32 % 4).min(width - 1);
+		let py = (y + i as u32 / 4).min(height - 1);
+		let offset = (py * width + px) as usize * 4;
+		texel.copy_from_slice(&pixels[offset..offset + 4]);
+	}
+	block
+}
+
+/// Writes a BC1 color block, using the corners of the block's bounding box in color space as endpoints.
+fn encode_color(block: &[[u8; 4]; 16], out: &mut Vec<u8>) {
+	let mut min = [255u8; 3];
+	let mut max = [0u8; 3];
+	for texel in block {
+		for c in 0..3 {
+			min[c] = min[c].min(texel[c]);
+			max[c] = max[c].max(texel[c]);
+		}
+	}
+
+	// pull the endpoints in a little, since the extremes are usually outliers
+	for c in 0..3 {
+		let inset = (max[c] - min[c]) / 16;
+		min[c] += inset;
+		max[c] -= inset;
+	}
+
+	let mut color0 = to_565(max);
+	let mut color1 = to_565(min);
+	if color0 < color1 {
+		std::mem::swap(&mut color0, &mut color1);
+	}
+
+	let mut indices = 0u32;
+	if color0 != color1 {
+		let c0 = from_565(color0);
+		let c1 = from_565(color1);
+		let palette = [
+			c0,
+			c1,
+			[(2 * c0[0] + c1[0]) / 3, (2 * c0[1] + c1[1]) / 3, (2 * c0[2] + c1[2]) / 3],
+			[(c0[0] + 2 * c1[0]) / 3, (c0[1] + 2 * c1[1]) / 3, (c0[2] + 2 * c1[2]) / 3],
+		];
+		for (i, texel) in block.iter().enumerate() {
+			let distance = |color: &[u32; 3]| {
+				(0..3).map(|c| (color[c] as i32 - texel[c] as i32).pow(2)).sum::<i32>()
+			};
+			let index = (0..4).min_by_key(|&index| distance(&palette[index])).unwrap();
+			indices |= (index as u32) << (i * 2);
+		}
+	}
+
+	out.extend_from_slice(&color0.to_le_bytes());
+	out.extend_from_slice(&color1.to_le_bytes());
+	out.extend_from_slice(&indices.to_le_bytes());
+}
+
+/// Writes a BC3 alpha block, interpolating eight values between the block's lowest and highest alpha.
+fn encode_alpha(block: &[[u8; 4]; 16], out: &mut Vec<u8>) {
+	let min = block.iter().map(|texel| texel[3]).min().unwrap();
+	let max = block.iter().map(|texel| texel[3]).max().unwrap();
+	out.push(max);
+	out.push(min);
+
+	let mut indices = 0u64;
+	if max != min {
+		// with alpha0 > alpha1, index 0 is alpha0, 1 is alpha1, and 2 to 7 step from alpha0 toward alpha1
+		let palette =
+			(0..8u32)
+				.map(|index| match index {
+					0 => max as u32,
+					1 => min as u32,
+					index => ((8 - index) * max as u32 + (index - 1) * min as u32) / 7,
+				})
+				.collect::<Vec<_>>();
+		for (i, texel) in block.iter().enumerate() {
+			let index =
+				(0..8).min_by_key(|&index| (palette[index] as i32 - texel[3] as i32).abs()).unwrap();
+			indices |= (index as u64) << (i * 3);
+		}
+	}
+	out.extend_from_slice(&indices.to_le_bytes()[..6]);
+}
+
+/// The luminance offsets of each ETC1/ETC2 table, as the small and large offset. Each texel adds or subtracts one.
+const ETC_TABLES: [[i32; 2]; 8] = [[2, 8], [5, 17], [9, 29], [13, 42], [18, 60], [24, 80], [33, 106], [47, 183]];
+
+/// The alpha offsets of each EAC table, in index order.
+const EAC_TABLES: [[i32; 8]; 16] = [
+	[-3, -6, -9, -15, 2, 5, 8, 14],
+	[-3, -7, -10, -13, 2, 6, 9, 12],
+	[-2, -5, -8, -13, 1, 4, 7, 12],
+	[-2, -4, -6, -13, 1, 3, 5, 12],
+	[-3, -6, -8, -12, 2, 5, 7, 11],
+	[-3, -7, -9, -11, 2, 6, 8, 10],
+	[-4, -7, -8, -11, 3, 6, 7, 10],
+	[-3, -5, -8, -11, 2, 4, 7, 10],
+	[-2, -6, -8, -10, 1, 5, 7, 9],
+	[-2, -5, -8, -10, 1, 4, 7, 9],
+	[-2, -4, -8, -10, 1, 3, 7, 9],
+	[-2, -5, -7, -10, 1, 4, 6, 9],
+	[-3, -4, -7, -10, 2, 3, 6, 9],
+	[-1, -2, -3, -10, 0, 1, 2, 9],
+	[-4, -6, -8, -9, 3, 5, 7, 8],
+	[-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+/// Writes an ETC2 color block in ETC1's individual or differential mode, which every ETC2 decoder reads the same way.
+/// The block is split in half both ways, and whichever split has less error is kept.
+fn encode_etc2_color(block: &[[u8; 4]; 16], out: &mut Vec<u8>) {
+	let (_, bits) =
+		[false, true].iter()
+			.map(|&flip| etc_halves(block, flip))
+			.min_by_key(|&(error, _)| error)
+			.unwrap();
+	out.extend_from_slice(&bits.to_be_bytes());
+}
+
+/// Encodes a block split into left and right halves, or top and bottom halves if `flip` is set. Returns the error and
+/// the block's bits.
+fn etc_halves(block: &[[u8; 4]; 16], flip: bool) -> (u32, u64) {
+	// texels are stored by row, but ETC numbers them by column
+	let in_second = |x: usize, y: usize| if flip { y >= 2 } else { x >= 2 };
+	let mut sums = [[0u32; 3]; 2];
+	for (i, texel) in block.iter().enumerate() {
+		let half = in_second(i % 4, i / 4) as usize;
+		for c in 0..3 {
+			sums[half][c] += texel[c] as u32;
+		}
+	}
+	let average = |half: usize, c: usize| sums[half][c] as f32 / 8.0;
+
+	// differential mode keeps 5 bits per channel when the halves are close enough, and individual mode has 4
+	let quantize = |half: usize, bits: u32| {
+		let channel = |c: usize| (average(half, c) * ((1 << bits) - 1) as f32 / 255.0).round() as i32;
+		[channel(0), channel(1), channel(2)]
+	};
+	let (first, second) = (quantize(0, 5), quantize(1, 5));
+	let deltas = [second[0] - first[0], second[1] - first[1], second[2] - first[2]];
+	let differential = deltas.iter().all(|&delta| delta >= -4 && delta <= 3);
+	let (first, second, bases) =
+		if differential {
+			(first, deltas, [etc_expand(first, 5), etc_expand(second, 5)])
+		} else {
+			let (first, second) = (quantize(0, 4), quantize(1, 4));
+			(first, second, [etc_expand(first, 4), etc_expand(second, 4)])
+		};
+
+	let mut error = 0;
+	let mut tables = [0u64; 2];
+	let mut indices = [0u8; 16];
+	for half in 0..2 {
+		let texels = (0..16).filter(|&i| in_second(i % 4, i / 4) == (half == 1)).collect::<Vec<_>>();
+		let (table_error, table, table_indices) =
+			(0..8)
+				.map(|table| {
+					let mut table_error = 0;
+					let mut table_indices = vec![];
+					for &i in &texels {
+						let (texel_error, index) = etc_closest(bases[half], ETC_TABLES[table], block[i]);
+						table_error += texel_error;
+						table_indices.push((i, index));
+					}
+					(table_error, table, table_indices)
+				})
+				.min_by_key(|&(table_error, _, _)| table_error)
+				.unwrap();
+		error += table_error;
+		tables[half] = table as u64;
+		for (i, index) in table_indices {
+			indices[i] = index;
+		}
+	}
+
+	let mut bits = 0u64;
+	for c in 0..3 {
+		let channel =
+			if differential {
+				(first[c] as u64) << 3 | (second[c] as u64 & 0x7)
+			} else {
+				(first[c] as u64) << 4 | second[c] as u64
+			};
+		bits |= channel << (56 - c * 8);
+	}
+	bits |= tables[0] << 37 | tables[1] << 34 | (differential as u64) << 33 | (flip as u64) << 32;
+	for (i, &index) in indices.iter().enumerate() {
+		let texel = (i % 4) * 4 + i / 4;
+		bits |= ((index >> 1) as u64) << (16 + texel) | ((index & 1) as u64) << texel;
+	}
+	(error, bits)
+}
+
+/// Widens a base color with 4 or 5 bits per channel to 8 bits, the way the decoder does.
+fn etc_expand(color: [i32; 3], bits: u32) -> [i32; 3] {
+	let expand = |c: i32| c << (8 - bits) | c >> (2 * bits - 8);
+	[expand(color[0]), expand(color[1]), expand(color[2])]
+}
+
+/// Finds the offset in an ETC table that brings `base` closest to the texel. Returns the squared error and the texel's
+/// index, where 0 and 1 add the small and large offsets and 2 and 3 subtract them.
+fn etc_closest(base: [i32; 3], table: [i32; 2], texel: [u8; 4]) -> (u32, u8) {
+	(0..4u8)
+		.map(|index| {
+			let offset = if index & 2 == 0 { table[index as usize & 1] } else { -table[index as usize & 1] };
+			let error =
+				(0..3)
+					.map(|c| ((base[c] + offset).max(0).min(255) - texel[c] as i32).pow(2) as u32)
+					.sum::<u32>();
+			(error, index)
+		})
+		.min_by_key(|&(error, _)| error)
+		.unwrap()
+}
+
+/// Writes an EAC alpha block for ETC2 RGBA8. Each table is scaled to span the block's range of alpha, and the one with
+/// the least error is kept.
+fn encode_etc2_alpha(block: &[[u8; 4]; 16], out: &mut Vec<u8>) {
+	let min = block.iter().map(|texel| texel[3] as i32).min().unwrap();
+	let max = block.iter().map(|texel| texel[3] as i32).max().unwrap();
+
+	let (_, bits) =
+		EAC_TABLES.iter().enumerate()
+			.map(|(table_index, table)| {
+				let span = table[3].abs() + table[7];
+				let multiplier = ((max - min + span - 1) / span).max(1).min(15);
+				let base = ((max + min) as f32 / 2.0 - (table[3] + table[7]) as f32 * multiplier as f32 / 2.0).round();
+				let base = (base as i32).max(0).min(255);
+
+				let mut error = 0;
+				let mut bits = (base as u64) << 56 | (multiplier as u64) << 52 | (table_index as u64) << 48;
+				for (i, texel) in block.iter().enumerate() {
+					let (texel_error, index) =
+						(0..8)
+							.map(|index| {
+								let alpha = (base + table[index] * multiplier).max(0).min(255);
+								((alpha - texel[3] as i32).pow(2) as u32, index)
+							})
+							.min_by_key(|&(error, _)| error)
+							.unwrap();
+					error += texel_error;
+					// texels are numbered by column, with the first in the highest bits
+					let texel = (i % 4) * 4 + i / 4;
+					bits |= (index as u64) << (45 - texel * 3);
+				}
+				(error, bits)
+			})
+			.min_by_key(|&(error, _)| error)
+			.unwrap();
+	out.extend_from_slice(&bits.to_be_bytes());
+}
+
+fn to_565(color: [u8; 3]) -> u16 {
+	(color[0] as u16 >> 3) << 11 | (color[1] as u16 >> 2) << 5 | color[2] as u16 >> 3
+}
+
+fn from_565(color: u16) -> [u32; 3] {
+	let r = (color >> 11) as u32 & 0x1F;
+	let g = (color >> 5) as u32 & 0x3F;
+	let b = color as u32 & 0x1F;
+	[r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn decode_bc1(bytes: &[u8]) -> [[u8; 3]; 16] {
+		let color0 = u16::from_le_bytes([bytes[0], bytes[1]]);
+		let color1 = u16::from_le_bytes([bytes[2], bytes[3]]);
+		let (c0, c1) = (from_565(color0), from_565(color1));
+		let mix = |a: u32, b: u32, c: usize| ((a * c0[c] + b * c1[c]) / (a + b)) as u8;
+		let palette =
+			if color0 > color1 {
+				[0, 1, 2, 3].iter()
+					.map(|&index| match index {
+						0 => [c0[0] as u8, c0[1] as u8, c0[2] as u8],
+						1 => [c1[0] as u8, c1[1] as u8, c1[2] as u8],
+						2 => [mix(2, 1, 0), mix(2, 1, 1), mix(2, 1, 2)],
+						_ => [mix(1, 2, 0), mix(1, 2, 1), mix(1, 2, 2)],
+					})
+					.collect::<Vec<_>>()
+			} else {
+				vec![
+					[c0[0] as u8, c0[1] as u8, c0[2] as u8],
+					[c1[0] as u8, c1[1] as u8, c1[2] as u8],
+					[mix(1, 1, 0), mix(1, 1, 1), mix(1, 1, 2)],
+					[0, 0, 0],
+				]
+			};
+		let indices = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+		let mut texels = [[0; 3]; 16];
+		for (i, texel) in texels.iter_mut().enumerate() {
+			*texel = palette[(indices >> (i * 2) & 3) as usize];
+		}
+		texels
+	}
+
+	fn decode_bc3_alpha(bytes: &[u8]) -> [u8; 16] {
+		let (a0, a1) = (bytes[0] as u32, bytes[1] as u32);
+		let palette =
+			(0..8u32)
+				.map(|index| match index {
+					0 => a0,
+					1 => a1,
+					index if a0 > a1 => ((8 - index) * a0 + (index - 1) * a1) / 7,
+					6 => 0,
+					7 => 255,
+					index => ((6 - index) * a0 + (index - 1) * a1) / 5,
+				})
+				.collect::<Vec<_>>();
+		let mut word = [0; 8];
+		word[..6].copy_from_slice(&bytes[2..8]);
+		let indices = u64::from_le_bytes(word);
+		let mut alphas = [0; 16];
+		for (i, alpha) in alphas.iter_mut().enumerate() {
+			*alpha = palette[(indices >> (i * 3) & 7) as usize] as u8;
+		}
+		alphas
+	}
+
+	fn decode_etc2_color(bytes: &[u8]) -> [[u8; 3]; 16] {
+		let mut word = [0; 8];
+		word.copy_from_slice(&bytes[..8]);
+		let bits = u64::from_be_bytes(word);
+		let differential = bits >> 33 & 1 == 1;
+		let flip = bits >> 32 & 1 == 1;
+
+		let mut bases = [[0; 3]; 2];
+		for c in 0..3 {
+			let byte = (bits >> (56 - c * 8)) as i32 & 0xFF;
+			let (first, second) =
+				if differential {
+					let first = byte >> 3;
+					let second = first + ((byte & 7) << 29 >> 29);
+					assert!(second >= 0 && second < 32, "the block uses an ETC2-only mode");
+					(first << 3 | first >> 2, second << 3 | second >> 2)
+				} else {
+					(byte >> 4 << 4 | byte >> 4, (byte & 0xF) << 4 | byte & 0xF)
+				};
+			bases[0][c] = first;
+			bases[1][c] = second;
+		}
+		let tables = [ETC_TABLES[(bits >> 37 & 7) as usize], ETC_TABLES[(bits >> 34 & 7) as usize]];
+
+		let mut texels = [[0; 3]; 16];
+		for (i, texel) in texels.iter_mut().enumerate() {
+			let (x, y) = (i % 4, i / 4);
+			let half = if flip { y >= 2 } else { x >= 2 } as usize;
+			let p = x * 4 + y;
+			let magnitude = tables[half][(bits >> p & 1) as usize];
+			let offset = if bits >> (16 + p) & 1 == 1 { -magnitude } else { magnitude };
+			for c in 0..3 {
+				texel[c] = (bases[half][c] + offset).max(0).min(255) as u8;
+			}
+		}
+		texels
+	}
+
+	fn decode_eac(bytes: &[u8]) -> [u8; 16] {
+		let mut word = [0; 8];
+		word.copy_from_slice(&bytes[..8]);
+		let bits = u64::from_be_bytes(word);
+		let base = (bits >> 56) as i32;
+		let multiplier = (bits >> 52 & 0xF) as i32;
+		let table = EAC_TABLES[(bits >> 48 & 0xF) as usize];
+
+		let mut alphas = [0; 16];
+		for (i, alpha) in alphas.iter_mut().enumerate() {
+			let p = (i % 4) * 4 + i / 4;
+			let index = (bits >> (45 - p * 3) & 7) as usize;
+			*alpha = (base + table[index] * multiplier).max(0).min(255) as u8;
+		}
+		alphas
+	}
+
+	/// Decodes every block back to RGBA8, leaving out texels past the edge of the image.
+	fn decode(width: u32, height: u32, data: &[u8], format: Format) -> Vec<u8> {
+		let bytes = block_bytes(format).unwrap() as usize;
+		let blocks_x = ((width + 3) / 4) as usize;
+		let (width, height) = (width as usize, height as usize);
+		let mut pixels = vec![0; width * height * 4];
+		for (b, block) in data.chunks(bytes).enumerate() {
+			let (alpha, color) = if bytes == 16 { block.split_at(8) } else { (&[][..], block) };
+			let colors = if is_etc2(format) { decode_etc2_color(color) } else { decode_bc1(color) };
+			let alphas =
+				match (alpha.is_empty(), is_etc2(format)) {
+					(true, _) => [255; 16],
+					(false, true) => decode_eac(alpha),
+					(false, false) => decode_bc3_alpha(alpha),
+				};
+			for i in 0..16 {
+				let x = (b % blocks_x) * 4 + i % 4;
+				let y = (b / blocks_x) * 4 + i / 4;
+				if x < width && y < height {
+					let offset = (y * width + x) * 4;
+					pixels[offset..offset + 3].copy_from_slice(&colors[i]);
+					pixels[offset + 3] = alphas[i];
+				}
+			}
+		}
+		pixels
+	}
+
+	/// Encodes and decodes an image, and returns the largest difference in any channel of any pixel.
+	fn round_trip(width: u32, height: u32, pixels: &[u8], format: Format) -> u8 {
+		let data = encode(width, height, pixels, format);
+		let blocks = ((width + 3) / 4 * ((height + 3) / 4)) as u64;
+		assert_eq!(data.len() as u64, blocks * block_bytes(format).unwrap());
+
+		let decoded = decode(width, height, &data, format);
+		pixels.iter().zip(&decoded).map(|(&a, &b)| (a as i32 - b as i32).abs() as u8).max().unwrap()
+	}
+
+	fn solid() -> Vec<u8> {
+		[200, 120, 40, 255].iter().cloned().cycle().take(16 * 4).collect()
+	}
+
+	fn gradient() -> Vec<u8> {
+		(0..16u8).flat_map(|i| vec![i * 17, i * 17, i * 17, 255]).collect()
+	}
+
+	fn alpha_ramp() -> Vec<u8> {
+		(0..16u8).flat_map(|i| vec![100, 150, 200, i * 17]).collect()
+	}
+
+	/// A 5x3 image, red except for a blue last column, so the second block is mostly past the edge.
+	fn uneven() -> Vec<u8> {
+		(0..15).flat_map(|i| if i % 5 < 4 { vec![255, 0, 0, 255] } else { vec![0, 0, 255, 255] }).collect()
+	}
+
+	#[test]
+	fn bc1_solid() {
+		assert!(round_trip(4, 4, &solid(), Format::BC1_RGBUnormBlock) <= 8);
+	}
+
+	#[test]
+	fn bc1_gradient() {
+		assert!(round_trip(4, 4, &gradient(), Format::BC1_RGBUnormBlock) <= 40);
+	}
+
+	#[test]
+	fn bc3_alpha() {
+		assert!(round_trip(4, 4, &alpha_ramp(), Format::BC3UnormBlock) <= 20);
+	}
+
+	#[test]
+	fn bc1_uneven_size() {
+		assert!(round_trip(5, 3, &uneven(), Format::BC1_RGBUnormBlock) <= 8);
+	}
+
+	#[test]
+	fn etc2_solid() {
+		assert!(round_trip(4, 4, &solid(), Format::ETC2_R8G8B8UnormBlock) <= 8);
+	}
+
+	#[test]
+	fn etc2_gradient() {
+		assert!(round_trip(4, 4, &gradient(), Format::ETC2_R8G8B8UnormBlock) <= 40);
+	}
+
+	#[test]
+	fn etc2_alpha() {
+		assert!(round_trip(4, 4, &alpha_ramp(), Format::ETC2_R8G8B8A8UnormBlock) <= 20);
+	}
+
+	#[test]
+	fn etc2_uneven_size() {
+		assert!(round_trip(5, 3, &uneven(), Format::ETC2_R8G8B8UnormBlock) <= 8);
+	}
+}
@@ -1,12 +1,15 @@
 use crate::cpu_pool::{ spawn_cpu, spawn_fs };
-use crate::texture::Texture;
+use crate::device::{ DeviceCtx, MemoryCategory, MemoryHandle, image_bytes };
+use crate::images::{ self, Pixels };
+use crate::progress::{ CancelToken, Cancelled, LoadProgress, LoadStage };
+use crate::texture::{ ColorEncoding, Texture, compress_rgba8 };
+use crate::vfs;
 use crate::window::Window;
 use futures::prelude::*;
-use image::{ self, ImageError, ImageFormat };
-use std::{ fs::File, io::{ self, prelude::* }, path::Path, sync::Arc };
+use image::{ ImageError, ImageFormat };
+use std::{ error::Error, fmt, io, path::Path, sync::Arc };
 use vulkano::{
 	OomError,
-	device::Queue,
 	format::{ AcceptsPixels, Format },
 	image::{ Dimensions, ImageCreationError, ImageViewAccess, ImmutableImage },
 	memory::DeviceMemoryAllocError,
@@ -16,6 +19,7 @@ use vulkano::{
 #[derive(Clone)]
 pub struct ImmutableTexture {
 	image: Arc<ImageViewAccess + Send + Sync + 'static>,
+	_memory: Option<Arc<MemoryHandle>>,
 }
 impl ImmutableTexture {
 	pub fn from_data<I, P>(window: &Window, data: I) -> Result<(Self, impl GpuFuture), TextureError>
@@ -27,52 +31,112 @@ impl ImmutableTexture {
 				Format::R8G8B8A8Unorm,
 				window.device().queue().clone(),
 			)?;
+		let memory =
+			window.device().memory_stats()
+				.track(MemoryCategory::Textures, "texture data", image_bytes([1, 1], Format::R8G8B8A8Unorm));
 
-		Ok((Self { image: image }, future))
+		Ok((Self { image: image, _memory: Some(Arc::new(memory)) }, future))
 	}
 
+	/// Loads an image file, in the format its extension implies. Files with unknown extensions are identified by their
+	/// contents.
+	pub fn from_file<P>(
+		window: &Window,
+		path: P,
+		encoding: ColorEncoding,
+	) -> impl Future<Output = Result<(Self, impl GpuFuture), TextureError>>
+	where P: AsRef<Path> + Send + 'static {
+		let format = images::format_from_path(&path);
+		Self::from_file_impl(window.device().clone(), path, format, encoding, LoadProgress::new())
+	}
+
+	/// Loads an image file. 8-bit images use the encoding, but 16-bit PNGs, HDR files and EXR files are always linear.
 	pub fn from_file_with_format<P>(
 		window: &Window,
 		path: P,
 		format: ImageFormat,
-		srgb: bool,
+		encoding: ColorEncoding,
 	) -> impl Future<Output = Result<(Self, impl GpuFuture), TextureError>>
 	where P: AsRef<Path> + Send + 'static {
-		Self::from_file_with_format_impl(window.device().queue().clone(), path, format, srgb)
+		Self::from_file_with_format_impl(window.device().clone(), path, format, encoding, LoadProgress::new())
+	}
+
+	/// Like `from_file_with_format`, but also returns a handle for showing how far along the load is, and stops with
+	/// `TextureError::Cancelled` if the token is cancelled first.
+	pub fn from_file_with_progress<P>(
+		window: &Window,
+		path: P,
+		format: ImageFormat,
+		encoding: ColorEncoding,
+		cancel: &CancelToken,
+	) -> (LoadProgress, impl Future<Output = Result<(Self, impl GpuFuture), TextureError>>)
+	where P: AsRef<Path> + Send + 'static {
+		let progress = LoadProgress::with_cancel_token(cancel.clone());
+		(progress.clone(), Self::from_file_with_format_impl(window.device().clone(), path, format, encoding, progress))
 	}
 
 	pub(crate) fn from_file_with_format_impl<P>(
-		queue: Arc<Queue>,
+		device: Arc<DeviceCtx>,
 		path: P,
 		format: ImageFormat,
-		srgb: bool,
+		encoding: ColorEncoding,
+		progress: LoadProgress,
 	) -> impl Future<Output = Result<(Self, impl GpuFuture), TextureError>>
 	where P: AsRef<Path> + Send + 'static {
-		spawn_fs(|| {
-			let mut bytes = vec![];
-			File::open(path)?.read_to_end(&mut bytes)?;
-			Ok(bytes)
-		})
+		Self::from_file_impl(device, path, Some(format), encoding, progress)
+	}
+
+	fn from_file_impl<P>(
+		device: Arc<DeviceCtx>,
+		path: P,
+		format: Option<ImageFormat>,
+		encoding: ColorEncoding,
+		progress: LoadProgress,
+	) -> impl Future<Output = Result<(Self, impl GpuFuture), TextureError>>
+	where P: AsRef<Path> + Send + 'static {
+		let name = path.as_ref().display().to_string();
+		let read_progress = progress.clone();
+		spawn_fs(move || vfs::read_with_progress(path, &read_progress))
 			.then(move |bytes: Result<Vec<u8>, io::Error>| spawn_cpu(move || {
+				progress.check_cancelled()?;
 				let bytes = bytes?;
-				let img = image::load_from_memory_with_format(&bytes, format)?.to_rgba();
-				let (width, height) = img.dimensions();
-				let img = img.into_raw();
+				progress.set_stage(LoadStage::Parsing);
+				let img = images::decode(&bytes, format)?;
+				let (width, height) = (img.width, img.height);
+				let mut format = img.format(encoding);
+				let mut pixels = img.pixels;
+				if let Pixels::Rgba8(rgba) = &pixels {
+					if let Some((blocks, block_format)) = compress_rgba8(&device, width, height, rgba, encoding) {
+						pixels = Pixels::Rgba8(blocks);
+						format = block_format;
+					}
+				}
 
+				progress.check_cancelled()?;
+				progress.set_stage(LoadStage::Uploading);
+				let dimensions = Dimensions::Dim2d { width: width, height: height };
+				let queue = device.queue().clone();
 				let (img, future) =
-					ImmutableImage::from_iter(
-						img.into_iter(),
-						Dimensions::Dim2d { width: width, height: height },
-						if srgb { Format::R8G8B8A8Srgb } else { Format::R8G8B8A8Unorm },
-						queue,
-					)?;
+					match pixels {
+						// compressed blocks are uploaded as bytes too
+						Pixels::Rgba8(pixels) =>
+							ImmutableImage::from_iter(pixels.into_iter(), dimensions, format, queue)?,
+						Pixels::Rgba16(pixels) =>
+							ImmutableImage::from_iter(pixels.into_iter(), dimensions, format, queue)?,
+						Pixels::Rgba32F(pixels) =>
+							ImmutableImage::from_iter(pixels.into_iter(), dimensions, format, queue)?,
+					};
+				device.debug_names().name_image(&*img, &name);
+				let memory =
+					device.memory_stats().track(MemoryCategory::Textures, name, image_bytes([width, height], format));
 
-				Ok((Self { image: img }, future))
+				progress.set_stage(LoadStage::Done);
+				Ok((Self { image: img, _memory: Some(Arc::new(memory)) }, future))
 			}))
 	}
 
 	pub(crate) fn from_image(image: Arc<ImageViewAccess + Send + Sync + 'static>) -> Self {
-		Self { image: image }
+		Self { image: image, _memory: None }
 	}
 }
 impl Texture for ImmutableTexture {
@@ -88,6 +152,34 @@ pub enum TextureError {
 	DeviceLost,
 	DeviceMemoryAllocError(DeviceMemoryAllocError),
 	OomError(OomError),
+	/// The file isn't a valid `.cube` file or PNG LUT strip.
+	InvalidColorLut,
+	/// The load's `CancelToken` was cancelled.
+	Cancelled,
+}
+impl fmt::Display for TextureError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			TextureError::IoError(err) => write!(f, "i/o error: {}", err),
+			TextureError::ImageError(err) => write!(f, "image decoding failed: {}", err),
+			TextureError::DeviceLost => write!(f, "the device was lost"),
+			TextureError::DeviceMemoryAllocError(err) => write!(f, "device memory allocation failed: {}", err),
+			TextureError::OomError(err) => write!(f, "out of memory: {}", err),
+			TextureError::InvalidColorLut => write!(f, "invalid color lookup table"),
+			TextureError::Cancelled => write!(f, "the load was cancelled"),
+		}
+	}
+}
+impl Error for TextureError {
+	fn source(&self) -> Option<&(Error + 'static)> {
+		match self {
+			TextureError::IoError(err) => Some(err),
+			TextureError::ImageError(err) => Some(err),
+			TextureError::DeviceMemoryAllocError(err) => Some(err),
+			TextureError::OomError(err) => Some(err),
+			TextureError::DeviceLost | TextureError::InvalidColorLut | TextureError::Cancelled => None,
+		}
+	}
 }
 impl From<FlushError> for TextureError {
 	fn from(val: FlushError) -> Self {
@@ -110,6 +202,11 @@ impl From<ImageError> for TextureError {
 		TextureError::ImageError(val)
 	}
 }
+impl From<Cancelled> for TextureError {
+	fn from(_: Cancelled) -> Self {
+		TextureError::Cancelled
+	}
+}
 impl From<io::Error> for TextureError {
 	fn from(val: io::Error) -> Self {
 		TextureError::IoError(val)
@@ -0,0 +1,116 @@
+use crate::cpu_pool::{ spawn_cpu, spawn_fs };
+use crate::device::{ MemoryCategory, MemoryHandle };
+use crate::images::{ self, ImageFormat };
+use crate::texture::{ Texture, TextureError };
+use crate::vfs;
+use crate::window::Window;
+use futures::prelude::*;
+use std::{ io, path::Path, sync::Arc };
+use vulkano::{
+	format::Format,
+	image::{ Dimensions, ImageViewAccess, ImmutableImage },
+	sync::GpuFuture,
+};
+
+/// A 3D color lookup table for color grading. Inputs and outputs are sRGB encoded, as in most grading tools.
+#[derive(Clone)]
+pub struct ColorLut {
+	image: Arc<ImageViewAccess + Send + Sync + 'static>,
+	_memory: Arc<MemoryHandle>,
+}
+impl ColorLut {
+	/// Loads a LUT from a `.cube` file, or otherwise from a PNG strip of square slices laid out left to right, such as
+	/// the common 1024x32 layout for a 32x32x32 LUT. Red increases to the right within a slice, green increases
+	/// downward, and blue increases from slice to slice.
+	pub fn from_file<P>(window: &Window, path: P) -> impl Future<Output = Result<(Self, impl GpuFuture), TextureError>>
+	where P: AsRef<Path> + Send + 'static {
+		let device = window.device().clone();
+		let name = path.as_ref().display().to_string();
+		let is_cube = path.as_ref().extension().map_or(false, |ext| ext.eq_ignore_ascii_case("cube"));
+
+		spawn_fs(|| vfs::read(path))
+			.then(move |bytes: Result<Vec<u8>, io::Error>| spawn_cpu(move || {
+				let bytes = bytes?;
+				let (size, texels) = if is_cube { parse_cube(&bytes)? } else { parse_strip(&bytes)? };
+
+				let (image, future) =
+					ImmutableImage::from_iter(
+						texels.into_iter(),
+						Dimensions::Dim3d { width: size, height: size, depth: size },
+						Format::R8G8B8A8Unorm,
+						device.queue().clone(),
+					)?;
+				device.debug_names().name_image(&*image, &name);
+				let memory = device.memory_stats().track(MemoryCategory::Textures, name, 4 * (size as u64).pow(3));
+
+				Ok((Self { image: image, _memory: Arc::new(memory) }, future))
+			}))
+	}
+}
+impl Texture for ColorLut {
+	fn image(&self) -> &Arc<ImageViewAccess + Send + Sync + 'static> {
+		&self.image
+	}
+}
+
+/// The texels of a 2x2x2 LUT that maps every color to itself.
+pub(crate) fn identity_lut() -> Vec<[u8; 4]> {
+	(0..8).map(|i| [(i & 1) as u8 * 255, (i >> 1 & 1) as u8 * 255, (i >> 2 & 1) as u8 * 255, 255]).collect()
+}
+
+fn parse_strip(bytes: &[u8]) -> Result<(u32, Vec<[u8; 4]>), TextureError> {
+	let img = images::decode(bytes, Some(ImageFormat::PNG))?;
+	let (width, size) = (img.width, img.height);
+	if size == 0 || width != size * size {
+		return Err(TextureError::InvalidColorLut);
+	}
+	let pixels = img.into_rgba8();
+	let texel = |x: u32, y: u32| {
+		let i = (y * width + x) as usize * 4;
+		[pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]
+	};
+
+	let mut texels = Vec::with_capacity((size * size * size) as usize);
+	for b in 0..size {
+		for g in 0..size {
+			for r in 0..size {
+				texels.push(texel(b * size + r, g));
+			}
+		}
+	}
+	Ok((size, texels))
+}
+
+/// Parses the Adobe/Resolve `.cube` format. Only 3D LUTs with the default 0 to 1 domain are supported.
+fn parse_cube(bytes: &[u8]) -> Result<(u32, Vec<[u8; 4]>), TextureError> {
+	let text = String::from_utf8_lossy(bytes);
+	let mut size = None;
+	let mut texels = vec![];
+
+	for line in text.lines().map(|line| line.trim()) {
+		let mut words = line.split_whitespace();
+		match words.next() {
+			None => (),
+			Some(word) if word.starts_with('#') => (),
+			Some("TITLE") | Some("DOMAIN_MIN") | Some("DOMAIN_MAX") => (),
+			Some("LUT_3D_SIZE") => {
+				size = words.next().and_then(|size| size.parse::<u32>().ok());
+				if size.is_none() {
+					return Err(TextureError::InvalidColorLut);
+				}
+			},
+			Some(first) => {
+				let mut channel = |word: Option<&str>| -> Result<u8, TextureError> {
+					let value = word.and_then(|word| word.parse::<f32>().ok()).ok_or(TextureError::InvalidColorLut)?;
+					Ok((value.max(0.0).min(1.0) * 255.0).round() as u8)
+				};
+				texels.push([channel(Some(first))?, channel(words.next())?, channel(words.next())?, 255]);
+			},
+		}
+	}
+
+	match size {
+		Some(size) if size > 0 && texels.len() == (size * size * size) as usize => Ok((size, texels)),
+		_ => Err(TextureError::InvalidColorLut),
+	}
+}
@@ -0,0 +1,56 @@
+use log::{ log, warn };
+use std::sync::Arc;
+use vulkano::{
+	device::Device,
+	sampler::{ Filter, MipmapMode, Sampler, SamplerAddressMode, SamplerCreationError },
+};
+
+/// How textures are filtered and addressed when they're sampled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplerSettings {
+	pub mag_filter: Filter,
+	pub min_filter: Filter,
+	pub mipmap_mode: MipmapMode,
+	/// Maximum anisotropy. 1.0 disables anisotropic filtering. Clamped to what the device supports.
+	pub anisotropy: f32,
+	pub address_mode: SamplerAddressMode,
+	/// Added to the mip level textures are sampled at. Positive values blur textures, and negative values sharpen
+	/// them. It only affects textures with mipmaps.
+	pub lod_bias: f32,
+}
+impl SamplerSettings {
+	pub(crate) fn build(&self, device: &Arc<Device>) -> Result<Arc<Sampler>, SamplerCreationError> {
+		let mut anisotropy = self.anisotropy.max(1.0);
+		if anisotropy > 1.0 {
+			if device.enabled_features().sampler_anisotropy {
+				anisotropy = anisotropy.min(device.physical_device().limits().max_sampler_anisotropy());
+			} else {
+				warn!("Anisotropic filtering is not supported by this device, so it will be disabled");
+				anisotropy = 1.0;
+			}
+		}
+
+		Sampler::new(
+			device.clone(),
+			self.mag_filter,
+			self.min_filter,
+			self.mipmap_mode,
+			self.address_mode,
+			self.address_mode,
+			self.address_mode,
+			self.lod_bias, anisotropy, 0.0, 1000.0
+		)
+	}
+}
+impl Default for SamplerSettings {
+	fn default() -> Self {
+		Self {
+			mag_filter: Filter::Linear,
+			min_filter: Filter::Linear,
+			mipmap_mode: MipmapMode::Nearest,
+			anisotropy: 1.0,
+			address_mode: SamplerAddressMode::Repeat,
+			lod_bias: 0.0,
+		}
+	}
+}
@@ -0,0 +1,159 @@
+use crate::device::{ MemoryCategory, MemoryHandle, image_bytes };
+use crate::texture::{ ColorEncoding, Texture };
+use crate::window::Window;
+use std::{ iter, sync::Arc };
+use vulkano::{
+	OomError,
+	buffer::{ BufferUsage, CpuAccessibleBuffer },
+	command_buffer::{ AutoCommandBufferBuilder, BuildError, CommandBuffer, CommandBufferExecError },
+	format::Format,
+	image::{ Dimensions, ImageCreationError, ImageUsage, ImageViewAccess, StorageImage },
+	memory::DeviceMemoryAllocError,
+};
+
+/// Staging buffers are reused in turn, so a new frame can be written while the GPU is still copying the last ones.
+const STAGING_BUFFERS: usize = 3;
+
+/// A texture whose pixels are replaced from the CPU every frame, for video playback, webcam feeds, or procedurally
+/// generated images. It can be used anywhere an `ImmutableTexture` can.
+pub struct StreamingTexture {
+	image: Arc<ImageViewAccess + Send + Sync + 'static>,
+	storage: Arc<StorageImage<Format>>,
+	dimensions: [u32; 2],
+	staging: Vec<Arc<CpuAccessibleBuffer<[u8]>>>,
+	next_staging: usize,
+	_memory: MemoryHandle,
+	_staging_memory: MemoryHandle,
+}
+impl StreamingTexture {
+	/// Creates an RGBA8 texture. Its contents are undefined until the first `update`.
+	pub fn new(window: &Window, dimensions: [u32; 2], encoding: ColorEncoding) -> Result<Self, StreamingTextureError> {
+		let device = window.device();
+		let format = encoding.rgba8_format();
+		let usage = ImageUsage { transfer_destination: true, sampled: true, ..ImageUsage::none() };
+		let storage =
+			StorageImage::with_usage(
+				device.device().clone(),
+				Dimensions::Dim2d { width: dimensions[0], height: dimensions[1] },
+				format,
+				usage,
+				Some(device.queue().family()),
+			)?;
+		device.debug_names().name_image(&*storage, "streaming texture");
+
+		let frame_bytes = image_bytes(dimensions, format);
+		let staging =
+			(0..STAGING_BUFFERS)
+				.map(|_| Self::create_staging(window, frame_bytes as usize))
+				.collect::<Result<Vec<_>, _>>()?;
+
+		Ok(Self {
+			image: storage.clone(),
+			storage: storage,
+			dimensions: dimensions,
+			staging: staging,
+			next_staging: 0,
+			_memory: device.memory_stats().track(MemoryCategory::Textures, "streaming texture", frame_bytes),
+			_staging_memory:
+				device.memory_stats()
+					.track(MemoryCategory::Buffers, "streaming texture staging", frame_bytes * STAGING_BUFFERS as u64),
+		})
+	}
+
+	pub fn dimensions(&self) -> [u32; 2] {
+		self.dimensions
+	}
+
+	/// Replaces the texture's pixels. `data` is tightly packed RGBA8, row by row from the top, and must fill the whole
+	/// texture. The copy is joined to the window's frame, so it's finished before the next frame is drawn.
+	pub fn update(&mut self, window: &mut Window, data: &[u8]) -> Result<(), StreamingTextureError> {
+		let expected = self.dimensions[0] as usize * self.dimensions[1] as usize * 4;
+		if data.len() != expected {
+			return Err(StreamingTextureError::WrongDataLength { expected: expected, actual: data.len() });
+		}
+
+		let index = self.next_staging;
+		self.next_staging = (self.next_staging + 1) % STAGING_BUFFERS;
+
+		// if the GPU is running far enough behind that it's still reading this buffer, it gets a fresh one instead
+		let written =
+			match self.staging[index].write() {
+				Ok(mut mapped) => {
+					mapped.copy_from_slice(data);
+					true
+				},
+				Err(_) => false,
+			};
+		if !written {
+			let buffer = Self::create_staging(window, expected)?;
+			buffer.write().unwrap().copy_from_slice(data);
+			self.staging[index] = buffer;
+		}
+
+		let queue = window.device().queue().clone();
+		let cmd =
+			AutoCommandBufferBuilder::primary_one_time_submit(window.device().device().clone(), queue.family())?
+				.copy_buffer_to_image(self.staging[index].clone(), self.storage.clone())
+				.unwrap()
+				.build()?;
+
+		window.join_future(cmd.execute(queue)?);
+		Ok(())
+	}
+
+	fn create_staging(window: &Window, len: usize) -> Result<Arc<CpuAccessibleBuffer<[u8]>>, DeviceMemoryAllocError> {
+		CpuAccessibleBuffer::from_iter(
+			window.device().device().clone(),
+			BufferUsage::transfer_source(),
+			iter::repeat(0).take(len),
+		)
+	}
+}
+impl Texture for StreamingTexture {
+	fn image(&self) -> &Arc<ImageViewAccess + Send + Sync + 'static> {
+		&self.image
+	}
+}
+
+#[derive(Debug)]
+pub enum StreamingTextureError {
+	/// The data passed to `update` isn't the size of one RGBA8 frame.
+	WrongDataLength { expected: usize, actual: usize },
+	CommandBufferExecError(CommandBufferExecError),
+	DeviceMemoryAllocError(DeviceMemoryAllocError),
+	/// The texture's image couldn't be created, such as when it's larger than the device allows.
+	ImageCreationError(ImageCreationError),
+	BuildError(BuildError),
+	OomError(OomError),
+}
+impl From<CommandBufferExecError> for StreamingTextureError {
+	fn from(val: CommandBufferExecError) -> Self {
+		StreamingTextureError::CommandBufferExecError(val)
+	}
+}
+impl From<DeviceMemoryAllocError> for StreamingTextureError {
+	fn from(val: DeviceMemoryAllocError) -> Self {
+		StreamingTextureError::DeviceMemoryAllocError(val)
+	}
+}
+impl From<ImageCreationError> for StreamingTextureError {
+	fn from(val: ImageCreationError) -> Self {
+		match val {
+			ImageCreationError::AllocError(err) => StreamingTextureError::DeviceMemoryAllocError(err),
+			val => StreamingTextureError::ImageCreationError(val),
+		}
+	}
+}
+impl From<BuildError> for StreamingTextureError {
+	fn from(val: BuildError) -> Self {
+		match val {
+			BuildError::OomError(err) => StreamingTextureError::OomError(err),
+			val => StreamingTextureError::BuildError(val),
+		}
+	}
+}
+impl From<OomError> for StreamingTextureError {
+	fn from(val: OomError) -> Self {
+		StreamingTextureError::OomError(val)
+	}
+}
@@ -1,22 +1,27 @@
 use crate::{ ObjectIdRoot, RenderTarget };
-use crate::texture::Texture;
+use crate::batch::BatchError;
+use crate::camera::{ AsymmetricFov, Camera };
+use crate::device::{ MemoryCategory, MemoryHandle, image_bytes };
+use crate::texture::{ Texture, srgb_equivalent };
 use crate::window::Window;
+use cgmath::{ vec3, Matrix3, Quaternion, Vector3 };
 use std::sync::Arc;
 use vulkano::{
 	format::Format,
 	image::{ AttachmentImage, ImageCreationError, ImageViewAccess },
-	memory::DeviceMemoryAllocError,
 };
 
 pub struct TargetTexture {
 	image: [Arc<ImageViewAccess + Send + Sync + 'static>; 1],
 	id_root: ObjectIdRoot,
+	_memory: MemoryHandle,
 }
 impl TargetTexture {
-	pub fn new(window: &Window, dimensions: [u32; 2]) -> Result<Self, DeviceMemoryAllocError> {
-		AttachmentImage::sampled(window.device().device().clone(), dimensions, window.format())
-			.map(|image| Self { image: [image], id_root: ObjectIdRoot::new() })
-			.map_err(|err| match err { ImageCreationError::AllocError(err) => err, _ => unreachable!() })
+	/// Creates a target with the window's format, but always sRGB encoded, so it looks the same once it's drawn to the
+	/// window.
+	pub fn new(window: &Window, dimensions: [u32; 2]) -> Result<Self, BatchError> {
+		let (images, memory) = make_images(window, dimensions, 1, "target texture")?;
+		Ok(Self { image: [images[0].clone()], id_root: ObjectIdRoot::new(), _memory: memory })
 	}
 }
 impl RenderTarget for TargetTexture {
@@ -37,3 +42,142 @@ impl Texture for TargetTexture {
 		&self.image[0]
 	}
 }
+
+/// A target with one image for each face of a cube, for reflection probes and point light shadows. The image number
+/// passed to a batch's `commands` is the face's index, in the order of `CubeFace::ALL`, and `MeshBatch` can draw every
+/// face at once with `commands_cubemap`. Faces are drawn the way cube map lookups expect.
+pub struct TargetCubemap {
+	faces: [Arc<ImageViewAccess + Send + Sync + 'static>; 6],
+	id_root: ObjectIdRoot,
+	_memory: MemoryHandle,
+}
+impl TargetCubemap {
+	/// Creates a cube with square faces `size` pixels wide, in the same format as `TargetTexture`.
+	pub fn new(window: &Window, size: u32) -> Result<Self, BatchError> {
+		let (images, memory) = make_images(window, [size, size], 6, "target cubemap")?;
+		let faces = [
+			images[0].clone(),
+			images[1].clone(),
+			images[2].clone(),
+			images[3].clone(),
+			images[4].clone(),
+			images[5].clone(),
+		];
+		Ok(Self { faces: faces, id_root: ObjectIdRoot::new(), _memory: memory })
+	}
+
+	pub fn face(&self, face: CubeFace) -> &Arc<ImageViewAccess + Send + Sync + 'static> {
+		&self.faces[face as usize]
+	}
+}
+impl RenderTarget for TargetCubemap {
+	fn format(&self) -> Format {
+		self.faces[0].format()
+	}
+
+	fn id_root(&self) -> &ObjectIdRoot {
+		&self.id_root
+	}
+
+	fn images(&self) -> &[Arc<ImageViewAccess + Send + Sync + 'static>] {
+		&self.faces
+	}
+}
+
+/// A target with several layers of the same size, like the layers of a texture array. The image number passed to a
+/// batch's `commands` is the layer.
+pub struct TargetTextureArray {
+	layers: Vec<Arc<ImageViewAccess + Send + Sync + 'static>>,
+	id_root: ObjectIdRoot,
+	_memory: MemoryHandle,
+}
+impl TargetTextureArray {
+	pub fn new(window: &Window, dimensions: [u32; 2], layers: u32) -> Result<Self, BatchError> {
+		let (layers, memory) = make_images(window, dimensions, layers.max(1), "target texture array")?;
+		Ok(Self { layers: layers, id_root: ObjectIdRoot::new(), _memory: memory })
+	}
+
+	pub fn layer(&self, layer: usize) -> &Arc<ImageViewAccess + Send + Sync + 'static> {
+		&self.layers[layer]
+	}
+}
+impl RenderTarget for TargetTextureArray {
+	fn format(&self) -> Format {
+		self.layers[0].format()
+	}
+
+	fn id_root(&self) -> &ObjectIdRoot {
+		&self.id_root
+	}
+
+	fn images(&self) -> &[Arc<ImageViewAccess + Send + Sync + 'static>] {
+		&self.layers
+	}
+}
+
+/// A face of a `TargetCubemap`, in the order cube maps store them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CubeFace {
+	PositiveX,
+	NegativeX,
+	PositiveY,
+	NegativeY,
+	PositiveZ,
+	NegativeZ,
+}
+impl CubeFace {
+	pub const ALL: [CubeFace; 6] = [
+		CubeFace::PositiveX,
+		CubeFace::NegativeX,
+		CubeFace::PositiveY,
+		CubeFace::NegativeY,
+		CubeFace::PositiveZ,
+		CubeFace::NegativeZ,
+	];
+
+	/// A camera looking out of this face from the base camera's position, with a square 90 degree field of view. The
+	/// base camera's rotation and field of view are replaced, and its other settings, like its depth planes, are kept.
+	pub fn camera(self, base: &Camera) -> Camera {
+		// cameras look down -Z with Y pointing down the screen, so the camera's Z axis points out of the back
+		let (right, down) = self.axes();
+		let mut camera = *base;
+		camera.set_rotation(Quaternion::from(Matrix3::from_cols(right, down, right.cross(down))));
+		camera.set_asymmetric_fov(AsymmetricFov { left: -1.0, right: 1.0, down: -1.0, up: 1.0 });
+		camera
+	}
+
+	/// The world space directions of the face image's right and down edges, from the cube map face table in the Vulkan
+	/// spec.
+	fn axes(self) -> (Vector3<f32>, Vector3<f32>) {
+		match self {
+			CubeFace::PositiveX => (vec3(0.0, 0.0, -1.0), vec3(0.0, -1.0, 0.0)),
+			CubeFace::NegativeX => (vec3(0.0, 0.0, 1.0), vec3(0.0, -1.0, 0.0)),
+			CubeFace::PositiveY => (vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0)),
+			CubeFace::NegativeY => (vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, -1.0)),
+			CubeFace::PositiveZ => (vec3(1.0, 0.0, 0.0), vec3(0.0, -1.0, 0.0)),
+			CubeFace::NegativeZ => (vec3(-1.0, 0.0, 0.0), vec3(0.0, -1.0, 0.0)),
+		}
+	}
+}
+
+/// Creates `count` sampled attachments with the window's format, but always sRGB encoded, tracked as one allocation.
+fn make_images(
+	window: &Window,
+	dimensions: [u32; 2],
+	count: u32,
+	name: &str,
+) -> Result<(Vec<Arc<ImageViewAccess + Send + Sync + 'static>>, MemoryHandle), BatchError> {
+	let format = srgb_equivalent(window.format());
+	let memory =
+		window.device().memory_stats()
+			.track(MemoryCategory::Attachments, name, count as u64 * image_bytes(dimensions, format));
+
+	let images = (0..count)
+		.map(|_| {
+			let image = AttachmentImage::sampled(window.device().device().clone(), dimensions, format)?;
+			window.device().debug_names().name_image(&*image, name);
+			Ok(image as _)
+		})
+		.collect::<Result<Vec<_>, ImageCreationError>>()?;
+	Ok((images, memory))
+}
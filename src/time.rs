@@ -0,0 +1,147 @@
+use std::time::Instant;
+
+/// The longest a single tick can be, in seconds. Longer gaps, like the window being dragged or a breakpoint, count as
+/// this long, so the simulation doesn't jump ahead or run hundreds of fixed steps to catch up.
+const MAX_DELTA: f32 = 0.25;
+
+/// The game's clock. Scaled time drives gameplay and engine-driven animation, like tile maps and tweens, so it slows
+/// down with the time scale and stops while paused. Unscaled time keeps going, for menus and the camera in a pause
+/// screen.
+///
+/// Call `tick` once per frame, or `advance` with a known delta to run deterministically, like when playing back an
+/// `InputPlayback` recording. Fixed-rate systems like physics then run one step for each `step_fixed` that returns
+/// true.
+#[derive(Clone, Debug)]
+pub struct Time {
+	scale: f32,
+	paused: bool,
+	delta: f32,
+	unscaled_delta: f32,
+	/// Seconds, as f64 so it doesn't lose precision over long sessions.
+	elapsed: f64,
+	unscaled_elapsed: f64,
+	frame: u64,
+	fixed_step: f32,
+	/// Scaled time that hasn't been consumed by fixed steps yet.
+	accumulator: f32,
+	fixed_frame: u64,
+	last_tick: Option<Instant>,
+}
+impl Time {
+	pub fn new() -> Self {
+		Self {
+			scale: 1.0,
+			paused: false,
+			delta: 0.0,
+			unscaled_delta: 0.0,
+			elapsed: 0.0,
+			unscaled_elapsed: 0.0,
+			frame: 0,
+			fixed_step: 1.0 / 60.0,
+			accumulator: 0.0,
+			fixed_frame: 0,
+			last_tick: None,
+		}
+	}
+
+	/// Starts a new frame, measuring its delta from the real clock. The first tick has a delta of 0.0.
+	pub fn tick(&mut self) {
+		let now = Instant::now();
+		let dt =
+			match self.last_tick.replace(now) {
+				Some(last_tick) => {
+					let dt = now.duration_since(last_tick);
+					dt.as_secs() as f32 + dt.subsec_nanos() as f32 / 1_000_000_000.0
+				},
+				None => 0.0,
+			};
+		self.advance(dt);
+	}
+
+	/// Starts a new frame with the given unscaled delta in seconds, without reading the real clock.
+	pub fn advance(&mut self, dt: f32) {
+		self.unscaled_delta = dt.max(0.0).min(MAX_DELTA);
+		self.delta = if self.paused { 0.0 } else { self.unscaled_delta * self.scale };
+		self.unscaled_elapsed += self.unscaled_delta as f64;
+		self.elapsed += self.delta as f64;
+		self.accumulator += self.delta;
+		self.frame += 1;
+	}
+
+	/// Returns true if another fixed step is due this frame, and consumes it. Loop on this once per frame.
+	pub fn step_fixed(&mut self) -> bool {
+		if self.accumulator < self.fixed_step {
+			return false;
+		}
+		self.accumulator -= self.fixed_step;
+		self.fixed_frame += 1;
+		true
+	}
+
+	/// How far the simulation is between the last fixed step and the next, from 0.0 to 1.0, for interpolating what's
+	/// drawn.
+	pub fn fixed_alpha(&self) -> f32 {
+		self.accumulator / self.fixed_step
+	}
+
+	/// Seconds per fixed step. The default is 1/60.
+	pub fn set_fixed_step(&mut self, step: f32) {
+		self.fixed_step = step.max(0.001);
+	}
+
+	pub fn fixed_step(&self) -> f32 {
+		self.fixed_step
+	}
+
+	/// How fast scaled time passes relative to real time, like 0.2 for slow motion. The default is 1.0.
+	pub fn set_scale(&mut self, scale: f32) {
+		self.scale = scale.max(0.0);
+	}
+
+	pub fn scale(&self) -> f32 {
+		self.scale
+	}
+
+	/// Stops scaled time, starting with the next frame. Unscaled time keeps going.
+	pub fn set_paused(&mut self, paused: bool) {
+		self.paused = paused;
+	}
+
+	pub fn is_paused(&self) -> bool {
+		self.paused
+	}
+
+	/// Scaled seconds since the previous frame. 0.0 while paused.
+	pub fn delta(&self) -> f32 {
+		self.delta
+	}
+
+	/// Real seconds since the previous frame.
+	pub fn unscaled_delta(&self) -> f32 {
+		self.unscaled_delta
+	}
+
+	/// Scaled seconds since the clock was created.
+	pub fn elapsed(&self) -> f64 {
+		self.elapsed
+	}
+
+	pub fn unscaled_elapsed(&self) -> f64 {
+		self.unscaled_elapsed
+	}
+
+	/// The number of frames started so far, including paused ones.
+	pub fn frame(&self) -> u64 {
+		self.frame
+	}
+
+	/// The number of fixed steps taken so far.
+	pub fn fixed_frame(&self) -> u64 {
+		self.fixed_frame
+	}
+}
+impl Default for Time {
+	fn default() -> Self {
+		Self::new()
+	}
+}
@@ -0,0 +1,335 @@
+use crate::batch::mesh::{ Mesh, MeshBatch, PointLight };
+use crate::camera::Camera;
+use crate::scene::Scene;
+use crate::time::Time;
+use crate::tween::{ Easing, Tweenable };
+use crate::vfs;
+use cgmath::{ Quaternion, Vector3 };
+use serde::{ Deserialize, Serialize };
+use std::{ collections::BTreeMap, error::Error, fmt, io, path::Path };
+
+/// Keyframed animation of a camera, meshes, and lights, with events along the way, for cutscenes and camera
+/// flythroughs. Play it with a `TimelinePlayer`.
+///
+/// Timeline files are RON, or JSON if the extension is `.json`. Times are in seconds, and rotations are quaternions in
+/// `[x, y, z, w]` order, like in scene files. Meshes and lights are named, and the game decides what the names refer
+/// to; `apply_meshes` uses the names from a `Scene`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeline {
+	pub camera: TransformTrack,
+	pub meshes: BTreeMap<String, TransformTrack>,
+	pub lights: BTreeMap<String, LightTrack>,
+	pub events: Vec<TimelineEvent>,
+}
+impl Timeline {
+	pub fn from_file(path: impl AsRef<Path>) -> Result<Self, TimelineError> {
+		let bytes = vfs::read(path.as_ref())?;
+		let ext = path.as_ref().extension().and_then(|ext| ext.to_str()).unwrap_or("");
+		let mut timeline: Self =
+			if ext.eq_ignore_ascii_case("json") {
+				serde_json::from_slice(&bytes).map_err(|err| TimelineError::Parse(err.to_string()))?
+			} else {
+				ron::de::from_bytes(&bytes).map_err(|err| TimelineError::Parse(err.to_string()))?
+			};
+		timeline.sort();
+		Ok(timeline)
+	}
+
+	/// Sorts every track's keyframes and the events by time. Timelines built in code should call this before they're
+	/// played, since sampling assumes sorted keyframes.
+	pub fn sort(&mut self) {
+		self.camera.sort();
+		for track in self.meshes.values_mut() {
+			track.sort();
+		}
+		for track in self.lights.values_mut() {
+			track.sort();
+		}
+		self.events.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(std::cmp::Ordering::Equal));
+	}
+
+	/// The time of the last keyframe or event.
+	pub fn duration(&self) -> f32 {
+		let tracks = self.meshes.values().map(TransformTrack::end).chain(self.lights.values().map(LightTrack::end));
+		tracks
+			.chain(self.events.iter().map(|event| event.time))
+			.fold(self.camera.end(), f32::max)
+	}
+
+	/// Moves the camera to where the camera track is at `time`. Parts of the transform without keyframes are left
+	/// alone.
+	pub fn apply_camera(&self, time: f32, camera: &mut Camera) {
+		if let Some(position) = self.camera.position_at(time) {
+			camera.set_position(position);
+		}
+		if let Some(rotation) = self.camera.rotation_at(time) {
+			camera.set_rotation(rotation);
+		}
+	}
+
+	/// Moves a mesh to where its track is at `time`. Returns false if the timeline has no track with that name.
+	pub fn apply_mesh(&self, name: &str, time: f32, mesh: &mut Mesh) -> bool {
+		let track = match self.meshes.get(name) { Some(track) => track, None => return false };
+		if let Some(position) = track.position_at(time) {
+			mesh.set_position(position);
+		}
+		if let Some(rotation) = track.rotation_at(time) {
+			mesh.set_rotation(rotation);
+		}
+		true
+	}
+
+	/// Moves every mesh track's mesh in a batch, finding them by their names in a populated scene.
+	pub fn apply_meshes(&self, time: f32, scene: &Scene, batch: &mut MeshBatch) {
+		for name in self.meshes.keys() {
+			if let Some(mesh) = scene.mesh_id(name).and_then(|id| batch.mesh_mut(id)) {
+				self.apply_mesh(name, time, mesh);
+			}
+		}
+	}
+
+	/// Sets a light's parameters to where its track is at `time`. Returns false if the timeline has no track with that
+	/// name.
+	pub fn apply_light(&self, name: &str, time: f32, light: &mut PointLight) -> bool {
+		let track = match self.lights.get(name) { Some(track) => track, None => return false };
+		if let Some(position) = sample(&track.position, time, Vector3::from) {
+			light.position = position;
+		}
+		if let Some(color) = sample(&track.color, time, |color| color) {
+			light.color = color;
+		}
+		if let Some(intensity) = sample(&track.intensity, time, |intensity| intensity) {
+			light.intensity = intensity;
+		}
+		if let Some(range) = sample(&track.range, time, |range| range) {
+			light.range = range;
+		}
+		true
+	}
+
+	/// The events from `start` up to, but not including, `end`.
+	pub fn events_between(&self, start: f32, end: f32) -> impl Iterator<Item = &TimelineEvent> {
+		self.events.iter().filter(move |event| event.time >= start && event.time < end)
+	}
+}
+
+/// A position and rotation over time.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransformTrack {
+	pub position: Vec<Keyframe<[f32; 3]>>,
+	pub rotation: Vec<Keyframe<[f32; 4]>>,
+}
+impl TransformTrack {
+	pub fn position_at(&self, time: f32) -> Option<Vector3<f32>> {
+		sample(&self.position, time, Vector3::from)
+	}
+
+	pub fn rotation_at(&self, time: f32) -> Option<Quaternion<f32>> {
+		sample(&self.rotation, time, |[x, y, z, w]| Quaternion::new(w, x, y, z))
+	}
+
+	fn sort(&mut self) {
+		sort_keyframes(&mut self.position);
+		sort_keyframes(&mut self.rotation);
+	}
+
+	fn end(&self) -> f32 {
+		end(&self.position).max(end(&self.rotation))
+	}
+}
+
+/// A point light's parameters over time. See `PointLight` for what they do.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightTrack {
+	pub position: Vec<Keyframe<[f32; 3]>>,
+	pub color: Vec<Keyframe<[f32; 3]>>,
+	pub intensity: Vec<Keyframe<f32>>,
+	pub range: Vec<Keyframe<f32>>,
+}
+impl LightTrack {
+	fn sort(&mut self) {
+		sort_keyframes(&mut self.position);
+		sort_keyframes(&mut self.color);
+		sort_keyframes(&mut self.intensity);
+		sort_keyframes(&mut self.range);
+	}
+
+	fn end(&self) -> f32 {
+		end(&self.position).max(end(&self.color)).max(end(&self.intensity)).max(end(&self.range))
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe<T> {
+	pub time: f32,
+	pub value: T,
+	/// How the value moves from this keyframe to the next.
+	#[serde(default)]
+	pub easing: Easing,
+}
+
+/// A named point in a timeline, for the game to react to, like playing a sound or showing a subtitle.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TimelineEvent {
+	pub time: f32,
+	pub name: String,
+}
+
+/// Plays a timeline over time, and reports the events it passes. Seeking jumps straight to a time without reporting
+/// events, for scrubbing through a cutscene in an editor.
+pub struct TimelinePlayer {
+	timeline: Timeline,
+	duration: f32,
+	time: f32,
+	playing: bool,
+	speed: f32,
+	looping: bool,
+}
+impl TimelinePlayer {
+	/// Creates a paused player at the start of the timeline.
+	pub fn new(timeline: Timeline) -> Self {
+		let duration = timeline.duration();
+		Self { timeline: timeline, duration: duration, time: 0.0, playing: false, speed: 1.0, looping: false }
+	}
+
+	pub fn timeline(&self) -> &Timeline {
+		&self.timeline
+	}
+
+	/// Starts playing from the current time, or from the start if the player finished.
+	pub fn play(&mut self) {
+		if self.is_finished() {
+			self.time = 0.0;
+		}
+		self.playing = true;
+	}
+
+	pub fn pause(&mut self) {
+		self.playing = false;
+	}
+
+	pub fn is_playing(&self) -> bool {
+		self.playing
+	}
+
+	/// Jumps to a time, clamped to the timeline. Events between the old and new times aren't reported.
+	pub fn seek(&mut self, time: f32) {
+		self.time = time.max(0.0).min(self.duration);
+	}
+
+	pub fn time(&self) -> f32 {
+		self.time
+	}
+
+	/// Plays faster or slower than the clock. The default is 1.0.
+	pub fn set_speed(&mut self, speed: f32) {
+		self.speed = speed.max(0.0);
+	}
+
+	/// Starts over from the beginning after the end, instead of pausing there.
+	pub fn set_looping(&mut self, looping: bool) {
+		self.looping = looping;
+	}
+
+	/// Whether the player stopped at the end of a timeline that doesn't loop.
+	pub fn is_finished(&self) -> bool {
+		!self.looping && self.time >= self.duration
+	}
+
+	/// Advances by the frame's scaled time, so cutscenes pause with the game. Returns the events passed, in order.
+	pub fn update(&mut self, time: &Time) -> Vec<TimelineEvent> {
+		self.advance(time.delta())
+	}
+
+	/// Advances by `dt` seconds, if the player is playing. Returns the events passed, in order.
+	pub fn advance(&mut self, dt: f32) -> Vec<TimelineEvent> {
+		if !self.playing {
+			return vec![];
+		}
+
+		let mut events = vec![];
+		let mut end = self.time + dt * self.speed;
+		while end >= self.duration {
+			// events right at the end are reported as the end is reached
+			events.extend(self.timeline.events_between(self.time, std::f32::INFINITY).cloned());
+			if !self.looping || self.duration <= 0.0 {
+				self.time = self.duration;
+				self.playing = false;
+				return events;
+			}
+			end -= self.duration;
+			self.time = 0.0;
+		}
+		events.extend(self.timeline.events_between(self.time, end).cloned());
+		self.time = end;
+		events
+	}
+
+	pub fn apply_camera(&self, camera: &mut Camera) {
+		self.timeline.apply_camera(self.time, camera);
+	}
+
+	pub fn apply_mesh(&self, name: &str, mesh: &mut Mesh) -> bool {
+		self.timeline.apply_mesh(name, self.time, mesh)
+	}
+
+	pub fn apply_meshes(&self, scene: &Scene, batch: &mut MeshBatch) {
+		self.timeline.apply_meshes(self.time, scene, batch);
+	}
+
+	pub fn apply_light(&self, name: &str, light: &mut PointLight) -> bool {
+		self.timeline.apply_light(name, self.time, light)
+	}
+}
+
+#[derive(Debug)]
+pub enum TimelineError {
+	Io(io::Error),
+	/// The file isn't valid RON or JSON, or doesn't match `Timeline`.
+	Parse(String),
+}
+impl fmt::Display for TimelineError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			TimelineError::Io(err) => write!(f, "i/o error: {}", err),
+			TimelineError::Parse(msg) => write!(f, "invalid timeline file: {}", msg),
+		}
+	}
+}
+impl Error for TimelineError {
+	fn source(&self) -> Option<&(Error + 'static)> {
+		match self {
+			TimelineError::Io(err) => Some(err),
+			TimelineError::Parse(_) => None,
+		}
+	}
+}
+impl From<io::Error> for TimelineError {
+	fn from(val: io::Error) -> Self {
+		TimelineError::Io(val)
+	}
+}
+
+/// The value of sorted keyframes at a time, converted with `convert`. It holds the first value before the first
+/// keyframe and the last value after the last.
+fn sample<K: Copy, T: Tweenable>(keyframes: &[Keyframe<K>], time: f32, convert: impl Fn(K) -> T) -> Option<T> {
+	let next = keyframes.iter().position(|keyframe| keyframe.time > time).unwrap_or(keyframes.len());
+	if next == 0 {
+		return keyframes.first().map(|keyframe| convert(keyframe.value));
+	}
+	let prev = &keyframes[next - 1];
+	let next = match keyframes.get(next) { Some(next) => next, None => return Some(convert(prev.value)) };
+	let t = (time - prev.time) / (next.time - prev.time);
+	Some(convert(prev.value).interpolate(convert(next.value), prev.easing.apply(t)))
+}
+
+fn sort_keyframes<T>(keyframes: &mut Vec<Keyframe<T>>) {
+	keyframes.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(std::cmp::Ordering::Equal));
+}
+
+fn end<T>(keyframes: &[Keyframe<T>]) -> f32 {
+	keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+}
@@ -0,0 +1,440 @@
+use crate::time::Time;
+use cgmath::{ Quaternion, Vector2, Vector3, Vector4, VectorSpace };
+use serde::{ Deserialize, Serialize };
+use std::collections::VecDeque;
+
+/// Values a `Tween` can animate. Colors are `[f32; 4]`s, and are interpolated per channel.
+pub trait Tweenable: Copy {
+	/// The value `t` of the way from `self` to `to`. Some easing curves overshoot, so `t` can be a little outside 0.0
+	/// to 1.0.
+	fn interpolate(self, to: Self, t: f32) -> Self;
+}
+impl Tweenable for f32 {
+	fn interpolate(self, to: Self, t: f32) -> Self {
+		self + (to - self) * t
+	}
+}
+impl Tweenable for [f32; 2] {
+	fn interpolate(self, to: Self, t: f32) -> Self {
+		[self[0].interpolate(to[0], t), self[1].interpolate(to[1], t)]
+	}
+}
+impl Tweenable for [f32; 3] {
+	fn interpolate(self, to: Self, t: f32) -> Self {
+		[self[0].interpolate(to[0], t), self[1].interpolate(to[1], t), self[2].interpolate(to[2], t)]
+	}
+}
+impl Tweenable for [f32; 4] {
+	fn interpolate(self, to: Self, t: f32) -> Self {
+		[
+			self[0].interpolate(to[0], t),
+			self[1].interpolate(to[1], t),
+			self[2].interpolate(to[2], t),
+			self[3].interpolate(to[3], t),
+		]
+	}
+}
+impl Tweenable for Vector2<f32> {
+	fn interpolate(self, to: Self, t: f32) -> Self {
+		self.lerp(to, t)
+	}
+}
+impl Tweenable for Vector3<f32> {
+	fn interpolate(self, to: Self, t: f32) -> Self {
+		self.lerp(to, t)
+	}
+}
+impl Tweenable for Vector4<f32> {
+	fn interpolate(self, to: Self, t: f32) -> Self {
+		self.lerp(to, t)
+	}
+}
+impl Tweenable for Quaternion<f32> {
+	/// Takes the shortest way around.
+	fn interpolate(self, to: Self, t: f32) -> Self {
+		self.slerp(to, t)
+	}
+}
+
+/// How a tween's progress maps to how far its value has moved. Timeline files use these too.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Easing {
+	Linear,
+	QuadIn,
+	QuadOut,
+	QuadInOut,
+	CubicIn,
+	CubicOut,
+	CubicInOut,
+	SineIn,
+	SineOut,
+	SineInOut,
+	/// Pulls back a little before moving.
+	BackIn,
+	/// Overshoots a little, then settles.
+	BackOut,
+	/// Springs past the end a few times before settling.
+	ElasticOut,
+	BounceOut,
+}
+impl Easing {
+	/// Maps progress from 0.0 to 1.0. The result starts at 0.0 and ends at 1.0.
+	pub fn apply(self, t: f32) -> f32 {
+		use std::f32::consts::PI;
+		const BACK: f32 = 1.70158;
+
+		let t = t.max(0.0).min(1.0);
+		match self {
+			Easing::Linear => t,
+			Easing::QuadIn => t * t,
+			Easing::QuadOut => t * (2.0 - t),
+			Easing::QuadInOut => if t < 0.5 { 2.0 * t * t } else { 1.0 - 2.0 * (1.0 - t) * (1.0 - t) },
+			Easing::CubicIn => t * t * t,
+			Easing::CubicOut => 1.0 - (1.0 - t).powi(3),
+			Easing::CubicInOut => if t < 0.5 { 4.0 * t * t * t } else { 1.0 - 4.0 * (1.0 - t).powi(3) },
+			Easing::SineIn => 1.0 - (t * PI / 2.0).cos(),
+			Easing::SineOut => (t * PI / 2.0).sin(),
+			Easing::SineInOut => (1.0 - (t * PI).cos()) / 2.0,
+			Easing::BackIn => t * t * ((BACK + 1.0) * t - BACK),
+			Easing::BackOut => {
+				let t = t - 1.0;
+				1.0 + t * t * ((BACK + 1.0) * t + BACK)
+			},
+			Easing::ElasticOut => {
+				if t == 0.0 || t == 1.0 {
+					t
+				} else {
+					2f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * 2.0 * PI / 3.0).sin() + 1.0
+				}
+			},
+			Easing::BounceOut => {
+				const N: f32 = 7.5625;
+				const D: f32 = 2.75;
+				if t < 1.0 / D {
+					N * t * t
+				} else if t < 2.0 / D {
+					let t = t - 1.5 / D;
+					N * t * t + 0.75
+				} else if t < 2.5 / D {
+					let t = t - 2.25 / D;
+					N * t * t + 0.9375
+				} else {
+					let t = t - 2.625 / D;
+					N * t * t + 0.984375
+				}
+			},
+		}
+	}
+}
+impl Default for Easing {
+	fn default() -> Self {
+		Easing::Linear
+	}
+}
+
+/// Moves a value from one end to another over time. It can be updated directly and its value copied to whatever it
+/// animates, or bound to a setter with `bind` and played by a `Tweens`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tween<T: Tweenable> {
+	from: T,
+	to: T,
+	/// Seconds.
+	duration: f32,
+	delay: f32,
+	easing: Easing,
+	/// Seconds since the tween started, including the delay.
+	elapsed: f32,
+}
+impl<T: Tweenable> Tween<T> {
+	pub fn new(from: T, to: T, duration: f32) -> Self {
+		Self { from: from, to: to, duration: duration.max(0.0), delay: 0.0, easing: Easing::Linear, elapsed: 0.0 }
+	}
+
+	pub fn with_easing(self, easing: Easing) -> Self {
+		Self { easing: easing, .. self }
+	}
+
+	/// Waits this many seconds before moving. The value stays at `from` until then.
+	pub fn with_delay(self, delay: f32) -> Self {
+		Self { delay: delay.max(0.0), .. self }
+	}
+
+	/// Advances the tween by `dt` seconds and returns its new value.
+	pub fn update(&mut self, dt: f32) -> T {
+		self.advance(dt);
+		self.value()
+	}
+
+	pub fn value(&self) -> T {
+		self.from.interpolate(self.to, self.easing.apply(self.progress()))
+	}
+
+	/// How far through the tween is, from 0.0 to 1.0, before easing.
+	pub fn progress(&self) -> f32 {
+		if self.duration == 0.0 {
+			return if self.elapsed >= self.delay { 1.0 } else { 0.0 };
+		}
+		((self.elapsed - self.delay) / self.duration).max(0.0).min(1.0)
+	}
+
+	pub fn is_finished(&self) -> bool {
+		self.elapsed >= self.delay + self.duration
+	}
+
+	/// Starts the tween over, including its delay.
+	pub fn reset(&mut self) {
+		self.elapsed = 0.0;
+	}
+
+	/// Makes a tween that passes its value to `apply` each update, to be played by a `Tweens`.
+	pub fn bind<C, F: FnMut(&mut C, T)>(self, apply: F) -> BoundTween<T, F> {
+		BoundTween { tween: self, apply: apply }
+	}
+
+	/// Advances the tween, and returns the seconds left over past its end.
+	fn advance(&mut self, dt: f32) -> f32 {
+		let end = self.delay + self.duration;
+		let leftover = (self.elapsed + dt - end).max(0.0);
+		self.elapsed = (self.elapsed + dt).min(end);
+		leftover
+	}
+}
+
+/// Something a `Tweens` plays over time, with access to the context passed to `Tweens::update`.
+pub trait Animation<C> {
+	/// Advances the animation by `dt` seconds. Returns `None` while it's still running, or the seconds left over past
+	/// its end once it's finished.
+	fn update(&mut self, context: &mut C, dt: f32) -> Option<f32>;
+}
+
+/// A tween bound to a setter. Made with `Tween::bind`.
+pub struct BoundTween<T: Tweenable, F> {
+	tween: Tween<T>,
+	apply: F,
+}
+impl<C, T: Tweenable, F: FnMut(&mut C, T)> Animation<C> for BoundTween<T, F> {
+	fn update(&mut self, context: &mut C, dt: f32) -> Option<f32> {
+		let leftover = self.tween.advance(dt);
+		(self.apply)(context, self.tween.value());
+		if self.tween.is_finished() { Some(leftover) } else { None }
+	}
+}
+
+/// Plays animations one after another. Time left over at the end of one step carries into the next, so sequences stay
+/// in sync however long frames are.
+pub struct Sequence<C> {
+	steps: VecDeque<Box<Animation<C>>>,
+}
+impl<C> Sequence<C> {
+	pub fn new() -> Self {
+		Self { steps: VecDeque::new() }
+	}
+
+	pub fn then(mut self, animation: impl Animation<C> + 'static) -> Self {
+		self.steps.push_back(Box::new(animation));
+		self
+	}
+
+	/// Waits this many seconds before the next step.
+	pub fn wait(self, seconds: f32) -> Self {
+		self.then(Wait { remaining: seconds.max(0.0) })
+	}
+
+	/// Calls `callback` once, when the sequence reaches this step.
+	pub fn call(self, callback: impl FnOnce(&mut C) + 'static) -> Self {
+		self.then(Callback { callback: Some(callback) })
+	}
+}
+impl<C> Animation<C> for Sequence<C> {
+	fn update(&mut self, context: &mut C, mut dt: f32) -> Option<f32> {
+		while let Some(step) = self.steps.front_mut() {
+			match step.update(context, dt) {
+				Some(leftover) => {
+					self.steps.pop_front();
+					dt = leftover;
+				},
+				None => return None,
+			}
+		}
+		Some(dt)
+	}
+}
+
+struct Wait {
+	remaining: f32,
+}
+impl<C> Animation<C> for Wait {
+	fn update(&mut self, _context: &mut C, dt: f32) -> Option<f32> {
+		self.remaining -= dt;
+		if self.remaining <= 0.0 { Some(-self.remaining) } else { None }
+	}
+}
+
+struct Callback<F> {
+	callback: Option<F>,
+}
+impl<C, F: FnOnce(&mut C)> Animation<C> for Callback<F> {
+	fn update(&mut self, context: &mut C, dt: f32) -> Option<f32> {
+		if let Some(callback) = self.callback.take() {
+			callback(context);
+		}
+		Some(dt)
+	}
+}
+
+/// Identifies an animation added to a `Tweens`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TweenId(u64);
+
+/// Plays animations on a context, like the game's UI state or a camera, and drops them when they finish. Animations are
+/// updated in the order they were added.
+pub struct Tweens<C> {
+	animations: Vec<(TweenId, Box<Animation<C>>)>,
+	next_id: u64,
+}
+impl<C> Tweens<C> {
+	pub fn new() -> Self {
+		Self { animations: vec![], next_id: 0 }
+	}
+
+	pub fn add(&mut self, animation: impl Animation<C> + 'static) -> TweenId {
+		let id = TweenId(self.next_id);
+		self.next_id += 1;
+		self.animations.push((id, Box::new(animation)));
+		id
+	}
+
+	/// Stops an animation where it is. Returns false if it already finished.
+	pub fn cancel(&mut self, id: TweenId) -> bool {
+		let len = self.animations.len();
+		self.animations.retain(|&(animation_id, _)| animation_id != id);
+		self.animations.len() != len
+	}
+
+	pub fn is_playing(&self, id: TweenId) -> bool {
+		self.animations.iter().any(|&(animation_id, _)| animation_id == id)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.animations.is_empty()
+	}
+
+	pub fn clear(&mut self) {
+		self.animations.clear();
+	}
+
+	/// Advances every animation by the frame's scaled time, so they slow down and stop with the game.
+	pub fn update(&mut self, time: &Time, context: &mut C) {
+		self.advance(time.delta(), context);
+	}
+
+	/// Advances every animation by the frame's unscaled time, for menus that keep animating while the game is paused.
+	pub fn update_unscaled(&mut self, time: &Time, context: &mut C) {
+		self.advance(time.unscaled_delta(), context);
+	}
+
+	/// Advances every animation by `dt` seconds.
+	pub fn advance(&mut self, dt: f32, context: &mut C) {
+		let mut i = 0;
+		while i < self.animations.len() {
+			if self.animations[i].1.update(context, dt).is_some() {
+				self.animations.remove(i);
+			} else {
+				i += 1;
+			}
+		}
+	}
+}
+impl<C> Default for Tweens<C> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const EASINGS: [Easing; 14] = [
+		Easing::Linear,
+		Easing::QuadIn,
+		Easing::QuadOut,
+		Easing::QuadInOut,
+		Easing::CubicIn,
+		Easing::CubicOut,
+		Easing::CubicInOut,
+		Easing::SineIn,
+		Easing::SineOut,
+		Easing::SineInOut,
+		Easing::BackIn,
+		Easing::BackOut,
+		Easing::ElasticOut,
+		Easing::BounceOut,
+	];
+
+	#[test]
+	fn easing_endpoints() {
+		for &easing in &EASINGS {
+			assert!(easing.apply(0.0).abs() < 1e-5, "{:?} doesn't start at 0", easing);
+			assert!((easing.apply(1.0) - 1.0).abs() < 1e-5, "{:?} doesn't end at 1", easing);
+		}
+	}
+
+	#[test]
+	fn easing_clamps_progress() {
+		for &easing in &EASINGS {
+			assert_eq!(easing.apply(-1.0), easing.apply(0.0));
+			assert_eq!(easing.apply(2.0), easing.apply(1.0));
+		}
+	}
+
+	#[test]
+	fn zero_duration() {
+		let tween = Tween::new(0.0, 10.0, 0.0);
+		assert_eq!(tween.value(), 10.0);
+		assert!(tween.is_finished());
+
+		let mut delayed = Tween::new(0.0, 10.0, 0.0).with_delay(1.0);
+		assert_eq!(delayed.update(0.5), 0.0);
+		assert!(!delayed.is_finished());
+		assert_eq!(delayed.update(0.5), 10.0);
+		assert!(delayed.is_finished());
+	}
+
+	#[test]
+	fn sequence_carries_leftover_time() {
+		let mut sequence =
+			Sequence::new()
+				.then(Tween::new(0.0, 1.0, 1.0).bind(|values: &mut [f32; 2], value| values[0] = value))
+				.then(Tween::new(0.0, 1.0, 1.0).bind(|values: &mut [f32; 2], value| values[1] = value));
+		let mut values = [0.0; 2];
+
+		assert_eq!(sequence.update(&mut values, 1.5), None);
+		assert_eq!(values, [1.0, 0.5]);
+		assert_eq!(sequence.update(&mut values, 1.0), Some(0.5));
+		assert_eq!(values, [1.0, 1.0]);
+	}
+
+	#[test]
+	fn wait_carries_leftover_time() {
+		let mut sequence =
+			Sequence::new().wait(0.25).then(Tween::new(0.0, 1.0, 1.0).bind(|x: &mut f32, value| *x = value));
+		let mut x = 0.0;
+
+		assert_eq!(sequence.update(&mut x, 0.75), None);
+		assert_eq!(x, 0.5);
+	}
+
+	#[test]
+	fn tweens_drop_finished_animations() {
+		let mut tweens = Tweens::new();
+		let short = tweens.add(Tween::new(0.0, 1.0, 0.5).bind(|x: &mut f32, value| *x = value));
+		let long = tweens.add(Tween::new(0.0, 1.0, 2.0).bind(|_: &mut f32, _| ()));
+		let mut x = 0.0;
+
+		tweens.advance(1.0, &mut x);
+		assert_eq!(x, 1.0);
+		assert!(!tweens.is_playing(short));
+		assert!(tweens.is_playing(long));
+	}
+}
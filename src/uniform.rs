@@ -0,0 +1,358 @@
+use cgmath::{ Matrix2, Matrix3, Matrix4, Vector2, Vector3, Vector4 };
+
+/// The rules a block's members are laid out by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayoutRules {
+	/// For uniform buffers. Arrays and structs are aligned to 16 bytes, and so are the elements of arrays.
+	Std140,
+	/// For storage buffers and push constants. Arrays and structs are only aligned as much as their members.
+	Std430,
+}
+
+/// Lays out values one after another the way a shader's block expects them, padding each to its alignment. Write the
+/// block's members in the order the shader declares them, then take the bytes with `finish`.
+///
+/// ```ignore
+/// let bytes =
+/// 	UniformWriter::new(LayoutRules::Std140)
+/// 		.field(&light_penetration)
+/// 		.field(&emissive_brightness)
+/// 		// a vec3 is aligned like a vec4, so this lands at offset 16, not 8
+/// 		.field(&base_albedo)
+/// 		.finish();
+/// ```
+#[derive(Clone, Debug)]
+pub struct UniformWriter {
+	rules: LayoutRules,
+	bytes: Vec<u8>,
+	/// The largest alignment of anything written so far, which is the alignment of the block as a whole.
+	align: usize,
+}
+impl UniformWriter {
+	pub fn new(rules: LayoutRules) -> Self {
+		Self { rules: rules, bytes: vec![], align: if rules == LayoutRules::Std140 { 16 } else { 4 } }
+	}
+
+	pub fn rules(&self) -> LayoutRules {
+		self.rules
+	}
+
+	/// Where the next member starts before it's aligned.
+	pub fn offset(&self) -> usize {
+		self.bytes.len()
+	}
+
+	/// Writes a scalar, vector, or matrix member.
+	pub fn field<T: UniformValue>(mut self, value: &T) -> Self {
+		value.write(&mut self);
+		self
+	}
+
+	/// Writes an array member. In std140 each element takes up a multiple of 16 bytes, so a `float[4]` is 64 bytes.
+	pub fn array<T: UniformValue>(mut self, values: &[T]) -> Self {
+		let stride = self.array_stride(T::size(self.rules), T::align(self.rules));
+		self.align_to(self.array_align(T::align(self.rules)));
+		for value in values {
+			let start = self.bytes.len();
+			value.write(&mut self);
+			self.pad_to(start + stride);
+		}
+		self
+	}
+
+	/// Writes a struct member.
+	pub fn structure(mut self, value: &impl Uniform) -> Self {
+		let inner = value.write_fields(UniformWriter::new(self.rules));
+		self.write_block(inner);
+		self
+	}
+
+	/// Writes an array of structs.
+	pub fn structure_array<T: Uniform>(mut self, values: &[T]) -> Self {
+		for value in values {
+			let inner = value.write_fields(UniformWriter::new(self.rules));
+			self.write_block(inner);
+		}
+		self
+	}
+
+	/// Pads the block to its alignment and returns the bytes, ready to copy into a buffer.
+	pub fn finish(mut self) -> Vec<u8> {
+		let align = self.align;
+		self.align_to(align);
+		self.bytes
+	}
+
+	/// Aligns to `align` and appends `bytes`. `UniformValue` implementations write through this.
+	pub fn write_bytes(&mut self, align: usize, bytes: &[u8]) {
+		self.align_to(align);
+		self.bytes.extend_from_slice(bytes);
+	}
+
+	fn write_block(&mut self, inner: UniformWriter) {
+		let align = self.array_align(inner.align);
+		let bytes = inner.finish();
+		self.write_bytes(align, &bytes);
+	}
+
+	fn align_to(&mut self, align: usize) {
+		self.align = self.align.max(align);
+		let len = (self.bytes.len() + align - 1) / align * align;
+		self.bytes.resize(len, 0);
+	}
+
+	fn pad_to(&mut self, len: usize) {
+		if self.bytes.len() < len {
+			self.bytes.resize(len, 0);
+		}
+	}
+
+	fn array_align(&self, align: usize) -> usize {
+		match self.rules {
+			LayoutRules::Std140 => (align + 15) / 16 * 16,
+			LayoutRules::Std430 => align,
+		}
+	}
+
+	fn array_stride(&self, size: usize, align: usize) -> usize {
+		let align = self.array_align(align);
+		(size + align - 1) / align * align
+	}
+}
+
+/// A struct that can be written to a uniform or storage buffer. Implement `write_fields` by writing each field in the
+/// order the shader declares them.
+pub trait Uniform {
+	fn write_fields(&self, writer: UniformWriter) -> UniformWriter;
+
+	fn to_bytes(&self, rules: LayoutRules) -> Vec<u8> {
+		self.write_fields(UniformWriter::new(rules)).finish()
+	}
+
+	fn to_std140(&self) -> Vec<u8> {
+		self.to_bytes(LayoutRules::Std140)
+	}
+
+	fn to_std430(&self) -> Vec<u8> {
+		self.to_bytes(LayoutRules::Std430)
+	}
+}
+
+/// A scalar, vector, or matrix that can be a member of a block.
+pub trait UniformValue {
+	/// Bytes, not counting padding after it.
+	fn size(rules: LayoutRules) -> usize;
+	fn align(rules: LayoutRules) -> usize;
+	fn write(&self, writer: &mut UniformWriter);
+}
+
+impl UniformValue for f32 {
+	fn size(_rules: LayoutRules) -> usize {
+		4
+	}
+
+	fn align(_rules: LayoutRules) -> usize {
+		4
+	}
+
+	fn write(&self, writer: &mut UniformWriter) {
+		self.to_bits().write(writer);
+	}
+}
+impl UniformValue for i32 {
+	fn size(_rules: LayoutRules) -> usize {
+		4
+	}
+
+	fn align(_rules: LayoutRules) -> usize {
+		4
+	}
+
+	fn write(&self, writer: &mut UniformWriter) {
+		(*self as u32).write(writer);
+	}
+}
+impl UniformValue for u32 {
+	fn size(_rules: LayoutRules) -> usize {
+		4
+	}
+
+	fn align(_rules: LayoutRules) -> usize {
+		4
+	}
+
+	fn write(&self, writer: &mut UniformWriter) {
+		writer.write_bytes(4, &self.to_le_bytes());
+	}
+}
+/// Booleans are 4 bytes in blocks.
+impl UniformValue for bool {
+	fn size(_rules: LayoutRules) -> usize {
+		4
+	}
+
+	fn align(_rules: LayoutRules) -> usize {
+		4
+	}
+
+	fn write(&self, writer: &mut UniformWriter) {
+		(*self as u32).write(writer);
+	}
+}
+
+macro_rules! vector {
+	($ty:ty, $n:expr, $align:expr) => {
+		impl UniformValue for $ty {
+			fn size(_rules: LayoutRules) -> usize {
+				4 * $n
+			}
+
+			fn align(_rules: LayoutRules) -> usize {
+				$align
+			}
+
+			fn write(&self, writer: &mut UniformWriter) {
+				let components: [f32; $n] = (*self).into();
+				let bytes = components.iter().flat_map(|c| c.to_bits().to_le_bytes().to_vec()).collect::<Vec<_>>();
+				writer.write_bytes($align, &bytes);
+			}
+		}
+	};
+}
+// a vec3 is aligned like a vec4
+vector!([f32; 2], 2, 8);
+vector!([f32; 3], 3, 16);
+vector!([f32; 4], 4, 16);
+vector!(Vector2<f32>, 2, 8);
+vector!(Vector3<f32>, 3, 16);
+vector!(Vector4<f32>, 4, 16);
+
+/// Matrices are column major, and each column is laid out like an array element, so in std140 every column is 16
+/// bytes.
+macro_rules! matrix {
+	($ty:ty, $column:ty, $n:expr) => {
+		impl UniformValue for $ty {
+			fn size(rules: LayoutRules) -> usize {
+				Self::align(rules) * $n
+			}
+
+			fn align(rules: LayoutRules) -> usize {
+				match rules { LayoutRules::Std140 => 16, LayoutRules::Std430 => <$column>::align(rules) }
+			}
+
+			fn write(&self, writer: &mut UniformWriter) {
+				let stride = Self::align(writer.rules());
+				for i in 0..$n {
+					writer.align_to(stride);
+					let start = writer.offset();
+					self[i].write(writer);
+					writer.pad_to(start + stride);
+				}
+			}
+		}
+	};
+}
+matrix!(Matrix2<f32>, Vector2<f32>, 2);
+matrix!(Matrix3<f32>, Vector3<f32>, 3);
+matrix!(Matrix4<f32>, Vector4<f32>, 4);
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn float_at(bytes: &[u8], offset: usize) -> f32 {
+		let mut word = [0; 4];
+		word.copy_from_slice(&bytes[offset..offset + 4]);
+		f32::from_bits(u32::from_le_bytes(word))
+	}
+
+	struct Light {
+		position: [f32; 3],
+		intensity: f32,
+	}
+	impl Uniform for Light {
+		fn write_fields(&self, writer: UniformWriter) -> UniformWriter {
+			writer.field(&self.position).field(&self.intensity)
+		}
+	}
+
+	#[test]
+	fn std140_vec3_then_float() {
+		let bytes = UniformWriter::new(LayoutRules::Std140).field(&[1.0f32, 2.0, 3.0]).field(&4.0f32).finish();
+		assert_eq!(bytes.len(), 16);
+		for (i, &expected) in [1.0, 2.0, 3.0, 4.0].iter().enumerate() {
+			assert_eq!(float_at(&bytes, i * 4), expected);
+		}
+	}
+
+	#[test]
+	fn std140_float_then_vec3() {
+		let bytes = UniformWriter::new(LayoutRules::Std140).field(&1.0f32).field(&[2.0f32, 3.0, 4.0]).finish();
+		assert_eq!(bytes.len(), 32);
+		assert_eq!(float_at(&bytes, 0), 1.0);
+		assert_eq!(float_at(&bytes, 16), 2.0);
+		assert_eq!(float_at(&bytes, 24), 4.0);
+	}
+
+	#[test]
+	fn std140_vec2_packing() {
+		let bytes = UniformWriter::new(LayoutRules::Std140)
+			.field(&1.0f32)
+			.field(&[2.0f32, 3.0])
+			.field(&4.0f32)
+			.finish();
+		// a vec2 only needs 8 byte alignment, so it fits after the float, and the block pads to 16
+		assert_eq!(bytes.len(), 32);
+		assert_eq!(float_at(&bytes, 8), 2.0);
+		assert_eq!(float_at(&bytes, 16), 4.0);
+	}
+
+	#[test]
+	fn array_strides() {
+		let values = [1.0f32, 2.0, 3.0];
+		let bytes = UniformWriter::new(LayoutRules::Std140).field(&0.0f32).array(&values).finish();
+		assert_eq!(bytes.len(), 64);
+		for (i, &value) in values.iter().enumerate() {
+			assert_eq!(float_at(&bytes, 16 + i * 16), value);
+		}
+
+		let bytes = UniformWriter::new(LayoutRules::Std430).field(&0.0f32).array(&values).finish();
+		assert_eq!(bytes.len(), 16);
+		for (i, &value) in values.iter().enumerate() {
+			assert_eq!(float_at(&bytes, 4 + i * 4), value);
+		}
+	}
+
+	#[test]
+	fn structures() {
+		let light = Light { position: [1.0, 2.0, 3.0], intensity: 4.0 };
+		assert_eq!(light.to_std140().len(), 16);
+
+		let bytes = UniformWriter::new(LayoutRules::Std140)
+			.field(&0.0f32)
+			.structure(&light)
+			.structure_array(&[Light { position: [5.0, 6.0, 7.0], intensity: 8.0 }, light])
+			.field(&9.0f32)
+			.finish();
+		assert_eq!(bytes.len(), 80);
+		assert_eq!(float_at(&bytes, 16), 1.0);
+		assert_eq!(float_at(&bytes, 28), 4.0);
+		assert_eq!(float_at(&bytes, 32), 5.0);
+		assert_eq!(float_at(&bytes, 48), 1.0);
+		// a member after a struct starts at the struct's end, which is already aligned
+		assert_eq!(float_at(&bytes, 64), 9.0);
+	}
+
+	#[test]
+	fn std140_matrix_columns() {
+		let matrix = Matrix3::new(1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0);
+		let bytes = UniformWriter::new(LayoutRules::Std140).field(&matrix).field(&10.0f32).finish();
+		assert_eq!(bytes.len(), 64);
+		for column in 0..3 {
+			for row in 0..3 {
+				assert_eq!(float_at(&bytes, column * 16 + row * 4), (column * 3 + row + 1) as f32);
+			}
+		}
+		assert_eq!(float_at(&bytes, 48), 10.0);
+	}
+}
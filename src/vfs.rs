@@ -0,0 +1,160 @@
+use crate::progress::LoadProgress;
+use lazy_static::lazy_static;
+use std::{
+	fs::File,
+	io::{ self, prelude::*, Cursor, ErrorKind },
+	path::{ Component, Path, PathBuf },
+	sync::{ Mutex, RwLock },
+};
+use zip::{ ZipArchive, result::ZipError };
+
+lazy_static! {
+	static ref MOUNTS: RwLock<Vec<Mount>> = RwLock::new(vec![]);
+}
+
+/// Makes the files in a directory available to every asset loader, as if they were in the working directory. Mounts
+/// with a higher priority are searched first, and among equal priorities, the most recent mount wins, so a mod
+/// directory mounted over the game's data overrides the files it contains.
+pub fn mount_dir(path: impl Into<PathBuf>, priority: i32) {
+	insert(Mount { priority: priority, source: Source::Dir(path.into()) });
+}
+
+/// Like `mount_dir`, for a zip archive, such as a game's `data.pak`. Entries are read straight from the archive, so it
+/// has to stay where it is while it's mounted.
+pub fn mount_archive(path: impl AsRef<Path>, priority: i32) -> io::Result<()> {
+	let archive = ZipArchive::new(File::open(path.as_ref())?).map_err(zip_to_io)?;
+	let source = Source::Archive { path: path.as_ref().to_owned(), archive: Mutex::new(archive) };
+	insert(Mount { priority: priority, source: source });
+	Ok(())
+}
+
+/// Removes a mounted directory or archive. Returns false if it wasn't mounted.
+pub fn unmount(path: impl AsRef<Path>) -> bool {
+	let mut mounts = MOUNTS.write().unwrap();
+	let len = mounts.len();
+	mounts.retain(|mount| mount.source.path() != path.as_ref());
+	mounts.len() != len
+}
+
+/// Reads a whole file from the highest priority mount that has it. Paths that aren't in any mount, and absolute paths,
+/// are read from the real filesystem, so games that don't mount anything work as if this layer wasn't here.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+	read_with_progress(path, &LoadProgress::new())
+}
+
+/// Like `read`, reporting the bytes read so far to a load's progress.
+pub(crate) fn read_with_progress(path: impl AsRef<Path>, progress: &LoadProgress) -> io::Result<Vec<u8>> {
+	let path = path.as_ref();
+	if !path.is_absolute() {
+		for mount in MOUNTS.read().unwrap().iter() {
+			match mount.source.read(path, progress) {
+				Err(ref err) if err.kind() == ErrorKind::NotFound => continue,
+				result => return result,
+			}
+		}
+	}
+	read_file(path, progress)
+}
+
+/// Like `read`, for loaders that need to seek.
+pub fn open(path: impl AsRef<Path>) -> io::Result<Cursor<Vec<u8>>> {
+	read(path).map(Cursor::new)
+}
+
+pub fn exists(path: impl AsRef<Path>) -> bool {
+	let path = path.as_ref();
+	(!path.is_absolute() && MOUNTS.read().unwrap().iter().any(|mount| mount.source.contains(path))) || path.exists()
+}
+
+struct Mount {
+	priority: i32,
+	source: Source,
+}
+
+enum Source {
+	Dir(PathBuf),
+	Archive { path: PathBuf, archive: Mutex<ZipArchive<File>> },
+}
+impl Source {
+	fn path(&self) -> &Path {
+		match self {
+			Source::Dir(path) => path,
+			Source::Archive { path, .. } => path,
+		}
+	}
+
+	fn read(&self, path: &Path, progress: &LoadProgress) -> io::Result<Vec<u8>> {
+		match self {
+			Source::Dir(dir) => read_file(&dir.join(path), progress),
+			Source::Archive { archive, .. } => {
+				let mut archive = archive.lock().unwrap();
+				let entry = archive.by_name(&entry_name(path)).map_err(zip_to_io)?;
+				let size = entry.size() as usize;
+				read_counted(entry, size, progress)
+			},
+		}
+	}
+
+	fn contains(&self, path: &Path) -> bool {
+		match self {
+			Source::Dir(dir) => dir.join(path).is_file(),
+			Source::Archive { archive, .. } => archive.lock().unwrap().by_name(&entry_name(path)).is_ok(),
+		}
+	}
+}
+
+fn read_file(path: &Path, progress: &LoadProgress) -> io::Result<Vec<u8>> {
+	let file = File::open(path)?;
+	let size = file.metadata()?.len() as usize;
+	read_counted(file, size, progress)
+}
+
+/// Reads in chunks so loading screens can show how far along big files are.
+fn read_counted(mut reader: impl Read, size: usize, progress: &LoadProgress) -> io::Result<Vec<u8>> {
+	progress.start_reading(size);
+	let mut bytes = Vec::with_capacity(size);
+	let mut chunk = vec![0; 64 * 1024];
+	loop {
+		if progress.is_cancelled() {
+			return Err(io::Error::new(ErrorKind::Interrupted, "load cancelled"));
+		}
+		match reader.read(&mut chunk) {
+			Ok(0) => return Ok(bytes),
+			Ok(len) => {
+				bytes.extend_from_slice(&chunk[..len]);
+				progress.add_bytes(len);
+			},
+			Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+			Err(err) => return Err(err),
+		}
+	}
+}
+
+fn insert(mount: Mount) {
+	let mut mounts = MOUNTS.write().unwrap();
+	let index = mounts.iter().position(|other| other.priority <= mount.priority).unwrap_or(mounts.len());
+	mounts.insert(index, mount);
+}
+
+/// Zip entries always use forward slashes, with no `.` or `..` components.
+fn entry_name(path: &Path) -> String {
+	let mut parts: Vec<String> = vec![];
+	for component in path.components() {
+		match component {
+			Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+			Component::ParentDir => {
+				parts.pop();
+			},
+			_ => (),
+		}
+	}
+	parts.join("/")
+}
+
+fn zip_to_io(err: ZipError) -> io::Error {
+	match err {
+		ZipError::Io(err) => err,
+		ZipError::FileNotFound => io::Error::new(ErrorKind::NotFound, "file not found in archive"),
+		err => io::Error::new(ErrorKind::InvalidData, err.to_string()),
+	}
+}
@@ -1,32 +1,74 @@
-pub use winit::{ Event, MouseButton, MouseCursor, WindowEvent, WindowId, dpi::{ LogicalPosition, LogicalSize } };
+mod chrome;
+
+pub use self::chrome::{ ResizeEdge, WindowChrome };
+pub use winit::{
+	Event,
+	MouseButton,
+	MouseCursor,
+	MouseScrollDelta,
+	Touch,
+	TouchPhase,
+	WindowEvent,
+	WindowId,
+	dpi::{ LogicalPosition, LogicalSize },
+};
 
 use crate::{ ObjectIdRoot, RenderTarget };
 use crate::device::DeviceCtx;
-use std::{ iter::Iterator, sync::{ Arc, atomic::{ AtomicBool, Ordering } }};
+use crate::diagnostics::WindowDiagnostics;
+use log::{ warn, log };
+use std::{
+	collections::VecDeque,
+	error::Error,
+	fmt,
+	iter::Iterator,
+	sync::{ Arc, atomic::{ AtomicBool, Ordering } },
+	thread,
+	time::{ Duration, Instant },
+};
 use vulkano::{
+	OomError,
+	device::DeviceCreationError,
 	format::Format,
-	image::ImageViewAccess,
-	memory::DeviceMemoryAllocError,
+	image::{ ImageViewAccess, SwapchainImage },
 	swapchain::{
 		acquire_next_image,
 		AcquireError,
+		Capabilities,
+		CapabilitiesError,
+		ColorSpace,
+		CompositeAlpha,
+		PresentFuture,
 		PresentMode,
 		Surface,
 		SurfaceTransform,
 		Swapchain,
 		SwapchainCreationError
 	},
-	sync::{ FlushError, GpuFuture },
+	sync::{ FenceSignalFuture, FlushError, GpuFuture },
 };
+use vulkano_win::CreationError;
 use winit;
 
+/// How long before a frame's deadline to stop sleeping and spin instead, since sleeps can overshoot by this much.
+const SPIN_TIME: Duration = Duration::from_millis(2);
+/// The shortest frame interval while minimized, so a minimized game doesn't spin.
+const MINIMIZED_INTERVAL: Duration = Duration::from_millis(50);
+
 pub struct Window {
 	surface: Arc<Surface<winit::Window>>,
 	device: Arc<DeviceCtx>,
 	swapchain: Arc<Swapchain<winit::Window>>,
 	images: Vec<Arc<ImageViewAccess + Send + Sync + 'static>>,
 	previous_frame_end: Option<Box<GpuFuture>>,
+	/// The fences of submitted frames, oldest first, with their ids from `DeferredDrops::begin_frame`.
+	frames_in_flight: VecDeque<(u64, Arc<FenceSignalFuture<PresentFuture<Box<GpuFuture>, winit::Window>>>)>,
 	resized: Arc<AtomicBool>,
+	minimized: Arc<AtomicBool>,
+	format: Format,
+	vsync: bool,
+	transparent: bool,
+	pacer: FramePacer,
 	id_root: ObjectIdRoot,
 }
 impl Window {
@@ -38,35 +80,52 @@ impl Window {
 		}
 	}
 
+	/// Acquires a swapchain image, records the frame with `get_commands`, and presents it. While the window is
+	/// minimized, there's nothing to present to, so `get_commands` isn't called.
 	pub fn present<F>(
 		&mut self,
 		get_commands: impl FnOnce(&mut Self, usize, Box<GpuFuture>) -> F
-	) -> Result<(), DeviceMemoryAllocError>
+	) -> Result<(), WindowError>
 	where
 		F: GpuFuture + 'static
 	{
-		if self.resized.swap(false, Ordering::Relaxed) {
-			let dimensions = self.surface.capabilities(self.device.device().physical_device())
-				.expect("failed to get surface capabilities")
-				.current_extent
-				.unwrap_or(
-					self.surface.window()
-						.get_inner_size()
-						.map(|size| {
-							let size: (u32, u32) = size.into();
-							[size.0, size.1]
-						})
-						.unwrap()
-				);
+		let caps = self.surface.capabilities(self.device.device().physical_device())?;
+		let dimensions = surface_dimensions(&self.surface, &caps).filter(|&[width, height]| width > 0 && height > 0);
+		let dimensions =
+			match dimensions {
+				Some(dimensions) if !self.is_minimized() => dimensions,
+				_ => {
+					// swapchains can't be zero sized, so keep the old one until the window is restored
+					if let Some(previous_frame_end) = &mut self.previous_frame_end {
+						previous_frame_end.cleanup_finished();
+					}
+					self.end_finished_frames();
+					self.pacer.wait(Some(MINIMIZED_INTERVAL));
+					return Ok(());
+				},
+			};
+		self.pacer.wait(None);
 
+		// vulkano doesn't report VK_SUBOPTIMAL_KHR, so a swapchain that doesn't match the surface anymore is
+		// recreated the same way as after a resize
+		if self.resized.swap(false, Ordering::Relaxed) || dimensions != self.swapchain.dimensions() {
+			let recreated =
+				Self::create_swapchain(
+					&self.surface,
+					&self.device,
+					self.format,
+					self.vsync,
+					self.transparent,
+					Some(&self.swapchain),
+				);
 			let (swapchain, images) =
-				match self.swapchain.recreate_with_dimension(dimensions) {
+				match recreated {
 					Ok(ret) => ret,
 					Err(SwapchainCreationError::UnsupportedDimensions) => {
 						self.resized.store(true, Ordering::Relaxed);
 						return Ok(());
 					},
-					Err(err) => unreachable!(err),
+					Err(err) => return Err(err.into()),
 				};
 
 			self.swapchain = swapchain;
@@ -80,7 +139,7 @@ impl Window {
 					self.resized.store(true, Ordering::Relaxed);
 					return Ok(());
 				},
-				Err(err) => unreachable!(err)
+				Err(err) => return Err(err.into()),
 			};
 
 		let mut future: Box<GpuFuture> =
@@ -90,26 +149,63 @@ impl Window {
 			} else {
 				Box::new(acquire_future)
 			};
+		self.end_finished_frames();
 		future = Box::new(get_commands(self, image_num, future));
+		let frame = self.device.deferred_drops().begin_frame();
 		let future = future.then_swapchain_present(self.device.queue().clone(), self.swapchain.clone(), image_num)
 			.then_signal_fence_and_flush();
 		self.previous_frame_end =
 			match future {
-				Ok(future) => Some(Box::new(future)),
+				Ok(future) => {
+					let future = Arc::new(future);
+					self.frames_in_flight.push_back((frame, future.clone()));
+					Some(Box::new(future))
+				},
 				Err(FlushError::OutOfDate) => {
+					self.device.deferred_drops().end_frame(frame);
 					self.resized.store(true, Ordering::Relaxed);
 					return Ok(());
 				},
-				Err(err) => unreachable!(err),
+				Err(err) => {
+					self.device.deferred_drops().end_frame(frame);
+					return Err(err.into());
+				},
 			};
 
 		Ok(())
 	}
 
+	/// Blocks until the GPU finishes the last presented frame. `Benchmark` uses this to time the GPU's work on each
+	/// frame, at the cost of the CPU no longer working ahead of the GPU.
+	pub fn wait_for_gpu(&mut self) {
+		if let Some((_, fence)) = self.frames_in_flight.back() {
+			if let Err(err) = fence.wait(None) {
+				warn!("Waiting for the GPU failed: {}", err);
+			}
+		}
+		self.end_finished_frames();
+	}
+
+	/// Tells the device which frames have finished on the GPU, so resources released during them can be dropped.
+	fn end_finished_frames(&mut self) {
+		while let Some((frame, fence)) = self.frames_in_flight.front() {
+			if let Err(FlushError::Timeout) = fence.wait(Some(Duration::from_secs(0))) {
+				break;
+			}
+			self.device.deferred_drops().end_frame(*frame);
+			self.frames_in_flight.pop_front();
+		}
+	}
+
 	pub fn get_inner_size(&self) -> Option<LogicalSize> {
 		self.surface.window().get_inner_size()
 	}
 
+	/// Whether the window is minimized. Nothing is presented while it is.
+	pub fn is_minimized(&self) -> bool {
+		self.minimized.load(Ordering::Relaxed)
+	}
+
 	pub fn set_cursor(&self, cursor: MouseCursor) {
 		self.surface.window().set_cursor(cursor)
 	}
@@ -118,48 +214,178 @@ impl Window {
 		self.surface.window().set_cursor_position(pos)
 	}
 
+	pub fn set_inner_size(&self, size: LogicalSize) {
+		self.surface.window().set_inner_size(size)
+	}
+
+	/// The position of the window's top left corner on the desktop, including decorations.
+	pub fn get_position(&self) -> Option<LogicalPosition> {
+		self.surface.window().get_position()
+	}
+
+	/// The position of the top left corner of the window's contents on the desktop.
+	pub fn get_inner_position(&self) -> Option<LogicalPosition> {
+		self.surface.window().get_inner_position()
+	}
+
+	/// Moves the window's top left corner, including decorations. `WindowChrome` uses this to move windows without
+	/// them.
+	pub fn set_position(&self, position: LogicalPosition) {
+		self.surface.window().set_position(position)
+	}
+
+	/// Switches between borderless fullscreen on the window's current monitor and windowed mode.
+	pub fn set_fullscreen(&self, fullscreen: bool) {
+		let window = self.surface.window();
+		window.set_fullscreen(if fullscreen { Some(window.get_current_monitor()) } else { None })
+	}
+
+	/// Waits for vertical blank before presenting. When disabled, mailbox presentation is preferred over immediate
+	/// presentation, to avoid tearing where possible. The swapchain is recreated before the next frame.
+	pub fn set_vsync(&mut self, vsync: bool) {
+		if self.vsync != vsync {
+			self.vsync = vsync;
+			self.resized.store(true, Ordering::Relaxed);
+		}
+	}
+
+	/// Limits how often `present` renders, by sleeping until each frame is due. Frames are spaced evenly, so this also
+	/// smooths out frame times under vsync when the target is the refresh rate or a fraction of it. With vsync off,
+	/// mailbox presentation keeps this from tearing where it's supported. `None` renders as fast as possible.
+	pub fn set_target_fps(&mut self, fps: Option<f32>) {
+		self.pacer.interval = fps.filter(|&fps| fps > 0.0).map(|fps| Duration::from_nanos((1e9 / fps as f64) as u64));
+		self.pacer.next_frame = None;
+	}
+
+	pub fn target_fps(&self) -> Option<f32> {
+		self.pacer.interval
+			.map(|interval| (1.0 / (interval.as_secs() as f64 + interval.subsec_nanos() as f64 / 1e9)) as f32)
+	}
+
+	pub fn vsync(&self) -> bool {
+		self.vsync
+	}
+
+	/// Whether the desktop shows through transparent pixels. This is only true if `WindowOptions::transparent` was set
+	/// and the surface supports an alpha composite mode.
+	pub fn is_transparent(&self) -> bool {
+		self.transparent && composite_alpha(&self.surface_capabilities(), true) != CompositeAlpha::Opaque
+	}
+
 	pub fn device(&self) -> &Arc<DeviceCtx> {
 		&self.device
 	}
 
-	pub(crate) fn new(surface: Arc<Surface<winit::Window>>, device: Arc<DeviceCtx>, resized: Arc<AtomicBool>) -> Self {
-		let (swapchain, images) = {
-			let caps = surface.capabilities(device.device().physical_device()).expect("failed to get surface capabilities");
-			Swapchain::new(
-				device.device().clone(),
-				surface.clone(),
-				caps.min_image_count,
-				Format::B8G8R8A8Srgb,
-				caps.current_extent
-					.unwrap_or(
-						surface.window()
-							.get_inner_size()
-							.map(|size| {
-								let size: (u32, u32) = size.into();
-								[size.0, size.1]
-							})
-							.unwrap()
-					),
-				1,
-				caps.supported_usage_flags,
-				device.queue(),
-				SurfaceTransform::Identity,
-				caps.supported_composite_alpha.iter().next().unwrap(),
-				PresentMode::Fifo,
-				true,
-				None
-			).expect("failed to create swapchain")
-		};
+	/// A copy of the window's swapchain images that can be sent to another thread, to record batches there. Batches
+	/// made for the window accept it as their target. Take a new one each frame, since the swapchain is recreated when
+	/// the window is resized.
+	pub fn target(&self) -> WindowTarget {
+		WindowTarget { format: self.swapchain.format(), id_root: self.id_root.share(), images: self.images.clone() }
+	}
+
+	/// The window's device, surface capabilities, and swapchain configuration, for bug reports.
+	pub fn diagnostics(&self) -> WindowDiagnostics {
+		WindowDiagnostics::new(self)
+	}
+
+	/// The swapchain formats the surface supports with the standard sRGB color space.
+	pub fn supported_formats(&self) -> Vec<Format> {
+		supported_formats(&self.surface_capabilities())
+	}
+
+	pub(crate) fn surface_capabilities(&self) -> Capabilities {
+		self.surface.capabilities(self.device.device().physical_device()).expect("failed to get surface capabilities")
+	}
+
+	pub(crate) fn swapchain(&self) -> &Arc<Swapchain<winit::Window>> {
+		&self.swapchain
+	}
+
+	pub(crate) fn new(
+		surface: Arc<Surface<winit::Window>>,
+		device: Arc<DeviceCtx>,
+		resized: Arc<AtomicBool>,
+		minimized: Arc<AtomicBool>,
+		options: &WindowOptions,
+	) -> Result<Self, WindowError> {
+		let caps = surface.capabilities(device.device().physical_device())?;
+		let supported = supported_formats(&caps);
+		let format =
+			match options.formats.iter().cloned().find(|format| supported.contains(format)) {
+				Some(format) => format,
+				None => {
+					let format = *supported.first().ok_or(WindowError::NoSupportedFormat)?;
+					warn!("None of the preferred swapchain formats are supported, so {:?} will be used", format);
+					format
+				},
+			};
+
+		let (swapchain, images) =
+			Self::create_swapchain(&surface, &device, format, options.vsync, options.transparent, None)?;
 		let images = images.into_iter().map(|x| x as _).collect();
 
-		Self {
+		Ok(Self {
 			surface: surface,
 			device: device,
 			swapchain: swapchain,
 			images: images,
 			previous_frame_end: None,
+			frames_in_flight: VecDeque::new(),
 			resized: resized,
+			minimized: minimized,
+			format: format,
+			vsync: options.vsync,
+			transparent: options.transparent,
+			pacer: FramePacer { interval: None, next_frame: None },
 			id_root: ObjectIdRoot::new(),
+		})
+	}
+
+	fn create_swapchain(
+		surface: &Arc<Surface<winit::Window>>,
+		device: &Arc<DeviceCtx>,
+		format: Format,
+		vsync: bool,
+		transparent: bool,
+		old_swapchain: Option<&Arc<Swapchain<winit::Window>>>,
+	) -> Result<(Arc<Swapchain<winit::Window>>, Vec<Arc<SwapchainImage<winit::Window>>>), SwapchainCreationError> {
+		let caps = surface.capabilities(device.device().physical_device()).expect("failed to get surface capabilities");
+		let present_mode =
+			if vsync {
+				PresentMode::Fifo
+			} else if caps.present_modes.mailbox {
+				PresentMode::Mailbox
+			} else if caps.present_modes.immediate {
+				PresentMode::Immediate
+			} else {
+				PresentMode::Fifo
+			};
+
+		let dimensions = surface_dimensions(surface, &caps).ok_or(SwapchainCreationError::UnsupportedDimensions)?;
+
+		Swapchain::new(
+			device.device().clone(),
+			surface.clone(),
+			caps.min_image_count,
+			format,
+			dimensions,
+			1,
+			caps.supported_usage_flags,
+			device.queue(),
+			SurfaceTransform::Identity,
+			composite_alpha(&caps, transparent),
+			present_mode,
+			true,
+			old_swapchain
+		)
+	}
+}
+impl Drop for Window {
+	fn drop(&mut self) {
+		// the fences block until their frames finish when dropped
+		self.previous_frame_end = None;
+		for (frame, _) in self.frames_in_flight.drain(..) {
+			self.device.deferred_drops().end_frame(frame);
 		}
 	}
 }
@@ -176,3 +402,208 @@ impl RenderTarget for Window {
 		&self.images
 	}
 }
+
+/// The window's swapchain images, as a render target that can be used from any thread. See `Window::target`.
+pub struct WindowTarget {
+	format: Format,
+	id_root: ObjectIdRoot,
+	images: Vec<Arc<ImageViewAccess + Send + Sync + 'static>>,
+}
+impl RenderTarget for WindowTarget {
+	fn format(&self) -> Format {
+		self.format
+	}
+
+	fn id_root(&self) -> &ObjectIdRoot {
+		&self.id_root
+	}
+
+	fn images(&self) -> &[Arc<ImageViewAccess + Send + Sync + 'static>] {
+		&self.images
+	}
+}
+
+/// Options for `Context::create_window_with_options`.
+#[derive(Clone, Debug)]
+pub struct WindowOptions {
+	/// Swapchain formats in order of preference. The first one the surface supports is used, or if none are, the
+	/// first format the surface lists. Batches encode their output to sRGB themselves when the format is linear, so
+	/// colors look the same either way.
+	pub formats: Vec<Format>,
+	pub vsync: bool,
+	/// Lets the desktop show through pixels with alpha below 1.0, where the compositor supports it. The window has to
+	/// be cleared to a transparent color, such as with `SpriteBatch::set_clear_color`. Colors are composited as
+	/// premultiplied by alpha where possible, which is what alpha blending onto a transparent clear produces.
+	pub transparent: bool,
+	/// Whether the window has a title bar and borders. Turn them off for launchers and overlays with custom shapes.
+	pub decorations: bool,
+}
+impl Default for WindowOptions {
+	fn default() -> Self {
+		Self {
+			formats: vec![Format::B8G8R8A8Srgb, Format::R8G8B8A8Srgb, Format::B8G8R8A8Unorm, Format::R8G8B8A8Unorm],
+			vsync: true,
+			transparent: false,
+			decorations: true,
+		}
+	}
+}
+
+/// Why a window couldn't be created or a frame couldn't be presented. Out of date swapchains are recreated without an
+/// error.
+#[derive(Debug)]
+pub enum WindowError {
+	OomError(OomError),
+	/// The OS window or its Vulkan surface couldn't be created.
+	CreationError(CreationError),
+	/// No device supports the required features and can present to the window.
+	NoSuitableDevice,
+	DeviceCreationError(DeviceCreationError),
+	/// The surface doesn't support any format with the standard sRGB color space.
+	NoSupportedFormat,
+	DeviceLost,
+	/// The window's surface is gone, usually because the window was closed.
+	SurfaceLost,
+	/// The swapchain couldn't be recreated after the window was resized or vsync was changed.
+	SwapchainCreationError(SwapchainCreationError),
+	/// The frame's commands couldn't be submitted, such as when they access an image that's in use elsewhere.
+	FlushError(FlushError),
+}
+impl fmt::Display for WindowError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			WindowError::OomError(err) => write!(f, "out of memory: {}", err),
+			WindowError::CreationError(err) => write!(f, "window creation failed: {}", err),
+			WindowError::NoSuitableDevice => write!(f, "no device supports the required features and the window"),
+			WindowError::DeviceCreationError(err) => write!(f, "device creation failed: {}", err),
+			WindowError::NoSupportedFormat => write!(f, "the window's surface doesn't support any sRGB format"),
+			WindowError::DeviceLost => write!(f, "the device was lost"),
+			WindowError::SurfaceLost => write!(f, "the window's surface was lost"),
+			WindowError::SwapchainCreationError(err) => write!(f, "swapchain creation failed: {}", err),
+			WindowError::FlushError(err) => write!(f, "frame submission failed: {}", err),
+		}
+	}
+}
+impl Error for WindowError {
+	fn source(&self) -> Option<&(Error + 'static)> {
+		match self {
+			WindowError::OomError(err) => Some(err),
+			WindowError::CreationError(err) => Some(err),
+			WindowError::DeviceCreationError(err) => Some(err),
+			WindowError::SwapchainCreationError(err) => Some(err),
+			WindowError::FlushError(err) => Some(err),
+			WindowError::NoSuitableDevice | WindowError::NoSupportedFormat => None,
+			WindowError::DeviceLost | WindowError::SurfaceLost => None,
+		}
+	}
+}
+impl From<CreationError> for WindowError {
+	fn from(val: CreationError) -> Self {
+		WindowError::CreationError(val)
+	}
+}
+impl From<DeviceCreationError> for WindowError {
+	fn from(val: DeviceCreationError) -> Self {
+		match val {
+			DeviceCreationError::OutOfHostMemory => WindowError::OomError(OomError::OutOfHostMemory),
+			DeviceCreationError::OutOfDeviceMemory => WindowError::OomError(OomError::OutOfDeviceMemory),
+			DeviceCreationError::DeviceLost => WindowError::DeviceLost,
+			val => WindowError::DeviceCreationError(val),
+		}
+	}
+}
+impl From<CapabilitiesError> for WindowError {
+	fn from(val: CapabilitiesError) -> Self {
+		match val {
+			CapabilitiesError::OomError(err) => WindowError::OomError(err),
+			CapabilitiesError::SurfaceLost => WindowError::SurfaceLost,
+		}
+	}
+}
+impl From<SwapchainCreationError> for WindowError {
+	fn from(val: SwapchainCreationError) -> Self {
+		match val {
+			SwapchainCreationError::OomError(err) => WindowError::OomError(err),
+			SwapchainCreationError::DeviceLost => WindowError::DeviceLost,
+			SwapchainCreationError::SurfaceLost => WindowError::SurfaceLost,
+			val => WindowError::SwapchainCreationError(val),
+		}
+	}
+}
+impl From<AcquireError> for WindowError {
+	fn from(val: AcquireError) -> Self {
+		match val {
+			AcquireError::OomError(err) => WindowError::OomError(err),
+			AcquireError::DeviceLost => WindowError::DeviceLost,
+			AcquireError::SurfaceLost => WindowError::SurfaceLost,
+			// images are acquired without a timeout, and out of date swapchains are recreated
+			AcquireError::Timeout | AcquireError::OutOfDate => unreachable!("{}", val),
+		}
+	}
+}
+impl From<FlushError> for WindowError {
+	fn from(val: FlushError) -> Self {
+		match val {
+			FlushError::OomError(err) => WindowError::OomError(err),
+			FlushError::DeviceLost => WindowError::DeviceLost,
+			FlushError::SurfaceLost => WindowError::SurfaceLost,
+			val => WindowError::FlushError(val),
+		}
+	}
+}
+
+/// Sleeps between frames to hold a frame rate.
+struct FramePacer {
+	interval: Option<Duration>,
+	next_frame: Option<Instant>,
+}
+impl FramePacer {
+	/// Waits until the next frame is due, spacing frames at least `min_interval` apart.
+	fn wait(&mut self, min_interval: Option<Duration>) {
+		let interval = match self.interval.max(min_interval) { Some(interval) => interval, None => return };
+		let now = Instant::now();
+		// after falling more than a frame behind, start over instead of rushing frames out to catch up
+		let deadline = match self.next_frame { Some(deadline) if deadline + interval > now => deadline, _ => now };
+
+		if deadline > now + SPIN_TIME {
+			thread::sleep(deadline - now - SPIN_TIME);
+		}
+		while Instant::now() < deadline {
+			thread::yield_now();
+		}
+		self.next_frame = Some(deadline + interval);
+	}
+}
+
+/// The size a swapchain for the surface should be, or `None` if the window is gone.
+fn surface_dimensions(surface: &Surface<winit::Window>, caps: &Capabilities) -> Option<[u32; 2]> {
+	caps.current_extent.or_else(|| {
+		surface.window().get_inner_size().map(|size| {
+			let size: (u32, u32) = size.into();
+			[size.0, size.1]
+		})
+	})
+}
+
+/// Opaque unless the window is transparent, in which case the first supported alpha mode, in order of preference.
+fn composite_alpha(caps: &Capabilities, transparent: bool) -> CompositeAlpha {
+	let supported = &caps.supported_composite_alpha;
+	if !transparent && supported.opaque {
+		CompositeAlpha::Opaque
+	} else if transparent && supported.pre_multiplied {
+		CompositeAlpha::PreMultiplied
+	} else if transparent && supported.post_multiplied {
+		CompositeAlpha::PostMultiplied
+	} else if transparent && supported.inherit {
+		CompositeAlpha::Inherit
+	} else {
+		supported.iter().next().unwrap()
+	}
+}
+
+fn supported_formats(caps: &Capabilities) -> Vec<Format> {
+	caps.supported_formats.iter()
+		.filter(|(_, color_space)| *color_space == ColorSpace::SrgbNonLinear)
+		.map(|(format, _)| *format)
+		.collect()
+}
@@ -0,0 +1,229 @@
+use super::{ Event, LogicalPosition, LogicalSize, MouseButton, MouseCursor, Window, WindowEvent };
+use winit::ElementState;
+
+/// Lets a window without decorations be moved and resized with the mouse, for custom title bars drawn with sprites or
+/// a UI library. Positions and sizes are in logical pixels.
+///
+/// Mark the title bar with `set_drag_regions`, and pass every window event to `handle_event`. Buttons drawn over the
+/// title bar can be left out of the regions, or the UI can start a move or resize itself with `begin_move` and
+/// `begin_resize` while the left button is held.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowChrome {
+	/// Left, top, right and bottom edges, relative to the window.
+	drag_regions: Vec<[f32; 4]>,
+	resize_border: f32,
+	min_size: LogicalSize,
+	cursor: Option<LogicalPosition>,
+	hovered_edge: Option<ResizeEdge>,
+	drag: Option<Drag>,
+}
+impl WindowChrome {
+	pub fn new() -> Self {
+		Self {
+			drag_regions: vec![],
+			resize_border: 6.0,
+			min_size: LogicalSize::new(160.0, 120.0),
+			cursor: None,
+			hovered_edge: None,
+			drag: None,
+		}
+	}
+
+	/// The parts of the window that move it when dragged, usually the title bar.
+	pub fn set_drag_regions(&mut self, regions: Vec<[f32; 4]>) {
+		self.drag_regions = regions;
+	}
+
+	/// How far in from the window's edges it can be grabbed to resize it. 0.0 turns resizing off. The default is 6.0.
+	pub fn set_resize_border(&mut self, border: f32) {
+		self.resize_border = border.max(0.0);
+	}
+
+	/// The smallest size resizing can shrink the window to.
+	pub fn set_min_size(&mut self, size: LogicalSize) {
+		self.min_size = size;
+	}
+
+	/// Whether the window is being moved or resized.
+	pub fn is_dragging(&self) -> bool {
+		self.drag.is_some()
+	}
+
+	/// Starts moving the window with the cursor until the left button is released.
+	pub fn begin_move(&mut self, window: &Window) {
+		self.drag = self.start_drag(window, None);
+	}
+
+	/// Starts resizing the window from an edge with the cursor until the left button is released.
+	pub fn begin_resize(&mut self, window: &Window, edge: ResizeEdge) {
+		self.drag = self.start_drag(window, Some(edge));
+	}
+
+	/// Moves or resizes the window for mouse events. Returns true if the event was used for that, in which case the
+	/// game and UI should usually ignore it.
+	pub fn handle_event(&mut self, window: &Window, event: &Event) -> bool {
+		let event = match event { Event::WindowEvent { event, .. } => event, _ => return false };
+		match event {
+			WindowEvent::CursorMoved { position, .. } => {
+				self.cursor = Some(*position);
+				if let Some(drag) = self.drag {
+					self.update_drag(window, &drag);
+					return true;
+				}
+
+				let edge = self.edge_at(window, *position);
+				if edge != self.hovered_edge {
+					window.set_cursor(edge.map_or(MouseCursor::Default, ResizeEdge::cursor));
+					self.hovered_edge = edge;
+				}
+				false
+			},
+			WindowEvent::CursorLeft { .. } => {
+				self.cursor = None;
+				false
+			},
+			WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. } => {
+				let position = match self.cursor { Some(position) => position, None => return false };
+				if let Some(edge) = self.edge_at(window, position) {
+					self.begin_resize(window, edge);
+				} else if self.drag_regions.iter().any(|&region| contains(region, position)) {
+					self.begin_move(window);
+				}
+				self.drag.is_some()
+			},
+			WindowEvent::MouseInput { state: ElementState::Released, button: MouseButton::Left, .. } => {
+				self.drag.take().is_some()
+			},
+			WindowEvent::Focused(false) => {
+				self.drag = None;
+				false
+			},
+			_ => false,
+		}
+	}
+
+	fn start_drag(&self, window: &Window, edge: Option<ResizeEdge>) -> Option<Drag> {
+		let cursor = self.cursor?;
+		let inner_position = window.get_inner_position()?;
+		Some(Drag {
+			edge: edge,
+			start_cursor: [inner_position.x + cursor.x, inner_position.y + cursor.y],
+			start_position: window.get_position()?,
+			start_size: window.get_inner_size()?,
+		})
+	}
+
+	fn update_drag(&self, window: &Window, drag: &Drag) {
+		let (cursor, inner_position) =
+			match (self.cursor, window.get_inner_position()) {
+				(Some(cursor), Some(inner_position)) => (cursor, inner_position),
+				_ => return,
+			};
+		// in screen space, since the window moves under the cursor
+		let dx = inner_position.x + cursor.x - drag.start_cursor[0];
+		let dy = inner_position.y + cursor.y - drag.start_cursor[1];
+		let LogicalPosition { x, y } = drag.start_position;
+
+		let edge =
+			match drag.edge {
+				Some(edge) => edge,
+				None => return window.set_position(LogicalPosition::new(x + dx, y + dy)),
+			};
+		let (left, top, right, bottom) = edge.sides();
+		let LogicalSize { width, height } = drag.start_size;
+		let resize = |start: f64, delta: f64, grows: bool, shrinks: bool, min: f64| {
+			(if grows { start + delta } else if shrinks { start - delta } else { start }).max(min)
+		};
+		let new_width = resize(width, dx, right, left, self.min_size.width);
+		let new_height = resize(height, dy, bottom, top, self.min_size.height);
+
+		// the left and top edges move the window too, as far as the size changed
+		if left || top {
+			let new_x = if left { x + width - new_width } else { x };
+			let new_y = if top { y + height - new_height } else { y };
+			window.set_position(LogicalPosition::new(new_x, new_y));
+		}
+		window.set_inner_size(LogicalSize::new(new_width, new_height));
+	}
+
+	fn edge_at(&self, window: &Window, position: LogicalPosition) -> Option<ResizeEdge> {
+		let size = window.get_inner_size()?;
+		let border = self.resize_border as f64;
+		if border <= 0.0 {
+			return None;
+		}
+
+		let left = position.x < border;
+		let top = position.y < border;
+		let right = position.x >= size.width - border;
+		let bottom = position.y >= size.height - border;
+		match (left, top, right, bottom) {
+			(true, true, _, _) => Some(ResizeEdge::TopLeft),
+			(_, true, true, _) => Some(ResizeEdge::TopRight),
+			(true, _, _, true) => Some(ResizeEdge::BottomLeft),
+			(_, _, true, true) => Some(ResizeEdge::BottomRight),
+			(true, _, _, _) => Some(ResizeEdge::Left),
+			(_, true, _, _) => Some(ResizeEdge::Top),
+			(_, _, true, _) => Some(ResizeEdge::Right),
+			(_, _, _, true) => Some(ResizeEdge::Bottom),
+			_ => None,
+		}
+	}
+}
+impl Default for WindowChrome {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// The edge or corner a window is resized from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResizeEdge {
+	Left,
+	Top,
+	Right,
+	Bottom,
+	TopLeft,
+	TopRight,
+	BottomLeft,
+	BottomRight,
+}
+impl ResizeEdge {
+	/// Whether the edge includes the left, top, right and bottom sides.
+	fn sides(self) -> (bool, bool, bool, bool) {
+		match self {
+			ResizeEdge::Left => (true, false, false, false),
+			ResizeEdge::Top => (false, true, false, false),
+			ResizeEdge::Right => (false, false, true, false),
+			ResizeEdge::Bottom => (false, false, false, true),
+			ResizeEdge::TopLeft => (true, true, false, false),
+			ResizeEdge::TopRight => (false, true, true, false),
+			ResizeEdge::BottomLeft => (true, false, false, true),
+			ResizeEdge::BottomRight => (false, false, true, true),
+		}
+	}
+
+	fn cursor(self) -> MouseCursor {
+		match self {
+			ResizeEdge::Left | ResizeEdge::Right => MouseCursor::EwResize,
+			ResizeEdge::Top | ResizeEdge::Bottom => MouseCursor::NsResize,
+			ResizeEdge::TopLeft | ResizeEdge::BottomRight => MouseCursor::NwseResize,
+			ResizeEdge::TopRight | ResizeEdge::BottomLeft => MouseCursor::NeswResize,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Drag {
+	/// `None` for moving the window.
+	edge: Option<ResizeEdge>,
+	/// The cursor's position on the screen when the drag started.
+	start_cursor: [f64; 2],
+	start_position: LogicalPosition,
+	start_size: LogicalSize,
+}
+
+fn contains(region: [f32; 4], position: LogicalPosition) -> bool {
+	let (x, y) = (position.x as f32, position.y as f32);
+	x >= region[0] && y >= region[1] && x < region[2] && y < region[3]
+}